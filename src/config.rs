@@ -1,25 +1,73 @@
 // src/config.rs
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::env;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::NaiveTime;
 use config::{Config as Loader, Environment, File};
+use crate::exchange::types::PriceSource;
 
 // ... ( HedgeStrategy и WsLimitOrderPlacementStrategy без изменений ) ...
-#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum HedgeStrategy {
     Sequential,
     WebsocketChunks,
 }
-#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "PascalCase")]
 pub enum WsLimitOrderPlacementStrategy {
     BestAskBid,
     OneTickInside,
 }
+/// Тип ордеров для ноги хеджа
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderType {
+    Limit,  // Лимитка с перестановкой за рынком (по умолчанию)
+    Market, // Рыночный ордер: быстро, но с проскальзыванием и комиссией тейкера
+}
+/// Управление плечом фьючерса при открытии хеджа
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LeverageMode {
+    Computed, // Бот выставляет точное плечо под каждую операцию (по умолчанию)
+    Fixed,    // Плечо выставлено пользователем заранее; бот только проверяет, что операция в него укладывается
+}
+/// Порядок монет в списке баланса кошелька
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WalletSort {
+    Alpha, // По алфавиту
+    Value, // По оценочной стоимости в quote_currency (по убыванию)
+    Free,  // По свободному количеству (по убыванию)
+}
 // --- КОНЕЦ ДОБАВЛЕНИЙ ---
 
-#[derive(Deserialize, Debug, Clone)]
+/// Переопределения параметров стратегии для конкретного символа (ключ — базовый символ, e.g. "BTC")
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct SymbolSettings {
+    pub slippage:      Option<f64>,
+    pub max_wait_secs: Option<u64>,
+    pub offset_points: Option<u32>,
+}
+
+/// Дополнительный аккаунт Bybit в том же процессе. Незаданные поля берутся из основного конфига.
+/// У каждого аккаунта своя БД и свой бот: Telegram отдает обновления токена только одному получателю
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AccountSettings {
+    pub name: String,
+    pub bybit_api_key: String,
+    pub bybit_api_secret: String,
+    #[serde(default)]
+    pub quote_currency: Option<String>,
+    #[serde(default)]
+    pub telegram_token: Option<String>,
+    #[serde(default)]
+    pub sqlite_path: Option<String>, // Не задан — sqlite_path основного конфига с суффиксом имени аккаунта
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Config {
     // Bybit
     pub bybit_api_key:    String,
@@ -29,9 +77,21 @@ pub struct Config {
 
     // SQLite
     pub sqlite_path:      String,
+    #[serde(default = "default_db_schema_self_check")]
+    pub db_schema_self_check: bool,
+    #[serde(default = "default_db_write_retry_attempts")]
+    pub db_write_retry_attempts: u32, // Попыток критической записи (финальный статус операции)
+    #[serde(default = "default_db_write_retry_delay_ms")]
+    pub db_write_retry_delay_ms: u64,
 
     // Telegram
     pub telegram_token:   String,
+    // Чаты с доступом к административным командам (/broadcast и т.п.); пусто — команды отключены
+    #[serde(default)]
+    pub allowed_chat_ids: Vec<i64>,
+    // Чат/канал для критических алертов (ошибки, незахеджированный спот) в дополнение к сообщению пользователю
+    #[serde(default)]
+    pub alert_chat_id: Option<i64>,
 
     // Общая Стратегия
     pub default_volatility: f64,
@@ -40,10 +100,108 @@ pub struct Config {
     pub slippage:           f64,
     pub max_wait_secs:      u64,
     pub max_allowed_leverage: f64,
+    // Плечо: computed — выставлять под каждую операцию, fixed — не менять, а проверять по fixed_leverage
+    #[serde(default = "default_leverage_mode")]
+    pub leverage_mode: LeverageMode,
+    #[serde(default)]
+    pub fixed_leverage: Option<f64>, // Для fixed: предел плеча; не задан — текущее плечо на бирже
+    // Отдельное подтверждение, если хедж повысит плечо символа (меняется ликвидация уже открытых позиций)
+    #[serde(default = "default_confirm_leverage_increase")]
+    pub confirm_leverage_increase: bool,
+    // При отмене хеджа пользователем продавать уже купленный спот (false — оставить его на балансе)
+    #[serde(default = "default_sell_spot_on_hedge_cancel")]
+    pub sell_spot_on_hedge_cancel: bool,
+    // Отменять фьючерсный ордер по таймауту (false — оставить ордер на бирже и следить за ним)
+    #[serde(default = "default_cancel_futures_on_timeout")]
+    pub cancel_futures_on_timeout: bool,
+    // Айсберг: фьючерсная нога хеджа выставляется срезами не больше этого объема (в базовой монете); None — одним ордером
+    #[serde(default)]
+    pub futures_slice_qty: Option<f64>,
+    // Повторы первичного размещения ордера при временных ошибках биржи (0 — без повторов)
+    #[serde(default = "default_order_placement_retries")]
+    pub order_placement_retries: u32,
+    // Начальная пауза между повторами, мс (удваивается с каждой попыткой)
+    #[serde(default = "default_order_placement_retry_delay_ms")]
+    pub order_placement_retry_delay_ms: u64,
+    // Общий бюджет повторов на всю операцию (размещение, замены после потери ордера); 0 — без ограничения
+    #[serde(default = "default_operation_retry_budget")]
+    pub operation_retry_budget: u32,
+    // Опорная цена для лимиток: last / mid / index (None — last для спота и середина bid/ask для фьючерса)
+    #[serde(default)]
+    pub price_source: Option<PriceSource>,
+    // Проверять после хеджа, что изменения спота и фьючерсной позиции компенсируют друг друга
+    #[serde(default)]
+    pub verify_hedge: bool,
+    // Хедж только фьючерсом: спот не покупается (хранится вне бота), шорт на указанный объем под залог свободного quote
+    #[serde(default)]
+    pub futures_only_hedge: bool,
+    // Тип ордеров по ногам (limit — цикл перестановки лимиток, market — рыночный ордер)
+    #[serde(default = "default_order_type")]
+    pub spot_order_type: OrderType,
+    #[serde(default = "default_order_type")]
+    pub futures_order_type: OrderType,
+    // Подгонять сумму хеджа так, чтобы объем спота был ровно кратен шагу (без отброшенной при округлении дроби)
+    #[serde(default)]
+    pub snap_sum_to_qty: bool,
+    // Перекуп спота сверх цели (% от цели), выше которого итог хеджа помечается предупреждением; фьючерс всегда покрывает весь купленный спот
+    #[serde(default = "default_spot_overfill_warn_pct")]
+    pub spot_overfill_warn_pct: f64,
+    // Операции, прерванные перезапуском не раньше этого срока (сек), возобновляются автоматически; старше — Interrupted
+    // для ручной проверки. 0 — не возобновлять
+    #[serde(default = "default_resume_grace_secs")]
+    pub resume_grace_secs: u64,
+    // Повторы запроса цены спота, если тикер вернул 0 (новые пары, тонкий рынок): попытки, пауза (мс) и запасная середина стакана
+    #[serde(default = "default_spot_price_attempts")]
+    pub spot_price_attempts: u32,
+    #[serde(default = "default_spot_price_retry_delay_ms")]
+    pub spot_price_retry_delay_ms: u64,
+    #[serde(default)]
+    pub spot_price_mid_fallback: bool,
+    // Не давать округлению до тика съесть slippage (микроцены): лимитка сдвигается минимум на offset_points тиков от опорной цены
+    #[serde(default = "default_enforce_tick_offset")]
+    pub enforce_tick_offset: bool,
+    // Аварийная остановка: файл-сигнал (пока существует — новые операции отклоняются)
+    // и отмена уже запущенных операций при /halt
+    #[serde(default)]
+    pub kill_switch_file: Option<String>,
+    #[serde(default)]
+    pub halt_cancels_running: bool,
+    // Пауза после неудачного хеджа, сек: новые хеджи по тому же символу отклоняются (0 — без паузы)
+    #[serde(default = "default_failure_cooldown_secs")]
+    pub failure_cooldown_secs: u64,
+    // Максимальный спред спота и фьючерса в % от середины bid/ask: шире на любой ноге — хедж не запускается (None — проверка выключена)
+    #[serde(default)]
+    pub max_spread_pct: Option<f64>,
+    // Начальная синхронизация времени с Bybit: повторы, пауза (мс, удваивается) и обязательность при старте
+    #[serde(default = "default_time_sync_retries")]
+    pub time_sync_retries: u32,
+    #[serde(default = "default_time_sync_retry_delay_ms")]
+    pub time_sync_retry_delay_ms: u64,
+    #[serde(default = "default_time_sync_required")]
+    pub time_sync_required: bool,
+    // Пул HTTP-соединений к Bybit: простаивающих соединений на хост, их время жизни (с) и TCP keepalive (с, 0 — выкл.)
+    #[serde(default = "default_http_pool_max_idle_per_host")]
+    pub http_pool_max_idle_per_host: usize,
+    #[serde(default = "default_http_pool_idle_timeout_secs")]
+    pub http_pool_idle_timeout_secs: u64,
+    #[serde(default = "default_http_tcp_keepalive_secs")]
+    pub http_tcp_keepalive_secs: u64,
+    // Логировать полный запрос и ответ Bybit при retCode != 0 (независимо от уровня логов; ключ и подпись скрыты)
+    #[serde(default)]
+    pub log_api_bodies_on_error: bool,
+    // UID субаккаунта для запросов мастер-ключом: только балансы, ордера и позиции с ним отклоняются
+    #[serde(default)]
+    pub bybit_member_id: Option<String>,
+    // MMR, если биржа вернула пустой maintenanceMargin (None — на mainnet операция отклоняется)
+    #[serde(default)]
+    pub default_mmr_fallback: Option<f64>,
 
     // --- Параметры WS стратегии ---
     #[serde(default = "default_hedge_strategy")]
     pub hedge_strategy_default: HedgeStrategy,
+    // Исполнять через WebSocket-задачи и хедж, и расхедж (false — хедж по hedge_strategy_default, расхедж последовательно)
+    #[serde(default)]
+    pub use_websocket_hedge: bool,
 
     #[serde(default = "default_ws_auto_chunk_target_count")]
     pub ws_auto_chunk_target_count: u32,
@@ -61,33 +219,570 @@ pub struct Config {
     #[serde(default = "default_ws_ping_interval_secs")]
     pub ws_ping_interval_secs: u64,
 
+    #[serde(default = "default_ws_reauth_interval_secs")]
+    pub ws_reauth_interval_secs: u64, // Плановая переавторизация приватного потока; 0 — отключена
+
     #[serde(default = "default_ws_limit_order_placement_strategy")]
     pub ws_limit_order_placement_strategy: WsLimitOrderPlacementStrategy,
 
+    #[serde(default = "default_max_price_staleness_ms")]
+    pub max_price_staleness_ms: u64, // Цена старше порога перед выставлением ордера запрашивается заново; 0 — без проверки
+
     // --- Добавим недостающий параметр из ТЗ ---
     #[serde(default = "default_ws_stale_price_ratio")]
     pub ws_stale_price_ratio: Option<f64>, // <-- Добавили и сделали Option<f64>
+
+    // --- Порог предупреждения о риске ликвидации (accountMMRate, 1.0 = ликвидация) ---
+    #[serde(default = "default_margin_ratio_warning_threshold")]
+    pub margin_ratio_warning_threshold: f64,
+
+    // --- Трейлинг-стоп для фьючерсной ноги (доля от цены, предлагается после хеджа) ---
+    #[serde(default = "default_trailing_stop_distance_ratio")]
+    pub trailing_stop_distance_ratio: f64,
+
+    // --- Минимальный интервал между правками прогресса в одном чате, мс (защита от 429 Telegram) ---
+    #[serde(default = "default_min_edit_interval_ms")]
+    pub min_edit_interval_ms: u64,
+    #[serde(default = "default_progress_edit_max_retries")]
+    pub progress_edit_max_retries: u32, // Повторы правки прогресса при 429/сетевых ошибках Telegram (0 — без повторов)
+
+    // --- Максимальное число знаков после запятой в сообщениях Telegram ---
+    #[serde(default = "default_display_max_decimals")]
+    pub display_max_decimals: u32,
+
+    // --- Период усреднения фандинга по умолчанию для /funding, дней ---
+    #[serde(default = "default_funding_days")]
+    pub default_funding_days: u16,
+
+    // --- Фоновый учет накопленного фандинга по открытым хеджам ---
+    #[serde(default = "default_funding_accrual_interval_secs")]
+    pub funding_accrual_interval_secs: u64, // 0 — учет отключен
+    #[serde(default)]
+    pub funding_cost_alert_threshold: Option<f64>, // Уведомить, когда расход на фандинг превысит сумму (в quote)
+
+    // --- Ежедневная сводка операций в чаты, где за сутки была активность ---
+    #[serde(default)]
+    pub daily_digest_time: Option<String>, // Местное время отправки "ЧЧ:ММ"; не задано — сводка отключена
+    #[serde(default)]
+    pub utc_offset_hours: i32, // Смещение местного времени от UTC, часов (например, 3 для МСК)
+
+    // --- HTTP /healthz для проверок живости (Docker/Kubernetes) ---
+    #[serde(default)]
+    pub health_port: Option<u16>, // Порт эндпоинта; не задан — эндпоинт и фоновый пинг биржи отключены
+    #[serde(default = "default_health_ping_interval_secs")]
+    pub health_ping_interval_secs: u64, // Период пинга биржи для /healthz
+    #[serde(default = "default_health_max_ping_age_secs")]
+    pub health_max_ping_age_secs: u64, // Последний успешный пинг старше — /healthz отвечает 503
+
+    // --- Удалять команды и ввод пользователя, чтобы в чате оставались только сообщения бота ---
+    #[serde(default = "default_delete_user_messages")]
+    pub delete_user_messages: bool,
+
+    // --- Порядок монет в балансе кошелька ---
+    #[serde(default = "default_wallet_sort")]
+    pub wallet_sort: WalletSort,
+
+    // --- Переопределения по символам ---
+    #[serde(default)]
+    pub symbol_overrides: HashMap<String, SymbolSettings>,
+
+    // --- Несколько аккаунтов в одном процессе (пусто — один аккаунт из полей выше) ---
+    #[serde(default)]
+    pub accounts: Vec<AccountSettings>,
 }
 
 // --- Функции для значений по умолчанию ---
+fn default_db_schema_self_check() -> bool { true }
+fn default_db_write_retry_attempts() -> u32 { 3 }
+fn default_db_write_retry_delay_ms() -> u64 { 200 }
+fn default_cancel_futures_on_timeout() -> bool { true }
+fn default_sell_spot_on_hedge_cancel() -> bool { true }
+fn default_order_placement_retries() -> u32 { 2 }
+fn default_order_placement_retry_delay_ms() -> u64 { 500 }
+fn default_operation_retry_budget() -> u32 { 20 }
 fn default_hedge_strategy() -> HedgeStrategy { HedgeStrategy::Sequential }
+fn default_order_type() -> OrderType { OrderType::Limit }
+fn default_spot_overfill_warn_pct() -> f64 { 1.0 }
+fn default_resume_grace_secs() -> u64 { 600 }
+fn default_failure_cooldown_secs() -> u64 { 60 }
+fn default_spot_price_attempts() -> u32 { 3 }
+fn default_spot_price_retry_delay_ms() -> u64 { 300 }
+fn default_enforce_tick_offset() -> bool { true }
+fn default_time_sync_retries() -> u32 { 3 }
+fn default_time_sync_retry_delay_ms() -> u64 { 1000 }
+fn default_time_sync_required() -> bool { true }
+fn default_http_pool_max_idle_per_host() -> usize { 16 }
+fn default_http_pool_idle_timeout_secs() -> u64 { 90 }
+fn default_http_tcp_keepalive_secs() -> u64 { 60 }
 fn default_ws_auto_chunk_target_count() -> u32 { 15 }
 fn default_ws_order_book_depth() -> u32 { 10 }
 // --- ИЗМЕНЕНО ТУТ ---
 fn default_ws_max_value_imbalance_ratio() -> Option<f64> { Some(0.05) } // <-- Возвращаем Some(...)
 fn default_ws_reconnect_delay_secs() -> u64 { 5 }
 fn default_ws_ping_interval_secs() -> u64 { 20 }
+fn default_ws_reauth_interval_secs() -> u64 { 1800 }
+fn default_max_price_staleness_ms() -> u64 { 5000 }
 fn default_ws_limit_order_placement_strategy() -> WsLimitOrderPlacementStrategy { WsLimitOrderPlacementStrategy::BestAskBid }
 // --- Добавим default для нового параметра ---
 fn default_ws_stale_price_ratio() -> Option<f64> { Some(0.01) } // <-- Добавили
+fn default_margin_ratio_warning_threshold() -> f64 { 0.7 }
+fn default_min_edit_interval_ms() -> u64 { 1000 }
+fn default_progress_edit_max_retries() -> u32 { 2 }
+fn default_health_ping_interval_secs() -> u64 { 30 }
+fn default_health_max_ping_age_secs() -> u64 { 120 }
+fn default_display_max_decimals() -> u32 { 8 }
+fn default_wallet_sort() -> WalletSort { WalletSort::Alpha }
+fn default_delete_user_messages() -> bool { true }
+fn default_leverage_mode() -> LeverageMode { LeverageMode::Computed }
+fn default_confirm_leverage_increase() -> bool { true }
+fn default_funding_days() -> u16 { 30 }
+fn default_funding_accrual_interval_secs() -> u64 { 3600 }
+fn default_trailing_stop_distance_ratio() -> f64 { 0.05 }
+
+/// Путь к БД аккаунта по умолчанию: "data/hedgehog.db" -> "data/hedgehog-<name>.db"
+fn account_sqlite_path(base: &str, name: &str) -> String {
+    match base.rsplit_once('.') {
+        Some((stem, ext)) if !ext.contains(['/', '\\']) && !stem.is_empty() => format!("{}-{}.{}", stem, name, ext),
+        _ => format!("{}-{}", base, name),
+    }
+}
+
+/// Переменные окружения HEDGER__<КЛЮЧ> (вложенные ключи через "__", например HEDGER__SYMBOL_OVERRIDES__BTC__SLIPPAGE).
+/// Числа и bool разбираются из строк, allowed_chat_ids задается списком через запятую
+fn env_source() -> Environment {
+    Environment::with_prefix("HEDGER")
+        .separator("__")
+        .try_parsing(true)
+        .list_separator(",")
+        .with_list_parse_key("allowed_chat_ids")
+}
 
 impl Config {
+    /// Слои настроек: значения по умолчанию → файл (если есть) → переменные окружения HEDGER__*.
+    /// Файл по умолчанию (Config.toml) необязателен: в контейнере все можно задать через окружение.
+    /// Путь из HEDGER_CONFIG обязателен — опечатка в пути не должна молча давать конфиг без файла
     pub fn load() -> Result<Self> {
-        let file = env::var("HEDGER_CONFIG").unwrap_or_else(|_| "Config.toml".into());
+        let (file, file_required) = match env::var("HEDGER_CONFIG") {
+            Ok(path) => (path, true),
+            Err(_) => ("Config.toml".to_string(), false),
+        };
+        Self::load_layered(File::with_name(&file).required(file_required), env_source())
+    }
+
+    fn load_layered<S>(file: S, env_overlay: Environment) -> Result<Self>
+    where
+        S: config::Source + Send + Sync + 'static,
+    {
         let loader = Loader::builder()
-            .add_source(File::with_name(&file).required(false))
-            .add_source(Environment::with_prefix("HEDGER").separator("__"))
+            .add_source(file)
+            .add_source(env_overlay)
             .build()?;
-        Ok(loader.try_deserialize()?)
+        let config: Self = loader.try_deserialize()?;
+        config.validate_order_types()?;
+        config.daily_digest_at()?;
+        Ok(config)
+    }
+
+    /// Конфиги запускаемых аккаунтов (имя, конфиг). Без [[accounts]] — единственный аккаунт "default".
+    /// Имена, токены ботов и пути к БД аккаунтов должны различаться
+    pub fn account_configs(&self) -> Result<Vec<(String, Config)>> {
+        if self.accounts.is_empty() {
+            return Ok(vec![("default".to_string(), self.clone())]);
+        }
+        let mut configs: Vec<(String, Config)> = Vec::with_capacity(self.accounts.len());
+        for account in &self.accounts {
+            let name = account.name.trim();
+            if name.is_empty() {
+                return Err(anyhow!("accounts: у аккаунта не задано имя (name)"));
+            }
+            let mut cfg = self.clone();
+            cfg.accounts.clear();
+            cfg.bybit_api_key = account.bybit_api_key.clone();
+            cfg.bybit_api_secret = account.bybit_api_secret.clone();
+            if let Some(quote) = &account.quote_currency {
+                cfg.quote_currency = quote.clone();
+            }
+            if let Some(token) = &account.telegram_token {
+                cfg.telegram_token = token.clone();
+            }
+            cfg.sqlite_path = account.sqlite_path.clone().unwrap_or_else(|| account_sqlite_path(&self.sqlite_path, name));
+
+            for (other_name, other) in &configs {
+                if other_name == name {
+                    return Err(anyhow!("accounts: имя аккаунта '{}' повторяется", name));
+                }
+                if other.telegram_token == cfg.telegram_token {
+                    return Err(anyhow!(
+                        "accounts: аккаунты '{}' и '{}' используют один telegram_token — задайте каждому своего бота",
+                        other_name, name
+                    ));
+                }
+                if other.sqlite_path == cfg.sqlite_path {
+                    return Err(anyhow!("accounts: аккаунты '{}' и '{}' используют одну БД {}", other_name, name, cfg.sqlite_path));
+                }
+            }
+            configs.push((name.to_string(), cfg));
+        }
+        Ok(configs)
+    }
+
+    /// Адрес REST API Bybit: bybit_base_url, иначе mainnet/testnet по use_testnet
+    pub fn bybit_api_url(&self) -> &str {
+        self.bybit_base_url
+            .as_deref()
+            .filter(|s| !s.is_empty())
+            .unwrap_or(if self.use_testnet { "https://api-testnet.bybit.com" } else { "https://api.bybit.com" })
+    }
+
+    /// Окружение биржи (testnet / mainnet), в котором исполняются операции
+    pub fn environment(&self) -> &'static str {
+        crate::exchange::bybit::environment_for_base_url(self.bybit_api_url())
+    }
+
+    /// Проверка сочетания типов ордеров по ногам
+    pub fn validate_order_types(&self) -> Result<()> {
+        let has_market_leg = self.spot_order_type == OrderType::Market || self.futures_order_type == OrderType::Market;
+        if has_market_leg && (self.hedge_strategy_default == HedgeStrategy::WebsocketChunks || self.use_websocket_hedge) {
+            return Err(anyhow!(
+                "spot_order_type/futures_order_type = \"market\" поддерживаются только стратегией sequential (hedge_strategy_default = \"websocketchunks\" и use_websocket_hedge работают лимитками)"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Время ежедневной сводки (местное, по utc_offset_hours); None — сводка отключена
+    pub fn daily_digest_at(&self) -> Result<Option<NaiveTime>> {
+        if !(-12..=14).contains(&self.utc_offset_hours) {
+            return Err(anyhow!("utc_offset_hours = {}: допустимо от -12 до 14", self.utc_offset_hours));
+        }
+        let Some(time) = self.daily_digest_time.as_deref().map(str::trim).filter(|t| !t.is_empty()) else {
+            return Ok(None);
+        };
+        NaiveTime::parse_from_str(time, "%H:%M")
+            .map(Some)
+            .map_err(|_| anyhow!("daily_digest_time = \"{}\": ожидается время в формате ЧЧ:ММ", time))
+    }
+
+    /// Действующий конфиг в TOML для /config: ключи API и токены ботов (в т.ч. в [[accounts]]) скрыты
+    pub fn redacted_toml(&self) -> Result<String> {
+        const REDACTED: &str = "***";
+        fn mask(secret: &mut String) {
+            if !secret.is_empty() {
+                *secret = REDACTED.to_string();
+            }
+        }
+        let mut cfg = self.clone();
+        mask(&mut cfg.bybit_api_key);
+        mask(&mut cfg.bybit_api_secret);
+        mask(&mut cfg.telegram_token);
+        for account in &mut cfg.accounts {
+            mask(&mut account.bybit_api_key);
+            mask(&mut account.bybit_api_secret);
+            if let Some(token) = account.telegram_token.as_mut() {
+                mask(token);
+            }
+        }
+        Ok(toml::to_string_pretty(&cfg)?)
+    }
+
+    /// Стратегия хеджа с учетом режимов: хедж только фьючерсом — всегда sequential, use_websocket_hedge — WS
+    pub fn effective_hedge_strategy(&self) -> HedgeStrategy {
+        if self.futures_only_hedge {
+            HedgeStrategy::Sequential
+        } else if self.use_websocket_hedge {
+            HedgeStrategy::WebsocketChunks
+        } else {
+            self.hedge_strategy_default
+        }
+    }
+
+    /// Расхедж через WS: включен use_websocket_hedge и операция не хедж только фьючерсом
+    /// (такой хедж расхеджируется последовательно)
+    pub fn unhedge_via_websocket(&self, futures_only: bool) -> bool {
+        self.use_websocket_hedge && !futures_only
+    }
+
+    /// Поиск переопределений для символа (принимает как "BTC", так и "BTCUSDT").
+    /// Полный символ важнее базовой монеты, точное совпадение ключа важнее регистронезависимого;
+    /// среди регистронезависимых совпадений берется наименьший ключ, чтобы порядок HashMap не влиял
+    pub fn symbol_settings(&self, symbol: &str) -> Option<&SymbolSettings> {
+        let base = symbol
+            .strip_suffix(self.quote_currency.as_str())
+            .filter(|b| !b.is_empty())
+            .unwrap_or(symbol);
+        [symbol, base].into_iter().find_map(|candidate| {
+            self.symbol_overrides.get(candidate).or_else(|| {
+                self.symbol_overrides
+                    .iter()
+                    .filter(|(key, _)| key.eq_ignore_ascii_case(candidate))
+                    .min_by(|(a, _), (b, _)| a.cmp(b))
+                    .map(|(_, settings)| settings)
+            })
+        })
+    }
+
+    /// Slippage для символа (с учетом symbol_overrides)
+    pub fn slippage_for(&self, symbol: &str) -> f64 {
+        self.symbol_settings(symbol)
+            .and_then(|s| s.slippage)
+            .unwrap_or(self.slippage)
+    }
+
+    /// Есть ли у чата доступ к административным командам
+    pub fn is_admin_chat(&self, chat_id: i64) -> bool {
+        self.allowed_chat_ids.contains(&chat_id)
+    }
+
+    /// max_wait_secs для символа (с учетом symbol_overrides)
+    pub fn max_wait_secs_for(&self, symbol: &str) -> u64 {
+        self.symbol_settings(symbol)
+            .and_then(|s| s.max_wait_secs)
+            .unwrap_or(self.max_wait_secs)
+    }
+
+    /// offset_points для символа (с учетом symbol_overrides)
+    pub fn offset_points_for(&self, symbol: &str) -> u32 {
+        self.symbol_settings(symbol)
+            .and_then(|s| s.offset_points)
+            .unwrap_or(self.offset_points)
+    }
+}
+
+/// Конфигурация для тестов других модулей: обязательные поля заполнены, extra_toml дописывается перед ними
+#[cfg(test)]
+pub(crate) fn test_config(extra_toml: &str) -> Config {
+    tests::load_from_str(&format!("{}\n{}", extra_toml, tests::BASE_TOML))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::FileFormat;
+
+    pub(super) const BASE_TOML: &str = r#"
+        bybit_api_key = ""
+        bybit_api_secret = ""
+        use_testnet = true
+        sqlite_path = "test.db"
+        telegram_token = ""
+        default_volatility = 0.6
+        offset_points = 10
+        quote_currency = "USDT"
+        slippage = 0.001
+        max_wait_secs = 30
+        max_allowed_leverage = 10.0
+
+        [symbol_overrides.BTC]
+        slippage = 0.0005
+        max_wait_secs = 10
+    "#;
+
+    fn env_from(vars: &[(&str, &str)]) -> Environment {
+        let map: config::Map<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        env_source().source(Some(map))
+    }
+
+    #[test]
+    fn config_can_come_from_environment_only() {
+        let env = env_from(&[
+            ("HEDGER__BYBIT_API_KEY", "env-key"),
+            ("HEDGER__BYBIT_API_SECRET", "env-secret"),
+            ("HEDGER__USE_TESTNET", "true"),
+            ("HEDGER__SQLITE_PATH", "/data/hedgehog.db"),
+            ("HEDGER__TELEGRAM_TOKEN", "123:abc"),
+            ("HEDGER__DEFAULT_VOLATILITY", "0.6"),
+            ("HEDGER__OFFSET_POINTS", "10"),
+            ("HEDGER__QUOTE_CURRENCY", "USDT"),
+            ("HEDGER__SLIPPAGE", "0.001"),
+            ("HEDGER__MAX_WAIT_SECS", "30"),
+            ("HEDGER__MAX_ALLOWED_LEVERAGE", "10"),
+            ("HEDGER__ALLOWED_CHAT_IDS", "1,-100200"),
+            ("HEDGER__SYMBOL_OVERRIDES__ETH__SLIPPAGE", "0.002"),
+        ]);
+
+        let cfg = Config::load_layered(File::from_str("", FileFormat::Toml), env).expect("env-only config");
+
+        assert_eq!(cfg.bybit_api_key, "env-key");
+        assert_eq!(cfg.telegram_token, "123:abc");
+        assert_eq!(cfg.offset_points, 10);
+        assert_eq!(cfg.allowed_chat_ids, vec![1, -100200]);
+        assert_eq!(cfg.slippage_for("ETH"), 0.002);
+    }
+
+    #[test]
+    fn environment_overrides_file_values() {
+        let env = env_from(&[("HEDGER__BYBIT_API_SECRET", "from-env"), ("HEDGER__MAX_WAIT_SECS", "45")]);
+
+        let cfg = Config::load_layered(File::from_str(BASE_TOML, FileFormat::Toml), env).expect("layered config");
+
+        assert_eq!(cfg.bybit_api_secret, "from-env");
+        assert_eq!(cfg.max_wait_secs, 45);
+        assert_eq!(cfg.slippage, 0.001);
+    }
+
+    const TWO_ACCOUNTS_TOML: &str = r#"
+        [[accounts]]
+        name = "main"
+        bybit_api_key = "key-main"
+        bybit_api_secret = "secret-main"
+        telegram_token = "1:main"
+
+        [[accounts]]
+        name = "sub"
+        bybit_api_key = "key-sub"
+        bybit_api_secret = "secret-sub"
+        quote_currency = "USDC"
+        telegram_token = "2:sub"
+        sqlite_path = "data/sub.db"
+    "#;
+
+    #[test]
+    fn accounts_inherit_shared_settings_and_keep_own_keys() {
+        let cfg = load_from_str(&format!("{}\n{}", BASE_TOML, TWO_ACCOUNTS_TOML));
+        let accounts = cfg.account_configs().expect("accounts");
+
+        assert_eq!(accounts.len(), 2);
+        let (main_name, main) = &accounts[0];
+        let (sub_name, sub) = &accounts[1];
+        assert_eq!((main_name.as_str(), sub_name.as_str()), ("main", "sub"));
+        assert_eq!((main.bybit_api_key.as_str(), sub.bybit_api_key.as_str()), ("key-main", "key-sub"));
+        assert_eq!((main.quote_currency.as_str(), sub.quote_currency.as_str()), ("USDT", "USDC"));
+        assert_eq!((main.sqlite_path.as_str(), sub.sqlite_path.as_str()), ("test-main.db", "data/sub.db"));
+        assert_eq!(sub.max_wait_secs, cfg.max_wait_secs);
+        assert!(main.accounts.is_empty());
+    }
+
+    #[test]
+    fn accounts_must_not_share_a_bot() {
+        let shared = TWO_ACCOUNTS_TOML.replace("\"2:sub\"", "\"1:main\"");
+        let cfg = load_from_str(&format!("{}\n{}", BASE_TOML, shared));
+        assert!(cfg.account_configs().is_err());
+
+        // Без [[accounts]] — один аккаунт из основных полей
+        let single = load_from_str(BASE_TOML).account_configs().expect("single account");
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].0, "default");
+    }
+
+    #[test]
+    fn daily_digest_time_is_validated() {
+        assert_eq!(load_from_str(BASE_TOML).daily_digest_at().expect("disabled"), None);
+        let enabled = load_from_str(&format!("daily_digest_time = \"21:30\"\nutc_offset_hours = 3\n{}", BASE_TOML));
+        assert_eq!(enabled.daily_digest_at().expect("valid"), NaiveTime::from_hms_opt(21, 30, 0));
+        assert!(load_from_str(&format!("daily_digest_time = \"25:00\"\n{}", BASE_TOML)).daily_digest_at().is_err());
+        assert!(load_from_str(&format!("utc_offset_hours = 20\n{}", BASE_TOML)).daily_digest_at().is_err());
+    }
+
+    #[test]
+    fn account_db_path_gets_name_suffix() {
+        assert_eq!(account_sqlite_path("hedgehog.db", "sub"), "hedgehog-sub.db");
+        assert_eq!(account_sqlite_path("data/hedgehog.db", "sub"), "data/hedgehog-sub.db");
+        assert_eq!(account_sqlite_path("./data/hedgehog", "sub"), "./data/hedgehog-sub");
+    }
+
+    pub(super) fn load_from_str(toml: &str) -> Config {
+        Loader::builder()
+            .add_source(File::from_str(toml, FileFormat::Toml))
+            .build()
+            .expect("config build")
+            .try_deserialize()
+            .expect("config deserialize")
+    }
+
+    #[test]
+    fn market_legs_are_rejected_for_websocket_strategy() {
+        let market_market = format!("spot_order_type = \"market\"\nfutures_order_type = \"market\"\n{}", BASE_TOML);
+        assert!(load_from_str(&market_market).validate_order_types().is_ok());
+
+        let ws_market = format!("hedge_strategy_default = \"websocketchunks\"\nfutures_order_type = \"market\"\n{}", BASE_TOML);
+        assert!(load_from_str(&ws_market).validate_order_types().is_err());
+
+        let ws_flag_market = format!("use_websocket_hedge = true\nspot_order_type = \"market\"\n{}", BASE_TOML);
+        assert!(load_from_str(&ws_flag_market).validate_order_types().is_err());
+    }
+
+    #[test]
+    fn websocket_flag_routes_hedge_and_unhedge() {
+        let polling = load_from_str(BASE_TOML);
+        assert_eq!(polling.effective_hedge_strategy(), HedgeStrategy::Sequential);
+        assert!(!polling.unhedge_via_websocket(false));
+
+        let ws = load_from_str(&format!("use_websocket_hedge = true\n{}", BASE_TOML));
+        assert_eq!(ws.effective_hedge_strategy(), HedgeStrategy::WebsocketChunks);
+        assert!(ws.unhedge_via_websocket(false));
+        // Хедж только фьючерсом (спот вне бота) остается на последовательном пути
+        assert!(!ws.unhedge_via_websocket(true));
+        let futures_only = load_from_str(&format!("use_websocket_hedge = true\nfutures_only_hedge = true\n{}", BASE_TOML));
+        assert_eq!(futures_only.effective_hedge_strategy(), HedgeStrategy::Sequential);
+    }
+
+    #[test]
+    fn leverage_mode_defaults_to_computed() {
+        let cfg = load_from_str(BASE_TOML);
+        assert_eq!(cfg.leverage_mode, LeverageMode::Computed);
+        assert_eq!(cfg.fixed_leverage, None);
+        let fixed = load_from_str(&format!("leverage_mode = \"fixed\"\nfixed_leverage = 3.0\n{}", BASE_TOML));
+        assert_eq!(fixed.leverage_mode, LeverageMode::Fixed);
+        assert_eq!(fixed.fixed_leverage, Some(3.0));
+        assert!(cfg.confirm_leverage_increase);
+    }
+
+    #[test]
+    fn redacted_toml_hides_secrets_and_lists_all_fields() {
+        let with_keys = BASE_TOML.replacen("bybit_api_key = \"\"", "bybit_api_key = \"key-root\"", 1);
+        let cfg = load_from_str(&format!("{}\n{}", with_keys, TWO_ACCOUNTS_TOML));
+
+        let text = cfg.redacted_toml().expect("toml");
+
+        for secret in ["key-root", "key-main", "secret-main", "1:main", "key-sub", "secret-sub", "2:sub"] {
+            assert!(!text.contains(secret), "secret {} leaked", secret);
+        }
+        assert!(text.contains("bybit_api_key = \"***\""));
+        // Пустой секрет остается пустым: видно, что он не задан
+        assert!(text.contains("telegram_token = \"\""));
+        // Поля со значениями по умолчанию тоже выводятся
+        assert!(text.contains("progress_edit_max_retries = 2"));
+        assert!(text.contains("max_wait_secs = 30"));
+        assert!(text.contains("name = \"sub\""));
+    }
+
+    #[test]
+    fn delete_user_messages_defaults_to_true() {
+        assert!(load_from_str(BASE_TOML).delete_user_messages);
+        let keep = load_from_str(&format!("delete_user_messages = false\n{}", BASE_TOML));
+        assert!(!keep.delete_user_messages);
+    }
+
+    #[test]
+    fn symbol_override_takes_precedence() {
+        let cfg = load_from_str(BASE_TOML);
+        assert_eq!(cfg.slippage_for("BTC"), 0.0005);
+        assert_eq!(cfg.slippage_for("BTCUSDT"), 0.0005);
+        assert_eq!(cfg.max_wait_secs_for("BTC"), 10);
+        // offset_points не переопределен для BTC -> глобальное значение
+        assert_eq!(cfg.offset_points_for("BTC"), 10);
+    }
+
+    #[test]
+    fn symbol_without_override_uses_global() {
+        let cfg = load_from_str(BASE_TOML);
+        assert_eq!(cfg.slippage_for("ETH"), 0.001);
+        assert_eq!(cfg.max_wait_secs_for("ETHUSDT"), 30);
+    }
+
+    #[test]
+    fn symbol_override_lookup_is_deterministic() {
+        let mut cfg = load_from_str(BASE_TOML);
+        let with_slippage = |slippage| SymbolSettings { slippage: Some(slippage), max_wait_secs: None, offset_points: None };
+        cfg.symbol_overrides.insert("btc".to_string(), with_slippage(0.004));
+        cfg.symbol_overrides.insert("BTCUSDT".to_string(), with_slippage(0.003));
+        cfg.symbol_overrides.insert("eth".to_string(), with_slippage(0.002));
+        cfg.symbol_overrides.insert("Eth".to_string(), with_slippage(0.001));
+
+        // Полный символ важнее базовой монеты, точный ключ важнее регистронезависимого
+        assert_eq!(cfg.slippage_for("BTCUSDT"), 0.003);
+        assert_eq!(cfg.slippage_for("BTC"), 0.0005);
+        // Среди регистронезависимых совпадений — наименьший ключ ("Eth" < "eth")
+        assert_eq!(cfg.slippage_for("ETHUSDT"), 0.001);
     }
 }
\ No newline at end of file
@@ -1,9 +1,13 @@
 use crate::exchange::types::{OrderStatus, FeeRate, FuturesTickerInfo};
+use crate::config::Config;
 use crate::exchange::Exchange;
+use crate::utils::{round_to_tick, with_retry, RetryPolicy};
 // --- ИСПРАВЛЕНО: Импортируем все нужные типы из types.rs ---
 use crate::exchange::types::{
     Balance, Order, OrderSide, DetailedOrderStatus, OrderStatusText,
-    SpotInstrumentInfo, LinearInstrumentInfo // Оставляем InstrumentInfo
+    SpotInstrumentInfo, LinearInstrumentInfo, SpotMarket, // Оставляем InstrumentInfo
+    MarginInfo, ExchangeError, PositionDetails, PriceSource, FundingRateStats, BelowMinimum, TimeSyncReport,
+    LeverageBracket, LeverageFilter,
 };
 // --- ИСПРАВЛЕНО: Добавляем импорт HedgeOperation из storage ---
 // --- Стандартные и внешние зависимости ---
@@ -14,6 +18,7 @@ use reqwest::{Client, Method};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use sha2::Sha256;
+use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
@@ -23,9 +28,17 @@ use rust_decimal::prelude::*;
 use rust_decimal_macros::dec;
 
 type HmacSha256 = Hmac<Sha256>;
+/// Категория -> (цены по символам, время запроса)
+type TickersCache = Arc<Mutex<HashMap<String, (HashMap<String, f64>, SystemTime)>>>;
+/// Список спотовых рынков и время запроса
+type SpotMarketsCache = Arc<Mutex<Option<(Vec<SpotMarket>, SystemTime)>>>;
 
 pub const SPOT_CATEGORY: &str = "spot";
 pub const LINEAR_CATEGORY: &str = "linear";
+/// Число начислений фандинга в сутки (каждые 8 часов)
+pub const FUNDING_INTERVALS_PER_DAY: u32 = 3;
+/// Максимум записей истории фандинга за один запрос
+pub const MAX_FUNDING_HISTORY_ENTRIES: u32 = 200;
 /// Универсальная обёртка для ответов Bybit API v5
 #[derive(Deserialize, Debug)]
 struct ApiResponse {
@@ -38,10 +51,34 @@ struct ApiResponse {
     #[serde(rename = "time")]
     _server_time: Option<i64>,
 }
-/// Пустой результат для запросов без данных
-#[derive(Deserialize, Debug, Default)]
+/// Пустой результат для запросов без данных (cancel, set-leverage, amend, trading-stop).
+/// Принимает любую форму 'result' (пустой объект, null, иная структура): данные не используются
+#[derive(Debug, Default)]
 struct EmptyResult {}
 
+impl<'de> Deserialize<'de> for EmptyResult {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        serde::de::IgnoredAny::deserialize(deserializer)?;
+        Ok(EmptyResult {})
+    }
+}
+
+/// Ответ по балансу субаккаунта (запрос мастер-ключом с memberId)
+#[derive(Deserialize, Debug, Default)]
+struct MemberCoinsBalanceResult {
+    #[serde(default)]
+    balance: Vec<MemberCoinBalance>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MemberCoinBalance {
+    coin: String,
+    #[serde(rename = "walletBalance", default)]
+    wallet_balance: String,
+    #[serde(rename = "transferBalance", default)]
+    transfer_balance: String,
+}
+
 /// Ответ по балансу
 #[derive(Deserialize, Debug, Default)]
 struct BalanceResult {
@@ -55,6 +92,22 @@ struct UnifiedAccountBalance {
     account_type: String,
     #[serde(rename = "coin")]
     coins: Vec<BalanceEntry>,
+    // --- Поля маржи аккаунта (могут приходить пустой строкой) ---
+    #[serde(rename = "accountMMRate", default)]
+    account_mm_rate: Option<String>,
+    #[serde(rename = "accountIMRate", default)]
+    account_im_rate: Option<String>,
+    #[serde(rename = "totalMaintenanceMargin", default)]
+    total_maintenance_margin: Option<String>,
+    #[serde(rename = "totalInitialMargin", default)]
+    total_initial_margin: Option<String>,
+    #[serde(rename = "totalEquity", default)]
+    total_equity: Option<String>,
+}
+
+/// Парсинг числового поля Bybit, которое может быть пустой строкой
+fn parse_optional_f64(value: &Option<String>) -> f64 {
+    value.as_deref().and_then(|v| v.parse::<f64>().ok()).unwrap_or(0.0)
 }
 
 #[derive(Deserialize, Debug)]
@@ -75,6 +128,12 @@ struct SpotInstrumentsInfoResult {
     list: Vec<SpotInstrumentInfo>, // Используем импортированный тип
 }
 
+/// Полный список спотовых пар (без фильтра по символу)
+#[derive(Deserialize, Debug, Clone, Default)]
+struct SpotMarketsResult {
+    list: Vec<SpotMarket>,
+}
+
 // --- Структуры для информации об инструменте ЛИНЕЙНОМ ---
 #[derive(Deserialize, Debug, Clone, Default)]
 struct LinearInstrumentsInfoResult {
@@ -93,7 +152,7 @@ struct RiskLimitEntry {
     id: u64,
     symbol: String,
     #[serde(rename = "riskLimitValue")]
-    _risk_limit_value: String,
+    risk_limit_value: String,
     #[serde(rename = "maintenanceMargin")]
     mmr: String,
     #[serde(rename = "initialMargin")]
@@ -101,7 +160,7 @@ struct RiskLimitEntry {
     #[serde(rename = "isLowestRisk")]
     is_lowest_risk: u8,
     #[serde(rename = "maxLeverage")]
-    _max_leverage: String,
+    max_leverage: String,
 }
 
 /// Ответ по funding‑rate
@@ -162,6 +221,131 @@ struct TickersResult {
     list: Vec<TickerInfo>,
 }
 
+/// Объем ордера по шагу инструмента (basePrecision/qtyStep) с проверкой minOrderQty — общий для всех place_*.
+/// Округление вниз; если округление "срезало" объем, который сам не меньше минимума, берется минимум.
+/// Нулевой или меньший минимума объем — ошибка BelowMinimum
+fn validate_and_format_qty(qty: f64, step: &str, min_qty: &str) -> Result<String> {
+    let qty_d = match Decimal::from_f64(qty) {
+        Some(qty_d) if qty_d >= Decimal::ZERO => qty_d,
+        _ => return Err(anyhow!("Invalid qty value {}", qty)),
+    };
+    let qty_decimals = step.split('.').nth(1).map_or(0, |s| s.trim_end_matches('0').len()) as u32;
+    let min_qty = Decimal::from_str(min_qty).unwrap_or(Decimal::ZERO);
+    let rounded = qty_d.trunc_with_scale(qty_decimals);
+    let below_minimum = || BelowMinimum { requested: qty, rounded, min_qty, step: step.to_string() };
+
+    if rounded < min_qty && qty_d >= min_qty {
+        warn!("Order quantity {} rounded down below min_order_qty {}. Using min_order_qty.", qty, min_qty);
+        return Ok(min_qty.normalize().to_string());
+    }
+    if rounded <= Decimal::ZERO || rounded < min_qty {
+        return Err(below_minimum().into());
+    }
+    Ok(rounded.normalize().to_string())
+}
+
+/// MMR из ответа risk-limit. Пустое значение: настроенный fallback (с предупреждением),
+/// на testnet — 0.005, иначе ошибка
+fn resolve_mmr(symbol: &str, raw_mmr: &str, is_testnet: bool, fallback: Option<f64>) -> Result<f64> {
+    if !raw_mmr.is_empty() {
+        return raw_mmr.parse::<f64>().map_err(|e| anyhow!("Failed to parse MMR for {}: {}", symbol, e));
+    }
+    match fallback {
+        Some(mmr) => {
+            warn!("MMR (maintenanceMargin) is empty for {}! Using configured default_mmr_fallback {} — margin estimates may be off", symbol, mmr);
+            Ok(mmr)
+        }
+        None if is_testnet => {
+            warn!("MMR is empty for {} on testnet, using fallback 0.005", symbol);
+            Ok(0.005)
+        }
+        None => Err(anyhow!("MMR (maintenanceMargin) is empty for {}", symbol)),
+    }
+}
+
+/// Ступень риск-лимита для стоимости позиции: первая (по возрастанию границы), вмещающая позицию;
+/// позиция больше всех границ — последняя ступень
+fn select_leverage_bracket(mut brackets: Vec<LeverageBracket>, position_value: f64) -> Option<LeverageBracket> {
+    brackets.sort_by(|a, b| a.risk_limit_value.total_cmp(&b.risk_limit_value));
+    brackets
+        .iter()
+        .find(|bracket| position_value <= bracket.risk_limit_value)
+        .or_else(|| brackets.last())
+        .copied()
+}
+
+/// Плечо для set-leverage по leverageFilter: кратное leverageStep в пределах [minLeverage, maxLeverage].
+/// Требуемое плечо на шаге (с точностью до погрешности f64) не меняется, иначе округляется вверх до шага:
+/// меньшее плечо не покроет позицию маржой, поэтому направление округления в конфиге не настраивается.
+/// Требуемое выше maxLeverage — ошибка. Решение по плечу (hedger::decide_leverage) округляет так же до подтверждения
+pub fn leverage_for_step(required: f64, filter: &LeverageFilter) -> Result<Decimal> {
+    let parse = |name: &str, value: &str| Decimal::from_str(value).map_err(|e| anyhow!("Invalid {} '{}': {}", name, value, e));
+    let step = parse("leverageStep", &filter.leverage_step)?;
+    let min = parse("minLeverage", &filter.min_leverage)?;
+    let max = parse("maxLeverage", &filter.max_leverage)?;
+    if step <= Decimal::ZERO {
+        return Err(anyhow!("Invalid leverageStep '{}'", filter.leverage_step));
+    }
+    let required_decimal = Decimal::from_f64(required).ok_or_else(|| anyhow!("Invalid leverage {}", required))?;
+    let steps = required_decimal / step;
+    let whole_steps = if steps - steps.floor() <= dec!(0.000001) { steps.floor() } else { steps.ceil() };
+    let leverage = (whole_steps * step).max(min);
+    if leverage > max {
+        return Err(anyhow!("Required leverage {:.2}x exceeds max leverage {}x for the symbol", required, max.normalize()));
+    }
+    Ok(leverage.normalize())
+}
+
+/// Тело запроса отмены по клиентскому ID: orderLinkId вместо orderId
+fn cancel_by_link_id_body(category: &str, api_symbol: &str, link_id: &str) -> Value {
+    json!({ "category": category, "symbol": api_symbol, "orderLinkId": link_id })
+}
+
+/// Клиентский orderLinkId в теле v5/order/create (если задан)
+fn set_order_link_id(body: &mut Value, link_id: Option<&str>) {
+    if let Some(link_id) = link_id {
+        body["orderLinkId"] = json!(link_id);
+    }
+}
+
+/// retCode Bybit "orderLinkId уже использован": linear (110072) и spot (170141)
+const DUPLICATE_LINK_ID_RET_CODES: [i64; 2] = [110072, 170141];
+
+/// Отказ из-за повторного orderLinkId — ордер с этим ID уже создан
+fn is_duplicate_link_id(error: &anyhow::Error) -> bool {
+    matches!(error.downcast_ref::<ExchangeError>(), Some(ExchangeError::Api { code, .. }) if DUPLICATE_LINK_ID_RET_CODES.contains(code))
+}
+
+/// Эндпоинты аккаунта API-ключа: memberId в них Bybit не принимает, и с выбранным субаккаунтом
+/// ордера, позиции, плечо и маржа ушли бы на мастер-аккаунт
+const MASTER_ACCOUNT_ENDPOINTS: [&str; 3] = ["v5/order/", "v5/position/", "v5/account/wallet-balance"];
+
+/// Сколько живет кэш списка спотовых пар (листинги меняются редко)
+const SPOT_MARKETS_CACHE_TTL: Duration = Duration::from_secs(600);
+
+/// Сколько живет кэш цен всех тикеров категории
+const ALL_TICKERS_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Попыток GET-запроса при временной ошибке (включая первую) и пауза перед первым повтором
+const API_READ_ATTEMPTS: u32 = 3;
+const API_READ_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Повторы вызова API: только GET (чтение) при временной ошибке. POST (ордера, отмены, плечо) выполняется
+/// один раз — у размещения свои повторы с orderLinkId и бюджетом операции (hedger::common)
+fn api_retry_policy(method: &Method) -> RetryPolicy {
+    let attempts = if *method == Method::GET { API_READ_ATTEMPTS } else { 1 };
+    RetryPolicy::new("Bybit API request", attempts, API_READ_RETRY_DELAY)
+        .with_jitter(API_READ_RETRY_DELAY / 2)
+        .retry_if(ExchangeError::is_transient)
+}
+
+/// Карта пара -> последняя цена; тикеры без сделок (цена 0 или пустая) пропускаются
+fn ticker_price_map(list: Vec<TickerInfo>) -> HashMap<String, f64> {
+    list.into_iter()
+        .filter_map(|t| t.price.trim().parse::<f64>().ok().filter(|p| *p > 0.0).map(|p| (t.symbol, p)))
+        .collect()
+}
+
 #[derive(Deserialize,Serialize, Debug)]
 struct TickerInfo {
     symbol: String,
@@ -169,6 +353,31 @@ struct TickerInfo {
     price: String,
 }
 
+/// Тикер для опорной цены (spot или linear); индекс есть только у деривативов
+#[derive(Deserialize, Debug)]
+struct ReferenceTickerInfo {
+    symbol: String,
+    #[serde(rename = "lastPrice")]
+    last_price: String,
+    #[serde(rename = "bid1Price", default)]
+    bid1_price: Option<String>,
+    #[serde(rename = "ask1Price", default)]
+    ask1_price: Option<String>,
+    #[serde(rename = "indexPrice", default)]
+    index_price: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct ReferenceTickersResult {
+    list: Vec<ReferenceTickerInfo>,
+}
+
+/// Разбор необязательного поля цены тикера (пустая строка — нет значения)
+fn parse_ticker_price(value: Option<&str>, field: &str, symbol: &str) -> Result<f64> {
+    let raw = value.map(str::trim).filter(|v| !v.is_empty()).ok_or_else(|| anyhow!("Ticker for {} has no {}", symbol, field))?;
+    raw.parse::<f64>().map_err(|e| anyhow!("Failed to parse {} for {}: {} (value: '{}')", field, symbol, e, raw))
+}
+
 // Структуры для фьючерсного тикера
 #[derive(Deserialize, Debug)]
 struct FuturesTickerApiResponse {
@@ -211,7 +420,7 @@ struct FeeRateEntry {
     maker_fee_rate: String,
 }
 
-/// Ответ по информации о позиции (для плеча)
+/// Ответ по информации о позиции (плечо, размер)
 #[derive(Deserialize, Debug, Default)]
 struct PositionInfoResult {
     list: Vec<PositionEntry>,
@@ -221,8 +430,14 @@ struct PositionInfoResult {
 struct PositionEntry {
     symbol: String,
     leverage: String,
+    #[serde(default)]
+    side: String, // "Buy" / "Sell" / "" (нет позиции)
+    #[serde(default)]
+    size: String,
+    #[serde(rename = "avgPrice", default)]
+    avg_price: String,
     #[serde(rename = "positionIdx", default)]
-    _position_idx: i32,
+    position_idx: i32,
     #[serde(rename = "riskId", default)]
     _risk_id: u64,
     #[serde(rename = "riskLimitValue", default)]
@@ -230,6 +445,105 @@ struct PositionEntry {
 }
 
 
+/// Ответ по залоговой информации (ставки займа)
+#[derive(Deserialize, Debug, Default)]
+struct CollateralInfoResult {
+    #[serde(default)]
+    list: Vec<CollateralInfoEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CollateralInfoEntry {
+    currency: String,
+    #[serde(rename = "hourlyBorrowRate", default)]
+    hourly_borrow_rate: String,
+    #[serde(default)]
+    borrowable: bool,
+}
+
+
+/// Окружение биржи по адресу API: "testnet" для тестовой сети, иначе "mainnet".
+/// Пишется в операции, чтобы не расхеджировать тестовую операцию боевым ключом и наоборот
+pub fn environment_for_base_url(base_url: &str) -> &'static str {
+    if base_url.to_ascii_lowercase().contains("testnet") { "testnet" } else { "mainnet" }
+}
+
+/// Политика начальной синхронизации времени с сервером
+#[derive(Debug, Clone, Copy)]
+pub struct TimeSyncPolicy {
+    pub retries: u32,          // Повторов после первой неудачной попытки
+    pub retry_delay: Duration, // Начальная пауза (удваивается с каждой попыткой)
+    pub required: bool,        // true — без синхронизации клиент не создается
+}
+
+impl Default for TimeSyncPolicy {
+    fn default() -> Self {
+        Self { retries: 3, retry_delay: Duration::from_secs(1), required: true }
+    }
+}
+
+impl TimeSyncPolicy {
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::new("Initial time sync", self.retries + 1, self.retry_delay)
+    }
+}
+
+/// Режим позиций linear-символа на аккаунте: one-way (positionIdx 0) или hedge-mode (1 — лонг, 2 — шорт)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PositionMode {
+    OneWay,
+    Hedge,
+}
+
+impl PositionMode {
+    /// В hedge-mode position/list возвращает отдельные записи по сторонам с positionIdx 1 и 2
+    fn from_position_indices(indices: impl IntoIterator<Item = i32>) -> Self {
+        if indices.into_iter().any(|idx| idx != 0) { PositionMode::Hedge } else { PositionMode::OneWay }
+    }
+
+    /// Запись позиции бота: в hedge-mode — шортовая сторона (positionIdx 2), а не первая открытая,
+    /// в one-way — единственная запись символа
+    fn select_position_entry(entries: Vec<PositionEntry>, symbol: &str) -> Option<PositionEntry> {
+        let entries: Vec<PositionEntry> = entries.into_iter().filter(|p| p.symbol == symbol).collect();
+        let (position_idx, _) = Self::from_position_indices(entries.iter().map(|p| p.position_idx)).order_position_params(OrderSide::Sell);
+        entries.into_iter().find(|p| p.position_idx == position_idx)
+    }
+
+    /// positionIdx и reduceOnly фьючерсного ордера. Бот держит только шорт: в hedge-mode продажа
+    /// открывает шортовую сторону (2), покупка закрывает ее же
+    fn order_position_params(self, side: OrderSide) -> (i32, bool) {
+        match self {
+            PositionMode::OneWay => (0, false),
+            PositionMode::Hedge => (2, side == OrderSide::Buy),
+        }
+    }
+}
+
+/// Пул HTTP-соединений: сколько простаивающих соединений держать на хост, сколько они живут и TCP keepalive
+#[derive(Debug, Clone, Copy)]
+pub struct HttpPoolPolicy {
+    pub max_idle_per_host: usize,
+    pub idle_timeout: Duration,
+    pub tcp_keepalive: Option<Duration>, // None — keepalive выключен
+}
+
+impl Default for HttpPoolPolicy {
+    fn default() -> Self {
+        Self { max_idle_per_host: 16, idle_timeout: Duration::from_secs(90), tcp_keepalive: Some(Duration::from_secs(60)) }
+    }
+}
+
+impl HttpPoolPolicy {
+    fn build_client(&self) -> Result<Client> {
+        Ok(Client::builder()
+            .timeout(Duration::from_secs(10))
+            .pool_max_idle_per_host(self.max_idle_per_host)
+            .pool_idle_timeout(self.idle_timeout)
+            .tcp_keepalive(self.tcp_keepalive)
+            .build()?)
+    }
+}
+
 /// Клиент Bybit
 #[derive(Debug, Clone)]
 pub struct Bybit {
@@ -241,11 +555,29 @@ pub struct Bybit {
     quote_currency: String,
     time_offset_ms: Arc<Mutex<Option<i64>>>,
     balance_cache: Arc<Mutex<Option<(Vec<(String, Balance)>, SystemTime)>>>,
+    tickers_cache: TickersCache,
+    spot_markets_cache: SpotMarketsCache,
+    position_modes: Arc<Mutex<HashMap<String, PositionMode>>>, // linear-символ -> режим позиций
+    member_id: Option<String>, // UID субаккаунта для запросов мастер-ключом (только балансы)
+    log_api_bodies_on_error: bool, // Логировать полный запрос/ответ при retCode != 0 независимо от уровня логов
+    mmr_fallback: Option<f64>, // MMR, если биржа вернула пустое значение (None — ошибка на mainnet)
 }
 
 impl Bybit {
-    /// Создаёт новый экземпляр клиента и синхронизирует время
+    /// Создаёт новый экземпляр клиента и синхронизирует время (политика по умолчанию)
     pub async fn new(key: &str, secret: &str, base_url: &str, quote_currency: &str) -> Result<Self> {
+        Self::with_policies(key, secret, base_url, quote_currency, TimeSyncPolicy::default(), HttpPoolPolicy::default()).await
+    }
+
+    /// Создаёт клиент с заданными пулом соединений и повторами начальной синхронизации времени
+    pub async fn with_policies(
+        key: &str,
+        secret: &str,
+        base_url: &str,
+        quote_currency: &str,
+        time_sync: TimeSyncPolicy,
+        http_pool: HttpPoolPolicy,
+    ) -> Result<Self> {
         info!(base_url, quote_currency, "Initializing Bybit client...");
         if !base_url.starts_with("http") {
             error!("Invalid base URL provided: {}", base_url);
@@ -255,9 +587,7 @@ impl Bybit {
             error!("Quote currency cannot be empty");
             return Err(anyhow!("Quote currency cannot be empty"));
         }
-        let client = Client::builder()
-            .timeout(Duration::from_secs(10))
-            .build()?;
+        let client = http_pool.build_client()?;
 
         let instance = Self {
             api_key: key.into(),
@@ -268,28 +598,337 @@ impl Bybit {
             quote_currency: quote_currency.to_uppercase(),
             time_offset_ms: Arc::new(Mutex::new(None)),
             balance_cache: Arc::new(Mutex::new(None)),
+            tickers_cache: Arc::default(),
+            spot_markets_cache: Arc::default(),
+            position_modes: Arc::default(),
+            member_id: None,
+            log_api_bodies_on_error: false,
+            mmr_fallback: None,
         };
 
-        if let Err(e) = instance.sync_time().await {
-            error!("Initial time sync failed: {}. Authentication might fail later.", e);
-        } else {
-            info!("Initial time sync successful.");
+        match with_retry(time_sync.retry_policy(), || instance.sync_time()).await {
+            Ok(()) => info!("Initial time sync successful."),
+            Err(e) if time_sync.required => {
+                error!("Initial time sync failed after {} attempts: {}", time_sync.retries + 1, e);
+                return Err(anyhow!("Initial time sync with Bybit failed: {}", e));
+            }
+            Err(e) => error!("Initial time sync failed: {}. Will retry on the first signed request.", e),
         }
 
         info!("Bybit client initialized successfully.");
         Ok(instance)
     }
 
+    /// Клиент аккаунта из конфига: адрес API, ключи, котируемая валюта, пул соединений и синхронизация времени.
+    /// У каждого вызова свои соединения и кэши — клиенты разных аккаунтов независимы
+    pub async fn from_config(cfg: &Config) -> Result<Self> {
+        let base_url = cfg.bybit_api_url();
+        info!("Using Bybit base URL: {} ({})", base_url, environment_for_base_url(base_url));
+        let time_sync = TimeSyncPolicy {
+            retries: cfg.time_sync_retries,
+            retry_delay: Duration::from_millis(cfg.time_sync_retry_delay_ms),
+            required: cfg.time_sync_required,
+        };
+        let http_pool = HttpPoolPolicy {
+            max_idle_per_host: cfg.http_pool_max_idle_per_host,
+            idle_timeout: Duration::from_secs(cfg.http_pool_idle_timeout_secs),
+            tcp_keepalive: (cfg.http_tcp_keepalive_secs > 0).then(|| Duration::from_secs(cfg.http_tcp_keepalive_secs)),
+        };
+        Ok(Self::with_policies(&cfg.bybit_api_key, &cfg.bybit_api_secret, base_url, &cfg.quote_currency, time_sync, http_pool)
+            .await?
+            .with_member_id(cfg.bybit_member_id.clone())
+            .with_error_body_logging(cfg.log_api_bodies_on_error)
+            .with_mmr_fallback(cfg.default_mmr_fallback))
+    }
+
+    /// Выбор субаккаунта (memberId) для запросов мастер-ключом.
+    /// Bybit принимает memberId только в asset-эндпоинтах, поэтому с ним доступны лишь балансы субаккаунта;
+    /// ордера, позиции, плечо и маржа отклоняются (см. ensure_not_master_scoped) — для торговли нужен ключ субаккаунта
+    pub fn with_member_id(mut self, member_id: Option<String>) -> Self {
+        self.member_id = member_id.filter(|id| !id.trim().is_empty());
+        if let Some(member_id) = &self.member_id {
+            warn!(%member_id, "Sub-account selected: only balances are available, order and position requests will be rejected.");
+        }
+        self
+    }
+
+    /// Логирование полного запроса и ответа для неуспешных вызовов (ключ и подпись скрываются)
+    pub fn with_error_body_logging(mut self, enabled: bool) -> Self {
+        self.log_api_bodies_on_error = enabled;
+        self
+    }
+
+    /// MMR на случай пустого maintenanceMargin в ответе risk-limit (по умолчанию на mainnet это ошибка)
+    pub fn with_mmr_fallback(mut self, mmr_fallback: Option<f64>) -> Self {
+        self.mmr_fallback = mmr_fallback;
+        self
+    }
+
+    /// Сброс кэша балансов: после размещения/отмены ордера средства переходят между free и locked
+    pub async fn invalidate_balance_cache(&self) {
+        *self.balance_cache.lock().await = None;
+        debug!("Balance cache invalidated.");
+    }
+
+    /// Добавляет memberId к параметрам запроса, если субаккаунт выбран
+    fn member_scoped_query<'a>(&'a self, query: &[(&'a str, &'a str)]) -> Vec<(&'a str, &'a str)> {
+        let mut scoped = query.to_vec();
+        if let Some(member_id) = &self.member_id {
+            scoped.push(("memberId", member_id.as_str()));
+        }
+        scoped
+    }
+
+    /// С выбранным субаккаунтом запросы к эндпоинтам аккаунта ключа отклоняются: иначе торговля
+    /// и позиции молча шли бы на мастер-аккаунт, а балансы — на субаккаунт
+    fn ensure_not_master_scoped(&self, endpoint: &str) -> Result<()> {
+        let Some(member_id) = &self.member_id else { return Ok(()) };
+        let endpoint = endpoint.trim_start_matches('/');
+        if MASTER_ACCOUNT_ENDPOINTS.iter().any(|prefix| endpoint.starts_with(prefix)) {
+            return Err(anyhow!(
+                "bybit_member_id = {} is set, but {} cannot be scoped to a sub-account; use the sub-account's own API key for trading",
+                member_id, endpoint
+            ));
+        }
+        Ok(())
+    }
+
+    /// Балансы субаккаунта через asset-эндпоинт (walletBalance/transferBalance)
+    async fn get_member_balances(&self) -> Result<Vec<(String, Balance)>> {
+        let query = self.member_scoped_query(&[("accountType", "UNIFIED")]);
+        let res: MemberCoinsBalanceResult = self.call_api(Method::GET, "v5/asset/transfer/query-account-coins-balance", Some(&query), None, true).await?;
+        let mut balances = Vec::new();
+        for entry in res.balance {
+            let total = entry.wallet_balance.parse::<f64>().unwrap_or(0.0);
+            let free = entry.transfer_balance.parse::<f64>().unwrap_or(total).min(total).max(0.0);
+            let locked = (total - free).max(0.0);
+            if total > 1e-9 {
+                balances.push((entry.coin, Balance { free, locked }));
+            }
+        }
+        Ok(balances)
+    }
+
     /// Формирует полный URL эндпоинта
     fn url(&self, ep: &str) -> String {
         format!("{}/{}", self.base_url, ep.trim_start_matches('/'))
     }
 
-    /// Формирует символ пары (например, BTC + USDT -> BTCUSDT)
+    /// Формирует символ пары (например, BTC + USDT -> BTCUSDT).
+    /// Датированный контракт (BTCUSDT-26DEC25) уже является полным символом и не меняется
     fn format_pair(&self, base_symbol: &str) -> String {
+        if is_dated_contract(base_symbol) {
+            return base_symbol.to_uppercase();
+        }
         format!("{}{}", base_symbol.to_uppercase(), self.quote_currency)
     }
 
+    /// Символ для get_linear_instrument_info по символу фьючерса (см. linear_info_symbol)
+    fn linear_info_symbol<'a>(&self, symbol: &'a str) -> Result<&'a str> {
+        linear_info_symbol(symbol, &self.quote_currency)
+    }
+
+    /// Режим позиций символа (кэшируется); не удалось определить — one-way, как у аккаунта по умолчанию
+    async fn position_mode(&self, symbol: &str) -> PositionMode {
+        if let Some(mode) = self.position_modes.lock().await.get(symbol) {
+            return *mode;
+        }
+        let params = [("category", LINEAR_CATEGORY), ("symbol", symbol)];
+        match self.call_api::<PositionInfoResult>(Method::GET, "v5/position/list", Some(&params), None, true).await {
+            Ok(result) => {
+                let mode = PositionMode::from_position_indices(result.list.iter().filter(|p| p.symbol == symbol).map(|p| p.position_idx));
+                info!(symbol=%symbol, ?mode, "Detected futures position mode");
+                self.position_modes.lock().await.insert(symbol.to_string(), mode);
+                mode
+            }
+            Err(e) => {
+                warn!(symbol=%symbol, "Failed to detect position mode: {}. Assuming one-way.", e);
+                PositionMode::OneWay
+            }
+        }
+    }
+
+    /// Размещение спотовой лимитки; с link_id в теле передаётся orderLinkId,
+    /// а ответ "дубликат orderLinkId" означает, что ордер уже создан предыдущей попыткой
+    async fn place_spot_limit_order(&self, symbol: &str, side: OrderSide, qty: f64, price: f64, link_id: Option<&str>) -> Result<Order> {
+        let spot_pair = self.format_pair(symbol);
+
+        let instrument_info = self.get_spot_instrument_info(symbol).await
+            .map_err(|e| anyhow!("Failed to get instrument info for {}: {}", symbol, e))?;
+        let tick_size_str = &instrument_info.price_filter.tick_size;
+        let base_precision_str = instrument_info.lot_size_filter.base_precision
+            .as_deref()
+            .ok_or_else(|| anyhow!("Missing basePrecision for spot symbol {}", spot_pair))?;
+        let min_order_qty_str = &instrument_info.lot_size_filter.min_order_qty;
+
+        let tick_size = Decimal::from_str(tick_size_str)
+            .map_err(|e| anyhow!("Invalid tickSize '{}' for {}: {}", tick_size_str, symbol, e))?;
+        let formatted_price = match Decimal::from_f64(price) {
+            Some(d) => round_to_tick(d, tick_size, Some(side)).to_string(),
+            None => return Err(anyhow!("Invalid price value {}", price)),
+        };
+
+        let formatted_qty = validate_and_format_qty(qty, base_precision_str, min_order_qty_str)?;
+
+        info!(symbol=%spot_pair, %side, %formatted_qty, %formatted_price, category=SPOT_CATEGORY, "Placing SPOT limit order");
+        let mut body = json!({ "category": SPOT_CATEGORY, "symbol": spot_pair, "side": side.to_string(), "orderType": "Limit", "qty": formatted_qty, "price": formatted_price, "timeInForce": "GTC" });
+        set_order_link_id(&mut body, link_id);
+        let result = match self.call_api::<OrderCreateResult>(Method::POST, "v5/order/create", None, Some(body), true).await {
+            Err(e) if is_duplicate_link_id(&e) => self.find_order_by_link_id(SPOT_CATEGORY, &spot_pair, link_id).await?,
+            other => other?,
+        };
+        self.invalidate_balance_cache().await;
+        info!(order_id=%result.id, "SPOT limit order placed successfully");
+        Ok(Order { id: result.id, side, qty, price: Some(price), ts: self.get_timestamp_ms().await? })
+    }
+
+    /// Размещение фьючерсного рыночного ордера; reduce_only — ордер только сокращает позицию
+    async fn place_linear_market_order(&self, symbol: &str, side: OrderSide, qty: f64, reduce_only: bool) -> Result<Order> {
+        let base_symbol = self.linear_info_symbol(symbol)?;
+
+        let instrument_info = self.get_linear_instrument_info(base_symbol).await?;
+        let qty_step_str = instrument_info.lot_size_filter.qty_step.as_deref().ok_or_else(|| anyhow!("Missing qtyStep for linear symbol {}", symbol))?;
+        let min_order_qty_str = &instrument_info.lot_size_filter.min_order_qty;
+
+        let formatted_qty = validate_and_format_qty(qty, qty_step_str, min_order_qty_str)?;
+
+        info!(
+            target: "bybit_futures_order",
+            symbol=%symbol,
+            side=%side,
+            original_qty=qty,
+            formatted_qty=%formatted_qty,
+            reduce_only,
+            category=LINEAR_CATEGORY,
+            "Preparing FUTURES market order parameters"
+        );
+
+        let mut body = json!({
+            "category": LINEAR_CATEGORY,
+            "symbol": symbol,
+            "side": side.to_string(),
+            "orderType": "Market",
+            "qty": formatted_qty
+        });
+        self.apply_position_params(symbol, side, &mut body).await;
+        if reduce_only {
+            body["reduceOnly"] = json!(true);
+        }
+
+        let body_string = serde_json::to_string(&body).unwrap_or_else(|_| "Failed to serialize body".to_string());
+        info!(
+            target: "bybit_futures_order",
+            request_body=%body_string,
+            "Sending FUTURES market order request"
+        );
+
+        let result = self.create_futures_order(symbol, body).await?;
+        self.invalidate_balance_cache().await;
+
+        info!(
+            target: "bybit_futures_order",
+            order_id = %result.id,
+            "FUTURES market order placed successfully: order_id={}", result.id
+        );
+
+        Ok(Order { id: result.id, side, qty, price: None, ts: self.get_timestamp_ms().await? })
+    }
+
+    /// Размещение фьючерсной лимитки; с link_id в теле передаётся orderLinkId,
+    /// а ответ "дубликат orderLinkId" означает, что ордер уже создан предыдущей попыткой
+    async fn place_linear_limit_order(&self, symbol: &str, side: OrderSide, qty: f64, price: f64, link_id: Option<&str>) -> Result<Order> {
+        let base_symbol = self.linear_info_symbol(symbol)?;
+
+        let instrument_info = self.get_linear_instrument_info(base_symbol).await?;
+        let qty_step_str = instrument_info.lot_size_filter.qty_step.as_deref().ok_or_else(|| anyhow!("Missing qtyStep for linear symbol {}", symbol))?;
+        let min_order_qty_str = &instrument_info.lot_size_filter.min_order_qty;
+        let tick_size_str = &instrument_info.price_filter.tick_size;
+
+        let formatted_qty = validate_and_format_qty(qty, qty_step_str, min_order_qty_str)?;
+
+        let tick_size = Decimal::from_str(tick_size_str)
+            .map_err(|e| anyhow!("Invalid tickSize '{}' for {}: {}", tick_size_str, symbol, e))?;
+        let formatted_price = match Decimal::from_f64(price) {
+            Some(d) => round_to_tick(d, tick_size, Some(side)).to_string(),
+            None => return Err(anyhow!("Invalid price value {}", price)),
+        };
+
+        info!(
+            target: "bybit_futures_order", symbol=%symbol, side=%side, order_type="Limit",
+            original_qty=qty, formatted_qty=%formatted_qty, original_price=price,
+            formatted_price=%formatted_price, category=LINEAR_CATEGORY,
+            "Preparing FUTURES limit order parameters"
+        );
+
+        let mut body = json!({
+            "category": LINEAR_CATEGORY, "symbol": symbol, "side": side.to_string(),
+            "orderType": "Limit", "qty": formatted_qty, "price": formatted_price,
+            "timeInForce": "GTC"
+        });
+        self.apply_position_params(symbol, side, &mut body).await;
+        set_order_link_id(&mut body, link_id);
+
+        let body_string = serde_json::to_string(&body).unwrap_or_else(|_| "Failed to serialize body".to_string());
+        info!(
+            target: "bybit_futures_order", request_body=%body_string,
+            "Sending FUTURES limit order request"
+        );
+
+        let result = match self.create_futures_order(symbol, body).await {
+            Err(e) if is_duplicate_link_id(&e) => self.find_order_by_link_id(LINEAR_CATEGORY, symbol, link_id).await?,
+            other => other?,
+        };
+        self.invalidate_balance_cache().await;
+
+        info!(
+            target: "bybit_futures_order", order_id = %result.id,
+            "FUTURES limit order placed successfully: order_id={}", result.id
+        );
+        Ok(Order { id: result.id, side, qty, price: Some(price), ts: self.get_timestamp_ms().await? })
+    }
+
+    /// Ордер, уже созданный с этим orderLinkId (повтор размещения после таймаута, дошедшего до биржи)
+    async fn find_order_by_link_id(&self, category: &str, api_symbol: &str, link_id: Option<&str>) -> Result<OrderCreateResult> {
+        let link_id = link_id.ok_or_else(|| anyhow!("Duplicate orderLinkId reported for order placed without one ({})", api_symbol))?;
+        warn!(symbol=%api_symbol, link_id, category, "orderLinkId already used: order was placed by a previous attempt, looking it up");
+        let params = [("category", category), ("symbol", api_symbol), ("orderLinkId", link_id)];
+        let query_result: OrderQueryResult = self.call_api(Method::GET, "v5/order/realtime", Some(&params), None, true).await?;
+        let entry = query_result.list.into_iter().next()
+            .ok_or_else(|| ExchangeError::OrderNotFound(format!("orderLinkId {} ({})", link_id, api_symbol)))?;
+        Ok(OrderCreateResult { id: entry.id, link_id: link_id.to_string() })
+    }
+
+    /// Добавляет positionIdx (и reduceOnly для закрытия в hedge-mode) в тело фьючерсного ордера
+    async fn apply_position_params(&self, symbol: &str, side: OrderSide, body: &mut Value) {
+        let (position_idx, reduce_only) = self.position_mode(symbol).await.order_position_params(side);
+        body["positionIdx"] = json!(position_idx);
+        if reduce_only {
+            body["reduceOnly"] = json!(true);
+        }
+    }
+
+    /// Размещение фьючерсного ордера. Отказ с retCode ошибки параметров сбрасывает кэш режима позиций:
+    /// если режим на бирже определился другим, чем в ордере, это PositionModeMismatch (следующий ордер
+    /// уйдет с верным positionIdx), иначе возвращается исходная ошибка
+    async fn create_futures_order(&self, symbol: &str, body: Value) -> Result<OrderCreateResult> {
+        let e = match self.call_api(Method::POST, "v5/order/create", None, Some(body), true).await {
+            Ok(created) => return Ok(created),
+            Err(e) => e,
+        };
+        if !matches!(e.downcast_ref::<ExchangeError>(), Some(ExchangeError::Api { code: POSITION_MODE_MISMATCH_RET_CODE, .. })) {
+            return Err(e);
+        }
+        let used_mode = self.position_modes.lock().await.remove(symbol);
+        let detected_mode = self.position_mode(symbol).await;
+        match used_mode {
+            Some(used_mode) if used_mode != detected_mode => {
+                warn!(symbol=%symbol, ?used_mode, ?detected_mode, "Futures order rejected: position mode changed: {}", e);
+                Err(ExchangeError::PositionModeMismatch(format!("{}: order sent for {:?}, account is in {:?} mode", symbol, used_mode, detected_mode)).into())
+            }
+            _ => Err(e),
+        }
+    }
+
     /// Синхронизация времени с сервером
     async fn sync_time(&self) -> Result<()> {
         let url = self.url("v5/market/time");
@@ -361,11 +1000,19 @@ impl Bybit {
              }
         };
 
-        let time_offset_guard = self.time_offset_ms.lock().await;
-        let offset = (*time_offset_guard).ok_or_else(|| {
-            error!("Time offset is not available. Cannot generate timestamp.");
-            anyhow!("Time not synchronized with server")
-        })?;
+        let cached_offset = *self.time_offset_ms.lock().await;
+        let offset = match cached_offset {
+            Some(offset) => offset,
+            None => {
+                // Начальная синхронизация не удалась (time_sync_required = false): пробуем еще раз
+                warn!("Time offset is not available. Retrying server time sync.");
+                self.sync_time().await.map_err(|e| {
+                    error!("Time offset is not available. Cannot generate timestamp: {}", e);
+                    anyhow!("Time not synchronized with server: {}", e)
+                })?;
+                (*self.time_offset_ms.lock().await).ok_or_else(|| anyhow!("Time not synchronized with server"))?
+            }
+        };
 
         Ok(local_time_ms + offset)
     }
@@ -396,8 +1043,9 @@ impl Bybit {
         ])
     }
 
-    /// Универсальный вызов Bybit API
-    async fn call_api<T: for<'de> Deserialize<'de> + Default>(
+    /// Универсальный вызов Bybit API с повторами временных ошибок (см. api_retry_policy).
+    /// Каждая попытка подписывается заново, чтобы не выйти за recv_window
+    async fn call_api<T: for<'de> Deserialize<'de>>(
         &self,
         method: Method,
         endpoint: &str,
@@ -405,7 +1053,22 @@ impl Bybit {
         body: Option<Value>,
         auth: bool,
     ) -> Result<T> {
-        let url = self.url(endpoint);
+        with_retry(api_retry_policy(&method), || self.call_api_once(method.clone(), endpoint, query, body.clone(), auth)).await
+    }
+
+    /// Одна попытка вызова Bybit API
+    async fn call_api_once<T: for<'de> Deserialize<'de>>(
+        &self,
+        method: Method,
+        endpoint: &str,
+        query: Option<&[(&str, &str)]>,
+        body: Option<Value>,
+        auth: bool,
+    ) -> Result<T> {
+        if auth {
+            self.ensure_not_master_scoped(endpoint)?;
+        }
+        let url = self.url(endpoint);
         debug!(%url, method=%method, ?query, ?body, auth, "Bybit API Call ->");
 
         let mut req = self.client.request(method.clone(), &url);
@@ -437,12 +1100,14 @@ impl Bybit {
             String::new()
         };
 
+        let mut sent_headers = Vec::new();
         if auth {
             match self.auth_headers(&qs, &body_str).await {
                 Ok(headers) => {
-                    for (h, v) in headers {
-                        req = req.header(h, v);
+                    for (h, v) in &headers {
+                        req = req.header(*h, v);
                     }
+                    sent_headers = headers;
                 }
                 Err(e) => {
                     return Err(e);
@@ -454,7 +1119,7 @@ impl Bybit {
             Ok(r) => r,
             Err(e) => {
                 error!(%url, error=%e, "Request failed");
-                return Err(anyhow!("Request failed to {}: {}", url, e));
+                return Err(ExchangeError::Transient(format!("Request failed to {}: {}", url, e)).into());
             }
         };
         let status = resp.status();
@@ -462,7 +1127,7 @@ impl Bybit {
              Ok(text) => text,
              Err(e) => {
                  error!(%url, %status, error=%e, "Failed to read response body");
-                 return Err(anyhow!("Failed to read response body from {}: {}", url, e));
+                 return Err(ExchangeError::Transient(format!("Failed to read response body from {}: {}", url, e)).into());
              }
         };
         debug!(%url, %status, body_len=raw_body.len(), "Bybit API Response <-");
@@ -475,68 +1140,164 @@ impl Bybit {
              }
         }
 
-        let json_value: Value = match serde_json::from_str(&raw_body) {
-             Ok(v) => v,
-             Err(e) => {
-                 error!(error=%e, raw_body, "Failed to parse response body as JSON");
-                 return Err(anyhow!("Failed to parse response body as JSON: {}", e));
-             }
-        };
+        if self.log_api_bodies_on_error && (!status.is_success() || response_ret_code(&raw_body) != Some(0)) {
+            warn!(
+                %url, method=%method, query=%qs, request_body=%body_str, headers=?redact_auth_headers(&sent_headers),
+                %status, response_body=%raw_body, "Bybit API failure details"
+            );
+        }
 
-        let ret_code = json_value.get("retCode").and_then(Value::as_i64).unwrap_or(-1);
-        let ret_msg = json_value.get("retMsg").and_then(Value::as_str).unwrap_or("Unknown error");
-
-        // Обработка ошибок API
-        if ret_code != 0 {
-            // Игнорируем ошибки "Order not found", "already filled/canceled", "leverage not modified"
-            if (endpoint.contains("cancel") || endpoint.contains("realtime"))
-               && (ret_code == 110025 // Order not found or finished
-                   || ret_code == 10001 // Parameter error (может быть, если ордер уже отменен)
-                   || ret_code == 170106 // Order is already cancelled
-                   || ret_code == 170213 // Order does not exist (Bybit Testnet)
-               ) || (endpoint.contains("set-leverage") && ret_code == 110043) // Leverage not modified
-            {
-                 warn!(code = ret_code, msg = ret_msg, %url, "Bybit API Warning (Order not found/filled/canceled or Leverage not modified - ignoring)");
-                 if endpoint.contains("realtime") {
-                     // Для realtime возвращаем ошибку, чтобы hedger мог ее обработать
-                     return Err(anyhow!("Order not found")); // Используем стандартное сообщение
-                 }
-                 // Для отмены или установки плеча без изменений просто продолжим, вернув Ok с default
-            } else {
-                error!(code = ret_code, msg = ret_msg, %url, "Bybit API Error");
-                return Err(anyhow!("Bybit API Error ({}): {}. Raw: {}", ret_code, ret_msg, raw_body));
-            }
+        // 503 с упоминанием работ в теле — техобслуживание, а не разовая перегрузка
+        if status == reqwest::StatusCode::SERVICE_UNAVAILABLE && is_maintenance_response(-1, &raw_body) {
+            warn!(%url, %status, "Bybit is under maintenance (HTTP)");
+            return Err(ExchangeError::Maintenance(format!("HTTP {} from {}", status, url)).into());
         }
 
+        // Перегрузка/лимит запросов на уровне HTTP — временная ошибка
+        if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Err(ExchangeError::Transient(format!("HTTP {} from {}", status, url)).into());
+        }
 
-        match json_value.get("result") {
-            Some(result_val) => {
-                match serde_json::from_value(result_val.clone()) {
-                    Ok(result_data) => {
-                        debug!(%url, "Bybit API call successful (retCode=0)");
-                        Ok(result_data)
-                    }
-                    Err(e) => {
-                        if result_val.is_object() && result_val.as_object().map_or(false, |obj| obj.is_empty()) {
-                             warn!("'result' field is an empty object, attempting to return default value for type.");
-                             Ok(T::default())
-                        } else {
-                            error!(error=%e, result_value=?result_val, "Failed to deserialize 'result' field into expected type");
-                            Err(anyhow!("Failed to deserialize 'result' field: {}", e))
-                        }
-                    }
+        parse_api_response(endpoint, &url, &raw_body)
+    }
+}
+
+/// Датированный фьючерс Bybit (BTCUSDT-26DEC25): дата экспирации через дефис
+pub fn is_dated_contract(symbol: &str) -> bool {
+    symbol.contains('-')
+}
+
+/// Символ для get_linear_instrument_info по символу фьючерса: базовая монета бессрочного контракта
+/// (BTCUSDT -> BTC) или полный символ датированного
+pub fn linear_info_symbol<'a>(symbol: &'a str, quote_currency: &str) -> Result<&'a str> {
+    if is_dated_contract(symbol) {
+        return Ok(symbol);
+    }
+    match symbol.strip_suffix(quote_currency) {
+        Some(base_symbol) if !base_symbol.is_empty() => Ok(base_symbol),
+        _ => Err(anyhow!("Invalid futures symbol format: {}", symbol)),
+    }
+}
+
+/// retCode из тела ответа (None — тело не JSON или поля нет)
+fn response_ret_code(raw_body: &str) -> Option<i64> {
+    serde_json::from_str::<Value>(raw_body).ok()?.get("retCode")?.as_i64()
+}
+
+/// Заголовки запроса для лога: API-ключ и подпись скрыты
+fn redact_auth_headers(headers: &[(&str, String)]) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = match *name {
+                "X-BAPI-API-KEY" | "X-BAPI-SIGN" => "<redacted>".to_string(),
+                _ => value.clone(),
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// retCode Bybit, означающие временный сбой (таймаут сервера, лимит запросов, перегрузка, перезапуск сервиса)
+const TRANSIENT_RET_CODES: [i64; 4] = [10000, 10006, 10016, 10429];
+
+/// retCode перезапуска сервиса: сбой короткий, поэтому повторяется как временный, даже если retMsg похож на работы
+const SERVICE_RESTART_RET_CODE: i64 = 10016;
+
+/// retCode ответа v5/position/trading-stop при отсутствии позиции
+const ZERO_POSITION_RET_CODE: i64 = 10001;
+
+/// retCode отказа v5/order/create, в т.ч. при positionIdx не по режиму позиций. Код общий для ошибок
+/// параметров, поэтому несовпадение режима подтверждается повторным определением (см. create_futures_order)
+const POSITION_MODE_MISMATCH_RET_CODE: i64 = 10001;
+
+/// Фрагменты retMsg / тела ответа, по которым распознаётся техобслуживание
+const MAINTENANCE_MESSAGE_MARKERS: [&str; 3] = ["maintenance", "system upgrade", "service is restarting"];
+
+/// Признак техобслуживания в ответе: характерный текст сообщения (кроме перезапуска сервиса — он повторяется)
+fn is_maintenance_response(ret_code: i64, message: &str) -> bool {
+    let message = message.to_lowercase();
+    ret_code != SERVICE_RESTART_RET_CODE && MAINTENANCE_MESSAGE_MARKERS.iter().any(|marker| message.contains(marker))
+}
+
+/// Разбор ответа Bybit API: проверка retCode и десериализация поля 'result' в T.
+/// Несовпадение формы 'result' — ошибка схемы; пустой/отсутствующий 'result'
+/// допустим только для EmptyResult (cancel/set-leverage).
+fn parse_api_response<T: for<'de> Deserialize<'de>>(
+    endpoint: &str,
+    url: &str,
+    raw_body: &str,
+) -> Result<T> {
+    let json_value: Value = match serde_json::from_str(raw_body) {
+         Ok(v) => v,
+         Err(e) => {
+             error!(error=%e, raw_body, "Failed to parse response body as JSON");
+             return Err(anyhow!("Failed to parse response body as JSON: {}", e));
+         }
+    };
+
+    let ret_code = json_value.get("retCode").and_then(Value::as_i64).unwrap_or(-1);
+    let ret_msg = json_value.get("retMsg").and_then(Value::as_str).unwrap_or("Unknown error");
+
+    // Обработка ошибок API
+    if ret_code != 0 {
+        // Игнорируем ошибки "Order not found", "already filled/canceled", "leverage not modified"
+        if (endpoint.contains("cancel") || endpoint.contains("realtime"))
+           && (ret_code == 110025 // Order not found or finished
+               || ret_code == 10001 // Parameter error (может быть, если ордер уже отменен)
+               || ret_code == 170106 // Order is already cancelled
+               || ret_code == 170213 // Order does not exist (Bybit Testnet)
+           ) || (endpoint.contains("set-leverage") && ret_code == 110043) // Leverage not modified
+        {
+             warn!(code = ret_code, msg = ret_msg, %url, "Bybit API Warning (Order not found/filled/canceled or Leverage not modified - ignoring)");
+             if endpoint.contains("realtime") {
+                 // Для realtime возвращаем типизированную ошибку, чтобы hedger мог ее обработать
+                 return Err(ExchangeError::OrderNotFound(format!("Bybit API ({}): {}", ret_code, ret_msg)).into());
+             }
+             // Для отмены или установки плеча без изменений просто продолжим (ожидается EmptyResult)
+             return empty_api_result(endpoint, url);
+        } else if is_maintenance_response(ret_code, ret_msg) {
+            warn!(code = ret_code, msg = ret_msg, %url, "Bybit is under maintenance");
+            return Err(ExchangeError::Maintenance(format!("Bybit API ({}): {}", ret_code, ret_msg)).into());
+        } else if TRANSIENT_RET_CODES.contains(&ret_code) {
+            warn!(code = ret_code, msg = ret_msg, %url, "Bybit API transient error");
+            return Err(ExchangeError::Transient(format!("Bybit API ({}): {}", ret_code, ret_msg)).into());
+        } else {
+            error!(code = ret_code, msg = ret_msg, %url, "Bybit API Error");
+            return Err(ExchangeError::Api { code: ret_code, message: ret_msg.to_string(), raw: raw_body.to_string() }.into());
+        }
+    }
+
+    match json_value.get("result") {
+        Some(result_val) => {
+            match serde_json::from_value(result_val.clone()) {
+                Ok(result_data) => {
+                    debug!(%url, "Bybit API call successful (retCode=0)");
+                    Ok(result_data)
+                }
+                Err(e) => {
+                    // retCode=0, но форма 'result' не совпала с T — изменение схемы API, не скрываем
+                    error!(%url, error=%e, result_value=?result_val, "'result' does not match expected type on successful response");
+                    Err(anyhow!("Unexpected 'result' shape from {}: {}", endpoint, e))
                 }
-            }
-            None => {
-                // Если поле 'result' отсутствует, но retCode = 0 (например, при set-leverage),
-                // возвращаем значение по умолчанию (например, EmptyResult {})
-                warn!("'result' field missing in successful Bybit response, returning default value for type.");
-                Ok(T::default())
             }
         }
+        None => {
+            // Поле 'result' отсутствует при retCode = 0 (например, при set-leverage)
+            debug!(%url, "'result' field missing in successful Bybit response");
+            empty_api_result(endpoint, url)
+        }
     }
 }
 
+/// Пустой ответ без данных: подходит только типам, принимающим любой 'result' (EmptyResult)
+fn empty_api_result<T: for<'de> Deserialize<'de>>(endpoint: &str, url: &str) -> Result<T> {
+    serde_json::from_value(Value::Null).map_err(|e| {
+        error!(%url, error=%e, "Empty 'result' for endpoint that expects data");
+        anyhow!("Missing 'result' in response from {}: {}", endpoint, e)
+    })
+}
+
 #[async_trait]
 impl Exchange for Bybit {
     /// Проверка соединения
@@ -578,6 +1339,13 @@ impl Exchange for Bybit {
             } else { warn!("System time error checking cache. Fetching fresh."); }
         } else { info!("Balance cache empty."); }
 
+        if self.member_id.is_some() {
+            info!("Fetching fresh sub-account balances from API...");
+            let balances = self.get_member_balances().await?;
+            *cache_guard = Some((balances.clone(), now));
+            return Ok(balances);
+        }
+
         info!("Fetching fresh balances from API...");
         let res: BalanceResult = self.call_api(Method::GET, "v5/account/wallet-balance", Some(&[("accountType", "UNIFIED")]), None, true).await?;
 
@@ -609,6 +1377,23 @@ impl Exchange for Bybit {
         Ok(balances)
     }
 
+    /// Получение маржинального состояния аккаунта (accountMMRate / accountIMRate)
+    async fn get_account_margin(&self) -> Result<MarginInfo> {
+        debug!("Fetching account margin info...");
+        let res: BalanceResult = self.call_api(Method::GET, "v5/account/wallet-balance", Some(&[("accountType", "UNIFIED")]), None, true).await?;
+        let account = res.list.into_iter().find(|acc| acc.account_type == "UNIFIED").ok_or_else(|| anyhow!("UNIFIED account type not found"))?;
+
+        let info = MarginInfo {
+            account_mm_rate: parse_optional_f64(&account.account_mm_rate),
+            account_im_rate: parse_optional_f64(&account.account_im_rate),
+            total_maintenance_margin: parse_optional_f64(&account.total_maintenance_margin),
+            total_initial_margin: parse_optional_f64(&account.total_initial_margin),
+            total_equity: parse_optional_f64(&account.total_equity),
+        };
+        debug!(?info, "Account margin info parsed");
+        Ok(info)
+    }
+
     /// Получить информацию об инструменте СПОТ
     async fn get_spot_instrument_info(&self, symbol: &str) -> Result<SpotInstrumentInfo> {
         let spot_pair = self.format_pair(symbol);
@@ -631,6 +1416,29 @@ impl Exchange for Bybit {
             })
     }
 
+    /// Все спотовые пары биржи (с кэшированием на SPOT_MARKETS_CACHE_TTL)
+    async fn get_all_spot_symbols(&self) -> Result<Vec<SpotMarket>> {
+        let mut cache_guard = self.spot_markets_cache.lock().await;
+        if let Some((markets, fetched_at)) = cache_guard.as_ref() {
+            if fetched_at.elapsed().map(|age| age < SPOT_MARKETS_CACHE_TTL).unwrap_or(false) {
+                debug!("Returning cached spot markets.");
+                return Ok(markets.clone());
+            }
+        }
+        debug!(category=SPOT_CATEGORY, "Fetching all spot markets");
+        let params = [("category", SPOT_CATEGORY)];
+        let result: SpotMarketsResult = self.call_api(
+            Method::GET,
+            "v5/market/instruments-info",
+            Some(&params),
+            None,
+            false,
+        ).await?;
+        info!(count = result.list.len(), "Fetched spot markets.");
+        *cache_guard = Some((result.list.clone(), SystemTime::now()));
+        Ok(result.list)
+    }
+
     /// Получить информацию об инструменте ЛИНЕЙНОМ
     async fn get_linear_instrument_info(&self, symbol: &str) -> Result<LinearInstrumentInfo> {
         let linear_pair = self.format_pair(symbol);
@@ -662,7 +1470,7 @@ impl Exchange for Bybit {
         };
         debug!(%pair, %category, "Fetching fee rate");
 
-        let params = [("symbol", pair.as_str())];
+        let params = [("category", category), ("symbol", pair.as_str())];
 
         let fee_result: FeeRateResult = self.call_api(
             Method::GET,
@@ -693,196 +1501,34 @@ impl Exchange for Bybit {
     }
 
     /// Размещение лимитного ордера (для СПОТА)
-    async fn place_limit_order(
-        &self,
-        symbol: &str,
-        side: OrderSide,
-        qty: f64,
-        price: f64,
-    ) -> Result<Order> {
-        let spot_pair = self.format_pair(symbol);
-
-        let instrument_info = self.get_spot_instrument_info(symbol).await
-            .map_err(|e| anyhow!("Failed to get instrument info for {}: {}", symbol, e))?;
-        let tick_size_str = &instrument_info.price_filter.tick_size;
-        let base_precision_str = instrument_info.lot_size_filter.base_precision
-            .as_deref()
-            .ok_or_else(|| anyhow!("Missing basePrecision for spot symbol {}", spot_pair))?;
-        let min_order_qty_str = &instrument_info.lot_size_filter.min_order_qty;
-
-        let price_decimals = tick_size_str.split('.').nth(1).map_or(0, |s| s.trim_end_matches('0').len()) as u32;
-        let formatted_price = match Decimal::from_f64(price) {
-            Some(d) => d.round_dp(price_decimals).to_string(),
-            None => return Err(anyhow!("Invalid price value {}", price)),
-        };
-
-        let qty_decimals = base_precision_str.split('.').nth(1).map_or(0, |s| s.trim_end_matches('0').len()) as u32;
-        let formatted_qty = match Decimal::from_f64(qty) {
-             Some(qty_d) if qty_d > dec!(0.0) => {
-                 let rounded_down = qty_d.trunc_with_scale(qty_decimals);
-                 let min_qty = Decimal::from_str(min_order_qty_str).unwrap_or(dec!(0.0));
-                 if rounded_down < min_qty && qty_d >= min_qty {
-                     warn!("Limit order quantity {} rounded down below min_order_qty {}. Using min_order_qty.", qty, min_order_qty_str);
-                     min_qty.normalize().to_string()
-                 } else if rounded_down <= dec!(0.0) {
-                     error!("Requested quantity {} is less than the minimum precision {} for {}", qty, base_precision_str, spot_pair);
-                     return Err(anyhow!("Quantity {} is less than minimum precision {}", qty, base_precision_str));
-                 } else {
-                     rounded_down.normalize().to_string()
-                 }
-             }
-             _ => return Err(anyhow!("Invalid qty value {}", qty)),
-        };
-
-        if formatted_qty == "0" { return Err(anyhow!("Formatted quantity is zero")); }
+    async fn place_limit_order(&self, symbol: &str, side: OrderSide, qty: f64, price: f64) -> Result<Order> {
+        self.place_spot_limit_order(symbol, side, qty, price, None).await
+    }
 
-        info!(symbol=%spot_pair, %side, %formatted_qty, %formatted_price, category=SPOT_CATEGORY, "Placing SPOT limit order");
-        let body = json!({ "category": SPOT_CATEGORY, "symbol": spot_pair, "side": side.to_string(), "orderType": "Limit", "qty": formatted_qty, "price": formatted_price, "timeInForce": "GTC" });
-        let result: OrderCreateResult = self.call_api(Method::POST, "v5/order/create", None, Some(body), true).await?;
-        info!(order_id=%result.id, "SPOT limit order placed successfully");
-        Ok(Order { id: result.id, side, qty, price: Some(price), ts: self.get_timestamp_ms().await? })
+    /// Спотовая лимитка с клиентским orderLinkId: повтор с тем же ID не создаёт второй ордер
+    async fn place_limit_order_with_link_id(&self, symbol: &str, side: OrderSide, qty: f64, price: f64, link_id: &str) -> Result<Order> {
+        self.place_spot_limit_order(symbol, side, qty, price, Some(link_id)).await
     }
 
     /// Размещение рыночного ордера (для ФЬЮЧЕРСОВ)
-    async fn place_futures_market_order(
-        &self,
-        symbol: &str,
-        side: OrderSide,
-        qty: f64,
-    ) -> Result<Order> {
-        let base_symbol = symbol.trim_end_matches(&self.quote_currency);
-        if base_symbol.is_empty() || base_symbol == symbol { return Err(anyhow!("Invalid futures symbol format: {}", symbol)); }
-
-        let instrument_info = self.get_linear_instrument_info(base_symbol).await?;
-        let qty_step_str = instrument_info.lot_size_filter.qty_step.as_deref().ok_or_else(|| anyhow!("Missing qtyStep for linear symbol {}", symbol))?;
-        let min_order_qty_str = &instrument_info.lot_size_filter.min_order_qty;
-
-        let qty_decimals = qty_step_str.split('.').nth(1).map_or(0, |s| s.trim_end_matches('0').len()) as u32;
-        let formatted_qty = match Decimal::from_f64(qty) {
-            Some(qty_d) if qty_d > dec!(0.0) => {
-                let rounded_down = qty_d.trunc_with_scale(qty_decimals);
-                let min_qty = Decimal::from_str(min_order_qty_str).unwrap_or(dec!(0.0));
-                if rounded_down < min_qty && qty_d >= min_qty {
-                    warn!("Futures market order quantity {} rounded down below min_order_qty {}. Using min_order_qty.", qty, min_order_qty_str);
-                    min_qty.normalize().to_string()
-                } else if rounded_down <= dec!(0.0) {
-                    error!("Requested futures quantity {} is less than the minimum precision {} (qtyStep) for {}", qty, qty_step_str, symbol);
-                    return Err(anyhow!("Quantity {} is less than minimum precision {} (qtyStep)", qty, qty_step_str));
-                } else {
-                    rounded_down.normalize().to_string()
-                }
-            }
-            _ => return Err(anyhow!("Invalid qty value {}", qty)),
-        };
-        if formatted_qty == "0" { return Err(anyhow!("Formatted quantity is zero")); }
-
-        info!(
-            target: "bybit_futures_order",
-            symbol=%symbol,
-            side=%side,
-            original_qty=qty,
-            formatted_qty=%formatted_qty,
-            category=LINEAR_CATEGORY,
-            "Preparing FUTURES market order parameters"
-        );
-
-        let body = json!({
-            "category": LINEAR_CATEGORY,
-            "symbol": symbol,
-            "side": side.to_string(),
-            "orderType": "Market",
-            "qty": formatted_qty
-        });
-
-        let body_string = serde_json::to_string(&body).unwrap_or_else(|_| "Failed to serialize body".to_string());
-        info!(
-            target: "bybit_futures_order",
-            request_body=%body_string,
-            "Sending FUTURES market order request"
-        );
-
-        let result: OrderCreateResult = self.call_api(
-            Method::POST,
-            "v5/order/create",
-            None,
-            Some(body),
-            true
-        ).await?;
-
-        info!(
-            target: "bybit_futures_order",
-            order_id = %result.id,
-            "FUTURES market order placed successfully: order_id={}", result.id
-        );
+    async fn place_futures_market_order(&self, symbol: &str, side: OrderSide, qty: f64) -> Result<Order> {
+        self.place_linear_market_order(symbol, side, qty, false).await
+    }
 
-        Ok(Order { id: result.id, side, qty, price: None, ts: self.get_timestamp_ms().await? })
+    /// Рыночный reduceOnly-ордер: только сокращает позицию и не открывает встречную
+    async fn place_futures_reduce_only_market_order(&self, symbol: &str, side: OrderSide, qty: f64) -> Result<Order> {
+        self.place_linear_market_order(symbol, side, qty, true).await
     }
 
     /// Размещение ЛИМИТНОГО ордера (для ФЬЮЧЕРСОВ)
     async fn place_futures_limit_order( &self, symbol: &str, side: OrderSide, qty: f64, price: f64 ) -> Result<Order> {
-        let base_symbol = symbol.trim_end_matches(&self.quote_currency);
-        if base_symbol.is_empty() || base_symbol == symbol { return Err(anyhow!("Invalid futures symbol format: {}", symbol)); }
-
-        let instrument_info = self.get_linear_instrument_info(base_symbol).await?;
-        let qty_step_str = instrument_info.lot_size_filter.qty_step.as_deref().ok_or_else(|| anyhow!("Missing qtyStep for linear symbol {}", symbol))?;
-        let min_order_qty_str = &instrument_info.lot_size_filter.min_order_qty;
-        let tick_size_str = &instrument_info.price_filter.tick_size;
-
-        let qty_decimals = qty_step_str.split('.').nth(1).map_or(0, |s| s.trim_end_matches('0').len()) as u32;
-        let formatted_qty = match Decimal::from_f64(qty) {
-             Some(qty_d) if qty_d > dec!(0.0) => {
-                 let rounded_down = qty_d.trunc_with_scale(qty_decimals);
-                 let min_qty = Decimal::from_str(min_order_qty_str).unwrap_or(dec!(0.0));
-                 if rounded_down < min_qty && qty_d >= min_qty {
-                     warn!("Futures limit order quantity {} rounded down below min_order_qty {}. Using min_order_qty.", qty, min_order_qty_str);
-                     min_qty.normalize().to_string()
-                 } else if rounded_down <= dec!(0.0) {
-                     error!("Requested futures quantity {} is less than the minimum precision {} (qtyStep) for {}", qty, qty_step_str, symbol);
-                     return Err(anyhow!("Quantity {} is less than minimum precision {} (qtyStep)", qty, qty_step_str));
-                 } else {
-                     rounded_down.normalize().to_string()
-                 }
-             }
-             _ => return Err(anyhow!("Invalid qty value {}", qty)),
-        };
-        if formatted_qty == "0" { return Err(anyhow!("Formatted quantity is zero")); }
-
-        let price_decimals = tick_size_str.split('.').nth(1).map_or(0, |s| s.trim_end_matches('0').len()) as u32;
-        let formatted_price = match Decimal::from_f64(price) {
-            Some(d) => d.round_dp(price_decimals).to_string(),
-            None => return Err(anyhow!("Invalid price value {}", price)),
-        };
-
-        info!(
-            target: "bybit_futures_order", symbol=%symbol, side=%side, order_type="Limit",
-            original_qty=qty, formatted_qty=%formatted_qty, original_price=price,
-            formatted_price=%formatted_price, category=LINEAR_CATEGORY,
-            "Preparing FUTURES limit order parameters"
-        );
-
-        let body = json!({
-            "category": LINEAR_CATEGORY, "symbol": symbol, "side": side.to_string(),
-            "orderType": "Limit", "qty": formatted_qty, "price": formatted_price,
-            "timeInForce": "GTC"
-        });
-
-        let body_string = serde_json::to_string(&body).unwrap_or_else(|_| "Failed to serialize body".to_string());
-        info!(
-            target: "bybit_futures_order", request_body=%body_string,
-            "Sending FUTURES limit order request"
-        );
-
-        let result: OrderCreateResult = self.call_api(
-            Method::POST, "v5/order/create", None, Some(body), true
-        ).await?;
-
-        info!(
-            target: "bybit_futures_order", order_id = %result.id,
-            "FUTURES limit order placed successfully: order_id={}", result.id
-        );
-        Ok(Order { id: result.id, side, qty, price: Some(price), ts: self.get_timestamp_ms().await? })
+        self.place_linear_limit_order(symbol, side, qty, price, None).await
     }
 
+    /// Фьючерсная лимитка с клиентским orderLinkId: повтор с тем же ID не создаёт второй ордер
+    async fn place_futures_limit_order_with_link_id(&self, symbol: &str, side: OrderSide, qty: f64, price: f64, link_id: &str) -> Result<Order> {
+        self.place_linear_limit_order(symbol, side, qty, price, Some(link_id)).await
+    }
 
     /// Размещение рыночного ордера (для СПОТА)
     async fn place_spot_market_order(
@@ -897,35 +1543,18 @@ impl Exchange for Bybit {
         let base_precision_str = instrument_info.lot_size_filter.base_precision.as_deref().ok_or_else(|| anyhow!("Missing basePrecision for spot symbol {}", spot_pair))?;
         let min_order_qty_str = &instrument_info.lot_size_filter.min_order_qty;
 
-        let qty_decimals = base_precision_str.split('.').nth(1).map_or(0, |s| s.trim_end_matches('0').len()) as u32;
-        let formatted_qty = match Decimal::from_f64(qty) {
-             Some(qty_d) if qty_d > dec!(0.0) => {
-                 let rounded_down = qty_d.trunc_with_scale(qty_decimals);
-                 let min_qty = Decimal::from_str(min_order_qty_str).unwrap_or(dec!(0.0));
-                 if rounded_down < min_qty && qty_d >= min_qty {
-                     warn!("Spot market order quantity {} rounded down below min_order_qty {}. Using min_order_qty.", qty, min_order_qty_str);
-                     min_qty.normalize().to_string()
-                 } else if rounded_down <= dec!(0.0) {
-                     return Err(anyhow!("Quantity {} is less than minimum precision {}", qty, base_precision_str));
-                 } else {
-                     rounded_down.normalize().to_string()
-                 }
-             }
-             _ => return Err(anyhow!("Invalid qty value {}", qty)),
-        };
-
-        if formatted_qty == "0" { return Err(anyhow!("Formatted quantity is zero")); }
+        let formatted_qty = validate_and_format_qty(qty, base_precision_str, min_order_qty_str)?;
 
         info!(symbol=%spot_pair, %side, %formatted_qty, category=SPOT_CATEGORY, "Placing SPOT market order");
 
-        let body = if side == OrderSide::Sell {
-            json!({ "category": SPOT_CATEGORY, "symbol": spot_pair, "side": side.to_string(), "orderType": "Market", "qty": formatted_qty })
-        } else {
-             error!("Spot Market Buy by base quantity not supported. Use limit order or quote quantity.");
-            return Err(anyhow!("Spot Market Buy by base quantity not supported"));
-        };
+        // marketUnit=baseCoin: qty в базовой монете и для покупки (по умолчанию Bybit считает покупку в quote)
+        let body = json!({
+            "category": SPOT_CATEGORY, "symbol": spot_pair, "side": side.to_string(),
+            "orderType": "Market", "qty": formatted_qty, "marketUnit": "baseCoin",
+        });
 
         let result: OrderCreateResult = self.call_api(Method::POST, "v5/order/create", None, Some(body), true).await?;
+        self.invalidate_balance_cache().await;
         info!(order_id=%result.id, "SPOT market order placed successfully");
         Ok(Order { id: result.id, side, qty, price: None, ts: self.get_timestamp_ms().await? })
     }
@@ -946,6 +1575,64 @@ impl Exchange for Bybit {
         ticker.price.parse::<f64>().map_err(|e| anyhow!("Failed to parse spot price for {}: {}", spot_pair, e))
     }
 
+    /// Лучшие bid/ask спотовой пары (для проверки спреда стакана)
+    async fn get_spot_bid_ask(&self, symbol: &str) -> Result<(f64, f64)> {
+        let spot_pair = self.format_pair(symbol);
+        debug!(symbol=%spot_pair, category=SPOT_CATEGORY, "Fetching spot bid/ask");
+        let params = [("category", SPOT_CATEGORY), ("symbol", spot_pair.as_str())];
+        let tickers_result: ReferenceTickersResult = self.call_api(Method::GET, "v5/market/tickers", Some(&params), None, false).await?;
+        let ticker = tickers_result.list.into_iter().find(|t| t.symbol == spot_pair).ok_or_else(|| anyhow!("No ticker info found for {}", spot_pair))?;
+        let bid = parse_ticker_price(ticker.bid1_price.as_deref(), "bid1Price", &spot_pair)?;
+        let ask = parse_ticker_price(ticker.ask1_price.as_deref(), "ask1Price", &spot_pair)?;
+        Ok((bid, ask))
+    }
+
+    /// Цены всех тикеров категории одним запросом (с кэшированием на ALL_TICKERS_CACHE_TTL)
+    async fn get_all_tickers(&self, category: &str) -> Result<HashMap<String, f64>> {
+        let mut cache_guard = self.tickers_cache.lock().await;
+        if let Some((prices, fetched_at)) = cache_guard.get(category) {
+            if fetched_at.elapsed().map(|age| age < ALL_TICKERS_CACHE_TTL).unwrap_or(false) {
+                debug!(category, "Returning cached tickers.");
+                return Ok(prices.clone());
+            }
+        }
+        debug!(category, "Fetching all tickers");
+        let params = [("category", category)];
+        let tickers_result: TickersResult = self.call_api(Method::GET, "v5/market/tickers", Some(&params), None, false).await?;
+        let prices = ticker_price_map(tickers_result.list);
+        info!(category, count = prices.len(), "Fetched all tickers.");
+        cache_guard.insert(category.to_string(), (prices.clone(), SystemTime::now()));
+        Ok(prices)
+    }
+
+    /// Опорная цена по источнику: last, середина bid/ask или индекс.
+    /// Индекс публикуется только для деривативов, поэтому для спота берется индекс линейного контракта той же пары
+    async fn get_reference_price(&self, symbol: &str, category: &str, source: PriceSource) -> Result<f64> {
+        let pair = if category == SPOT_CATEGORY { self.format_pair(symbol) } else { symbol.to_string() };
+        let ticker_category = if source == PriceSource::Index { LINEAR_CATEGORY } else { category };
+        debug!(symbol=%pair, category=ticker_category, ?source, "Fetching reference price");
+        let params = [("category", ticker_category), ("symbol", pair.as_str())];
+        let tickers_result: ReferenceTickersResult = self.call_api(Method::GET, "v5/market/tickers", Some(&params), None, false).await?;
+        let ticker = tickers_result.list.into_iter().find(|t| t.symbol == pair).ok_or_else(|| anyhow!("No ticker info found for {}", pair))?;
+
+        let price = match source {
+            PriceSource::Last => parse_ticker_price(Some(&ticker.last_price), "lastPrice", &pair)?,
+            PriceSource::Mid => {
+                let bid = parse_ticker_price(ticker.bid1_price.as_deref(), "bid1Price", &pair)?;
+                let ask = parse_ticker_price(ticker.ask1_price.as_deref(), "ask1Price", &pair)?;
+                if bid <= 0.0 || ask <= 0.0 {
+                    return Err(anyhow!("Invalid bid/ask for mid price of {}: bid={}, ask={}", pair, bid, ask));
+                }
+                (bid + ask) / 2.0
+            }
+            PriceSource::Index => parse_ticker_price(ticker.index_price.as_deref(), "indexPrice", &pair)?,
+        };
+        if price <= 0.0 {
+            return Err(anyhow!("Invalid {:?} price for {}: {}", source, pair, price));
+        }
+        Ok(price)
+    }
+
     /// Получение статуса ордера (устаревший, используйте get_spot_order_status или get_futures_order_status)
     async fn get_order_status(&self, symbol: &str, order_id: &str) -> Result<OrderStatus> {
         warn!("Deprecated get_order_status called. Assuming SPOT order.");
@@ -959,29 +1646,72 @@ impl Exchange for Bybit {
         let risk_limit_result: RiskLimitResult = self.call_api(Method::GET, "v5/market/risk-limit", Some(&params), None, false).await?;
         let risk_level = risk_limit_result.list.into_iter().find(|level| level.id == 1 || level.is_lowest_risk == 1).ok_or_else(|| anyhow!("No risk limit level 1 found for {}", symbol))?;
 
-        if risk_level.mmr.is_empty() {
-            if self.base_url.contains("testnet") {
-                 warn!("MMR is empty for {} on testnet, using fallback 0.005", symbol);
-                 Ok(0.005)
-            } else { Err(anyhow!("MMR (maintenanceMargin) is empty for {}", symbol)) }
-        } else {
-            risk_level.mmr.parse::<f64>().map_err(|e| anyhow!("Failed to parse MMR for {}: {}", symbol, e))
-        }
+        resolve_mmr(symbol, &risk_level.mmr, self.base_url.contains("testnet"), self.mmr_fallback)
+    }
+
+    /// Ступень риск-лимита ЛИНЕЙНОГО контракта под стоимость позиции
+    async fn get_symbol_leverage_bracket(&self, symbol: &str, position_value: f64) -> Result<LeverageBracket> {
+        debug!(symbol=%symbol, position_value, "Fetching risk limit brackets");
+        let params = [("category", LINEAR_CATEGORY), ("symbol", symbol)];
+        let risk_limit_result: RiskLimitResult = self.call_api(Method::GET, "v5/market/risk-limit", Some(&params), None, false).await?;
+        let is_testnet = self.base_url.contains("testnet");
+        let brackets = risk_limit_result
+            .list
+            .iter()
+            .map(|entry| {
+                Ok(LeverageBracket {
+                    risk_limit_value: entry.risk_limit_value.parse::<f64>()
+                        .map_err(|e| anyhow!("Failed to parse riskLimitValue '{}' for {}: {}", entry.risk_limit_value, symbol, e))?,
+                    mmr: resolve_mmr(symbol, &entry.mmr, is_testnet, self.mmr_fallback)?,
+                    max_leverage: entry.max_leverage.parse::<f64>()
+                        .map_err(|e| anyhow!("Failed to parse maxLeverage '{}' for {}: {}", entry.max_leverage, symbol, e))?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let bracket = select_leverage_bracket(brackets, position_value).ok_or_else(|| anyhow!("No risk limit brackets found for {}", symbol))?;
+        debug!(symbol=%symbol, ?bracket, "Selected risk limit bracket");
+        Ok(bracket)
     }
 
     /// Получение средней ставки финансирования
-    async fn get_funding_rate(&self, symbol: &str, days: u16) -> Result<f64> {
-        let limit = days.min(200).to_string();
+    async fn get_funding_rate(&self, symbol: &str, days: u16) -> Result<FundingRateStats> {
+        // Фандинг начисляется каждые 8 часов (3 раза в день); API отдает не более 200 записей
+        let limit = (u32::from(days) * FUNDING_INTERVALS_PER_DAY).min(MAX_FUNDING_HISTORY_ENTRIES).to_string();
         debug!(symbol=%symbol, days, limit=%limit, "Fetching funding rate history");
         let params = [("category", LINEAR_CATEGORY), ("symbol", symbol), ("limit", limit.as_str())];
         let funding_result: FundingResult = self.call_api(Method::GET, "v5/market/funding-rate-history", Some(&params), None, false).await?;
 
-        if funding_result.list.is_empty() { return Ok(0.0); }
         let mut sum = 0.0; let mut count = 0;
         for entry in &funding_result.list {
             if let Ok(rate) = entry.rate.parse::<f64>() { sum += rate; count += 1; }
         }
-        if count == 0 { Ok(0.0) } else { Ok(sum / count as f64) }
+        if count == 0 { return Ok(FundingRateStats::default()); }
+        Ok(FundingRateStats { avg_rate: sum / count as f64, intervals: count })
+    }
+
+    /// Почасовая ставка займа монеты (UTA); ошибка, если заём монеты недоступен
+    async fn get_borrow_rate(&self, coin: &str) -> Result<f64> {
+        debug!(%coin, "Fetching borrow rate via collateral info");
+        let params = [("currency", coin)];
+        let collateral_result: CollateralInfoResult = self.call_api(Method::GET, "v5/account/collateral-info", Some(&params), None, true).await?;
+        let entry = collateral_result.list.into_iter()
+            .find(|entry| entry.currency.eq_ignore_ascii_case(coin))
+            .ok_or_else(|| anyhow!("Collateral info not found for {}", coin))?;
+
+        if !entry.borrowable {
+            return Err(anyhow!("Borrowing is not available for {} on this account", coin));
+        }
+        entry.hourly_borrow_rate.parse::<f64>()
+            .map_err(|e| anyhow!("Failed to parse hourly borrow rate for {}: {}", coin, e))
+    }
+
+    /// Повторная синхронизация времени по запросу (/resync): смещение до и после
+    async fn resync_time(&self) -> Result<TimeSyncReport> {
+        let previous_offset_ms = *self.time_offset_ms.lock().await;
+        self.sync_time().await?;
+        let offset_ms = (*self.time_offset_ms.lock().await).ok_or_else(|| anyhow!("Time offset missing after sync"))?;
+        info!(?previous_offset_ms, offset_ms, "Manual time resync done");
+        Ok(TimeSyncReport { previous_offset_ms, offset_ms, recv_window_ms: self.recv_window })
     }
 
     /// Получить текущее кредитное плечо для символа (linear)
@@ -996,7 +1726,7 @@ impl Exchange for Bybit {
             true,
         ).await?;
 
-        let position = match position_result.list.into_iter().find(|p| p.symbol == symbol) {
+        let position = match PositionMode::select_position_entry(position_result.list, symbol) {
              Some(p) => p,
              None => {
                  warn!("No position info found for {} to get leverage. Cannot determine current leverage.", symbol);
@@ -1010,13 +1740,49 @@ impl Exchange for Bybit {
         })
     }
 
+    /// Размер и сторона позиции по символу (linear); отсутствие позиции — нулевой размер
+    async fn get_position_details(&self, symbol: &str) -> Result<PositionDetails> {
+        debug!(symbol=%symbol, category=LINEAR_CATEGORY, "Fetching position details");
+        let params = [("category", LINEAR_CATEGORY), ("symbol", symbol)];
+        let position_result: PositionInfoResult = self.call_api(Method::GET, "v5/position/list", Some(&params), None, true).await?;
+
+        // В hedge-mode по символу две записи (лонг/шорт): берем сторону бота по positionIdx
+        let Some(position) = PositionMode::select_position_entry(position_result.list, symbol) else {
+            return Ok(PositionDetails { symbol: symbol.to_string(), side: None, size: 0.0, avg_price: 0.0 });
+        };
+        let size = if position.size.is_empty() { 0.0 } else {
+            position.size.parse::<f64>().map_err(|e| anyhow!("Failed to parse position size for {}: {} (value: '{}')", symbol, e, position.size))?
+        };
+        let side = match position.side.as_str() {
+            "Buy" if size > 0.0 => Some(OrderSide::Buy),
+            "Sell" if size > 0.0 => Some(OrderSide::Sell),
+            _ => None,
+        };
+        let avg_price = position.avg_price.parse::<f64>().unwrap_or(0.0);
+        Ok(PositionDetails { symbol: position.symbol, side, size, avg_price })
+    }
+
     /// Установить кредитное плечо для символа (linear)
     async fn set_leverage(&self, symbol: &str, leverage: f64) -> Result<()> {
-        let leverage_str = format!("{:.2}", leverage);
         if leverage <= 0.0 {
             error!("Attempted to set non-positive leverage: {}", leverage);
             return Err(anyhow!("Leverage must be positive"));
         }
+        // Шаг плеча у символов разный (целое, 0.01): без leverageFilter значение уходит с двумя знаками
+        let leverage_filter = match self.linear_info_symbol(symbol) {
+            Ok(info_symbol) => match self.get_linear_instrument_info(info_symbol).await {
+                Ok(info) => info.leverage_filter,
+                Err(e) => {
+                    warn!(symbol=%symbol, "Failed to fetch leverage filter, sending leverage with 2 decimals: {}", e);
+                    None
+                }
+            },
+            Err(_) => None,
+        };
+        let leverage_str = match &leverage_filter {
+            Some(filter) => leverage_for_step(leverage, filter).map_err(|e| anyhow!("Cannot set leverage for {}: {}", symbol, e))?.to_string(),
+            None => format!("{:.2}", leverage),
+        };
         info!(symbol=%symbol, leverage=%leverage_str, category=LINEAR_CATEGORY, "Setting leverage");
 
         let body = json!({
@@ -1039,12 +1805,53 @@ impl Exchange for Bybit {
         Ok(())
     }
 
+    /// Установить (или снять при distance = 0) трейлинг-стоп для позиции (linear)
+    async fn set_trailing_stop(&self, symbol: &str, distance: f64) -> Result<()> {
+        if distance < 0.0 {
+            return Err(anyhow!("Trailing stop distance must be non-negative"));
+        }
+        let base_symbol = self.linear_info_symbol(symbol)?;
+
+        let instrument_info = self.get_linear_instrument_info(base_symbol).await?;
+        let tick_size = Decimal::from_str(&instrument_info.price_filter.tick_size)
+            .map_err(|e| anyhow!("Invalid tickSize '{}' for {}: {}", instrument_info.price_filter.tick_size, symbol, e))?;
+        let formatted_distance = match Decimal::from_f64(distance) {
+            Some(d) => round_to_tick(d, tick_size, None).to_string(),
+            None => return Err(anyhow!("Invalid trailing stop distance {}", distance)),
+        };
+        info!(symbol=%symbol, distance=%formatted_distance, category=LINEAR_CATEGORY, "Setting trailing stop");
+
+        // Трейлинг-стоп защищает шорт: в hedge-mode это сторона 2
+        let (position_idx, _) = self.position_mode(symbol).await.order_position_params(OrderSide::Sell);
+        let body = json!({
+            "category": LINEAR_CATEGORY,
+            "symbol": symbol,
+            "tpslMode": "Full",
+            "trailingStop": formatted_distance,
+            "positionIdx": position_idx,
+        });
+
+        match self.call_api::<EmptyResult>(Method::POST, "v5/position/trading-stop", None, Some(body), true).await {
+            Ok(_) => {
+                info!(symbol=%symbol, distance=%formatted_distance, "Trailing stop request sent successfully");
+                Ok(())
+            }
+            // Bybit отклоняет запрос, если позиции нет ("can not set tp/sl/ts for zero position")
+            Err(e) if matches!(e.downcast_ref::<ExchangeError>(), Some(ExchangeError::Api { code: ZERO_POSITION_RET_CODE, .. })) => {
+                warn!(symbol=%symbol, "Cannot set trailing stop: no open position");
+                Err(anyhow!("No open futures position for {} to attach trailing stop", symbol))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     /// Отмена СПОТ ордера
     async fn cancel_spot_order(&self, symbol: &str, order_id: &str) -> Result<()> {
         let spot_pair = self.format_pair(symbol);
         info!(symbol=%spot_pair, order_id, category=SPOT_CATEGORY, "Cancelling SPOT order");
         let body = json!({ "category": SPOT_CATEGORY, "symbol": spot_pair, "orderId": order_id });
         self.call_api::<EmptyResult>(Method::POST, "v5/order/cancel", None, Some(body), true).await?;
+        self.invalidate_balance_cache().await;
         info!(order_id, "SPOT Order cancel request sent (or order was inactive)");
         Ok(())
     }
@@ -1054,10 +1861,68 @@ impl Exchange for Bybit {
         info!(symbol=%symbol, order_id, category=LINEAR_CATEGORY, "Cancelling FUTURES order");
         let body = json!({ "category": LINEAR_CATEGORY, "symbol": symbol, "orderId": order_id });
         self.call_api::<EmptyResult>(Method::POST, "v5/order/cancel", None, Some(body), true).await?;
+        self.invalidate_balance_cache().await;
         info!(order_id, "FUTURES Order cancel request sent (or order was inactive)");
         Ok(())
     }
 
+    /// Отмена ордера по orderLinkId — для восстановления, когда orderId не успел сохраниться
+    async fn cancel_order_by_link_id(&self, symbol: &str, link_id: &str, category: &str) -> Result<()> {
+        let api_symbol = match category {
+            SPOT_CATEGORY => self.format_pair(symbol),
+            LINEAR_CATEGORY => symbol.to_string(),
+            _ => return Err(anyhow!("Unsupported category for cancel by link id: {}", category)),
+        };
+        info!(symbol=%api_symbol, link_id, category, "Cancelling order by orderLinkId");
+        let body = cancel_by_link_id_body(category, &api_symbol, link_id);
+        self.call_api::<EmptyResult>(Method::POST, "v5/order/cancel", None, Some(body), true).await?;
+        self.invalidate_balance_cache().await;
+        info!(link_id, "Cancel by orderLinkId request sent (or order was inactive)");
+        Ok(())
+    }
+
+    /// Изменение цены (и при необходимости количества) активного ордера без отмены
+    async fn amend_order(&self, symbol: &str, order_id: &str, new_price: f64, new_qty: Option<f64>, category: &str) -> Result<()> {
+        let (api_symbol, tick_size_str, qty_step_str) = if category == SPOT_CATEGORY {
+            let info = self.get_spot_instrument_info(symbol).await
+                .map_err(|e| anyhow!("Failed to get instrument info for {}: {}", symbol, e))?;
+            let qty_step = info.lot_size_filter.base_precision.clone()
+                .ok_or_else(|| anyhow!("Missing basePrecision for spot symbol {}", symbol))?;
+            (self.format_pair(symbol), info.price_filter.tick_size, qty_step)
+        } else if category == LINEAR_CATEGORY {
+            let base_symbol = self.linear_info_symbol(symbol)?;
+            let info = self.get_linear_instrument_info(base_symbol).await?;
+            let qty_step = info.lot_size_filter.qty_step.clone()
+                .ok_or_else(|| anyhow!("Missing qtyStep for linear symbol {}", symbol))?;
+            (symbol.to_string(), info.price_filter.tick_size, qty_step)
+        } else {
+            return Err(anyhow!("Unsupported category for amend: {}", category));
+        };
+
+        let tick_size = Decimal::from_str(&tick_size_str)
+            .map_err(|e| anyhow!("Invalid tickSize '{}' for {}: {}", tick_size_str, symbol, e))?;
+        let formatted_price = match Decimal::from_f64(new_price) {
+            Some(d) if d > dec!(0.0) => round_to_tick(d, tick_size, None).to_string(),
+            _ => return Err(anyhow!("Invalid price value {}", new_price)),
+        };
+
+        let mut body = json!({ "category": category, "symbol": api_symbol, "orderId": order_id, "price": formatted_price });
+        if let Some(qty) = new_qty {
+            let qty_decimals = qty_step_str.split('.').nth(1).map_or(0, |s| s.trim_end_matches('0').len()) as u32;
+            let formatted_qty = match Decimal::from_f64(qty) {
+                Some(qty_d) if qty_d.trunc_with_scale(qty_decimals) > dec!(0.0) => qty_d.trunc_with_scale(qty_decimals).normalize().to_string(),
+                _ => return Err(anyhow!("Invalid qty value {}", qty)),
+            };
+            body["qty"] = json!(formatted_qty);
+        }
+
+        info!(symbol=%api_symbol, order_id, %formatted_price, ?new_qty, category, "Amending order");
+        self.call_api::<EmptyResult>(Method::POST, "v5/order/amend", None, Some(body), true).await?;
+        self.invalidate_balance_cache().await;
+        info!(order_id, "Order amend request accepted");
+        Ok(())
+    }
+
     /// Получение статуса СПОТ ордера
     async fn get_spot_order_status(&self, symbol: &str, order_id: &str) -> Result<OrderStatus> {
         let spot_pair = self.format_pair(symbol);
@@ -1067,13 +1932,14 @@ impl Exchange for Bybit {
 
         let order_entry = query_result.list.into_iter().next().ok_or_else(|| {
             warn!("Order ID {} not found in realtime query for {}", order_id, spot_pair);
-            anyhow!("Order not found")
+            ExchangeError::OrderNotFound(format!("{} ({})", order_id, spot_pair))
         })?;
 
         let filled = if order_entry.cum_exec_qty.is_empty() { 0.0 } else { order_entry.cum_exec_qty.parse::<f64>()? };
         let remaining = if order_entry.leaves_qty.is_empty() { 0.0 } else { order_entry.leaves_qty.parse::<f64>()? };
         info!(order_id, status=%order_entry.status, filled, remaining, "Order status received (SPOT)");
-        Ok(OrderStatus { filled_qty: filled, remaining_qty: remaining })
+        let status = OrderStatusText::from(order_entry.status.as_str());
+        Ok(OrderStatus { filled_qty: filled, remaining_qty: remaining, status })
     }
 
     /// Получение статуса ФЬЮЧЕРСНОГО ордера
@@ -1084,13 +1950,14 @@ impl Exchange for Bybit {
 
         let order_entry = query_result.list.into_iter().next().ok_or_else(|| {
             warn!("Futures Order ID {} not found in realtime query for {}", order_id, symbol);
-            anyhow!("Order not found")
+            ExchangeError::OrderNotFound(format!("{} ({})", order_id, symbol))
         })?;
 
         let filled = if order_entry.cum_exec_qty.is_empty() { 0.0 } else { order_entry.cum_exec_qty.parse::<f64>()? };
         let remaining = if order_entry.leaves_qty.is_empty() { 0.0 } else { order_entry.leaves_qty.parse::<f64>()? };
         info!(order_id, status=%order_entry.status, filled, remaining, "Order status received (FUTURES)");
-        Ok(OrderStatus { filled_qty: filled, remaining_qty: remaining })
+        let status = OrderStatusText::from(order_entry.status.as_str());
+        Ok(OrderStatus { filled_qty: filled, remaining_qty: remaining, status })
     }
 
     /// Получение детального статуса СПОТ ордера
@@ -1109,7 +1976,7 @@ impl Exchange for Bybit {
 
         let order_entry = query_result.list.into_iter().next().ok_or_else(|| {
             warn!("Order ID {} not found in realtime query for {}", order_id, spot_pair);
-            anyhow!("Order not found")
+            ExchangeError::OrderNotFound(format!("{} ({})", order_id, spot_pair))
         })?;
 
         let filled_quantity = order_entry.cum_exec_qty.trim().parse::<f64>().unwrap_or_else(|error| {
@@ -1137,7 +2004,7 @@ impl Exchange for Bybit {
         );
 
         // --- ИСПРАВЛЕНО: Распаковываем Result ---
-        let status_text = OrderStatusText::from_str(&order_entry.status).unwrap(); // unwrap() безопасен здесь
+        let status_text = OrderStatusText::from(order_entry.status.as_str());
 
         Ok(DetailedOrderStatus {
             filled_qty: filled_quantity,
@@ -1229,3 +2096,428 @@ impl Exchange for Bybit {
         }
     }
 } // --- Конец impl Exchange for Bybit ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://api-testnet.bybit.com/test";
+
+    #[test]
+    fn cancel_response_with_order_ids_returns_default() {
+        let body = r#"{"retCode":0,"retMsg":"OK","result":{"orderId":"1321003749386327552","orderLinkId":"spot-test-postonly"},"retExtInfo":{},"time":1672217377164}"#;
+        let res = parse_api_response::<EmptyResult>("v5/order/cancel", URL, body);
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn only_read_requests_are_retried_on_transient_errors() {
+        let calls = std::sync::atomic::AtomicU32::new(0);
+        let transient = || async {
+            calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Err::<(), _>(ExchangeError::Transient("HTTP 503".to_string()).into())
+        };
+        assert!(with_retry(api_retry_policy(&Method::GET), transient).await.is_err());
+        assert_eq!(calls.swap(0, std::sync::atomic::Ordering::SeqCst), API_READ_ATTEMPTS);
+
+        // Ордер/отмена не повторяются на уровне HTTP
+        assert!(with_retry(api_retry_policy(&Method::POST), transient).await.is_err());
+        assert_eq!(calls.swap(0, std::sync::atomic::Ordering::SeqCst), 1);
+
+        // Постоянная ошибка GET возвращается сразу
+        let permanent = || async {
+            calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Err::<(), _>(ExchangeError::Api { code: 10001, message: "params error".to_string(), raw: String::new() }.into())
+        };
+        assert!(with_retry(api_retry_policy(&Method::GET), permanent).await.is_err());
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn qty_exactly_at_minimum_is_accepted() {
+        assert_eq!(validate_and_format_qty(0.001, "0.001", "0.001").expect("qty"), "0.001");
+        assert_eq!(validate_and_format_qty(1.23456, "0.01", "0.01").expect("qty"), "1.23");
+    }
+
+    #[test]
+    fn qty_just_below_minimum_is_typed_error() {
+        let err = validate_and_format_qty(0.0009, "0.0001", "0.001").unwrap_err();
+        let below = err.downcast_ref::<BelowMinimum>().expect("BelowMinimum");
+        assert_eq!(below.rounded, dec!(0.0009));
+        assert_eq!(below.min_qty, dec!(0.001));
+        assert_eq!(below.step, "0.0001");
+    }
+
+    #[test]
+    fn zero_qty_is_below_minimum() {
+        assert!(validate_and_format_qty(0.0, "0.001", "0.001").unwrap_err().downcast_ref::<BelowMinimum>().is_some());
+        // Меньше шага при нулевом минимуме — тоже ноль после округления
+        assert!(validate_and_format_qty(0.0004, "0.001", "0").unwrap_err().downcast_ref::<BelowMinimum>().is_some());
+        assert!(validate_and_format_qty(-1.0, "0.001", "0.001").is_err());
+    }
+
+    #[test]
+    fn empty_mmr_on_mainnet_fails_unless_fallback_is_configured() {
+        assert!(resolve_mmr("BTCUSDT", "", false, None).is_err());
+        assert_eq!(resolve_mmr("BTCUSDT", "", false, Some(0.01)).unwrap(), 0.01);
+        assert_eq!(resolve_mmr("BTCUSDT", "", true, None).unwrap(), 0.005);
+        // Значение биржи важнее fallback
+        assert_eq!(resolve_mmr("BTCUSDT", "0.004", false, Some(0.01)).unwrap(), 0.004);
+        assert!(resolve_mmr("BTCUSDT", "abc", false, Some(0.01)).is_err());
+    }
+
+    #[test]
+    fn position_mode_selects_position_idx_for_short_hedge() {
+        assert_eq!(PositionMode::from_position_indices([0]), PositionMode::OneWay);
+        assert_eq!(PositionMode::from_position_indices([1, 2]), PositionMode::Hedge);
+        assert_eq!(PositionMode::from_position_indices([]), PositionMode::OneWay);
+
+        assert_eq!(PositionMode::OneWay.order_position_params(OrderSide::Sell), (0, false));
+        assert_eq!(PositionMode::OneWay.order_position_params(OrderSide::Buy), (0, false));
+        // Hedge-mode: шорт открывается и закрывается на стороне 2
+        assert_eq!(PositionMode::Hedge.order_position_params(OrderSide::Sell), (2, false));
+        assert_eq!(PositionMode::Hedge.order_position_params(OrderSide::Buy), (2, true));
+    }
+
+    #[test]
+    fn position_entry_is_selected_by_position_idx() {
+        let entries = |json: &str| serde_json::from_str::<PositionInfoResult>(json).expect("position list").list;
+        // Hedge-mode: открыт лонг на стороне 1 — позиция бота все равно шорт на стороне 2
+        let hedge = entries(r#"{"list":[
+            {"symbol":"ETHUSDT","leverage":"5","side":"Buy","size":"3","avgPrice":"2000","positionIdx":1},
+            {"symbol":"ETHUSDT","leverage":"5","side":"Sell","size":"1.5","avgPrice":"2100","positionIdx":2}
+        ]}"#);
+        let short = PositionMode::select_position_entry(hedge, "ETHUSDT").expect("short side");
+        assert_eq!((short.position_idx, short.side.as_str(), short.size.as_str()), (2, "Sell", "1.5"));
+
+        let one_way = entries(r#"{"list":[{"symbol":"ETHUSDT","leverage":"5","side":"","size":"0","avgPrice":"0","positionIdx":0}]}"#);
+        assert_eq!(PositionMode::select_position_entry(one_way, "ETHUSDT").expect("entry").position_idx, 0);
+        assert!(PositionMode::select_position_entry(entries(r#"{"list":[]}"#), "ETHUSDT").is_none());
+    }
+
+    #[tokio::test]
+    async fn accounts_initialize_independent_exchanges() {
+        // Локальный адрес без сервера: синхронизация времени сразу неуспешна, но не обязательна
+        let toml = r#"
+            bybit_api_key = ""
+            bybit_api_secret = ""
+            use_testnet = true
+            bybit_base_url = "http://127.0.0.1:9"
+            time_sync_required = false
+            time_sync_retries = 0
+            sqlite_path = "test.db"
+            telegram_token = ""
+            default_volatility = 0.6
+            offset_points = 10
+            quote_currency = "USDT"
+            slippage = 0.001
+            max_wait_secs = 30
+            max_allowed_leverage = 10.0
+
+            [[accounts]]
+            name = "main"
+            bybit_api_key = "key-main"
+            bybit_api_secret = "secret-main"
+            telegram_token = "1:main"
+
+            [[accounts]]
+            name = "sub"
+            bybit_api_key = "key-sub"
+            bybit_api_secret = "secret-sub"
+            quote_currency = "USDC"
+            telegram_token = "2:sub"
+        "#;
+        let cfg: Config = ::config::Config::builder()
+            .add_source(::config::File::from_str(toml, ::config::FileFormat::Toml))
+            .build()
+            .and_then(|loader| loader.try_deserialize())
+            .expect("config");
+        let accounts = cfg.account_configs().expect("accounts");
+
+        let main = Bybit::from_config(&accounts[0].1).await.expect("main exchange");
+        let sub = Bybit::from_config(&accounts[1].1).await.expect("sub exchange");
+
+        assert_eq!((main.api_key.as_str(), sub.api_key.as_str()), ("key-main", "key-sub"));
+        assert_eq!((main.quote_currency.as_str(), sub.quote_currency.as_str()), ("USDT", "USDC"));
+        assert!(!Arc::ptr_eq(&main.balance_cache, &sub.balance_cache));
+        assert!(!Arc::ptr_eq(&main.position_modes, &sub.position_modes));
+    }
+
+    #[test]
+    fn environment_is_detected_from_base_url() {
+        assert_eq!(environment_for_base_url("https://api-testnet.bybit.com"), "testnet");
+        assert_eq!(environment_for_base_url("https://API-TESTNET.bybit.com/"), "testnet");
+        assert_eq!(environment_for_base_url("https://api.bybit.com"), "mainnet");
+        assert_eq!(environment_for_base_url("https://api.bytick.com"), "mainnet");
+    }
+
+    #[test]
+    fn http_client_accepts_pool_settings() {
+        assert!(HttpPoolPolicy::default().build_client().is_ok());
+        let tuned = HttpPoolPolicy { max_idle_per_host: 0, idle_timeout: Duration::from_secs(5), tcp_keepalive: None };
+        assert!(tuned.build_client().is_ok());
+    }
+
+    #[test]
+    fn leverage_is_rounded_to_symbol_step() {
+        let filter = |min: &str, max: &str, step: &str| LeverageFilter {
+            min_leverage: min.to_string(),
+            max_leverage: max.to_string(),
+            leverage_step: step.to_string(),
+        };
+
+        // Только целое плечо: дробное округляется вверх, чтобы покрыть требуемое
+        let integer_only = filter("1", "25", "1");
+        assert_eq!(leverage_for_step(2.35, &integer_only).unwrap(), dec!(3));
+        assert_eq!(leverage_for_step(2.0, &integer_only).unwrap(), dec!(2));
+        assert_eq!(leverage_for_step(2.000_000_000_1, &integer_only).unwrap(), dec!(2));
+        assert_eq!(leverage_for_step(0.4, &integer_only).unwrap(), dec!(1));
+        assert!(leverage_for_step(25.5, &integer_only).is_err());
+
+        // Шаг 0.01
+        let fine_step = filter("1", "100.00", "0.01");
+        assert_eq!(leverage_for_step(2.345, &fine_step).unwrap(), dec!(2.35));
+        assert_eq!(leverage_for_step(2.35, &fine_step).unwrap(), dec!(2.35));
+        assert_eq!(leverage_for_step(100.0, &fine_step).unwrap().to_string(), "100");
+        assert!(leverage_for_step(2.0, &filter("1", "10", "0")).is_err());
+    }
+
+    #[test]
+    fn leverage_bracket_is_selected_by_position_value() {
+        let bracket = |risk_limit_value, mmr, max_leverage| LeverageBracket { risk_limit_value, mmr, max_leverage };
+        // Биржа может вернуть ступени в любом порядке
+        let brackets = vec![bracket(4_000_000.0, 0.01, 50.0), bracket(2_000_000.0, 0.005, 100.0), bracket(6_000_000.0, 0.015, 33.33)];
+
+        assert_eq!(select_leverage_bracket(brackets.clone(), 10_000.0).unwrap().mmr, 0.005);
+        assert_eq!(select_leverage_bracket(brackets.clone(), 2_000_000.0).unwrap().mmr, 0.005);
+        assert_eq!(select_leverage_bracket(brackets.clone(), 3_000_000.0).unwrap().max_leverage, 50.0);
+        // Больше верхней ступени — самая рискованная ступень
+        assert_eq!(select_leverage_bracket(brackets, 9_000_000.0).unwrap().mmr, 0.015);
+        assert!(select_leverage_bracket(Vec::new(), 1.0).is_none());
+    }
+
+    #[test]
+    fn cancel_by_link_id_sends_order_link_id_field() {
+        let body = cancel_by_link_id_body(LINEAR_CATEGORY, "BTCUSDT", "hh-42-fut-1");
+        assert_eq!(body["orderLinkId"], "hh-42-fut-1");
+        assert_eq!(body["symbol"], "BTCUSDT");
+        assert_eq!(body["category"], LINEAR_CATEGORY);
+        assert!(body.get("orderId").is_none());
+    }
+
+    #[test]
+    fn duplicate_order_link_id_is_recognized_by_ret_code() {
+        let body = r#"{"retCode":110072,"retMsg":"OrderLinkedID is duplicate","result":{},"retExtInfo":{},"time":1672217377164}"#;
+        let err = parse_api_response::<OrderCreateResult>("v5/order/create", URL, body).unwrap_err();
+        assert!(is_duplicate_link_id(&err));
+        let other = r#"{"retCode":110007,"retMsg":"Insufficient balance","result":{},"retExtInfo":{},"time":1672217377164}"#;
+        assert!(!is_duplicate_link_id(&parse_api_response::<OrderCreateResult>("v5/order/create", URL, other).unwrap_err()));
+
+        let mut create = json!({ "category": SPOT_CATEGORY });
+        set_order_link_id(&mut create, Some("hh-42-spot-1"));
+        assert_eq!(create["orderLinkId"], "hh-42-spot-1");
+    }
+
+    #[test]
+    fn cancel_already_cancelled_is_ignored() {
+        let body = r#"{"retCode":170106,"retMsg":"Order is already cancelled","result":{},"retExtInfo":{},"time":1672217377164}"#;
+        let res = parse_api_response::<EmptyResult>("v5/order/cancel", URL, body);
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn set_leverage_empty_result_returns_default() {
+        let body = r#"{"retCode":0,"retMsg":"OK","result":{},"retExtInfo":{},"time":1672281607343}"#;
+        assert!(parse_api_response::<EmptyResult>("v5/position/set-leverage", URL, body).is_ok());
+    }
+
+    #[test]
+    fn set_leverage_not_modified_is_ignored() {
+        let body = r#"{"retCode":110043,"retMsg":"leverage not modified","result":{},"retExtInfo":{},"time":1672281607343}"#;
+        assert!(parse_api_response::<EmptyResult>("v5/position/set-leverage", URL, body).is_ok());
+    }
+
+    #[test]
+    fn null_or_missing_result_returns_default() {
+        let null_body = r#"{"retCode":0,"retMsg":"OK","result":null}"#;
+        assert!(parse_api_response::<EmptyResult>("v5/order/cancel", URL, null_body).is_ok());
+        let missing_body = r#"{"retCode":0,"retMsg":"OK"}"#;
+        assert!(parse_api_response::<EmptyResult>("v5/position/set-leverage", URL, missing_body).is_ok());
+    }
+
+    #[test]
+    fn unexpected_result_shape_is_an_error() {
+        let empty = r#"{"retCode":0,"retMsg":"OK","result":{}}"#;
+        assert!(parse_api_response::<OrderCreateResult>("v5/order/create", URL, empty).is_err());
+        let missing = r#"{"retCode":0,"retMsg":"OK"}"#;
+        assert!(parse_api_response::<OrderCreateResult>("v5/order/create", URL, missing).is_err());
+        // Игнорируемый код отмены допустим только для запросов без данных
+        let not_found = r#"{"retCode":110025,"retMsg":"Order not found","result":{}}"#;
+        assert!(parse_api_response::<OrderCreateResult>("v5/order/cancel", URL, not_found).is_err());
+    }
+
+    #[test]
+    fn api_error_is_propagated() {
+        let body = r#"{"retCode":10004,"retMsg":"error sign!","result":{}}"#;
+        assert!(parse_api_response::<EmptyResult>("v5/order/cancel", URL, body).is_err());
+    }
+
+    #[test]
+    fn realtime_order_not_found_is_error() {
+        let body = r#"{"retCode":110025,"retMsg":"Order not found","result":{}}"#;
+        let err = parse_api_response::<EmptyResult>("v5/order/realtime", URL, body).unwrap_err();
+        assert!(ExchangeError::is_order_not_found(&err), "{}", err);
+    }
+
+    #[test]
+    fn maintenance_response_maps_to_typed_error() {
+        let body = r#"{"retCode":10000,"retMsg":"System maintenance in progress","result":{}}"#;
+        let err = parse_api_response::<EmptyResult>("v5/order/create", URL, body).unwrap_err();
+        assert!(ExchangeError::is_maintenance(&err), "{}", err);
+        assert!(!ExchangeError::is_transient(&err));
+
+        // Перезапуск сервиса короткий и по-прежнему повторяется
+        let body = r#"{"retCode":10016,"retMsg":"Service is restarting","result":{}}"#;
+        let err = parse_api_response::<EmptyResult>("v5/market/time", URL, body).unwrap_err();
+        assert!(ExchangeError::is_transient(&err), "{}", err);
+        assert!(!ExchangeError::is_maintenance(&err));
+
+        let body = r#"{"retCode":10006,"retMsg":"Too many visits!","result":{}}"#;
+        let err = parse_api_response::<EmptyResult>("v5/order/create", URL, body).unwrap_err();
+        assert!(ExchangeError::is_transient(&err));
+    }
+
+    fn offline_client(member_id: Option<&str>) -> Bybit {
+        Bybit {
+            api_key: "key".into(),
+            api_secret: "secret".into(),
+            client: Client::new(),
+            base_url: URL.into(),
+            recv_window: 5_000,
+            quote_currency: "USDT".into(),
+            time_offset_ms: Arc::new(Mutex::new(Some(0))),
+            balance_cache: Arc::new(Mutex::new(None)),
+            tickers_cache: Arc::default(),
+            spot_markets_cache: Arc::default(),
+            position_modes: Arc::default(),
+            member_id: None,
+            log_api_bodies_on_error: false,
+            mmr_fallback: None,
+        }
+        .with_member_id(member_id.map(str::to_string))
+    }
+
+    #[test]
+    fn all_tickers_response_is_mapped_by_symbol() {
+        let body = r#"{
+            "category": "spot",
+            "list": [
+                {"symbol": "BTCUSDT", "lastPrice": "65000.5", "bid1Price": "65000.4"},
+                {"symbol": "ETHUSDT", "lastPrice": "3200"},
+                {"symbol": "NEWUSDT", "lastPrice": ""},
+                {"symbol": "DEADUSDT", "lastPrice": "0"}
+            ]
+        }"#;
+        let result: TickersResult = serde_json::from_str(body).expect("tickers response");
+        let prices = ticker_price_map(result.list);
+
+        assert_eq!(prices.len(), 2);
+        assert_eq!(prices.get("BTCUSDT"), Some(&65000.5));
+        assert_eq!(prices.get("ETHUSDT"), Some(&3200.0));
+    }
+
+    #[tokio::test]
+    async fn invalidation_clears_cached_balances() {
+        let client = offline_client(None);
+        *client.balance_cache.lock().await = Some((vec![("BTC".to_string(), Balance { free: 1.0, locked: 0.0 })], SystemTime::now()));
+
+        client.invalidate_balance_cache().await;
+
+        assert!(client.balance_cache.lock().await.is_none());
+    }
+
+    #[test]
+    fn dated_contract_symbols_are_used_as_is() {
+        let client = offline_client(None);
+
+        assert_eq!(client.format_pair("btc"), "BTCUSDT");
+        assert_eq!(client.format_pair("BTCUSDT-26DEC25"), "BTCUSDT-26DEC25");
+        assert_eq!(client.linear_info_symbol("BTCUSDT").unwrap(), "BTC");
+        assert_eq!(client.linear_info_symbol("BTCUSDT-26DEC25").unwrap(), "BTCUSDT-26DEC25");
+        assert!(client.linear_info_symbol("BTCUSDC").is_err());
+    }
+
+    #[test]
+    fn failure_log_redacts_key_and_signature() {
+        let headers = vec![
+            ("X-BAPI-API-KEY", "key".to_string()),
+            ("X-BAPI-TIMESTAMP", "1700000000000".to_string()),
+            ("X-BAPI-SIGN", "abcdef".to_string()),
+        ];
+
+        let redacted = redact_auth_headers(&headers);
+
+        assert_eq!(redacted[0], ("X-BAPI-API-KEY".to_string(), "<redacted>".to_string()));
+        assert_eq!(redacted[1].1, "1700000000000");
+        assert_eq!(redacted[2].1, "<redacted>");
+        assert_eq!(response_ret_code(r#"{"retCode":110007,"retMsg":"ab not enough"}"#), Some(110007));
+        assert_eq!(response_ret_code("<html>502</html>"), None);
+    }
+
+    #[test]
+    fn member_id_is_added_to_scoped_queries_when_configured() {
+        let sub_account = offline_client(Some("123456"));
+        assert_eq!(
+            sub_account.member_scoped_query(&[("accountType", "UNIFIED")]),
+            vec![("accountType", "UNIFIED"), ("memberId", "123456")]
+        );
+
+        let main_account = offline_client(Some("  "));
+        assert_eq!(main_account.member_scoped_query(&[("accountType", "UNIFIED")]), vec![("accountType", "UNIFIED")]);
+    }
+
+    #[tokio::test]
+    async fn sub_account_rejects_master_scoped_requests() {
+        let sub_account = offline_client(Some("123456"));
+        let err = sub_account.get_futures_order_status("BTCUSDT", "order-1").await.unwrap_err();
+        assert!(err.to_string().contains("bybit_member_id"), "{}", err);
+        assert!(sub_account.ensure_not_master_scoped("v5/position/set-leverage").is_err());
+        assert!(sub_account.ensure_not_master_scoped("v5/asset/transfer/query-account-coins-balance").is_ok());
+
+        let main_account = offline_client(None);
+        assert!(main_account.ensure_not_master_scoped("v5/order/create").is_ok());
+    }
+
+    #[tokio::test]
+    async fn initial_sync_is_retried_after_transient_failure() {
+        let attempts = std::sync::atomic::AtomicU32::new(0);
+
+        let policy = TimeSyncPolicy { retries: 2, retry_delay: Duration::from_millis(1), required: true };
+        let result = with_retry(policy.retry_policy(), || async {
+            if attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                Err(anyhow!("Failed to send time sync request: connection reset"))
+            } else {
+                Ok(42_i64)
+            }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn sync_retries_are_bounded() {
+        let attempts = std::sync::atomic::AtomicU32::new(0);
+
+        let policy = TimeSyncPolicy { retries: 2, retry_delay: Duration::from_millis(1), required: true };
+        let result: Result<()> = with_retry(policy.retry_policy(), || async {
+            attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Err(anyhow!("still down"))
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+}
@@ -12,6 +12,7 @@ pub(super) type WsStream = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>
 
 pub(super) const CONNECT_TIMEOUT_SECONDS: u64 = 10;
 pub(super) const READ_TIMEOUT_SECONDS: u64 = 60;
+pub(super) const MAX_REAUTH_ATTEMPTS: u32 = 3; // Повторных авторизаций по истечению до полного переподключения
 // --- КОНЕЦ ДОБАВЛЕНИЙ ---
 
 // Объявляем внутренние подмодули
@@ -61,6 +61,21 @@ pub(super) async fn authenticate(ws_sender: &mut WsSink, api_key: &str, api_secr
     ws_sender.send(Message::Text(msg.into())).await.context("Send auth failed")
 }
 
+/// ret_code Bybit: запрос вне окна времени / подпись истекла (10002), срок действия API-ключа истек (33004)
+const AUTH_EXPIRED_RET_CODES: [i64; 2] = [10002, 33004];
+
+/// Сообщение сервера о том, что авторизация приватного потока истекла (по ret_code, не по тексту)
+pub(super) fn is_auth_expired_message(text: &str) -> bool {
+    let Ok(response) = serde_json::from_str::<BybitWsResponse>(text) else { return false };
+    response.success == Some(false) && response.ret_code.is_some_and(|code| AUTH_EXPIRED_RET_CODES.contains(&code))
+}
+
+/// Успешный ответ на авторизацию (первичную или повторную)
+pub(super) fn is_auth_success_message(text: &str) -> bool {
+    let Ok(response) = serde_json::from_str::<BybitWsResponse>(text) else { return false };
+    response.op.as_deref() == Some("auth") && response.success == Some(true)
+}
+
 pub(super) async fn subscribe(ws_sender: &mut WsSink, args: Vec<String>) -> Result<()> {
     let msg = json!({"op": "subscribe", "args": args}).to_string();
     debug!("Sending subscribe: {}", msg);
@@ -6,7 +6,7 @@ use serde_json::json;
 use std::time::{Duration, Instant};
 use tokio::{
     sync::mpsc,
-    time::{interval, sleep, timeout},
+    time::{interval, interval_at, sleep, timeout},
 };
 use tokio_tungstenite::tungstenite::protocol::Message;
 use tracing::{debug, error, info, warn};
@@ -14,8 +14,34 @@ use tracing::{debug, error, info, warn};
 use crate::config::Config;
 use crate::exchange::types::{SubscriptionType, WebSocketMessage};
 use crate::exchange::bybit_ws::connection::connect_auth_and_subscribe_internal;
-use crate::exchange::bybit_ws::protocol::handle_message;
-use crate::exchange::bybit_ws::{WsStream, WsSink, READ_TIMEOUT_SECONDS};
+use crate::exchange::bybit_ws::protocol::{authenticate, handle_message, is_auth_expired_message, is_auth_success_message};
+use crate::exchange::bybit_ws::{WsStream, WsSink, MAX_REAUTH_ATTEMPTS, READ_TIMEOUT_SECONDS};
+
+/// Что делать при сообщении об истекшей авторизации
+#[derive(Debug, PartialEq, Eq)]
+enum ReauthAction {
+    Reauthenticate,
+    Reconnect,
+}
+
+/// Счетчик переавторизаций подряд в рамках одного соединения: если биржа продолжает
+/// отвечать об истечении, передаем управление логике переподключения. Успешная авторизация сбрасывает счетчик
+struct ReauthTracker {
+    attempts: u32,
+}
+
+impl ReauthTracker {
+    fn new() -> Self { Self { attempts: 0 } }
+
+    fn on_auth_expired(&mut self) -> ReauthAction {
+        self.attempts += 1;
+        if self.attempts <= MAX_REAUTH_ATTEMPTS { ReauthAction::Reauthenticate } else { ReauthAction::Reconnect }
+    }
+
+    fn on_auth_success(&mut self) {
+        self.attempts = 0;
+    }
+}
 
 
 pub(super) async fn read_loop(
@@ -29,6 +55,7 @@ pub(super) async fn read_loop(
     info!("WebSocket read_loop started.");
     let ping_interval_secs = config.ws_ping_interval_secs;
     let reconnect_delay_secs = config.ws_reconnect_delay_secs;
+    let reauth_interval_secs = config.ws_reauth_interval_secs;
 
     'reconnect_loop: loop {
 
@@ -40,6 +67,10 @@ pub(super) async fn read_loop(
         let mut ping_timer = interval(Duration::from_secs(ping_interval_secs));
         let mut last_pong_received = Instant::now();
         let pong_timeout = Duration::from_secs(READ_TIMEOUT_SECONDS);
+        // Первая авторизация уже выполнена при подключении, поэтому первый тик — через полный период
+        let reauth_period = Duration::from_secs(reauth_interval_secs.max(1));
+        let mut reauth_timer = interval_at(tokio::time::Instant::now() + reauth_period, reauth_period);
+        let mut reauth_tracker = ReauthTracker::new();
 
         info!("Entering inner message processing loop.");
         loop {
@@ -53,6 +84,27 @@ pub(super) async fn read_loop(
                                  if mpsc_sender.send(Ok(WebSocketMessage::Pong)).await.is_err() { break 'reconnect_loop; }
                                  continue;
                             }
+                            let auth_expired = matches!(&message, Message::Text(text) if is_auth_expired_message(text));
+                            if auth_expired {
+                                match reauth_tracker.on_auth_expired() {
+                                    ReauthAction::Reauthenticate => {
+                                        warn!("WebSocket auth expired, re-authenticating (attempt {}/{}).", reauth_tracker.attempts, MAX_REAUTH_ATTEMPTS);
+                                        if let Err(e) = authenticate(&mut ws_sender, &config.bybit_api_key, &config.bybit_api_secret).await {
+                                            error!("WebSocket re-auth failed: {}", e);
+                                            let _ = mpsc_sender.send(Err(anyhow!("WebSocket re-auth failed: {}", e))).await;
+                                            break;
+                                        }
+                                    }
+                                    ReauthAction::Reconnect => {
+                                        error!("WebSocket auth keeps expiring after {} re-auth attempts, reconnecting.", MAX_REAUTH_ATTEMPTS);
+                                        break;
+                                    }
+                                }
+                                continue;
+                            }
+                            if matches!(&message, Message::Text(text) if is_auth_success_message(text)) {
+                                reauth_tracker.on_auth_success();
+                            }
                             // --- ИСПРАВЛЕНО: Обработка ошибки handle_error ---
                             if let Err(handle_error) = handle_message(message, &mpsc_sender).await {
                                 warn!("Error handling WebSocket message: {}", handle_error);
@@ -99,6 +151,14 @@ pub(super) async fn read_loop(
                         let _ = mpsc_sender.send(Err(anyhow!("Failed to send WebSocket Ping: {}", e))).await;
                         break;
                     }
+                }
+                _ = reauth_timer.tick(), if reauth_interval_secs > 0 => {
+                    debug!("Sending scheduled WebSocket re-auth");
+                    if let Err(e) = authenticate(&mut ws_sender, &config.bybit_api_key, &config.bybit_api_secret).await {
+                        error!("Scheduled WebSocket re-auth failed: {}", e);
+                        let _ = mpsc_sender.send(Err(anyhow!("Scheduled WebSocket re-auth failed: {}", e))).await;
+                        break;
+                    }
                 }
                  else => { if mpsc_sender.is_closed() { info!("MPSC channel closed externally."); break 'reconnect_loop; } }
             } // конец select!
@@ -132,4 +192,45 @@ pub(super) async fn read_loop(
     } // конец внешнего 'reconnect_loop
 
     info!("WebSocket read_loop permanently stopped.");
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AUTH_EXPIRED: &str = r#"{"success":false,"ret_code":10002,"ret_msg":"error:request expired","op":"auth","conn_id":"c1"}"#;
+
+    #[test]
+    fn auth_expiry_message_triggers_reauth_then_reconnect() {
+        assert!(is_auth_expired_message(AUTH_EXPIRED));
+
+        let mut tracker = ReauthTracker::new();
+        for _ in 0..MAX_REAUTH_ATTEMPTS {
+            assert_eq!(tracker.on_auth_expired(), ReauthAction::Reauthenticate);
+        }
+        assert_eq!(tracker.on_auth_expired(), ReauthAction::Reconnect);
+    }
+
+    #[test]
+    fn successful_reauth_resets_attempts() {
+        let mut tracker = ReauthTracker::new();
+        for _ in 0..MAX_REAUTH_ATTEMPTS {
+            assert_eq!(tracker.on_auth_expired(), ReauthAction::Reauthenticate);
+        }
+        assert!(is_auth_success_message(r#"{"success":true,"ret_msg":"","op":"auth","conn_id":"c1"}"#));
+        tracker.on_auth_success();
+        // Редкие истечения на долгоживущем соединении не приводят к переподключению
+        assert_eq!(tracker.on_auth_expired(), ReauthAction::Reauthenticate);
+        assert!(!is_auth_success_message(AUTH_EXPIRED));
+    }
+
+    #[test]
+    fn regular_messages_are_not_auth_expiry() {
+        assert!(!is_auth_expired_message(r#"{"success":true,"ret_msg":"","op":"auth","conn_id":"c1"}"#));
+        assert!(!is_auth_expired_message(r#"{"success":false,"ret_msg":"Params Error","op":"subscribe"}"#));
+        // Решает код, а не текст: "expired" в сообщении без кода истечения — не повод переавторизоваться
+        assert!(!is_auth_expired_message(r#"{"success":false,"ret_code":10001,"ret_msg":"order expired","op":"order.create"}"#));
+        assert!(is_auth_expired_message(r#"{"success":false,"retCode":33004,"retMsg":"api key expired","op":"auth"}"#));
+        assert!(!is_auth_expired_message("not json"));
+    }
+}
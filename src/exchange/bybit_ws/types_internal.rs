@@ -16,6 +16,8 @@ pub(super) struct BybitWsResponse { // Делаем pub(super), чтобы бы
     pub(super) req_id: Option<String>,
     pub(super) success: Option<bool>,
     pub(super) ret_msg: Option<String>,
+    #[serde(alias = "retCode")]
+    pub(super) ret_code: Option<i64>,
     pub(super) topic: Option<String>,
     #[serde(rename = "type")]
     pub(super) message_type: Option<String>,
@@ -67,8 +69,7 @@ pub(super) fn parse_order_update(data: Value) -> Result<DetailedOrderStatus, any
     let orders_data: Vec<BybitWsOrderData> = serde_json::from_value(data)
         .map_err(|e| anyhow!("Failed to parse order data array: {}", e))?;
     if let Some(order_data) = orders_data.into_iter().next() {
-        let status_text = OrderStatusText::from_str(&order_data.status)
-            .unwrap_or_else(|_| OrderStatusText::Unknown(order_data.status.clone()));
+        let status_text = OrderStatusText::from(order_data.status.as_str());
         let side = OrderSide::from_str(&order_data.side)?;
 
         Ok(DetailedOrderStatus {
@@ -0,0 +1,585 @@
+// src/exchange/mock.rs
+// Тестовая реализация Exchange с фиксированными рыночными данными (только для #[cfg(test)])
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::exchange::types::{
+    Balance, BelowMinimum, DetailedOrderStatus, ExchangeError, FeeRate, FundingRateStats, FuturesTickerInfo, LinearInstrumentInfo, LotSizeFilter,
+    LeverageBracket, LeverageFilter, MarginInfo, Order, OrderSide, OrderStatus, OrderStatusText, PositionDetails, PriceFilter, PriceSource, SpotInstrumentInfo,
+    SpotMarket, TimeSyncReport,
+};
+use crate::exchange::bybit::LINEAR_CATEGORY;
+use crate::exchange::Exchange;
+
+/// Как исполняются спотовые лимитки мока при опросе статуса (вместо dry-run режима, которого в боте нет):
+/// позволяет прогонять прогресс, перестановку и отмену без биржи
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FillSchedule {
+    Instant,                               // Полностью с первого опроса
+    Linear { polls: u32 },                 // Равными долями за polls опросов
+    PartialThenComplete { fraction: f64 }, // Первый опрос — fraction объема, второй — остаток
+    Overfill { ratio: f64 },               // С первого опроса исполнено qty * ratio (ratio > 1 — перекуп сверх ордера)
+    Stalled { fraction: f64 },             // С первого опроса исполнена fraction объема, дальше исполнение не идет
+}
+
+impl FillSchedule {
+    /// Исполненный объем после `poll`-го опроса статуса (нумерация с 1)
+    pub fn filled_after(&self, qty: f64, poll: u32) -> f64 {
+        let ratio = match *self {
+            FillSchedule::Instant => 1.0,
+            FillSchedule::Linear { polls } => f64::from(poll) / f64::from(polls.max(1)),
+            FillSchedule::PartialThenComplete { fraction } if poll <= 1 => fraction.clamp(0.0, 1.0),
+            FillSchedule::PartialThenComplete { .. } => 1.0,
+            FillSchedule::Overfill { ratio } => return qty * ratio.max(1.0),
+            FillSchedule::Stalled { fraction } => fraction.clamp(0.0, 1.0),
+        };
+        qty * ratio.min(1.0)
+    }
+}
+
+/// Биржа-заглушка: отдаёт заданные цены/фильтры, торговые методы возвращают ошибку
+#[derive(Debug, Clone)]
+pub struct MockExchange {
+    pub spot_price: f64,
+    pub mmr: f64,
+    pub spot_taker_fee: f64,
+    pub spot_base_precision: String,
+    pub spot_min_qty: String,
+    pub fut_qty_step: String,
+    pub fut_min_qty: String,
+    pub tick_size: String,
+    pub balances: Vec<(String, Balance)>,
+    pub spot_prices: HashMap<String, f64>, // Цены по монетам (иначе spot_price)
+    pub spot_markets: Vec<SpotMarket>, // Список спотовых пар (для выбора котируемой валюты)
+    pub spot_status: Option<String>,
+    pub linear_status: Option<String>,
+    pub fetch_delay: Option<Duration>, // Задержка запросов рыночных данных (для проверки параллельности)
+    pub fut_price_band: Option<(f64, f64)>, // Допустимый диапазон цен фьючерсных лимиток (min, max)
+    pub borrow_hourly_rate: Option<f64>, // None — заём недоступен
+    pub index_price: Option<f64>, // Индексная цена (иначе spot_price)
+    pub position: Option<(OrderSide, f64)>, // Фьючерсная позиция (сторона, размер); None — позиции нет
+    pub futures_bid_ask: Option<(f64, f64)>, // Bid/ask фьючерса (иначе spot_price)
+    pub spot_bid_ask: Option<(f64, f64)>, // Bid/ask спота (иначе цена спота)
+    pub fill_schedule: Option<FillSchedule>, // None — спотовые лимитки не исполняются (статус не поддерживается)
+    pub market_fill_fraction: Option<f64>, // Доля рыночного ордера, исполненная до отмены IOC-остатка; None — исполняется целиком
+    pub leverage_filter: Option<LeverageFilter>, // leverageFilter фьючерса (шаг и пределы плеча); None — не отдается
+    pub(crate) in_flight: Arc<AtomicUsize>,
+    pub(crate) max_in_flight: Arc<AtomicUsize>,
+    pub(crate) transient_place_failures: Arc<AtomicUsize>,
+    pub(crate) zero_spot_prices: Arc<AtomicUsize>, // Сколько следующих запросов цены спота вернут 0
+    pub(crate) moved_spot_price: Arc<Mutex<Option<f64>>>, // Цена спота, сдвинутая во время теста (вместо spot_price)
+    pub(crate) place_attempts: Arc<AtomicUsize>,
+    pub(crate) failing_status_polls: Arc<AtomicUsize>, // Сколько следующих опросов статуса рыночного ордера вернут ошибку
+    pub(crate) lost_place_responses: Arc<AtomicUsize>, // Сколько следующих лимиток создадутся, но ответ потеряется по таймауту
+    pub(crate) created_limit_orders: Arc<AtomicUsize>, // Сколько спотовых лимиток реально создано на "бирже"
+    pub(crate) reduce_only_orders: Arc<Mutex<Vec<(String, OrderSide, f64)>>>, // Фьючерсные reduceOnly-ордера (символ, сторона, объем)
+    pub(crate) leverage_changes: Arc<Mutex<Vec<(String, f64)>>>, // Вызовы set_leverage (символ, плечо)
+    pub(crate) link_ids: Arc<Mutex<HashMap<String, String>>>, // orderLinkId -> ID созданного ордера
+    pub(crate) market_orders: Arc<Mutex<HashMap<String, f64>>>, // Рыночные ордера (ID -> qty), исполняются сразу
+    pub(crate) amended_orders: Arc<Mutex<Vec<(String, f64)>>>, // Успешные изменения ордеров (ID, новая цена)
+    pub(crate) limit_orders: Arc<Mutex<HashMap<String, (f64, u32)>>>, // Лимитки по fill_schedule (ID -> (qty, число опросов))
+    pub(crate) cancelled_orders: Arc<Mutex<HashSet<String>>>, // Лимитки, отмененные "вручную на бирже", и фьючерсные ордера, снятые ботом
+}
+
+impl Default for MockExchange {
+    fn default() -> Self {
+        Self {
+            spot_price: 100.0,
+            mmr: 0.0,
+            spot_taker_fee: 0.001,
+            spot_base_precision: "0.0001".to_string(),
+            spot_min_qty: "0.0001".to_string(),
+            fut_qty_step: "0.01".to_string(),
+            fut_min_qty: "0.01".to_string(),
+            tick_size: "0.01".to_string(),
+            balances: Vec::new(),
+            spot_prices: HashMap::new(),
+            spot_markets: Vec::new(),
+            spot_status: Some("Trading".to_string()),
+            linear_status: Some("Trading".to_string()),
+            fetch_delay: None,
+            fut_price_band: None,
+            borrow_hourly_rate: None,
+            index_price: None,
+            position: None,
+            futures_bid_ask: None,
+            spot_bid_ask: None,
+            fill_schedule: None,
+            market_fill_fraction: None,
+            leverage_filter: None,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            max_in_flight: Arc::new(AtomicUsize::new(0)),
+            transient_place_failures: Arc::new(AtomicUsize::new(0)),
+            zero_spot_prices: Arc::new(AtomicUsize::new(0)),
+            moved_spot_price: Arc::default(),
+            place_attempts: Arc::new(AtomicUsize::new(0)),
+            failing_status_polls: Arc::new(AtomicUsize::new(0)),
+            lost_place_responses: Arc::new(AtomicUsize::new(0)),
+            created_limit_orders: Arc::new(AtomicUsize::new(0)),
+            reduce_only_orders: Arc::default(),
+            leverage_changes: Arc::default(),
+            link_ids: Arc::default(),
+            market_orders: Arc::default(),
+            amended_orders: Arc::default(),
+            limit_orders: Arc::default(),
+            cancelled_orders: Arc::default(),
+        }
+    }
+}
+
+impl MockExchange {
+    /// Максимальное число одновременно выполнявшихся запросов рыночных данных
+    pub fn max_in_flight(&self) -> usize {
+        self.max_in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Следующие `count` размещений спотовых лимиток завершатся временной ошибкой
+    pub fn fail_next_placements(&self, count: usize) {
+        self.transient_place_failures.store(count, Ordering::SeqCst);
+    }
+
+    /// Следующие `count` спотовых лимиток дойдут до биржи, но вернут таймаут (ответ потерян)
+    pub fn lose_next_placement_responses(&self, count: usize) {
+        self.lost_place_responses.store(count, Ordering::SeqCst);
+    }
+
+    /// Сколько спотовых лимиток реально создано (повтор с тем же orderLinkId нового ордера не создаёт)
+    pub fn created_limit_orders(&self) -> usize {
+        self.created_limit_orders.load(Ordering::SeqCst)
+    }
+
+    /// Следующие `count` опросов статуса рыночных ордеров завершатся сетевой ошибкой
+    pub fn fail_next_status_polls(&self, count: usize) {
+        self.failing_status_polls.store(count, Ordering::SeqCst);
+    }
+
+    /// Сдвинуть цену спота для всех клонов заглушки (движение рынка во время цикла ордера)
+    pub fn move_spot_price(&self, price: f64) {
+        *self.moved_spot_price.lock().unwrap() = Some(price);
+    }
+
+    /// Следующие `count` запросов цены спота вернут 0 (как тикер только что листингованной пары)
+    pub fn return_zero_spot_prices(&self, count: usize) {
+        self.zero_spot_prices.store(count, Ordering::SeqCst);
+    }
+
+    /// Сколько раз вызывалось размещение спотовой лимитки
+    pub fn placement_attempts(&self) -> usize {
+        self.place_attempts.load(Ordering::SeqCst)
+    }
+
+    /// Отмена лимитки в обход бота (как вручную в приложении биржи): исполнение дальше не идет
+    pub fn cancel_order_externally(&self, order_id: &str) {
+        self.cancelled_orders.lock().unwrap().insert(order_id.to_string());
+    }
+
+    /// Ордер отменен (ботом или извне)
+    pub fn is_cancelled(&self, order_id: &str) -> bool {
+        self.cancelled_orders.lock().unwrap().contains(order_id)
+    }
+
+    /// Размещенные фьючерсные reduceOnly-ордера (символ, сторона, объем) в порядке вызова
+    pub fn reduce_only_orders(&self) -> Vec<(String, OrderSide, f64)> {
+        self.reduce_only_orders.lock().unwrap().clone()
+    }
+
+    /// Выставленные плечи (символ, плечо) в порядке вызова
+    pub fn leverage_changes(&self) -> Vec<(String, f64)> {
+        self.leverage_changes.lock().unwrap().clone()
+    }
+
+    /// Принятые изменения ордеров (ID, новая цена) в порядке вызова
+    pub fn amended_orders(&self) -> Vec<(String, f64)> {
+        self.amended_orders.lock().unwrap().clone()
+    }
+
+    async fn simulate_fetch(&self) {
+        let current = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(current, Ordering::SeqCst);
+        if let Some(delay) = self.fetch_delay {
+            tokio::time::sleep(delay).await;
+        }
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn place_market_order(&self, prefix: &str, side: OrderSide, qty: f64) -> Order {
+        let mut orders = self.market_orders.lock().unwrap();
+        let id = format!("{}-{}", prefix, orders.len() + 1);
+        orders.insert(id.clone(), qty);
+        Order { id, side, qty, price: None, ts: 0 }
+    }
+
+    /// Как Bybit (validate_and_format_qty): спотовый объем меньше minOrderQty отклоняется с BelowMinimum
+    fn check_spot_min_qty(&self, qty: f64) -> Result<()> {
+        let min_qty = Decimal::from_str(&self.spot_min_qty).unwrap_or(Decimal::ZERO);
+        let requested = Decimal::from_f64(qty).unwrap_or(Decimal::ZERO);
+        if requested <= Decimal::ZERO || requested < min_qty {
+            return Err(BelowMinimum { requested: qty, rounded: requested, min_qty, step: self.spot_base_precision.clone() }.into());
+        }
+        Ok(())
+    }
+
+    /// Спотовая лимитка; повтор с уже использованным orderLinkId возвращает созданный ранее ордер (как Bybit после "дубликата")
+    fn place_spot_limit(&self, side: OrderSide, qty: f64, price: f64, link_id: Option<&str>) -> Result<Order> {
+        self.check_spot_min_qty(qty)?;
+        self.place_attempts.fetch_add(1, Ordering::SeqCst);
+        if let Some(id) = link_id.and_then(|link_id| self.link_ids.lock().unwrap().get(link_id).cloned()) {
+            return Ok(Order { id, side, qty, price: Some(price), ts: 0 });
+        }
+        if take_one(&self.transient_place_failures) {
+            return Err(ExchangeError::Transient("MockExchange: simulated network error".to_string()).into());
+        }
+        let id = if self.fill_schedule.is_some() {
+            let mut orders = self.limit_orders.lock().unwrap();
+            let id = format!("mock-spot-order-{}", orders.len() + 1);
+            orders.insert(id.clone(), (qty, 0));
+            id
+        } else {
+            "mock-spot-order".to_string()
+        };
+        self.created_limit_orders.fetch_add(1, Ordering::SeqCst);
+        if let Some(link_id) = link_id {
+            self.link_ids.lock().unwrap().insert(link_id.to_string(), id.clone());
+        }
+        if take_one(&self.lost_place_responses) {
+            return Err(ExchangeError::Transient("MockExchange: simulated timeout after order was accepted".to_string()).into());
+        }
+        Ok(Order { id, side, qty, price: Some(price), ts: 0 })
+    }
+
+    fn market_order_status(&self, method: &str, order_id: &str) -> Result<OrderStatus> {
+        let Some(qty) = self.market_orders.lock().unwrap().get(order_id).copied() else {
+            return unsupported(method);
+        };
+        if take_one(&self.failing_status_polls) {
+            return Err(ExchangeError::Transient("MockExchange: simulated status poll failure".to_string()).into());
+        }
+        match self.market_fill_fraction {
+            // Как у Bybit: отмененный IOC-остаток дает leavesQty = 0
+            Some(fraction) if fraction < 1.0 => {
+                let filled_qty = qty * fraction.max(0.0);
+                let status = if filled_qty > 0.0 { OrderStatusText::PartiallyFilledCanceled } else { OrderStatusText::Cancelled };
+                Ok(OrderStatus { filled_qty, remaining_qty: 0.0, status })
+            }
+            _ => Ok(OrderStatus { filled_qty: qty, remaining_qty: 0.0, status: OrderStatusText::Filled }),
+        }
+    }
+
+    /// Статус лимитки по fill_schedule: каждый опрос продвигает исполнение на шаг
+    fn scheduled_order_status(&self, order_id: &str) -> Option<OrderStatus> {
+        let schedule = self.fill_schedule?;
+        let mut orders = self.limit_orders.lock().unwrap();
+        let (qty, polls) = orders.get_mut(order_id)?;
+        if self.cancelled_orders.lock().unwrap().contains(order_id) {
+            // Как у Bybit: у отмененного ордера leavesQty = 0
+            let filled_qty = if *polls == 0 { 0.0 } else { schedule.filled_after(*qty, *polls) };
+            let status = if filled_qty > 0.0 { OrderStatusText::PartiallyFilledCanceled } else { OrderStatusText::Cancelled };
+            return Some(OrderStatus { filled_qty, remaining_qty: 0.0, status });
+        }
+        *polls += 1;
+        let filled_qty = schedule.filled_after(*qty, *polls);
+        let remaining_qty = (*qty - filled_qty).max(0.0);
+        let status = match (filled_qty > 0.0, remaining_qty > 0.0) {
+            (_, false) => OrderStatusText::Filled,
+            (true, true) => OrderStatusText::PartiallyFilled,
+            (false, true) => OrderStatusText::New,
+        };
+        Some(OrderStatus { filled_qty, remaining_qty, status })
+    }
+
+    fn lot_size_filter(&self, is_spot: bool) -> LotSizeFilter {
+        LotSizeFilter {
+            base_precision: is_spot.then(|| self.spot_base_precision.clone()),
+            qty_step: (!is_spot).then(|| self.fut_qty_step.clone()),
+            max_order_qty: "1000000".to_string(),
+            min_order_qty: if is_spot { self.spot_min_qty.clone() } else { self.fut_min_qty.clone() },
+            max_mkt_order_qty: None,
+            min_notional_value: None,
+            post_only_max_order_qty: None,
+        }
+    }
+}
+
+/// Отказ Bybit по цене вне коридора (retCode 110003) в том же виде, что у настоящего клиента
+fn price_band_error() -> anyhow::Error {
+    let message = "Order price is out of permissible range".to_string();
+    ExchangeError::Api { code: 110003, raw: message.clone(), message }.into()
+}
+
+fn unsupported<T>(method: &str) -> Result<T> {
+    Err(anyhow!("MockExchange: {} не поддерживается", method))
+}
+
+/// Уменьшает счетчик на 1, если он не нулевой; true — событие срабатывает
+fn take_one(counter: &AtomicUsize) -> bool {
+    counter.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1)).is_ok()
+}
+
+#[async_trait]
+impl Exchange for MockExchange {
+    async fn check_connection(&mut self) -> Result<()> {
+        Ok(())
+    }
+    async fn get_balance(&self, coin: &str) -> Result<Balance> {
+        Ok(self
+            .balances
+            .iter()
+            .find(|(c, _)| c == coin)
+            .map(|(_, balance)| *balance)
+            .unwrap_or(Balance { free: 0.0, locked: 0.0 }))
+    }
+    async fn get_all_balances(&self) -> Result<Vec<(String, Balance)>> {
+        Ok(self.balances.clone())
+    }
+    async fn get_account_margin(&self) -> Result<MarginInfo> {
+        Ok(MarginInfo {
+            account_mm_rate: 0.0,
+            account_im_rate: 0.0,
+            total_maintenance_margin: 0.0,
+            total_initial_margin: 0.0,
+            total_equity: 0.0,
+        })
+    }
+    async fn get_spot_instrument_info(&self, symbol: &str) -> Result<SpotInstrumentInfo> {
+        self.simulate_fetch().await;
+        Ok(SpotInstrumentInfo {
+            symbol: symbol.to_string(),
+            status: self.spot_status.clone(),
+            lot_size_filter: self.lot_size_filter(true),
+            price_filter: PriceFilter { tick_size: self.tick_size.clone(), min_price: None, max_price: None },
+        })
+    }
+    async fn get_all_spot_symbols(&self) -> Result<Vec<SpotMarket>> {
+        self.simulate_fetch().await;
+        Ok(self.spot_markets.clone())
+    }
+    async fn get_linear_instrument_info(&self, symbol: &str) -> Result<LinearInstrumentInfo> {
+        self.simulate_fetch().await;
+        // Как у Bybit: клиент сам дописывает котируемую валюту, полный символ бессрочного контракта — ошибка вызывающего
+        if !symbol.contains('-') && (symbol.ends_with("USDT") || symbol.ends_with("USDC")) {
+            return Err(anyhow!("MockExchange: get_linear_instrument_info expects a base symbol or dated contract, got {}", symbol));
+        }
+        Ok(LinearInstrumentInfo {
+            symbol: symbol.to_string(),
+            status: self.linear_status.clone(),
+            lot_size_filter: self.lot_size_filter(false),
+            price_filter: PriceFilter {
+                tick_size: self.tick_size.clone(),
+                min_price: self.fut_price_band.map(|(min, _)| min.to_string()),
+                max_price: self.fut_price_band.map(|(_, max)| max.to_string()),
+            },
+            leverage_filter: self.leverage_filter.clone(),
+        })
+    }
+    async fn get_fee_rate(&self, _symbol: &str, _category: &str) -> Result<FeeRate> {
+        self.simulate_fetch().await;
+        Ok(FeeRate { maker: self.spot_taker_fee, taker: self.spot_taker_fee })
+    }
+    async fn place_limit_order(&self, _symbol: &str, side: OrderSide, qty: f64, price: f64) -> Result<Order> {
+        self.place_spot_limit(side, qty, price, None)
+    }
+    async fn place_limit_order_with_link_id(&self, _symbol: &str, side: OrderSide, qty: f64, price: f64, link_id: &str) -> Result<Order> {
+        self.place_spot_limit(side, qty, price, Some(link_id))
+    }
+    async fn place_futures_market_order(&self, _symbol: &str, side: OrderSide, qty: f64) -> Result<Order> {
+        Ok(self.place_market_order("mock-futures-market", side, qty))
+    }
+    async fn place_futures_reduce_only_market_order(&self, symbol: &str, side: OrderSide, qty: f64) -> Result<Order> {
+        self.reduce_only_orders.lock().unwrap().push((symbol.to_string(), side, qty));
+        Ok(self.place_market_order("mock-futures-reduce-only", side, qty))
+    }
+    async fn place_futures_limit_order(&self, _symbol: &str, side: OrderSide, qty: f64, price: f64) -> Result<Order> {
+        let (min, max) = match self.fut_price_band {
+            Some(band) => band,
+            None => return unsupported("place_futures_limit_order"),
+        };
+        if price < min || price > max {
+            return Err(price_band_error());
+        }
+        Ok(Order { id: "mock-futures-order".to_string(), side, qty, price: Some(price), ts: 0 })
+    }
+    async fn place_futures_limit_order_with_link_id(&self, symbol: &str, side: OrderSide, qty: f64, price: f64, _link_id: &str) -> Result<Order> {
+        self.place_futures_limit_order(symbol, side, qty, price).await
+    }
+    async fn place_spot_market_order(&self, _symbol: &str, side: OrderSide, qty: f64) -> Result<Order> {
+        self.check_spot_min_qty(qty)?;
+        Ok(self.place_market_order("mock-spot-market", side, qty))
+    }
+    async fn cancel_order(&self, _symbol: &str, _order_id: &str) -> Result<()> {
+        unsupported("cancel_order")
+    }
+    async fn get_spot_price(&self, symbol: &str) -> Result<f64> {
+        self.simulate_fetch().await;
+        if take_one(&self.zero_spot_prices) {
+            return Ok(0.0);
+        }
+        if let Some(price) = *self.moved_spot_price.lock().unwrap() {
+            return Ok(price);
+        }
+        Ok(self.spot_prices.get(symbol).copied().unwrap_or(self.spot_price))
+    }
+    async fn get_spot_bid_ask(&self, symbol: &str) -> Result<(f64, f64)> {
+        if let Some(bid_ask) = self.spot_bid_ask {
+            return Ok(bid_ask);
+        }
+        let price = self.get_spot_price(symbol).await?;
+        Ok((price, price))
+    }
+    async fn get_all_tickers(&self, _category: &str) -> Result<HashMap<String, f64>> {
+        self.simulate_fetch().await;
+        Ok(self.spot_prices.iter().map(|(coin, price)| (format!("{}USDT", coin), *price)).collect())
+    }
+    async fn get_reference_price(&self, symbol: &str, _category: &str, source: PriceSource) -> Result<f64> {
+        match source {
+            PriceSource::Index => Ok(self.index_price.unwrap_or(self.spot_price)),
+            PriceSource::Last | PriceSource::Mid => self.get_spot_price(symbol).await,
+        }
+    }
+    async fn get_order_status(&self, _symbol: &str, _order_id: &str) -> Result<OrderStatus> {
+        unsupported("get_order_status")
+    }
+    async fn get_mmr(&self, _symbol: &str) -> Result<f64> {
+        self.simulate_fetch().await;
+        Ok(self.mmr)
+    }
+    async fn get_symbol_leverage_bracket(&self, _symbol: &str, _position_value: f64) -> Result<LeverageBracket> {
+        self.simulate_fetch().await;
+        Ok(LeverageBracket { risk_limit_value: f64::MAX, mmr: self.mmr, max_leverage: 100.0 })
+    }
+    async fn get_funding_rate(&self, _symbol: &str, _days: u16) -> Result<FundingRateStats> {
+        Ok(FundingRateStats::default())
+    }
+    async fn get_borrow_rate(&self, coin: &str) -> Result<f64> {
+        self.borrow_hourly_rate.ok_or_else(|| anyhow!("Borrowing is not available for {} on this account", coin))
+    }
+    async fn resync_time(&self) -> Result<TimeSyncReport> {
+        Ok(TimeSyncReport { previous_offset_ms: Some(0), offset_ms: 0, recv_window_ms: 5_000 })
+    }
+    async fn get_current_leverage(&self, _symbol: &str) -> Result<f64> {
+        Ok(1.0)
+    }
+    async fn get_position_details(&self, symbol: &str) -> Result<PositionDetails> {
+        let (side, size) = self.position.map_or((None, 0.0), |(side, size)| (Some(side), size));
+        Ok(PositionDetails { symbol: symbol.to_string(), side, size, avg_price: self.spot_price })
+    }
+    async fn set_leverage(&self, symbol: &str, leverage: f64) -> Result<()> {
+        self.leverage_changes.lock().unwrap().push((symbol.to_string(), leverage));
+        Ok(())
+    }
+    async fn set_trailing_stop(&self, _symbol: &str, _distance: f64) -> Result<()> {
+        Ok(())
+    }
+    async fn cancel_spot_order(&self, _symbol: &str, order_id: &str) -> Result<()> {
+        if self.fill_schedule.is_some() && self.limit_orders.lock().unwrap().contains_key(order_id) {
+            return Ok(());
+        }
+        unsupported("cancel_spot_order")
+    }
+    async fn cancel_futures_order(&self, _symbol: &str, order_id: &str) -> Result<()> {
+        if self.market_orders.lock().unwrap().contains_key(order_id) {
+            self.cancelled_orders.lock().unwrap().insert(order_id.to_string());
+            return Ok(());
+        }
+        unsupported("cancel_futures_order")
+    }
+    async fn cancel_order_by_link_id(&self, _symbol: &str, _link_id: &str, _category: &str) -> Result<()> {
+        unsupported("cancel_order_by_link_id")
+    }
+    async fn amend_order(&self, _symbol: &str, order_id: &str, new_price: f64, _new_qty: Option<f64>, category: &str) -> Result<()> {
+        // Фьючерсный коридор цен проверяется так же, как при размещении
+        if category == LINEAR_CATEGORY {
+            if let Some((min, max)) = self.fut_price_band {
+                if new_price < min || new_price > max {
+                    return Err(price_band_error());
+                }
+            }
+        }
+        self.amended_orders.lock().unwrap().push((order_id.to_string(), new_price));
+        Ok(())
+    }
+    async fn get_spot_order_status(&self, _symbol: &str, order_id: &str) -> Result<OrderStatus> {
+        if let Some(status) = self.scheduled_order_status(order_id) {
+            return Ok(status);
+        }
+        self.market_order_status("get_spot_order_status", order_id)
+    }
+    async fn get_futures_order_status(&self, _symbol: &str, order_id: &str) -> Result<OrderStatus> {
+        self.market_order_status("get_futures_order_status", order_id)
+    }
+    async fn get_spot_order_execution_details(&self, _symbol: &str, _order_id: &str) -> Result<DetailedOrderStatus> {
+        unsupported("get_spot_order_execution_details")
+    }
+    async fn get_futures_ticker(&self, symbol: &str) -> Result<FuturesTickerInfo> {
+        let (bid_price, ask_price) = self.futures_bid_ask.unwrap_or((self.spot_price, self.spot_price));
+        Ok(FuturesTickerInfo {
+            symbol: symbol.to_string(),
+            bid_price,
+            ask_price,
+            last_price: self.spot_price,
+        })
+    }
+    async fn get_market_price(&self, _symbol: &str, _is_spot: bool) -> Result<f64> {
+        Ok(self.spot_price)
+    }
+    async fn get_spot_price_fallback(&self, _futures_symbol: &str) -> Result<f64> {
+        Ok(self.spot_price)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_then_complete_schedule_fills_in_two_steps() {
+        let schedule = FillSchedule::PartialThenComplete { fraction: 0.4 };
+        assert_eq!(schedule.filled_after(10.0, 1), 4.0);
+        assert_eq!(schedule.filled_after(10.0, 2), 10.0);
+        assert_eq!(schedule.filled_after(10.0, 5), 10.0);
+        assert_eq!(FillSchedule::Linear { polls: 4 }.filled_after(10.0, 1), 2.5);
+        assert_eq!(FillSchedule::Linear { polls: 4 }.filled_after(10.0, 9), 10.0);
+        assert_eq!(FillSchedule::Instant.filled_after(10.0, 1), 10.0);
+        assert_eq!(FillSchedule::Overfill { ratio: 1.5 }.filled_after(10.0, 1), 15.0);
+        assert_eq!(FillSchedule::Stalled { fraction: 0.5 }.filled_after(10.0, 7), 5.0);
+    }
+
+    #[tokio::test]
+    async fn scheduled_limit_order_reports_partial_then_full_fill() {
+        let exchange = MockExchange {
+            fill_schedule: Some(FillSchedule::PartialThenComplete { fraction: 0.5 }),
+            ..MockExchange::default()
+        };
+        let order = exchange.place_limit_order("BTC", OrderSide::Buy, 2.0, 100.0).await.expect("order");
+
+        let first = exchange.get_spot_order_status("BTC", &order.id).await.expect("status");
+        assert_eq!((first.filled_qty, first.remaining_qty), (1.0, 1.0));
+        let second = exchange.get_spot_order_status("BTC", &order.id).await.expect("status");
+        assert_eq!((second.filled_qty, second.remaining_qty), (2.0, 0.0));
+        assert!(exchange.cancel_spot_order("BTC", &order.id).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn externally_cancelled_order_reports_terminal_status() {
+        let exchange = MockExchange {
+            fill_schedule: Some(FillSchedule::Linear { polls: 4 }),
+            ..MockExchange::default()
+        };
+        let order = exchange.place_limit_order("BTC", OrderSide::Buy, 2.0, 100.0).await.expect("order");
+        let first = exchange.get_spot_order_status("BTC", &order.id).await.expect("status");
+        assert_eq!(first.status, OrderStatusText::PartiallyFilled);
+
+        exchange.cancel_order_externally(&order.id);
+        let cancelled = exchange.get_spot_order_status("BTC", &order.id).await.expect("status");
+        assert_eq!((cancelled.filled_qty, cancelled.remaining_qty), (0.5, 0.0));
+        assert_eq!(cancelled.status, OrderStatusText::PartiallyFilledCanceled);
+        assert!(cancelled.status.is_cancelled());
+    }
+}
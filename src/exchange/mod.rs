@@ -1,34 +1,51 @@
 // src/exchange/mod.rs
 use anyhow::Result;
 use async_trait::async_trait;
+use std::collections::HashMap;
 // --- ИСПРАВЛЕНО: Импортируем типы отсюда ---
 use crate::exchange::types::{
     Balance, Order, OrderSide, OrderStatus, FeeRate, FuturesTickerInfo, DetailedOrderStatus,
-    SpotInstrumentInfo, LinearInstrumentInfo // Добавили InstrumentInfo
+    SpotInstrumentInfo, LinearInstrumentInfo, SpotMarket, // Добавили InstrumentInfo
+    MarginInfo, PositionDetails, PriceSource, FundingRateStats, TimeSyncReport, LeverageBracket,
 };
 // --- УДАЛЕНО: use crate::hedger::params::{SpotInstrumentInfo, LinearInstrumentInfo}; ---
 #[async_trait]
 pub trait Exchange: Send + Sync {
     async fn check_connection(&mut self) -> Result<()>;
+    async fn resync_time(&self) -> Result<TimeSyncReport>; // Принудительная синхронизация времени с сервером (/resync)
     async fn get_balance(&self, coin: &str) -> Result<Balance>;
     async fn get_all_balances(&self) -> Result<Vec<(String, Balance)>>;
+    async fn get_account_margin(&self) -> Result<MarginInfo>;
     // --- ИСПРАВЛЕНО: Используем типы из types.rs ---
     async fn get_spot_instrument_info(&self, symbol: &str) -> Result<SpotInstrumentInfo>;
     async fn get_linear_instrument_info(&self, symbol: &str) -> Result<LinearInstrumentInfo>;
+    async fn get_all_spot_symbols(&self) -> Result<Vec<SpotMarket>>; // Все спотовые пары с базовой/котируемой монетой (для выбора рынка)
     async fn get_fee_rate(&self, symbol: &str, category: &str) -> Result<FeeRate>;
     async fn place_limit_order(&self, symbol: &str, side: OrderSide, qty: f64, price: f64) -> Result<Order>;
+    async fn place_limit_order_with_link_id(&self, symbol: &str, side: OrderSide, qty: f64, price: f64, link_id: &str) -> Result<Order>; // Идемпотентное размещение: повтор с тем же orderLinkId возвращает уже созданный ордер
     async fn place_futures_market_order(&self, symbol: &str, side: OrderSide, qty: f64) -> Result<Order>;
+    async fn place_futures_reduce_only_market_order(&self, symbol: &str, side: OrderSide, qty: f64) -> Result<Order>; // Закрытие позиции: reduceOnly не даст открыть встречную
     async fn place_futures_limit_order(&self, symbol: &str, side: OrderSide, qty: f64, price: f64) -> Result<Order>;
+    async fn place_futures_limit_order_with_link_id(&self, symbol: &str, side: OrderSide, qty: f64, price: f64, link_id: &str) -> Result<Order>; // То же для фьючерсов
     async fn place_spot_market_order(&self, symbol: &str, side: OrderSide, qty: f64) -> Result<Order>;
     async fn cancel_order(&self, symbol: &str, order_id: &str) -> Result<()>; // Deprecated
     async fn get_spot_price(&self, symbol: &str) -> Result<f64>;
+    async fn get_spot_bid_ask(&self, symbol: &str) -> Result<(f64, f64)>; // Лучшие bid/ask спотовой пары (symbol — базовая монета, как в get_spot_price)
+    async fn get_all_tickers(&self, category: &str) -> Result<HashMap<String, f64>>; // Последние цены всех символов категории (пара -> цена) одним запросом
+    async fn get_reference_price(&self, symbol: &str, category: &str, source: PriceSource) -> Result<f64>; // Цена по выбранному источнику
     async fn get_order_status(&self, symbol: &str, order_id: &str) -> Result<OrderStatus>; // Deprecated
     async fn get_mmr(&self, symbol: &str) -> Result<f64>;
-    async fn get_funding_rate(&self, symbol: &str, days: u16) -> Result<f64>;
+    async fn get_symbol_leverage_bracket(&self, symbol: &str, position_value: f64) -> Result<LeverageBracket>; // Ступень риск-лимита под стоимость позиции (MMR и макс. плечо)
+    async fn get_funding_rate(&self, symbol: &str, days: u16) -> Result<FundingRateStats>; // Средняя ставка за days дней (не более 200 интервалов)
+    async fn get_borrow_rate(&self, coin: &str) -> Result<f64>; // Почасовая ставка займа (маржинальный спот)
     async fn get_current_leverage(&self, symbol: &str) -> Result<f64>;
+    async fn get_position_details(&self, symbol: &str) -> Result<PositionDetails>; // Позиция по linear-символу (например, BTCUSDT)
     async fn set_leverage(&self, symbol: &str, leverage: f64) -> Result<()>;
+    async fn set_trailing_stop(&self, symbol: &str, distance: f64) -> Result<()>; // distance = 0 снимает трейлинг-стоп
     async fn cancel_spot_order(&self, symbol: &str, order_id: &str) -> Result<()>;
     async fn cancel_futures_order(&self, symbol: &str, order_id: &str) -> Result<()>;
+    async fn cancel_order_by_link_id(&self, symbol: &str, link_id: &str, category: &str) -> Result<()>; // Отмена по клиентскому orderLinkId, когда orderId неизвестен (symbol как в place/cancel для категории)
+    async fn amend_order(&self, symbol: &str, order_id: &str, new_price: f64, new_qty: Option<f64>, category: &str) -> Result<()>; // Изменение цены/количества активного ордера (symbol как в place/cancel для категории)
     async fn get_spot_order_status(&self, symbol: &str, order_id: &str) -> Result<OrderStatus>;
     async fn get_futures_order_status(&self, symbol: &str, order_id: &str) -> Result<OrderStatus>;
     async fn get_spot_order_execution_details(&self, symbol: &str, order_id: &str) -> Result<DetailedOrderStatus>;
@@ -40,6 +57,8 @@ pub trait Exchange: Send + Sync {
 pub mod bybit;
 pub mod types;
 pub mod bybit_ws; // <-- Изменяем объявление на модуль
+#[cfg(test)]
+pub mod mock;
 
 // Функция для создания экземпляра биржи
 pub async fn create_exchange(
@@ -49,10 +49,11 @@ pub struct Order {
     pub ts: i64, // Timestamp создания
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct OrderStatus {
     pub filled_qty: f64,
     pub remaining_qty: f64,
+    pub status: OrderStatusText, // orderStatus биржи: у отмененного ордера remaining_qty = 0, как у исполненного
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -68,11 +69,10 @@ pub enum OrderStatusText {
     Unknown(String),
 }
 
-impl FromStr for OrderStatusText {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(match s {
+/// Статус из ответа биржи; неизвестный текст сохраняется в Unknown
+impl From<&str> for OrderStatusText {
+    fn from(s: &str) -> Self {
+        match s {
             "New" => OrderStatusText::New,
             "PartiallyFilled" => OrderStatusText::PartiallyFilled,
             "Filled" => OrderStatusText::Filled,
@@ -82,7 +82,14 @@ impl FromStr for OrderStatusText {
             "Untriggered" => OrderStatusText::Untriggered,
             "Triggered" => OrderStatusText::Triggered,
             _ => OrderStatusText::Unknown(s.to_string()),
-        })
+        }
+    }
+}
+
+impl OrderStatusText {
+    /// Ордер закрыт биржей без исполнения остатка (отменен, в т.ч. после частичного исполнения)
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, OrderStatusText::Cancelled | OrderStatusText::PartiallyFilledCanceled)
     }
 }
 
@@ -107,6 +114,75 @@ pub struct FeeRate {
     pub taker: f64,
 }
 
+/// Результат ручной синхронизации времени с биржей (/resync)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeSyncReport {
+    pub previous_offset_ms: Option<i64>, // None — время еще не было синхронизировано
+    pub offset_ms: i64,                  // Время сервера минус локальное
+    pub recv_window_ms: u64,
+}
+
+impl TimeSyncReport {
+    /// Расхождение часов меньше recv_window (подписанные запросы проходят и без поправки)
+    pub fn within_recv_window(&self) -> bool {
+        self.offset_ms.unsigned_abs() < self.recv_window_ms
+    }
+}
+
+/// Ступень риск-лимита фьючерса: до какой стоимости позиции действует, ее MMR и максимальное плечо
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LeverageBracket {
+    pub risk_limit_value: f64, // Верхняя граница стоимости позиции (в quote) для ступени
+    pub mmr: f64,
+    pub max_leverage: f64,
+}
+
+/// Средняя ставка финансирования и число усредненных интервалов
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FundingRateStats {
+    pub avg_rate: f64,
+    pub intervals: usize,
+}
+
+/// Маржинальное состояние единого торгового аккаунта
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarginInfo {
+    pub account_mm_rate: f64, // Коэффициент поддерживающей маржи (1.0 = ликвидация)
+    pub account_im_rate: f64, // Коэффициент начальной маржи
+    pub total_maintenance_margin: f64,
+    pub total_initial_margin: f64,
+    pub total_equity: f64,
+}
+
+/// Открытая позиция по линейному контракту (side = None — позиции нет)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionDetails {
+    pub symbol: String,
+    pub side: Option<OrderSide>,
+    pub size: f64,
+    pub avg_price: f64,
+}
+
+impl PositionDetails {
+    /// Размер со знаком: лонг положительный, шорт отрицательный
+    pub fn signed_size(&self) -> f64 {
+        match self.side {
+            Some(OrderSide::Buy) => self.size,
+            Some(OrderSide::Sell) => -self.size,
+            None => 0.0,
+        }
+    }
+}
+
+/// Источник опорной цены для расчета лимитных ордеров
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PriceSource {
+    Last,  // Последняя сделка
+    Mid,   // Середина между лучшими bid/ask
+    Index, // Индексная цена (меньше скачет, чем last)
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct FuturesTickerInfo {
     pub symbol: String,
@@ -137,26 +213,143 @@ pub struct LotSizeFilter {
 pub struct PriceFilter {
     #[serde(rename = "tickSize")]
     pub tick_size: String,
+    #[serde(rename = "minPrice", default)]
+    pub min_price: Option<String>,
+    #[serde(rename = "maxPrice", default)]
+    pub max_price: Option<String>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct SpotInstrumentInfo {
     pub symbol: String,
+    #[serde(default)]
+    pub status: Option<String>, // "Trading", "PreLaunch", "Delivering", "Closed", ...
     #[serde(rename = "lotSizeFilter")]
     pub lot_size_filter: LotSizeFilter,
     #[serde(rename = "priceFilter")]
     pub price_filter: PriceFilter,
 }
 
+/// Спотовая пара из общего списка инструментов: базовая и котируемая монеты
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct SpotMarket {
+    pub symbol: String,
+    #[serde(rename = "baseCoin")]
+    pub base_coin: String,
+    #[serde(rename = "quoteCoin")]
+    pub quote_coin: String,
+    #[serde(default)]
+    pub status: Option<String>,
+}
+
+/// Допустимое плечо фьючерса: set-leverage принимает только значения, кратные leverageStep, в пределах min/max
+#[derive(Deserialize, Debug, Clone)]
+pub struct LeverageFilter {
+    #[serde(rename = "minLeverage")]
+    pub min_leverage: String,
+    #[serde(rename = "maxLeverage")]
+    pub max_leverage: String,
+    #[serde(rename = "leverageStep")]
+    pub leverage_step: String,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct LinearInstrumentInfo {
     pub symbol: String,
+    #[serde(default)]
+    pub status: Option<String>, // "Trading", "PreLaunch", "Delivering", "Closed", ...
     #[serde(rename = "lotSizeFilter")]
     pub lot_size_filter: LotSizeFilter,
     #[serde(rename = "priceFilter")]
     pub price_filter: PriceFilter,
+    #[serde(rename = "leverageFilter", default)]
+    pub leverage_filter: Option<LeverageFilter>,
+}
+
+/// Проверяет статус инструмента: торговать можно только в статусе "Trading" (отсутствие статуса не блокирует)
+pub fn ensure_instrument_trading(symbol: &str, status: Option<&str>) -> anyhow::Result<()> {
+    match status {
+        Some(status) if !status.eq_ignore_ascii_case("Trading") => Err(anyhow::anyhow!(
+            "Symbol {} is not available for trading (status: {}). It may be delisted or in settlement.",
+            symbol,
+            status
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Типизированные ошибки биржи: позволяют отличить временный сбой от постоянного отказа
+#[derive(Debug, Clone)]
+pub enum ExchangeError {
+    /// Сеть, таймаут, лимит запросов, перегрузка биржи — запрос можно повторить
+    Transient(String),
+    /// Ответ API с ненулевым retCode (валидация, баланс, минимумы и т.п.)
+    Api { code: i64, message: String, raw: String },
+    /// Биржа на техническом обслуживании — повторять бессмысленно до окончания работ
+    Maintenance(String),
+    /// Ордер не найден среди активных (исполнен и ушел в историю, отменен или не существует)
+    OrderNotFound(String),
+    /// positionIdx ордера не совпал с режимом позиций аккаунта (one-way / hedge-mode): режим определен заново
+    PositionModeMismatch(String),
+}
+
+impl ExchangeError {
+    /// Является ли ошибка (в т.ч. завёрнутая в anyhow) временной
+    pub fn is_transient(error: &anyhow::Error) -> bool {
+        matches!(error.downcast_ref::<ExchangeError>(), Some(ExchangeError::Transient(_)))
+    }
+
+    /// Является ли ошибка (в т.ч. завёрнутая в anyhow) признаком техобслуживания биржи
+    pub fn is_maintenance(error: &anyhow::Error) -> bool {
+        matches!(error.downcast_ref::<ExchangeError>(), Some(ExchangeError::Maintenance(_)))
+    }
+
+    /// Ордер не найден на бирже (запрос статуса по v5/order/realtime)
+    pub fn is_order_not_found(error: &anyhow::Error) -> bool {
+        matches!(error.downcast_ref::<ExchangeError>(), Some(ExchangeError::OrderNotFound(_)))
+    }
+
+    /// positionIdx ордера не соответствует режиму позиций аккаунта (one-way / hedge-mode)
+    pub fn is_position_mode_mismatch(error: &anyhow::Error) -> bool {
+        matches!(error.downcast_ref::<ExchangeError>(), Some(ExchangeError::PositionModeMismatch(_)))
+    }
 }
 
+impl fmt::Display for ExchangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExchangeError::Transient(message) => write!(f, "Transient exchange error: {}", message),
+            ExchangeError::Api { code, message, raw } => write!(f, "Bybit API Error ({}): {}. Raw: {}", code, message, raw),
+            ExchangeError::Maintenance(message) => write!(f, "Exchange under maintenance: {}", message),
+            ExchangeError::OrderNotFound(message) => write!(f, "Order not found: {}", message),
+            ExchangeError::PositionModeMismatch(message) => write!(f, "Position mode mismatch: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ExchangeError {}
+
+/// Объем ордера после округления до шага меньше минимального объема инструмента (или нулевой)
+#[derive(Debug, Clone, PartialEq)]
+pub struct BelowMinimum {
+    pub requested: f64,   // Запрошенный объем
+    pub rounded: Decimal, // После округления вниз до шага
+    pub min_qty: Decimal, // minOrderQty инструмента
+    pub step: String,     // basePrecision (спот) или qtyStep (фьючерс)
+}
+
+impl fmt::Display for BelowMinimum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Order quantity {} (rounded to step {}: {}) is below minimum order quantity {}",
+            self.requested, self.step, self.rounded, self.min_qty
+        )
+    }
+}
+
+impl std::error::Error for BelowMinimum {}
+
 // --- ДОБАВЛЕНЫ ТИПЫ ДЛЯ WEBSOCKET ---
 
 /// Типы подписок для WebSocket
@@ -0,0 +1,197 @@
+// src/health.rs
+
+//! HTTP-эндпоинт GET /healthz для проверок живости контейнера (health_port).
+//! Отвечает по сохраненному состоянию: запущен ли диспетчер каждого аккаунта и когда биржа последний раз
+//! успешно ответила на check_connection. Саму биржу при запросе не пингует — это делает фоновая задача.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{error, info, warn};
+
+use crate::exchange::Exchange;
+
+/// Сколько ждать строку запроса от клиента
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Состояние одного аккаунта для /healthz
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountHealth {
+    pub dispatcher_running: bool,
+    pub last_ping_ok: Option<i64>, // Unix-время последнего успешного check_connection
+}
+
+/// Общее состояние здоровья всех аккаунтов процесса
+#[derive(Debug, Default)]
+pub struct HealthState {
+    accounts: Mutex<BTreeMap<String, AccountHealth>>,
+}
+
+pub type SharedHealth = Arc<HealthState>;
+
+impl HealthState {
+    fn update(&self, account: &str, apply: impl FnOnce(&mut AccountHealth)) {
+        let mut accounts = self.accounts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        apply(accounts.entry(account.to_string()).or_default());
+    }
+
+    /// Аккаунт учитывается в /healthz с момента запуска: не поднявшийся аккаунт дает 503
+    pub fn register(&self, account: &str) {
+        self.update(account, |_| {});
+    }
+
+    pub fn record_ping_ok(&self, account: &str, now: i64) {
+        self.update(account, |health| health.last_ping_ok = Some(now));
+    }
+
+    pub fn set_dispatcher_running(&self, account: &str, running: bool) {
+        self.update(account, |health| health.dispatcher_running = running);
+    }
+
+    fn snapshot(&self) -> BTreeMap<String, AccountHealth> {
+        self.accounts.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+}
+
+/// Код ответа и текст /healthz: 200, если у всех аккаунтов запущен диспетчер и пинг не старше max_ping_age_secs
+pub fn health_response(accounts: &BTreeMap<String, AccountHealth>, now: i64, max_ping_age_secs: u64) -> (u16, String) {
+    let mut healthy = !accounts.is_empty();
+    let mut body = String::new();
+    for (name, health) in accounts {
+        let ping_age = health.last_ping_ok.map(|ts| now.saturating_sub(ts).max(0) as u64);
+        let ping_fresh = ping_age.is_some_and(|age| age <= max_ping_age_secs);
+        healthy &= health.dispatcher_running && ping_fresh;
+        body.push_str(&format!(
+            "{}: dispatcher={} last_ping_age_secs={}\n",
+            name,
+            if health.dispatcher_running { "running" } else { "stopped" },
+            ping_age.map(|age| age.to_string()).unwrap_or_else(|| "never".to_string()),
+        ));
+    }
+    let status = if healthy { 200 } else { 503 };
+    (status, format!("{}\n{}", if healthy { "ok" } else { "unhealthy" }, body))
+}
+
+async fn serve_connection(mut stream: TcpStream, health: &HealthState, max_ping_age_secs: u64) -> Result<()> {
+    let mut buf = [0u8; 1024];
+    let read = tokio::time::timeout(REQUEST_READ_TIMEOUT, stream.read(&mut buf)).await??;
+    let request = String::from_utf8_lossy(&buf[..read]);
+    let mut request_line = request.lines().next().unwrap_or("").split_whitespace();
+    let (method, path) = (request_line.next().unwrap_or(""), request_line.next().unwrap_or(""));
+
+    let (status, body) = if method == "GET" && path == "/healthz" {
+        health_response(&health.snapshot(), chrono::Utc::now().timestamp(), max_ping_age_secs)
+    } else {
+        (404, "not found\n".to_string())
+    };
+    let reason = match status {
+        200 => "OK",
+        503 => "Service Unavailable",
+        _ => "Not Found",
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Запускает HTTP-сервер /healthz на 0.0.0.0:port (ошибка привязки порта — только в лог, бот продолжает работу)
+pub fn spawn_health_server(port: u16, health: SharedHealth, max_ping_age_secs: u64) {
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind(("0.0.0.0", port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Failed to bind health endpoint on port {}: {}", port, e);
+                return;
+            }
+        };
+        info!("Health endpoint listening on port {} (GET /healthz).", port);
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Health endpoint accept failed: {}", e);
+                    continue;
+                }
+            };
+            let health = health.clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve_connection(stream, &health, max_ping_age_secs).await {
+                    warn!("Health request from {} failed: {}", peer, e);
+                }
+            });
+        }
+    });
+}
+
+/// Фоновый пинг биржи аккаунта: успешный check_connection обновляет время последнего пинга
+pub fn spawn_health_ping_task<E>(mut exchange: E, account: String, health: SharedHealth, interval_secs: u64)
+where
+    E: Exchange + Send + Sync + 'static,
+{
+    let interval = Duration::from_secs(interval_secs.max(1));
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            match exchange.check_connection().await {
+                Ok(()) => health.record_ping_ok(&account, chrono::Utc::now().timestamp()),
+                Err(e) => warn!("Health ping to exchange failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn accounts(entries: &[(&str, bool, Option<i64>)]) -> BTreeMap<String, AccountHealth> {
+        entries
+            .iter()
+            .map(|(name, running, ping)| (name.to_string(), AccountHealth { dispatcher_running: *running, last_ping_ok: *ping }))
+            .collect()
+    }
+
+    #[test]
+    fn healthy_only_when_all_dispatchers_run_with_recent_ping() {
+        let (status, body) = health_response(&accounts(&[("main", true, Some(NOW - 30))]), NOW, 120);
+        assert_eq!(status, 200);
+        assert!(body.contains("main: dispatcher=running last_ping_age_secs=30"));
+
+        // Пинг устарел
+        let (status, body) = health_response(&accounts(&[("main", true, Some(NOW - 121))]), NOW, 120);
+        assert_eq!(status, 503);
+        assert!(body.contains("last_ping_age_secs=121"));
+
+        // Один из аккаунтов не запустился
+        let mixed = accounts(&[("main", true, Some(NOW)), ("sub", false, None)]);
+        let (status, body) = health_response(&mixed, NOW, 120);
+        assert_eq!(status, 503);
+        assert!(body.contains("sub: dispatcher=stopped last_ping_age_secs=never"));
+
+        assert_eq!(health_response(&BTreeMap::new(), NOW, 120).0, 503);
+    }
+
+    #[test]
+    fn state_tracks_registered_accounts() {
+        let state = HealthState::default();
+        state.register("main");
+        assert_eq!(state.snapshot()["main"], AccountHealth::default());
+
+        state.record_ping_ok("main", NOW);
+        state.set_dispatcher_running("main", true);
+        assert_eq!(state.snapshot()["main"], AccountHealth { dispatcher_running: true, last_ping_ok: Some(NOW) });
+    }
+}
@@ -3,17 +3,24 @@
 use anyhow::{anyhow, Result};
 use rust_decimal::Decimal;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
 use tokio::sync::Mutex as TokioMutex;
-use tokio::time::sleep;
+use tokio::time::{sleep, Instant}; // Instant tokio: в тестах с остановленным временем таймеры цикла идут вместе со sleep
 use tracing::{debug, error, info, warn};
 use rust_decimal::prelude::{FromPrimitive, ToPrimitive}; // Добавили ToPrimitive
 
 
-use crate::hedger::{HedgeProgressCallback, HedgeProgressUpdate, HedgeStage, Hedger, ORDER_FILL_TOLERANCE};
-use crate::exchange::types::{OrderSide, OrderStatus as ExchangeOrderStatus};
+use crate::hedger::{ActiveOrder, DbRecordDiverged, FuturesOrderLeftActive, MarketFillShortfall, PriceGuardHit, HedgeProgressCallback, HedgeProgressUpdate, HedgeStage, Hedger, ORDER_FILL_TOLERANCE};
+use crate::exchange::types::{ExchangeError, OrderSide, OrderStatus as ExchangeOrderStatus, OrderStatusText, PriceSource};
+use crate::exchange::bybit::{linear_info_symbol, LINEAR_CATEGORY, SPOT_CATEGORY};
+use crate::config::{Config, OrderType};
 use crate::exchange::Exchange;
-use crate::storage::{update_hedge_spot_order, Db}; // Добавим Db и нужные функции
+use crate::storage::{
+    finalize_operation, get_open_hedge_operations, is_trailing_stop_active, set_trailing_stop_active, update_hedge_spot_order,
+    update_running_futures_order, Db, LegFill, OperationStatus,
+}; // Добавим Db и нужные функции
+use crate::utils::{is_price_stale, round_to_tick, with_retry, RetryPolicy};
 
 // Структура для передачи параметров в цикл управления ордером
 pub(super) struct OrderLoopParams<'a, E: Exchange> {
@@ -29,8 +36,18 @@ pub(super) struct OrderLoopParams<'a, E: Exchange> {
     pub is_spot: bool,     // Флаг для выбора API методов
     pub min_order_qty_decimal: Option<Decimal>, // Для проверки на пыль (только для unhedge spot)
     pub total_filled_qty_storage: Arc<TokioMutex<f64>>, // Общее исполненное кол-во на этом этапе
+    pub keep_order_on_timeout: bool, // По таймауту не переставлять ордер, а вернуть FuturesOrderLeftActive
+    pub order_type: OrderType, // Market — один рыночный ордер вместо цикла перестановки лимиток
+    pub retry_budget: RetryBudget, // Общий лимит повторов на всю операцию
+    pub qty_precision: QtyPrecision, // Шаг количества инструмента: допуск исполнения и проверка цели (см. qty_precision_for)
+    pub futures_recovery_base: Option<f64>, // Some(исполнено до этапа) — писать живой фьючерсный ордер в БД для восстановления после перезапуска
+    pub futures_recovery_target: Option<f64>, // Цель фьючерсной ноги для БД, если цикл исполняет только ее часть (срез); None — цель цикла
+    pub price_guard: Option<f64>, // Худшая допустимая цена (Buy — максимум, Sell — минимум): дальше этап останавливается, а не догоняет рынок
 }
 
+const MARKET_FILL_POLL_ATTEMPTS: u32 = 10;
+const MARKET_FILL_POLL_INTERVAL: Duration = Duration::from_millis(300);
+
 // Общая функция цикла управления ордером
 pub(super) async fn manage_order_loop<'a, E>(
     params: OrderLoopParams<'a, E>,
@@ -51,7 +68,17 @@ where
         is_spot,
         min_order_qty_decimal,
         total_filled_qty_storage,
+        keep_order_on_timeout,
+        order_type,
+        retry_budget,
+        qty_precision,
+        futures_recovery_base,
+        futures_recovery_target,
+        price_guard,
     } = params;
+    let fill_tolerance = qty_precision.tolerance();
+    // После перезапуска операция восстанавливается по полной цели ноги, а не по концу текущего среза
+    let recovery_target_qty = futures_recovery_target.unwrap_or(initial_target_qty);
 
     let mut cumulative_filled_qty = *total_filled_qty_storage.lock().await; // Начинаем с того, что уже есть
     let mut current_order_target_qty = initial_target_qty - cumulative_filled_qty; // Сколько осталось для первого ордера
@@ -59,11 +86,13 @@ where
     let mut current_order_id: Option<String> = None;
     let mut limit_price = initial_limit_price; // Цена для текущего ордера
     let mut last_placed_order_id: Option<String> = None; // Храним ID последнего *успешно размещенного* ордера
-    // Используем config для доступа к slippage
-    let mut current_market_price = initial_limit_price / (1.0 - hedger.config.slippage * side.sign()); // Примерная рыночная цена
+    // Параметры с учетом переопределений для символа (symbol_overrides)
+    let slippage = hedger.config.slippage_for(symbol);
+    let max_wait = Duration::from_secs(hedger.config.max_wait_secs_for(symbol));
+    let mut current_market_price = initial_limit_price / (1.0 - slippage * side.sign()); // Примерная рыночная цена
 
     // --- Размещение начального ордера ---
-    if current_order_target_qty <= ORDER_FILL_TOLERANCE {
+    if current_order_target_qty <= fill_tolerance {
         info!(
             "op_id:{}: Stage {:?} target already reached ({:.8}/{:.8}). Skipping placement.",
             operation_id, stage, cumulative_filled_qty, initial_target_qty
@@ -71,6 +100,58 @@ where
         return Ok((cumulative_filled_qty, None)); // Возвращаем None, т.к. ордер не размещался
      }
 
+    if order_type == OrderType::Market {
+        if breaches_price_guard(current_market_price, side, price_guard) {
+            return Err(price_guard_hit(operation_id, stage, current_market_price, price_guard, cumulative_filled_qty, initial_target_qty));
+        }
+        let (market_filled_qty, market_order_id) =
+            execute_market_leg(hedger.exchange.clone(), operation_id, symbol, side, current_order_target_qty, is_spot, fill_tolerance, &retry_budget).await?;
+        cumulative_filled_qty += market_filled_qty;
+        *total_filled_qty_storage.lock().await = cumulative_filled_qty;
+        if is_spot {
+            if let Err(e) = update_hedge_spot_order(db, operation_id, Some(&market_order_id), cumulative_filled_qty).await {
+                error!("op_id:{}: Failed update spot market order info in DB: {}", operation_id, e);
+            }
+        }
+        // Частичное исполнение — не успех: вызывающий видит фактический объем и не подгоняет под цель другую ногу
+        if current_order_target_qty - market_filled_qty > fill_tolerance {
+            return Err(MarketFillShortfall { order_id: market_order_id, filled_qty: cumulative_filled_qty, target_qty: initial_target_qty }.into());
+        }
+        let update = HedgeProgressUpdate {
+            stage,
+            current_spot_price: current_market_price,
+            new_limit_price: limit_price,
+            is_replacement: false,
+            filled_qty: market_filled_qty,
+            target_qty: current_order_target_qty,
+            cumulative_filled_qty,
+            total_target_qty: initial_target_qty,
+        };
+        if let Err(e) = progress_callback(update).await {
+            if !e.to_string().contains("message is not modified") {
+                warn!("op_id:{}: Progress callback failed after market order (Stage: {:?}): {}", operation_id, stage, e);
+            }
+        }
+        return Ok((cumulative_filled_qty, Some(market_order_id)));
+    }
+
+    // Тик инструмента: slippage меньше тика не должен пропадать при округлении цены
+    let tick_size = if hedger.config.enforce_tick_offset { tick_size_for(hedger, symbol, is_spot).await } else { None };
+    let offset_ticks = hedger.config.offset_points_for(symbol);
+    let limit_price_for = |market_price: f64| {
+        let price = calculate_limit_price(market_price, side, slippage);
+        match tick_size {
+            Some(tick) => enforce_tick_offset(market_price, price, side, tick, offset_ticks),
+            None => price,
+        }
+    };
+    if let Some(tick) = tick_size {
+        limit_price = enforce_tick_offset(current_market_price, limit_price, side, tick, offset_ticks);
+    }
+    if breaches_price_guard(limit_price, side, price_guard) {
+        return Err(price_guard_hit(operation_id, stage, limit_price, price_guard, cumulative_filled_qty, initial_target_qty));
+    }
+
     info!(
         "op_id:{}: Placing initial {} {} order at {:.8} for qty {:.8} (Stage: {:?})",
         operation_id,
@@ -81,18 +162,28 @@ where
         stage
     );
 
-    let order_result = place_order(
-        hedger.exchange.clone(), // Клонируем для передачи в функцию
+    let link_id = new_order_link_id(operation_id, is_spot);
+    let order_spec = OrderSpec {
         symbol,
+        quote_currency: &hedger.quote_currency,
         side,
-        current_order_target_qty,
-        limit_price,
+        qty: current_order_target_qty,
+        price: limit_price,
         is_spot,
+        link_id: &link_id,
+    };
+    let order_result = place_order_with_retry(
+        hedger.exchange.clone(), // Клонируем для передачи в функцию
+        order_spec,
+        PlacementRetry::from_config(&hedger.config, &retry_budget),
     )
     .await;
 
     let order_id = match order_result {
-        Ok(id) => id,
+        Ok((id, placed_price)) => {
+            limit_price = placed_price;
+            id
+        }
         Err(e) => {
             error!(
                 "op_id:{}: Failed place initial {} order (Stage: {:?}): {}",
@@ -113,12 +204,16 @@ where
     );
     current_order_id = Some(order_id.clone());
     last_placed_order_id = current_order_id.clone();
+    set_active_order(hedger, stage, is_spot, symbol, current_order_id.as_deref(), cumulative_filled_qty).await;
     // Обновляем БД, если это hedge spot
     if is_spot {
         if let Err(e) = update_hedge_spot_order(db, operation_id, current_order_id.as_deref(), cumulative_filled_qty).await {
              error!("op_id:{}: Failed update initial spot order info in DB: {}", operation_id, e);
         }
      }
+    if let Some(base) = futures_recovery_base {
+        persist_futures_order(db, operation_id, &order_id, base + cumulative_filled_qty, base + recovery_target_qty).await;
+    }
 
 
     let mut start_of_current_order = Instant::now(); // Таймер для текущего ордера
@@ -128,6 +223,8 @@ where
     let price_check_interval = Duration::from_secs(5); // Проверяем цену каждые 5 секунд
     let mut last_price_check = Instant::now();
     // --- КОНЕЦ ДОБАВЛЕНИЯ ---
+    // Когда current_market_price получена с биржи; None — цена лишь оценена по начальной лимитке
+    let mut market_price_updated: Option<Instant> = None;
 
     // --- Основной цикл управления ордером ---
     loop {
@@ -140,7 +237,7 @@ where
             Some(id) => id,
             None => {
                 // Если ID нет, проверяем, достигнута ли цель
-                if cumulative_filled_qty >= initial_target_qty - ORDER_FILL_TOLERANCE {
+                if qty_precision.target_reached(cumulative_filled_qty, initial_target_qty) {
                     info!(
                         "op_id:{}: No active {} order and target reached. Exiting loop. (Stage: {:?})",
                         operation_id, if is_spot { "spot" } else { "futures" }, stage
@@ -178,7 +275,7 @@ where
             Ok(s) => s,
             Err(e) => {
                 // Обработка "Order not found"
-                if e.to_string().contains("Order not found")
+                if ExchangeError::is_order_not_found(&e)
                     && now.duration_since(start_of_current_order) > Duration::from_secs(5) // Используем start_of_current_order
                 {
                     warn!(
@@ -191,7 +288,7 @@ where
                     cumulative_filled_qty = cumulative_filled_qty.min(initial_target_qty);
                     let filled_diff = cumulative_filled_qty - filled_before;
 
-                    if filled_diff.abs() > ORDER_FILL_TOLERANCE {
+                    if filled_diff.abs() > fill_tolerance {
                          *total_filled_qty_storage.lock().await = cumulative_filled_qty;
                          if is_spot {
                              if let Err(db_err) = update_hedge_spot_order(db, operation_id, None, cumulative_filled_qty).await {
@@ -200,9 +297,10 @@ where
                          }
                     }
                     current_order_id = None;
+                    set_active_order(hedger, stage, is_spot, symbol, None, cumulative_filled_qty).await;
                     qty_filled_in_current_order = 0.0;
 
-                    if cumulative_filled_qty >= initial_target_qty - ORDER_FILL_TOLERANCE {
+                    if qty_precision.target_reached(cumulative_filled_qty, initial_target_qty) {
                         info!(
                             "op_id:{}: {} target reached after order not found assumption. Exiting loop. (Stage: {:?})",
                             operation_id, if is_spot { "spot" } else { "futures" }, stage
@@ -213,8 +311,12 @@ where
                             "op_id:{}: {} target not reached after assumption. Triggering replacement. (Stage: {:?})",
                             operation_id, if is_spot { "spot" } else { "futures" }, stage
                         );
+                        if !retry_budget.try_consume() {
+                            error!("op_id:{}: Operation retry budget exhausted, aborting stage. (Stage: {:?})", operation_id, stage);
+                            return Err(anyhow!("Operation retry budget exhausted (Stage: {:?})", stage));
+                        }
                         // Используем config для доступа к max_wait
-                        start_of_current_order = now - Duration::from_secs(hedger.config.max_wait_secs) - Duration::from_secs(1); // Форсируем замену
+                        start_of_current_order = now - max_wait - Duration::from_secs(1); // Форсируем замену
                         last_price_check = start_of_current_order; // Сбрасываем и проверку цены
                         continue;
                     }
@@ -236,13 +338,12 @@ where
         qty_filled_in_current_order = status.filled_qty;
         let filled_since_last_check = qty_filled_in_current_order - previously_filled_in_current;
 
-        if filled_since_last_check.abs() > ORDER_FILL_TOLERANCE {
+        if filled_since_last_check.abs() > fill_tolerance {
             let filled_before = cumulative_filled_qty;
-            cumulative_filled_qty += filled_since_last_check;
-            cumulative_filled_qty = cumulative_filled_qty.max(0.0).min(initial_target_qty * 1.00001);
+            cumulative_filled_qty = add_fill(cumulative_filled_qty, filled_since_last_check);
             let filled_diff = cumulative_filled_qty - filled_before;
 
-            if filled_diff.abs() > ORDER_FILL_TOLERANCE {
+            if filled_diff.abs() > fill_tolerance {
                 *total_filled_qty_storage.lock().await = cumulative_filled_qty;
                 debug!(
                     "op_id:{}: {} fill update. Filled in current: {:.8}, Cum: {:.8}/{:.8} (Stage: {:?})",
@@ -266,13 +367,38 @@ where
             }
         }
 
+        // --- Ордер закрыт биржей без исполнения остатка (отменен вручную или отклонен) ---
+        // remaining_qty у такого ордера 0, поэтому он не должен попасть в ветку полного исполнения
+        let order_cancelled = status.status.is_cancelled();
+        if order_cancelled || status.status == OrderStatusText::Rejected {
+            warn!(
+                "op_id:{}: {} order {} closed on exchange as {:?} with {:.8} filled (cum {:.8}/{:.8}). (Stage: {:?})",
+                operation_id, if is_spot { "spot" } else { "futures" }, order_id_to_check, status.status,
+                status.filled_qty, cumulative_filled_qty, initial_target_qty, stage
+            );
+            if qty_precision.target_reached(cumulative_filled_qty, initial_target_qty) {
+                current_order_id = None;
+                set_active_order(hedger, stage, is_spot, symbol, None, cumulative_filled_qty).await;
+                break Ok((cumulative_filled_qty, last_placed_order_id));
+            }
+            if !order_cancelled {
+                // Повтор отклоненного ордера, скорее всего, будет отклонен так же
+                current_order_id = None;
+                set_active_order(hedger, stage, is_spot, symbol, None, cumulative_filled_qty).await;
+                return Err(anyhow!(
+                    "{} order {} rejected by exchange after {:.8}/{:.8} filled (Stage: {:?})",
+                    if is_spot { "Spot" } else { "Futures" }, order_id_to_check, cumulative_filled_qty, initial_target_qty, stage
+                ));
+            }
+        }
+
         // --- Проверка полного исполнения ордера ---
-        if status.remaining_qty <= ORDER_FILL_TOLERANCE {
+        if !order_cancelled && status.remaining_qty <= fill_tolerance {
             info!(
                 "op_id:{}: {} order {} considered filled (remaining: {:.8}). (Stage: {:?})",
                 operation_id, if is_spot { "spot" } else { "futures" }, order_id_to_check, status.remaining_qty, stage
             );
-            if (cumulative_filled_qty - initial_target_qty).abs() > ORDER_FILL_TOLERANCE && cumulative_filled_qty < initial_target_qty {
+            if (cumulative_filled_qty - initial_target_qty).abs() > fill_tolerance && cumulative_filled_qty < initial_target_qty {
                  warn!(
                      "op_id:{}: {} final fill correction after order fill: {:.8} -> {:.8}. (Stage: {:?})",
                      operation_id, if is_spot { "spot" } else { "futures" }, cumulative_filled_qty, initial_target_qty, stage
@@ -287,14 +413,15 @@ where
                  }
             }
             current_order_id = None;
+            set_active_order(hedger, stage, is_spot, symbol, None, cumulative_filled_qty).await;
             qty_filled_in_current_order = 0.0;
-            if cumulative_filled_qty >= initial_target_qty - ORDER_FILL_TOLERANCE {
+            if qty_precision.target_reached(cumulative_filled_qty, initial_target_qty) {
                  info!("op_id:{}: Target reached after order fill. Exiting loop. (Stage: {:?})", operation_id, stage);
                  break Ok((cumulative_filled_qty, last_placed_order_id));
             } else {
                  warn!("op_id:{}: Order filled but target not reached? Triggering replacement check. (Stage: {:?})", operation_id, stage);
                  // Используем config для доступа к max_wait
-                 start_of_current_order = now - Duration::from_secs(hedger.config.max_wait_secs) - Duration::from_secs(1);
+                 start_of_current_order = now - max_wait - Duration::from_secs(1);
                  last_price_check = start_of_current_order; // Сбрасываем и проверку цены
                  continue;
             }
@@ -304,14 +431,31 @@ where
         let elapsed_since_order_start = now.duration_since(start_of_current_order);
         let mut should_replace = false; // Флаг для решения о замене
         let mut is_replacement = false; // Флаг для колбэка
+        let mut price_is_stale = false; // Замена из-за ушедшей цены (рыночная цена свежая)
 
+        // 0. Ордер отменен на бирже: остаток выставляется новым ордером
+        if order_cancelled {
+            should_replace = true;
+        }
         // 1. Проверка по таймауту max_wait
-        // Используем config для доступа к max_wait
-        if elapsed_since_order_start > Duration::from_secs(hedger.config.max_wait_secs) {
+        else if elapsed_since_order_start > max_wait {
             warn!(
                 "op_id:{}: {} order {} MAX_WAIT timeout (elapsed: {:?}). Triggering replacement. (Stage: {:?})",
                 operation_id, if is_spot { "spot" } else { "futures" }, order_id_to_check, elapsed_since_order_start, stage
             );
+            if keep_order_on_timeout && status.remaining_qty > fill_tolerance {
+                info!(
+                    "op_id:{}: Leaving {} order {} active after timeout (cancel on timeout disabled). (Stage: {:?})",
+                    operation_id, if is_spot { "spot" } else { "futures" }, order_id_to_check, stage
+                );
+                return Err(FuturesOrderLeftActive {
+                    order_id: order_id_to_check,
+                    base_filled_qty: cumulative_filled_qty - qty_filled_in_current_order,
+                    order_filled_qty: qty_filled_in_current_order,
+                    target_qty: recovery_target_qty,
+                }
+                .into());
+            }
             should_replace = true;
         }
         // 2. Проверка по интервалу price_check_interval и "свежести" цены
@@ -320,11 +464,12 @@ where
             last_price_check = now; // Сбрасываем таймер проверки цены
 
             // --- ИСПРАВЛЕНО: Передаем quote_currency в get_market_price ---
-            match get_market_price(hedger.exchange.clone(), symbol, is_spot, &hedger.config.quote_currency).await {
+            match get_market_price(hedger.exchange.clone(), symbol, is_spot, &hedger.config.quote_currency, hedger.config.price_source, SpotPriceRetry::from_config(&hedger.config).with_budget(&retry_budget)).await {
                 Ok(market_price) => {
                     current_market_price = market_price; // Обновляем текущую рыночную цену
+                    market_price_updated = Some(Instant::now());
                     // Используем config для доступа к slippage
-                    let price_diff_threshold = hedger.config.slippage * 2.0; // Порог в 2 раза больше slippage
+                    let price_diff_threshold = slippage * 2.0; // Порог в 2 раза больше slippage
                     let is_stale = match side {
                         OrderSide::Buy => limit_price < market_price * (1.0 - price_diff_threshold),
                         OrderSide::Sell => limit_price > market_price * (1.0 + price_diff_threshold),
@@ -336,6 +481,7 @@ where
                             operation_id, if is_spot { "spot" } else { "futures" }, order_id_to_check, limit_price, market_price, stage
                         );
                         should_replace = true;
+                        price_is_stale = true;
                     } else {
                          debug!("op_id:{}: Price {:.8} is still relevant vs market {:.8}.", operation_id, limit_price, market_price);
                     }
@@ -346,8 +492,40 @@ where
             }
         }
 
+        // --- Изменение цены ордера без отмены (amend), если меняется только цена ---
+        if price_is_stale
+            && status.remaining_qty > fill_tolerance
+            && !breaches_price_guard(limit_price_for(current_market_price), side, price_guard)
+            && is_price_only_change(initial_target_qty, cumulative_filled_qty, status.remaining_qty, min_order_qty_decimal, fill_tolerance)
+        {
+            let amended_price = limit_price_for(current_market_price);
+            match amend_order_price(hedger.exchange.clone(), symbol, &order_id_to_check, amended_price, is_spot).await {
+                Ok(()) => {
+                    info!(
+                        "op_id:{}: Amended {} order {} price {:.8} -> {:.8} (Stage: {:?})",
+                        operation_id, if is_spot { "spot" } else { "futures" }, order_id_to_check, limit_price, amended_price, stage
+                    );
+                    limit_price = amended_price;
+                    should_replace = false;
+                    is_replacement = true;
+                    start_of_current_order = now;
+                    last_price_check = now;
+                }
+                Err(e) => {
+                    warn!(
+                        "op_id:{}: Failed amend {} order {}: {}. Falling back to cancel+replace. (Stage: {:?})",
+                        operation_id, if is_spot { "spot" } else { "futures" }, order_id_to_check, e, stage
+                    );
+                    if !retry_budget.try_consume() {
+                        error!("op_id:{}: Operation retry budget exhausted, aborting stage. (Stage: {:?})", operation_id, stage);
+                        return Err(anyhow!("Operation retry budget exhausted (Stage: {:?})", stage));
+                    }
+                }
+            }
+        }
+
         // --- Выполнение замены, если флаг установлен ---
-        if should_replace && status.remaining_qty > ORDER_FILL_TOLERANCE {
+        if should_replace && (order_cancelled || status.remaining_qty > fill_tolerance) {
             is_replacement = true; // Устанавливаем флаг для колбэка
 
             // --- ИСПРАВЛЕНО: Используем Decimal для точного вычитания ---
@@ -360,7 +538,7 @@ where
             // --- Проверка на пыль (только для unhedge spot) ---
             if is_spot && side == OrderSide::Sell && min_order_qty_decimal.is_some() {
                 // Сравниваем Decimal с Decimal
-                if remaining_total_qty > ORDER_FILL_TOLERANCE
+                if remaining_total_qty > fill_tolerance
                     && remaining_total_qty_d < min_order_qty_decimal.unwrap()
                 {
                     warn!(
@@ -374,16 +552,16 @@ where
                         sleep(Duration::from_millis(500)).await;
                     }
                     current_order_id = None;
+                    set_active_order(hedger, stage, is_spot, symbol, None, cumulative_filled_qty).await;
                     qty_filled_in_current_order = 0.0;
                     // Перепроверка исполнения после отмены на всякий случай
                     match get_order_status(hedger.exchange.clone(), symbol, &order_id_to_check, is_spot).await {
                         Ok(fs) => {
                             let filled_after_cancel = fs.filled_qty - previously_filled_in_current;
-                            if filled_after_cancel > ORDER_FILL_TOLERANCE {
+                            if filled_after_cancel > fill_tolerance {
                                 let filled_before = cumulative_filled_qty;
-                                cumulative_filled_qty += filled_after_cancel;
-                                cumulative_filled_qty = cumulative_filled_qty.max(0.0).min(initial_target_qty * 1.00001);
-                                if (cumulative_filled_qty - filled_before).abs() > ORDER_FILL_TOLERANCE {
+                                cumulative_filled_qty = add_fill(cumulative_filled_qty, filled_after_cancel);
+                                if (cumulative_filled_qty - filled_before).abs() > fill_tolerance {
                                     *total_filled_qty_storage.lock().await = cumulative_filled_qty;
                                     if is_spot {
                                          if let Err(e) = update_hedge_spot_order(db, operation_id, None, cumulative_filled_qty).await {
@@ -424,15 +602,14 @@ where
                  match get_order_status(hedger.exchange.clone(), symbol, &prev_id, is_spot).await {
                     Ok(final_status) => {
                         let filled_after_cancel = final_status.filled_qty - previously_filled_in_current;
-                        if filled_after_cancel > ORDER_FILL_TOLERANCE {
+                        if filled_after_cancel > fill_tolerance {
                             info!(
                                 "op_id:{}: Order {} filled further ({}) during/after cancel. (Stage: {:?})",
                                 operation_id, prev_id, filled_after_cancel, stage
                             );
                             let filled_before = cumulative_filled_qty;
-                            cumulative_filled_qty += filled_after_cancel;
-                            cumulative_filled_qty = cumulative_filled_qty.max(0.0).min(initial_target_qty * 1.00001);
-                             if (cumulative_filled_qty - filled_before).abs() > ORDER_FILL_TOLERANCE {
+                            cumulative_filled_qty = add_fill(cumulative_filled_qty, filled_after_cancel);
+                             if (cumulative_filled_qty - filled_before).abs() > fill_tolerance {
                                 *total_filled_qty_storage.lock().await = cumulative_filled_qty;
                                 if is_spot {
                                      if let Err(e) = update_hedge_spot_order(db, operation_id, None, cumulative_filled_qty).await {
@@ -443,13 +620,13 @@ where
                         }
                         // --- ИСПРАВЛЕНО: Условие выхода после отмены ---
                         // Проверяем, достигнута ли цель ПОСЛЕ обновления cumulative_filled_qty
-                        if cumulative_filled_qty >= initial_target_qty - ORDER_FILL_TOLERANCE {
+                        if qty_precision.target_reached(cumulative_filled_qty, initial_target_qty) {
                             info!(
                                 "op_id:{}: Target reached after checking cancelled order {}. Exiting loop. (Stage: {:?})", // Уточнили лог
                                 operation_id, prev_id, stage
                             );
                             // Финальная коррекция до цели, если нужно (остается)
-                            if (cumulative_filled_qty - initial_target_qty).abs() > ORDER_FILL_TOLERANCE && cumulative_filled_qty < initial_target_qty {
+                            if (cumulative_filled_qty - initial_target_qty).abs() > fill_tolerance && cumulative_filled_qty < initial_target_qty {
                                 warn!(
                                     "op_id:{}: Final fill correction after cancel check: {:.8} -> {:.8}. (Stage: {:?})",
                                     operation_id, cumulative_filled_qty, initial_target_qty, stage
@@ -475,7 +652,7 @@ where
                         // --- КОНЕЦ ИСПРАВЛЕНИЯ ---
                     }
                     Err(e) => {
-                         if !e.to_string().contains("Order not found") {
+                         if !ExchangeError::is_order_not_found(&e) {
                             warn!(
                                 "op_id:{}: Failed get {} order status after cancel for {}: {}. Assuming processed. (Stage: {:?})",
                                 operation_id, if is_spot { "spot" } else { "futures" }, prev_id, e, stage
@@ -483,7 +660,7 @@ where
                          } else {
                              info!("op_id:{}: Order {} not found after cancel, assuming processed. (Stage: {:?})", operation_id, prev_id, stage);
                          }
-                         if cumulative_filled_qty >= initial_target_qty - ORDER_FILL_TOLERANCE {
+                         if qty_precision.target_reached(cumulative_filled_qty, initial_target_qty) {
                              info!("op_id:{}: Target reached after order cancel/not found. Exiting loop. (Stage: {:?})", operation_id, stage);
                              break Ok((cumulative_filled_qty, last_placed_order_id));
                          }
@@ -494,27 +671,33 @@ where
 
             // --- Пересчет остатка и размещение нового ордера ---
             // Используем f64 значение remaining_total_qty, полученное из Decimal
-            if remaining_total_qty <= ORDER_FILL_TOLERANCE {
+            if remaining_total_qty <= fill_tolerance {
                 // Добавим вывод Decimal для отладки
                 info!("op_id:{}: Remaining qty {:.8} (Decimal: {}) is negligible after cancel/recheck. Exiting loop. (Stage: {:?})",
                       operation_id, remaining_total_qty, remaining_total_qty_d, stage);
                 break Ok((cumulative_filled_qty, last_placed_order_id));
             }
 
-            // Получаем новую цену (если еще не получили при проверке свежести)
-            if !should_replace { // should_replace был false, значит, цена не проверялась
-                 // --- ИСПРАВЛЕНО: Передаем quote_currency в get_market_price ---
-                 current_market_price = match get_market_price(hedger.exchange.clone(), symbol, is_spot, &hedger.config.quote_currency).await {
+            // Цена старше max_price_staleness_ms (или еще не запрашивалась) — запрашиваем заново, как в WS-режиме
+            if is_price_stale(market_price_updated.map(|updated| updated.elapsed()), hedger.config.max_price_staleness_ms) {
+                 current_market_price = match get_market_price(hedger.exchange.clone(), symbol, is_spot, &hedger.config.quote_currency, hedger.config.price_source, SpotPriceRetry::from_config(&hedger.config).with_budget(&retry_budget)).await {
                     Ok(p) => p,
                     Err(e) => {
                         error!("op_id:{}: Failed to get new market price for replacement: {}. Aborting stage.", operation_id, e);
                         return Err(anyhow!("Failed get price for replacement: {}", e));
                     }
                 };
-            } // Иначе используем current_market_price, полученную при проверке свежести
+                 market_price_updated = Some(Instant::now());
+            } // Иначе используем свежую current_market_price, полученную при проверке цены
 
             // Используем config для доступа к slippage
-            limit_price = calculate_limit_price(current_market_price, side, hedger.config.slippage);
+            let replacement_price = limit_price_for(current_market_price);
+            // Старый ордер уже отменен: за ограничителем этап останавливается с тем, что исполнено
+            if breaches_price_guard(replacement_price, side, price_guard) {
+                set_active_order(hedger, stage, is_spot, symbol, None, cumulative_filled_qty).await;
+                return Err(price_guard_hit(operation_id, stage, replacement_price, price_guard, cumulative_filled_qty, initial_target_qty));
+            }
+            limit_price = replacement_price;
             current_order_target_qty = remaining_total_qty; // Цель нового ордера - остаток (f64)
 
             // Добавим вывод Decimal для отладки
@@ -523,15 +706,30 @@ where
 
             // Передаем f64 в place_order, т.к. он ожидает f64.
             // Внутри place_order (в bybit.rs) уже есть логика округления с Decimal.
-            let new_order_id = place_order(hedger.exchange.clone(), symbol, side, current_order_target_qty, limit_price, is_spot).await?;
+            let replacement_link_id = new_order_link_id(operation_id, is_spot);
+            let replacement_spec = OrderSpec {
+                symbol,
+                quote_currency: &hedger.quote_currency,
+                side,
+                qty: current_order_target_qty,
+                price: limit_price,
+                is_spot,
+                link_id: &replacement_link_id,
+            };
+            let (new_order_id, placed_price) = place_order(hedger.exchange.clone(), &replacement_spec).await?;
+            limit_price = placed_price;
             info!("op_id:{}: Placed replacement {} order: id={} (Stage: {:?})", operation_id, if is_spot { "spot" } else { "futures" }, new_order_id, stage);
             current_order_id = Some(new_order_id.clone());
             last_placed_order_id = current_order_id.clone();
+            set_active_order(hedger, stage, is_spot, symbol, current_order_id.as_deref(), cumulative_filled_qty).await;
             if is_spot {
                 if let Err(e) = update_hedge_spot_order(db, operation_id, current_order_id.as_deref(), cumulative_filled_qty).await {
                     error!("op_id:{}: Failed update DB after replacement order placement: {}", operation_id, e);
                 }
              }
+            if let Some(base) = futures_recovery_base {
+                persist_futures_order(db, operation_id, &new_order_id, base + cumulative_filled_qty, base + recovery_target_qty).await;
+            }
 
             start_of_current_order = now; // Сбрасываем таймер для нового ордера
             last_price_check = now; // Сбрасываем и таймер проверки цены
@@ -569,24 +767,329 @@ where
 
 // --- Вспомогательные асинхронные функции для работы с биржей ---
 
-async fn place_order<E: Exchange>(
-    exchange: E,
-    symbol: &str,
+/// Политика повторов размещения ордера при временных ошибках
+#[derive(Debug, Clone)]
+struct PlacementRetry {
+    max_retries: u32,
+    base_delay: Duration,
+    budget: RetryBudget, // Каждый повтор списывается с общего бюджета операции
+}
+
+impl PlacementRetry {
+    fn from_config(config: &Config, budget: &RetryBudget) -> Self {
+        Self {
+            max_retries: config.order_placement_retries,
+            base_delay: Duration::from_millis(config.order_placement_retry_delay_ms),
+            budget: budget.clone(),
+        }
+    }
+}
+
+/// Общий на всю операцию бюджет повторов: делится между этапами и подоперациями
+/// (повторы размещения, замены после потери ордера), чтобы ограничить число запросов к API.
+/// None — без ограничения
+#[derive(Debug, Clone)]
+pub(super) struct RetryBudget {
+    remaining: Option<Arc<AtomicU32>>,
+}
+
+impl RetryBudget {
+    /// limit = 0 — без ограничения
+    pub(super) fn new(limit: u32) -> Self {
+        Self { remaining: (limit > 0).then(|| Arc::new(AtomicU32::new(limit))) }
+    }
+
+    pub(super) fn from_config(config: &Config) -> Self {
+        Self::new(config.operation_retry_budget)
+    }
+
+    /// Списывает один повтор; false — бюджет исчерпан
+    pub(super) fn try_consume(&self) -> bool {
+        match &self.remaining {
+            None => true,
+            Some(remaining) => remaining.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1)).is_ok(),
+        }
+    }
+}
+
+/// Бюджет повторов операции исчерпан: повторять запрос дальше бессмысленно
+#[derive(Debug, Clone)]
+pub(super) struct RetryBudgetExhausted {
+    pub what: String, // Что делали, когда закончился бюджет
+}
+
+impl std::fmt::Display for RetryBudgetExhausted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Operation retry budget exhausted while {}", self.what)
+    }
+}
+
+impl std::error::Error for RetryBudgetExhausted {}
+
+impl RetryBudgetExhausted {
+    fn error(what: String) -> anyhow::Error {
+        anyhow::Error::new(Self { what })
+    }
+
+    /// Для RetryPolicy::retry_if: ошибку исчерпанного бюджета не повторяем
+    fn is_not_exhausted(e: &anyhow::Error) -> bool {
+        e.downcast_ref::<Self>().is_none()
+    }
+}
+
+/// Политика повторов запроса цены спота при нулевом или пустом тикере
+#[derive(Debug, Clone)]
+pub(super) struct SpotPriceRetry {
+    attempts: u32,
+    delay: Duration,
+    mid_fallback: bool, // После неудачных попыток взять середину bid/ask
+    budget: RetryBudget, // Повторы внутри операции списываются с ее общего бюджета
+}
+
+impl SpotPriceRetry {
+    pub(super) fn from_config(config: &Config) -> Self {
+        Self {
+            attempts: config.spot_price_attempts.max(1),
+            delay: Duration::from_millis(config.spot_price_retry_delay_ms),
+            mid_fallback: config.spot_price_mid_fallback,
+            budget: RetryBudget::new(0),
+        }
+    }
+
+    /// Повторы списываются с бюджета операции
+    pub(super) fn with_budget(self, budget: &RetryBudget) -> Self {
+        Self { budget: budget.clone(), ..self }
+    }
+}
+
+/// Цена спота с повторами (with_retry): нулевая цена или ошибка запроса повторяются до retry.attempts раз,
+/// затем (если включено) берется середина bid/ask. Каждый повтор списывается с бюджета операции (retry.budget);
+/// когда бюджет исчерпан, повторы и запасная цена не запрашиваются
+pub(super) async fn get_spot_price_with_retry<E: Exchange>(exchange: &E, symbol: &str, retry: SpotPriceRetry) -> Result<f64> {
+    let policy = RetryPolicy::new("Spot price fetch", retry.attempts, retry.delay).retry_if(RetryBudgetExhausted::is_not_exhausted);
+    let mut first_attempt = true;
+    let last_error = match with_retry(policy, || {
+        let within_budget = std::mem::take(&mut first_attempt) || retry.budget.try_consume();
+        async move {
+            if !within_budget {
+                return Err(RetryBudgetExhausted::error(format!("fetching spot price for {}", symbol)));
+            }
+            match exchange.get_spot_price(symbol).await? {
+                price if price > 0.0 => Ok(price),
+                price => Err(anyhow!("Invalid spot price for {}: {}", symbol, price)),
+            }
+        }
+    })
+    .await
+    {
+        Ok(price) => return Ok(price),
+        Err(e) => e,
+    };
+    if retry.mid_fallback && RetryBudgetExhausted::is_not_exhausted(&last_error) {
+        match exchange.get_reference_price(symbol, SPOT_CATEGORY, PriceSource::Mid).await {
+            Ok(price) if price > 0.0 => {
+                warn!("Using bid/ask mid {:.8} for {} after {} failed spot price attempts", price, symbol, retry.attempts);
+                return Ok(price);
+            }
+            Ok(price) => warn!("Invalid bid/ask mid {} for {}", price, symbol),
+            Err(e) => warn!("Failed to get bid/ask mid for {}: {}", symbol, e),
+        }
+    }
+    Err(last_error)
+}
+
+/// Размещение ордера с повторами при временных ошибках (ExchangeError::Transient) и экспоненциальной паузой
+/// со случайной добавкой до половины базовой (чтобы повторы двух ног не шли синхронно).
+/// Все повторы идут с одним orderLinkId (spec.link_id): если таймаут случился после того,
+/// как биржа приняла ордер, повтор вернет этот же ордер, а не создаст второй.
+/// Постоянные ошибки (валидация, минимумы, баланс) возвращаются сразу.
+/// Каждый повтор списывается с общего бюджета операции (retry.budget).
+async fn place_order_with_retry<E: Exchange + Clone>(exchange: E, spec: OrderSpec<'_>, retry: PlacementRetry) -> Result<(String, f64)> {
+    let policy = RetryPolicy::new("Order placement", retry.max_retries + 1, retry.base_delay)
+        .with_jitter(retry.base_delay / 2)
+        .retry_if(ExchangeError::is_transient);
+    let mut first_attempt = true;
+    with_retry(policy, || {
+        // Повтор списывается с бюджета операции до обращения к бирже
+        let within_budget = std::mem::take(&mut first_attempt) || retry.budget.try_consume();
+        let exchange = exchange.clone();
+        async move {
+            if !within_budget {
+                return Err(RetryBudgetExhausted::error(format!("placing {} order for {}", if spec.is_spot { "spot" } else { "futures" }, spec.symbol)));
+            }
+            place_order(exchange, &spec).await
+        }
+    })
+    .await
+}
+
+/// Ордер для размещения: символ API, котируемая валюта (ключ инструмента фьючерса), сторона, объем, цена
+/// и клиентский orderLinkId логического ордера
+#[derive(Debug, Clone, Copy)]
+struct OrderSpec<'a> {
+    symbol: &'a str,
+    quote_currency: &'a str,
     side: OrderSide,
     qty: f64,
     price: f64,
     is_spot: bool,
-) -> Result<String> { // --- Возвращаем String (ID ордера) ---
+    link_id: &'a str,
+}
+
+/// Счетчик размещений процесса: различает orderLinkId ордеров одной операции
+static ORDER_LINK_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// orderLinkId нового логического ордера (Bybit: до 36 символов, уникален на аккаунт).
+/// Метка времени отделяет ID разных запусков бота, счетчик — ордера внутри запуска.
+fn new_order_link_id(operation_id: i64, is_spot: bool) -> String {
+    let seq = ORDER_LINK_SEQ.fetch_add(1, Ordering::Relaxed);
+    let millis = chrono::Utc::now().timestamp_millis() % 10_000_000_000;
+    format!("hh-{}-{}-{}-{}", operation_id, if is_spot { "spot" } else { "fut" }, millis, seq)
+}
+
+async fn place_order<E: Exchange>(exchange: E, spec: &OrderSpec<'_>) -> Result<(String, f64)> { // --- Возвращаем ID ордера и фактическую цену ---
+    let OrderSpec { symbol, quote_currency, side, qty, price, is_spot, link_id } = *spec;
     if is_spot {
-        let order_info = exchange.place_limit_order(symbol, side, qty, price).await?;
-        Ok(order_info.id)
+        let order_info = exchange.place_limit_order_with_link_id(symbol, side, qty, price, link_id).await?;
+        return Ok((order_info.id, price));
+    }
+
+    match exchange.place_futures_limit_order_with_link_id(symbol, side, qty, price, link_id).await {
+        Ok(order_info) => Ok((order_info.id, price)),
+        Err(e) if is_price_band_rejection(&e) => {
+            // Цена вне ценового коридора контракта: переставляем в пределы коридора один раз,
+            // иначе спот остался бы без хеджа
+            let instrument_key = linear_info_symbol(symbol, quote_currency).unwrap_or(symbol);
+            let band_price = price_within_band(&exchange, symbol, instrument_key, price).await?;
+            warn!(
+                "Futures order for {} rejected by price band at {:.8} ({}). Retrying once at {:.8}",
+                symbol, price, e, band_price
+            );
+            // Отклоненный ордер не создан, но новую цену ставим отдельным ID, чтобы не спутать попытки
+            let band_link_id = format!("{}-b", link_id);
+            let order_info = exchange
+                .place_futures_limit_order_with_link_id(symbol, side, qty, band_price, &band_link_id)
+                .await?;
+            Ok((order_info.id, band_price))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Код Bybit "цена вне допустимого коридора"
+const PRICE_BAND_RET_CODE: i64 = 110003;
+
+/// Отказ биржи из-за цены вне допустимого коридора (Bybit retCode 110003)
+fn is_price_band_rejection(error: &anyhow::Error) -> bool {
+    matches!(error.downcast_ref::<ExchangeError>(), Some(ExchangeError::Api { code: PRICE_BAND_RET_CODE, .. }))
+}
+
+/// Цена, прижатая к коридору minPrice/maxPrice из priceFilter инструмента.
+/// Если статический коридор цену не ограничивает, берём текущую цену тикера (динамический коридор).
+/// instrument_key — ключ get_linear_instrument_info (базовая монета), symbol — символ тикера
+async fn price_within_band<E: Exchange>(exchange: &E, symbol: &str, instrument_key: &str, price: f64) -> Result<f64> {
+    let info = exchange.get_linear_instrument_info(instrument_key).await?;
+    let parse = |value: &Option<String>| value.as_deref().and_then(|v| v.parse::<f64>().ok()).filter(|v| *v > 0.0);
+    let min_price = parse(&info.price_filter.min_price);
+    let max_price = parse(&info.price_filter.max_price);
+
+    let mut clamped = price;
+    if let Some(max) = max_price {
+        clamped = clamped.min(max);
+    }
+    if let Some(min) = min_price {
+        clamped = clamped.max(min);
+    }
+    if (clamped - price).abs() > f64::EPSILON {
+        return Ok(clamped);
+    }
+
+    let ticker = exchange.get_futures_ticker(symbol).await?;
+    let market_price = if ticker.bid_price > 0.0 && ticker.ask_price > 0.0 {
+        (ticker.bid_price + ticker.ask_price) / 2.0
     } else {
-        let order_info = exchange
-            .place_futures_limit_order(symbol, side, qty, price)
-            .await?;
-        Ok(order_info.id)
+        ticker.last_price
+    };
+    if market_price <= 0.0 {
+        return Err(anyhow!("Cannot reprice {} within price band: no valid ticker price", symbol));
     }
+    Ok(market_price)
 }
+/// Исполнение ноги рыночным ордером: размещение и ожидание исполнения.
+/// Каждый неудачный опрос статуса списывается с бюджета операции (retry_budget)
+async fn execute_market_leg<E: Exchange + Clone>(
+    exchange: E,
+    operation_id: i64,
+    symbol: &str,
+    side: OrderSide,
+    qty: f64,
+    is_spot: bool,
+    fill_tolerance: f64,
+    retry_budget: &RetryBudget,
+) -> Result<(f64, String)> {
+    let leg = if is_spot { "spot" } else { "futures" };
+    info!("op_id:{}: Placing {} {} market order for qty {:.8}", operation_id, leg, side, qty);
+    let order = if is_spot {
+        exchange.place_spot_market_order(symbol, side, qty).await?
+    } else {
+        exchange.place_futures_market_order(symbol, side, qty).await?
+    };
+
+    // Последний полученный статус и последняя ошибка опроса: без статуса исполнение неизвестно, а не "не исполнено"
+    let mut last_status: Option<ExchangeOrderStatus> = None;
+    let mut last_poll_error: Option<anyhow::Error> = None;
+    for attempt in 1..=MARKET_FILL_POLL_ATTEMPTS {
+        match get_order_status(exchange.clone(), symbol, &order.id, is_spot).await {
+            Ok(status) => {
+                let is_closed = market_order_closed(&status, fill_tolerance);
+                last_status = Some(status);
+                if is_closed {
+                    break;
+                }
+            }
+            Err(e) => {
+                debug!("op_id:{}: {} market order {} status not available yet (attempt {}): {}", operation_id, leg, order.id, attempt, e);
+                if !retry_budget.try_consume() {
+                    return Err(RetryBudgetExhausted::error(format!(
+                        "polling {} market order {} (fill unknown, last error: {}); check the order on the exchange",
+                        leg, order.id, e
+                    )));
+                }
+                last_poll_error = Some(e);
+            }
+        }
+        sleep(MARKET_FILL_POLL_INTERVAL).await;
+    }
+
+    let Some(status) = last_status else {
+        let reason = last_poll_error.map_or_else(|| "no response".to_string(), |e| e.to_string());
+        return Err(anyhow!(
+            "{} market order {} was placed but its fill is unknown ({}); check the order on the exchange",
+            leg, order.id, reason
+        ));
+    };
+    if !market_order_closed(&status, fill_tolerance) {
+        return Err(anyhow!(
+            "{} market order {} is still open after {} status checks ({:.8}/{:.8} filled); check the order on the exchange",
+            leg, order.id, MARKET_FILL_POLL_ATTEMPTS, status.filled_qty, qty
+        ));
+    }
+    if status.filled_qty <= fill_tolerance {
+        return Err(anyhow!("{} market order {} was not filled", leg, order.id));
+    }
+    if qty - status.filled_qty > fill_tolerance {
+        warn!("op_id:{}: {} market order {} filled only {:.8}/{:.8}", operation_id, leg, order.id, status.filled_qty, qty);
+    } else {
+        info!("op_id:{}: {} market order {} filled {:.8}/{:.8}", operation_id, leg, order.id, status.filled_qty, qty);
+    }
+    Ok((status.filled_qty, order.id))
+}
+
+/// Рыночный ордер больше не исполняется: остатка нет или биржа его закрыла (IOC-остаток отменяется с leavesQty = 0)
+fn market_order_closed(status: &ExchangeOrderStatus, fill_tolerance: f64) -> bool {
+    status.remaining_qty <= fill_tolerance || status.status.is_cancelled() || status.status == OrderStatusText::Rejected
+}
+
 async fn get_order_status<E: Exchange>(
     exchange: E,
     symbol: &str,
@@ -613,15 +1116,49 @@ async fn cancel_order<E: Exchange>(
     }
 }
 
+/// Меняется только цена: остаток ордера совпадает с общим остатком и не ниже минимального количества
+fn is_price_only_change(
+    initial_target_qty: f64,
+    cumulative_filled_qty: f64,
+    order_remaining_qty: f64,
+    min_order_qty_decimal: Option<Decimal>,
+    fill_tolerance: f64,
+) -> bool {
+    let remaining_total_qty = (initial_target_qty - cumulative_filled_qty).max(0.0);
+    if (remaining_total_qty - order_remaining_qty).abs() > fill_tolerance {
+        return false;
+    }
+    match (min_order_qty_decimal, Decimal::from_f64(order_remaining_qty)) {
+        (Some(min_qty), Some(remaining_d)) => remaining_d >= min_qty,
+        _ => true,
+    }
+}
+
+async fn amend_order_price<E: Exchange>(
+    exchange: E,
+    symbol: &str,
+    order_id: &str,
+    price: f64,
+    is_spot: bool,
+) -> Result<()> {
+    let category = if is_spot { SPOT_CATEGORY } else { LINEAR_CATEGORY };
+    exchange.amend_order(symbol, order_id, price, None, category).await
+}
+
 async fn get_market_price<E: Exchange>(
     exchange: E,
     symbol: &str, // Символ для API (spot или futures)
     is_spot: bool,
     quote_currency: &str, // <-- ДОБАВЛЕН ПАРАМЕТР
+    price_source: Option<PriceSource>, // None — прежняя логика (last для спота, mid для фьючерса)
+    spot_retry: SpotPriceRetry,
 ) -> Result<f64> {
-    let price = if is_spot {
+    let price = if let Some(source) = price_source {
+        let category = if is_spot { SPOT_CATEGORY } else { LINEAR_CATEGORY };
+        exchange.get_reference_price(symbol, category, source).await?
+    } else if is_spot {
         // Для спота quote_currency не нужен
-        exchange.get_spot_price(symbol).await?
+        get_spot_price_with_retry(&exchange, symbol, spot_retry).await?
     } else {
         // Для фьючерса берем среднюю цену между бидом и аском или последнюю цену
         match exchange.get_futures_ticker(symbol).await {
@@ -650,6 +1187,24 @@ async fn get_market_price<E: Exchange>(
     }
 }
 
+/// Базовая цена для начальной лимитки по настройке price_source;
+/// без настройки или при ошибке получения используется переданная цена
+pub(super) async fn reference_price_or<E: Exchange>(hedger: &Hedger<E>, symbol: &str, is_spot: bool, fallback: f64) -> f64 {
+    let Some(source) = hedger.config.price_source else { return fallback };
+    let category = if is_spot { SPOT_CATEGORY } else { LINEAR_CATEGORY };
+    match hedger.exchange.get_reference_price(symbol, category, source).await {
+        Ok(price) if price > 0.0 => price,
+        Ok(price) => {
+            warn!("Invalid {:?} reference price {} for {}. Using {:.8}.", source, price, symbol, fallback);
+            fallback
+        }
+        Err(e) => {
+            warn!("Failed to get {:?} reference price for {}: {}. Using {:.8}.", source, symbol, e, fallback);
+            fallback
+        }
+    }
+}
+
 // --- Helper for spot price fallback in get_market_price ---
 async fn get_spot_price_fallback<E: Exchange>(
     exchange: &E,
@@ -671,11 +1226,219 @@ async fn get_spot_price_fallback<E: Exchange>(
 
 
 
+/// Обновляет информацию о текущем активном ордере (для корректной отмены по кнопке)
+async fn set_active_order<E: Exchange>(
+    hedger: &Hedger<E>,
+    stage: HedgeStage,
+    is_spot: bool,
+    symbol: &str,
+    order_id: Option<&str>,
+    filled_before: f64,
+) {
+    *hedger.active_order.lock().await = order_id.map(|id| ActiveOrder {
+        stage,
+        is_spot,
+        symbol: symbol.to_string(),
+        order_id: id.to_string(),
+        filled_before,
+    });
+}
+
+/// Живой фьючерсный ордер в БД: после перезапуска за ним можно продолжить следить (см. hedger::recovery).
+/// Ошибка записи только логируется — операция продолжается
+async fn persist_futures_order(db: &Db, operation_id: i64, order_id: &str, filled_before: f64, target_qty: f64) {
+    if let Err(e) = update_running_futures_order(db, operation_id, order_id, filled_before, target_qty).await {
+        error!("op_id:{}: Failed to persist live futures order {} in DB: {}", operation_id, order_id, e);
+    }
+}
+
+/// Шаг цены инструмента; None — получить не удалось (цена не корректируется)
+async fn tick_size_for<E: Exchange>(hedger: &Hedger<E>, symbol: &str, is_spot: bool) -> Option<Decimal> {
+    let tick_size = if is_spot {
+        hedger.exchange.get_spot_instrument_info(symbol).await.map(|info| info.price_filter.tick_size)
+    } else {
+        match linear_info_symbol(symbol, &hedger.quote_currency) {
+            Ok(info_symbol) => hedger.exchange.get_linear_instrument_info(info_symbol).await.map(|info| info.price_filter.tick_size),
+            Err(e) => Err(e),
+        }
+    };
+    match tick_size {
+        Ok(raw) => raw.parse::<Decimal>().ok().filter(|tick| *tick > Decimal::ZERO),
+        Err(e) => {
+            warn!("Failed to get tick size for {}: {}. Tick offset is not enforced.", symbol, e);
+            None
+        }
+    }
+}
+
+/// Шаг количества инструмента (спот: basePrecision, фьючерс: qtyStep) для цикла ордеров
+pub(super) async fn qty_precision_for<E: Exchange>(hedger: &Hedger<E>, symbol: &str, is_spot: bool) -> QtyPrecision {
+    exchange_qty_precision(&hedger.exchange, &hedger.quote_currency, symbol, is_spot).await
+}
+
+/// То же без Hedger: для фоновых задач, у которых есть только биржа (см. hedger::pending)
+pub(super) async fn exchange_qty_precision<E: Exchange>(exchange: &E, quote_currency: &str, symbol: &str, is_spot: bool) -> QtyPrecision {
+    let qty_step = if is_spot {
+        exchange
+            .get_spot_instrument_info(symbol)
+            .await
+            .map(|info| info.lot_size_filter.base_precision.or(info.lot_size_filter.qty_step))
+    } else {
+        match linear_info_symbol(symbol, quote_currency) {
+            Ok(info_symbol) => exchange.get_linear_instrument_info(info_symbol).await.map(|info| info.lot_size_filter.qty_step),
+            Err(e) => Err(e),
+        }
+    };
+    match qty_step {
+        Ok(raw) => QtyPrecision::new(raw.and_then(|step| step.parse::<Decimal>().ok())),
+        Err(e) => {
+            warn!("Failed to get qty step for {}: {}. Using default fill tolerance.", symbol, e);
+            QtyPrecision::new(None)
+        }
+    }
+}
+
+/// Точность количества инструмента. Цель округлена до spot_decimals, а исполнение приходит с точностью биржи,
+/// поэтому "цель достигнута" проверяется после привязки обоих значений к шагу
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct QtyPrecision {
+    step: Option<Decimal>, // None — шаг неизвестен, сравнение с допуском ORDER_FILL_TOLERANCE
+}
+
+impl QtyPrecision {
+    pub(super) fn new(step: Option<Decimal>) -> Self {
+        Self { step: step.filter(|step| *step > Decimal::ZERO) }
+    }
+
+    /// Допуск исполнения остатка (половина шага)
+    pub(super) fn tolerance(&self) -> f64 {
+        fill_tolerance_for_step(self.step)
+    }
+
+    /// Количество, привязанное к ближайшему кратному шага
+    fn snap(step: Decimal, qty: f64) -> Option<Decimal> {
+        Some((Decimal::from_f64(qty)? / step).round() * step)
+    }
+
+    /// Исполнено не меньше цели с точностью до шага инструмента
+    pub(super) fn target_reached(&self, filled_qty: f64, target_qty: f64) -> bool {
+        match self.step.and_then(|step| Some((Self::snap(step, filled_qty)?, Self::snap(step, target_qty)?))) {
+            Some((filled, target)) => filled >= target,
+            None => filled_qty >= target_qty - ORDER_FILL_TOLERANCE,
+        }
+    }
+}
+
 // --- Вспомогательные синхронные функции ---
+
+/// Половина шага количества: остаток меньше шага уже не выставить, а остаток в целый шаг — не исполнен.
+/// Шаг неизвестен — ORDER_FILL_TOLERANCE
+pub(super) fn fill_tolerance_for_step(qty_step: Option<Decimal>) -> f64 {
+    qty_step
+        .filter(|step| *step > Decimal::ZERO)
+        .and_then(|step| (step / Decimal::TWO).to_f64())
+        .unwrap_or(ORDER_FILL_TOLERANCE)
+}
+
+/// Накопление исполнения без обрезки по цели: перекуп (ордер, подогнанный под minOrderQty,
+/// дозаполнение во время отмены) сохраняется, чтобы следующая нога покрыла реальный объем
+pub(super) fn add_fill(cumulative_filled_qty: f64, filled_delta: f64) -> f64 {
+    (cumulative_filled_qty + filled_delta).max(0.0)
+}
+
+/// Цена за ценовым ограничителем операции: для покупки — выше него, для продажи — ниже
+pub(super) fn breaches_price_guard(price: f64, side: OrderSide, price_guard: Option<f64>) -> bool {
+    match (price_guard, side) {
+        (Some(guard), OrderSide::Buy) => price > guard,
+        (Some(guard), OrderSide::Sell) => price < guard,
+        (None, _) => false,
+    }
+}
+
+fn price_guard_hit(operation_id: i64, stage: HedgeStage, required_price: f64, price_guard: Option<f64>, filled_qty: f64, target_qty: f64) -> anyhow::Error {
+    let guard_price = price_guard.unwrap_or_default();
+    warn!(
+        "op_id:{}: Required price {:.8} is beyond price guard {:.8}. Stopping stage with {:.8}/{:.8} filled. (Stage: {:?})",
+        operation_id, required_price, guard_price, filled_qty, target_qty, stage
+    );
+    PriceGuardHit { required_price, guard_price, filled_qty, target_qty }.into()
+}
+
+/// Исполнено сверх цели; 0 — перекупа нет или он в пределах допуска
+pub(super) fn overfill_qty(filled_qty: f64, target_qty: f64, tolerance: f64) -> f64 {
+    let excess = filled_qty - target_qty;
+    if excess > tolerance { excess } else { 0.0 }
+}
+
+/// Снятие трейлинг-стопа перед закрытием фьючерсной ноги операции. Стоп стоит на позиции контракта целиком,
+/// поэтому снимается только вместе с последним открытым хеджем контракта; иначе он защищает остальные хеджи
+pub(super) async fn release_trailing_stop<E>(hedger: &Hedger<E>, db: &Db, operation_id: i64, futures_symbol: &str)
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let environment = hedger.config.environment();
+    match is_trailing_stop_active(db, environment, futures_symbol).await {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            warn!("op_id:{}: Failed to read trailing stop state for {}: {}", operation_id, futures_symbol, e);
+            return;
+        }
+    }
+    let other_open_hedges = match get_open_hedge_operations(db).await {
+        Ok(ops) => ops
+            .iter()
+            .filter(|op| op.id != operation_id && op.belongs_to_environment(environment) && op.futures_contract() == futures_symbol)
+            .count(),
+        Err(e) => {
+            warn!("op_id:{}: Failed to load open hedges for {}: {}. Keeping trailing stop.", operation_id, futures_symbol, e);
+            return;
+        }
+    };
+    if other_open_hedges > 0 {
+        info!("op_id:{}: Trailing stop on {} kept for {} other open hedge(s)", operation_id, futures_symbol, other_open_hedges);
+        return;
+    }
+    info!("op_id:{}: Clearing trailing stop on {} (last open hedge of the contract)", operation_id, futures_symbol);
+    match hedger.exchange.set_trailing_stop(futures_symbol, 0.0).await {
+        Ok(()) => {
+            if let Err(e) = set_trailing_stop_active(db, environment, futures_symbol, false).await {
+                warn!("op_id:{}: Failed to reset trailing stop state in DB: {}", operation_id, e);
+            }
+        }
+        Err(e) => warn!("op_id:{}: Failed to clear trailing stop for {}: {}. Continuing.", operation_id, futures_symbol, e),
+    }
+}
+
 pub(super) fn calculate_limit_price(market_price: f64, side: OrderSide, slippage: f64) -> f64 {
     market_price * (1.0 - slippage * side.sign()) // Buy: ниже рынка, Sell: выше рынка
 }
 
+/// Если slippage меньше тика, биржевое округление (Buy — вниз, Sell — вверх) возвращает лимитку
+/// в тик опорной цены. В этом случае цена сдвигается от опорной в сторону slippage минимум
+/// на `min_ticks` тиков (offset_points, не меньше одного).
+/// Лимитка ровно по опорной цене (slippage = 0) не меняется
+pub(super) fn enforce_tick_offset(reference_price: f64, limit_price: f64, side: OrderSide, tick_size: Decimal, min_ticks: u32) -> f64 {
+    if limit_price == reference_price || tick_size <= Decimal::ZERO {
+        return limit_price;
+    }
+    let (Some(reference), Some(limit)) = (Decimal::from_f64(reference_price), Decimal::from_f64(limit_price)) else {
+        return limit_price;
+    };
+    let reference_tick = round_to_tick(reference, tick_size, None);
+    let limit_tick = round_to_tick(limit, tick_size, Some(side));
+    let min_offset = tick_size * Decimal::from(min_ticks.max(1));
+    let adjusted = match side {
+        OrderSide::Buy if limit > reference && limit_tick < reference_tick + min_offset => reference_tick + min_offset,
+        OrderSide::Sell if limit < reference && limit_tick > reference_tick - min_offset => reference_tick - min_offset,
+        _ => return limit_price,
+    };
+    if adjusted <= Decimal::ZERO {
+        return limit_price;
+    }
+    adjusted.to_f64().unwrap_or(limit_price)
+}
+
 // Расширяем OrderSide для получения знака
 trait SideSign {
     fn sign(&self) -> f64;
@@ -695,3 +1458,549 @@ impl SideSign for OrderSide {
         }
     }
 }
+
+/// Политика повторов критической записи в БД
+#[derive(Debug, Clone, Copy)]
+pub struct DbWriteRetry {
+    attempts: u32,
+    delay: Duration,
+}
+
+impl DbWriteRetry {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            attempts: config.db_write_retry_attempts,
+            delay: Duration::from_millis(config.db_write_retry_delay_ms),
+        }
+    }
+}
+
+/// Критическая запись в БД (финальный статус операции) с повторами.
+/// Если все попытки неудачны — DbRecordDiverged: сделка на бирже есть, а в БД не записана
+pub async fn write_with_retry<T, F, Fut>(retry: DbWriteRetry, operation_id: i64, what: &str, mut write: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, sqlx::Error>>,
+{
+    let policy = RetryPolicy::new("Critical DB write", retry.attempts, retry.delay);
+    with_retry(policy, || {
+        let pending = write();
+        async move { pending.await.map_err(anyhow::Error::from) }
+    })
+    .await
+    .map_err(|e| {
+        error!("op_id:{}: DB write '{}' failed after retries: {}. Exchange and DB state diverged!", operation_id, what, e);
+        anyhow::Error::new(DbRecordDiverged { operation_id, what: what.to_string(), error: e.to_string() })
+    })
+}
+
+/// Финальный статус операции (finalize_operation) с повторами записи.
+/// false — операция уже не 'Running'; сбой после повторов — DbRecordDiverged
+pub async fn finalize_with_retry(
+    db: &Db,
+    retry: DbWriteRetry,
+    operation_id: i64,
+    status: OperationStatus,
+    spot: Option<LegFill<'_>>,
+    futures: LegFill<'_>,
+    error_message: Option<&str>,
+) -> Result<bool> {
+    let what = format!("finalize {}", status);
+    write_with_retry(retry, operation_id, &what, || finalize_operation(db, operation_id, status, spot, futures, error_message)).await
+}
+
+/// Операция завершилась ошибкой: пишет Failed с `error_message` и возвращает `error` для вызывающего.
+/// Если запись не удалась — DbRecordDiverged с исходной ошибкой в контексте: расхождение не теряется
+pub async fn fail_operation(
+    db: &Db,
+    retry: DbWriteRetry,
+    operation_id: i64,
+    spot: Option<LegFill<'_>>,
+    futures: LegFill<'_>,
+    error_message: &str,
+    error: anyhow::Error,
+) -> anyhow::Error {
+    match finalize_with_retry(db, retry, operation_id, OperationStatus::Failed, spot, futures, Some(error_message)).await {
+        Ok(_) => error,
+        Err(diverged) => diverged.context(error.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::mock::{FillSchedule, MockExchange};
+    use futures::future::FutureExt;
+
+    fn order_spec(side: OrderSide, price: f64, is_spot: bool) -> OrderSpec<'static> {
+        OrderSpec { symbol: "BTCUSDT", quote_currency: "USDT", side, qty: 1.0, price, is_spot, link_id: "hh-1-test" }
+    }
+
+    const FAST_RETRY: PlacementRetry = PlacementRetry { max_retries: 2, base_delay: Duration::from_millis(1), budget: RetryBudget { remaining: None } };
+    const FAST_PRICE_RETRY: SpotPriceRetry =
+        SpotPriceRetry { attempts: 3, delay: Duration::from_millis(1), mid_fallback: false, budget: RetryBudget { remaining: None } };
+    const FAST_DB_RETRY: DbWriteRetry = DbWriteRetry { attempts: 3, delay: Duration::from_millis(1) };
+
+    #[tokio::test]
+    async fn critical_db_write_survives_transient_failures() {
+        let mut calls = 0;
+        let written = write_with_retry(FAST_DB_RETRY, 7, "final status", || {
+            calls += 1;
+            let failing = calls <= 2;
+            async move { if failing { Err(sqlx::Error::PoolTimedOut) } else { Ok(true) } }
+        })
+        .await
+        .expect("written after retries");
+        assert!(written);
+        assert_eq!(calls, 3);
+    }
+
+    #[tokio::test]
+    async fn persistent_db_failure_is_reported_as_divergence() {
+        let mut calls = 0;
+        let result: Result<bool> = write_with_retry(FAST_DB_RETRY, 7, "final status", || {
+            calls += 1;
+            async { Err(sqlx::Error::PoolTimedOut) }
+        })
+        .await;
+        assert_eq!(calls, 3);
+        let error = result.expect_err("write must fail");
+        let diverged = error.downcast_ref::<DbRecordDiverged>().expect("divergence error");
+        assert_eq!(diverged.operation_id, 7);
+        assert_eq!(diverged.what, "final status");
+    }
+
+    #[tokio::test]
+    async fn failed_status_write_surfaces_divergence_with_stage_error() {
+        let db = memory_db().await;
+        let (operation_id, ..) = crate::storage::insert_hedge_operation(&db, 42, "BTC", "USDT", "testnet", 100.0, 0.01, 1.0, 1.0, false)
+            .await
+            .expect("insert");
+
+        // Запись удалась: вызывающему возвращается исходная ошибка, спот и фьючерс в своих колонках
+        let spot = LegFill { order_id: None, filled_qty: 0.4 };
+        let error = fail_operation(&db, FAST_DB_RETRY, operation_id, Some(spot), LegFill::NONE, "Spot stage failed", anyhow!("boom")).await;
+        assert!(error.downcast_ref::<DbRecordDiverged>().is_none());
+        let operation = crate::storage::get_hedge_operation_by_id(&db, operation_id).await.expect("load").expect("op");
+        assert!(operation.has_status(OperationStatus::Failed));
+        assert_eq!((operation.spot_filled_qty, operation.futures_filled_qty), (0.4, 0.0));
+
+        // БД недоступна: после повторов — DbRecordDiverged, исходная ошибка остается в контексте
+        db.close().await;
+        let error = fail_operation(&db, FAST_DB_RETRY, operation_id, None, LegFill::NONE, "Spot stage failed", anyhow!("boom")).await;
+        let diverged = error.downcast_ref::<DbRecordDiverged>().expect("divergence error");
+        assert_eq!((diverged.operation_id, diverged.what.as_str()), (operation_id, "finalize Failed"));
+        assert_eq!(error.to_string(), "boom");
+    }
+
+    #[tokio::test]
+    async fn futures_order_is_repriced_into_price_band() {
+        let exchange = MockExchange { fut_price_band: Some((90.0, 110.0)), ..MockExchange::default() };
+
+        let (order_id, price) = place_order(exchange, &order_spec(OrderSide::Sell, 120.0, false))
+            .await
+            .expect("order should be placed after repricing");
+
+        assert_eq!(order_id, "mock-futures-order");
+        assert_eq!(price, 110.0);
+    }
+
+    #[tokio::test]
+    async fn transient_placement_failure_is_retried() {
+        let exchange = MockExchange::default();
+        exchange.fail_next_placements(1);
+
+        let (order_id, _) = place_order_with_retry(exchange.clone(), order_spec(OrderSide::Buy, 100.0, true), FAST_RETRY)
+            .await
+            .expect("second attempt should succeed");
+
+        assert_eq!(order_id, "mock-spot-order");
+        assert_eq!(exchange.placement_attempts(), 2);
+    }
+
+    #[tokio::test]
+    async fn placement_retry_after_lost_response_does_not_duplicate_order() {
+        // Первая попытка дошла до биржи, но ответ потерян по таймауту
+        let exchange = MockExchange::default();
+        exchange.lose_next_placement_responses(1);
+
+        let (order_id, _) = place_order_with_retry(exchange.clone(), order_spec(OrderSide::Buy, 100.0, true), FAST_RETRY)
+            .await
+            .expect("retry should resolve the already placed order");
+
+        assert_eq!(order_id, "mock-spot-order");
+        assert_eq!(exchange.placement_attempts(), 2);
+        assert_eq!(exchange.created_limit_orders(), 1);
+    }
+
+    #[test]
+    fn order_link_ids_are_unique_and_fit_bybit_limit() {
+        let first = new_order_link_id(1_234_567, true);
+        let second = new_order_link_id(1_234_567, true);
+        assert_ne!(first, second);
+        assert!(first.starts_with("hh-1234567-spot-"));
+        // Запас под суффикс "-b" перестановки в ценовой коридор
+        assert!(format!("{}-b", first).len() <= 36, "{}", first);
+    }
+
+    #[tokio::test]
+    async fn permanent_placement_failure_is_not_retried() {
+        // Фьючерсная лимитка без заданного коридора в моке — постоянная ошибка
+        let exchange = MockExchange::default();
+
+        let result = place_order_with_retry(exchange, order_spec(OrderSide::Sell, 100.0, false), FAST_RETRY).await;
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn micro_price_limit_keeps_one_tick_offset() {
+        let tick = Decimal::from_str_exact("0.00000001").unwrap();
+        let reference = 0.00001234;
+
+        // Slippage 0.01% меньше тика: без сдвига лимитка округлилась бы обратно в 0.00001234
+        let buy = enforce_tick_offset(reference, calculate_limit_price(reference, OrderSide::Buy, 0.0001), OrderSide::Buy, tick, 1);
+        assert!((buy - 0.00001235).abs() < 1e-12, "buy {}", buy);
+        let sell = enforce_tick_offset(reference, calculate_limit_price(reference, OrderSide::Sell, 0.0001), OrderSide::Sell, tick, 1);
+        assert!((sell - 0.00001233).abs() < 1e-12, "sell {}", sell);
+
+        // Slippage больше тика и нулевой slippage не трогаются
+        let btc_buy = calculate_limit_price(65000.0, OrderSide::Buy, 0.0005);
+        assert_eq!(enforce_tick_offset(65000.0, btc_buy, OrderSide::Buy, Decimal::new(1, 1), 10), btc_buy);
+        assert_eq!(enforce_tick_offset(reference, reference, OrderSide::Buy, tick, 1), reference);
+    }
+
+    #[test]
+    fn offset_points_widen_minimum_tick_offset() {
+        let tick = Decimal::from_str_exact("0.00000001").unwrap();
+        let reference = 0.00001234;
+
+        let buy = enforce_tick_offset(reference, calculate_limit_price(reference, OrderSide::Buy, 0.0001), OrderSide::Buy, tick, 3);
+        assert!((buy - 0.00001237).abs() < 1e-12, "buy {}", buy);
+        let sell = enforce_tick_offset(reference, calculate_limit_price(reference, OrderSide::Sell, 0.0001), OrderSide::Sell, tick, 3);
+        assert!((sell - 0.00001231).abs() < 1e-12, "sell {}", sell);
+
+        // offset_points = 0 ведет себя как один тик
+        let zero = enforce_tick_offset(reference, calculate_limit_price(reference, OrderSide::Buy, 0.0001), OrderSide::Buy, tick, 0);
+        assert!((zero - 0.00001235).abs() < 1e-12, "zero {}", zero);
+    }
+
+    #[tokio::test]
+    async fn exhausted_retry_budget_aborts_operation() {
+        let exchange = MockExchange::default();
+        exchange.fail_next_placements(2);
+        // Бюджет на всю операцию — один повтор, хотя политика размещения допускает два
+        let budget = RetryBudget::new(1);
+
+        let retry = PlacementRetry { budget: budget.clone(), ..FAST_RETRY };
+
+        let err = place_order_with_retry(exchange.clone(), order_spec(OrderSide::Buy, 100.0, true), retry)
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("retry budget exhausted"));
+        assert_eq!(exchange.placement_attempts(), 2);
+        // Клоны делят один счетчик: следующая подоперация тоже не получает повторов
+        assert!(!budget.clone().try_consume());
+        assert!(RetryBudget::new(0).try_consume());
+    }
+
+    #[tokio::test]
+    async fn market_market_legs_are_filled_by_market_orders() {
+        let exchange = MockExchange::default();
+
+        let (spot_filled, spot_order) = execute_market_leg(exchange.clone(), 1, "BTC", OrderSide::Buy, 0.5, true, ORDER_FILL_TOLERANCE, &RetryBudget::new(0)).await.unwrap();
+        let (fut_filled, fut_order) = execute_market_leg(exchange, 1, "BTCUSDT", OrderSide::Sell, 0.5, false, ORDER_FILL_TOLERANCE, &RetryBudget::new(0)).await.unwrap();
+
+        assert_eq!((spot_filled, fut_filled), (0.5, 0.5));
+        assert_ne!(spot_order, fut_order);
+    }
+
+    #[tokio::test]
+    async fn partial_ioc_fill_returns_actual_qty() {
+        let exchange = MockExchange { market_fill_fraction: Some(0.4), ..MockExchange::default() };
+
+        let (filled, _) = execute_market_leg(exchange, 1, "BTCUSDT", OrderSide::Sell, 0.5, false, ORDER_FILL_TOLERANCE, &RetryBudget::new(0)).await.unwrap();
+
+        assert!((filled - 0.2).abs() < 1e-12, "{}", filled);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_status_polls_are_not_reported_as_unfilled() {
+        let exchange = MockExchange::default();
+        exchange.fail_next_status_polls(MARKET_FILL_POLL_ATTEMPTS as usize);
+
+        let err = execute_market_leg(exchange, 1, "BTCUSDT", OrderSide::Sell, 0.5, false, ORDER_FILL_TOLERANCE, &RetryBudget::new(0)).await.unwrap_err();
+
+        assert!(err.to_string().contains("fill is unknown"), "{}", err);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_status_polls_consume_operation_retry_budget() {
+        let exchange = MockExchange::default();
+        exchange.fail_next_status_polls(MARKET_FILL_POLL_ATTEMPTS as usize);
+        let budget = RetryBudget::new(1);
+
+        let err = execute_market_leg(exchange, 1, "BTCUSDT", OrderSide::Sell, 0.5, false, ORDER_FILL_TOLERANCE, &budget).await.unwrap_err();
+
+        assert!(err.downcast_ref::<RetryBudgetExhausted>().is_some(), "{}", err);
+        assert!(!budget.try_consume());
+    }
+
+    #[tokio::test]
+    async fn zero_spot_price_is_refetched() {
+        let exchange = MockExchange::default();
+        exchange.return_zero_spot_prices(1);
+
+        let price = get_spot_price_with_retry(&exchange, "BTC", FAST_PRICE_RETRY).await.unwrap();
+        assert_eq!(price, 100.0);
+
+        exchange.return_zero_spot_prices(3);
+        let err = get_spot_price_with_retry(&exchange, "BTC", FAST_PRICE_RETRY).await.unwrap_err();
+        assert!(err.to_string().contains("Invalid spot price"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn spot_price_refetches_consume_operation_retry_budget() {
+        let exchange = MockExchange::default();
+        exchange.return_zero_spot_prices(3);
+        // Политика допускает три попытки, но бюджет операции — один повтор
+        let budget = RetryBudget::new(1);
+        let retry = SpotPriceRetry { mid_fallback: true, ..FAST_PRICE_RETRY }.with_budget(&budget);
+
+        let err = get_spot_price_with_retry(&exchange, "BTC", retry).await.unwrap_err();
+
+        // Бюджет исчерпан: ни третьей попытки, ни запасной цены bid/ask
+        assert!(err.downcast_ref::<RetryBudgetExhausted>().is_some(), "{}", err);
+        assert!(!budget.try_consume());
+    }
+
+    #[tokio::test]
+    async fn market_price_follows_configured_source() {
+        let exchange = MockExchange { index_price: Some(95.0), ..MockExchange::default() };
+
+        let index = get_market_price(exchange.clone(), "BTCUSDT", false, "USDT", Some(PriceSource::Index), FAST_PRICE_RETRY).await.unwrap();
+        let default = get_market_price(exchange, "BTCUSDT", false, "USDT", None, FAST_PRICE_RETRY).await.unwrap();
+
+        assert_eq!(index, 95.0);
+        assert_eq!(default, 100.0); // Без price_source — середина bid/ask фьючерса
+    }
+
+    #[tokio::test]
+    async fn futures_order_inside_band_keeps_price() {
+        let exchange = MockExchange { fut_price_band: Some((90.0, 110.0)), ..MockExchange::default() };
+
+        let (_, price) = place_order(exchange, &order_spec(OrderSide::Sell, 101.5, false))
+            .await
+            .expect("order inside band should be placed");
+
+        assert_eq!(price, 101.5);
+    }
+
+    async fn memory_db() -> Db {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+        crate::storage::schema::apply_migrations(&db).await.expect("migrations");
+        db
+    }
+
+    /// Покупка спота лимитками через manage_order_loop на заглушке биржи; возвращает результат цикла и исполненный объем
+    async fn run_spot_buy_loop(exchange: MockExchange, target_qty: f64, price_guard: Option<f64>) -> (Result<(f64, Option<String>)>, f64) {
+        let progress_callback: HedgeProgressCallback = Box::new(|_update| async { Ok(()) }.boxed());
+        run_spot_buy_loop_with(exchange, crate::config::test_config(""), target_qty, price_guard, progress_callback).await
+    }
+
+    async fn run_spot_buy_loop_with(
+        exchange: MockExchange,
+        config: Config,
+        target_qty: f64,
+        price_guard: Option<f64>,
+        mut progress_callback: HedgeProgressCallback,
+    ) -> (Result<(f64, Option<String>)>, f64) {
+        let hedger = Hedger::new(exchange, config);
+        let db = memory_db().await;
+        let filled_storage = Arc::new(TokioMutex::new(0.0));
+        let params = OrderLoopParams {
+            hedger: &hedger,
+            db: &db,
+            operation_id: 1,
+            symbol: "ETH",
+            side: OrderSide::Buy,
+            initial_target_qty: target_qty,
+            initial_limit_price: calculate_limit_price(100.0, OrderSide::Buy, hedger.config.slippage_for("ETH")),
+            progress_callback: &mut progress_callback,
+            stage: HedgeStage::Spot,
+            is_spot: true,
+            min_order_qty_decimal: None,
+            total_filled_qty_storage: filled_storage.clone(),
+            keep_order_on_timeout: false,
+            order_type: OrderType::Limit,
+            retry_budget: RetryBudget::new(0),
+            qty_precision: QtyPrecision::new(None),
+            futures_recovery_base: None,
+            futures_recovery_target: None,
+            price_guard,
+        };
+        let result = manage_order_loop(params).await;
+        let filled = *filled_storage.lock().await;
+        (result, filled)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stale_order_is_amended_by_order_loop() {
+        let exchange = MockExchange { fill_schedule: Some(FillSchedule::Linear { polls: 30 }), ..MockExchange::default() };
+        let market = exchange.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_secs(2)).await;
+            market.move_spot_price(101.0); // Рынок ушел вверх: лимитка покупки устарела
+        });
+
+        let (result, filled) = run_spot_buy_loop(exchange.clone(), 1.0, None).await;
+
+        let (loop_filled, last_order) = result.expect("loop completes");
+        assert_eq!((loop_filled, filled), (1.0, 1.0));
+        assert_eq!(last_order.as_deref(), Some("mock-spot-order-1"));
+        // Цена изменена на месте: ордер тот же, новых лимиток не создавалось
+        let amended = exchange.amended_orders();
+        assert_eq!(amended.len(), 1, "{:?}", amended);
+        assert_eq!(amended[0].0, "mock-spot-order-1");
+        assert!(amended[0].1 > 100.0);
+        assert_eq!(exchange.created_limit_orders(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn externally_cancelled_order_is_replaced_for_remainder() {
+        let exchange = MockExchange { fill_schedule: Some(FillSchedule::Linear { polls: 4 }), ..MockExchange::default() };
+        let market = exchange.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(1200)).await;
+            market.cancel_order_externally("mock-spot-order-1"); // Отмена вручную в приложении биржи до полного исполнения
+        });
+
+        let (result, filled) = run_spot_buy_loop(exchange.clone(), 1.0, None).await;
+
+        // Исполненная часть отмененного ордера учтена, остаток докуплен новым ордером
+        let (loop_filled, last_order) = result.expect("loop completes after external cancel");
+        assert_eq!((loop_filled, filled), (1.0, 1.0));
+        assert_eq!(last_order.as_deref(), Some("mock-spot-order-2"));
+        assert_eq!(exchange.created_limit_orders(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replacement_refreshes_stale_market_price() {
+        let exchange = MockExchange { fill_schedule: Some(FillSchedule::Linear { polls: 4 }), ..MockExchange::default() };
+        let market = exchange.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_secs(1)).await;
+            market.move_spot_price(103.0); // Рынок ушел до проверки цены цикла
+            sleep(Duration::from_millis(200)).await;
+            market.cancel_order_externally("mock-spot-order-1");
+        });
+        let replacements: Arc<std::sync::Mutex<Vec<(f64, f64)>>> = Arc::default();
+        let recorded = replacements.clone();
+        let progress_callback: HedgeProgressCallback = Box::new(move |update| {
+            if update.is_replacement {
+                recorded.lock().unwrap().push((update.current_spot_price, update.new_limit_price));
+            }
+            async { Ok(()) }.boxed()
+        });
+
+        let (result, _) = run_spot_buy_loop_with(exchange.clone(), crate::config::test_config(""), 1.0, None, progress_callback).await;
+
+        result.expect("loop completes after external cancel");
+        // Перестановка не использует оценку цены по первой лимитке: цена запрошена заново
+        let replacements = replacements.lock().unwrap().clone();
+        assert_eq!(replacements.len(), 1, "{:?}", replacements);
+        assert_eq!(replacements[0].0, 103.0);
+        assert!(replacements[0].1 > 100.5 && replacements[0].1 < 103.0, "{:?}", replacements);
+    }
+
+    #[tokio::test]
+    async fn price_only_change_amends_order_in_place() {
+        let exchange = MockExchange { fut_price_band: Some((90.0, 110.0)), ..MockExchange::default() };
+
+        amend_order_price(exchange.clone(), "BTCUSDT", "fut-1", 105.0, false).await.expect("amend inside band");
+        let rejected = amend_order_price(exchange.clone(), "BTCUSDT", "fut-1", 120.0, false).await;
+
+        assert!(rejected.is_err(), "amend outside band should fall back to cancel+replace");
+        assert_eq!(exchange.amended_orders(), vec![("fut-1".to_string(), 105.0)]);
+    }
+
+    #[test]
+    fn replacement_beyond_guard_halts_mid_fill() {
+        // Куплено 0.4 из 1.0 по ~100, рынок ушел до 103: перестановка за ним нарушила бы ограничитель 102
+        let guard = Some(102.0);
+        let replacement_price = calculate_limit_price(103.0, OrderSide::Buy, 0.001);
+
+        assert!(!breaches_price_guard(calculate_limit_price(100.0, OrderSide::Buy, 0.001), OrderSide::Buy, guard));
+        assert!(breaches_price_guard(replacement_price, OrderSide::Buy, guard));
+        assert!(!breaches_price_guard(replacement_price, OrderSide::Buy, None));
+        // Для продажи ограничитель — нижняя граница
+        assert!(breaches_price_guard(97.0, OrderSide::Sell, Some(98.0)));
+        assert!(!breaches_price_guard(99.0, OrderSide::Sell, Some(98.0)));
+
+        let err = price_guard_hit(1, HedgeStage::Spot, replacement_price, guard, 0.4, 1.0);
+        let hit = err.downcast_ref::<PriceGuardHit>().expect("guard error");
+        assert_eq!((hit.filled_qty, hit.target_qty, hit.guard_price), (0.4, 1.0, 102.0));
+    }
+
+    #[test]
+    fn overfill_is_kept_instead_of_clamped_to_target() {
+        // Цель 1.0, но ордер подогнан под minOrderQty 1.05 и исполнен полностью
+        let target = 1.0;
+        let filled = add_fill(add_fill(0.0, 0.6), 0.45);
+
+        assert!((filled - 1.05).abs() < 1e-12, "over-fill must not be discarded: {}", filled);
+        assert!((overfill_qty(filled, target, ORDER_FILL_TOLERANCE) - 0.05).abs() < 1e-12);
+        assert_eq!(overfill_qty(target + ORDER_FILL_TOLERANCE / 2.0, target, ORDER_FILL_TOLERANCE), 0.0);
+        assert_eq!(overfill_qty(0.9, target, ORDER_FILL_TOLERANCE), 0.0);
+        assert_eq!(add_fill(0.1, -0.2), 0.0);
+    }
+
+    #[test]
+    fn amend_only_when_order_covers_remaining_qty() {
+        let min_qty = Some(Decimal::new(1, 2)); // 0.01
+
+        assert!(is_price_only_change(1.0, 0.4, 0.6, min_qty, ORDER_FILL_TOLERANCE));
+        assert!(!is_price_only_change(1.0, 0.4, 0.5, min_qty, ORDER_FILL_TOLERANCE), "order smaller than remaining needs new qty");
+        assert!(!is_price_only_change(1.0, 0.995, 0.005, min_qty, ORDER_FILL_TOLERANCE), "remaining below min qty");
+    }
+
+    #[test]
+    fn fill_tolerance_follows_instrument_step() {
+        // Целые лоты: остаток 0.3 уже не выставить, остаток в целый лот — не исполнен
+        let whole_units = fill_tolerance_for_step(Some(Decimal::ONE));
+        assert_eq!(whole_units, 0.5);
+        assert!(0.3 < whole_units && 1.0 > whole_units);
+
+        // Шаг 1e-8: один неисполненный шаг не должен считаться исполнением
+        let fine = fill_tolerance_for_step(Some(Decimal::new(1, 8)));
+        assert!(fine < 0.00000001 && fine > 0.0);
+        assert!(fine < ORDER_FILL_TOLERANCE);
+
+        assert_eq!(fill_tolerance_for_step(None), ORDER_FILL_TOLERANCE);
+        assert_eq!(fill_tolerance_for_step(Some(Decimal::ZERO)), ORDER_FILL_TOLERANCE);
+    }
+
+    #[test]
+    fn target_reached_compares_at_instrument_step() {
+        let precision = QtyPrecision::new(Some(Decimal::new(1, 3))); // 0.001
+        // Исполнение с "хвостом" точности биржи вокруг округленной цели
+        assert!(precision.target_reached(0.0999999999, 0.1));
+        assert!(precision.target_reached(0.1000000001, 0.1));
+        assert!(precision.target_reached(0.1004, 0.1));
+        // Не хватает целого шага — цель не достигнута
+        assert!(!precision.target_reached(0.099, 0.1));
+        assert!(!precision.target_reached(0.0994, 0.1));
+        // Граница полушага округляется к ближайшему шагу
+        assert!(precision.target_reached(0.0996, 0.1));
+
+        let unknown = QtyPrecision::new(None);
+        assert!(unknown.target_reached(0.1 - 5e-9, 0.1));
+        assert!(!unknown.target_reached(0.0999, 0.1));
+        assert_eq!(unknown.tolerance(), ORDER_FILL_TOLERANCE);
+    }
+}
@@ -9,17 +9,26 @@ use tokio::time::sleep;
 use tracing::{error, info, warn};
 
 // --- ИСПРАВЛЕНО: manage_order_loop теперь возвращает Result<(f64, Option<String>)> ---
-use crate::hedger::common::{calculate_limit_price, manage_order_loop, OrderLoopParams};
+use crate::hedger::common::{
+    calculate_limit_price, fail_operation, finalize_with_retry, get_spot_price_with_retry, manage_order_loop, overfill_qty, qty_precision_for,
+    reference_price_or, write_with_retry, DbWriteRetry, OrderLoopParams, RetryBudget, SpotPriceRetry,
+};
+use crate::hedger::params::futures_qty_precision;
 use crate::hedger::{
-    HedgeParams, HedgeProgressCallback, HedgeProgressUpdate, HedgeStage, Hedger,
-    ORDER_FILL_TOLERANCE,
+    spawn_pending_futures_monitor, FuturesOrderLeftActive, HedgeOutcome, HedgeParams, HedgeProgressCallback, HedgeProgressUpdate, HedgeStage, Hedger,
+    MarketFillShortfall, PendingFuturesOrder, PriceGuardHit, SpotOnlyOrphan, ORDER_FILL_TOLERANCE,
 };
-use crate::exchange::types::{DetailedOrderStatus, OrderSide, OrderStatusText}; // Добавили OrderStatusText
+use crate::exchange::bybit::{leverage_for_step, linear_info_symbol};
+use crate::exchange::types::{DetailedOrderStatus, LeverageFilter, OrderSide, OrderStatusText}; // Добавили OrderStatusText
+use crate::config::LeverageMode;
 use crate::exchange::Exchange;
-use crate::storage::{update_hedge_final_status, update_hedge_spot_order, Db};
+use crate::storage::{
+    claim_spot_only_orphan, finalize_operation, LegFill, mark_hedge_pending_futures, mark_hedge_spot_only_orphan, update_hedge_spot_order, Db,
+    HedgeOperation, OperationStatus,
+};
 
 // Вспомогательная функция для округления ВНИЗ
-fn round_down_to_precision(value: f64, decimals: u32) -> Result<Decimal> {
+pub(super) fn round_down_to_precision(value: f64, decimals: u32) -> Result<Decimal> {
     // Конвертируем f64 в строку, затем в Decimal
     let decimal_value = Decimal::from_str(&value.to_string())
         .map_err(|e| anyhow!("Invalid f64 value for decimal conversion: {} ({})", value, e))?
@@ -31,6 +40,26 @@ fn round_down_to_precision(value: f64, decimals: u32) -> Result<Decimal> {
     Ok(decimal_value)
 }
 
+/// Накопленные цели срезов фьючерсной ноги (последняя — total). Срез округляется вниз до шага и не меньше
+/// минимального ордера; остаток меньше минимума добавляется к последнему срезу. None / 0 — один ордер на весь объем
+pub(super) fn futures_slice_targets(total: Decimal, slice_qty: Option<f64>, min_qty: Decimal, decimals: u32) -> Vec<Decimal> {
+    let slice = match slice_qty.filter(|q| *q > 0.0).and_then(|q| round_down_to_precision(q, decimals).ok()) {
+        Some(slice) => slice.max(min_qty),
+        None => return vec![total],
+    };
+    if slice <= Decimal::ZERO || slice >= total {
+        return vec![total];
+    }
+    let mut ends = Vec::new();
+    let mut end = slice;
+    while end < total && total - end >= min_qty {
+        ends.push(end);
+        end += slice;
+    }
+    ends.push(total);
+    ends
+}
+
 // Реализация основной логики хеджирования
 pub(super) async fn run_hedge_impl<ExchangeType>(
     hedger: &Hedger<ExchangeType>,
@@ -38,8 +67,9 @@ pub(super) async fn run_hedge_impl<ExchangeType>(
     mut progress_callback: HedgeProgressCallback,
     total_filled_spot_quantity_storage: Arc<TokioMutex<f64>>,
     operation_identifier: i64,
+    chat_id: i64,
     database: &Db,
-) -> Result<(f64, f64, f64)>
+) -> Result<HedgeOutcome>
 where
     ExchangeType: Exchange + Clone + Send + Sync + 'static,
 {
@@ -49,14 +79,29 @@ where
         current_spot_price,
         initial_limit_price: initial_spot_limit_price,
         symbol,
+        hedge_sum: _hedge_sum, // Показывается в превью; в БД пишется сумма из запроса
         spot_value: _estimated_spot_value, // Не используется напрямую, т.к. есть динамический расчет
         available_collateral,
+        borrow_required: _borrow_required, // Показывается только в превью подтверждения
+        estimated_liquidation_price: _estimated_liquidation_price, // Показывается только в превью подтверждения
         min_spot_qty_decimal: _min_spot_quantity_decimal, // Не используется напрямую
         min_fut_qty_decimal: min_futures_quantity_decimal,
         spot_decimals: _spot_quantity_decimals, // Не используется напрямую
         fut_decimals: futures_quantity_decimals,
         futures_symbol,
+        spot_price_guard,
+        futures_only,
     } = params;
+    // Бюджет повторов общий для спотовой и фьючерсной ноги
+    let retry_budget = RetryBudget::from_config(&hedger.config);
+
+    // При заданном price_source начальная лимитка спота считается от выбранной опорной цены
+    let initial_spot_limit_price = if hedger.config.price_source.is_some() {
+        let reference_price = reference_price_or(hedger, &symbol, true, current_spot_price).await;
+        calculate_limit_price(reference_price, OrderSide::Buy, hedger.config.slippage_for(&symbol))
+    } else {
+        initial_spot_limit_price
+    };
 
     info!(
         "op_id:{}: Running hedge for {} with spot target={:.8}, initial futures estimate={:.8}",
@@ -69,8 +114,7 @@ where
     if available_collateral <= 0.0 {
         let error_message = "Available collateral is non-positive".to_string();
         error!("op_id:{}: {}. Aborting.", operation_identifier, error_message);
-        let _ = update_hedge_final_status(database, operation_identifier, "Failed", None, 0.0, Some(&error_message)).await;
-        return Err(anyhow!(error_message));
+        return Err(fail_operation(database, DbWriteRetry::from_config(&hedger.config), operation_identifier, None, LegFill::NONE, &error_message, anyhow!(error_message.clone())).await);
     }
     let required_leverage = futures_position_value_estimate / available_collateral;
     info!(
@@ -80,8 +124,7 @@ where
     if required_leverage.is_nan() || required_leverage.is_infinite() || required_leverage <= 0.0 {
         let error_message = format!("Invalid required leverage calculation: {}", required_leverage);
         error!("op_id:{}: {}. Aborting.", operation_identifier, error_message);
-        let _ = update_hedge_final_status(database, operation_identifier, "Failed", None, 0.0, Some(&error_message)).await;
-        return Err(anyhow!(error_message));
+        return Err(fail_operation(database, DbWriteRetry::from_config(&hedger.config), operation_identifier, None, LegFill::NONE, &error_message, anyhow!(error_message.clone())).await);
     }
 
     // Используем futures_symbol для установки плеча
@@ -94,9 +137,23 @@ where
     }
     // --- Конец проверки плеча ---
 
+    // --- Хедж только фьючерсом: спот хранится вне бота (плечо выше уже проверено и выставлено) ---
+    if futures_only {
+        let stage_context = FuturesStageContext { hedger, database, operation_identifier, chat_id, futures_symbol: &futures_symbol };
+        return run_futures_only_impl(
+            &stage_context,
+            progress_callback,
+            _initial_futures_quantity,
+            current_spot_price,
+            min_futures_quantity_decimal,
+        )
+        .await;
+    }
+
     // --- Этап 1: Спот ---
     info!("op_id:{}: Starting SPOT buy stage...", operation_identifier);
     *total_filled_spot_quantity_storage.lock().await = 0.0; // Сбрасываем счетчик перед циклом
+    let spot_qty_precision = qty_precision_for(hedger, &symbol, true).await;
 
     let spot_loop_params = OrderLoopParams {
         hedger,
@@ -111,6 +168,13 @@ where
         is_spot: true,
         min_order_qty_decimal: None, // Минимальный размер проверяется внутри цикла
         total_filled_qty_storage: Arc::clone(&total_filled_spot_quantity_storage), // Передаем хранилище общего кол-ва
+        keep_order_on_timeout: false,
+        order_type: hedger.config.spot_order_type,
+        retry_budget: retry_budget.clone(),
+        qty_precision: spot_qty_precision,
+        futures_recovery_base: None,
+        futures_recovery_target: None,
+        price_guard: spot_price_guard,
     };
 
     let (final_spot_quantity_gross, last_spot_order_id_option) = match manage_order_loop(spot_loop_params).await {
@@ -147,16 +211,18 @@ where
                 _ => {
                     let error_message = "Spot order loop succeeded but failed to return order ID.".to_string();
                     error!("op_id:{}: {}", operation_identifier, error_message);
-                    let _ = update_hedge_final_status(
+                    // Объем спота пишется в спотовую ногу, фьючерс не выставлялся
+                    let spot = LegFill { order_id: None, filled_qty: filled_quantity };
+                    return Err(fail_operation(
                         database,
+                        DbWriteRetry::from_config(&hedger.config),
                         operation_identifier,
-                        "Failed",
-                        None,
-                        filled_quantity,
-                        Some(&error_message),
+                        Some(spot),
+                        LegFill::NONE,
+                        &error_message,
+                        anyhow!(error_message.clone()),
                     )
-                    .await;
-                    return Err(anyhow!(error_message));
+                    .await);
                 }
             }
         }
@@ -166,26 +232,44 @@ where
                 operation_identifier, loop_error
             );
             let current_filled_quantity = *total_filled_spot_quantity_storage.lock().await;
-            let _ = update_hedge_final_status(
+            // Ограничитель или частичный рыночный ордер остановили покупку: купленный спот не захеджирован
+            let partial_halt = match (loop_error.downcast_ref::<PriceGuardHit>(), loop_error.downcast_ref::<MarketFillShortfall>()) {
+                (Some(guard_hit), _) => Some(format!("Spot buy halted by price guard: {}", guard_hit)),
+                (None, Some(shortfall)) => Some(format!("Spot buy incomplete: {}", shortfall)),
+                (None, None) => None,
+            };
+            if let Some(reason) = partial_halt {
+                if current_filled_quantity > ORDER_FILL_TOLERANCE {
+                    if let Err(e) = update_hedge_spot_order(database, operation_identifier, None, current_filled_quantity).await {
+                        error!("op_id:{}: Failed to persist spot fill before partial halt: {}", operation_identifier, e);
+                    }
+                    return Err(spot_only_orphan(database, DbWriteRetry::from_config(&hedger.config), operation_identifier, current_filled_quantity, 0.0, reason).await);
+                }
+            }
+            let spot = LegFill { order_id: None, filled_qty: current_filled_quantity };
+            return Err(fail_operation(
                 database,
+                DbWriteRetry::from_config(&hedger.config),
                 operation_identifier,
-                "Failed",
-                None,
-                current_filled_quantity,
-                Some(&format!("Spot stage failed: {}", loop_error)),
+                Some(spot),
+                LegFill::NONE,
+                &format!("Spot stage failed: {}", loop_error),
+                loop_error,
             )
-            .await;
-            return Err(loop_error);
+            .await);
         }
     };
 
+    // Спот куплен: дальше отмена может оставить спот и бросить только фьючерсную ногу
+    *hedger.stage.lock().await = HedgeStage::Futures;
+
     let final_spot_order_id = match last_spot_order_id_option {
         Some(id) => id,
         None => {
              error!("op_id:{}: Critical error - last spot order identifier is None after spot stage completion.", operation_identifier);
              let error_message = "Failed to retrieve last spot order ID internally".to_string();
-              let _ = update_hedge_final_status(database, operation_identifier, "Failed", None, final_spot_quantity_gross, Some(&error_message)).await;
-             return Err(anyhow!(error_message));
+             let spot = LegFill { order_id: None, filled_qty: final_spot_quantity_gross };
+             return Err(fail_operation(database, DbWriteRetry::from_config(&hedger.config), operation_identifier, Some(spot), LegFill::NONE, &error_message, anyhow!(error_message.clone())).await);
         }
     };
 
@@ -223,17 +307,27 @@ where
         );
      }
 
+    // Перекуп не отбрасывается: фьючерс ниже рассчитывается по стоимости всего купленного спота
+    let spot_overfill = overfill_qty(final_spot_quantity_gross, initial_spot_quantity, spot_qty_precision.tolerance());
+    if spot_overfill > 0.0 {
+        warn!(
+            "op_id:{}: Spot over-filled by {:.8} (bought {:.8}, target {:.8}). Futures leg will cover the actual spot.",
+            operation_identifier, spot_overfill, final_spot_quantity_gross, initial_spot_quantity
+        );
+    }
+
     // --- ИСПРАВЛЕНО: Вычисление actual_spot_value ---
     // Используем ОБЩЕЕ исполненное количество из цикла и СРЕДНЮЮ цену из деталей ПОСЛЕДНЕГО ордера (как приближение)
     // или запасную текущую цену.
-    let avg_price_for_value_calc = if detailed_spot_status.average_price > 0.0 {
+    let used_price_fallback = detailed_spot_status.average_price <= 0.0;
+    let avg_price_for_value_calc = if !used_price_fallback {
         detailed_spot_status.average_price
     } else {
         warn!("op_id:{}: Average price from details of last order {} was zero or unavailable. Using current spot price as fallback for value calculation.", operation_identifier, final_spot_order_id);
         // Получаем текущую цену снова как запасной вариант
-        match hedger.exchange.get_spot_price(&symbol).await {
-             Ok(price) if price > 0.0 => price,
-             _ => {
+        match get_spot_price_with_retry(&hedger.exchange, &symbol, SpotPriceRetry::from_config(&hedger.config).with_budget(&retry_budget)).await {
+             Ok(price) => price,
+             Err(_) => {
                  error!("op_id:{}: Failed to get fallback spot price. Using initial price from params.", operation_identifier);
                  current_spot_price // Запасной вариант - цена из параметров, если текущая не получена
              }
@@ -250,8 +344,7 @@ where
              actual_spot_value, final_spot_quantity_gross, avg_price_for_value_calc
          );
          error!("op_id:{}: {}", operation_identifier, error_message);
-         let _ = update_hedge_final_status(database, operation_identifier, "Failed", Some(&final_spot_order_id), final_spot_quantity_gross, Some(&error_message)).await;
-         return Err(anyhow!(error_message));
+         return Err(spot_only_orphan(database, DbWriteRetry::from_config(&hedger.config), operation_identifier, final_spot_quantity_gross, 0.0, error_message).await);
      }
     info!("op_id:{}: Estimated actual executed spot value: {:.8} (Total Qty: {:.8}, Avg Price Used: {:.8})", // Уточнили лог
         operation_identifier, actual_spot_value, final_spot_quantity_gross, avg_price_for_value_calc);
@@ -287,16 +380,14 @@ where
          Err(error) => {
               error!("op_id:{}: Failed get futures ticker for dynamic quantity calculation: {}. Aborting futures stage.", operation_identifier, error);
               let error_message = format!("Failed get futures ticker: {}", error);
-              let _ = update_hedge_final_status(database, operation_identifier, "Failed", Some(&final_spot_order_id), final_spot_quantity_gross, Some(&error_message)).await;
-              return Err(anyhow!(error_message));
+              return Err(spot_only_orphan(database, DbWriteRetry::from_config(&hedger.config), operation_identifier, final_spot_quantity_gross, 0.0, error_message).await);
          }
     };
     let futures_price_now = (futures_ticker.bid_price + futures_ticker.ask_price) / 2.0;
     if futures_price_now <= 0.0 {
          let error_message = format!("Invalid futures price for calculation: {:.2}", futures_price_now);
          error!("op_id:{}: {}", operation_identifier, error_message);
-         let _ = update_hedge_final_status(database, operation_identifier, "Failed", Some(&final_spot_order_id), final_spot_quantity_gross, Some(&error_message)).await;
-         return Err(anyhow!(error_message));
+         return Err(spot_only_orphan(database, DbWriteRetry::from_config(&hedger.config), operation_identifier, final_spot_quantity_gross, 0.0, error_message).await);
     }
 
     let dynamic_futures_quantity = actual_spot_value / futures_price_now; // Используем исправленное значение
@@ -309,16 +400,14 @@ where
         Err(error) => {
             error!("op_id:{}: Failed to round dynamic futures quantity: {}", operation_identifier, error);
             let error_message = format!("Failed to round fut qty: {}", error);
-             let _ = update_hedge_final_status(database, operation_identifier, "Failed", Some(&final_spot_order_id), final_spot_quantity_gross, Some(&error_message)).await;
-            return Err(anyhow!(error_message));
+             return Err(spot_only_orphan(database, DbWriteRetry::from_config(&hedger.config), operation_identifier, final_spot_quantity_gross, 0.0, error_message).await);
         }
     };
 
     if rounded_dynamic_futures_quantity_decimal <= Decimal::ZERO {
          let error_message = format!("Rounded dynamic futures quantity is zero or negative: {}", rounded_dynamic_futures_quantity_decimal);
          error!("op_id:{}: {}", operation_identifier, error_message);
-         let _ = update_hedge_final_status(database, operation_identifier, "Failed", Some(&final_spot_order_id), final_spot_quantity_gross, Some(&error_message)).await;
-         return Err(anyhow!(error_message));
+         return Err(spot_only_orphan(database, DbWriteRetry::from_config(&hedger.config), operation_identifier, final_spot_quantity_gross, 0.0, error_message).await);
     }
 
     if rounded_dynamic_futures_quantity_decimal < min_futures_quantity_decimal {
@@ -327,8 +416,7 @@ where
             rounded_dynamic_futures_quantity_decimal, min_futures_quantity_decimal
          );
          error!("op_id:{}: {}", operation_identifier, error_message);
-         let _ = update_hedge_final_status(database, operation_identifier, "Failed", Some(&final_spot_order_id), final_spot_quantity_gross, Some(&error_message)).await;
-         return Err(anyhow!(error_message));
+         return Err(spot_only_orphan(database, DbWriteRetry::from_config(&hedger.config), operation_identifier, final_spot_quantity_gross, 0.0, error_message).await);
     }
 
     let final_futures_target_quantity = match rounded_dynamic_futures_quantity_decimal.to_f64() {
@@ -336,8 +424,7 @@ where
         None => {
             let error_message = format!("Failed to convert final futures decimal {} back to f64", rounded_dynamic_futures_quantity_decimal);
              error!("op_id:{}: {}", operation_identifier, error_message);
-            let _ = update_hedge_final_status(database, operation_identifier, "Failed", Some(&final_spot_order_id), final_spot_quantity_gross, Some(&error_message)).await;
-            return Err(anyhow!(error_message));
+            return Err(spot_only_orphan(database, DbWriteRetry::from_config(&hedger.config), operation_identifier, final_spot_quantity_gross, 0.0, error_message).await);
         }
     };
 
@@ -348,75 +435,117 @@ where
     info!("op_id:{}: Starting FUTURES sell stage with dynamic quantity {:.8}...", operation_identifier, final_futures_target_quantity);
     let futures_filled_storage = Arc::new(TokioMutex::new(0.0));
     // Используем config для доступа к slippage
+    let futures_reference_price = reference_price_or(hedger, &futures_symbol, false, futures_price_now).await;
     let futures_initial_limit_price =
-        calculate_limit_price(futures_price_now, OrderSide::Sell, hedger.config.slippage);
-
-    let futures_loop_params = OrderLoopParams {
-        hedger,
-        db: database,
-        operation_id: operation_identifier,
-        symbol: &futures_symbol,
-        side: OrderSide::Sell,
-        initial_target_qty: final_futures_target_quantity,
-        initial_limit_price: futures_initial_limit_price,
-        progress_callback: &mut progress_callback,
-        stage: HedgeStage::Futures,
-        is_spot: false,
-        min_order_qty_decimal: Some(min_futures_quantity_decimal),
-        total_filled_qty_storage: futures_filled_storage.clone(),
-    };
+        calculate_limit_price(futures_reference_price, OrderSide::Sell, hedger.config.slippage_for(&futures_symbol));
 
-    let (final_futures_quantity, _last_futures_order_id_option) = match manage_order_loop(futures_loop_params).await {
-        Ok((filled_quantity, last_order_id_opt)) => {
+    // Айсберг (futures_slice_qty): фьючерс выставляется срезами подряд, каждый следующий — после исполнения предыдущего
+    let futures_slice_ends = futures_slice_targets(
+        rounded_dynamic_futures_quantity_decimal,
+        hedger.config.futures_slice_qty,
+        min_futures_quantity_decimal,
+        futures_quantity_decimals,
+    );
+    let futures_slice_count = futures_slice_ends.len();
+    let mut final_futures_quantity = 0.0;
+    let mut last_futures_order_id: Option<String> = None;
+    for (slice_index, slice_end) in futures_slice_ends.iter().enumerate() {
+        let is_last_slice = slice_index + 1 == futures_slice_count;
+        let slice_target_quantity = if is_last_slice { final_futures_target_quantity } else { slice_end.to_f64().unwrap_or(final_futures_target_quantity) };
+        let slice_limit_price = if slice_index == 0 {
+            futures_initial_limit_price
+        } else {
+            let slice_reference_price = reference_price_or(hedger, &futures_symbol, false, futures_price_now).await;
+            calculate_limit_price(slice_reference_price, OrderSide::Sell, hedger.config.slippage_for(&futures_symbol))
+        };
+        if futures_slice_count > 1 {
             info!(
-                "op_id:{}: Hedge FUTURES sell stage finished. Final actual futures net quantity: {:.8}",
-                operation_identifier, filled_quantity
+                "op_id:{}: Placing futures slice {}/{} up to {:.8} of {:.8}",
+                operation_identifier, slice_index + 1, futures_slice_count, slice_target_quantity, final_futures_target_quantity
             );
-             if (filled_quantity - final_futures_target_quantity).abs() > ORDER_FILL_TOLERANCE * 10.0 {
-                warn!(
-                    "op_id:{}: Final FUTURES net filled {:.8} significantly differs from dynamic target {:.8}. Using actual filled.",
-                    operation_identifier, filled_quantity, final_futures_target_quantity
+        }
+
+        let futures_loop_params = OrderLoopParams {
+            hedger,
+            db: database,
+            operation_id: operation_identifier,
+            symbol: &futures_symbol,
+            side: OrderSide::Sell,
+            initial_target_qty: slice_target_quantity,
+            initial_limit_price: slice_limit_price,
+            progress_callback: &mut progress_callback,
+            stage: HedgeStage::Futures,
+            is_spot: false,
+            min_order_qty_decimal: Some(min_futures_quantity_decimal),
+            total_filled_qty_storage: futures_filled_storage.clone(),
+            // Оставить на бирже можно только последний срез: иначе следующие срезы не будут выставлены
+            keep_order_on_timeout: is_last_slice && !hedger.config.cancel_futures_on_timeout,
+            order_type: hedger.config.futures_order_type,
+            retry_budget: retry_budget.clone(),
+            qty_precision: qty_precision_for(hedger, &futures_symbol, false).await,
+            futures_recovery_base: Some(0.0),
+            futures_recovery_target: Some(final_futures_target_quantity),
+            price_guard: None,
+        };
+
+        final_futures_quantity = match manage_order_loop(futures_loop_params).await {
+            Ok((filled_quantity, last_order_id_option)) => {
+                if last_order_id_option.is_some() {
+                    last_futures_order_id = last_order_id_option;
+                }
+                info!(
+                    "op_id:{}: Hedge FUTURES sell stage finished. Final actual futures net quantity: {:.8}",
+                    operation_identifier, filled_quantity
                 );
+                 if (filled_quantity - slice_target_quantity).abs() > ORDER_FILL_TOLERANCE * 10.0 {
+                    warn!(
+                        "op_id:{}: Final FUTURES net filled {:.8} significantly differs from dynamic target {:.8}. Using actual filled.",
+                        operation_identifier, filled_quantity, slice_target_quantity
+                    );
+                }
+                filled_quantity
             }
-            (filled_quantity, last_order_id_opt)
-        }
-        Err(loop_error) => {
-            error!(
-                "op_id:{}: Hedge FUTURES sell stage failed: {}",
-                operation_identifier, loop_error
-            );
-            let last_futures_filled_quantity = *futures_filled_storage.lock().await;
-            let _ = update_hedge_final_status(
-                database,
-                operation_identifier,
-                "Failed",
-                Some(&final_spot_order_id),
-                final_spot_quantity_gross,
-                Some(&format!("Futures stage failed: {}", loop_error)),
-                // None, // futures_order_id_on_fail
-                // Some(last_futures_filled_quantity), // futures_filled_qty_on_fail
-            )
-            .await;
-            return Err(loop_error);
-        }
-    };
+            Err(loop_error) => {
+                // --- Фьючерсный ордер оставлен на бирже (cancel_futures_on_timeout = false) ---
+                if let Some(left_active) = loop_error.downcast_ref::<FuturesOrderLeftActive>() {
+                    let stage_context = FuturesStageContext { hedger, database, operation_identifier, chat_id, futures_symbol: &futures_symbol };
+                    leave_futures_order_pending(&stage_context, &left_active.order_id, left_active.base_filled_qty, left_active.target_qty).await;
+                    return Err(loop_error);
+                }
+                error!(
+                    "op_id:{}: Hedge FUTURES sell stage failed: {}",
+                    operation_identifier, loop_error
+                );
+                let last_futures_filled_quantity = *futures_filled_storage.lock().await;
+                return Err(spot_only_orphan(
+                    database,
+                    DbWriteRetry::from_config(&hedger.config),
+                    operation_identifier,
+                    final_spot_quantity_gross,
+                    last_futures_filled_quantity,
+                    format!("Futures stage failed: {}", loop_error),
+                )
+                .await);
+            }
+        };
+    }
 
     // --- Успешное завершение ---
     info!(
         "op_id:{}: Hedge completed successfully. Spot Gross: {:.8}, Fut Net: {:.8}, Actual Spot Value: {:.8}",
         operation_identifier, final_spot_quantity_gross, final_futures_quantity, actual_spot_value
     );
-    let _ = update_hedge_final_status(
-        database,
-        operation_identifier,
-        "Completed",
-        Some(&final_spot_order_id),
-        final_spot_quantity_gross,
-        None,
-        // _last_futures_order_id_option.as_deref(),
-        // Some(final_futures_quantity),
-    )
-    .await;
+    // Статус и объемы пишутся одной транзакцией: сбой посередине не оставит операцию полузавершенной
+    // Запись не теряется молча: после повторов ошибка уходит наверх и поднимает алерт
+    let finalized = write_with_retry(DbWriteRetry::from_config(&hedger.config), operation_identifier, "finalize Completed", || {
+        let spot = LegFill { order_id: Some(&final_spot_order_id), filled_qty: final_spot_quantity_gross };
+        let futures = LegFill { order_id: last_futures_order_id.as_deref(), filled_qty: final_futures_quantity };
+        finalize_operation(database, operation_identifier, OperationStatus::Completed, Some(spot), futures, None)
+    })
+    .await?;
+    if !finalized {
+        warn!("op_id:{}: Operation was no longer Running when finalizing as Completed.", operation_identifier);
+    }
 
     // --- Отправляем финальный колбэк для фьючерса (100%) ---
      let futures_price_for_callback = match hedger.exchange.get_futures_ticker(&futures_symbol).await {
@@ -440,10 +569,398 @@ where
     }
     // --- Конец колбэка фьючерса ---
 
-    Ok((final_spot_quantity_gross, final_futures_quantity, actual_spot_value))
+    Ok(HedgeOutcome {
+        spot_filled: final_spot_quantity_gross,
+        spot_overfill,
+        fut_filled: final_futures_quantity,
+        spot_value: actual_spot_value,
+        avg_spot_price: avg_price_for_value_calc,
+        avg_fut_price: None, // Детали исполнения фьючерсных ордеров не запрашиваются
+        used_fallback: used_price_fallback,
+    })
+}
+
+/// Операция и фьючерсный контракт, общие для этапов работы с фьючерсной ногой
+struct FuturesStageContext<'a, ExchangeType> {
+    hedger: &'a Hedger<ExchangeType>,
+    database: &'a Db,
+    operation_identifier: i64,
+    chat_id: i64, // Чат операции (монитор оставленного ордера виден в его /watchers)
+    futures_symbol: &'a str,
+}
+
+/// Хедж только фьючерсом: шорт на заданный объем без покупки спота (spot_filled_qty остается 0)
+async fn run_futures_only_impl<ExchangeType>(
+    stage_context: &FuturesStageContext<'_, ExchangeType>,
+    mut progress_callback: HedgeProgressCallback,
+    target_quantity: f64,
+    current_spot_price: f64,
+    min_futures_quantity_decimal: Decimal,
+) -> Result<HedgeOutcome>
+where
+    ExchangeType: Exchange + Clone + Send + Sync + 'static,
+{
+    let &FuturesStageContext { hedger, database, operation_identifier, futures_symbol, .. } = stage_context;
+    info!(
+        "op_id:{}: Futures-only hedge: skipping spot leg, shorting {:.8} {}",
+        operation_identifier, target_quantity, futures_symbol
+    );
+    *hedger.stage.lock().await = HedgeStage::Futures;
+    let futures_price_now = match hedger.exchange.get_futures_ticker(futures_symbol).await {
+        Ok(ticker) if ticker.bid_price > 0.0 && ticker.ask_price > 0.0 => (ticker.bid_price + ticker.ask_price) / 2.0,
+        Ok(_) | Err(_) => {
+            warn!("op_id:{}: Futures ticker unavailable, using spot price {:.8} as reference.", operation_identifier, current_spot_price);
+            current_spot_price
+        }
+    };
+    let futures_reference_price = reference_price_or(hedger, futures_symbol, false, futures_price_now).await;
+    let futures_initial_limit_price =
+        calculate_limit_price(futures_reference_price, OrderSide::Sell, hedger.config.slippage_for(futures_symbol));
+
+    let futures_filled_storage = Arc::new(TokioMutex::new(0.0));
+    let futures_loop_params = OrderLoopParams {
+        hedger,
+        db: database,
+        operation_id: operation_identifier,
+        symbol: futures_symbol,
+        side: OrderSide::Sell,
+        initial_target_qty: target_quantity,
+        initial_limit_price: futures_initial_limit_price,
+        progress_callback: &mut progress_callback,
+        stage: HedgeStage::Futures,
+        is_spot: false,
+        min_order_qty_decimal: Some(min_futures_quantity_decimal),
+        total_filled_qty_storage: futures_filled_storage.clone(),
+        keep_order_on_timeout: !hedger.config.cancel_futures_on_timeout,
+        order_type: hedger.config.futures_order_type,
+        retry_budget: RetryBudget::from_config(&hedger.config),
+        qty_precision: qty_precision_for(hedger, futures_symbol, false).await,
+        futures_recovery_base: Some(0.0),
+        futures_recovery_target: None,
+        price_guard: None,
+    };
+
+    let (final_futures_quantity, last_futures_order_id) = match manage_order_loop(futures_loop_params).await {
+        Ok(result) => result,
+        Err(loop_error) => {
+            if let Some(left_active) = loop_error.downcast_ref::<FuturesOrderLeftActive>() {
+                leave_futures_order_pending(stage_context, &left_active.order_id, left_active.base_filled_qty, left_active.target_qty).await;
+                return Err(loop_error);
+            }
+            // Спот не покупался — незахеджированного спота нет, операция просто неуспешна
+            error!("op_id:{}: Futures-only hedge stage failed: {}", operation_identifier, loop_error);
+            let filled_quantity = *futures_filled_storage.lock().await;
+            return Err(fail_operation(
+                database,
+                DbWriteRetry::from_config(&hedger.config),
+                operation_identifier,
+                None,
+                LegFill { order_id: None, filled_qty: filled_quantity },
+                &format!("Futures stage failed: {}", loop_error),
+                loop_error,
+            )
+            .await);
+        }
+    };
+
+    info!(
+        "op_id:{}: Futures-only hedge completed. Fut Net: {:.8}",
+        operation_identifier, final_futures_quantity
+    );
+    let finalized = write_with_retry(DbWriteRetry::from_config(&hedger.config), operation_identifier, "finalize Completed", || {
+        let futures = LegFill { order_id: last_futures_order_id.as_deref(), filled_qty: final_futures_quantity };
+        finalize_operation(database, operation_identifier, OperationStatus::Completed, None, futures, None)
+    })
+    .await?;
+    if !finalized {
+        warn!("op_id:{}: Operation was no longer Running when finalizing as Completed.", operation_identifier);
+    }
+
+    Ok(HedgeOutcome {
+        spot_filled: 0.0,
+        spot_overfill: 0.0,
+        fut_filled: final_futures_quantity,
+        spot_value: final_futures_quantity * current_spot_price, // Стоимость внешнего спота, закрытого шортом
+        avg_spot_price: current_spot_price,
+        avg_fut_price: None,
+        used_fallback: false,
+    })
+}
+
+/// Довыставление фьючерсной ноги для операции SpotOnlyOrphan: спот уже куплен, шортим недостающий объем
+pub(super) async fn resume_futures_leg_impl<ExchangeType>(
+    hedger: &Hedger<ExchangeType>,
+    operation: HedgeOperation,
+    mut progress_callback: HedgeProgressCallback,
+    database: &Db,
+) -> Result<f64>
+where
+    ExchangeType: Exchange + Clone + Send + Sync + 'static,
+{
+    let operation_identifier = operation.id;
+    let chat_id = operation.chat_id;
+    let futures_symbol = format!("{}{}", operation.base_symbol, hedger.quote_currency);
+    let already_filled_quantity = operation.futures_filled_qty;
+    let remaining_quantity = operation.spot_filled_qty - already_filled_quantity;
+    info!(
+        "op_id:{}: Resuming futures leg for {}: spot filled {:.8}, futures filled {:.8}, remaining {:.8}",
+        operation_identifier, futures_symbol, operation.spot_filled_qty, already_filled_quantity, remaining_quantity
+    );
+
+    // --- Проверки до захвата операции: при ошибке она остается в SpotOnlyOrphan ---
+    let linear_info = hedger
+        .exchange
+        .get_linear_instrument_info(&operation.base_symbol)
+        .await
+        .map_err(|e| anyhow!("Failed to get LINEAR instrument info: {}", e))?;
+    let (futures_quantity_decimals, min_futures_quantity_decimal) = futures_qty_precision(&linear_info)?;
+    let target_quantity_decimal = round_down_to_precision(remaining_quantity.max(0.0), futures_quantity_decimals)?;
+    if target_quantity_decimal <= Decimal::ZERO || target_quantity_decimal < min_futures_quantity_decimal {
+        return Err(anyhow!(
+            "Remaining futures quantity {} is less than minimum order size {}",
+            target_quantity_decimal, min_futures_quantity_decimal
+        ));
+    }
+    let target_quantity = target_quantity_decimal
+        .to_f64()
+        .ok_or_else(|| anyhow!("Failed to convert futures decimal {} back to f64", target_quantity_decimal))?;
+
+    if !claim_spot_only_orphan(database, DbWriteRetry::from_config(&hedger.config), operation_identifier).await? {
+        return Err(anyhow!("Operation is no longer waiting for the futures leg"));
+    }
+
+    let futures_price_now = match hedger.exchange.get_futures_ticker(&futures_symbol).await {
+        Ok(ticker) if ticker.bid_price > 0.0 && ticker.ask_price > 0.0 => (ticker.bid_price + ticker.ask_price) / 2.0,
+        Ok(ticker) => {
+            let error_message = format!("Invalid futures price: bid {:.8}, ask {:.8}", ticker.bid_price, ticker.ask_price);
+            return Err(spot_only_orphan(database, DbWriteRetry::from_config(&hedger.config), operation_identifier, operation.spot_filled_qty, already_filled_quantity, error_message).await);
+        }
+        Err(error) => {
+            let error_message = format!("Failed get futures ticker: {}", error);
+            return Err(spot_only_orphan(database, DbWriteRetry::from_config(&hedger.config), operation_identifier, operation.spot_filled_qty, already_filled_quantity, error_message).await);
+        }
+    };
+    let futures_reference_price = reference_price_or(hedger, &futures_symbol, false, futures_price_now).await;
+    let futures_initial_limit_price =
+        calculate_limit_price(futures_reference_price, OrderSide::Sell, hedger.config.slippage_for(&futures_symbol));
+
+    let futures_filled_storage = Arc::new(TokioMutex::new(0.0));
+    let futures_loop_params = OrderLoopParams {
+        hedger,
+        db: database,
+        operation_id: operation_identifier,
+        symbol: &futures_symbol,
+        side: OrderSide::Sell,
+        initial_target_qty: target_quantity,
+        initial_limit_price: futures_initial_limit_price,
+        progress_callback: &mut progress_callback,
+        stage: HedgeStage::Futures,
+        is_spot: false,
+        min_order_qty_decimal: Some(min_futures_quantity_decimal),
+        total_filled_qty_storage: futures_filled_storage.clone(),
+        keep_order_on_timeout: !hedger.config.cancel_futures_on_timeout,
+        order_type: hedger.config.futures_order_type,
+        retry_budget: RetryBudget::from_config(&hedger.config),
+        qty_precision: qty_precision_for(hedger, &futures_symbol, false).await,
+        futures_recovery_base: Some(already_filled_quantity),
+        futures_recovery_target: None,
+        price_guard: None,
+    };
+
+    match manage_order_loop(futures_loop_params).await {
+        Ok((filled_quantity, last_order_id_opt)) => {
+            let total_futures_quantity = already_filled_quantity + filled_quantity;
+            info!(
+                "op_id:{}: Futures leg resumed successfully. Futures filled now {:.8}, total {:.8}",
+                operation_identifier, filled_quantity, total_futures_quantity
+            );
+            let futures = LegFill { order_id: last_order_id_opt.as_deref(), filled_qty: total_futures_quantity };
+            let finalized = finalize_with_retry(
+                database,
+                DbWriteRetry::from_config(&hedger.config),
+                operation_identifier,
+                OperationStatus::Completed,
+                None,
+                futures,
+                None,
+            )
+            .await?;
+            if !finalized {
+                warn!("op_id:{}: Operation was no longer Running when finalizing resumed futures leg.", operation_identifier);
+            }
+            Ok(total_futures_quantity)
+        }
+        Err(loop_error) => {
+            if let Some(left_active) = loop_error.downcast_ref::<FuturesOrderLeftActive>() {
+                let stage_context = FuturesStageContext { hedger, database, operation_identifier, chat_id, futures_symbol: &futures_symbol };
+                leave_futures_order_pending(
+                    &stage_context,
+                    &left_active.order_id,
+                    already_filled_quantity + left_active.base_filled_qty,
+                    already_filled_quantity + left_active.target_qty,
+                )
+                .await;
+                return Err(loop_error);
+            }
+            let filled_quantity = *futures_filled_storage.lock().await;
+            Err(spot_only_orphan(
+                database,
+                DbWriteRetry::from_config(&hedger.config),
+                operation_identifier,
+                operation.spot_filled_qty,
+                already_filled_quantity + filled_quantity,
+                format!("Futures leg resume failed: {}", loop_error),
+            )
+            .await)
+        }
+    }
+}
+
+/// Спот куплен, но фьючерсная нога не выставлена: статус SpotOnlyOrphan вместо Failed
+async fn spot_only_orphan(
+    database: &Db,
+    retry: DbWriteRetry,
+    operation_identifier: i64,
+    spot_filled_quantity: f64,
+    futures_filled_quantity: f64,
+    reason: String,
+) -> anyhow::Error {
+    error!(
+        "op_id:{}: Spot leg filled ({:.8}) but futures leg failed ({:.8} filled). Position is NOT hedged: {}",
+        operation_identifier, spot_filled_quantity, futures_filled_quantity, reason
+    );
+    let marked = write_with_retry(retry, operation_identifier, "mark SpotOnlyOrphan", || {
+        mark_hedge_spot_only_orphan(database, operation_identifier, futures_filled_quantity, &reason)
+    })
+    .await;
+    let orphan = SpotOnlyOrphan {
+        spot_filled_qty: spot_filled_quantity,
+        futures_filled_qty: futures_filled_quantity,
+        reason,
+    };
+    // Запись не удалась: DbRecordDiverged, а SpotOnlyOrphan остается доступным вызывающему через контекст
+    match marked {
+        Ok(()) => orphan.into(),
+        Err(diverged) => diverged.context(orphan),
+    }
+}
+
+/// Фьючерсный ордер оставлен на бирже по таймауту: статус PendingFutures и фоновое наблюдение
+async fn leave_futures_order_pending<ExchangeType>(
+    stage_context: &FuturesStageContext<'_, ExchangeType>,
+    order_id: &str,
+    base_filled_quantity: f64,
+    target_quantity: f64,
+) where
+    ExchangeType: Exchange + Clone + Send + Sync + 'static,
+{
+    let &FuturesStageContext { hedger, database, operation_identifier, chat_id, futures_symbol } = stage_context;
+    warn!(
+        "op_id:{}: Futures order {} left active. Marking operation as PendingFutures.",
+        operation_identifier, order_id
+    );
+    if let Err(db_error) =
+        mark_hedge_pending_futures(database, operation_identifier, order_id, base_filled_quantity, target_quantity).await
+    {
+        error!("op_id:{}: Failed to mark operation as PendingFutures: {}", operation_identifier, db_error);
+    }
+    let order = PendingFuturesOrder {
+        operation_id: operation_identifier,
+        futures_symbol: futures_symbol.to_string(),
+        quote_currency: hedger.quote_currency.clone(),
+        order_id: order_id.to_string(),
+        base_filled_qty: base_filled_quantity,
+        target_qty: target_quantity,
+    };
+    spawn_pending_futures_monitor(hedger.exchange.clone(), database.clone(), &hedger.watchers, chat_id, order);
 }
 
-// Вспомогательная функция для установки плеча
+/// Что сделать с плечом перед открытием хеджа
+#[derive(Debug, Clone, PartialEq)]
+pub enum LeverageAction {
+    Set(f64),       // Выставить плечо под операцию (режим computed)
+    Keep,           // Текущее плечо подходит
+    Reject(String), // Режим fixed: операции нужно больше плеча, чем выставлено
+}
+
+/// Решение по плечу: computed — точное плечо под операцию,
+/// fixed — плечо не меняется, операция должна укладываться в min(fixed_leverage, текущее на бирже).
+/// leverage_filter — шаг и пределы плеча символа: плечо округляется до шага здесь, чтобы подтверждение
+/// и set-leverage получили одно значение; None — фильтр недоступен, плечо округляется до сотых
+pub fn decide_leverage(
+    mode: LeverageMode,
+    fixed_leverage: Option<f64>,
+    required_leverage: f64,
+    current_leverage: f64,
+    leverage_filter: Option<&LeverageFilter>,
+) -> LeverageAction {
+    match mode {
+        LeverageMode::Computed => {
+            let target_leverage = match leverage_to_set(required_leverage, leverage_filter) {
+                Ok(leverage) => leverage,
+                Err(e) => return LeverageAction::Reject(e.to_string()),
+            };
+            let tolerance = if leverage_filter.is_some() { 1e-9 } else { 0.01 };
+            if (target_leverage - current_leverage).abs() > tolerance {
+                LeverageAction::Set(target_leverage)
+            } else {
+                LeverageAction::Keep
+            }
+        }
+        LeverageMode::Fixed => {
+            let limit = fixed_leverage.map_or(current_leverage, |fixed| fixed.min(current_leverage));
+            if required_leverage > limit + 1e-9 {
+                LeverageAction::Reject(format!(
+                    "Required leverage {:.2}x exceeds fixed leverage {:.2}x (exchange: {:.2}x). Reduce the sum or raise leverage manually",
+                    required_leverage, limit, current_leverage
+                ))
+            } else {
+                LeverageAction::Keep
+            }
+        }
+    }
+}
+
+/// Плечо, которое уйдет в set-leverage в режиме computed: на шаге символа (leverage_for_step), без фильтра — до сотых
+pub fn leverage_to_set(required_leverage: f64, leverage_filter: Option<&LeverageFilter>) -> Result<f64> {
+    let required_leverage = required_leverage.max(0.01);
+    match leverage_filter {
+        Some(filter) => leverage_for_step(required_leverage, filter)?
+            .to_f64()
+            .ok_or_else(|| anyhow!("Invalid leverage {:.2}x for the symbol step", required_leverage)),
+        None => Ok((required_leverage * 100.0).round() / 100.0),
+    }
+}
+
+/// Повышение плеча, которое выполнит операция: Ok(Some((текущее, новое))) только при росте плеча.
+/// Операция, которую decide_leverage отклонит (плечо выше maxLeverage символа, режим fixed), — ошибка до подтверждения
+pub fn leverage_increase(
+    mode: LeverageMode,
+    fixed_leverage: Option<f64>,
+    required_leverage: f64,
+    current_leverage: f64,
+    leverage_filter: Option<&LeverageFilter>,
+) -> Result<Option<(f64, f64)>> {
+    match decide_leverage(mode, fixed_leverage, required_leverage, current_leverage, leverage_filter) {
+        LeverageAction::Set(new_leverage) if new_leverage > current_leverage + 1e-9 => Ok(Some((current_leverage, new_leverage))),
+        LeverageAction::Reject(reason) => Err(anyhow!(reason)),
+        _ => Ok(None),
+    }
+}
+
+/// leverageFilter бессрочного контракта для decide_leverage; None — инструмент недоступен (плечо округляется до сотых)
+pub async fn leverage_filter_for<E: Exchange>(exchange: &E, futures_symbol: &str, quote_currency: &str) -> Option<LeverageFilter> {
+    let info_symbol = linear_info_symbol(futures_symbol, quote_currency).ok()?;
+    match exchange.get_linear_instrument_info(info_symbol).await {
+        Ok(info) => info.leverage_filter,
+        Err(e) => {
+            warn!("Failed to fetch leverage filter for {}: {}. Leverage is rounded to 0.01.", futures_symbol, e);
+            None
+        }
+    }
+}
+
+// Вспомогательная функция для проверки и установки плеча
 async fn set_leverage_if_needed<ExchangeType>(
     hedger: &Hedger<ExchangeType>,
     futures_symbol: &str,
@@ -459,34 +976,312 @@ where
         Err(error) => {
             let error_message = format!("Leverage check failed for {}: {}", futures_symbol, error);
             error!("op_id:{}: {}. Aborting.", operation_identifier, error_message);
-            let _ = update_hedge_final_status(database, operation_identifier, "Failed", None, 0.0, Some(&error_message)).await;
-            return Err(anyhow!(error_message));
+            return Err(fail_operation(database, DbWriteRetry::from_config(&hedger.config), operation_identifier, None, LegFill::NONE, &error_message, anyhow!(error_message.clone())).await);
         }
     };
 
-    let target_leverage_to_set = (required_leverage.max(0.01) * 100.0).round() / 100.0;
-
-    if (target_leverage_to_set - current_leverage).abs() > 0.01 {
-        info!(
-            "op_id:{}: Setting leverage for {} from {:.2}x to {:.2}x",
-            operation_identifier, futures_symbol, current_leverage, target_leverage_to_set
-        );
-        if let Err(error) = hedger.exchange.set_leverage(futures_symbol, target_leverage_to_set).await {
-            let error_message = format!("Failed to set leverage for {}: {}", futures_symbol, error);
-            error!(
-                "op_id:{}: Failed to set leverage to {:.2}x: {}. Aborting.",
-                operation_identifier, target_leverage_to_set, error
+    let leverage_filter = leverage_filter_for(&hedger.exchange, futures_symbol, &hedger.quote_currency).await;
+    match decide_leverage(hedger.config.leverage_mode, hedger.config.fixed_leverage, required_leverage, current_leverage, leverage_filter.as_ref()) {
+        LeverageAction::Set(target_leverage_to_set) => {
+            info!(
+                "op_id:{}: Setting leverage for {} from {:.2}x to {:.2}x",
+                operation_identifier, futures_symbol, current_leverage, target_leverage_to_set
             );
-            let _ = update_hedge_final_status(database, operation_identifier, "Failed", None, 0.0, Some(&error_message)).await;
-            return Err(anyhow!(error_message));
+            if let Err(error) = hedger.exchange.set_leverage(futures_symbol, target_leverage_to_set).await {
+                let error_message = format!("Failed to set leverage for {}: {}", futures_symbol, error);
+                error!(
+                    "op_id:{}: Failed to set leverage to {:.2}x: {}. Aborting.",
+                    operation_identifier, target_leverage_to_set, error
+                );
+                return Err(fail_operation(database, DbWriteRetry::from_config(&hedger.config), operation_identifier, None, LegFill::NONE, &error_message, anyhow!(error_message.clone())).await);
+            }
+            info!("op_id:{}: Leverage set successfully for {}.", operation_identifier, futures_symbol);
+            sleep(Duration::from_millis(500)).await;
+        }
+        LeverageAction::Keep => info!(
+            "op_id:{}: Leverage {:.2}x kept for {} (required {:.2}x, mode {:?}).",
+            operation_identifier, current_leverage, futures_symbol, required_leverage, hedger.config.leverage_mode
+        ),
+        LeverageAction::Reject(error_message) => {
+            error!("op_id:{}: {}. Aborting.", operation_identifier, error_message);
+            return Err(fail_operation(database, DbWriteRetry::from_config(&hedger.config), operation_identifier, None, LegFill::NONE, &error_message, anyhow!(error_message.clone())).await);
         }
-        info!("op_id:{}: Leverage set successfully for {}.", operation_identifier, futures_symbol);
-        sleep(Duration::from_millis(500)).await;
-    } else {
-        info!(
-            "op_id:{}: Leverage {:.2}x already set for {}.",
-            operation_identifier, current_leverage, futures_symbol
-        );
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::mock::MockExchange;
+    use crate::exchange::types::Balance;
+    use crate::models::HedgeRequest;
+    use crate::storage::{get_hedge_operation_by_id, insert_hedge_operation, update_hedge_final_status};
+    use futures::FutureExt;
+
+    fn no_progress() -> HedgeProgressCallback {
+        Box::new(|_update| async { Ok(()) }.boxed())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn futures_only_hedge_is_persisted_and_unhedged_without_spot() {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+        crate::storage::schema::apply_migrations(&db).await.expect("migrations");
+        let exchange = MockExchange {
+            balances: vec![("USDT".to_string(), Balance { free: 200.0, locked: 0.0 })],
+            ..MockExchange::default()
+        };
+        let hedger = Hedger::new(
+            exchange.clone(),
+            crate::config::test_config("futures_only_hedge = true\nfutures_order_type = \"market\""),
+        );
+        let request = HedgeRequest { sum: 500.0, symbol: "ETH".to_string(), volatility: 0.1, spot_price_guard: None };
+
+        let params = hedger.calculate_hedge_params(&request).await.expect("params");
+        assert!(params.futures_only);
+        let (operation_id, _, _) = insert_hedge_operation(
+            &db, 1, "ETH", "USDT", "testnet", request.sum, request.volatility, params.spot_order_qty, params.fut_order_qty, params.futures_only,
+        )
+        .await
+        .expect("insert");
+        let outcome = hedger
+            .run_hedge(params, no_progress(), Arc::new(TokioMutex::new(0.0)), operation_id, 1, &db)
+            .await
+            .expect("hedge");
+
+        // 500 / 100 = 5 контрактов под залог 200 USDT: плечо выставлено и на этом пути
+        assert!((outcome.fut_filled - 5.0).abs() < 1e-9);
+        assert_eq!(outcome.spot_filled, 0.0);
+        assert_eq!(exchange.leverage_changes(), vec![("ETHUSDT".to_string(), 2.5)]);
+        let operation = get_hedge_operation_by_id(&db, operation_id).await.expect("query").expect("op");
+        assert!(operation.has_status(OperationStatus::Completed));
+        assert!(operation.futures_only, "mode is stored with the operation");
+
+        // Спота на балансе нет: расхедж откупает только шорт
+        let unhedged = hedger.run_unhedge(operation, &db, no_progress(), None).await.expect("unhedge");
+        assert_eq!(unhedged.spot_sold, 0.0);
+        assert!((unhedged.fut_bought - 5.0).abs() < 1e-9);
+        let operation = get_hedge_operation_by_id(&db, operation_id).await.expect("query").expect("op");
+        assert!(operation.unhedged_op_id.is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn spot_overfill_is_covered_by_futures_leg() {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+        crate::storage::schema::apply_migrations(&db).await.expect("migrations");
+        // Спотовая лимитка исполняется на 5% больше заказанного (дозаполнение во время отмены и т.п.)
+        let exchange = MockExchange {
+            balances: vec![("USDT".to_string(), Balance { free: 1000.0, locked: 0.0 })],
+            fill_schedule: Some(crate::exchange::mock::FillSchedule::Overfill { ratio: 1.05 }),
+            ..MockExchange::default()
+        };
+        let hedger = Hedger::new(exchange, crate::config::test_config("futures_order_type = \"market\""));
+        let request = HedgeRequest { sum: 500.0, symbol: "ETH".to_string(), volatility: 0.1, spot_price_guard: None };
+
+        let params = hedger.calculate_hedge_params(&request).await.expect("params");
+        let spot_target = params.spot_order_qty;
+        let (operation_id, _, _) = insert_hedge_operation(
+            &db, 1, "ETH", "USDT", "testnet", request.sum, request.volatility, params.spot_order_qty, params.fut_order_qty, params.futures_only,
+        )
+        .await
+        .expect("insert");
+        let outcome = hedger
+            .run_hedge(params, no_progress(), Arc::new(TokioMutex::new(0.0)), operation_id, 1, &db)
+            .await
+            .expect("hedge");
+
+        // Перекуп сохранен, а шорт рассчитан по всему купленному споту (шаг фьючерса 0.01)
+        assert!((outcome.spot_filled - spot_target * 1.05).abs() < 1e-9, "spot {} vs target {}", outcome.spot_filled, spot_target);
+        assert!((outcome.spot_overfill - spot_target * 0.05).abs() < 1e-9);
+        assert!(outcome.fut_filled > spot_target, "futures {} must exceed the ordered spot {}", outcome.fut_filled, spot_target);
+        assert!(outcome.spot_filled - outcome.fut_filled < 0.01, "futures {} must cover spot {}", outcome.fut_filled, outcome.spot_filled);
+        let operation = get_hedge_operation_by_id(&db, operation_id).await.expect("query").expect("op");
+        assert!(operation.has_status(OperationStatus::Completed));
+        assert!((operation.spot_filled_qty - outcome.spot_filled).abs() < 1e-9);
+        assert!((operation.futures_filled_qty - outcome.fut_filled).abs() < 1e-9);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn regular_hedge_without_spot_is_not_unhedged_as_futures_only() {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+        crate::storage::schema::apply_migrations(&db).await.expect("migrations");
+        let (operation_id, _, _) = insert_hedge_operation(&db, 1, "ETH", "USDT", "testnet", 500.0, 0.1, 5.0, 5.0, false).await.expect("insert");
+        update_hedge_final_status(&db, operation_id, OperationStatus::Completed, Some("fut-1"), 5.0, None).await.expect("complete");
+        let operation = get_hedge_operation_by_id(&db, operation_id).await.expect("query").expect("op");
+        let hedger = Hedger::new(MockExchange::default(), crate::config::test_config("futures_order_type = \"market\""));
+
+        // Обычный хедж с нулевым спотом (сбой учета) не должен молча откупать один фьючерс
+        let error = hedger.run_unhedge(operation, &db, no_progress(), None).await.expect_err("unhedge must fail");
+        assert!(error.to_string().contains("Target spot sell quantity"), "{}", error);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unhedge_halted_by_guard_mid_fill_keeps_only_unsold_spot() {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+        crate::storage::schema::apply_migrations(&db).await.expect("migrations");
+        let (operation_id, _, _) = insert_hedge_operation(&db, 1, "ETH", "USDT", "testnet", 100.0, 0.1, 1.0, 1.0, false).await.expect("insert");
+        crate::storage::update_hedge_spot_order(&db, operation_id, Some("spot-1"), 1.0).await.expect("spot");
+        update_hedge_final_status(&db, operation_id, OperationStatus::Completed, Some("fut-1"), 1.0, None).await.expect("complete");
+        let operation = get_hedge_operation_by_id(&db, operation_id).await.expect("query").expect("op");
+
+        // Спот продается лимитками постепенно; рынок падает ниже ограничителя 98, пока ордер исполнен частично
+        let exchange = MockExchange {
+            balances: vec![("ETH".to_string(), Balance { free: 1.0, locked: 0.0 })],
+            fill_schedule: Some(crate::exchange::mock::FillSchedule::Linear { polls: 20 }),
+            ..MockExchange::default()
+        };
+        let market = exchange.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_secs(1)).await;
+            market.move_spot_price(95.0);
+        });
+        let hedger = Hedger::new(exchange, crate::config::test_config(""));
+
+        let error = hedger.run_unhedge(operation, &db, no_progress(), Some(98.0)).await.expect_err("guard halts the spot sell");
+        let guard_hit = error.downcast_ref::<PriceGuardHit>().expect("price guard error");
+        assert!(guard_hit.filled_qty > 0.0 && guard_hit.filled_qty < 1.0, "{}", guard_hit);
+        assert_eq!(guard_hit.guard_price, 98.0);
+
+        // Проданный спот списан с операции: повторное расхеджирование продаст только остаток, шорт не тронут
+        let operation = get_hedge_operation_by_id(&db, operation_id).await.expect("query").expect("op");
+        assert!((operation.spot_filled_qty - (1.0 - guard_hit.filled_qty)).abs() < 1e-9, "spot left {}", operation.spot_filled_qty);
+        assert_eq!(operation.target_futures_qty, 1.0);
+        assert!(operation.unhedged_op_id.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unhedge_records_spot_dust_below_min_qty_without_selling_it() {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+        crate::storage::schema::apply_migrations(&db).await.expect("migrations");
+        let (operation_id, _, _) = insert_hedge_operation(&db, 1, "ETH", "USDT", "testnet", 100.0, 0.1, 1.0, 1.0, false).await.expect("insert");
+        crate::storage::update_hedge_spot_order(&db, operation_id, Some("spot-1"), 1.0).await.expect("spot");
+        update_hedge_final_status(&db, operation_id, OperationStatus::Completed, Some("fut-1"), 1.0, None).await.expect("complete");
+        let operation = get_hedge_operation_by_id(&db, operation_id).await.expect("query").expect("op");
+
+        // Лимитка продает 0.95 и встает; остаток 0.05 меньше minOrderQty 0.1, и мок, как Bybit, такой ордер отклоняет
+        let exchange = MockExchange {
+            balances: vec![("ETH".to_string(), Balance { free: 1.0, locked: 0.0 })],
+            spot_min_qty: "0.1".to_string(),
+            fill_schedule: Some(crate::exchange::mock::FillSchedule::Stalled { fraction: 0.95 }),
+            ..MockExchange::default()
+        };
+        let hedger = Hedger::new(exchange.clone(), crate::config::test_config(""));
+
+        let outcome = hedger.run_unhedge(operation, &db, no_progress(), None).await.expect("unhedge completes");
+        assert!((outcome.spot_sold - 0.95).abs() < 1e-9, "spot sold {}", outcome.spot_sold);
+        assert!((outcome.spot_dust - 0.05).abs() < 1e-9, "dust {}", outcome.spot_dust);
+        assert!((outcome.fut_bought - 1.0).abs() < 1e-9, "futures bought {}", outcome.fut_bought);
+        // Пыль не отправлялась на биржу: единственный спотовый ордер — исходная лимитка
+        assert_eq!(exchange.created_limit_orders(), 1);
+        assert!(!exchange.market_orders.lock().unwrap().keys().any(|id| id.starts_with("mock-spot-market")));
+        let dust: f64 = sqlx::query_scalar("SELECT unhedge_dust_qty FROM hedge_operations WHERE id = ?")
+            .bind(operation_id)
+            .fetch_one(&db)
+            .await
+            .expect("dust");
+        assert!((dust - 0.05).abs() < 1e-9, "recorded dust {}", dust);
+    }
+
+    #[test]
+    fn futures_slices_sum_to_target_and_respect_min_qty() {
+        let total = Decimal::new(1005, 3); // 1.005
+        let min_qty = Decimal::new(1, 2); // 0.01
+
+        let ends = futures_slice_targets(total, Some(0.3), min_qty, 3);
+        assert_eq!(ends, vec![Decimal::new(3, 1), Decimal::new(6, 1), Decimal::new(9, 1), total]);
+        let slices: Vec<Decimal> = ends.iter().scan(Decimal::ZERO, |prev, end| {
+            let slice = *end - *prev;
+            *prev = *end;
+            Some(slice)
+        }).collect();
+        assert_eq!(slices.iter().copied().sum::<Decimal>(), total);
+        assert!(slices.iter().all(|slice| *slice >= min_qty));
+
+        // Остаток 0.005 меньше минимума — добавляется к последнему срезу
+        assert_eq!(futures_slice_targets(Decimal::new(605, 3), Some(0.3), min_qty, 3), vec![Decimal::new(3, 1), Decimal::new(605, 3)]);
+        // Срез меньше минимума поднимается до минимума; без среза — один ордер
+        assert_eq!(futures_slice_targets(Decimal::new(2, 2), Some(0.001), min_qty, 3), vec![Decimal::new(1, 2), Decimal::new(2, 2)]);
+        assert_eq!(futures_slice_targets(total, None, min_qty, 3), vec![total]);
+        assert_eq!(futures_slice_targets(total, Some(2.0), min_qty, 3), vec![total]);
+    }
+
+    #[test]
+    fn computed_mode_sets_exact_leverage() {
+        assert_eq!(decide_leverage(LeverageMode::Computed, None, 2.345, 1.0, None), LeverageAction::Set(2.35));
+        assert_eq!(decide_leverage(LeverageMode::Computed, Some(5.0), 2.0, 2.0, None), LeverageAction::Keep);
+    }
+
+    fn leverage_filter(min: &str, max: &str, step: &str) -> LeverageFilter {
+        LeverageFilter { min_leverage: min.to_string(), max_leverage: max.to_string(), leverage_step: step.to_string() }
+    }
+
+    #[test]
+    fn computed_leverage_is_rounded_to_symbol_step_before_deciding() {
+        let integer_only = leverage_filter("1", "25", "1");
+        // Шаг 1: 2.35x округляется вверх до 3x — это значение и сравнивается с текущим, и уходит в set-leverage
+        assert_eq!(decide_leverage(LeverageMode::Computed, None, 2.35, 1.0, Some(&integer_only)), LeverageAction::Set(3.0));
+        assert_eq!(decide_leverage(LeverageMode::Computed, None, 2.35, 3.0, Some(&integer_only)), LeverageAction::Keep);
+        let fine_step = leverage_filter("1", "100", "0.01");
+        assert_eq!(decide_leverage(LeverageMode::Computed, None, 2.341, 1.0, Some(&fine_step)), LeverageAction::Set(2.35));
+        // Выше maxLeverage символа — отказ до подтверждения, а не ошибка биржи после него
+        assert!(matches!(decide_leverage(LeverageMode::Computed, None, 25.5, 10.0, Some(&integer_only)), LeverageAction::Reject(_)));
+        assert!(leverage_increase(LeverageMode::Computed, None, 25.5, 10.0, Some(&integer_only)).is_err());
+        assert_eq!(leverage_increase(LeverageMode::Computed, None, 2.35, 2.0, Some(&integer_only)).unwrap(), Some((2.0, 3.0)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn leverage_sent_to_exchange_is_the_decided_step_value() {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+        crate::storage::schema::apply_migrations(&db).await.expect("migrations");
+        let exchange = MockExchange { leverage_filter: Some(leverage_filter("1", "25", "1")), ..MockExchange::default() };
+        let hedger = Hedger::new(exchange.clone(), crate::config::test_config(""));
+
+        set_leverage_if_needed(&hedger, "ETHUSDT", 2.35, 1, &db).await.expect("leverage set");
+        assert_eq!(exchange.leverage_changes(), vec![("ETHUSDT".to_string(), 3.0)]);
+    }
+
+    #[test]
+    fn fixed_mode_never_sets_and_rejects_above_limit() {
+        // Операция укладывается в выставленное плечо — плечо не трогаем
+        assert_eq!(decide_leverage(LeverageMode::Fixed, Some(3.0), 2.5, 5.0, None), LeverageAction::Keep);
+        // Предел — меньшее из fixed_leverage и плеча на бирже
+        assert!(matches!(decide_leverage(LeverageMode::Fixed, Some(5.0), 3.5, 3.0, None), LeverageAction::Reject(_)));
+        // fixed_leverage не задан — проверка по текущему плечу на бирже
+        assert_eq!(decide_leverage(LeverageMode::Fixed, None, 3.0, 3.0, None), LeverageAction::Keep);
+        assert!(matches!(decide_leverage(LeverageMode::Fixed, None, 3.01, 3.0, None), LeverageAction::Reject(_)));
+    }
+
+    #[test]
+    fn only_leverage_increase_needs_confirmation() {
+        assert_eq!(leverage_increase(LeverageMode::Computed, None, 3.0, 2.0, None).unwrap(), Some((2.0, 3.0)));
+        // Понижение и то же плечо — без дополнительного подтверждения
+        assert_eq!(leverage_increase(LeverageMode::Computed, None, 1.5, 2.0, None).unwrap(), None);
+        assert_eq!(leverage_increase(LeverageMode::Computed, None, 2.0, 2.0, None).unwrap(), None);
+        // В режиме fixed бот плечо не меняет
+        assert_eq!(leverage_increase(LeverageMode::Fixed, Some(5.0), 3.0, 4.0, None).unwrap(), None);
+    }
+}
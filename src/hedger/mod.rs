@@ -1,8 +1,8 @@
 // src/hedger/mod.rs
 use anyhow::Result;
 use rust_decimal::Decimal;
+use std::fmt;
 use std::sync::Arc;
-use std::time::Duration;
 use tokio::sync::Mutex as TokioMutex;
 
 use crate::config::Config;
@@ -14,20 +14,171 @@ use crate::storage::{Db, HedgeOperation}; // Добавлено для сигн
 mod common;
 mod hedge;
 mod params;
+mod pending;
+mod recovery;
+mod roll;
+mod spread;
 mod unhedge;
+mod verify;
+pub mod watchers;
+
+pub use common::{fail_operation, finalize_with_retry, write_with_retry, DbWriteRetry};
+pub use hedge::{decide_leverage, leverage_filter_for, leverage_increase, leverage_to_set, LeverageAction};
+pub use pending::{resume_pending_futures_monitors, spawn_pending_futures_monitor, PendingFuturesOrder};
+pub use recovery::{recover_interrupted_operations, RecoveryAction, RecoveryReport, ResumeTracker, ResumedOperation};
+pub use spread::hedge_spreads;
+pub use verify::{compare_exposure, snapshot_exposure, ExposureSnapshot, HEDGE_DELTA_TOLERANCE_RATIO};
+pub use watchers::WatcherRegistry;
 
 // --- Константы и Общие Типы ---
 
-pub const ORDER_FILL_TOLERANCE: f64 = 1e-8;
+pub const ORDER_FILL_TOLERANCE: f64 = 1e-8; // Запасной допуск, если шаг количества инструмента неизвестен (см. common::qty_precision_for)
 
 // Основная структура Hedger остается здесь
 #[derive(Clone)]
 pub struct Hedger<E> {
     exchange: E,
-    slippage: f64,
-    max_wait: Duration,
     quote_currency: String,
     config: Config, // Оставляем Config здесь, т.к. он нужен в разных местах
+    active_order: ActiveOrderStorage, // Текущий активный ордер (нога + ID) для отмены
+    stage: StageStorage,              // Текущий этап хеджа (спот/фьючерс) для выбора вида отмены
+    watchers: WatcherRegistry,        // Реестр аккаунта для мониторов оставленных фьючерсных ордеров (/watchers)
+}
+
+// Текущий активный ордер операции (какая нога и какой ID)
+#[derive(Debug, Clone)]
+pub struct ActiveOrder {
+    pub stage: HedgeStage,
+    pub is_spot: bool,
+    pub symbol: String, // Символ в том виде, в котором его использует цикл ордеров
+    pub order_id: String,
+    pub filled_before: f64, // Исполнено на этапе до размещения этого ордера (для сверки после отмены)
+}
+
+pub type ActiveOrderStorage = Arc<TokioMutex<Option<ActiveOrder>>>;
+
+/// Этап выполняющегося хеджа: Futures — спот уже куплен, идет ожидание фьючерсной ноги
+pub type StageStorage = Arc<TokioMutex<HedgeStage>>;
+
+/// Фьючерсный ордер хеджа оставлен на бирже по таймауту (cancel_futures_on_timeout = false)
+#[derive(Debug, Clone)]
+pub struct FuturesOrderLeftActive {
+    pub order_id: String,
+    pub base_filled_qty: f64,  // Исполнено предыдущими ордерами этапа
+    pub order_filled_qty: f64, // Исполнено в оставленном ордере на момент таймаута
+    pub target_qty: f64,
+}
+
+impl fmt::Display for FuturesOrderLeftActive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Futures order {} left active after timeout ({:.8}/{:.8} filled)",
+            self.order_id,
+            self.base_filled_qty + self.order_filled_qty,
+            self.target_qty
+        )
+    }
+}
+
+impl std::error::Error for FuturesOrderLeftActive {}
+
+/// Продолжение исполнения требует цены за ограничителем операции: этап остановлен, исполнено filled_qty из target_qty
+#[derive(Debug, Clone)]
+pub struct PriceGuardHit {
+    pub required_price: f64,
+    pub guard_price: f64,
+    pub filled_qty: f64,
+    pub target_qty: f64,
+}
+
+impl fmt::Display for PriceGuardHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Price guard {:.8} reached: required price {:.8} ({:.8}/{:.8} filled)",
+            self.guard_price, self.required_price, self.filled_qty, self.target_qty
+        )
+    }
+}
+
+impl std::error::Error for PriceGuardHit {}
+
+/// Рыночный (IOC) ордер исполнен частично, остаток отменен биржей: исполнено filled_qty из target_qty
+#[derive(Debug, Clone)]
+pub struct MarketFillShortfall {
+    pub order_id: String,
+    pub filled_qty: f64,
+    pub target_qty: f64,
+}
+
+impl fmt::Display for MarketFillShortfall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Market order {} filled only {:.8}/{:.8}", self.order_id, self.filled_qty, self.target_qty)
+    }
+}
+
+impl std::error::Error for MarketFillShortfall {}
+
+/// Спот куплен, а фьючерсная нога не выставлена (статус SpotOnlyOrphan): позиция не захеджирована
+#[derive(Debug, Clone)]
+pub struct SpotOnlyOrphan {
+    pub spot_filled_qty: f64,
+    pub futures_filled_qty: f64,
+    pub reason: String,
+}
+
+impl fmt::Display for SpotOnlyOrphan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Spot filled {:.8} but futures leg failed ({:.8} filled): {}",
+            self.spot_filled_qty, self.futures_filled_qty, self.reason
+        )
+    }
+}
+
+impl std::error::Error for SpotOnlyOrphan {}
+
+/// Сделка на бирже исполнена, но критическая запись в БД не удалась даже после повторов:
+/// состояние биржи и БД расходится, нужна ручная проверка
+#[derive(Debug, Clone)]
+pub struct DbRecordDiverged {
+    pub operation_id: i64,
+    pub what: String,
+    pub error: String,
+}
+
+impl fmt::Display for DbRecordDiverged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Operation {} executed on exchange but DB write '{}' failed: {}",
+            self.operation_id, self.what, self.error
+        )
+    }
+}
+
+impl std::error::Error for DbRecordDiverged {}
+
+/// Итог успешного хеджа
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HedgeOutcome {
+    pub spot_filled: f64, // Куплено спота (брутто)
+    pub spot_overfill: f64, // Из них сверх цели (0 — перекупа нет); фьючерс рассчитан на весь купленный спот
+    pub fut_filled: f64,  // Продано фьючерса (нетто)
+    pub spot_value: f64,  // Стоимость купленного спота
+    pub avg_spot_price: f64,
+    pub avg_fut_price: Option<f64>, // None — детали исполнения фьючерса недоступны
+    pub used_fallback: bool, // Средняя цена спота взята не из исполнения, а из текущей цены
+}
+
+/// Итог успешного расхеджирования
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnhedgeOutcome {
+    pub spot_sold: f64,
+    pub fut_bought: f64,
+    pub spot_dust: f64, // Непроданный остаток спота меньше минимального ордера
 }
 
 // Параметры, возвращаемые калькулятором
@@ -38,14 +189,19 @@ pub struct HedgeParams {
     pub current_spot_price: f64,
     pub initial_limit_price: f64, // Цена для первого спот ордера
     pub symbol: String,
+    pub hedge_sum: f64, // Сумма расчета (с snap_sum_to_qty — подогнанная под целый шаг количества)
     pub spot_value: f64, // Расчетное значение спота
     pub available_collateral: f64, // Расчетный доступный коллатерал
+    pub borrow_required: f64, // Нехватка свободного quote для покупки спота (потребуется заём), 0 — заём не нужен
+    pub estimated_liquidation_price: Option<f64>, // Оценка цены ликвидации шорта (изолированно, по MMR); None — позиции нет
     // Добавляем информацию, нужную для циклов ордеров
     pub min_spot_qty_decimal: Decimal,
     pub min_fut_qty_decimal: Decimal,
     pub spot_decimals: u32,
     pub fut_decimals: u32,
     pub futures_symbol: String, // Добавим сразу символ фьючерса
+    pub spot_price_guard: Option<f64>, // Худшая допустимая цена покупки спота (из HedgeRequest)
+    pub futures_only: bool, // Хедж только фьючерсом: спот не покупается (сохраняется в операции)
 }
 
 // Этапы операции
@@ -81,21 +237,61 @@ where
     pub fn new(exchange: E, config: Config) -> Self {
         Self {
             exchange,
-            slippage: config.slippage,
-            max_wait: Duration::from_secs(config.max_wait_secs),
             quote_currency: config.quote_currency.clone(),
             config,
+            active_order: Arc::new(TokioMutex::new(None)),
+            stage: Arc::new(TokioMutex::new(HedgeStage::Spot)),
+            watchers: WatcherRegistry::default(),
         }
     }
 
+    /// Реестр наблюдателей аккаунта: без него мониторы оставленных ордеров не видны в /watchers
+    pub fn with_watchers(mut self, watchers: WatcherRegistry) -> Self {
+        self.watchers = watchers;
+        self
+    }
+
+    /// Хранилище текущего активного ордера (разделяется с RunningOperationInfo)
+    pub fn active_order_storage(&self) -> ActiveOrderStorage {
+        self.active_order.clone()
+    }
+
+    /// Хранилище текущего этапа хеджа (разделяется с RunningOperationInfo)
+    pub fn stage_storage(&self) -> StageStorage {
+        self.stage.clone()
+    }
+
     // Используем функции из подмодулей
     pub async fn calculate_hedge_params(&self, req: &HedgeRequest) -> Result<HedgeParams> {
+        self.calculate_hedge_params_with_snap(req, self.config.snap_sum_to_qty).await
+    }
+
+    /// Пересчет перед запуском для суммы, которую пользователь уже подтвердил: повторная подгонка
+    /// (snap_sum_to_qty) по новой цене изменила бы подтвержденную сумму
+    pub async fn calculate_confirmed_hedge_params(&self, req: &HedgeRequest) -> Result<HedgeParams> {
+        self.calculate_hedge_params_with_snap(req, false).await
+    }
+
+    async fn calculate_hedge_params_with_snap(&self, req: &HedgeRequest, snap_sum: bool) -> Result<HedgeParams> {
+        if self.config.futures_only_hedge {
+            return params::calculate_futures_only_params_impl(
+                &self.exchange,
+                req,
+                self.config.slippage_for(&req.symbol),
+                &self.quote_currency,
+                self.config.max_allowed_leverage,
+                common::SpotPriceRetry::from_config(&self.config),
+            )
+            .await;
+        }
         params::calculate_hedge_params_impl(
             &self.exchange,
             req,
-            self.slippage,
+            self.config.slippage_for(&req.symbol), // Учитываем symbol_overrides
             &self.quote_currency,
             self.config.max_allowed_leverage,
+            snap_sum,
+            common::SpotPriceRetry::from_config(&self.config),
         )
         .await
     }
@@ -107,8 +303,9 @@ where
         // --- ИСПРАВЛЕНО: Убрали current_order_id_storage ---
         total_filled_qty_storage: Arc<TokioMutex<f64>>,
         operation_id: i64,
+        chat_id: i64, // Чат операции: в нем виден монитор оставленного фьючерсного ордера
         db: &Db,
-    ) -> Result<(f64, f64, f64)> { // (spot_filled, fut_filled, spot_value_estimate)
+    ) -> Result<HedgeOutcome> {
         hedge::run_hedge_impl(
             self, // Передаем всего Hedger, чтобы иметь доступ к exchange, max_wait и т.д.
             params,
@@ -116,23 +313,55 @@ where
             // --- ИСПРАВЛЕНО: Убрали current_order_id_storage ---
             total_filled_qty_storage,
             operation_id,
+            chat_id,
             db,
         )
         .await
     }
 
+    /// Довыставить фьючерсную ногу для операции SpotOnlyOrphan; возвращает итоговый объем фьючерса
+    pub async fn resume_futures_leg(
+        &self,
+        operation: HedgeOperation,
+        progress_callback: HedgeProgressCallback,
+        db: &Db,
+    ) -> Result<f64> {
+        hedge::resume_futures_leg_impl(self, operation, progress_callback, db).await
+    }
+
+    /// Перенести фьючерсную ногу операции на другой контракт (спот не трогается).
+    /// rolled_operation_id — заранее созданная операция-преемник; возвращает объем нового шорта
+    pub async fn roll_futures_leg(
+        &self,
+        original_op: HedgeOperation,
+        new_futures_symbol: &str,
+        rolled_operation_id: i64,
+        progress_callback: HedgeProgressCallback,
+        db: &Db,
+    ) -> Result<f64> {
+        roll::run_roll_impl(self, original_op, new_futures_symbol, rolled_operation_id, progress_callback, db).await
+    }
+
     pub async fn run_unhedge(
         &self,
         original_op: HedgeOperation,
         db: &Db,
         progress_callback: HedgeProgressCallback,
-    ) -> Result<(f64, f64)> { // (spot_sold, fut_bought)
+        spot_price_guard: Option<f64>, // Худшая допустимая цена продажи спота; None — без ограничения
+    ) -> Result<UnhedgeOutcome> {
         unhedge::run_unhedge_impl(
             self, // Передаем всего Hedger
             original_op,
             db,
             progress_callback,
+            spot_price_guard,
         )
         .await
     }
+
+    /// Снять трейлинг-стоп перед закрытием фьючерсной ноги вне run_unhedge (WS-расхедж);
+    /// стоп остается, пока контракт держат другие открытые хеджи
+    pub async fn release_trailing_stop(&self, db: &Db, operation_id: i64, futures_symbol: &str) {
+        common::release_trailing_stop(self, db, operation_id, futures_symbol).await
+    }
 }
@@ -6,10 +6,175 @@ use std::str::FromStr;
 use tracing::{debug, info, warn};
 
 use crate::hedger::HedgeParams; // Используем типы из родительского модуля
+use crate::hedger::common::{get_spot_price_with_retry, SpotPriceRetry};
 use crate::exchange::bybit::SPOT_CATEGORY;
+use crate::exchange::types::{ensure_instrument_trading, LinearInstrumentInfo, SpotInstrumentInfo};
 use crate::exchange::Exchange;
 use crate::models::HedgeRequest;
 
+/// Точность количества фьючерса (знаков после запятой) и минимальный объем ордера
+pub(super) fn futures_qty_precision(linear_info: &LinearInstrumentInfo) -> Result<(u32, Decimal)> {
+    let fut_qty_step_str = linear_info
+        .lot_size_filter
+        .qty_step
+        .as_deref()
+        .ok_or_else(|| anyhow!("Missing qtyStep for futures"))?;
+    let fut_decimals = fut_qty_step_str
+        .split('.')
+        .nth(1)
+        .map_or(0, |s| s.trim_end_matches('0').len()) as u32;
+    let min_fut_qty_str = &linear_info.lot_size_filter.min_order_qty;
+    let min_fut_qty_decimal = Decimal::from_str(min_fut_qty_str)
+        .map_err(|e| anyhow!("Failed to parse min futures qty '{}': {}", min_fut_qty_str, e))?;
+    Ok((fut_decimals, min_fut_qty_decimal))
+}
+
+/// Точность количества спота (знаков после запятой) и минимальный объем ордера
+pub(super) fn spot_qty_precision(spot_info: &SpotInstrumentInfo) -> Result<(u32, Decimal)> {
+    let spot_precision_str = spot_info
+        .lot_size_filter
+        .base_precision
+        .as_deref()
+        .ok_or_else(|| anyhow!("Missing basePrecision for spot"))?;
+    let spot_decimals = spot_precision_str
+        .split('.')
+        .nth(1)
+        .map_or(0, |s| s.trim_end_matches('0').len()) as u32;
+    let min_spot_qty_str = &spot_info.lot_size_filter.min_order_qty;
+    let min_spot_qty_decimal = Decimal::from_str(min_spot_qty_str)
+        .map_err(|e| anyhow!("Failed to parse min spot qty '{}': {}", min_spot_qty_str, e))?;
+    Ok((spot_decimals, min_spot_qty_decimal))
+}
+
+/// Оценка цены ликвидации шорта: залог покрывает рост цены, пока не останется поддерживающая маржа (MMR).
+/// Считается как для изолированной позиции; на едином аккаунте спот и остальной баланс отодвигают ликвидацию дальше.
+/// None — позиции нет (нулевой объем) или данные некорректны
+pub(super) fn estimate_short_liquidation_price(entry_price: f64, position_qty: f64, collateral: f64, mmr: f64) -> Option<f64> {
+    if position_qty <= 0.0 || entry_price <= 0.0 {
+        return None;
+    }
+    let maintenance_margin = entry_price * position_qty * mmr;
+    let liquidation_price = entry_price + (collateral - maintenance_margin) / position_qty;
+    (liquidation_price.is_finite() && liquidation_price > 0.0).then_some(liquidation_price)
+}
+
+/// f64 -> Decimal по кратчайшему десятичному представлению (0.1 -> 0.1, а не 0.1000000000000000055...)
+fn to_decimal(value: f64, what: &str) -> Result<Decimal> {
+    Decimal::from_f64(value).ok_or_else(|| anyhow!("Failed to convert {} {} to Decimal", what, value))
+}
+
+/// Decimal -> f64 на границе с биржей
+fn to_f64(value: Decimal, what: &str) -> Result<f64> {
+    value.to_f64().ok_or_else(|| anyhow!("Failed to convert {} {} back to f64", what, value))
+}
+
+/// Шаг и минимальный объем ордера инструмента
+#[derive(Debug, Clone, Copy)]
+pub(super) struct LotRules {
+    pub decimals: u32,
+    pub min_qty: Decimal,
+}
+
+/// Исходные данные расчета хеджа (все денежные величины — Decimal)
+#[derive(Debug, Clone, Copy)]
+pub(super) struct HedgeInputs {
+    pub sum: Decimal,
+    pub volatility: Decimal,
+    pub mmr: Decimal,
+    pub spot_fee: Decimal,
+    pub price: Decimal,
+}
+
+/// Результат расчета хеджа в Decimal: объемы уже округлены до шагов инструментов
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct HedgeAmounts {
+    pub spot_qty: Decimal,        // Брутто спота (с учетом комиссии), по шагу спота
+    pub fut_qty: Decimal,         // Нетто, по шагу фьючерса
+    pub spot_value: Decimal,      // Стоимость покупки спота
+    pub available_collateral: Decimal,
+    pub futures_value: Decimal,   // Стоимость шорта по текущей цене
+}
+
+/// Сумма, при которой объем спота получается ровно на шаге, без отброшенной дроби (snap_sum_to_qty):
+/// идеальный объем округляется вниз до шага спота, сумма пересчитывается от него обратно
+pub(super) fn snap_sum_to_qty(inputs: HedgeInputs, spot: &LotRules) -> Result<Decimal> {
+    let HedgeInputs { sum, volatility, mmr, price, .. } = inputs;
+    let ratio = (Decimal::ONE + volatility) * (Decimal::ONE + mmr);
+    if price <= Decimal::ZERO || ratio <= Decimal::ZERO {
+        return Err(anyhow!("Cannot snap hedge sum: invalid price {} or ratio {}", price, ratio));
+    }
+    let clean_spot_qty = (sum / ratio / price).trunc_with_scale(spot.decimals);
+    // Вверх: погрешность обратного деления не должна опустить объем на шаг ниже
+    Ok((clean_spot_qty * price * ratio).round_dp_with_strategy(8, RoundingStrategy::AwayFromZero))
+}
+
+/// Объемы спота/фьючерса и залог без промежуточных f64: граничные значения
+/// (0.3 / 0.1, min qty ровно на шаге) не "проваливаются" на шаг вниз при округлении
+pub(super) fn compute_hedge_amounts(inputs: HedgeInputs, spot: &LotRules, fut: &LotRules) -> Result<HedgeAmounts> {
+    let HedgeInputs { sum, volatility, mmr, spot_fee, price } = inputs;
+    if price <= Decimal::ZERO {
+        return Err(anyhow!("Invalid spot price: {}", price));
+    }
+    let initial_spot_value = sum
+        .checked_div((Decimal::ONE + volatility) * (Decimal::ONE + mmr))
+        .ok_or_else(|| anyhow!("Initial spot value is non-positive"))?;
+    if initial_spot_value <= Decimal::ZERO {
+        return Err(anyhow!("Initial spot value is non-positive"));
+    }
+
+    let ideal_gross_qty = initial_spot_value / price;
+    debug!("Ideal gross quantity (before fees/rounding): {}", ideal_gross_qty);
+
+    let fut_qty = ideal_gross_qty.trunc_with_scale(fut.decimals); // Округляем до точности фьючерса
+    if fut_qty < fut.min_qty {
+        return Err(anyhow!(
+            "Target net quantity {:.8} < min futures quantity {}",
+            fut_qty,
+            fut.min_qty
+        ));
+    }
+    debug!("Target NET quantity (rounded to fut_decimals): {}", fut_qty);
+
+    let net_ratio = Decimal::ONE - spot_fee;
+    if net_ratio <= Decimal::ZERO {
+        return Err(anyhow!("Spot fee rate is 100% or invalid"));
+    }
+    let spot_qty = (fut_qty / net_ratio).trunc_with_scale(spot.decimals); // Округляем до точности спота
+    if spot_qty < spot.min_qty {
+        return Err(anyhow!(
+            "Calculated final spot quantity {:.8} < min spot quantity {}",
+            spot_qty,
+            spot.min_qty
+        ));
+    }
+    debug!("Final SPOT GROSS quantity (rounded to spot_decimals): {}", spot_qty);
+
+    if spot_qty <= Decimal::ZERO || fut_qty <= Decimal::ZERO {
+        return Err(anyhow!(
+            "Final order quantities are non-positive: spot={}, fut={}",
+            spot_qty,
+            fut_qty
+        ));
+    }
+
+    let spot_value = spot_qty * price;
+    let available_collateral = sum - spot_value;
+    if available_collateral <= Decimal::ZERO {
+        return Err(anyhow!(
+            "Available collateral non-positive after spot value calculation (Sum: {}, Spot Value: {})",
+            sum, spot_value
+        ));
+    }
+
+    Ok(HedgeAmounts {
+        spot_qty,
+        fut_qty,
+        spot_value,
+        available_collateral,
+        futures_value: fut_qty * price, // Оценка по текущей спот цене
+    })
+}
+
 // Делаем функцию pub(super), чтобы она была доступна в mod.rs
 pub(super) async fn calculate_hedge_params_impl<E>(
     exchange: &E,
@@ -17,6 +182,8 @@ pub(super) async fn calculate_hedge_params_impl<E>(
     slippage: f64,
     quote_currency: &str, // Убедись, что этот параметр передается при вызове!
     max_allowed_leverage: f64,
+    snap_sum: bool, // snap_sum_to_qty: подогнать сумму под целый шаг количества
+    spot_retry: SpotPriceRetry, // Повторы запроса цены спота (нулевой тикер, временные ошибки)
 ) -> Result<HedgeParams>
 where
     E: Exchange + Clone + Send + Sync + 'static,
@@ -25,20 +192,32 @@ where
         sum,
         symbol, // Это базовый символ, e.g., "BTC"
         volatility,
+        spot_price_guard,
     } = req;
     debug!("Calculating hedge params for {}...", symbol);
 
-    let spot_info = exchange
-        .get_spot_instrument_info(symbol)
-        .await
-        .map_err(|e| anyhow!("Failed to get SPOT instrument info: {}", e))?;
+    // --- ИЗМЕНЕНО: Независимые запросы выполняются параллельно ---
     // Передаем базовый символ, т.к. bybit.rs сам добавит quote_currency для linear
-    let linear_info = exchange
-        .get_linear_instrument_info(symbol)
-        .await
-        .map_err(|e| anyhow!("Failed to get LINEAR instrument info: {}", e))?;
+    let futures_symbol = format!("{}{}", symbol, quote_currency);
+    debug!("Using futures symbol {} for MMR lookup", futures_symbol);
+    // Стоимость шорта не больше суммы хеджа — по ней выбираем ступень риск-лимита (оценка сверху)
+    let (spot_info_res, linear_info_res, spot_fee_res, bracket_res, spot_price_res, quote_balance_res) = tokio::join!(
+        exchange.get_spot_instrument_info(symbol),
+        exchange.get_linear_instrument_info(symbol),
+        exchange.get_fee_rate(symbol, SPOT_CATEGORY),
+        exchange.get_symbol_leverage_bracket(&futures_symbol, *sum),
+        get_spot_price_with_retry(exchange, symbol, spot_retry),
+        exchange.get_balance(quote_currency),
+    );
+
+    let spot_info = spot_info_res.map_err(|e| anyhow!("Failed to get SPOT instrument info: {}", e))?;
+    let linear_info = linear_info_res.map_err(|e| anyhow!("Failed to get LINEAR instrument info: {}", e))?;
+
+    // Не начинаем хедж по делистингуемому/расчетному инструменту: иначе спот купится, а фьючерс не откроется
+    ensure_instrument_trading(&spot_info.symbol, spot_info.status.as_deref())?;
+    ensure_instrument_trading(&linear_info.symbol, linear_info.status.as_deref())?;
 
-    let spot_fee = match exchange.get_fee_rate(symbol, SPOT_CATEGORY).await {
+    let spot_fee = match spot_fee_res {
         Ok(fee) => {
             info!("Spot fee rate: Taker={}", fee.taker);
             fee.taker
@@ -52,158 +231,177 @@ where
         }
     };
 
-    // --- ИСПРАВЛЕНО: Формируем фьючерсный символ ПЕРЕД вызовом get_mmr ---
-    let futures_symbol = format!("{}{}", symbol, quote_currency);
-    debug!("Using futures symbol {} for MMR lookup", futures_symbol);
-
-    // --- ИСПРАВЛЕНО: Передаем фьючерсный символ в get_mmr ---
-    let mmr = exchange.get_mmr(&futures_symbol).await?;
-    let initial_spot_value = sum / ((1.0 + volatility) * (1.0 + mmr));
-
-    if initial_spot_value <= 0.0 {
-        return Err(anyhow!("Initial spot value is non-positive"));
-    }
-
-    let current_spot_price = exchange.get_spot_price(symbol).await?;
+    let bracket = bracket_res.map_err(|e| anyhow!("Failed to get MMR for {}: {}", futures_symbol, e))?;
+    let (mmr, max_allowed_leverage) = (bracket.mmr, max_allowed_leverage.min(bracket.max_leverage));
+    debug!("Risk limit bracket for {}: MMR {}, max leverage {}", futures_symbol, bracket.mmr, bracket.max_leverage);
+    let current_spot_price = spot_price_res.map_err(|e| anyhow!("Failed to get spot price for {}: {}", symbol, e))?;
     if current_spot_price <= 0.0 {
         return Err(anyhow!("Invalid spot price: {}", current_spot_price));
     }
 
-    let ideal_gross_qty = initial_spot_value / current_spot_price;
-    debug!(
-        "Ideal gross quantity (before fees/rounding): {}",
-        ideal_gross_qty
-    );
-
     // --- Расчет точности фьючерса ---
-    let fut_qty_step_str = linear_info
-        .lot_size_filter
-        .qty_step
-        .as_deref()
-        .ok_or_else(|| anyhow!("Missing qtyStep for futures"))?;
-    let fut_decimals = fut_qty_step_str
-        .split('.')
-        .nth(1)
-        .map_or(0, |s| s.trim_end_matches('0').len()) as u32;
-    let min_fut_qty_str = &linear_info.lot_size_filter.min_order_qty;
-    let min_fut_qty_decimal = Decimal::from_str(min_fut_qty_str)
-        .map_err(|e| anyhow!("Failed to parse min futures qty '{}': {}", min_fut_qty_str, e))?;
+    let (fut_decimals, min_fut_qty_decimal) = futures_qty_precision(&linear_info)?;
     debug!(
         "Futures precision: {} decimals, Min Qty: {}",
         fut_decimals, min_fut_qty_decimal
     );
 
     // --- Расчет точности спота ---
-    let spot_precision_str = spot_info
-        .lot_size_filter
-        .base_precision
-        .as_deref()
-        .ok_or_else(|| anyhow!("Missing basePrecision for spot"))?;
-    let spot_decimals = spot_precision_str
-        .split('.')
-        .nth(1)
-        .map_or(0, |s| s.trim_end_matches('0').len()) as u32;
-    let min_spot_qty_str = &spot_info.lot_size_filter.min_order_qty;
-    let min_spot_qty_decimal = Decimal::from_str(min_spot_qty_str)
-        .map_err(|e| anyhow!("Failed to parse min spot qty '{}': {}", min_spot_qty_str, e))?;
+    let (spot_decimals, min_spot_qty_decimal) = spot_qty_precision(&spot_info)?;
     debug!(
         "Spot precision: {} decimals, Min Qty: {}",
         spot_decimals, min_spot_qty_decimal
     );
 
-    // --- Расчет количества ---
-    let target_net_qty_decimal = Decimal::from_f64(ideal_gross_qty)
-        .ok_or_else(|| anyhow!("Failed to convert ideal qty to Decimal"))?
-        .trunc_with_scale(fut_decimals); // Округляем до точности фьючерса
-
-    if target_net_qty_decimal < min_fut_qty_decimal {
-        return Err(anyhow!(
-            "Target net quantity {:.8} < min futures quantity {}",
-            target_net_qty_decimal,
-            min_fut_qty_decimal
-        ));
+    // --- Количества и стоимость: вся арифметика в Decimal, в f64 — только на выходе ---
+    let mut inputs = HedgeInputs {
+        sum: to_decimal(*sum, "sum")?,
+        volatility: to_decimal(*volatility, "volatility")?,
+        mmr: to_decimal(mmr, "MMR")?,
+        spot_fee: to_decimal(spot_fee, "spot fee")?,
+        price: to_decimal(current_spot_price, "spot price")?,
+    };
+    let spot_rules = LotRules { decimals: spot_decimals, min_qty: min_spot_qty_decimal };
+    let fut_rules = LotRules { decimals: fut_decimals, min_qty: min_fut_qty_decimal };
+    if snap_sum {
+        let snapped_sum = snap_sum_to_qty(inputs, &spot_rules)?;
+        info!("Hedge sum snapped to whole spot qty step: {} -> {}", inputs.sum, snapped_sum);
+        inputs.sum = snapped_sum;
     }
-    let target_net_qty = target_net_qty_decimal
-        .to_f64()
-        .ok_or_else(|| anyhow!("Failed to convert target net qty back to f64"))?;
-    debug!(
-        "Target NET quantity (rounded to fut_decimals): {}",
-        target_net_qty
-    );
+    let amounts = compute_hedge_amounts(inputs, &spot_rules, &fut_rules)?;
+    debug!("Hedge amounts (Decimal): {:?}", amounts);
 
-    if (1.0 - spot_fee).abs() < f64::EPSILON {
-        return Err(anyhow!("Spot fee rate is 100% or invalid"));
-    }
-    let required_gross_qty = target_net_qty / (1.0 - spot_fee);
-    debug!(
-        "Required GROSS quantity (to get target net after fee): {}",
-        required_gross_qty
-    );
+    // Если свободного quote не хватает на спот, покупка пойдет в заём (маржинальный спот)
+    let borrow_required = match quote_balance_res {
+        Ok(balance) => (amounts.spot_value - to_decimal(balance.free, "quote balance")?).max(Decimal::ZERO),
+        Err(e) => {
+            warn!("Could not get {} balance to check borrowing: {}. Assuming no borrow.", quote_currency, e);
+            Decimal::ZERO
+        }
+    };
+    debug!("Borrow required for spot buy: {}", borrow_required);
 
-    let final_spot_gross_qty_decimal = Decimal::from_f64(required_gross_qty)
-        .ok_or_else(|| anyhow!("Failed to convert required gross qty to Decimal"))?
-        .trunc_with_scale(spot_decimals); // Округляем до точности спота
+    let required_leverage = amounts.futures_value
+        .checked_div(amounts.available_collateral)
+        .and_then(|leverage| leverage.to_f64())
+        .ok_or_else(|| anyhow!(
+            "Invalid leverage calculation (Fut Value: {}, Collateral: {})",
+            amounts.futures_value, amounts.available_collateral
+        ))?;
+    debug!("Calculated required leverage: {}", required_leverage);
 
-    if final_spot_gross_qty_decimal < min_spot_qty_decimal {
-        // Если после округления стало меньше минимума, возможно, стоит увеличить до минимума?
-        // Или вернуть ошибку, как сейчас. Оставим ошибку для ясности.
+    if required_leverage > max_allowed_leverage {
         return Err(anyhow!(
-            "Calculated final spot quantity {:.8} < min spot quantity {}",
-            final_spot_gross_qty_decimal,
-            min_spot_qty_decimal
+            "Required leverage {:.2}x > max allowed {:.2}x",
+            required_leverage,
+            max_allowed_leverage
         ));
     }
-    let final_spot_gross_qty = final_spot_gross_qty_decimal
-        .to_f64()
-        .ok_or_else(|| anyhow!("Failed to convert final gross qty back to f64"))?;
-    debug!(
-        "Final SPOT GROSS quantity (rounded to spot_decimals): {}",
-        final_spot_gross_qty
+    info!(
+        "Required leverage {:.2}x is within max allowed {:.2}x",
+        required_leverage, max_allowed_leverage
     );
 
-    let spot_order_qty = final_spot_gross_qty;
-    let fut_order_qty = target_net_qty; // Используем уже округленное до точности фьючерса
+    // --- Граница с биржей и HedgeParams: перевод в f64 ---
+    let spot_order_qty = to_f64(amounts.spot_qty, "spot qty")?;
+    let fut_order_qty = to_f64(amounts.fut_qty, "futures qty")?;
+    let adjusted_spot_value = to_f64(amounts.spot_value, "spot value")?;
+    let available_collateral = to_f64(amounts.available_collateral, "collateral")?;
+    let borrow_required = to_f64(borrow_required, "borrow")?;
 
-    if spot_order_qty <= 0.0 || fut_order_qty <= 0.0 {
-        return Err(anyhow!(
-            "Final order quantities are non-positive: spot={}, fut={}",
-            spot_order_qty,
-            fut_order_qty
-        ));
-    }
+    let estimated_liquidation_price = estimate_short_liquidation_price(current_spot_price, fut_order_qty, available_collateral, mmr);
+    debug!("Estimated short liquidation price: {:?}", estimated_liquidation_price);
 
-    // --- Расчет стоимости и плеча ---
-    let adjusted_spot_value = spot_order_qty * current_spot_price;
-    let available_collateral = sum - adjusted_spot_value;
-    let futures_position_value = fut_order_qty * current_spot_price; // Оценка по текущей спот цене
+    // Начальная цена для лимитного ордера (для run_hedge)
+    let initial_limit_price = current_spot_price * (1.0 - slippage);
+    debug!("Initial limit price for spot buy: {}", initial_limit_price);
 
-    debug!("Adjusted spot value (cost): {}", adjusted_spot_value);
-    debug!(
-        "Available collateral after spot buy: {}",
-        available_collateral
-    );
-    debug!(
-        "Futures position value (based on net qty): {}",
-        futures_position_value
+    // futures_symbol уже был создан выше перед параллельными запросами
+
+    Ok(HedgeParams {
+        spot_order_qty,
+        fut_order_qty,
+        current_spot_price,
+        initial_limit_price,
+        symbol: symbol.clone(),
+        hedge_sum: to_f64(inputs.sum, "hedge sum")?,
+        spot_value: adjusted_spot_value,
+        available_collateral,
+        borrow_required,
+        estimated_liquidation_price,
+        min_spot_qty_decimal, // Передаем дальше
+        min_fut_qty_decimal,  // Передаем дальше
+        spot_decimals,        // Передаем дальше
+        fut_decimals,         // Передаем дальше
+        futures_symbol,       // Используем уже созданный futures_symbol
+        spot_price_guard: *spot_price_guard,
+        futures_only: false,
+    })
+}
+
+/// Параметры хеджа только фьючерсом (спот хранится вне бота): шорт на sum / цену,
+/// залог — свободный баланс quote, которого должно хватать на рост цены на volatility + MMR
+pub(super) async fn calculate_futures_only_params_impl<E>(
+    exchange: &E,
+    req: &HedgeRequest,
+    slippage: f64,
+    quote_currency: &str,
+    max_allowed_leverage: f64,
+    spot_retry: SpotPriceRetry,
+) -> Result<HedgeParams>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let HedgeRequest { sum, symbol, volatility, .. } = req;
+    debug!("Calculating futures-only hedge params for {}...", symbol);
+
+    let futures_symbol = format!("{}{}", symbol, quote_currency);
+    let (spot_info_res, linear_info_res, bracket_res, spot_price_res, quote_balance_res) = tokio::join!(
+        exchange.get_spot_instrument_info(symbol), // Точность спота — для отображения внешнего спота
+        exchange.get_linear_instrument_info(symbol),
+        exchange.get_symbol_leverage_bracket(&futures_symbol, *sum), // Шорт на sum — ступень риск-лимита по ней
+        get_spot_price_with_retry(exchange, symbol, spot_retry),
+        exchange.get_balance(quote_currency),
     );
 
-    if available_collateral <= 0.0 {
-        // Эта проверка может быть излишней, если sum всегда > adjusted_spot_value, но оставим для надежности
+    let linear_info = linear_info_res.map_err(|e| anyhow!("Failed to get LINEAR instrument info: {}", e))?;
+    ensure_instrument_trading(&linear_info.symbol, linear_info.status.as_deref())?;
+    let bracket = bracket_res.map_err(|e| anyhow!("Failed to get MMR for {}: {}", futures_symbol, e))?;
+    let (mmr, max_allowed_leverage) = (bracket.mmr, max_allowed_leverage.min(bracket.max_leverage));
+    let current_spot_price = spot_price_res.map_err(|e| anyhow!("Failed to get spot price for {}: {}", symbol, e))?;
+    if current_spot_price <= 0.0 {
+        return Err(anyhow!("Invalid spot price: {}", current_spot_price));
+    }
+    // Без спота залогом служит только свободный quote — без баланса размер не проверить
+    let available_collateral = quote_balance_res
+        .map_err(|e| anyhow!("Failed to get {} balance for collateral: {}", quote_currency, e))?
+        .free;
+
+    let (fut_decimals, min_fut_qty_decimal) = futures_qty_precision(&linear_info)?;
+    let spot_info = spot_info_res.map_err(|e| anyhow!("Failed to get SPOT instrument info: {}", e))?;
+    let (spot_decimals, min_spot_qty_decimal) = spot_qty_precision(&spot_info)?;
+    let fut_qty_decimal = (to_decimal(*sum, "sum")? / to_decimal(current_spot_price, "spot price")?)
+        .trunc_with_scale(fut_decimals);
+    if fut_qty_decimal <= Decimal::ZERO || fut_qty_decimal < min_fut_qty_decimal {
         return Err(anyhow!(
-            "Available collateral non-positive after spot value calculation (Sum: {}, Spot Value: {})",
-            sum, adjusted_spot_value
+            "Target futures quantity {:.8} < min futures quantity {}",
+            fut_qty_decimal,
+            min_fut_qty_decimal
         ));
     }
+    let fut_order_qty = to_f64(fut_qty_decimal, "futures qty")?;
 
-    let required_leverage = futures_position_value / available_collateral;
-    debug!("Calculated required leverage: {}", required_leverage);
-
-    if required_leverage.is_nan() || required_leverage.is_infinite() {
+    let futures_position_value = fut_order_qty * current_spot_price;
+    let required_collateral = futures_position_value * (volatility + mmr);
+    if available_collateral <= 0.0 || available_collateral < required_collateral {
         return Err(anyhow!(
-            "Invalid leverage calculation (Fut Value: {}, Collateral: {})",
-            futures_position_value, available_collateral
+            "Insufficient collateral: free {} {:.2} < required {:.2} (position {:.2} x (volatility {:.2}% + MMR {:.2}%))",
+            quote_currency, available_collateral, required_collateral,
+            futures_position_value, volatility * 100.0, mmr * 100.0
         ));
     }
+
+    let required_leverage = futures_position_value / available_collateral;
     if required_leverage > max_allowed_leverage {
         return Err(anyhow!(
             "Required leverage {:.2}x > max allowed {:.2}x",
@@ -212,28 +410,267 @@ where
         ));
     }
     info!(
-        "Required leverage {:.2}x is within max allowed {:.2}x",
-        required_leverage, max_allowed_leverage
+        "Futures-only hedge for {}: qty {:.8}, collateral {:.2} {}, leverage {:.2}x",
+        symbol, fut_order_qty, available_collateral, quote_currency, required_leverage
     );
 
-    // Начальная цена для лимитного ордера (для run_hedge)
-    let initial_limit_price = current_spot_price * (1.0 - slippage);
-    debug!("Initial limit price for spot buy: {}", initial_limit_price);
-
-    // futures_symbol уже был создан выше перед вызовом get_mmr
-
     Ok(HedgeParams {
-        spot_order_qty,
+        spot_order_qty: 0.0,
         fut_order_qty,
         current_spot_price,
-        initial_limit_price,
+        initial_limit_price: current_spot_price * (1.0 - slippage), // Спот не покупается
         symbol: symbol.clone(),
-        spot_value: adjusted_spot_value,
+        hedge_sum: *sum,
+        spot_value: futures_position_value, // Стоимость внешнего спота, который закрывает шорт
         available_collateral,
-        min_spot_qty_decimal, // Передаем дальше
-        min_fut_qty_decimal,  // Передаем дальше
-        spot_decimals,        // Передаем дальше
-        fut_decimals,         // Передаем дальше
-        futures_symbol,       // Используем уже созданный futures_symbol
+        borrow_required: 0.0,
+        estimated_liquidation_price: estimate_short_liquidation_price(current_spot_price, fut_order_qty, available_collateral, mmr),
+        min_spot_qty_decimal,
+        min_fut_qty_decimal,
+        spot_decimals,
+        fut_decimals,
+        futures_symbol,
+        spot_price_guard: None, // Спот не покупается
+        futures_only: true,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::mock::MockExchange;
+    use crate::exchange::types::Balance;
+    use std::time::Duration;
+
+    // sum=1000, volatility=0.1, mmr=0 → spot value 909.0909, при цене 100: 9.090909 BTC
+    fn request() -> HedgeRequest {
+        HedgeRequest { sum: 1000.0, symbol: "BTC".to_string(), volatility: 0.1, spot_price_guard: None }
+    }
+
+    fn spot_retry() -> SpotPriceRetry {
+        SpotPriceRetry::from_config(&crate::config::test_config("spot_price_retry_delay_ms = 1"))
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "expected {}, got {}", expected, actual);
+    }
+
+    #[tokio::test]
+    async fn zero_spot_price_is_refetched_for_params() {
+        let exchange = MockExchange::default();
+        exchange.return_zero_spot_prices(1);
+
+        let params = calculate_hedge_params_impl(&exchange, &request(), 0.005, "USDT", 20.0, false, spot_retry())
+            .await
+            .expect("params after a zero ticker");
+
+        assert_close(params.fut_order_qty, 9.09);
+    }
+
+    #[tokio::test]
+    async fn normal_case_matches_hand_computed_values() {
+        let exchange = MockExchange::default();
+        let params = calculate_hedge_params_impl(&exchange, &request(), 0.005, "USDT", 20.0, false, spot_retry())
+            .await
+            .expect("params");
+
+        // фьючерс: 9.090909 → 9.09 (шаг 0.01)
+        assert_close(params.fut_order_qty, 9.09);
+        // спот: 9.09 / (1 - 0.001) = 9.099099 → 9.0990 (точность 0.0001)
+        assert_close(params.spot_order_qty, 9.099);
+        // залог: 1000 - 9.099 * 100
+        assert_close(params.available_collateral, 90.1);
+        assert_close(params.spot_value, 909.9);
+        assert_close(params.initial_limit_price, 99.5);
+        assert_eq!(params.futures_symbol, "BTCUSDT");
+        assert_eq!(params.spot_decimals, 4);
+        assert_eq!(params.fut_decimals, 2);
+        // ликвидация: 100 + 90.1 / 9.09 (mmr = 0)
+        assert_close(params.estimated_liquidation_price.expect("liq price"), 100.0 + 90.1 / 9.09);
+    }
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::from_str(value).expect("decimal literal")
+    }
+
+    fn inputs(sum: &str, volatility: &str, price: &str) -> HedgeInputs {
+        HedgeInputs { sum: dec(sum), volatility: dec(volatility), mmr: Decimal::ZERO, spot_fee: dec("0.001"), price: dec(price) }
+    }
+
+    #[test]
+    fn decimal_math_does_not_drop_a_step_where_f64_drifts() {
+        // 0.6 / 2 / 0.1 в f64 = 2.9999999999999996 → по шагу 1 получилось бы 2 контракта
+        assert_eq!((0.6_f64 / (1.0 + 1.0) / 0.1).trunc(), 2.0);
+
+        let lots = LotRules { decimals: 0, min_qty: dec("3") };
+        let spot = LotRules { decimals: 4, min_qty: dec("0.0001") };
+        let amounts = compute_hedge_amounts(inputs("0.6", "1", "0.1"), &spot, &lots).expect("amounts");
+        // В Decimal ровно 3 — и минимальный объем 3 проходит
+        assert_eq!(amounts.fut_qty, dec("3"));
+        // 3 / 0.999 = 3.003003... → 3.003
+        assert_eq!(amounts.spot_qty, dec("3.003"));
+        assert_eq!(amounts.spot_value, dec("0.3003"));
+        assert_eq!(amounts.available_collateral, dec("0.2997"));
+    }
+
+    #[test]
+    fn decimal_collateral_is_exact_where_f64_accumulates_error() {
+        // В f64: 1000 - 9.099 * 100 = 90.10000000000002
+        assert_ne!(1000.0 - 9.099 * 100.0, 90.1);
+
+        let spot = LotRules { decimals: 4, min_qty: dec("0.0001") };
+        let fut = LotRules { decimals: 2, min_qty: dec("0.01") };
+        let amounts = compute_hedge_amounts(inputs("1000", "0.1", "100"), &spot, &fut).expect("amounts");
+        assert_eq!(amounts.fut_qty, dec("9.09"));
+        assert_eq!(amounts.spot_qty, dec("9.099"));
+        assert_eq!(amounts.available_collateral, dec("90.1"));
+        assert_eq!(amounts.futures_value, dec("909"));
+    }
+
+    #[test]
+    fn snapped_sum_yields_whole_step_quantity() {
+        let spot = LotRules { decimals: 4, min_qty: dec("0.0001") };
+        let fut = LotRules { decimals: 3, min_qty: dec("0.001") };
+        let raw = HedgeInputs { mmr: dec("0.005"), ..inputs("1000", "0.1", "30000") };
+        // 1000 / 1.1 / 1.005 / 30000 = 0.030153... → 0.0301 по шагу спота, дробь шага отбрасывается
+        let snapped = snap_sum_to_qty(raw, &spot).expect("snapped sum");
+        assert_eq!(snapped, dec("998.2665"));
+        // Идеальный объем спота при подогнанной сумме ровно на шаге
+        let ideal_qty = snapped / ((Decimal::ONE + raw.volatility) * (Decimal::ONE + raw.mmr)) / raw.price;
+        assert_eq!(ideal_qty, dec("0.0301"));
+        assert_eq!(ideal_qty, ideal_qty.trunc_with_scale(spot.decimals));
+        // Повторная подгонка при той же цене сумму не меняет
+        assert_eq!(snap_sum_to_qty(HedgeInputs { sum: snapped, ..raw }, &spot).expect("snapped sum"), snapped);
+        assert_eq!(compute_hedge_amounts(HedgeInputs { sum: snapped, ..raw }, &spot, &fut).expect("amounts").fut_qty, dec("0.030"));
+    }
+
+    #[tokio::test]
+    async fn confirmed_sum_is_not_snapped_again_before_launch() {
+        let exchange = MockExchange::default();
+        let hedger = crate::hedger::Hedger::new(exchange.clone(), crate::config::test_config("snap_sum_to_qty = true"));
+        // 1000 / 1.1 / 100 = 9.090909... → 9.0909 по шагу спота 0.0001
+        let shown = hedger.calculate_hedge_params(&request()).await.expect("params");
+        assert_close(shown.hedge_sum, 999.999);
+
+        // Цена сдвинулась до запуска: подтвержденная сумма остается той, что видел пользователь
+        exchange.move_spot_price(101.0);
+        let confirmed_request = HedgeRequest { sum: shown.hedge_sum, ..request() };
+        let launched = hedger.calculate_confirmed_hedge_params(&confirmed_request).await.expect("params");
+        assert_close(launched.hedge_sum, shown.hedge_sum);
+        let resnapped = hedger.calculate_hedge_params(&confirmed_request).await.expect("params");
+        assert!((resnapped.hedge_sum - shown.hedge_sum).abs() > 1e-9);
+    }
+
+    #[test]
+    fn liquidation_estimate_accounts_for_mmr_and_flat_position() {
+        // 10 контрактов по 100, залог 100, MMR 0.5%: 100 + (100 - 5) / 10
+        assert_close(estimate_short_liquidation_price(100.0, 10.0, 100.0, 0.005).expect("liq price"), 109.5);
+        assert_eq!(estimate_short_liquidation_price(100.0, 0.0, 100.0, 0.005), None);
+    }
+
+    #[tokio::test]
+    async fn borrow_is_required_when_quote_balance_is_short() {
+        let short = MockExchange {
+            balances: vec![("USDT".to_string(), Balance { free: 500.0, locked: 0.0 })],
+            ..MockExchange::default()
+        };
+        let params = calculate_hedge_params_impl(&short, &request(), 0.005, "USDT", 20.0, false, spot_retry()).await.expect("params");
+        assert_close(params.borrow_required, 409.9);
+
+        let funded = MockExchange {
+            balances: vec![("USDT".to_string(), Balance { free: 1000.0, locked: 0.0 })],
+            ..MockExchange::default()
+        };
+        let params = calculate_hedge_params_impl(&funded, &request(), 0.005, "USDT", 20.0, false, spot_retry()).await.expect("params");
+        assert_close(params.borrow_required, 0.0);
+    }
+
+    #[tokio::test]
+    async fn market_data_is_fetched_concurrently() {
+        let exchange = MockExchange { fetch_delay: Some(Duration::from_millis(20)), ..MockExchange::default() };
+        calculate_hedge_params_impl(&exchange, &request(), 0.005, "USDT", 20.0, false, spot_retry())
+            .await
+            .expect("params");
+        // Все пять запросов (spot info, linear info, fee, mmr, price) были в полете одновременно
+        assert_eq!(exchange.max_in_flight(), 5);
+    }
+
+    #[tokio::test]
+    async fn rejects_spot_qty_below_minimum() {
+        let exchange = MockExchange { spot_min_qty: "10".to_string(), ..MockExchange::default() };
+        let err = calculate_hedge_params_impl(&exchange, &request(), 0.005, "USDT", 20.0, false, spot_retry())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("min spot quantity"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn rejects_futures_qty_below_minimum() {
+        let exchange = MockExchange { fut_min_qty: "10".to_string(), ..MockExchange::default() };
+        let err = calculate_hedge_params_impl(&exchange, &request(), 0.005, "USDT", 20.0, false, spot_retry())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("min futures quantity"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn rejects_leverage_above_cap() {
+        // требуемое плечо: 909 / 90.1 ≈ 10.09x
+        let exchange = MockExchange::default();
+        let err = calculate_hedge_params_impl(&exchange, &request(), 0.005, "USDT", 5.0, false, spot_retry())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Required leverage 10.09x"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn rejects_non_trading_futures_symbol() {
+        let exchange = MockExchange { linear_status: Some("Delivering".to_string()), ..MockExchange::default() };
+        let err = calculate_hedge_params_impl(&exchange, &request(), 0.005, "USDT", 20.0, false, spot_retry())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not available for trading (status: Delivering)"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn futures_only_shorts_full_sum_against_free_quote() {
+        let exchange = MockExchange {
+            balances: vec![("USDT".to_string(), Balance { free: 200.0, locked: 0.0 })],
+            ..MockExchange::default()
+        };
+        let params = calculate_futures_only_params_impl(&exchange, &request(), 0.005, "USDT", 20.0, spot_retry())
+            .await
+            .expect("params");
+
+        // 1000 / 100 = 10 контрактов, спот не покупается
+        assert_close(params.fut_order_qty, 10.0);
+        assert_close(params.spot_order_qty, 0.0);
+        assert_close(params.available_collateral, 200.0);
+        assert_close(params.borrow_required, 0.0);
+        assert_close(params.estimated_liquidation_price.expect("liq price"), 120.0);
+        assert!(params.futures_only);
+        assert_eq!(params.spot_decimals, 4, "spot precision comes from the spot instrument");
+    }
+
+    #[tokio::test]
+    async fn futures_only_rejects_insufficient_collateral() {
+        // Нужно 1000 * (0.1 + 0) = 100 USDT залога, свободно 50
+        let exchange = MockExchange {
+            balances: vec![("USDT".to_string(), Balance { free: 50.0, locked: 0.0 })],
+            ..MockExchange::default()
+        };
+        let err = calculate_futures_only_params_impl(&exchange, &request(), 0.005, "USDT", 20.0, spot_retry())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Insufficient collateral"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn rejects_zero_spot_price() {
+        let exchange = MockExchange { spot_price: 0.0, ..MockExchange::default() };
+        let err = calculate_hedge_params_impl(&exchange, &request(), 0.005, "USDT", 20.0, false, spot_retry())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Invalid spot price"), "{}", err);
+    }
+}
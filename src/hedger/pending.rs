@@ -0,0 +1,238 @@
+// src/hedger/pending.rs
+// Наблюдение за фьючерсными ордерами, оставленными на бирже после таймаута (статус PendingFutures)
+
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::sleep;
+use tracing::{debug, error, info, warn};
+
+use crate::exchange::Exchange;
+use crate::exchange::types::{ExchangeError, OrderStatus};
+use crate::hedger::common::{exchange_qty_precision, QtyPrecision};
+use crate::hedger::watchers::{WatcherKind, WatcherRegistry, WatcherSummary};
+use crate::storage::{finish_pending_futures_operation, get_pending_futures_operations, Db, OperationStatus};
+
+const PENDING_FUTURES_CHECK_INTERVAL_SECS: u64 = 15;
+/// Пауза проверок, пока биржа на техобслуживании (ордер не исполнится и не исчезнет)
+const MAINTENANCE_BACKOFF_SECS: u64 = 120;
+
+/// Фьючерсный ордер операции, оставленный на бирже
+#[derive(Debug, Clone)]
+pub struct PendingFuturesOrder {
+    pub operation_id: i64,
+    pub futures_symbol: String,
+    pub quote_currency: String, // Для шага количества контракта
+    pub order_id: String,
+    pub base_filled_qty: f64, // Исполнено до оставленного ордера
+    pub target_qty: f64,
+}
+
+/// Запускает фоновую проверку оставленного фьючерсного ордера до его исполнения или исчезновения.
+/// Монитор виден в /watchers чата chat_id
+pub fn spawn_pending_futures_monitor<E>(exchange: E, db: Db, registry: &WatcherRegistry, chat_id: i64, order: PendingFuturesOrder)
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let PendingFuturesOrder { operation_id, futures_symbol, quote_currency, order_id, base_filled_qty, target_qty } = order;
+    let watcher_id = registry.next_id();
+    let summary = WatcherSummary {
+        id: watcher_id,
+        kind: WatcherKind::PendingFutures,
+        chat_id,
+        operation_id,
+        symbol: futures_symbol.clone(),
+        details: format!("ордер {}, цель {:.8}", order_id, target_qty),
+    };
+    let task_registry = registry.clone();
+    // Задача ждет регистрации: иначе быстро завершившийся монитор снимет себя раньше, чем попадет в реестр
+    let (registered_tx, registered_rx) = oneshot::channel::<()>();
+    let task = tokio::spawn(async move {
+        let _ = registered_rx.await;
+        info!(
+            "op_id:{}: Monitoring pending futures order {} for {} (target {:.8}).",
+            operation_id, order_id, futures_symbol, target_qty
+        );
+        let qty_precision = exchange_qty_precision(&exchange, &quote_currency, &futures_symbol, false).await;
+        let mut next_check = Duration::from_secs(PENDING_FUTURES_CHECK_INTERVAL_SECS);
+        loop {
+            sleep(next_check).await;
+            next_check = Duration::from_secs(PENDING_FUTURES_CHECK_INTERVAL_SECS);
+            let (status, filled_qty, error_message) =
+                match exchange.get_futures_order_status(&futures_symbol, &order_id).await {
+                    Ok(order_status) => match pending_order_outcome(&order_status, &order_id, base_filled_qty, target_qty, qty_precision) {
+                        Some(outcome) => outcome,
+                        None => {
+                            debug!(
+                                "op_id:{}: Pending futures order {} still active (filled {:.8}, remaining {:.8}).",
+                                operation_id, order_id, order_status.filled_qty, order_status.remaining_qty
+                            );
+                            continue;
+                        }
+                    },
+                    Err(e) if ExchangeError::is_order_not_found(&e) => (
+                        OperationStatus::Failed,
+                        base_filled_qty,
+                        Some(format!("Pending futures order {} no longer found on exchange", order_id)),
+                    ),
+                    Err(e) if ExchangeError::is_maintenance(&e) => {
+                        info!(
+                            "op_id:{}: Exchange under maintenance, pausing pending futures checks for {}s.",
+                            operation_id, MAINTENANCE_BACKOFF_SECS
+                        );
+                        next_check = Duration::from_secs(MAINTENANCE_BACKOFF_SECS);
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("op_id:{}: Failed to check pending futures order {}: {}", operation_id, order_id, e);
+                        continue;
+                    }
+                };
+
+            info!(
+                "op_id:{}: Pending futures order {} finished. Status: {}, futures filled: {:.8}",
+                operation_id, order_id, status, filled_qty
+            );
+            if let Err(e) = finish_pending_futures_operation(&db, operation_id, status, filled_qty, error_message.as_deref()).await {
+                error!("op_id:{}: Failed to finish pending futures operation in DB: {}", operation_id, e);
+            }
+            break;
+        }
+        task_registry.remove(watcher_id);
+    });
+    registry.register(task.abort_handle(), summary);
+    let _ = registered_tx.send(());
+}
+
+/// Итог проверки оставленного ордера: None — ордер еще активен, иначе (статус, исполнено всего, ошибка)
+fn pending_order_outcome(
+    order_status: &OrderStatus,
+    order_id: &str,
+    base_filled_qty: f64,
+    target_qty: f64,
+    qty_precision: QtyPrecision,
+) -> Option<(OperationStatus, f64, Option<String>)> {
+    if order_status.remaining_qty > qty_precision.tolerance() && !order_status.status.is_cancelled() {
+        return None;
+    }
+    let total_filled = base_filled_qty + order_status.filled_qty;
+    if qty_precision.target_reached(total_filled, target_qty) {
+        Some((OperationStatus::Completed, total_filled, None))
+    } else {
+        // Ордер закрыт (например, отменен вручную) без полного исполнения
+        Some((OperationStatus::Failed, total_filled, Some(format!(
+            "Pending futures order {} closed with partial fill {:.8}/{:.8}",
+            order_id, total_filled, target_qty
+        ))))
+    }
+}
+
+/// Возобновляет наблюдение за операциями PendingFutures текущего окружения биржи (после перезапуска бота).
+/// Ордера другого окружения этим ключом не опрашиваются
+pub async fn resume_pending_futures_monitors<E>(exchange: E, db: Db, registry: &WatcherRegistry, environment: &str)
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let operations = match get_pending_futures_operations(&db).await {
+        Ok(ops) => ops,
+        Err(e) => {
+            error!("Failed to load PendingFutures operations: {}", e);
+            return;
+        }
+    };
+    for op in operations {
+        if !op.belongs_to_environment(environment) {
+            info!("op_id:{}: PendingFutures operation belongs to {:?}, not {}. Skipping.", op.id, op.environment, environment);
+            continue;
+        }
+        let Some(order_id) = op.futures_order_id.clone() else {
+            warn!("op_id:{}: PendingFutures operation has no futures order id. Skipping.", op.id);
+            continue;
+        };
+        let order = PendingFuturesOrder {
+            operation_id: op.id,
+            futures_symbol: op.futures_contract(),
+            quote_currency: op.quote_currency.clone(),
+            order_id,
+            base_filled_qty: op.futures_filled_qty,
+            target_qty: op.target_futures_qty,
+        };
+        spawn_pending_futures_monitor(exchange.clone(), db.clone(), registry, op.chat_id, order);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::mock::MockExchange;
+    use crate::exchange::types::OrderStatusText;
+
+    fn status(filled_qty: f64, remaining_qty: f64, status: OrderStatusText) -> OrderStatus {
+        OrderStatus { filled_qty, remaining_qty, status }
+    }
+
+    #[test]
+    fn pending_order_outcome_follows_order_state() {
+        let unknown_step = QtyPrecision::new(None);
+        // Ордер еще стоит — наблюдение продолжается
+        assert!(pending_order_outcome(&status(0.4, 0.6, OrderStatusText::PartiallyFilled), "o1", 0.5, 1.5, unknown_step).is_none());
+
+        // Исполнен до цели (с учетом исполненного до оставленного ордера)
+        let (op_status, filled, error) = pending_order_outcome(&status(1.0, 0.0, OrderStatusText::Filled), "o1", 0.5, 1.5, unknown_step).expect("finished");
+        assert_eq!(op_status, OperationStatus::Completed);
+        assert_eq!(filled, 1.5);
+        assert!(error.is_none());
+
+        // Отменен вручную с частичным исполнением
+        let (op_status, filled, error) = pending_order_outcome(&status(0.4, 0.0, OrderStatusText::PartiallyFilledCanceled), "o1", 0.5, 1.5, unknown_step).expect("finished");
+        assert_eq!(op_status, OperationStatus::Failed);
+        assert!((filled - 0.9).abs() < 1e-9);
+        assert!(error.expect("reason").contains("partial fill"));
+    }
+
+    #[test]
+    fn pending_order_outcome_compares_on_qty_step() {
+        let step = QtyPrecision::new(Some(rust_decimal::Decimal::new(1, 2))); // 0.01
+        // Остаток меньше половины шага уже не исполнить — ордер считается закрытым
+        assert!(pending_order_outcome(&status(0.999, 0.001, OrderStatusText::PartiallyFilled), "o1", 0.5, 1.5, step).is_some());
+        // Недобор в пределах шага после округления — цель достигнута
+        let (op_status, _, error) = pending_order_outcome(&status(0.9999, 0.0, OrderStatusText::Filled), "o1", 0.5, 1.5, step).expect("finished");
+        assert_eq!(op_status, OperationStatus::Completed);
+        assert!(error.is_none());
+        // Остаток в целый шаг — ордер еще активен
+        assert!(pending_order_outcome(&status(0.99, 0.01, OrderStatusText::PartiallyFilled), "o1", 0.5, 1.5, step).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn monitor_is_registered_in_injected_registry_on_spawn() {
+        let db = sqlx::sqlite::SqlitePoolOptions::new().connect_lazy("sqlite::memory:").expect("lazy db");
+        let registry = WatcherRegistry::default();
+        let order = PendingFuturesOrder {
+            operation_id: 7,
+            futures_symbol: "ETHUSDT".to_string(),
+            quote_currency: "USDT".to_string(),
+            order_id: "fut-1".to_string(),
+            base_filled_qty: 0.0,
+            target_qty: 1.0,
+        };
+
+        spawn_pending_futures_monitor(MockExchange::default(), db, &registry, 42, order);
+        // Монитор зарегистрирован до первого шага задачи, в чате операции
+        let listed = registry.list_for_chat(42);
+        assert_eq!(listed.len(), 1);
+        assert_eq!((listed[0].operation_id, listed[0].kind), (7, WatcherKind::PendingFutures));
+        assert!(WatcherRegistry::default().list_for_chat(42).is_empty());
+
+        // Остановка через тот же реестр прерывает задачу
+        assert!(registry.cancel(42, listed[0].id).is_some());
+        sleep(Duration::from_secs(PENDING_FUTURES_CHECK_INTERVAL_SECS * 2)).await;
+        assert!(registry.list_for_chat(42).is_empty());
+    }
+
+    #[test]
+    fn missing_order_is_detected_by_error_type() {
+        let not_found: anyhow::Error = ExchangeError::OrderNotFound("o1 (BTCUSDT)".into()).into();
+        assert!(ExchangeError::is_order_not_found(&not_found));
+        // Текст ошибки без типа не считается пропажей ордера
+        assert!(!ExchangeError::is_order_not_found(&anyhow::anyhow!("Order not found")));
+    }
+}
@@ -0,0 +1,437 @@
+// src/hedger/recovery.rs
+// Операции, прерванные перезапуском бота (остались в статусе Running): свежие возобновляются, старые — на ручную проверку
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::task::AbortHandle;
+use tracing::{error, info, warn};
+
+use crate::config::Config;
+use crate::exchange::Exchange;
+use crate::hedger::common::qty_precision_for;
+use crate::hedger::pending::{spawn_pending_futures_monitor, PendingFuturesOrder};
+use crate::hedger::{
+    ActiveOrderStorage, HedgeProgressCallback, HedgeProgressUpdate, HedgeStage, Hedger, SpotOnlyOrphan, StageStorage, WatcherRegistry,
+    ORDER_FILL_TOLERANCE,
+};
+use crate::storage::{
+    get_running_hedge_operations, mark_hedge_interrupted, mark_hedge_pending_futures, mark_hedge_spot_only_orphan,
+    update_hedge_spot_order, Db, HedgeOperation,
+};
+
+/// Что делать с прерванной операцией при старте
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecoveryAction {
+    ReattachFuturesOrder { order_id: String }, // Фьючерсный ордер остался на бирже: следить за ним (PendingFutures)
+    PlaceFuturesLeg,                           // Спот куплен, фьючерса нет: довыставить фьючерсную ногу
+    ManualReview { reason: String },           // Статус Interrupted: позиции проверяются вручную
+}
+
+/// Итог восстановления одной операции
+#[derive(Debug)]
+pub struct RecoveryReport {
+    pub operation: HedgeOperation,
+    pub action: RecoveryAction,
+    pub result: Result<()>,
+}
+
+/// Операция, возобновленная задачей довыставления фьючерса: хэндл и хранилища для отмены, как у обычного хеджа
+pub struct ResumedOperation {
+    pub operation: HedgeOperation,
+    pub handle: AbortHandle,
+    pub active_order: ActiveOrderStorage,
+    pub stage: StageStorage,
+}
+
+/// Учет возобновленной операции на время задачи (в боте — запись в /active, ее видят /cancel и /resolve).
+/// Учет снимается, когда возвращенное значение уничтожается
+pub type ResumeTracker = Box<dyn Fn(ResumedOperation) -> BoxFuture<'static, Box<dyn Send>> + Send + Sync>;
+
+/// Решение по прерванной операции: в пределах resume_grace_secs от старта — возобновление по типу прерывания,
+/// иначе (и при resume_grace_secs = 0) — ручная проверка
+pub fn plan_recovery(operation: &HedgeOperation, now: i64, resume_grace_secs: u64) -> RecoveryAction {
+    if resume_grace_secs == 0 {
+        return RecoveryAction::ManualReview { reason: "Interrupted by restart (auto-resume disabled)".to_string() };
+    }
+    let age_secs = now.saturating_sub(operation.start_timestamp);
+    if age_secs > resume_grace_secs as i64 {
+        return RecoveryAction::ManualReview {
+            reason: format!("Interrupted by restart {}s after start (resume grace {}s expired)", age_secs, resume_grace_secs),
+        };
+    }
+    if let Some(order_id) = operation.futures_order_id.clone() {
+        return RecoveryAction::ReattachFuturesOrder { order_id };
+    }
+    // Довыставление фьючерса работает с бессрочным контрактом; роллированные операции проверяются вручную
+    if operation.spot_filled_qty > ORDER_FILL_TOLERANCE && operation.futures_symbol.is_none() {
+        return RecoveryAction::PlaceFuturesLeg;
+    }
+    RecoveryAction::ManualReview { reason: "Interrupted by restart before any leg could be resumed".to_string() }
+}
+
+/// Разбирает все операции Running, оставшиеся от прошлого запуска. Довыставление фьючерса ждет завершения,
+/// поэтому вызывается в отдельной задаче после resume_pending_futures_monitors.
+/// Довыставление фьючерса идет в отдельной задаче, учтенной через tracker на время ее работы
+pub async fn recover_interrupted_operations<E>(
+    exchange: E,
+    config: Config,
+    db: Db,
+    watchers: WatcherRegistry,
+    tracker: ResumeTracker,
+) -> Vec<RecoveryReport>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    // Операции другого окружения биржи не трогаем: их разберет запуск с ключами того окружения
+    let environment = config.environment();
+    let operations = match get_running_hedge_operations(&db).await {
+        Ok(ops) => ops
+            .into_iter()
+            .filter(|op| {
+                let own = op.belongs_to_environment(environment);
+                if !own {
+                    info!("op_id:{}: Running operation belongs to {:?}, not {}. Skipping recovery.", op.id, op.environment, environment);
+                }
+                own
+            })
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            error!("Failed to load interrupted Running operations: {}", e);
+            return Vec::new();
+        }
+    };
+    if operations.is_empty() {
+        return Vec::new();
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+    let resume_grace_secs = config.resume_grace_secs;
+    let hedger = Hedger::new(exchange, config).with_watchers(watchers);
+
+    let mut reports = Vec::with_capacity(operations.len());
+    for operation in operations {
+        let action = plan_recovery(&operation, now, resume_grace_secs);
+        info!("op_id:{}: Operation interrupted by restart, recovery action: {:?}", operation.id, action);
+        let applied = apply_recovery(&hedger, &db, &operation, &action, &tracker).await;
+        let (action, result) = match applied {
+            Ok(applied_action) => (applied_action, Ok(())),
+            Err(e) => {
+                warn!("op_id:{}: Recovery {:?} failed: {}", operation.id, action, e);
+                (action, Err(e))
+            }
+        };
+        reports.push(RecoveryReport { operation, action, result });
+    }
+    reports
+}
+
+/// Возвращает фактически выполненное действие: прерванный срез фьючерса вместо слежения довыставляется (PlaceFuturesLeg)
+async fn apply_recovery<E>(
+    hedger: &Hedger<E>,
+    db: &Db,
+    operation: &HedgeOperation,
+    action: &RecoveryAction,
+    tracker: &ResumeTracker,
+) -> Result<RecoveryAction>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    match action {
+        RecoveryAction::ReattachFuturesOrder { order_id } => {
+            // Ордер среза айсберга закрывает только часть ноги (в БД — цель всей ноги): за ним не следим, а довыставляем остаток
+            if operation.futures_symbol.is_none()
+                && let Some(order_end_qty) = futures_order_end_qty(hedger, operation, order_id).await
+                && !qty_precision_for(hedger, &operation.futures_contract(), false).await.target_reached(order_end_qty, operation.target_futures_qty)
+            {
+                resume_after_futures_slice(hedger, db, operation, order_id, tracker).await?;
+                return Ok(RecoveryAction::PlaceFuturesLeg);
+            }
+            mark_hedge_pending_futures(db, operation.id, order_id, operation.futures_filled_qty, operation.target_futures_qty).await?;
+            let order = PendingFuturesOrder {
+                operation_id: operation.id,
+                futures_symbol: operation.futures_contract(),
+                quote_currency: operation.quote_currency.clone(),
+                order_id: order_id.clone(),
+                base_filled_qty: operation.futures_filled_qty,
+                target_qty: operation.target_futures_qty,
+            };
+            spawn_pending_futures_monitor(hedger.exchange.clone(), db.clone(), &hedger.watchers, operation.chat_id, order);
+            Ok(action.clone())
+        }
+        RecoveryAction::PlaceFuturesLeg => {
+            let mut operation = operation.clone();
+            // Спотовая лимитка могла остаться на бирже: докупать спот после перезапуска не нужно
+            if let Some(spot_order_id) = operation.spot_order_id.clone() {
+                if let Err(e) = hedger.exchange.cancel_spot_order(&operation.base_symbol, &spot_order_id).await {
+                    info!("op_id:{}: Spot order {} not cancelled on recovery (likely already closed): {}", operation.id, spot_order_id, e);
+                }
+                operation.spot_filled_qty = settle_spot_fill(hedger, db, &operation, &spot_order_id).await?;
+            }
+            mark_hedge_spot_only_orphan(db, operation.id, operation.futures_filled_qty, "Interrupted by restart, resuming futures leg").await?;
+            resume_futures_leg_tracked(hedger, db, operation, tracker).await?;
+            Ok(action.clone())
+        }
+        RecoveryAction::ManualReview { reason } => {
+            if !mark_hedge_interrupted(db, operation.id, reason).await? {
+                warn!("op_id:{}: Operation was no longer Running when marking as Interrupted.", operation.id);
+            }
+            Ok(action.clone())
+        }
+    }
+}
+
+/// Объем ноги, на котором закончится оставленный фьючерсный ордер; None — статус недоступен (ордер восстанавливается как есть)
+async fn futures_order_end_qty<E>(hedger: &Hedger<E>, operation: &HedgeOperation, order_id: &str) -> Option<f64>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    match hedger.exchange.get_futures_order_status(&operation.futures_contract(), order_id).await {
+        Ok(status) => Some(operation.futures_filled_qty + status.filled_qty + status.remaining_qty),
+        Err(e) => {
+            warn!("op_id:{}: Futures order {} status unavailable on recovery, reattaching as is: {}", operation.id, order_id, e);
+            None
+        }
+    }
+}
+
+/// Перезапуск прервал не последний срез фьючерса: ордер среза снимается, остаток ноги выставляется одним ордером.
+/// Если исполнение среза узнать не удалось, операция остается в SpotOnlyOrphan для ручного довыставления
+async fn resume_after_futures_slice<E>(
+    hedger: &Hedger<E>,
+    db: &Db,
+    operation: &HedgeOperation,
+    order_id: &str,
+    tracker: &ResumeTracker,
+) -> Result<()>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let mut operation = operation.clone();
+    let futures_symbol = operation.futures_contract();
+    if let Err(e) = hedger.exchange.cancel_futures_order(&futures_symbol, order_id).await {
+        info!("op_id:{}: Futures slice order {} not cancelled on recovery (likely already closed): {}", operation.id, order_id, e);
+    }
+    let order_filled_qty = match hedger.exchange.get_futures_order_status(&futures_symbol, order_id).await {
+        Ok(status) => status.filled_qty,
+        Err(e) => {
+            let orphan = SpotOnlyOrphan {
+                spot_filled_qty: operation.spot_filled_qty,
+                futures_filled_qty: operation.futures_filled_qty,
+                reason: format!("Interrupted by restart mid futures slice, slice order {} status unavailable: {}", order_id, e),
+            };
+            mark_hedge_spot_only_orphan(db, operation.id, orphan.futures_filled_qty, &orphan.reason).await?;
+            return Err(orphan.into());
+        }
+    };
+    operation.futures_filled_qty += order_filled_qty;
+    info!(
+        "op_id:{}: Futures slice order {} cancelled with {:.8} filled, futures filled {:.8} of {:.8}. Resuming the rest of the leg.",
+        operation.id, order_id, order_filled_qty, operation.futures_filled_qty, operation.target_futures_qty
+    );
+    mark_hedge_spot_only_orphan(db, operation.id, operation.futures_filled_qty, "Interrupted by restart mid futures slice, resuming futures leg").await?;
+    resume_futures_leg_tracked(hedger, db, operation, tracker).await
+}
+
+/// Довыставление фьючерсной ноги операции в SpotOnlyOrphan в отдельной задаче, учтенной через tracker
+async fn resume_futures_leg_tracked<E>(hedger: &Hedger<E>, db: &Db, operation: HedgeOperation, tracker: &ResumeTracker) -> Result<()>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    // Отдельный Hedger: свои хранилища ордера и этапа для отмены из /active
+    let resumer = Hedger::new(hedger.exchange.clone(), hedger.config.clone()).with_watchers(hedger.watchers.clone());
+    *resumer.stage.lock().await = HedgeStage::Futures; // Спот уже куплен: отмена бросает только фьючерсную ногу
+    let (active_order, stage) = (resumer.active_order_storage(), resumer.stage_storage());
+    // Прогресс не показываем: итог уходит отдельным сообщением
+    let progress_callback: HedgeProgressCallback =
+        Box::new(|_update: HedgeProgressUpdate| async { Ok::<(), anyhow::Error>(()) }.boxed());
+    let task = tokio::spawn({
+        let (operation, db) = (operation.clone(), db.clone());
+        async move { resumer.resume_futures_leg(operation, progress_callback, &db).await }
+    });
+    let _tracked = tracker(ResumedOperation { operation: operation.clone(), handle: task.abort_handle(), active_order, stage }).await;
+    let total_futures_qty = match task.await {
+        Ok(result) => result?,
+        Err(e) if e.is_cancelled() => return Err(anyhow!("Futures leg resume cancelled by user")),
+        Err(e) => return Err(anyhow!("Futures leg resume task failed: {}", e)),
+    };
+    info!("op_id:{}: Futures leg placed after restart, total futures {:.8}", operation.id, total_futures_qty);
+    Ok(())
+}
+
+/// Исполнение спотовой лимитки после последнего опроса (до перезапуска и отмены) в БД не попало:
+/// сверяем с биржей и сохраняем. В БД — исполнение замененных ордеров плюс этот ордер на момент опроса,
+/// поэтому итог не меньше ни записи в БД, ни исполнения самого ордера
+async fn settle_spot_fill<E>(hedger: &Hedger<E>, db: &Db, operation: &HedgeOperation, spot_order_id: &str) -> Result<f64>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let recorded_qty = operation.spot_filled_qty;
+    let order_filled_qty = match hedger.exchange.get_spot_order_status(&operation.base_symbol, spot_order_id).await {
+        Ok(status) => status.filled_qty,
+        Err(e) => {
+            warn!("op_id:{}: Spot order {} status unavailable on recovery, using recorded fill {:.8}: {}", operation.id, spot_order_id, recorded_qty, e);
+            return Ok(recorded_qty);
+        }
+    };
+    if order_filled_qty <= recorded_qty + ORDER_FILL_TOLERANCE {
+        return Ok(recorded_qty);
+    }
+    info!(
+        "op_id:{}: Spot order {} filled {:.8} while recorded fill was {:.8}. Updating before the futures leg.",
+        operation.id, spot_order_id, order_filled_qty, recorded_qty
+    );
+    update_hedge_spot_order(db, operation.id, Some(spot_order_id), order_filled_qty).await?;
+    Ok(order_filled_qty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::mock::{FillSchedule, MockExchange};
+    use crate::exchange::types::OrderSide;
+    use crate::storage::{get_hedge_operation_by_id, insert_hedge_operation, OperationStatus};
+    use std::sync::{Arc, Mutex};
+
+    /// Учет без бота: запоминает ID и объем спота возобновленных операций
+    fn recording_tracker(tracked: Arc<Mutex<Vec<(i64, f64)>>>) -> ResumeTracker {
+        Box::new(move |resumed: ResumedOperation| {
+            tracked.lock().unwrap().push((resumed.operation.id, resumed.operation.spot_filled_qty));
+            async { Box::new(()) as Box<dyn Send> }.boxed()
+        })
+    }
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn running_op(started_secs_ago: i64, spot_filled_qty: f64, futures_order_id: Option<&str>) -> HedgeOperation {
+        HedgeOperation {
+            id: 1,
+            chat_id: 1,
+            base_symbol: "BTC".to_string(),
+            quote_currency: "USDT".to_string(),
+            initial_sum: 100.0,
+            volatility: 0.6,
+            target_spot_qty: 0.001,
+            target_futures_qty: 0.001,
+            start_timestamp: NOW - started_secs_ago,
+            status: "Running".to_string(),
+            spot_order_id: Some("spot-1".to_string()),
+            spot_filled_qty,
+            futures_order_id: futures_order_id.map(str::to_string),
+            futures_filled_qty: 0.0,
+            end_timestamp: None,
+            error_message: None,
+            unhedged_op_id: None,
+            op_ref: None,
+            futures_symbol: None,
+            rolled_from_op_id: None,
+            accrued_funding: 0.0,
+            environment: None,
+            futures_only: false,
+        }
+    }
+
+    #[test]
+    fn recent_operations_resume_by_interruption_type() {
+        let resting_futures = running_op(60, 0.001, Some("fut-1"));
+        let spot_only = running_op(60, 0.001, None);
+
+        assert_eq!(plan_recovery(&resting_futures, NOW, 600), RecoveryAction::ReattachFuturesOrder { order_id: "fut-1".to_string() });
+        assert_eq!(plan_recovery(&spot_only, NOW, 600), RecoveryAction::PlaceFuturesLeg);
+        // Ничего не исполнено — возобновлять нечего
+        assert!(matches!(plan_recovery(&running_op(60, 0.0, None), NOW, 600), RecoveryAction::ManualReview { .. }));
+    }
+
+    #[test]
+    fn expired_or_disabled_grace_needs_manual_review() {
+        let stale = running_op(601, 0.001, Some("fut-1"));
+
+        assert!(matches!(plan_recovery(&stale, NOW, 600), RecoveryAction::ManualReview { reason } if reason.contains("expired")));
+        assert!(matches!(plan_recovery(&running_op(60, 0.001, None), NOW, 0), RecoveryAction::ManualReview { .. }));
+        // Роллированная операция (дата-контракт) не довыставляется автоматически
+        let rolled = HedgeOperation { futures_symbol: Some("BTCUSDT-26DEC25".to_string()), ..running_op(60, 0.001, None) };
+        assert!(matches!(plan_recovery(&rolled, NOW, 600), RecoveryAction::ManualReview { .. }));
+    }
+
+    #[tokio::test]
+    async fn operations_of_other_environment_are_not_recovered() {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+        crate::storage::schema::apply_migrations(&db).await.expect("migrations");
+        let (testnet_id, ..) = insert_hedge_operation(&db, 1, "BTC", "USDT", "testnet", 100.0, 0.6, 0.001, 0.001, false).await.expect("insert");
+        let (mainnet_id, ..) = insert_hedge_operation(&db, 1, "BTC", "USDT", "mainnet", 100.0, 0.6, 0.001, 0.001, false).await.expect("insert");
+
+        // Бот подключен к testnet: mainnet-операция остается Running для запуска с mainnet-ключами
+        let config = crate::config::test_config("");
+        let tracked = Arc::new(Mutex::new(Vec::new()));
+        let reports =
+            recover_interrupted_operations(MockExchange::default(), config, db.clone(), WatcherRegistry::default(), recording_tracker(tracked)).await;
+
+        assert_eq!(reports.iter().map(|report| report.operation.id).collect::<Vec<_>>(), vec![testnet_id]);
+        let mainnet = get_hedge_operation_by_id(&db, mainnet_id).await.expect("load").expect("op");
+        assert!(mainnet.has_status(OperationStatus::Running));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn futures_leg_covers_spot_filled_after_last_poll() {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+        crate::storage::schema::apply_migrations(&db).await.expect("migrations");
+        let exchange = MockExchange { fill_schedule: Some(FillSchedule::Instant), ..MockExchange::default() };
+        let spot_order = exchange.place_limit_order("ETH", OrderSide::Buy, 0.5, 99.0).await.expect("spot order");
+        let (operation_id, ..) = insert_hedge_operation(&db, 1, "ETH", "USDT", "testnet", 50.0, 0.1, 0.5, 0.5, false).await.expect("insert");
+        // Последний опрос до перезапуска застал 0.3 из 0.5; остаток исполнился, пока бот был остановлен
+        update_hedge_spot_order(&db, operation_id, Some(&spot_order.id), 0.3).await.expect("spot progress");
+
+        let tracked = Arc::new(Mutex::new(Vec::new()));
+        let config = crate::config::test_config("futures_order_type = \"market\"");
+        let reports = recover_interrupted_operations(exchange, config, db.clone(), WatcherRegistry::default(), recording_tracker(tracked.clone())).await;
+
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].action, RecoveryAction::PlaceFuturesLeg);
+        assert!(reports[0].result.is_ok(), "{:?}", reports[0].result);
+        // Задача довыставления учтена (в боте — /active) уже с объемом спота по бирже
+        assert_eq!(*tracked.lock().unwrap(), vec![(operation_id, 0.5)]);
+        let operation = get_hedge_operation_by_id(&db, operation_id).await.expect("load").expect("op");
+        assert!(operation.has_status(OperationStatus::Completed));
+        assert!((operation.spot_filled_qty - 0.5).abs() < 1e-9);
+        assert!((operation.futures_filled_qty - 0.5).abs() < 1e-9, "futures {}", operation.futures_filled_qty);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn interrupted_futures_slice_is_followed_by_the_rest_of_the_leg() {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+        crate::storage::schema::apply_migrations(&db).await.expect("migrations");
+        let exchange = MockExchange::default();
+        let (operation_id, ..) = insert_hedge_operation(&db, 1, "ETH", "USDT", "testnet", 50.0, 0.1, 0.5, 0.5, false).await.expect("insert");
+        update_hedge_spot_order(&db, operation_id, Some("spot-1"), 0.5).await.expect("spot filled");
+        // Перезапуск застал первый срез 0.2 из 0.5: в БД ордер среза и цель всей ноги
+        let slice_order = exchange.place_futures_market_order("ETHUSDT", OrderSide::Sell, 0.2).await.expect("slice order");
+        crate::storage::update_running_futures_order(&db, operation_id, &slice_order.id, 0.0, 0.5).await.expect("persist slice");
+
+        let tracked = Arc::new(Mutex::new(Vec::new()));
+        let config = crate::config::test_config("futures_order_type = \"market\"");
+        let reports =
+            recover_interrupted_operations(exchange.clone(), config, db.clone(), WatcherRegistry::default(), recording_tracker(tracked.clone())).await;
+
+        // Вместо слежения за срезом — довыставление ноги (так и сообщается пользователю)
+        assert_eq!(reports[0].action, RecoveryAction::PlaceFuturesLeg);
+        assert!(reports[0].result.is_ok(), "{:?}", reports[0].result);
+        assert!(exchange.is_cancelled(&slice_order.id));
+        // Остаток ноги довыставлен, операция не завершена на цели среза
+        assert_eq!(*tracked.lock().unwrap(), vec![(operation_id, 0.5)]);
+        let operation = get_hedge_operation_by_id(&db, operation_id).await.expect("load").expect("op");
+        assert!(operation.has_status(OperationStatus::Completed));
+        assert!((operation.futures_filled_qty - 0.5).abs() < 1e-9, "futures {}", operation.futures_filled_qty);
+    }
+}
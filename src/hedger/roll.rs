@@ -0,0 +1,297 @@
+// src/hedger/roll.rs
+// Роллирование хеджа на другой фьючерсный контракт: спот остается, фьючерсная нога переносится
+
+use anyhow::{anyhow, Result};
+use rust_decimal::prelude::*;
+use std::sync::Arc;
+use tokio::sync::Mutex as TokioMutex;
+use tracing::{error, info, warn};
+
+use crate::hedger::common::{
+    calculate_limit_price, fail_operation, manage_order_loop, qty_precision_for, reference_price_or, release_trailing_stop, write_with_retry,
+    DbWriteRetry, OrderLoopParams, RetryBudget,
+};
+use crate::hedger::hedge::round_down_to_precision;
+use crate::hedger::params::futures_qty_precision;
+use crate::hedger::{HedgeProgressCallback, HedgeStage, Hedger, ORDER_FILL_TOLERANCE};
+use crate::exchange::bybit::linear_info_symbol;
+use crate::exchange::types::{ensure_instrument_trading, OrderSide};
+use crate::exchange::Exchange;
+use crate::storage::{complete_hedge_roll, Db, HedgeOperation, LegFill};
+
+/// Объем фьючерсной ноги исходной операции (исполненный, иначе целевой)
+fn original_futures_qty(operation: &HedgeOperation) -> f64 {
+    if operation.futures_filled_qty > ORDER_FILL_TOLERANCE {
+        operation.futures_filled_qty
+    } else {
+        operation.target_futures_qty
+    }
+}
+
+/// Фьючерсная нога роллирования: операция, в которую пишется прогресс, контракт, сторона и объем
+struct FuturesLeg<'a> {
+    operation_id: i64,
+    futures_symbol: &'a str,
+    side: OrderSide,
+    qty: f64,
+    min_qty: Option<Decimal>, // Минимальный ордер нового контракта (для откупа старого не проверяется)
+}
+
+/// Лимитный цикл одной фьючерсной ноги; при ошибке возвращает ее вместе с уже исполненным объемом
+async fn run_futures_leg<E>(
+    hedger: &Hedger<E>,
+    db: &Db,
+    leg: FuturesLeg<'_>,
+    progress_callback: &mut HedgeProgressCallback,
+    retry_budget: &RetryBudget,
+) -> Result<(f64, Option<String>), (anyhow::Error, f64)>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let FuturesLeg { operation_id, futures_symbol, side, qty, min_qty } = leg;
+    let ticker = hedger
+        .exchange
+        .get_futures_ticker(futures_symbol)
+        .await
+        .map_err(|e| (anyhow!("Failed get futures ticker for {}: {}", futures_symbol, e), 0.0))?;
+    if ticker.bid_price <= 0.0 || ticker.ask_price <= 0.0 {
+        return Err((anyhow!("Invalid futures price for {}: bid {:.8}, ask {:.8}", futures_symbol, ticker.bid_price, ticker.ask_price), 0.0));
+    }
+    let mid_price = (ticker.bid_price + ticker.ask_price) / 2.0;
+    let reference_price = reference_price_or(hedger, futures_symbol, false, mid_price).await;
+    let initial_limit_price = calculate_limit_price(reference_price, side, hedger.config.slippage_for(futures_symbol));
+
+    let filled_storage = Arc::new(TokioMutex::new(0.0));
+    let loop_params = OrderLoopParams {
+        hedger,
+        db,
+        operation_id,
+        symbol: futures_symbol,
+        side,
+        initial_target_qty: qty,
+        initial_limit_price,
+        progress_callback,
+        stage: HedgeStage::Futures,
+        is_spot: false,
+        min_order_qty_decimal: min_qty,
+        total_filled_qty_storage: filled_storage.clone(),
+        keep_order_on_timeout: false, // При роллировании ордер не оставляем: нужен откат
+        order_type: hedger.config.futures_order_type,
+        retry_budget: retry_budget.clone(),
+        qty_precision: qty_precision_for(hedger, futures_symbol, false).await,
+        futures_recovery_base: Some(0.0),
+        futures_recovery_target: None,
+        price_guard: None,
+    };
+    match manage_order_loop(loop_params).await {
+        Ok(result) => Ok(result),
+        Err(e) => {
+            let filled = *filled_storage.lock().await;
+            Err((e, filled))
+        }
+    }
+}
+
+/// Ошибка роллирования без открытой новой позиции: операция-преемник помечается Failed, исходная не меняется
+async fn fail_roll(db: &Db, db_retry: DbWriteRetry, rolled_operation_id: i64, message: String) -> anyhow::Error {
+    error!("op_id:{}: Roll failed: {}", rolled_operation_id, message);
+    fail_operation(db, db_retry, rolled_operation_id, None, LegFill::NONE, &message, anyhow!(message.clone())).await
+}
+
+pub(super) async fn run_roll_impl<E>(
+    hedger: &Hedger<E>,
+    original_op: HedgeOperation,
+    new_futures_symbol: &str,
+    rolled_operation_id: i64,
+    mut progress_callback: HedgeProgressCallback,
+    db: &Db,
+) -> Result<f64>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let original_op_id = original_op.id;
+    let old_futures_symbol = original_op.futures_contract();
+    let old_qty = original_futures_qty(&original_op);
+    let db_retry = DbWriteRetry::from_config(&hedger.config);
+    info!(
+        "op_id:{}: Rolling futures leg {} -> {} (qty {:.8}) into op_id:{}",
+        original_op_id, old_futures_symbol, new_futures_symbol, old_qty, rolled_operation_id
+    );
+
+    if new_futures_symbol.eq_ignore_ascii_case(&old_futures_symbol) {
+        return Err(fail_roll(db, db_retry, rolled_operation_id, format!("Operation is already hedged on {}", old_futures_symbol)).await);
+    }
+    if old_qty <= ORDER_FILL_TOLERANCE {
+        return Err(fail_roll(db, db_retry, rolled_operation_id, format!("Original futures quantity {:.8} is too low to roll", old_qty)).await);
+    }
+
+    // --- Проверка нового контракта ---
+    let new_info_symbol = match linear_info_symbol(new_futures_symbol, &hedger.quote_currency) {
+        Ok(info_symbol) => info_symbol,
+        Err(e) => return Err(fail_roll(db, db_retry, rolled_operation_id, e.to_string()).await),
+    };
+    let new_info = match hedger.exchange.get_linear_instrument_info(new_info_symbol).await {
+        Ok(info) => info,
+        Err(e) => return Err(fail_roll(db, db_retry, rolled_operation_id, format!("Failed to get instrument info for {}: {}", new_futures_symbol, e)).await),
+    };
+    if let Err(e) = ensure_instrument_trading(new_futures_symbol, new_info.status.as_deref()) {
+        return Err(fail_roll(db, db_retry, rolled_operation_id, e.to_string()).await);
+    }
+    let (new_decimals, new_min_qty) = match futures_qty_precision(&new_info) {
+        Ok(precision) => precision,
+        Err(e) => return Err(fail_roll(db, db_retry, rolled_operation_id, e.to_string()).await),
+    };
+    let new_qty_decimal = match round_down_to_precision(old_qty, new_decimals) {
+        Ok(qty) => qty,
+        Err(e) => return Err(fail_roll(db, db_retry, rolled_operation_id, e.to_string()).await),
+    };
+    if new_qty_decimal <= Decimal::ZERO || new_qty_decimal < new_min_qty {
+        return Err(fail_roll(db, db_retry, rolled_operation_id, format!("Quantity {} is below minimum order size {} for {}", new_qty_decimal, new_min_qty, new_futures_symbol)).await);
+    }
+    let new_qty = new_qty_decimal.to_f64().unwrap_or(old_qty);
+
+    // Бюджет повторов общий для открытия нового и откупа старого контракта
+    let retry_budget = RetryBudget::from_config(&hedger.config);
+
+    // --- Шаг 1: шорт нового контракта (пока открыты оба шорта, спот перехеджирован, но не оголен) ---
+    let open_leg = FuturesLeg {
+        operation_id: rolled_operation_id,
+        futures_symbol: new_futures_symbol,
+        side: OrderSide::Sell,
+        qty: new_qty,
+        min_qty: Some(new_min_qty),
+    };
+    let (new_filled, new_order_id) = match run_futures_leg(hedger, db, open_leg, &mut progress_callback, &retry_budget).await {
+        Ok(result) => result,
+        Err((e, partially_filled)) => {
+            // Откат: закрываем частично открытый шорт нового контракта, исходный хедж остается как был
+            let rollback = if partially_filled > ORDER_FILL_TOLERANCE {
+                warn!("op_id:{}: Rolling back {:.8} {} short opened before failure", rolled_operation_id, partially_filled, new_futures_symbol);
+                match hedger.exchange.place_futures_reduce_only_market_order(new_futures_symbol, OrderSide::Buy, partially_filled).await {
+                    Ok(_) => "partial short closed".to_string(),
+                    Err(rollback_err) => format!("FAILED to close partial short {:.8} {}: {}", partially_filled, new_futures_symbol, rollback_err),
+                }
+            } else {
+                "nothing filled".to_string()
+            };
+            return Err(fail_roll(db, db_retry, rolled_operation_id, format!("Failed to open {} short: {} (rollback: {})", new_futures_symbol, e, rollback)).await);
+        }
+    };
+
+    // --- Трейлинг-стоп старого контракта снимаем до откупа ---
+    release_trailing_stop(hedger, db, original_op_id, &old_futures_symbol).await;
+
+    // --- Шаг 2: откуп старого контракта ---
+    let buyback_leg = FuturesLeg {
+        operation_id: original_op_id,
+        futures_symbol: &old_futures_symbol,
+        side: OrderSide::Buy,
+        qty: old_qty,
+        min_qty: None,
+    };
+    if let Err((e, bought_back)) = run_futures_leg(hedger, db, buyback_leg, &mut progress_callback, &retry_budget).await {
+        // Новый шорт уже открыт: добиваем откуп старого рыночным ордером, иначе требуется ручное вмешательство
+        let remaining = old_qty - bought_back;
+        warn!(
+            "op_id:{}: Buyback of {} failed ({}), {:.8} left. Retrying with market order.",
+            original_op_id, old_futures_symbol, e, remaining
+        );
+        if remaining > ORDER_FILL_TOLERANCE
+            && let Err(market_err) = hedger.exchange.place_futures_reduce_only_market_order(&old_futures_symbol, OrderSide::Buy, remaining).await
+        {
+            let message = format!(
+                "New {} short {:.8} is open, but {:.8} {} is still short ({}; market retry: {}). Manual intervention required",
+                new_futures_symbol, new_filled, remaining, old_futures_symbol, e, market_err
+            );
+            error!("op_id:{}: {}", rolled_operation_id, message);
+            let futures = LegFill { order_id: new_order_id.as_deref(), filled_qty: new_filled };
+            return Err(fail_operation(db, db_retry, rolled_operation_id, None, futures, &message, anyhow!(message.clone())).await);
+        }
+    }
+
+    // --- Шаг 3: связываем операции в БД ---
+    // Новая позиция открыта, старая закрыта: сбой записи после повторов — DbRecordDiverged
+    let linked = write_with_retry(db_retry, rolled_operation_id, "complete roll", || {
+        complete_hedge_roll(db, original_op_id, rolled_operation_id, new_order_id.as_deref(), new_filled)
+    })
+    .await?;
+    if !linked {
+        warn!("op_id:{}: Original operation was already unhedged; roll recorded without link", original_op_id);
+    }
+    info!(
+        "op_id:{}: Roll completed: {} -> {} ({:.8}), new op_id:{}",
+        original_op_id, old_futures_symbol, new_futures_symbol, new_filled, rolled_operation_id
+    );
+    Ok(new_filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::mock::MockExchange;
+    use crate::storage::{
+        get_hedge_operation_by_id, insert_hedge_operation, insert_rolled_hedge_operation, update_hedge_final_status, OperationStatus,
+    };
+    use futures::FutureExt;
+
+    const NEW_CONTRACT: &str = "ETHUSDT-26DEC25";
+
+    /// БД с завершенным хеджем ETH (шорт 1.0 ETHUSDT) и операцией-преемником роллирования на NEW_CONTRACT
+    async fn db_with_roll() -> (Db, HedgeOperation, i64) {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+        crate::storage::schema::apply_migrations(&db).await.expect("migrations");
+        let (original_id, _, _) = insert_hedge_operation(&db, 1, "ETH", "USDT", "testnet", 100.0, 0.6, 1.0, 1.0, false).await.expect("insert");
+        update_hedge_final_status(&db, original_id, OperationStatus::Completed, Some("fut-1"), 1.0, None).await.expect("complete");
+        let original = get_hedge_operation_by_id(&db, original_id).await.expect("query").expect("op");
+        let (rolled_id, _) = insert_rolled_hedge_operation(&db, &original, NEW_CONTRACT, 1.0).await.expect("insert rolled");
+        (db, original, rolled_id)
+    }
+
+    async fn roll(exchange: MockExchange, db: &Db, original: HedgeOperation, rolled_id: i64) -> Result<f64> {
+        let hedger = Hedger::new(exchange, crate::config::test_config("futures_order_type = \"market\""));
+        let progress_callback: HedgeProgressCallback = Box::new(|_update| async { Ok(()) }.boxed());
+        run_roll_impl(&hedger, original, NEW_CONTRACT, rolled_id, progress_callback, db).await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn roll_moves_short_and_links_successor() {
+        let (db, original, rolled_id) = db_with_roll().await;
+        let original_id = original.id;
+        let exchange = MockExchange::default();
+
+        let rolled_qty = roll(exchange.clone(), &db, original, rolled_id).await.expect("roll");
+
+        assert!((rolled_qty - 1.0).abs() < 1e-9);
+        assert!(exchange.reduce_only_orders().is_empty(), "no rollback on success");
+        let original = get_hedge_operation_by_id(&db, original_id).await.expect("query").expect("op");
+        let rolled = get_hedge_operation_by_id(&db, rolled_id).await.expect("query").expect("op");
+        assert!(original.unhedged_op_id.is_some_and(|marker| marker != rolled_id));
+        assert!(rolled.has_status(OperationStatus::Completed));
+        assert_eq!(rolled.rolled_from_op_id, Some(original_id));
+        assert_eq!(rolled.futures_contract(), NEW_CONTRACT);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn partial_new_short_is_closed_reduce_only() {
+        let (db, original, rolled_id) = db_with_roll().await;
+        let original_id = original.id;
+        // Новый шорт исполняется лишь на 40%: его надо закрыть, не открыв лонг
+        let exchange = MockExchange { market_fill_fraction: Some(0.4), ..MockExchange::default() };
+
+        let error = roll(exchange.clone(), &db, original, rolled_id).await.expect_err("roll must fail");
+
+        assert!(error.to_string().contains("partial short closed"), "{}", error);
+        let rollback = exchange.reduce_only_orders();
+        assert_eq!(rollback.len(), 1);
+        assert_eq!((rollback[0].0.as_str(), rollback[0].1), (NEW_CONTRACT, OrderSide::Buy));
+        assert!((rollback[0].2 - 0.4).abs() < 1e-9);
+        let original = get_hedge_operation_by_id(&db, original_id).await.expect("query").expect("op");
+        let rolled = get_hedge_operation_by_id(&db, rolled_id).await.expect("query").expect("op");
+        assert_eq!(original.unhedged_op_id, None, "original hedge stays as it was");
+        assert!(rolled.has_status(OperationStatus::Failed));
+    }
+}
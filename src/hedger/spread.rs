@@ -0,0 +1,90 @@
+// src/hedger/spread.rs
+// Проверка ширины спреда перед хеджем (включается max_spread_pct)
+
+use anyhow::{anyhow, Result};
+
+use crate::exchange::Exchange;
+
+/// Относительный спред в процентах от середины bid/ask; None — котировки некорректны
+pub fn relative_spread_pct(bid: f64, ask: f64) -> Option<f64> {
+    if bid <= 0.0 || ask <= 0.0 || ask < bid {
+        return None;
+    }
+    let mid = (bid + ask) / 2.0;
+    Some((ask - bid) / mid * 100.0)
+}
+
+/// Спреды обеих ног хеджа в процентах
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HedgeSpreads {
+    pub spot_pct: f64,
+    pub futures_pct: f64,
+}
+
+impl HedgeSpreads {
+    /// Хотя бы одна нога шире допустимого спреда
+    pub fn exceeds(&self, max_spread_pct: f64) -> bool {
+        self.spot_pct > max_spread_pct || self.futures_pct > max_spread_pct
+    }
+}
+
+/// Текущий спред спота в процентах (лучшие bid/ask стакана)
+pub async fn spot_spread_pct<E: Exchange>(exchange: &E, symbol: &str) -> Result<f64> {
+    let (bid, ask) = exchange.get_spot_bid_ask(symbol).await?;
+    relative_spread_pct(bid, ask).ok_or_else(|| anyhow!("Invalid spot bid/ask for {}: {} / {}", symbol, bid, ask))
+}
+
+/// Спреды спота и фьючерса перед хеджем: исполняются обе ноги, поэтому проверяются обе
+pub async fn hedge_spreads<E: Exchange>(exchange: &E, symbol: &str, futures_symbol: &str) -> Result<HedgeSpreads> {
+    let spot_pct = spot_spread_pct(exchange, symbol).await?;
+    let futures_pct = futures_spread_pct(exchange, futures_symbol).await?;
+    Ok(HedgeSpreads { spot_pct, futures_pct })
+}
+
+/// Текущий спред фьючерса в процентах (по тикеру)
+pub async fn futures_spread_pct<E: Exchange>(exchange: &E, futures_symbol: &str) -> Result<f64> {
+    let ticker = exchange.get_futures_ticker(futures_symbol).await?;
+    relative_spread_pct(ticker.bid_price, ticker.ask_price).ok_or_else(|| {
+        anyhow!("Invalid bid/ask for {}: {} / {}", futures_symbol, ticker.bid_price, ticker.ask_price)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::mock::MockExchange;
+
+    #[test]
+    fn spread_is_relative_to_mid() {
+        let spread = relative_spread_pct(99.0, 101.0).unwrap();
+        assert!((spread - 2.0).abs() < 1e-9);
+        assert_eq!(relative_spread_pct(101.0, 99.0), None);
+        assert_eq!(relative_spread_pct(0.0, 99.0), None);
+    }
+
+    #[tokio::test]
+    async fn wide_futures_spread_is_measured() {
+        let exchange = MockExchange { futures_bid_ask: Some((95.0, 105.0)), ..MockExchange::default() };
+
+        let spread = futures_spread_pct(&exchange, "BTCUSDT").await.unwrap();
+
+        assert!((spread - 10.0).abs() < 1e-9);
+        assert!(spread > 0.5, "wide spread should exceed a typical max_spread_pct");
+    }
+
+    #[tokio::test]
+    async fn wide_spot_spread_fails_check_with_tight_futures() {
+        let exchange = MockExchange {
+            spot_bid_ask: Some((98.0, 102.0)),
+            futures_bid_ask: Some((99.95, 100.05)),
+            ..MockExchange::default()
+        };
+
+        let spreads = hedge_spreads(&exchange, "BTC", "BTCUSDT").await.unwrap();
+
+        assert!((spreads.spot_pct - 4.0).abs() < 1e-9);
+        assert!(spreads.futures_pct < 0.5);
+        assert!(spreads.exceeds(0.5), "wide spot spread alone must block the hedge");
+        assert!(!spreads.exceeds(5.0));
+    }
+}
@@ -5,20 +5,26 @@ use std::sync::Arc;
 use tokio::sync::Mutex as TokioMutex;
 use tracing::{error, info, warn};
 
-use crate::hedger::common::{manage_order_loop, OrderLoopParams}; // Используем общую функцию
+use crate::hedger::common::{
+    get_spot_price_with_retry, manage_order_loop, qty_precision_for, release_trailing_stop,
+    write_with_retry, DbWriteRetry, OrderLoopParams, RetryBudget, SpotPriceRetry,
+}; // Используем общую функцию
 use crate::hedger::{
-    HedgeProgressCallback, HedgeProgressUpdate, HedgeStage, Hedger, ORDER_FILL_TOLERANCE,
+    HedgeProgressCallback, HedgeProgressUpdate, HedgeStage, Hedger, PriceGuardHit, UnhedgeOutcome, ORDER_FILL_TOLERANCE,
 };
 use crate::exchange::types::OrderSide;
 use crate::exchange::Exchange;
-use crate::storage::{mark_hedge_as_unhedged, Db, HedgeOperation};
+use crate::storage::{
+    mark_hedge_as_unhedged, record_partial_spot_sale, update_unhedge_dust_qty, Db, HedgeOperation,
+};
 
 pub(super) async fn run_unhedge_impl<E>(
     hedger: &Hedger<E>,
     original_op: HedgeOperation,
     db: &Db,
     mut progress_callback: HedgeProgressCallback,
-) -> Result<(f64, f64)>
+    spot_price_guard: Option<f64>,
+) -> Result<UnhedgeOutcome>
 where
     E: Exchange + Clone + Send + Sync + 'static,
 {
@@ -26,15 +32,17 @@ where
     let original_hedge_op_id = original_op.id;
     let futures_buy_qty = original_op.target_futures_qty; // Сколько фьюча откупать
     let target_spot_sell_qty = original_op.spot_filled_qty; // Сколько спота продавать (цель)
-    let futures_symbol = format!("{}{}", symbol, hedger.quote_currency); // Символ фьючерса
+    let futures_symbol = original_op.futures_contract(); // Символ фьючерса (с учетом роллирования)
 
     info!(
         "Starting unhedge for original op_id={} ({}), target spot sell qty={:.8}, target futures qty to buy back={:.8}",
         original_hedge_op_id, symbol, target_spot_sell_qty, futures_buy_qty
     );
 
+    // Хедж только фьючерсом (futures_only_hedge): спот хранится вне бота, откупаем лишь фьючерс
+    let futures_only = original_op.futures_only;
     // Проверка целевого количества спота
-    if target_spot_sell_qty <= ORDER_FILL_TOLERANCE {
+    if !futures_only && target_spot_sell_qty <= ORDER_FILL_TOLERANCE {
         let msg = format!(
             "Target spot sell quantity ({:.8}) based on original operation is too low",
             target_spot_sell_qty
@@ -54,8 +62,151 @@ where
      }
 
 
+    // --- Снятие трейлинг-стопа, если он был установлен после хеджирования ---
+    release_trailing_stop(hedger, db, original_hedge_op_id, &futures_symbol).await;
+
+    // Бюджет повторов общий для обеих ног расхеджирования
+    let retry_budget = RetryBudget::from_config(&hedger.config);
+
+    // --- Этап 1: Спот (Продажа); у хеджа только фьючерсом спот хранится вне бота ---
+    let (final_spot_sold_qty, spot_price_for_cb, spot_dust_qty) = if futures_only {
+        info!("op_id={}: Futures-only operation: spot is held externally, skipping spot sell.", original_hedge_op_id);
+        (0.0, None, 0.0)
+    } else {
+        let (spot_sold_qty, spot_price, dust_qty) =
+            sell_spot_leg(hedger, db, original_hedge_op_id, &symbol, target_spot_sell_qty, &mut progress_callback, &retry_budget, spot_price_guard).await?;
+        (spot_sold_qty, Some(spot_price), dust_qty)
+    };
+
+
+    // --- Этап 2: Фьючерс (Покупка) ---
+    info!("op_id:{}: Starting FUTURES buy stage...", original_hedge_op_id);
+    let futures_filled_storage = Arc::new(TokioMutex::new(0.0)); // Свой счетчик
+
+    // Получаем актуальную цену фьючерса для начального ордера
+    let futures_market_price = match hedger.exchange.get_futures_ticker(&futures_symbol).await {
+        Ok(ticker) => {
+            // Для покупки используем Ask
+            info!(
+                "op_id:{}: Futures ticker received: bid={:.2}, ask={:.2}.",
+                original_hedge_op_id, ticker.bid_price, ticker.ask_price
+            );
+            ticker.ask_price
+        }
+        Err(e) => match spot_price_for_cb {
+            Some(spot_price) => {
+                warn!(
+                    "op_id:{}: Failed to get futures ticker: {}. Using last spot price as fallback.",
+                    original_hedge_op_id, e
+                );
+                spot_price // Fallback на последнюю цену спота
+            }
+            None => return Err(anyhow!("Failed to get futures ticker for {}: {}", futures_symbol, e)),
+        },
+    };
+    let futures_reference_price = crate::hedger::common::reference_price_or(hedger, &futures_symbol, false, futures_market_price).await;
+    let futures_initial_limit_price =
+        crate::hedger::common::calculate_limit_price(futures_reference_price, OrderSide::Buy, hedger.config.slippage_for(&futures_symbol));
+
+    let futures_loop_params = OrderLoopParams {
+        hedger,
+        db, // Не используется
+        operation_id: original_hedge_op_id,
+        symbol: &futures_symbol, // Символ фьючерса
+        side: OrderSide::Buy,
+        initial_target_qty: futures_buy_qty, // Откупаем исходное кол-во фьюча
+        initial_limit_price: futures_initial_limit_price,
+        progress_callback: &mut progress_callback,
+        stage: HedgeStage::Futures,
+        is_spot: false,
+        min_order_qty_decimal: None, // Не нужно
+        // --- ИСПРАВЛЕНО: Убрали current_order_id_storage ---
+        total_filled_qty_storage: futures_filled_storage.clone(), // Свой счетчик
+        keep_order_on_timeout: false,
+        order_type: hedger.config.futures_order_type,
+        retry_budget,
+        qty_precision: qty_precision_for(hedger, &futures_symbol, false).await,
+        futures_recovery_base: None,
+        futures_recovery_target: None,
+        price_guard: None,
+    };
+
+    // --- ИСПРАВЛЕНО: Обрабатываем результат (f64, Option<String>) ---
+    let final_fut_bought_qty = match manage_order_loop(futures_loop_params).await {
+        Ok((filled_qty, _last_order_id_opt)) => { // Деструктурируем кортеж
+            info!(
+                "op_id:{}: Unhedge FUTURES buy stage finished. Final actual futures bought quantity: {:.8}",
+                original_hedge_op_id, filled_qty // Используем filled_qty (f64)
+            );
+             if (filled_qty - futures_buy_qty).abs() > ORDER_FILL_TOLERANCE * 10.0 {
+                warn!(
+                    "op_id:{}: Final futures bought qty {:.8} significantly differs from target {:.8}.",
+                    original_hedge_op_id, filled_qty, futures_buy_qty // Используем filled_qty (f64)
+                );
+            }
+            filled_qty // Возвращаем только f64
+        }
+        Err(loop_err) => {
+            error!(
+                "op_id:{}: Unhedge FUTURES buy stage failed: {}",
+                original_hedge_op_id, loop_err
+            );
+            // Спот уже продан! Это частичный успех/неудача.
+            // Статус в БД НЕ МЕНЯЕМ на unhedged.
+            // Возвращаем ошибку, вызывающий код должен обработать ситуацию.
+            warn!("op_id:{}: Spot was sold, but futures buy failed! Manual intervention may be required.", original_hedge_op_id);
+            return Err(loop_err);
+        }
+    };
+
+    // --- Успешное завершение ---
+    // Помечаем исходную операцию как расхеджированную
+    // Без этой отметки операция останется "открытой" в БД при уже закрытых позициях: после повторов — алерт
+    write_with_retry(DbWriteRetry::from_config(&hedger.config), original_hedge_op_id, "mark unhedged", || {
+        mark_hedge_as_unhedged(db, original_hedge_op_id)
+    })
+    .await?;
+    info!(
+        "op_id:{}: Unhedge completed successfully for original op_id={}. Spot Sold: {:.8}, Fut Bought: {:.8}",
+        original_hedge_op_id, original_hedge_op_id, final_spot_sold_qty, final_fut_bought_qty
+    );
+
+    // --- Отправляем финальный колбэк для фьючерса (100%) ---
+    let fut_price_for_cb = match hedger.exchange.get_futures_ticker(&futures_symbol).await {
+         Ok(t) => (t.bid_price + t.ask_price) / 2.0, Err(_) => futures_market_price
+      };
+    let fut_done_update = HedgeProgressUpdate {
+        stage: HedgeStage::Futures,
+        current_spot_price: fut_price_for_cb, // Цена фьючерса
+        new_limit_price: futures_initial_limit_price, // Не так важно
+        is_replacement: false,
+        filled_qty: final_fut_bought_qty,
+        target_qty: final_fut_bought_qty,
+        cumulative_filled_qty: final_fut_bought_qty,
+        total_target_qty: futures_buy_qty,
+    };
+    let _ = progress_callback(fut_done_update).await; // Игнорируем ошибку
+    // --- Конец колбэка фьючерса ---
+
+    Ok(UnhedgeOutcome { spot_sold: final_spot_sold_qty, fut_bought: final_fut_bought_qty, spot_dust: spot_dust_qty })
+}
+
+/// Продажа спотовой ноги при расхеджировании: (продано, последняя цена спота для колбэка/запасной цены, непроданная пыль)
+async fn sell_spot_leg<E>(
+    hedger: &Hedger<E>,
+    db: &Db,
+    original_hedge_op_id: i64,
+    symbol: &str,
+    target_spot_sell_qty: f64,
+    progress_callback: &mut HedgeProgressCallback,
+    retry_budget: &RetryBudget,
+    spot_price_guard: Option<f64>, // Минимальная допустимая цена продажи
+) -> Result<(f64, f64, f64)>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
     // --- Проверка баланса и определение реального кол-ва спота для продажи ---
-    let available_balance = match hedger.exchange.get_balance(&symbol).await {
+    let available_balance = match hedger.exchange.get_balance(symbol).await {
         Ok(balance) => {
             info!(
                 "op_id={}: Checked available balance {} for {}",
@@ -75,8 +226,9 @@ where
 
     // Реальное количество для продажи = минимум из цели и доступного
     let actual_spot_sell_qty = target_spot_sell_qty.min(available_balance);
+    let spot_qty_precision = qty_precision_for(hedger, symbol, true).await;
 
-    if actual_spot_sell_qty < target_spot_sell_qty - ORDER_FILL_TOLERANCE {
+    if !spot_qty_precision.target_reached(actual_spot_sell_qty, target_spot_sell_qty) {
         warn!(
             "op_id={}: Available balance {:.8} is less than target sell quantity {:.8}. Selling available amount.",
             original_hedge_op_id, available_balance, target_spot_sell_qty
@@ -86,7 +238,7 @@ where
     // --- Получаем Min Order Qty для спота и проверяем РЕАЛЬНОЕ количество ---
     let spot_info = hedger
         .exchange
-        .get_spot_instrument_info(&symbol)
+        .get_spot_instrument_info(symbol)
         .await
         .map_err(|e| {
             anyhow!(
@@ -113,7 +265,7 @@ where
     let actual_spot_sell_qty_decimal =
         Decimal::try_from(actual_spot_sell_qty).unwrap_or_else(|_| Decimal::ZERO);
 
-    if actual_spot_sell_qty <= ORDER_FILL_TOLERANCE
+    if actual_spot_sell_qty <= spot_qty_precision.tolerance()
         || actual_spot_sell_qty_decimal < min_spot_qty_decimal
     {
         let msg = format!(
@@ -139,36 +291,40 @@ where
     let spot_filled_storage = Arc::new(TokioMutex::new(0.0)); // Свой счетчик для спота
 
     // Получаем начальную цену спота
-    let current_spot_price = match hedger.exchange.get_spot_price(&symbol).await {
-        Ok(p) if p > 0.0 => p,
-        Ok(p) => {
-            let msg = format!("Invalid initial spot price received: {}", p);
-            error!("op_id={}: {}", original_hedge_op_id, msg);
-            return Err(anyhow!(msg));
-        }
+    let spot_retry = SpotPriceRetry::from_config(&hedger.config).with_budget(retry_budget);
+    let current_spot_price = match get_spot_price_with_retry(&hedger.exchange, symbol, spot_retry.clone()).await {
+        Ok(p) => p,
         Err(e) => {
             let msg = format!("Failed to get initial spot price: {}", e);
             error!("op_id={}: {}. Aborting.", original_hedge_op_id, msg);
             return Err(anyhow!(msg));
         }
     };
+    let spot_reference_price = crate::hedger::common::reference_price_or(hedger, symbol, true, current_spot_price).await;
     let spot_initial_limit_price =
-        crate::hedger::common::calculate_limit_price(current_spot_price, OrderSide::Sell, hedger.slippage);
+        crate::hedger::common::calculate_limit_price(spot_reference_price, OrderSide::Sell, hedger.config.slippage_for(symbol));
 
     let spot_loop_params = OrderLoopParams {
         hedger,
         db, // Db не используется в цикле спота для unhedge, но тип требует
         operation_id: original_hedge_op_id,
-        symbol: &symbol, // Базовый символ
+        symbol, // Базовый символ
         side: OrderSide::Sell,
         initial_target_qty: actual_spot_sell_qty, // Продаем реальное кол-во
         initial_limit_price: spot_initial_limit_price,
-        progress_callback: &mut progress_callback,
+        progress_callback,
         stage: HedgeStage::Spot,
         is_spot: true,
         min_order_qty_decimal: Some(min_spot_qty_decimal), // Передаем для проверки на пыль
         // --- ИСПРАВЛЕНО: Убрали current_order_id_storage ---
         total_filled_qty_storage: spot_filled_storage.clone(), // Свой счетчик
+        keep_order_on_timeout: false,
+        order_type: hedger.config.spot_order_type,
+        retry_budget: retry_budget.clone(),
+        qty_precision: spot_qty_precision,
+        futures_recovery_base: None,
+        futures_recovery_target: None,
+        price_guard: spot_price_guard,
     };
 
     // --- ИСПРАВЛЕНО: Обрабатываем результат (f64, Option<String>) ---
@@ -191,15 +347,47 @@ where
                 "op_id={}: Unhedge SPOT sell stage failed: {}",
                 original_hedge_op_id, loop_err
             );
-            // Статус в БД не меняем, т.к. операция не завершена и не факт, что что-то продалось
+            // Ограничитель остановил продажу после частичного исполнения: проданный спот фиксируется в операции,
+            // чтобы повторное расхеджирование продавало только остаток (шорт остается целиком)
+            if let Some(guard_hit) = loop_err.downcast_ref::<PriceGuardHit>()
+                && guard_hit.filled_qty > spot_qty_precision.tolerance()
+            {
+                let remaining_spot_qty = (target_spot_sell_qty - guard_hit.filled_qty).max(0.0);
+                let recorded = write_with_retry(DbWriteRetry::from_config(&hedger.config), original_hedge_op_id, "record partial spot sale", || {
+                    record_partial_spot_sale(db, original_hedge_op_id, remaining_spot_qty)
+                })
+                .await;
+                match recorded {
+                    Ok(true) => warn!(
+                        "op_id={}: Unhedge halted after selling {:.8} of {:.8} spot. Operation keeps {:.8} spot against the full short.",
+                        original_hedge_op_id, guard_hit.filled_qty, target_spot_sell_qty, remaining_spot_qty
+                    ),
+                    Ok(false) => warn!("op_id={}: Operation already unhedged when recording partial spot sale.", original_hedge_op_id),
+                    Err(diverged) => return Err(diverged.context(loop_err.to_string())),
+                }
+            }
+            // Иначе статус в БД не меняем: операция не завершена и ничего не продано
             return Err(loop_err);
         }
     };
 
     // --- Отправляем финальный колбэк для спота (100%) ---
-    let spot_price_for_cb = match hedger.exchange.get_spot_price(&symbol).await {
+    let spot_price_for_cb = match get_spot_price_with_retry(&hedger.exchange, symbol, spot_retry).await {
          Ok(p) => p, Err(_) => current_spot_price // Fallback
      };
+
+    // --- Остаток меньше минимального ордера ("пыль"): биржа его не примет, только сохраняем и сообщаем ---
+    let dust_qty = if spot_qty_precision.target_reached(final_spot_sold_qty, actual_spot_sell_qty) {
+        0.0
+    } else {
+        (actual_spot_sell_qty - final_spot_sold_qty).max(0.0)
+    };
+    if dust_qty > 0.0 {
+        warn!("op_id={}: {:.8} {} left unsold below minimum order size.", original_hedge_op_id, dust_qty, symbol);
+        if let Err(e) = update_unhedge_dust_qty(db, original_hedge_op_id, dust_qty).await {
+            warn!("op_id={}: Failed to record unhedge dust qty in DB: {}", original_hedge_op_id, e);
+        }
+    }
     let spot_done_update = HedgeProgressUpdate {
         stage: HedgeStage::Spot,
         current_spot_price: spot_price_for_cb,
@@ -220,106 +408,5 @@ where
     }
     // --- Конец колбэка спота ---
 
-
-    // --- Этап 2: Фьючерс (Покупка) ---
-    info!("op_id:{}: Starting FUTURES buy stage...", original_hedge_op_id);
-    let futures_filled_storage = Arc::new(TokioMutex::new(0.0)); // Свой счетчик
-
-    // Получаем актуальную цену фьючерса для начального ордера
-    let futures_market_price = match hedger.exchange.get_futures_ticker(&futures_symbol).await {
-        Ok(ticker) => {
-            // Для покупки используем Ask
-            info!(
-                "op_id:{}: Futures ticker received: bid={:.2}, ask={:.2}.",
-                original_hedge_op_id, ticker.bid_price, ticker.ask_price
-            );
-            ticker.ask_price
-        }
-        Err(e) => {
-            warn!(
-                "op_id:{}: Failed to get futures ticker: {}. Using last spot price as fallback.",
-                original_hedge_op_id, e
-            );
-            spot_price_for_cb // Fallback на последнюю цену спота
-        }
-    };
-    let futures_initial_limit_price =
-        crate::hedger::common::calculate_limit_price(futures_market_price, OrderSide::Buy, hedger.slippage);
-
-    let futures_loop_params = OrderLoopParams {
-        hedger,
-        db, // Не используется
-        operation_id: original_hedge_op_id,
-        symbol: &futures_symbol, // Символ фьючерса
-        side: OrderSide::Buy,
-        initial_target_qty: futures_buy_qty, // Откупаем исходное кол-во фьюча
-        initial_limit_price: futures_initial_limit_price,
-        progress_callback: &mut progress_callback,
-        stage: HedgeStage::Futures,
-        is_spot: false,
-        min_order_qty_decimal: None, // Не нужно
-        // --- ИСПРАВЛЕНО: Убрали current_order_id_storage ---
-        total_filled_qty_storage: futures_filled_storage.clone(), // Свой счетчик
-    };
-
-    // --- ИСПРАВЛЕНО: Обрабатываем результат (f64, Option<String>) ---
-    let final_fut_bought_qty = match manage_order_loop(futures_loop_params).await {
-        Ok((filled_qty, _last_order_id_opt)) => { // Деструктурируем кортеж
-            info!(
-                "op_id:{}: Unhedge FUTURES buy stage finished. Final actual futures bought quantity: {:.8}",
-                original_hedge_op_id, filled_qty // Используем filled_qty (f64)
-            );
-             if (filled_qty - futures_buy_qty).abs() > ORDER_FILL_TOLERANCE * 10.0 {
-                warn!(
-                    "op_id:{}: Final futures bought qty {:.8} significantly differs from target {:.8}.",
-                    original_hedge_op_id, filled_qty, futures_buy_qty // Используем filled_qty (f64)
-                );
-            }
-            filled_qty // Возвращаем только f64
-        }
-        Err(loop_err) => {
-            error!(
-                "op_id:{}: Unhedge FUTURES buy stage failed: {}",
-                original_hedge_op_id, loop_err
-            );
-            // Спот уже продан! Это частичный успех/неудача.
-            // Статус в БД НЕ МЕНЯЕМ на unhedged.
-            // Возвращаем ошибку, вызывающий код должен обработать ситуацию.
-            warn!("op_id:{}: Spot was sold, but futures buy failed! Manual intervention may be required.", original_hedge_op_id);
-            return Err(loop_err);
-        }
-    };
-
-    // --- Успешное завершение ---
-    // Помечаем исходную операцию как расхеджированную
-    if let Err(e) = mark_hedge_as_unhedged(db, original_hedge_op_id).await {
-        error!(
-            "op_id:{}: Failed mark original hedge {} as unhedged in DB: {}",
-            original_hedge_op_id, original_hedge_op_id, e
-        );
-        // Не фатально для самой операции, но плохо для учета
-    }
-    info!(
-        "op_id:{}: Unhedge completed successfully for original op_id={}. Spot Sold: {:.8}, Fut Bought: {:.8}",
-        original_hedge_op_id, original_hedge_op_id, final_spot_sold_qty, final_fut_bought_qty
-    );
-
-    // --- Отправляем финальный колбэк для фьючерса (100%) ---
-    let fut_price_for_cb = match hedger.exchange.get_futures_ticker(&futures_symbol).await {
-         Ok(t) => (t.bid_price + t.ask_price) / 2.0, Err(_) => futures_market_price
-      };
-    let fut_done_update = HedgeProgressUpdate {
-        stage: HedgeStage::Futures,
-        current_spot_price: fut_price_for_cb, // Цена фьючерса
-        new_limit_price: futures_initial_limit_price, // Не так важно
-        is_replacement: false,
-        filled_qty: final_fut_bought_qty,
-        target_qty: final_fut_bought_qty,
-        cumulative_filled_qty: final_fut_bought_qty,
-        total_target_qty: futures_buy_qty,
-    };
-    let _ = progress_callback(fut_done_update).await; // Игнорируем ошибку
-    // --- Конец колбэка фьючерса ---
-
-    Ok((final_spot_sold_qty, final_fut_bought_qty))
+    Ok((final_spot_sold_qty, spot_price_for_cb, dust_qty))
 }
@@ -0,0 +1,95 @@
+// src/hedger/verify.rs
+// Проверка дельта-нейтральности после хеджа (включается флагом verify_hedge)
+
+use anyhow::{anyhow, Result};
+
+use crate::exchange::Exchange;
+
+/// Допустимое расхождение ног относительно изменения спота (2%): частичные исполнения и комиссии
+pub const HEDGE_DELTA_TOLERANCE_RATIO: f64 = 0.02;
+
+/// Экспозиция по монете: спот на балансе и фьючерсная позиция со знаком (шорт отрицательный)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExposureSnapshot {
+    pub spot_qty: f64,
+    pub futures_qty: f64,
+}
+
+/// Результат сравнения экспозиции до и после хеджа
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HedgeDeltaReport {
+    pub spot_delta: f64,    // Изменение спотового баланса
+    pub futures_delta: f64, // Изменение фьючерсной позиции (со знаком)
+    pub net_delta: f64,     // Итоговая дельта хеджа (0 — нейтрально)
+    pub balanced: bool,
+}
+
+/// Снимает текущую экспозицию: баланс базовой монеты и позицию по фьючерсу
+pub async fn snapshot_exposure<E: Exchange>(exchange: &E, base_symbol: &str, futures_symbol: &str) -> Result<ExposureSnapshot> {
+    let (balance_res, position_res) = tokio::join!(
+        exchange.get_balance(base_symbol),
+        exchange.get_position_details(futures_symbol),
+    );
+    let balance = balance_res.map_err(|e| anyhow!("Failed to get {} balance: {}", base_symbol, e))?;
+    let position = position_res.map_err(|e| anyhow!("Failed to get {} position: {}", futures_symbol, e))?;
+    Ok(ExposureSnapshot {
+        spot_qty: balance.free + balance.locked,
+        futures_qty: position.signed_size(),
+    })
+}
+
+/// Сравнивает экспозицию до и после: хедж сбалансирован, если изменения ног компенсируют друг друга
+pub fn compare_exposure(before: ExposureSnapshot, after: ExposureSnapshot, tolerance_ratio: f64) -> HedgeDeltaReport {
+    let spot_delta = after.spot_qty - before.spot_qty;
+    let futures_delta = after.futures_qty - before.futures_qty;
+    let net_delta = spot_delta + futures_delta;
+    let tolerance = spot_delta.abs().max(futures_delta.abs()) * tolerance_ratio;
+    HedgeDeltaReport {
+        spot_delta,
+        futures_delta,
+        net_delta,
+        balanced: net_delta.abs() <= tolerance,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::mock::MockExchange;
+    use crate::exchange::types::{Balance, OrderSide};
+
+    const BEFORE: ExposureSnapshot = ExposureSnapshot { spot_qty: 0.5, futures_qty: -0.5 };
+
+    #[test]
+    fn matched_legs_are_balanced() {
+        let after = ExposureSnapshot { spot_qty: 1.499, futures_qty: -1.5 };
+
+        let report = compare_exposure(BEFORE, after, HEDGE_DELTA_TOLERANCE_RATIO);
+
+        assert!(report.balanced);
+        assert!((report.net_delta + 0.001).abs() < 1e-9);
+    }
+
+    #[test]
+    fn partial_futures_fill_is_unbalanced() {
+        let after = ExposureSnapshot { spot_qty: 1.5, futures_qty: -1.0 };
+
+        let report = compare_exposure(BEFORE, after, HEDGE_DELTA_TOLERANCE_RATIO);
+
+        assert!(!report.balanced);
+        assert!((report.net_delta - 0.5).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn snapshot_uses_total_balance_and_signed_position() {
+        let exchange = MockExchange {
+            balances: vec![("BTC".to_string(), Balance { free: 1.0, locked: 0.25 })],
+            position: Some((OrderSide::Sell, 1.2)),
+            ..MockExchange::default()
+        };
+
+        let snapshot = snapshot_exposure(&exchange, "BTC", "BTCUSDT").await.unwrap();
+
+        assert_eq!(snapshot, ExposureSnapshot { spot_qty: 1.25, futures_qty: -1.2 });
+    }
+}
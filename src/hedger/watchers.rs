@@ -0,0 +1,167 @@
+// src/hedger/watchers.rs
+// Реестр фоновых наблюдателей (мониторы оставленных фьючерсных ордеров и т.п.) для /watchers
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::task::AbortHandle;
+use tracing::{info, warn};
+
+/// Тип фонового наблюдателя
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatcherKind {
+    PendingFutures, // Ждет исполнения фьючерсного ордера, оставленного на бирже (статус PendingFutures)
+}
+
+impl WatcherKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            WatcherKind::PendingFutures => "Ожидание фьючерсного ордера",
+        }
+    }
+}
+
+/// Описание наблюдателя для списка (без хэндла задачи)
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatcherSummary {
+    pub id: u64,
+    pub kind: WatcherKind,
+    pub chat_id: i64,
+    pub operation_id: i64,
+    pub symbol: String,
+    pub details: String, // Порог/цель наблюдателя в читаемом виде
+}
+
+#[derive(Debug)]
+struct WatcherEntry {
+    handle: AbortHandle,
+    summary: WatcherSummary,
+}
+
+/// Активные наблюдатели аккаунта по ID. Создается один на аккаунт в run_account и передается
+/// диспетчеру, Hedger и задачам запуска, поэтому /watchers одного бота не видит мониторы другого
+#[derive(Debug, Clone, Default)]
+pub struct WatcherRegistry {
+    entries: Arc<Mutex<HashMap<u64, WatcherEntry>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl WatcherRegistry {
+    /// Следующий ID: выдается до запуска задачи, чтобы задача могла снять себя по завершении
+    pub fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Регистрирует запущенную задачу; уже завершившаяся не добавляется
+    pub fn register(&self, handle: AbortHandle, summary: WatcherSummary) {
+        let id = summary.id;
+        let mut entries = self.entries.lock().unwrap();
+        if handle.is_finished() {
+            return;
+        }
+        info!("op_id:{}: Watcher {} registered ({:?}, {}).", summary.operation_id, id, summary.kind, summary.symbol);
+        entries.insert(id, WatcherEntry { handle, summary });
+    }
+
+    /// Снимает наблюдатель с учета (задача завершилась сама)
+    pub fn remove(&self, id: u64) {
+        self.entries.lock().unwrap().remove(&id);
+    }
+
+    /// Наблюдатели чата, по возрастанию ID
+    pub fn list_for_chat(&self, chat_id: i64) -> Vec<WatcherSummary> {
+        let mut list: Vec<WatcherSummary> = self
+            .entries
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.summary.chat_id == chat_id)
+            .map(|entry| entry.summary.clone())
+            .collect();
+        list.sort_by_key(|summary| summary.id);
+        list
+    }
+
+    /// За операцией следит живой наблюдатель (в любом чате): ее статус ведет задача
+    pub fn is_watching_operation(&self, operation_id: i64) -> bool {
+        self.entries
+            .lock()
+            .unwrap()
+            .values()
+            .any(|entry| entry.summary.operation_id == operation_id && !entry.handle.is_finished())
+    }
+
+    /// Останавливает наблюдатель чата; None — не найден или принадлежит другому чату
+    pub fn cancel(&self, chat_id: i64, id: u64) -> Option<WatcherSummary> {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.get(&id).is_none_or(|entry| entry.summary.chat_id != chat_id) {
+            warn!("Watcher {} not found for chat {}", id, chat_id);
+            return None;
+        }
+        let entry = entries.remove(&id)?;
+        entry.handle.abort();
+        info!("op_id:{}: Watcher {} cancelled by chat {}.", entry.summary.operation_id, id, chat_id);
+        Some(entry.summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn summary(registry: &WatcherRegistry, chat_id: i64, operation_id: i64) -> WatcherSummary {
+        WatcherSummary {
+            id: registry.next_id(),
+            kind: WatcherKind::PendingFutures,
+            chat_id,
+            operation_id,
+            symbol: "BTCUSDT".to_string(),
+            details: "order 1".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn watchers_are_listed_and_cancelled_per_chat() {
+        let registry = WatcherRegistry::default();
+        let task = tokio::spawn(tokio::time::sleep(Duration::from_secs(60)));
+        let own = summary(&registry, 1, 10);
+        registry.register(task.abort_handle(), own.clone());
+        let other = summary(&registry, 2, 20);
+        registry.register(tokio::spawn(tokio::time::sleep(Duration::from_secs(60))).abort_handle(), other.clone());
+
+        assert_eq!(registry.list_for_chat(1), vec![own.clone()]);
+        assert!(registry.is_watching_operation(10) && registry.is_watching_operation(20));
+        // Чужой наблюдатель отменить нельзя
+        assert_eq!(registry.cancel(1, other.id), None);
+        assert_eq!(registry.cancel(1, own.id), Some(own));
+        assert!(registry.list_for_chat(1).is_empty());
+        assert!(task.await.unwrap_err().is_cancelled());
+        assert!(!registry.is_watching_operation(10));
+        assert_eq!(registry.list_for_chat(2), vec![other]);
+    }
+
+    #[tokio::test]
+    async fn accounts_have_separate_registries() {
+        let (main_registry, sub_registry) = (WatcherRegistry::default(), WatcherRegistry::default());
+        let watcher = summary(&main_registry, 1, 10);
+        main_registry.register(tokio::spawn(tokio::time::sleep(Duration::from_secs(60))).abort_handle(), watcher.clone());
+
+        // Клон (диспетчер, Hedger) видит те же наблюдатели
+        assert_eq!(main_registry.clone().list_for_chat(1), vec![watcher.clone()]);
+        // Тот же чат в боте другого аккаунта: чужой монитор не виден и не останавливается
+        assert!(sub_registry.list_for_chat(1).is_empty());
+        assert_eq!(sub_registry.cancel(1, watcher.id), None);
+        assert_eq!(main_registry.cancel(1, watcher.id), Some(watcher));
+    }
+
+    #[tokio::test]
+    async fn finished_task_is_not_registered() {
+        let registry = WatcherRegistry::default();
+        let task = tokio::spawn(async {});
+        let handle = task.abort_handle();
+        task.await.unwrap();
+        registry.register(handle, summary(&registry, 1, 10));
+        assert!(registry.list_for_chat(1).is_empty());
+    }
+}
@@ -1,5 +1,6 @@
 pub mod config;
 pub mod exchange;
+pub mod health;
 pub mod hedger; 
 pub mod notifier;
 pub mod logger;
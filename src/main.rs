@@ -2,6 +2,7 @@
 
 mod config;
 mod exchange;
+mod health;
 mod hedger;
 mod notifier;
 mod logger;
@@ -10,21 +11,15 @@ mod utils;
 mod storage;
 mod telegram;
 mod webservice_hedge;
-use anyhow::Result;
-// --- ИЗМЕНЕНО: Используем tokio::sync::OnceCell ---
-use tokio::sync::OnceCell;
-// --- Конец изменений ---
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
 use teloxide::Bot;
-use tracing::info;
+use tokio::task::JoinSet;
+use tracing::{error, info, info_span, Instrument};
 
 use crate::config::Config;
-use crate::exchange::{bybit::Bybit, Exchange}; // --- ИЗМЕНЕНО: Импортируем Db из storage ---
-use crate::storage::Db;
-// --- Конец изменений ---
-
-// --- ИЗМЕНЕНО: Тип DB теперь Db (SqlitePool) ---
-static DB: OnceCell<Db> = OnceCell::const_new();
-// --- Конец изменений ---
+use crate::exchange::{bybit::Bybit, Exchange};
+use crate::health::{HealthState, SharedHealth};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -33,49 +28,83 @@ async fn main() -> Result<()> {
     logger::init(&cfg);
     info!("Logger initialized. Default volatility = {}", cfg.default_volatility);
 
-    // 2) Подключение к SQLite
-    // --- ИЗМЕНЕНО: Используем storage::connect ---
-    let db_pool = storage::connect(&cfg.sqlite_path).await?;
-    DB.set(db_pool).expect("DB can only be set once"); // Используем expect для уверенности
+    // 2) Аккаунты: каждый со своей БД, клиентом биржи и ботом; диспетчеры работают параллельно
+    let accounts = cfg.account_configs()?;
+    info!("Starting {} account(s)", accounts.len());
+    let health: SharedHealth = Arc::new(HealthState::default());
+    if let Some(port) = cfg.health_port {
+        health::spawn_health_server(port, health.clone(), cfg.health_max_ping_age_secs);
+    }
+    let mut dispatchers = JoinSet::new();
+    for (name, account_cfg) in accounts {
+        health.register(&name);
+        let span = info_span!("account", %name);
+        dispatchers.spawn(run_account(name, account_cfg, health.clone()).instrument(span));
+    }
+
+    // Ошибка запуска одного аккаунта не останавливает остальные
+    let mut failed = 0;
+    while let Some(result) = dispatchers.join_next().await {
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                error!("Account stopped with error: {:?}", e);
+                failed += 1;
+            }
+            Err(e) => {
+                error!("Account task panicked: {}", e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow!("{} account(s) failed", failed));
+    }
+    Ok(())
+}
+
+/// Запуск одного аккаунта: БД, бот, клиент Bybit, фоновые задачи и Telegram-диспетчер
+async fn run_account(name: String, cfg: Config, health: SharedHealth) -> Result<()> {
+    // 1) Подключение к SQLite
+    let db = storage::connect(&cfg.sqlite_path, cfg.db_schema_self_check).await?;
     info!("Connected to SQLite database: {}", cfg.sqlite_path);
-    // --- Конец изменений ---
 
-    // 3) Telegram Bot
+    // 2) Telegram Bot
     let bot = Bot::new(&cfg.telegram_token);
     info!("Telegram bot initialized.");
 
-    // 4) Выбираем base_url
-    let base_url: &str = cfg
-        .bybit_base_url
-        .as_deref()
-        .filter(|s| !s.is_empty())
-        .unwrap_or_else(|| {
-            if cfg.use_testnet {
-                "https://api-testnet.bybit.com"
-            } else {
-                "https://api.bybit.com"
-            }
-        });
-    info!("Using Bybit base URL: {}", base_url);
-
-    // 5) Создаём клиента биржи
-    let mut exchange = Bybit::new(
-        &cfg.bybit_api_key,
-        &cfg.bybit_api_secret,
-        base_url,
-        &cfg.quote_currency,
-    ).await?;
+    // 3) Создаём клиента биржи
+    let mut exchange = Bybit::from_config(&cfg).await?;
     info!("Bybit client created for quote currency: {}", cfg.quote_currency);
 
-    // 6) Пингуем Bybit
+    // 4) Пингуем Bybit
     info!("Pinging Bybit...");
     exchange.check_connection().await?;
+    health.record_ping_ok(&name, chrono::Utc::now().timestamp());
+    if cfg.health_port.is_some() {
+        health::spawn_health_ping_task(exchange.clone(), name.clone(), health.clone(), cfg.health_ping_interval_secs);
+    }
+
+    // 5) Возобновляем наблюдение за оставленными фьючерсными ордерами (реестр /watchers — свой у каждого аккаунта)
+    let watchers = hedger::WatcherRegistry::default();
+    hedger::resume_pending_futures_monitors(exchange.clone(), db.clone(), &watchers, cfg.environment()).await;
+
+    // 6) Операции, прерванные перезапуском: свежие (resume_grace_secs) возобновляются, старые — на ручную проверку.
+    // Довыставляемые ноги попадают в /active диспетчера, поэтому запущенные операции создаются здесь
+    let running_operations = notifier::RunningOperations::default();
+    notifier::recovery::spawn_startup_recovery(bot.clone(), exchange.clone(), cfg.clone(), db.clone(), watchers.clone(), running_operations.clone());
+
+    // 7) Фоновый учет накопленного фандинга по открытым хеджам
+    notifier::funding_accrual::spawn_funding_accrual_task(bot.clone(), exchange.clone(), cfg.clone(), db.clone());
+
+    // 8) Ежедневная сводка операций (daily_digest_time)
+    notifier::daily_digest::spawn_daily_digest_task(bot.clone(), cfg.clone(), db.clone());
 
-    // 7) Стартуем Telegram‑диспетчер
+    // 9) Стартуем Telegram‑диспетчер (состояния диалогов и запущенные операции — свои у каждого бота)
     info!("Starting Telegram dispatcher...");
-    // --- ИЗМЕНЕНО: Передаем DB.get().unwrap() ---
-    telegram::run(bot, exchange, cfg.clone(), DB.get().unwrap().clone()).await; // Клонируем пул соединений
-    // --- Конец изменений ---
+    health.set_dispatcher_running(&name, true);
+    telegram::run(bot, exchange, cfg, db, watchers, running_operations).await;
+    health.set_dispatcher_running(&name, false);
 
     Ok(())
 }
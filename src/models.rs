@@ -7,6 +7,7 @@ pub struct HedgeRequest {
     pub sum: f64,
     pub symbol: String,
     pub volatility: f64,
+    pub spot_price_guard: Option<f64>, // Худшая допустимая цена покупки спота; None — без ограничения
 }
 
 /// Запрос на расхеджирование
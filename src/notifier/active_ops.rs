@@ -1,12 +1,14 @@
 // src/notifier/active_ops.rs
 
 use crate::notifier::{
-    RunningOperations, RunningOperationInfo, OperationType, callback_data, navigation, StateStorage
+    RunningOperations, RunningOperationInfo, OperationType, alerts, callback_data, navigation, StateStorage
 };
-use crate::storage::{Db, update_hedge_final_status, get_hedge_operation_by_id};
+use crate::storage::{Db, HedgeOperation, LegFill, OperationStatus, finalize_operation, get_hedge_operation_by_id, get_all_completed_unhedged_ops, mark_hedge_spot_only_orphan, update_hedge_spot_order};
 use crate::config::Config;
 use crate::exchange::Exchange;
-use crate::hedger::ORDER_FILL_TOLERANCE;
+use crate::hedger::{finalize_with_retry, write_with_retry, DbWriteRetry, HedgeStage, SpotOnlyOrphan, ORDER_FILL_TOLERANCE};
+use crate::notifier::spot_orphan::{format_spot_orphan_warning, make_spot_orphan_keyboard};
+use crate::notifier::utils::{delete_user_message, describe_error, display_decimals, format_qty, operation_label};
 use crate::exchange::types::OrderSide; // <-- ДОБАВЬ ЭТУ СТРОКУ
 use std::sync::Arc;
 use teloxide::prelude::*;
@@ -59,14 +61,18 @@ async fn format_active_operations(
         };
         // TODO: Получить target_qty из БД?
         text.push_str(&format!(
-            "🔹 ID:{} ({}) - {} \n   Прогресс спот: ~{:.6} (?)\n",
-            op_id, info.symbol, op_type_str, filled_qty
+            "🔹 {} ({}) - {} \n   Прогресс спот: ~{:.6} (?)\n",
+            operation_label(info.op_ref.as_deref(), *op_id), info.symbol, op_type_str, filled_qty
         ));
         let cancel_data = format!("{}{}", callback_data::PREFIX_CANCEL_ACTIVE_OP, op_id);
-        buttons.push(vec![InlineKeyboardButton::callback(
-            format!("❌ Отменить ID:{}", op_id),
-            cancel_data,
-        )]);
+        let status_data = format!("{}{}", callback_data::PREFIX_SHOW_OP_STATUS, op_id);
+        buttons.push(vec![
+            InlineKeyboardButton::callback(format!("🔄 Статус ID:{}", op_id), status_data),
+            InlineKeyboardButton::callback(format!("❌ Отменить ID:{}", op_id), cancel_data),
+        ]);
+        if let Some(keep_spot_button) = make_keep_spot_cancel_button(*op_id, *info.stage.lock().await, filled_qty) {
+            buttons.push(vec![keep_spot_button]);
+        }
     }
 
     buttons.push(vec![InlineKeyboardButton::callback(
@@ -77,6 +83,71 @@ async fn format_active_operations(
     (text, InlineKeyboardMarkup::new(buttons))
 }
 
+/// Раздел /active об открытых хеджах и накопленном расходе на фандинг (None — открытых хеджей нет)
+fn format_open_hedges_funding(open_hedges: &[HedgeOperation]) -> Option<String> {
+    if open_hedges.is_empty() {
+        return None;
+    }
+    let mut text = format!("\n💸 Открытые хеджи, расход на фандинг ({} шт.):\n", open_hedges.len());
+    for op in open_hedges {
+        text.push_str(&format!(
+            "🔸 {} ({}): {:+.2} {}\n",
+            operation_label(op.op_ref.as_deref(), op.id), op.base_symbol, op.accrued_funding, op.quote_currency
+        ));
+    }
+    Some(text)
+}
+
+/// Формирует свежее сообщение о состоянии активной операции (или None, если она уже завершилась)
+async fn format_operation_status(
+    running_operations: &RunningOperations,
+    chat_id: ChatId,
+    operation_id: i64,
+    max_decimals: u32,
+) -> Option<String> {
+    let ops_guard = running_operations.lock().await;
+    let info = ops_guard.get(&(chat_id, operation_id))?;
+    let filled_qty = *info.total_filled_spot_qty.lock().await;
+    let stage = *info.stage.lock().await;
+    let active_order = info.active_order.lock().await.clone();
+
+    let mut text = format!(
+        "📍 Операция {} ({}) - {}\nИсполнено спот: ~{}\nЭтап: {}\n",
+        operation_label(info.op_ref.as_deref(), operation_id),
+        info.symbol,
+        info.operation_type.as_str(),
+        format_qty(filled_qty, display_decimals(None, max_decimals)),
+        match stage {
+            HedgeStage::Spot => "спот",
+            HedgeStage::Futures => "фьючерс",
+        }
+    );
+    match active_order {
+        Some(order) => text.push_str(&format!(
+            "Текущий ордер: {} ({}, {})",
+            order.order_id,
+            order.symbol,
+            if order.is_spot { "спот" } else { "фьючерс" }
+        )),
+        None => text.push_str("Текущий ордер: нет (ожидание/пересчет)"),
+    }
+    Some(text)
+}
+
+/// Клавиатура сообщения о статусе: обновление на месте и отмена
+fn make_op_status_keyboard(operation_id: i64) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback(
+            "🔄 Обновить",
+            format!("{}{}", callback_data::PREFIX_REFRESH_OP_STATUS, operation_id),
+        ),
+        InlineKeyboardButton::callback(
+            "❌ Отменить",
+            format!("{}{}", callback_data::PREFIX_CANCEL_ACTIVE_OP, operation_id),
+        ),
+    ]])
+}
+
 // --- Обработчики Команд и Колбэков ---
 
 /// Обработчик команды /active
@@ -86,8 +157,8 @@ pub async fn handle_active_command<E>(
     _exchange: Arc<E>,
     _state_storage: StateStorage,
     running_operations: RunningOperations,
-    _cfg: Arc<Config>,
-    _db: Arc<Db>,
+    cfg: Arc<Config>,
+    db: Arc<Db>,
 ) -> anyhow::Result<()>
 where
     E: Exchange + Clone + Send + Sync + 'static,
@@ -95,14 +166,20 @@ where
     let chat_id = msg.chat.id;
     info!("Processing /active command for chat_id: {}", chat_id);
 
-    let (text, keyboard) = format_active_operations(&running_operations, chat_id).await;
+    let (mut text, keyboard) = format_active_operations(&running_operations, chat_id).await;
+    match get_all_completed_unhedged_ops(db.as_ref(), chat_id.0, cfg.environment()).await {
+        Ok(open_hedges) => {
+            if let Some(funding_text) = format_open_hedges_funding(&open_hedges) {
+                text.push_str(&funding_text);
+            }
+        }
+        Err(e) => warn!("Failed to load open hedges for /active funding section: {}", e),
+    }
     bot.send_message(chat_id, text)
         .reply_markup(keyboard)
         .await?;
 
-    if let Err(e) = bot.delete_message(chat_id, msg.id).await {
-        warn!("Failed to delete /active command message: {}", e);
-    }
+    delete_user_message(&bot, &cfg, chat_id, msg.id, "/active command message").await;
     Ok(())
 }
 
@@ -139,7 +216,7 @@ pub async fn handle_cancel_active_op_callback<E>(
     exchange: Arc<E>,
     _state_storage: StateStorage,
     running_operations: RunningOperations,
-    _cfg: Arc<Config>,
+    cfg: Arc<Config>,
     db: Arc<Db>,
 ) -> anyhow::Result<()>
 where
@@ -194,15 +271,17 @@ where
                 // --- Если информация найдена, выполняем отмену ---
                 if let Some(operation_info) = operation_info_opt {
                     let symbol = operation_info.symbol.clone();
+                    let op_label = operation_label(operation_info.op_ref.as_deref(), operation_id_to_cancel);
                     let bot_message_id_to_edit = MessageId(operation_info.bot_message_id);
                     let operation_type = operation_info.operation_type;
+                    let stage = *operation_info.stage.lock().await;
 
                     info!("op_id:{}: Aborting task...", operation_id_to_cancel);
                     operation_info.handle.abort();
 
                     let cancelling_text = format!(
-                        "⏳ Отмена операции ID:{} ({}) ...",
-                        operation_id_to_cancel, symbol
+                        "⏳ Отмена операции {} ({}) ...",
+                        op_label, symbol
                     );
                     let _ = bot
                         .edit_message_text(chat_id, bot_message_id_to_edit, cancelling_text)
@@ -212,67 +291,84 @@ where
                     // --- Логика обработки отмены ---
                     let mut final_error_message: Option<String> = None;
                     let mut net_spot_change_on_cancel = 0.0;
+                    let mut kept_spot_on_cancel = 0.0;
+                    let mut cancelled_spot_order_id: Option<String> = None;
 
-                    // Получаем ID последнего ордера из БД
-                    let last_spot_order_id_from_db = match get_hedge_operation_by_id(db.as_ref(), operation_id_to_cancel).await {
-                        // --- ИСПРАВЛЕНО: Используем spot_order_id вместо last_spot_order_id ---
-                        Ok(Some(op)) => op.spot_order_id, // Получаем ID из записи операции
-                        Ok(None) => {
-                            warn!("op_id:{}: Operation not found in DB during cancellation.", operation_id_to_cancel);
-                            None
+                    // 1. Отмена текущего активного ордера с учетом его ноги (спот/фьючерс)
+                    let active_order = operation_info.active_order.lock().await.clone();
+                    // (символ, ID, спот?, исполнено на этапе до ордера — если известно)
+                    let order_to_cancel: Option<(String, String, bool, Option<f64>)> = match active_order {
+                        Some(order) => {
+                            info!(
+                                "op_id:{}: Active order {} on {:?} leg ({}) will be cancelled",
+                                operation_id_to_cancel, order.order_id, order.stage, order.symbol
+                            );
+                            Some((order.symbol, order.order_id, order.is_spot, Some(order.filled_before)))
                         }
-                        Err(e) => {
-                            error!("op_id:{}: Failed to query DB for last order ID during cancellation: {}", operation_id_to_cancel, e);
-                            if final_error_message.is_none() {
-                                final_error_message = Some(format!("DB query failed: {}", e));
+                        None => {
+                            // Нет данных об активном ордере (например, WS-задача) - берем спотовый ордер из БД
+                            match get_hedge_operation_by_id(db.as_ref(), operation_id_to_cancel).await {
+                                Ok(Some(op)) => op.spot_order_id.map(|id| (symbol.clone(), id, true, None)),
+                                Ok(None) => {
+                                    warn!("op_id:{}: Operation not found in DB during cancellation.", operation_id_to_cancel);
+                                    None
+                                }
+                                Err(e) => {
+                                    error!("op_id:{}: Failed to query DB for last order ID during cancellation: {}", operation_id_to_cancel, e);
+                                    if final_error_message.is_none() {
+                                        final_error_message = Some(format!("DB query failed: {}", e));
+                                    }
+                                    None
+                                }
                             }
-                            None
                         }
                     };
 
-                    // 1. Отмена текущего активного ордера (если ID известен из БД)
-                    if let Some(ref order_id) = last_spot_order_id_from_db {
+                    if let Some((order_symbol, order_id, is_spot_order, filled_before)) = order_to_cancel {
                         info!(
-                            "op_id:{}: Cancelling last known order {} from DB ({:?})",
-                            operation_id_to_cancel, order_id, operation_type
+                            "op_id:{}: Cancelling {} order {} ({:?})",
+                            operation_id_to_cancel, if is_spot_order { "spot" } else { "futures" }, order_id, operation_type
                         );
-                        let is_spot_order = match operation_type {
-                            OperationType::Hedge => true,
-                            OperationType::Unhedge => false,
-                        };
-                        let symbol_for_cancel = if is_spot_order {
-                            &symbol
-                        } else {
-                            warn!("op_id:{}: Cancellation for futures order in Unhedge not fully implemented yet.", operation_id_to_cancel);
-                            ""
-                        };
-
-                        if !symbol_for_cancel.is_empty() {
-                            match cancel_order_generic(exchange.clone(), symbol_for_cancel, order_id, is_spot_order).await {
-                                Ok(_) => info!(
-                                    "op_id:{}: Order cancel request sent OK.",
-                                    operation_id_to_cancel
-                                ),
-                                Err(e) => {
-                                    warn!(
-                                        "op_id:{}: Order cancel FAILED: {}. Might be already filled/cancelled.",
-                                        operation_id_to_cancel, e
-                                    );
-                                    if final_error_message.is_none() {
-                                        final_error_message = Some(format!("Failed cancel order: {}", e));
-                                    }
+                        match cancel_order_generic(exchange.clone(), &order_symbol, &order_id, is_spot_order).await {
+                            Ok(_) => info!(
+                                "op_id:{}: Order cancel request sent OK.",
+                                operation_id_to_cancel
+                            ),
+                            Err(e) => {
+                                warn!(
+                                    "op_id:{}: Order cancel FAILED: {}. Might be already filled/cancelled.",
+                                    operation_id_to_cancel, e
+                                );
+                                if final_error_message.is_none() {
+                                    final_error_message = Some(format!("Failed cancel order: {}", e));
                                 }
                             }
-                            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                        }
+                        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                        // Спотовая покупка хеджа могла доисполниться между последним опросом и отменой
+                        if is_spot_order {
+                            cancelled_spot_order_id = Some(order_id.clone());
+                        }
+                        if let (OperationType::Hedge, true, Some(filled_before)) = (operation_type, is_spot_order, filled_before) {
+                            filled_spot_qty_in_operation = settle_spot_fill_after_cancel(
+                                exchange.as_ref(), operation_id_to_cancel, &order_symbol, &order_id, filled_before, filled_spot_qty_in_operation,
+                            ).await;
                         }
                     } else {
-                        info!("op_id:{}: No active order ID found in DB to cancel.", operation_id_to_cancel);
+                        info!("op_id:{}: No active order ID found to cancel.", operation_id_to_cancel);
                     }
 
                     // 2. Компенсирующее действие на бирже (логика остается прежней)
                     match operation_type {
-                        OperationType::Hedge => {
-                            if filled_spot_qty_in_operation > ORDER_FILL_TOLERANCE {
+                        OperationType::Hedge => match spot_on_hedge_cancel(HedgeCancelMode::Full, stage, filled_spot_qty_in_operation, cfg.sell_spot_on_hedge_cancel) {
+                            Some(SpotOnCancel::KeepAsOrphan(_)) => {
+                                info!(
+                                    "op_id:{}: Hedge cancelled. Keeping filled spot qty {} (sell_spot_on_hedge_cancel = false)",
+                                    operation_id_to_cancel, filled_spot_qty_in_operation
+                                );
+                                kept_spot_on_cancel = filled_spot_qty_in_operation;
+                            }
+                            Some(SpotOnCancel::Sell(_)) => {
                                 info!(
                                     "op_id:{}: Hedge cancelled. Attempting to sell filled spot qty: {}",
                                     operation_id_to_cancel, filled_spot_qty_in_operation
@@ -308,13 +404,14 @@ where
                                         final_error_message = Some("Balance too low to sell filled spot.".to_string());
                                     }
                                 }
-                            } else {
+                            }
+                            _ => {
                                 info!(
                                     "op_id:{}: No significant spot filled ({}) during hedge cancel, skipping sell.",
                                     operation_id_to_cancel, filled_spot_qty_in_operation
                                 );
                             }
-                        }
+                        },
                         OperationType::Unhedge => {
                             warn!(
                                 "op_id:{}: Unhedge cancellation logic: Assuming futures were not bought yet. No futures action taken.",
@@ -330,11 +427,23 @@ where
                         }
                     }
 
-                    // 3. Обновление статуса в БД
-                    let final_db_status = "Cancelled";
-                    let final_spot_qty_for_db = match operation_type {
-                         OperationType::Hedge => net_spot_change_on_cancel,
-                         OperationType::Unhedge => 0.0,
+                    // 3. Обновление статуса в БД: оставленный спот не захеджирован — операция SpotOnlyOrphan, иначе Cancelled
+                    let mut kept_spot_orphan: Option<SpotOnlyOrphan> = None;
+                    let db_retry = DbWriteRetry::from_config(&cfg);
+                    if kept_spot_on_cancel > ORDER_FILL_TOLERANCE {
+                        match keep_spot_as_orphan(db.as_ref(), db_retry, operation_id_to_cancel, cancelled_spot_order_id.as_deref(), kept_spot_on_cancel, KEPT_SPOT_CANCEL_REASON).await {
+                            Ok(orphan) => kept_spot_orphan = Some(orphan),
+                            Err(db_err) => {
+                                error!("op_id:{}: Failed to mark operation as SpotOnlyOrphan after cancellation: {}", operation_id_to_cancel, db_err);
+                                final_error_message.get_or_insert(describe_error(&db_err));
+                            }
+                        }
+                    }
+                    let final_db_status = OperationStatus::Cancelled;
+                    // Купленный спот — в спотовую ногу (фьючерсная нога отменой не меняется); расхедж спот хеджа не трогает
+                    let final_spot_leg = match operation_type {
+                         OperationType::Hedge => Some(LegFill { order_id: cancelled_spot_order_id.as_deref(), filled_qty: filled_spot_qty_in_operation }),
+                         OperationType::Unhedge => None,
                     };
 
                     let cancel_reason_str = "cancelled by user";
@@ -345,14 +454,15 @@ where
                         final_error_text_for_db = Some(cancel_reason_str.to_string());
                     }
 
-                    // Вызываем update_hedge_final_status
+                    // Финальная запись с повторами (finalize_cancelled)
                     // --- ИСПРАВЛЕНО: Используем .as_deref() для final_error_text_for_db ---
-                    if let Err(db_err) = update_hedge_final_status(
+                    if kept_spot_orphan.is_some() {
+                        info!("op_id:{}: DB status updated to 'SpotOnlyOrphan'. Spot qty kept on cancel: {}", operation_id_to_cancel, kept_spot_on_cancel);
+                    } else if let Err(db_err) = finalize_cancelled(
                         db.as_ref(),
+                        db_retry,
                         operation_id_to_cancel,
-                        final_db_status,
-                        None, // last_spot_order_id - оставляем None при отмене
-                        final_spot_qty_for_db,
+                        final_spot_leg,
                         final_error_text_for_db.as_deref(), // <-- ИСПОЛЬЗУЕМ .as_deref()
                     )
                     .await
@@ -362,25 +472,27 @@ where
                             operation_id_to_cancel, db_err
                         );
                          if final_error_message.is_none() {
-                            final_error_message = Some(format!("DB update failed: {}", db_err));
+                            final_error_message = Some(describe_error(&db_err));
                          }
                     } else {
                         info!(
-                            "op_id:{}: DB status updated to '{}'. Spot qty changed on cancel: {}",
-                            operation_id_to_cancel, final_db_status, final_spot_qty_for_db
+                            "op_id:{}: DB status updated to '{}'. Spot qty sold on cancel: {}",
+                            operation_id_to_cancel, final_db_status, net_spot_change_on_cancel
                         );
                     }
 
 
                     // 4. Финальное сообщение пользователю (логика остается прежней)
                     let mut final_text = format!(
-                        "❌ Операция ID:{} ({}, {}) отменена пользователем.",
-                        operation_id_to_cancel, symbol, operation_type.as_str()
+                        "❌ Операция {} ({}, {}) отменена пользователем.",
+                        op_label, symbol, operation_type.as_str()
                     );
                      match operation_type {
                          OperationType::Hedge => {
                               if net_spot_change_on_cancel > ORDER_FILL_TOLERANCE {
                                   final_text.push_str(&format!("\nПродано ~{:.8} {} спота.", net_spot_change_on_cancel, symbol));
+                              } else if kept_spot_on_cancel > ORDER_FILL_TOLERANCE {
+                                  final_text.push_str(&format!("\nКупленный спот ~{:.8} {} оставлен на балансе без хеджа.", kept_spot_on_cancel, symbol));
                               } else if filled_spot_qty_in_operation > ORDER_FILL_TOLERANCE {
                                    if final_error_message.as_ref().map_or(false, |s| s.contains("Failed sell spot") || s.contains("Failed get balance") || s.contains("Balance too low")) {
                                         final_text.push_str("\nПопытка продать накопленный спот не удалась.");
@@ -391,13 +503,22 @@ where
                              // Добавить информацию при необходимости
                          }
                      }
+                     // Незахеджированный спот: предупреждение с кнопками довыставления фьючерса и алерт
+                     let keyboard = match &kept_spot_orphan {
+                         Some(orphan) => {
+                             final_text = format_spot_orphan_warning(&op_label, &symbol, orphan, cfg.display_max_decimals);
+                             alerts::send_alert(&bot, &cfg, chat_id, &final_text).await;
+                             make_spot_orphan_keyboard(operation_id_to_cancel)
+                         }
+                         None => navigation::make_main_menu_keyboard(),
+                     };
                      if let Some(err_msg) = final_error_message {
                          final_text.push_str(&format!("\n⚠️ Ошибка при отмене: {}", err_msg));
                      }
 
                     let _ = bot
                         .edit_message_text(chat_id, bot_message_id_to_edit, final_text)
-                        .reply_markup(navigation::make_main_menu_keyboard())
+                        .reply_markup(keyboard)
                         .await;
                 }
 
@@ -426,6 +547,388 @@ where
     Ok(())
 }
 
+/// Вид отмены хеджа
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HedgeCancelMode {
+    Full,     // Отмена операции целиком (спот продается или остается SpotOnlyOrphan по sell_spot_on_hedge_cancel)
+    KeepSpot, // Отмена только ожидания фьючерса: спот остается, операция -> SpotOnlyOrphan
+}
+
+/// Что происходит с купленным спотом при отмене хеджа
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpotOnCancel {
+    Sell(f64),
+    KeepAsOrphan(f64), // Операция SpotOnlyOrphan: спот на балансе, фьючерсную ногу можно довыставить позже
+    Nothing,           // Спот не куплен
+}
+
+/// Решение по споту для отмены хеджа; None — вид отмены недоступен на этом этапе
+/// (KeepSpot — только после покупки спота, на этапе фьючерса)
+pub fn spot_on_hedge_cancel(mode: HedgeCancelMode, stage: HedgeStage, filled_spot_qty: f64, sell_spot_on_hedge_cancel: bool) -> Option<SpotOnCancel> {
+    let has_spot = filled_spot_qty > ORDER_FILL_TOLERANCE;
+    match mode {
+        HedgeCancelMode::Full if !has_spot => Some(SpotOnCancel::Nothing),
+        HedgeCancelMode::Full if sell_spot_on_hedge_cancel => Some(SpotOnCancel::Sell(filled_spot_qty)),
+        HedgeCancelMode::Full => Some(SpotOnCancel::KeepAsOrphan(filled_spot_qty)),
+        HedgeCancelMode::KeepSpot if stage == HedgeStage::Futures && has_spot => Some(SpotOnCancel::KeepAsOrphan(filled_spot_qty)),
+        HedgeCancelMode::KeepSpot => None,
+    }
+}
+
+/// Кнопка отмены только фьючерсной ноги (None — на этом этапе недоступна)
+pub fn make_keep_spot_cancel_button(operation_id: i64, stage: HedgeStage, filled_spot_qty: f64) -> Option<InlineKeyboardButton> {
+    spot_on_hedge_cancel(HedgeCancelMode::KeepSpot, stage, filled_spot_qty, false)?;
+    Some(InlineKeyboardButton::callback(
+        "⏹ Отменить фьючерс, оставить спот",
+        format!("{}{}", callback_data::PREFIX_CANCEL_FUTURES_KEEP_SPOT, operation_id),
+    ))
+}
+
+/// Обработчик отмены ожидания фьючерсной ноги с сохранением спота (префикс cancel_fut_):
+/// задача прерывается, фьючерсный ордер снимается, операция помечается SpotOnlyOrphan без продажи спота
+pub async fn handle_cancel_futures_keep_spot_callback<E>(
+    bot: Bot,
+    query: CallbackQuery,
+    exchange: Arc<E>,
+    running_operations: RunningOperations,
+    cfg: Arc<Config>,
+    db: Arc<Db>,
+) -> anyhow::Result<()>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let (Some(data), Some(msg)) = (query.data.as_deref(), query.message.as_ref()) else {
+        warn!("CallbackQuery missing data or message in handle_cancel_futures_keep_spot_callback");
+        bot.answer_callback_query(query.id).await?;
+        return Ok(());
+    };
+    let chat_id = msg.chat().id;
+    let Some(operation_id) = data
+        .strip_prefix(callback_data::PREFIX_CANCEL_FUTURES_KEEP_SPOT)
+        .and_then(|s| s.parse::<i64>().ok())
+    else {
+        error!("Failed to parse operation_id from keep-spot cancel callback data: {}", data);
+        bot.answer_callback_query(query.id).text("Ошибка: Неверный ID операции.").await?;
+        return Ok(());
+    };
+    info!("op_id:{}: User {} requested futures-only cancellation (keep spot)", operation_id, chat_id);
+
+    // Этап проверяется до удаления из карты: пока спот покупается, доступна только полная отмена
+    let operation_info = {
+        let mut ops_guard = running_operations.lock().await;
+        let Some(info) = ops_guard.get(&(chat_id, operation_id)) else {
+            bot.answer_callback_query(query.id).text("Операция уже завершена или отменена.").show_alert(true).await?;
+            return Ok(());
+        };
+        let stage = *info.stage.lock().await;
+        let filled_spot_qty = *info.total_filled_spot_qty.lock().await;
+        if info.operation_type != OperationType::Hedge
+            || spot_on_hedge_cancel(HedgeCancelMode::KeepSpot, stage, filled_spot_qty, cfg.sell_spot_on_hedge_cancel).is_none()
+        {
+            bot.answer_callback_query(query.id)
+                .text("Спот еще не куплен — доступна только полная отмена операции.")
+                .show_alert(true)
+                .await?;
+            return Ok(());
+        }
+        ops_guard.remove(&(chat_id, operation_id)).expect("operation present under the same lock")
+    };
+    bot.answer_callback_query(query.id).await?;
+
+    info!("op_id:{}: Aborting task, spot leg will be kept...", operation_id);
+    operation_info.handle.abort();
+    let op_label = operation_label(operation_info.op_ref.as_deref(), operation_id);
+    let bot_message_id_to_edit = MessageId(operation_info.bot_message_id);
+    let _ = bot
+        .edit_message_text(chat_id, bot_message_id_to_edit, format!("⏳ Отмена фьючерсной части {} ({})...", op_label, operation_info.symbol))
+        .reply_markup(InlineKeyboardMarkup::new(Vec::<Vec<InlineKeyboardButton>>::new()))
+        .await;
+
+    let (orphan, cancel_error) =
+        abandon_futures_leg(exchange.as_ref(), db.as_ref(), DbWriteRetry::from_config(&cfg), operation_id, &operation_info).await;
+    let mut text = format_spot_orphan_warning(&op_label, &operation_info.symbol, &orphan, cfg.display_max_decimals);
+    if let Some(err_msg) = cancel_error {
+        text.push_str(&format!("\n⚠️ Ошибка при отмене: {}", err_msg));
+    }
+    let _ = bot
+        .edit_message_text(chat_id, bot_message_id_to_edit, text)
+        .reply_markup(make_spot_orphan_keyboard(operation_id))
+        .await;
+    Ok(())
+}
+
+/// Снятие фьючерсной ноги после прерывания задачи (отмена с сохранением спота): ордер снимается,
+/// операция помечается SpotOnlyOrphan. Объемы — из БД и учета задачи; при сбое чтения БД — только из учета,
+/// чтобы ордер все равно был снят, а пользователь получил итог. Второе значение — ошибки для сообщения
+async fn abandon_futures_leg<E: Exchange>(
+    exchange: &E,
+    db: &Db,
+    db_retry: DbWriteRetry,
+    operation_id: i64,
+    operation_info: &RunningOperationInfo,
+) -> (SpotOnlyOrphan, Option<String>) {
+    let mut errors: Vec<String> = Vec::new();
+    let operation = match get_hedge_operation_by_id(db, operation_id).await {
+        Ok(operation) => operation,
+        Err(e) => {
+            error!("op_id:{}: Failed to load operation after keep-spot abort: {}. Using tracked quantities.", operation_id, e);
+            errors.push(format!("DB read failed: {}", e));
+            None
+        }
+    };
+
+    // Исполненный объем фьючерса: из БД (фиксируется циклом ордеров) и по статусу снятого ордера
+    let mut futures_filled_qty = operation.as_ref().map_or(0.0, |op| op.futures_filled_qty);
+    if let Some(order) = operation_info.active_order.lock().await.clone().filter(|order| !order.is_spot) {
+        futures_filled_qty = futures_filled_qty.max(order.filled_before);
+        if let Err(e) = exchange.cancel_futures_order(&order.symbol, &order.order_id).await {
+            warn!("op_id:{}: Futures order {} cancel FAILED: {}. Might be already filled/cancelled.", operation_id, order.order_id, e);
+            errors.push(format!("Failed cancel order: {}", e));
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        match exchange.get_futures_order_status(&order.symbol, &order.order_id).await {
+            Ok(status) => futures_filled_qty = futures_filled_qty.max(order.filled_before + status.filled_qty),
+            Err(e) => warn!("op_id:{}: Failed to get futures order {} status after cancel: {}", operation_id, order.order_id, e),
+        }
+    }
+
+    let spot_filled_qty = operation
+        .as_ref()
+        .map_or(0.0, |op| op.spot_filled_qty)
+        .max(*operation_info.total_filled_spot_qty.lock().await);
+    let reason = "Futures leg cancelled by user, spot kept".to_string();
+    let marked = write_with_retry(db_retry, operation_id, "mark SpotOnlyOrphan", || {
+        mark_hedge_spot_only_orphan(db, operation_id, futures_filled_qty, &reason)
+    })
+    .await;
+    if let Err(e) = marked {
+        error!("op_id:{}: Failed to mark operation as SpotOnlyOrphan after keep-spot cancel: {}", operation_id, e);
+        errors.push(describe_error(&e));
+    }
+    info!(
+        "op_id:{}: Futures leg abandoned by user: spot {:.8} kept, futures filled {:.8}",
+        operation_id, spot_filled_qty, futures_filled_qty
+    );
+    let cancel_error = (!errors.is_empty()).then(|| errors.join("\n"));
+    (SpotOnlyOrphan { spot_filled_qty, futures_filled_qty, reason }, cancel_error)
+}
+
+/// Обработчик колбэков статуса активной операции: op_status_ присылает новое сообщение,
+/// op_refresh_ (кнопка «Обновить» в нем) перерисовывает это сообщение на месте
+pub async fn handle_show_op_status_callback(
+    bot: Bot,
+    query: CallbackQuery,
+    running_operations: RunningOperations,
+    cfg: Arc<Config>,
+    db: Arc<Db>,
+) -> anyhow::Result<()> {
+    let (Some(data), Some(msg)) = (query.data.as_deref(), query.message.as_ref()) else {
+        warn!("CallbackQuery missing data or message in handle_show_op_status_callback");
+        bot.answer_callback_query(query.id).await?;
+        return Ok(());
+    };
+    let chat_id = msg.chat().id;
+    let (refresh, id_part) = match data.strip_prefix(callback_data::PREFIX_REFRESH_OP_STATUS) {
+        Some(rest) => (true, Some(rest)),
+        None => (false, data.strip_prefix(callback_data::PREFIX_SHOW_OP_STATUS)),
+    };
+    let Some(operation_id) = id_part.and_then(|s| s.parse::<i64>().ok()) else {
+        error!("Failed to parse operation_id from status callback data: {}", data);
+        bot.answer_callback_query(query.id).text("Ошибка: Неверный ID операции.").await?;
+        return Ok(());
+    };
+    info!("op_id:{}: User {} requested operation status (refresh: {})", operation_id, chat_id, refresh);
+
+    match format_operation_status(&running_operations, chat_id, operation_id, cfg.display_max_decimals).await {
+        Some(text) if refresh => {
+            let _ = bot
+                .edit_message_text(chat_id, msg.id(), text)
+                .reply_markup(make_op_status_keyboard(operation_id))
+                .await; // "message is not modified", если ничего не изменилось
+            bot.answer_callback_query(query.id).await?;
+        }
+        Some(text) => {
+            bot.send_message(chat_id, text).reply_markup(make_op_status_keyboard(operation_id)).await?;
+            bot.answer_callback_query(query.id).await?;
+        }
+        None => {
+            // Операция успела завершиться между запросом и обработкой
+            let final_status = match get_hedge_operation_by_id(db.as_ref(), operation_id).await {
+                Ok(Some(op)) => op.status.parse::<OperationStatus>().ok(),
+                Ok(None) => None,
+                Err(e) => {
+                    warn!("op_id:{}: Failed to load final status: {}", operation_id, e);
+                    None
+                }
+            };
+            let text = format!(
+                "Операция ID:{} уже неактивна (статус: {}).",
+                operation_id,
+                final_status.map_or("неизвестно", OperationStatus::as_str)
+            );
+            if refresh {
+                let _ = bot
+                    .edit_message_text(chat_id, msg.id(), text.clone())
+                    .reply_markup(navigation::make_main_menu_keyboard())
+                    .await;
+            }
+            bot.answer_callback_query(query.id).text(text).show_alert(true).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Аварийная отмена всех запущенных операций (/halt при halt_cancels_running):
+/// задачи прерываются, активные ордера снимаются, операции помечаются Cancelled,
+/// а остановленные на этапе фьючерса — SpotOnlyOrphan.
+/// Купленный спот не продается — его можно продать вручную или через /flatten
+pub async fn cancel_all_running_operations<E>(
+    exchange: Arc<E>,
+    running_operations: &RunningOperations,
+    db: &Db,
+    db_retry: DbWriteRetry,
+    reason: &str,
+) -> HaltCancelReport
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let operations: Vec<RunningOperationInfo> = running_operations.lock().await.drain().map(|(_, info)| info).collect();
+    let mut report = HaltCancelReport { cancelled: operations.len(), diverged: Vec::new() };
+    for info in operations {
+        let operation_id = info.operation_id;
+        warn!("op_id:{}: Aborting running {} operation: {}", operation_id, info.operation_type.as_str(), reason);
+        info.handle.abort();
+
+        let operation = match get_hedge_operation_by_id(db, operation_id).await {
+            Ok(operation) => operation,
+            Err(e) => {
+                warn!("op_id:{}: Failed to load operation on halt: {}", operation_id, e);
+                None
+            }
+        };
+        let mut filled_spot_qty = *info.total_filled_spot_qty.lock().await;
+        let mut futures_filled_qty = operation.as_ref().map_or(0.0, |op| op.futures_filled_qty);
+        let mut spot_order_id = operation.as_ref().and_then(|op| op.spot_order_id.clone());
+        if let Some(order) = info.active_order.lock().await.clone() {
+            if let Err(e) = cancel_order_generic(exchange.clone(), &order.symbol, &order.order_id, order.is_spot).await {
+                warn!("op_id:{}: Failed to cancel active order {} on halt: {}", operation_id, order.order_id, e);
+            }
+            if info.operation_type == OperationType::Hedge {
+                if order.is_spot {
+                    filled_spot_qty =
+                        settle_spot_fill_after_cancel(exchange.as_ref(), operation_id, &order.symbol, &order.order_id, order.filled_before, filled_spot_qty).await;
+                    spot_order_id = Some(order.order_id.clone());
+                } else {
+                    match exchange.get_futures_order_status(&order.symbol, &order.order_id).await {
+                        Ok(status) => futures_filled_qty = futures_filled_qty.max(order.filled_before + status.filled_qty),
+                        Err(e) => warn!("op_id:{}: Failed to get futures order {} status after halt cancel: {}", operation_id, order.order_id, e),
+                    }
+                }
+            }
+        }
+
+        // Остановка на этапе фьючерса: спот уже куплен, операция остается SpotOnlyOrphan для довыставления фьючерса
+        if info.operation_type == OperationType::Hedge && *info.stage.lock().await == HedgeStage::Futures {
+            let marked = write_with_retry(db_retry, operation_id, "mark SpotOnlyOrphan", || {
+                mark_hedge_spot_only_orphan(db, operation_id, futures_filled_qty, reason)
+            })
+            .await;
+            if let Err(e) = marked {
+                error!("op_id:{}: Failed to mark operation as SpotOnlyOrphan after halt: {}", operation_id, e);
+                report.diverged.push(e.to_string());
+            }
+            continue;
+        }
+        // Спот и статус пишутся одной транзакцией: купленный объем не теряется при сбое записи статуса
+        let spot = (info.operation_type == OperationType::Hedge)
+            .then(|| LegFill { order_id: spot_order_id.as_deref(), filled_qty: filled_spot_qty });
+        let futures = LegFill { order_id: None, filled_qty: futures_filled_qty };
+        if let Err(e) = finalize_with_retry(db, db_retry, operation_id, OperationStatus::Cancelled, spot, futures, Some(reason)).await {
+            error!("op_id:{}: Failed DB update after halt cancellation: {}", operation_id, e);
+            report.diverged.push(e.to_string());
+        }
+    }
+    report
+}
+
+/// Итог аварийной отмены: сколько операций отменено и какие записи в БД не удались после повторов
+#[derive(Debug, Default)]
+pub struct HaltCancelReport {
+    pub cancelled: usize,
+    pub diverged: Vec<String>,
+}
+
+/// Причина SpotOnlyOrphan при полной отмене без продажи спота
+const KEPT_SPOT_CANCEL_REASON: &str = "Hedge cancelled by user, spot kept (sell_spot_on_hedge_cancel = false)";
+
+/// Полная отмена хеджа без продажи спота: купленный объем записывается в операцию, и она остается
+/// SpotOnlyOrphan, как при отмене только фьючерса. spot_order_id — снятый спотовый ордер (None — оставить из БД)
+async fn keep_spot_as_orphan(
+    db: &Db,
+    db_retry: DbWriteRetry,
+    operation_id: i64,
+    spot_order_id: Option<&str>,
+    spot_filled_qty: f64,
+    reason: &str,
+) -> anyhow::Result<SpotOnlyOrphan> {
+    write_with_retry(db_retry, operation_id, "mark SpotOnlyOrphan", || async move {
+        let operation = get_hedge_operation_by_id(db, operation_id).await?;
+        let futures_filled_qty = operation.as_ref().map_or(0.0, |op| op.futures_filled_qty);
+        let spot_order_id = spot_order_id.map(str::to_string).or_else(|| operation.and_then(|op| op.spot_order_id));
+        update_hedge_spot_order(db, operation_id, spot_order_id.as_deref(), spot_filled_qty).await?;
+        mark_hedge_spot_only_orphan(db, operation_id, futures_filled_qty, reason).await?;
+        Ok(SpotOnlyOrphan { spot_filled_qty, futures_filled_qty, reason: reason.to_string() })
+    })
+    .await
+}
+
+/// Финальная запись полной отмены с повторами: Cancelled и купленный спот (spot = None — не менять).
+/// Фьючерсная нога отменой не меняется и переписывается из БД. Сбой после повторов — DbRecordDiverged
+async fn finalize_cancelled(
+    db: &Db,
+    db_retry: DbWriteRetry,
+    operation_id: i64,
+    spot: Option<LegFill<'_>>,
+    reason: Option<&str>,
+) -> anyhow::Result<bool> {
+    write_with_retry(db_retry, operation_id, "finalize Cancelled", || async move {
+        let operation = get_hedge_operation_by_id(db, operation_id).await?;
+        let futures_order_id = operation.as_ref().and_then(|op| op.futures_order_id.clone());
+        let futures_filled_qty = operation.as_ref().map_or(0.0, |op| op.futures_filled_qty);
+        let futures = LegFill { order_id: futures_order_id.as_deref(), filled_qty: futures_filled_qty };
+        finalize_operation(db, operation_id, OperationStatus::Cancelled, spot, futures, reason).await
+    })
+    .await
+}
+
+/// Сколько спота куплено с учетом отмененного ордера: он мог доисполниться между последним
+/// опросом цикла ордеров и отменой, поэтому сверяемся с его статусом на бирже
+async fn settle_spot_fill_after_cancel<E: Exchange>(
+    exchange: &E,
+    operation_id: i64,
+    symbol: &str,
+    order_id: &str,
+    filled_before: f64,
+    tracked_qty: f64,
+) -> f64 {
+    match exchange.get_spot_order_status(symbol, order_id).await {
+        Ok(status) => {
+            let settled_qty = filled_before + status.filled_qty;
+            if settled_qty > tracked_qty + ORDER_FILL_TOLERANCE {
+                warn!(
+                    "op_id:{}: Spot order {} filled further around cancellation: {:.8} -> {:.8}",
+                    operation_id, order_id, tracked_qty, settled_qty
+                );
+            }
+            tracked_qty.max(settled_qty)
+        }
+        Err(e) => {
+            warn!("op_id:{}: Failed to get spot order {} status after cancel: {}. Using tracked qty {:.8}", operation_id, order_id, e, tracked_qty);
+            tracked_qty
+        }
+    }
+}
+
 // Общая функция отмены ордера
 // --- ИСПРАВЛЕНО: Возвращаемый тип Result ---
 async fn cancel_order_generic<E: Exchange>(
@@ -450,3 +953,190 @@ impl OperationType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::mock::{FillSchedule, MockExchange};
+    use crate::storage::insert_hedge_operation;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn spot_phase_cancel_counts_fill_after_last_poll() {
+        let exchange = MockExchange { fill_schedule: Some(FillSchedule::Linear { polls: 2 }), ..MockExchange::default() };
+        // До ордера на этапе уже куплено 0.2; цикл успел увидеть половину ордера
+        let order = exchange.place_limit_order("BTC", OrderSide::Buy, 1.0, 100.0).await.expect("order");
+        let seen = exchange.get_spot_order_status("BTC", &order.id).await.expect("status").filled_qty;
+        let tracked_qty = 0.2 + seen;
+
+        exchange.cancel_spot_order("BTC", &order.id).await.expect("cancel");
+        let settled = settle_spot_fill_after_cancel(&exchange, 1, "BTC", &order.id, 0.2, tracked_qty).await;
+
+        assert!((tracked_qty - 0.7).abs() < 1e-9);
+        assert!((settled - 1.2).abs() < 1e-9, "fill after the last poll must be sold too, got {}", settled);
+    }
+
+    #[test]
+    fn full_cancel_and_keep_spot_cancel_treat_spot_differently() {
+        // Полная отмена: спот продается или остается по sell_spot_on_hedge_cancel (тогда операция SpotOnlyOrphan)
+        assert_eq!(spot_on_hedge_cancel(HedgeCancelMode::Full, HedgeStage::Futures, 0.5, true), Some(SpotOnCancel::Sell(0.5)));
+        assert_eq!(spot_on_hedge_cancel(HedgeCancelMode::Full, HedgeStage::Spot, 0.5, false), Some(SpotOnCancel::KeepAsOrphan(0.5)));
+        assert_eq!(spot_on_hedge_cancel(HedgeCancelMode::Full, HedgeStage::Spot, 0.0, true), Some(SpotOnCancel::Nothing));
+
+        // Отмена фьючерса: спот не продается даже при sell_spot_on_hedge_cancel, операция -> SpotOnlyOrphan
+        assert_eq!(spot_on_hedge_cancel(HedgeCancelMode::KeepSpot, HedgeStage::Futures, 0.5, true), Some(SpotOnCancel::KeepAsOrphan(0.5)));
+        // Пока идет покупка спота (или спота нет) — недоступна
+        assert_eq!(spot_on_hedge_cancel(HedgeCancelMode::KeepSpot, HedgeStage::Spot, 0.5, true), None);
+        assert_eq!(spot_on_hedge_cancel(HedgeCancelMode::KeepSpot, HedgeStage::Futures, 0.0, true), None);
+        assert!(make_keep_spot_cancel_button(1, HedgeStage::Spot, 0.5).is_none());
+        assert!(make_keep_spot_cancel_button(1, HedgeStage::Futures, 0.5).is_some());
+    }
+
+    #[tokio::test]
+    async fn halt_keeps_spot_of_futures_stage_operations_as_orphans() {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+        crate::storage::schema::apply_migrations(&db).await.expect("migrations");
+        let (spot_stage_id, ..) = insert_hedge_operation(&db, 42, "BTC", "USDT", "testnet", 100.0, 0.01, 1.0, 1.0, false).await.expect("insert");
+        let (futures_stage_id, ..) = insert_hedge_operation(&db, 42, "ETH", "USDT", "testnet", 100.0, 0.01, 2.0, 2.0, false).await.expect("insert");
+        update_hedge_spot_order(&db, futures_stage_id, Some("spot-1"), 2.0).await.expect("spot fill");
+
+        let running_operations: RunningOperations = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+        for (operation_id, stage, spot_qty) in [(spot_stage_id, HedgeStage::Spot, 0.4), (futures_stage_id, HedgeStage::Futures, 2.0)] {
+            let info = RunningOperationInfo {
+                handle: tokio::spawn(async {}).abort_handle(),
+                operation_id,
+                op_ref: None,
+                operation_type: OperationType::Hedge,
+                symbol: "BTC".to_string(),
+                bot_message_id: 1,
+                total_filled_spot_qty: Arc::new(tokio::sync::Mutex::new(spot_qty)),
+                active_order: Arc::new(tokio::sync::Mutex::new(None)),
+                stage: Arc::new(tokio::sync::Mutex::new(stage)),
+            };
+            running_operations.lock().await.insert((ChatId(42), operation_id), info);
+        }
+
+        let db_retry = DbWriteRetry::from_config(&crate::config::test_config(""));
+        let report = cancel_all_running_operations(Arc::new(MockExchange::default()), &running_operations, &db, db_retry, "halt").await;
+        assert_eq!((report.cancelled, report.diverged.len()), (2, 0));
+
+        // Спот куплен не полностью: операция отменена, купленный спот записан в спотовую колонку
+        let op = get_hedge_operation_by_id(&db, spot_stage_id).await.expect("load").expect("op");
+        assert!(op.has_status(OperationStatus::Cancelled));
+        assert_eq!((op.spot_filled_qty, op.futures_filled_qty), (0.4, 0.0));
+
+        // Остановка на этапе фьючерса: спот остается для довыставления фьючерса
+        let op = get_hedge_operation_by_id(&db, futures_stage_id).await.expect("load").expect("op");
+        assert!(op.has_status(OperationStatus::SpotOnlyOrphan));
+        assert_eq!((op.spot_filled_qty, op.futures_filled_qty), (2.0, 0.0));
+    }
+
+    #[tokio::test]
+    async fn full_cancel_without_spot_sale_leaves_operation_spot_only_orphan() {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+        crate::storage::schema::apply_migrations(&db).await.expect("migrations");
+        let (spot_stage_id, ..) = insert_hedge_operation(&db, 42, "BTC", "USDT", "testnet", 100.0, 0.01, 1.0, 1.0, false).await.expect("insert");
+        let (futures_stage_id, ..) = insert_hedge_operation(&db, 42, "ETH", "USDT", "testnet", 100.0, 0.01, 2.0, 2.0, false).await.expect("insert");
+        update_hedge_spot_order(&db, futures_stage_id, Some("spot-1"), 2.0).await.expect("spot fill");
+
+        // Отмена на этапе спота: снятый ордер и доисполненный объем записываются в операцию
+        let db_retry = DbWriteRetry::from_config(&crate::config::test_config(""));
+        let orphan = keep_spot_as_orphan(&db, db_retry, spot_stage_id, Some("spot-2"), 0.6, KEPT_SPOT_CANCEL_REASON).await.expect("orphan");
+        assert_eq!((orphan.spot_filled_qty, orphan.futures_filled_qty), (0.6, 0.0));
+        let op = get_hedge_operation_by_id(&db, spot_stage_id).await.expect("load").expect("op");
+        assert!(op.has_status(OperationStatus::SpotOnlyOrphan));
+        assert_eq!((op.spot_order_id.as_deref(), op.spot_filled_qty), (Some("spot-2"), 0.6));
+        assert_eq!(op.error_message.as_deref(), Some(KEPT_SPOT_CANCEL_REASON));
+
+        // Отмена на этапе фьючерса: спотовый ордер из БД сохраняется
+        keep_spot_as_orphan(&db, db_retry, futures_stage_id, None, 2.0, KEPT_SPOT_CANCEL_REASON).await.expect("orphan");
+        let op = get_hedge_operation_by_id(&db, futures_stage_id).await.expect("load").expect("op");
+        assert!(op.has_status(OperationStatus::SpotOnlyOrphan));
+        assert_eq!((op.spot_order_id.as_deref(), op.spot_filled_qty), (Some("spot-1"), 2.0));
+    }
+
+    #[tokio::test]
+    async fn spot_phase_cancel_keeps_tracked_qty_when_status_unavailable() {
+        let exchange = MockExchange::default();
+        let settled = settle_spot_fill_after_cancel(&exchange, 1, "BTC", "unknown-order", 0.2, 0.5).await;
+        assert_eq!(settled, 0.5);
+    }
+
+    #[tokio::test]
+    async fn operation_status_reflects_live_stage_and_display_precision() {
+        let running_operations: RunningOperations = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+        let stage = Arc::new(tokio::sync::Mutex::new(HedgeStage::Spot));
+        let info = RunningOperationInfo {
+            handle: tokio::spawn(async {}).abort_handle(),
+            operation_id: 7,
+            op_ref: None,
+            operation_type: OperationType::Hedge,
+            symbol: "BTC".to_string(),
+            bot_message_id: 1,
+            total_filled_spot_qty: Arc::new(tokio::sync::Mutex::new(0.123456789)),
+            active_order: Arc::new(tokio::sync::Mutex::new(None)),
+            stage: stage.clone(),
+        };
+        running_operations.lock().await.insert((ChatId(42), 7), info);
+
+        let text = format_operation_status(&running_operations, ChatId(42), 7, 4).await.expect("running op");
+        assert!(text.contains("~0.1235"), "{}", text);
+        assert!(text.contains("Этап: спот"), "{}", text);
+
+        *stage.lock().await = HedgeStage::Futures;
+        let text = format_operation_status(&running_operations, ChatId(42), 7, 4).await.expect("running op");
+        assert!(text.contains("Этап: фьючерс"), "{}", text);
+
+        assert!(format_operation_status(&running_operations, ChatId(1), 7, 4).await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keep_spot_cancel_removes_futures_order_when_db_read_fails() {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+        crate::storage::schema::apply_migrations(&db).await.expect("migrations");
+        let exchange = MockExchange::default();
+        // До снимаемого ордера исполнено 0.2, сам ордер успел исполниться на 0.3
+        let order = exchange.place_futures_market_order("BTCUSDT", OrderSide::Sell, 0.3).await.expect("order");
+        let info = RunningOperationInfo {
+            handle: tokio::spawn(async {}).abort_handle(),
+            operation_id: 7,
+            op_ref: None,
+            operation_type: OperationType::Hedge,
+            symbol: "BTC".to_string(),
+            bot_message_id: 1,
+            total_filled_spot_qty: Arc::new(tokio::sync::Mutex::new(1.0)),
+            active_order: Arc::new(tokio::sync::Mutex::new(Some(crate::hedger::ActiveOrder {
+                stage: HedgeStage::Futures,
+                is_spot: false,
+                symbol: "BTCUSDT".to_string(),
+                order_id: order.id.clone(),
+                filled_before: 0.2,
+            }))),
+            stage: Arc::new(tokio::sync::Mutex::new(HedgeStage::Futures)),
+        };
+        db.close().await; // БД недоступна после прерывания задачи
+
+        let db_retry = DbWriteRetry::from_config(&crate::config::test_config(""));
+        let (orphan, cancel_error) = abandon_futures_leg(&exchange, &db, db_retry, 7, &info).await;
+
+        // Ордер снят, итог посчитан по учету задачи, а сбой БД попадает в сообщение пользователю
+        assert!(exchange.is_cancelled(&order.id));
+        assert_eq!(orphan.spot_filled_qty, 1.0);
+        assert!((orphan.futures_filled_qty - 0.5).abs() < 1e-12, "{}", orphan.futures_filled_qty);
+        let cancel_error = cancel_error.expect("DB failure is reported");
+        assert!(cancel_error.contains("DB read failed"), "{}", cancel_error);
+        assert!(cancel_error.contains("не записана в БД"), "{}", cancel_error);
+    }
+}
@@ -0,0 +1,715 @@
+// src/notifier/admin.rs
+// Административные команды (доступны только чатам из allowed_chat_ids)
+
+use crate::config::Config;
+use crate::exchange::Exchange;
+use crate::exchange::bybit::is_dated_contract;
+use crate::exchange::types::{ExchangeError, TimeSyncReport};
+use crate::hedger::{DbWriteRetry, WatcherRegistry};
+use crate::notifier::active_ops::cancel_all_running_operations;
+use crate::notifier::market_info::parse_history_range;
+use crate::notifier::{callback_data, navigation, FailureCooldowns, RunningOperations, TradingHalt};
+use crate::notifier::utils::{operation_label, DB_RECORD_DIVERGED_TEXT};
+use crate::storage::{
+    Db, HedgeOperation, OperationStatus, export_operations_json, get_all_user_chat_ids, get_hedge_operation_by_id,
+    import_operations_json, resolve_operation_manually,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use teloxide::net::Download;
+use teloxide::prelude::*;
+use teloxide::types::{CallbackQuery, ChatId, InlineKeyboardButton, InlineKeyboardMarkup, InputFile, Message};
+use tracing::{error, info, warn};
+
+/// Пауза между сообщениями рассылки (лимит Telegram ~30 сообщений/сек)
+const BROADCAST_SEND_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Предел длины одного сообщения /config (лимит Telegram — 4096 символов)
+const CONFIG_MESSAGE_MAX_CHARS: usize = 4000;
+
+/// Коды Bybit "ордер не найден / уже исполнен или отменен" — для ручной отмены это не ошибка
+const ORDER_GONE_RET_CODES: [i64; 3] = [110001, 110025, 170213];
+
+/// Ордер для /cancelorder: символ в формате методов отмены (спот — базовая монета, фьючерс — полный символ)
+#[derive(Debug, PartialEq)]
+struct CancelOrderTarget {
+    symbol: String,
+    order_id: String,
+    is_spot: bool,
+}
+
+/// Разбор аргументов /cancelorder <SYMBOL> <ORDER_ID> <spot|futures>
+fn parse_cancel_order_args(args: &str, quote_currency: &str) -> Result<CancelOrderTarget, String> {
+    let parts: Vec<&str> = args.split_whitespace().collect();
+    let [symbol, order_id, category] = parts.as_slice() else {
+        return Err("нужно три аргумента: символ, ID ордера и категория.".to_string());
+    };
+    let symbol = symbol.to_uppercase();
+    let quote = quote_currency.to_uppercase();
+    let is_spot = match category.to_lowercase().as_str() {
+        "spot" => true,
+        "futures" | "linear" => false,
+        other => return Err(format!("неизвестная категория '{}' (spot или futures).", other)),
+    };
+    let base = symbol.strip_suffix(quote.as_str()).filter(|base| !base.is_empty()).unwrap_or(&symbol);
+    let symbol = if is_spot {
+        base.to_string()
+    } else if is_dated_contract(&symbol) {
+        symbol.clone()
+    } else {
+        format!("{}{}", base, quote)
+    };
+    Ok(CancelOrderTarget { symbol, order_id: order_id.to_string(), is_spot })
+}
+
+/// Разбор аргументов /resolve <ID> <Completed|Cancelled|Failed> (статус без учета регистра)
+fn parse_resolve_args(args: &str) -> Result<(i64, OperationStatus), String> {
+    let parts: Vec<&str> = args.split_whitespace().collect();
+    let [operation_id, status] = parts.as_slice() else {
+        return Err("нужно два аргумента: ID операции и итоговый статус.".to_string());
+    };
+    let operation_id = operation_id.parse::<i64>().map_err(|_| format!("неверный ID операции '{}'.", operation_id))?;
+    let status = OperationStatus::ALL
+        .into_iter()
+        .find(|candidate| candidate.as_str().eq_ignore_ascii_case(status))
+        .filter(|candidate| candidate.is_terminal())
+        .ok_or_else(|| format!("статус '{}' не итоговый (Completed, Cancelled или Failed).", status))?;
+    Ok((operation_id, status))
+}
+
+/// Операция ведется живой задачей бота (в любом чате) или фоновым наблюдателем (PendingFutures):
+/// закрывать ее вручную нельзя
+async fn is_tracked_operation(running_operations: &RunningOperations, watchers: &WatcherRegistry, operation_id: i64) -> bool {
+    watchers.is_watching_operation(operation_id) || running_operations.lock().await.keys().any(|(_, id)| *id == operation_id)
+}
+
+/// Текущее состояние операции для подтверждения /resolve
+fn format_resolve_prompt(operation: &HedgeOperation, status: OperationStatus) -> String {
+    let mut text = format!(
+        "🛠 Ручное закрытие операции {}\n\
+         {} {:.2} {} — сейчас {}\n\
+         Спот: {:.8}, фьючерс: {:.8}\n",
+        operation_label(operation.op_ref.as_deref(), operation.id),
+        operation.base_symbol, operation.initial_sum, operation.quote_currency, operation.status,
+        operation.spot_filled_qty, operation.futures_filled_qty,
+    );
+    if let Some(error) = &operation.error_message {
+        text.push_str(&format!("Ошибка: {}\n", error));
+    }
+    text.push_str(&format!(
+        "\nСтатус будет изменен на {} только в БД; ордера и позиции на бирже не трогаются. Подтвердите.",
+        status
+    ));
+    text
+}
+
+/// Ошибка отмены означает, что ордера уже нет (исполнен, отменен или не найден)
+fn is_order_gone_error(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<ExchangeError>() {
+        Some(ExchangeError::Api { code, .. }) => ORDER_GONE_RET_CODES.contains(code),
+        Some(ExchangeError::OrderNotFound(_)) => true, // Типизирована по retCode в bybit.rs
+        _ => false,
+    }
+}
+
+/// Обработчик команды /whoami: chat_id и username отправителя (доступна всем — для настройки allowed_chat_ids)
+pub async fn handle_whoami_command(bot: Bot, msg: Message, cfg: Arc<Config>) -> anyhow::Result<()> {
+    let chat_id = msg.chat.id;
+    let username = msg.from.as_ref().and_then(|u| u.username.clone());
+    info!(
+        "/whoami requested by chat {} (username: {:?}, in allowed_chat_ids: {})",
+        chat_id, username, cfg.is_admin_chat(chat_id.0)
+    );
+
+    let text = format!(
+        "🆔 chat_id: {}\nusername: {}",
+        chat_id.0,
+        username.map(|u| format!("@{}", u)).unwrap_or_else(|| "—".to_string())
+    );
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+/// Обработчик команды /broadcast <текст>: рассылка всем пользователям бота
+pub async fn handle_broadcast_command(
+    bot: Bot,
+    msg: Message,
+    text: String,
+    cfg: Arc<Config>,
+    db: Arc<Db>,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat.id;
+
+    if !cfg.is_admin_chat(chat_id.0) {
+        warn!("Chat {} tried to use /broadcast without admin rights", chat_id);
+        bot.send_message(chat_id, "⛔ Команда доступна только администраторам.").await?;
+        return Ok(());
+    }
+
+    let text = text.trim();
+    if text.is_empty() {
+        bot.send_message(chat_id, "⚠️ Укажите текст: /broadcast <сообщение>").await?;
+        return Ok(());
+    }
+
+    let recipients = match get_all_user_chat_ids(db.as_ref()).await {
+        Ok(ids) => ids,
+        Err(e) => {
+            warn!("Failed to load users for broadcast: {}", e);
+            bot.send_message(chat_id, "❌ Не удалось получить список пользователей.").await?;
+            return Ok(());
+        }
+    };
+
+    info!("Broadcast from chat {} to {} users", chat_id, recipients.len());
+    bot.send_message(chat_id, format!("📣 Рассылка запущена: {} получателей.", recipients.len())).await?;
+
+    // Рассылка с паузами между сообщениями идет в фоне, чтобы не блокировать обработку других апдейтов
+    let text = text.to_string();
+    tokio::spawn(async move {
+        let mut sent = 0usize;
+        let mut failed = 0usize;
+        for (i, recipient) in recipients.iter().enumerate() {
+            if i > 0 {
+                tokio::time::sleep(BROADCAST_SEND_INTERVAL).await;
+            }
+            match bot.send_message(ChatId(*recipient), text.as_str()).await {
+                Ok(_) => sent += 1,
+                Err(e) => {
+                    warn!("Broadcast to chat {} failed: {}", recipient, e);
+                    failed += 1;
+                }
+            }
+        }
+
+        info!("Broadcast from chat {} finished: sent {}, failed {}", chat_id, sent, failed);
+        if let Err(e) = bot
+            .send_message(chat_id, format!("📣 Рассылка завершена: доставлено {}, ошибок {}.", sent, failed))
+            .await
+        {
+            warn!("Failed to report broadcast result to chat {}: {}", chat_id, e);
+        }
+    });
+    Ok(())
+}
+
+/// Обработчик команды /clearcooldown [SYMBOL]: снять паузу после ошибки (без символа — по всем)
+pub async fn handle_clear_cooldown_command(
+    bot: Bot,
+    msg: Message,
+    symbol: String,
+    failure_cooldowns: FailureCooldowns,
+    cfg: Arc<Config>,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat.id;
+
+    if !cfg.is_admin_chat(chat_id.0) {
+        warn!("Chat {} tried to use /clearcooldown without admin rights", chat_id);
+        bot.send_message(chat_id, "⛔ Команда доступна только администраторам.").await?;
+        return Ok(());
+    }
+
+    let symbol = symbol.trim().to_uppercase();
+    let text = if symbol.is_empty() {
+        let cleared = failure_cooldowns.clear_all().await;
+        info!("Chat {} cleared all failure cooldowns ({})", chat_id, cleared);
+        format!("✅ Паузы после ошибок сняты (символов: {}).", cleared)
+    } else if failure_cooldowns.clear(&symbol).await {
+        info!("Chat {} cleared failure cooldown for {}", chat_id, symbol);
+        format!("✅ Пауза для {} снята.", symbol)
+    } else {
+        format!("ℹ️ Для {} нет активной паузы.", symbol)
+    };
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+/// Обработчик команды /exportops [с] [по]: история операций JSON-файлом (для переноса в другую БД).
+/// Без дат выгружаются все операции, с датами — начатые в диапазоне (как у /history)
+pub async fn handle_export_ops_command(bot: Bot, msg: Message, args: String, cfg: Arc<Config>, db: Arc<Db>) -> anyhow::Result<()> {
+    let chat_id = msg.chat.id;
+
+    if !cfg.is_admin_chat(chat_id.0) {
+        warn!("Chat {} tried to use /exportops without admin rights", chat_id);
+        bot.send_message(chat_id, "⛔ Команда доступна только администраторам.").await?;
+        return Ok(());
+    }
+
+    let (from_ts, to_ts) = if args.trim().is_empty() {
+        (None, None)
+    } else {
+        match parse_history_range(&args, chrono::Utc::now().timestamp()) {
+            Ok((from_ts, to_ts)) => (Some(from_ts), Some(to_ts)),
+            Err(e) => {
+                bot.send_message(chat_id, format!("⚠️ {}", e)).await?;
+                return Ok(());
+            }
+        }
+    };
+
+    let json = match export_operations_json(db.as_ref(), from_ts, to_ts).await {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to export operations for chat {}: {}", chat_id, e);
+            bot.send_message(chat_id, format!("❌ Не удалось выгрузить операции: {}", e)).await?;
+            return Ok(());
+        }
+    };
+    let file_name = format!("hedge_operations_{}.json", chrono::Utc::now().format("%Y%m%d_%H%M%S"));
+    info!("Chat {} exported operations to {}", chat_id, file_name);
+    bot.send_document(chat_id, InputFile::memory(json.into_bytes()).file_name(file_name))
+        .caption("Для загрузки в другую БД ответьте на этот файл командой /importops")
+        .await?;
+    Ok(())
+}
+
+/// Обработчик команды /importops: ответом на файл из /exportops добавляет операции с новыми ID
+pub async fn handle_import_ops_command(bot: Bot, msg: Message, cfg: Arc<Config>, db: Arc<Db>) -> anyhow::Result<()> {
+    let chat_id = msg.chat.id;
+
+    if !cfg.is_admin_chat(chat_id.0) {
+        warn!("Chat {} tried to use /importops without admin rights", chat_id);
+        bot.send_message(chat_id, "⛔ Команда доступна только администраторам.").await?;
+        return Ok(());
+    }
+
+    let Some(document) = msg.reply_to_message().and_then(|reply| reply.document()) else {
+        bot.send_message(chat_id, "⚠️ Отправьте /importops ответом на JSON-файл, полученный через /exportops.").await?;
+        return Ok(());
+    };
+    let file = bot.get_file(document.file.id.clone()).await?;
+    let mut content = Vec::new();
+    bot.download_file(&file.path, &mut content).await?;
+
+    let result = match String::from_utf8(content) {
+        Ok(json) => import_operations_json(db.as_ref(), &json).await,
+        Err(e) => Err(anyhow::anyhow!("file is not UTF-8: {}", e)),
+    };
+    let text = match result {
+        Ok(mapping) => {
+            info!("Chat {} imported {} operations", chat_id, mapping.len());
+            format!("✅ Импортировано операций: {} (ID назначены заново, связи роллирования сохранены).", mapping.len())
+        }
+        Err(e) => {
+            warn!("Operations import from chat {} failed: {:#}", chat_id, e);
+            format!("❌ Импорт не выполнен, БД не изменена: {:#}", e)
+        }
+    };
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+/// Обработчик команды /resolve <ID> <статус>: ручное закрытие зависшей операции (после подтверждения)
+pub async fn handle_resolve_command(
+    bot: Bot,
+    msg: Message,
+    args: String,
+    running_operations: RunningOperations,
+    watchers: WatcherRegistry,
+    cfg: Arc<Config>,
+    db: Arc<Db>,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat.id;
+
+    if !cfg.is_admin_chat(chat_id.0) {
+        warn!("Chat {} tried to use /resolve without admin rights", chat_id);
+        bot.send_message(chat_id, "⛔ Команда доступна только администраторам.").await?;
+        return Ok(());
+    }
+
+    let (operation_id, status) = match parse_resolve_args(&args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            bot.send_message(chat_id, format!("⚠️ /resolve <ID> <Completed|Cancelled|Failed>: {}", e)).await?;
+            return Ok(());
+        }
+    };
+    let operation = match get_hedge_operation_by_id(db.as_ref(), operation_id).await {
+        Ok(Some(operation)) => operation,
+        Ok(None) => {
+            bot.send_message(chat_id, format!("⚠️ Операция {} не найдена.", operation_id)).await?;
+            return Ok(());
+        }
+        Err(e) => {
+            error!("op_id:{}: Failed to load operation for /resolve: {}", operation_id, e);
+            bot.send_message(chat_id, "❌ Ошибка БД при загрузке операции.").await?;
+            return Ok(());
+        }
+    };
+    let current_status = operation.status.parse::<OperationStatus>().ok();
+    if current_status.is_some_and(OperationStatus::is_terminal) {
+        bot.send_message(chat_id, format!("ℹ️ Операция {} уже завершена ({}).", operation_id, operation.status)).await?;
+        return Ok(());
+    }
+    if is_tracked_operation(&running_operations, &watchers, operation_id).await {
+        bot.send_message(chat_id, format!("⛔ Операция {} выполняется задачей бота — отмените ее через /active или /watchers.", operation_id)).await?;
+        return Ok(());
+    }
+
+    let keyboard = InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback(
+            format!("✅ Закрыть как {}", status),
+            format!("{}{}_{}", callback_data::PREFIX_RESOLVE_CONFIRM, operation_id, status),
+        ),
+        InlineKeyboardButton::callback("❌ Отмена", callback_data::CANCEL_DIALOG),
+    ]]);
+    bot.send_message(chat_id, format_resolve_prompt(&operation, status)).reply_markup(keyboard).await?;
+    Ok(())
+}
+
+/// Обработчик подтверждения /resolve (префикс resolve_conf_<ID>_<статус>)
+pub async fn handle_resolve_confirm_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    running_operations: RunningOperations,
+    watchers: WatcherRegistry,
+    cfg: Arc<Config>,
+    db: Arc<Db>,
+) -> anyhow::Result<()> {
+    let (Some(data), Some(msg)) = (q.data.as_deref(), q.message.as_ref()) else {
+        warn!("CallbackQuery missing data or message in handle_resolve_confirm_callback");
+        bot.answer_callback_query(q.id).await?;
+        return Ok(());
+    };
+    let chat_id = msg.chat().id;
+    let message_id = msg.id();
+
+    if !cfg.is_admin_chat(chat_id.0) {
+        warn!("Chat {} tried to confirm /resolve without admin rights", chat_id);
+        bot.answer_callback_query(q.id).text("⛔ Только для администраторов.").show_alert(true).await?;
+        return Ok(());
+    }
+    let parsed = data
+        .strip_prefix(callback_data::PREFIX_RESOLVE_CONFIRM)
+        .map(|payload| payload.replacen('_', " ", 1))
+        .and_then(|args| parse_resolve_args(&args).ok());
+    let Some((operation_id, status)) = parsed else {
+        error!("Failed to parse resolve callback data: {}", data);
+        bot.answer_callback_query(q.id).text("Ошибка: неверные данные.").await?;
+        return Ok(());
+    };
+    bot.answer_callback_query(q.id).await?;
+
+    // Задача могла запуститься (например, возобновление ноги) между командой и подтверждением
+    let text = if is_tracked_operation(&running_operations, &watchers, operation_id).await {
+        format!("⛔ Операция {} выполняется задачей бота — отмените ее через /active или /watchers.", operation_id)
+    } else {
+        let note = format!("Закрыта вручную (/resolve) чатом {} как {}", chat_id, status);
+        match resolve_operation_manually(db.as_ref(), operation_id, status, &note).await {
+            Ok(true) => {
+                warn!("op_id:{}: Operation manually resolved as {} by chat {}", operation_id, status, chat_id);
+                format!("✅ Операция {} закрыта как {}.", operation_id, status)
+            }
+            Ok(false) => format!("ℹ️ Операция {} уже завершена или не найдена — статус не изменен.", operation_id),
+            Err(e) => {
+                error!("op_id:{}: Failed to resolve operation: {}", operation_id, e);
+                format!("❌ Ошибка БД: {}", e)
+            }
+        }
+    };
+    bot.edit_message_text(chat_id, message_id, text)
+        .reply_markup(navigation::make_main_menu_keyboard())
+        .await?;
+    Ok(())
+}
+
+/// Обработчик команды /halt: аварийная остановка запуска новых операций
+/// (при halt_cancels_running запущенные операции тоже отменяются)
+pub async fn handle_halt_command<E>(
+    bot: Bot,
+    msg: Message,
+    exchange: Arc<E>,
+    running_operations: RunningOperations,
+    trading_halt: TradingHalt,
+    cfg: Arc<Config>,
+    db: Arc<Db>,
+) -> anyhow::Result<()>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let chat_id = msg.chat.id;
+
+    if !cfg.is_admin_chat(chat_id.0) {
+        warn!("Chat {} tried to use /halt without admin rights", chat_id);
+        bot.send_message(chat_id, "⛔ Команда доступна только администраторам.").await?;
+        return Ok(());
+    }
+
+    let newly_engaged = trading_halt.engage();
+    warn!("TRADING HALT engaged by chat {} (was already engaged: {})", chat_id, !newly_engaged);
+    let mut text = "🛑 Торговля остановлена: новые хеджи и расхеджи не запускаются. Снять: /resume".to_string();
+    if cfg.halt_cancels_running {
+        let db_retry = DbWriteRetry::from_config(&cfg);
+        let report = cancel_all_running_operations(exchange, &running_operations, db.as_ref(), db_retry, "trading halted by admin").await;
+        text.push_str(&format!("\nОтменено запущенных операций: {}. Купленный спот не продавался — проверьте балансы.", report.cancelled));
+        for diverged in &report.diverged {
+            text.push_str(&format!("\n{}", diverged));
+        }
+        if !report.diverged.is_empty() {
+            text.push_str(&format!("\n{}", DB_RECORD_DIVERGED_TEXT));
+        }
+    } else {
+        text.push_str("\nЗапущенные операции продолжают работу.");
+    }
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+/// Обработчик команды /resume: снять аварийную остановку
+pub async fn handle_resume_command(bot: Bot, msg: Message, trading_halt: TradingHalt, cfg: Arc<Config>) -> anyhow::Result<()> {
+    let chat_id = msg.chat.id;
+
+    if !cfg.is_admin_chat(chat_id.0) {
+        warn!("Chat {} tried to use /resume without admin rights", chat_id);
+        bot.send_message(chat_id, "⛔ Команда доступна только администраторам.").await?;
+        return Ok(());
+    }
+
+    let was_engaged = trading_halt.release();
+    info!("Trading halt released by chat {} (was engaged: {})", chat_id, was_engaged);
+    let text = if trading_halt.sentinel_present() {
+        "⚠️ Остановка командой снята, но файл-сигнал kill_switch_file еще существует — торговля остановлена, пока он не удален."
+    } else if was_engaged {
+        "✅ Торговля возобновлена."
+    } else {
+        "ℹ️ Торговля не была остановлена."
+    };
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+/// Обработчик команды /cancelorder <SYMBOL> <ORDER_ID> <spot|futures>: ручная отмена ордера,
+/// который автоматический цикл не убрал. Уже отсутствующий ордер считается успешно отмененным
+pub async fn handle_cancel_order_command<E>(
+    bot: Bot,
+    msg: Message,
+    args: String,
+    exchange: Arc<E>,
+    cfg: Arc<Config>,
+) -> anyhow::Result<()>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let chat_id = msg.chat.id;
+
+    if !cfg.is_admin_chat(chat_id.0) {
+        warn!("Chat {} tried to use /cancelorder without admin rights", chat_id);
+        bot.send_message(chat_id, "⛔ Команда доступна только администраторам.").await?;
+        return Ok(());
+    }
+
+    let target = match parse_cancel_order_args(&args, &cfg.quote_currency) {
+        Ok(target) => target,
+        Err(reason) => {
+            bot.send_message(
+                chat_id,
+                format!(
+                    "⚠️ Неверные аргументы: {}\nИспользование: /cancelorder <SYMBOL> <ORDER_ID> <spot|futures>\nНапример: /cancelorder BTC 1234567890 spot",
+                    reason
+                ),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    let category = if target.is_spot { "spot" } else { "futures" };
+
+    warn!("Chat {} requested manual cancel of {} order {} on {}", chat_id, category, target.order_id, target.symbol);
+    let result = if target.is_spot {
+        exchange.cancel_spot_order(&target.symbol, &target.order_id).await
+    } else {
+        exchange.cancel_futures_order(&target.symbol, &target.order_id).await
+    };
+    let text = match result {
+        Ok(()) => {
+            info!("Manual cancel of {} order {} on {} succeeded", category, target.order_id, target.symbol);
+            format!("✅ Ордер {} ({}, {}) отменен (или уже был исполнен/отменен).", target.order_id, target.symbol, category)
+        }
+        Err(e) if is_order_gone_error(&e) => {
+            info!("Manual cancel: {} order {} on {} is already gone: {}", category, target.order_id, target.symbol, e);
+            format!("ℹ️ Ордер {} ({}, {}) не найден — уже исполнен или отменен.", target.order_id, target.symbol, category)
+        }
+        Err(e) => {
+            error!("Manual cancel of {} order {} on {} failed: {}", category, target.order_id, target.symbol, e);
+            format!("❌ Не удалось отменить ордер {}: {}", target.order_id, e)
+        }
+    };
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+/// Текст ответа /resync: смещение до и после и запас относительно recv_window
+fn format_resync_report(report: &TimeSyncReport) -> String {
+    let previous = report
+        .previous_offset_ms
+        .map_or_else(|| "не синхронизировано".to_string(), |offset| format!("{} мс", offset));
+    let verdict = if report.within_recv_window() {
+        "✅ Расхождение часов в пределах recv_window."
+    } else {
+        "⚠️ Расхождение часов больше recv_window: запросы проходят только благодаря поправке, проверьте NTP на сервере."
+    };
+    format!(
+        "🕒 Время синхронизировано с биржей.\nСмещение до: {}\nСмещение после: {} мс\nrecv_window: {} мс\n{}",
+        previous, report.offset_ms, report.recv_window_ms, verdict
+    )
+}
+
+/// Обработчик команды /resync: принудительная синхронизация времени с биржей
+pub async fn handle_resync_command<E>(bot: Bot, msg: Message, exchange: Arc<E>, cfg: Arc<Config>) -> anyhow::Result<()>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let chat_id = msg.chat.id;
+
+    if !cfg.is_admin_chat(chat_id.0) {
+        warn!("Chat {} tried to use /resync without admin rights", chat_id);
+        bot.send_message(chat_id, "⛔ Команда доступна только администраторам.").await?;
+        return Ok(());
+    }
+
+    let text = match exchange.resync_time().await {
+        Ok(report) => {
+            info!("Chat {} resynced time: {:?}", chat_id, report);
+            format_resync_report(&report)
+        }
+        Err(e) => {
+            error!("Manual time resync requested by chat {} failed: {}", chat_id, e);
+            format!("❌ Не удалось синхронизировать время: {}", e)
+        }
+    };
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+/// Делит текст на сообщения не длиннее max_chars по границам строк
+fn split_into_messages(text: &str, max_chars: usize) -> Vec<String> {
+    let mut messages = Vec::new();
+    let mut current = String::new();
+    for line in text.lines() {
+        if !current.is_empty() && current.chars().count() + line.chars().count() + 1 > max_chars {
+            messages.push(std::mem::take(&mut current));
+        }
+        current.push_str(line);
+        current.push('\n');
+    }
+    if !current.trim().is_empty() {
+        messages.push(current);
+    }
+    messages
+}
+
+/// Обработчик команды /config: действующие настройки бота (с учетом окружения) без ключей и токенов
+pub async fn handle_config_command(bot: Bot, msg: Message, cfg: Arc<Config>) -> anyhow::Result<()> {
+    let chat_id = msg.chat.id;
+
+    if !cfg.is_admin_chat(chat_id.0) {
+        warn!("Chat {} tried to use /config without admin rights", chat_id);
+        bot.send_message(chat_id, "⛔ Команда доступна только администраторам.").await?;
+        return Ok(());
+    }
+
+    let text = match cfg.redacted_toml() {
+        Ok(text) => text,
+        Err(e) => {
+            error!("Failed to render config for chat {}: {}", chat_id, e);
+            bot.send_message(chat_id, format!("❌ Не удалось показать конфиг: {}", e)).await?;
+            return Ok(());
+        }
+    };
+    info!("Chat {} requested effective config", chat_id);
+    bot.send_message(chat_id, format!("⚙️ Действующий конфиг ({}):", cfg.environment())).await?;
+    for part in split_into_messages(&text, CONFIG_MESSAGE_MAX_CHARS) {
+        bot.send_message(chat_id, part).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resync_report_shows_offsets_and_recv_window_verdict() {
+        let drifted = TimeSyncReport { previous_offset_ms: None, offset_ms: -6200, recv_window_ms: 5000 };
+        let text = format_resync_report(&drifted);
+        assert!(text.contains("Смещение до: не синхронизировано"));
+        assert!(text.contains("Смещение после: -6200 мс"));
+        assert!(text.contains("больше recv_window"));
+
+        let ok = TimeSyncReport { previous_offset_ms: Some(120), offset_ms: 80, recv_window_ms: 5000 };
+        assert!(format_resync_report(&ok).contains("Смещение до: 120 мс"));
+        assert!(ok.within_recv_window());
+    }
+
+    #[test]
+    fn config_text_is_split_on_line_boundaries() {
+        let text = "a = 1\nbb = 22\nccc = 333\n";
+        assert_eq!(split_into_messages(text, 100), vec![text.to_string()]);
+
+        let parts = split_into_messages(text, 15);
+        assert_eq!(parts, vec!["a = 1\nbb = 22\n".to_string(), "ccc = 333\n".to_string()]);
+        assert!(parts.iter().all(|p| p.chars().count() <= 15));
+    }
+
+    #[tokio::test]
+    async fn operation_with_live_watcher_is_tracked() {
+        use crate::hedger::watchers::{WatcherKind, WatcherSummary};
+        use std::collections::HashMap;
+
+        let running_operations: RunningOperations = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+        let watchers = WatcherRegistry::default();
+        assert!(!is_tracked_operation(&running_operations, &watchers, 10).await);
+
+        // PendingFutures: статус операции ведет монитор оставленного ордера, а не задача в /active
+        let monitor = tokio::spawn(tokio::time::sleep(Duration::from_secs(60)));
+        let summary = WatcherSummary {
+            id: watchers.next_id(),
+            kind: WatcherKind::PendingFutures,
+            chat_id: 1,
+            operation_id: 10,
+            symbol: "BTCUSDT".to_string(),
+            details: "order 1".to_string(),
+        };
+        watchers.register(monitor.abort_handle(), summary);
+        assert!(is_tracked_operation(&running_operations, &watchers, 10).await);
+        assert!(!is_tracked_operation(&running_operations, &watchers, 11).await);
+
+        monitor.abort();
+        let _ = monitor.await;
+        assert!(!is_tracked_operation(&running_operations, &watchers, 10).await);
+    }
+
+    #[test]
+    fn resolve_args_accept_only_terminal_statuses() {
+        assert_eq!(parse_resolve_args("42 failed"), Ok((42, OperationStatus::Failed)));
+        assert_eq!(parse_resolve_args(" 7  Completed "), Ok((7, OperationStatus::Completed)));
+        assert!(parse_resolve_args("42 Running").is_err());
+        assert!(parse_resolve_args("42 PendingFutures").is_err());
+        assert!(parse_resolve_args("abc Failed").is_err());
+        assert!(parse_resolve_args("42").is_err());
+    }
+
+    #[test]
+    fn cancel_order_args_are_validated() {
+        assert_eq!(
+            parse_cancel_order_args("btcusdt 123 spot", "USDT"),
+            Ok(CancelOrderTarget { symbol: "BTC".to_string(), order_id: "123".to_string(), is_spot: true })
+        );
+        assert_eq!(
+            parse_cancel_order_args("BTC abc-1 futures", "USDT"),
+            Ok(CancelOrderTarget { symbol: "BTCUSDT".to_string(), order_id: "abc-1".to_string(), is_spot: false })
+        );
+        assert_eq!(parse_cancel_order_args("BTCUSDT-26DEC25 7 futures", "USDT").unwrap().symbol, "BTCUSDT-26DEC25");
+        assert!(parse_cancel_order_args("BTC 123 margin", "USDT").is_err());
+        assert!(parse_cancel_order_args("BTC 123", "USDT").is_err());
+    }
+
+    #[test]
+    fn missing_order_counts_as_cancelled() {
+        let gone: anyhow::Error = ExchangeError::Api { code: 110001, message: "order not exists".into(), raw: String::new() }.into();
+        assert!(is_order_gone_error(&gone));
+        assert!(!is_order_gone_error(&anyhow::anyhow!("Order not found")));
+        let other: anyhow::Error = ExchangeError::Api { code: 10001, message: "params error".into(), raw: String::new() }.into();
+        assert!(!is_order_gone_error(&other));
+    }
+}
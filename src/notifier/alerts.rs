@@ -0,0 +1,51 @@
+// src/notifier/alerts.rs
+
+//! Критические уведомления (ошибки операций, незахеджированный спот) дублируются в отдельный
+//! чат/канал alert_chat_id, чтобы мониторинг не зависел от того, кто запустил операцию.
+
+use crate::config::Config;
+use teloxide::prelude::*;
+use teloxide::RequestError;
+use tracing::{info, warn};
+
+/// Куда дублировать алерт: None — alert_chat_id не задан или совпадает с чатом пользователя
+pub fn alert_target(alert_chat_id: Option<i64>, source_chat: ChatId) -> Option<ChatId> {
+    alert_chat_id
+        .map(ChatId)
+        .filter(|target| *target != source_chat)
+}
+
+/// Текст алерта: откуда пришло уведомление и его исходный текст
+pub fn format_alert(source_chat: ChatId, text: &str) -> String {
+    format!("🚨 Алерт (чат {}):\n{}", source_chat, text)
+}
+
+/// Дублирует уведомление уровня ошибки в alert_chat_id (если задан).
+/// Ошибки отправки только логируются: пользователь свое сообщение уже получил
+pub async fn send_alert(bot: &Bot, cfg: &Config, source_chat: ChatId, text: &str) {
+    let Some(target) = alert_target(cfg.alert_chat_id, source_chat) else {
+        return;
+    };
+    match bot.send_message(target, format_alert(source_chat, text)).await {
+        Ok(_) => info!("Alert from chat {} cross-posted to {}", source_chat, target),
+        // Бот не добавлен в канал, исключен или не может публиковать — настраивается вручную
+        Err(RequestError::Api(e)) => warn!(
+            "Cannot post alert to alert_chat_id {}: {}. Check that the bot is a member with permission to post messages.",
+            target, e
+        ),
+        Err(e) => warn!("Failed to send alert to alert_chat_id {}: {}", target, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alert_target_skips_unset_and_source_chat() {
+        assert_eq!(alert_target(None, ChatId(1)), None);
+        assert_eq!(alert_target(Some(-100500), ChatId(1)), Some(ChatId(-100500)));
+        // Ошибка в самом канале алертов не дублируется второй раз
+        assert_eq!(alert_target(Some(-100500), ChatId(-100500)), None);
+    }
+}
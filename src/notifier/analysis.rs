@@ -0,0 +1,188 @@
+// src/notifier/analysis.rs
+
+//! /analyze <SYMBOL> [days]: прогноз доходности удержания хеджа (спот + шорт фьючерса) на N дней —
+//! комиссии входа/выхода против ожидаемого фандинга по истории ставок.
+
+use crate::config::Config;
+use crate::exchange::Exchange;
+use crate::exchange::bybit::{FUNDING_INTERVALS_PER_DAY, LINEAR_CATEGORY, MAX_FUNDING_HISTORY_ENTRIES, SPOT_CATEGORY};
+use crate::utils::trading_symbol;
+use std::sync::Arc;
+use teloxide::prelude::*;
+use tracing::{info, warn};
+
+/// Максимальный горизонт прогноза, дней
+const MAX_ANALYZE_DAYS: u16 = 365;
+/// Глубина истории ставок, которую отдает биржа, дней
+const MAX_HISTORY_DAYS: u16 = (MAX_FUNDING_HISTORY_ENTRIES / FUNDING_INTERVALS_PER_DAY) as u16;
+/// Комиссия taker, если биржа ее не вернула (как в расчете параметров хеджа)
+const FALLBACK_TAKER_FEE: f64 = 0.001;
+/// Условный объем для примера в валюте котировки
+const EXAMPLE_NOTIONAL: f64 = 1000.0;
+
+/// Прогноз доходности хеджа в долях от объема позиции
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CarryProjection {
+    pub fees: f64,                    // Комиссии: покупка и продажа спота + открытие и закрытие шорта
+    pub funding: Option<f64>,         // Фандинг шорта за период (плюс — шорт получает); None — нет истории
+    pub net: Option<f64>,             // Фандинг минус комиссии
+    pub break_even_days: Option<f64>, // Через сколько дней фандинг окупит комиссии (только при положительной ставке)
+}
+
+/// Прогноз: комиссии taker на обе ноги туда и обратно и фандинг по средней ставке за days дней
+pub fn project_carry(spot_fee: f64, futures_fee: f64, avg_funding_rate: Option<f64>, days: u16) -> CarryProjection {
+    let fees = 2.0 * (spot_fee + futures_fee);
+    let daily_funding = avg_funding_rate.map(|rate| rate * f64::from(FUNDING_INTERVALS_PER_DAY));
+    let funding = daily_funding.map(|daily| daily * f64::from(days));
+    CarryProjection {
+        fees,
+        funding,
+        net: funding.map(|funding| funding - fees),
+        break_even_days: daily_funding.filter(|daily| *daily > 0.0).map(|daily| fees / daily),
+    }
+}
+
+/// Разбор аргументов: монета и горизонт в днях (по умолчанию default_days)
+fn parse_analyze_args(args: &str, default_days: u16) -> Result<(String, u16), String> {
+    let parts: Vec<&str> = args.split_whitespace().collect();
+    let Some(symbol) = parts.first() else {
+        return Err("Использование: /analyze <SYMBOL> [days]\nПример: /analyze BTC 30".to_string());
+    };
+    let days = match parts.get(1) {
+        None => default_days.clamp(1, MAX_ANALYZE_DAYS),
+        Some(arg) => match arg.parse::<u16>() {
+            Ok(days) if (1..=MAX_ANALYZE_DAYS).contains(&days) => days,
+            _ => return Err(format!("⚠️ Количество дней должно быть целым числом от 1 до {}, получено: {}.", MAX_ANALYZE_DAYS, arg)),
+        },
+    };
+    Ok((symbol.to_uppercase(), days))
+}
+
+fn format_pct(value: f64) -> String {
+    format!("{:+.3}%", value * 100.0)
+}
+
+/// Текст прогноза с явными допущениями
+fn format_projection(symbol: &str, quote: &str, days: u16, history_days: u16, projection: &CarryProjection, assumptions: &[String]) -> String {
+    let mut text = format!("📊 Прогноз хеджа {} на {} дн. (спот + шорт фьючерса)\n\n", symbol, days);
+    text.push_str(&format!("💸 Комиссии (вход и выход): {}\n", format_pct(-projection.fees)));
+    match (projection.funding, projection.net) {
+        (Some(funding), Some(net)) => {
+            text.push_str(&format!("📈 Фандинг шорта (по средней за {} дн.): {}\n", history_days, format_pct(funding)));
+            text.push_str(&format!(
+                "🧮 Итого: {} (≈ {:+.2} {} на {:.0} {})\n\n",
+                format_pct(net), net * EXAMPLE_NOTIONAL, quote, EXAMPLE_NOTIONAL, quote
+            ));
+            if funding > 0.0 {
+                text.push_str("✅ Фандинг положительный: шорт получает выплаты.");
+                if let Some(break_even) = projection.break_even_days {
+                    text.push_str(&format!(" Комиссии окупаются примерно за {:.1} дн.", break_even));
+                }
+            } else {
+                text.push_str("⚠️ Фандинг не положительный: удержание шорта стоит денег, хедж только защищает от падения цены.");
+            }
+        }
+        _ => text.push_str("ℹ️ Нет истории фандинга — учтены только комиссии, итог не рассчитан."),
+    }
+    text.push_str("\n\nДопущения:\n• исполнение по taker-комиссии на обеих ногах\n• будущая ставка равна средней за период истории\n• цена и базис не учитываются");
+    for assumption in assumptions {
+        text.push_str(&format!("\n• {}", assumption));
+    }
+    text
+}
+
+/// Обработчик команды /analyze <SYMBOL> [days]
+pub async fn handle_analyze_command<E>(
+    bot: Bot,
+    msg: Message,
+    args: String,
+    exchange: Arc<E>,
+    cfg: Arc<Config>,
+) -> anyhow::Result<()>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let chat_id = msg.chat.id;
+    let (symbol, days) = match parse_analyze_args(&args, cfg.default_funding_days) {
+        Ok(parsed) => parsed,
+        Err(text) => {
+            bot.send_message(chat_id, text).await?;
+            return Ok(());
+        }
+    };
+    info!("Processing /analyze {} ({} days) for chat_id: {}", symbol, days, chat_id);
+    let indicator_msg = bot.send_message(chat_id, format!("⏳ Анализ {} на {} дн...", symbol, days)).await?;
+
+    let futures_symbol = trading_symbol(&symbol, &cfg.quote_currency);
+    let history_days = days.min(MAX_HISTORY_DAYS);
+    let (spot_fee_res, futures_fee_res, funding_res) = tokio::join!(
+        exchange.get_fee_rate(&symbol, SPOT_CATEGORY),
+        exchange.get_fee_rate(&symbol, LINEAR_CATEGORY),
+        exchange.get_funding_rate(&futures_symbol, history_days),
+    );
+
+    // Недостающие данные заменяются явными допущениями, а не ошибкой
+    let mut assumptions = Vec::new();
+    let mut fee_or_fallback = |res: anyhow::Result<f64>, leg: &str| match res {
+        Ok(fee) => fee,
+        Err(e) => {
+            warn!("Failed to get {} fee rate for {}: {}", leg, symbol, e);
+            assumptions.push(format!("комиссия {} недоступна, взята {:.2}%", leg, FALLBACK_TAKER_FEE * 100.0));
+            FALLBACK_TAKER_FEE
+        }
+    };
+    let spot_fee = fee_or_fallback(spot_fee_res.map(|fee| fee.taker), "спота");
+    let futures_fee = fee_or_fallback(futures_fee_res.map(|fee| fee.taker), "фьючерса");
+    let avg_funding_rate = match funding_res {
+        Ok(stats) if stats.intervals > 0 => Some(stats.avg_rate),
+        Ok(_) => None,
+        Err(e) => {
+            warn!("Failed to get funding history for {}: {}", futures_symbol, e);
+            assumptions.push(format!("история фандинга {} недоступна", futures_symbol));
+            None
+        }
+    };
+
+    let projection = project_carry(spot_fee, futures_fee, avg_funding_rate, days);
+    let text = format_projection(&symbol, &cfg.quote_currency, days, history_days, &projection, &assumptions);
+    bot.edit_message_text(chat_id, indicator_msg.id, text).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-12, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn positive_funding_projects_net_carry_and_break_even() {
+        // Ставка 0.01% за 8 ч → 0.03% в день; комиссии 2 * (0.1% + 0.055%) = 0.31%
+        let projection = project_carry(0.001, 0.00055, Some(0.0001), 30);
+        assert_close(projection.fees, 0.0031);
+        assert_close(projection.funding.expect("funding"), 0.009);
+        assert_close(projection.net.expect("net"), 0.0059);
+        assert_close(projection.break_even_days.expect("break even"), 0.0031 / 0.0003);
+    }
+
+    #[test]
+    fn negative_or_missing_funding_has_no_break_even() {
+        let negative = project_carry(0.001, 0.001, Some(-0.0001), 10);
+        assert_close(negative.net.expect("net"), -0.003 - 0.004);
+        assert_eq!(negative.break_even_days, None);
+
+        let missing = project_carry(0.001, 0.001, None, 10);
+        assert_eq!((missing.funding, missing.net, missing.break_even_days), (None, None, None));
+    }
+
+    #[test]
+    fn analyze_args_are_validated() {
+        assert_eq!(parse_analyze_args("btc 7", 30), Ok(("BTC".to_string(), 7)));
+        assert_eq!(parse_analyze_args("ETH", 30), Ok(("ETH".to_string(), 30)));
+        assert!(parse_analyze_args("", 30).is_err());
+        assert!(parse_analyze_args("BTC 0", 30).is_err());
+        assert!(parse_analyze_args("BTC 1000", 30).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+// src/notifier/daily_digest.rs
+
+//! Ежедневная сводка: в заданное местное время (daily_digest_time, utc_offset_hours) каждому чату,
+//! где за прошедшие сутки завершились или упали операции, уходят итоги из БД.
+
+use crate::config::Config;
+use crate::storage::{get_daily_activity, DailyActivity, Db};
+use chrono::{DateTime, Duration as ChronoDuration, FixedOffset, NaiveTime, Utc};
+use std::time::Duration;
+use teloxide::prelude::*;
+use tracing::{error, info, warn};
+
+/// Ближайший момент отправки сводки строго после now (в местном времени по смещению)
+pub fn next_digest_at(now: DateTime<Utc>, at: NaiveTime, offset: FixedOffset) -> DateTime<Utc> {
+    let local_now = now.with_timezone(&offset);
+    let today = local_now.date_naive().and_time(at);
+    let candidate = if today > local_now.naive_local() { today } else { today + ChronoDuration::days(1) };
+    candidate
+        .and_local_timezone(offset)
+        .single()
+        .map(|local| local.with_timezone(&Utc))
+        .unwrap_or(now + ChronoDuration::days(1))
+}
+
+/// Текст сводки для одного чата; period_end — момент отправки (дата берется в местном времени)
+pub fn format_daily_digest(activity: &DailyActivity, period_end: DateTime<Utc>, offset: FixedOffset, quote_currency: &str) -> String {
+    let mut text = format!(
+        "📊 Сводка за сутки до {}\n\n✅ Завершено хеджей: {}\n❌ С ошибкой: {}\n💰 Объем: {:.2} {}",
+        period_end.with_timezone(&offset).format("%d.%m %H:%M"),
+        activity.completed_count,
+        activity.failed_count,
+        activity.hedged_volume,
+        quote_currency,
+    );
+    if activity.accrued_funding != 0.0 {
+        text.push_str(&format!("\n💸 Расход на фандинг по открытым хеджам: {:+.2} {}", activity.accrued_funding, quote_currency));
+    }
+    text
+}
+
+/// Одна рассылка: активность за сутки до period_end
+async fn send_daily_digest(bot: &Bot, cfg: &Config, db: &Db, period_end: DateTime<Utc>, offset: FixedOffset) {
+    let to_ts = period_end.timestamp();
+    let activity = match get_daily_activity(db, to_ts - 86_400, to_ts, cfg.environment()).await {
+        Ok(activity) => activity,
+        Err(e) => {
+            error!("Failed to load daily activity for digest: {}", e);
+            return;
+        }
+    };
+    info!("Sending daily digest to {} chat(s).", activity.len());
+    for chat_activity in &activity {
+        let text = format_daily_digest(chat_activity, period_end, offset, &cfg.quote_currency);
+        if let Err(e) = bot.send_message(ChatId(chat_activity.chat_id), text).await {
+            warn!("Failed to send daily digest to chat {}: {}", chat_activity.chat_id, e);
+        }
+    }
+}
+
+/// Запускает ежедневную рассылку сводки (daily_digest_time не задано — не запускается)
+pub fn spawn_daily_digest_task(bot: Bot, cfg: Config, db: Db) {
+    // Формат проверен при загрузке конфига
+    let Ok(Some(at)) = cfg.daily_digest_at() else {
+        info!("Daily digest disabled (daily_digest_time not set).");
+        return;
+    };
+    let Some(offset) = FixedOffset::east_opt(cfg.utc_offset_hours * 3600) else {
+        warn!("Daily digest disabled: invalid utc_offset_hours {}.", cfg.utc_offset_hours);
+        return;
+    };
+    info!("Starting daily digest task (at {} UTC{:+}).", at.format("%H:%M"), cfg.utc_offset_hours);
+    tokio::spawn(async move {
+        loop {
+            let now = Utc::now();
+            let send_at = next_digest_at(now, at, offset);
+            let wait = (send_at - now).to_std().unwrap_or(Duration::ZERO);
+            tokio::time::sleep(wait).await;
+            send_daily_digest(&bot, &cfg, &db, send_at, offset).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn msk() -> FixedOffset {
+        FixedOffset::east_opt(3 * 3600).expect("offset")
+    }
+
+    #[test]
+    fn next_digest_respects_local_offset() {
+        let at = NaiveTime::from_hms_opt(21, 0, 0).expect("time");
+        // 17:00 UTC = 20:00 МСК: сводка сегодня в 21:00 МСК (18:00 UTC)
+        let now = Utc.with_ymd_and_hms(2025, 3, 10, 17, 0, 0).unwrap();
+        assert_eq!(next_digest_at(now, at, msk()), Utc.with_ymd_and_hms(2025, 3, 10, 18, 0, 0).unwrap());
+        // Ровно в момент отправки — следующая через сутки
+        let now = Utc.with_ymd_and_hms(2025, 3, 10, 18, 0, 0).unwrap();
+        assert_eq!(next_digest_at(now, at, msk()), Utc.with_ymd_and_hms(2025, 3, 11, 18, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn digest_summarizes_seeded_day() {
+        let activity = DailyActivity { chat_id: 1, completed_count: 2, failed_count: 1, hedged_volume: 400.0, accrued_funding: 3.0 };
+        let period_end = Utc.with_ymd_and_hms(2025, 3, 10, 18, 0, 0).unwrap();
+
+        let text = format_daily_digest(&activity, period_end, msk(), "USDT");
+
+        assert!(text.contains("до 10.03 21:00"));
+        assert!(text.contains("Завершено хеджей: 2"));
+        assert!(text.contains("С ошибкой: 1"));
+        assert!(text.contains("400.00 USDT"));
+        assert!(text.contains("фандинг по открытым хеджам: +3.00 USDT"));
+
+        let quiet = DailyActivity { accrued_funding: 0.0, ..activity };
+        assert!(!format_daily_digest(&quiet, period_end, msk(), "USDT").contains("фандинг"));
+    }
+}
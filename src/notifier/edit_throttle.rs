@@ -0,0 +1,195 @@
+// src/notifier/edit_throttle.rs
+
+//! Ограничение частоты правок сообщений прогресса (Telegram ограничивает правки в одном чате).
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use teloxide::types::ChatId;
+use tokio::sync::Mutex as TokioMutex;
+use tokio::time::Instant;
+
+/// Время последней правки в чате: общее для всех сообщений прогресса этого чата
+type ChatClock = Arc<TokioMutex<Option<Instant>>>;
+
+/// Часы правок по чатам: операции одного чата делят общий интервал между правками
+#[derive(Debug, Clone, Default)]
+pub struct ChatEditClocks {
+    clocks: Arc<Mutex<HashMap<ChatId, ChatClock>>>,
+}
+
+impl ChatEditClocks {
+    /// Троттлер правок одного сообщения в чате chat_id
+    pub fn throttle<T: Send + 'static>(&self, chat_id: ChatId, min_interval: Duration) -> EditThrottle<T> {
+        let chat_clock = self.clocks.lock().unwrap().entry(chat_id).or_default().clone();
+        EditThrottle {
+            state: Arc::new(TokioMutex::new(ThrottleState { pending: None, flush_scheduled: false, closed: false })),
+            chat_clock,
+            min_interval,
+        }
+    }
+}
+
+struct ThrottleState<T> {
+    pending: Option<T>, // Последнее подавленное состояние (ждет отправки)
+    flush_scheduled: bool,
+    closed: bool,
+}
+
+/// Троттлинг правок одного сообщения: правки в чате не чаще min_interval,
+/// подавленные правки схлопываются в последнюю и отправляются, когда чат освободится
+pub struct EditThrottle<T> {
+    state: Arc<TokioMutex<ThrottleState<T>>>,
+    chat_clock: ChatClock,
+    min_interval: Duration,
+}
+
+impl<T> Clone for EditThrottle<T> {
+    fn clone(&self) -> Self {
+        Self { state: self.state.clone(), chat_clock: self.chat_clock.clone(), min_interval: self.min_interval }
+    }
+}
+
+/// Занять слот правки в чате: ноль — можно править сейчас (время правки записано), иначе сколько ждать
+fn reserve_edit_slot(last_edit: &mut Option<Instant>, min_interval: Duration) -> Duration {
+    let now = Instant::now();
+    let wait = last_edit.map_or(Duration::ZERO, |last| min_interval.saturating_sub(now.duration_since(last)));
+    if wait.is_zero() {
+        *last_edit = Some(now);
+    }
+    wait
+}
+
+impl<T: Send + 'static> EditThrottle<T> {
+    /// Отправить состояние сразу, если интервал в чате прошел; иначе запомнить его и отправить позже
+    pub async fn submit<F, Fut>(&self, item: T, send: F)
+    where
+        F: FnOnce(T) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut state = self.state.lock().await;
+        if state.closed {
+            return;
+        }
+        if state.flush_scheduled {
+            state.pending = Some(item);
+            return;
+        }
+
+        let wait = reserve_edit_slot(&mut *self.chat_clock.lock().await, self.min_interval);
+        if wait.is_zero() {
+            state.pending = None;
+            drop(state);
+            send(item).await;
+            return;
+        }
+
+        state.pending = Some(item);
+        state.flush_scheduled = true;
+        drop(state);
+
+        // Слабая ссылка: если операция завершилась (троттлер удален), отложенная правка не отправляется
+        let weak_state = Arc::downgrade(&self.state);
+        let chat_clock = self.chat_clock.clone();
+        let min_interval = self.min_interval;
+        tokio::spawn(async move {
+            let mut wait = wait;
+            loop {
+                tokio::time::sleep(wait).await;
+                let Some(state) = weak_state.upgrade() else { return };
+                let mut state = state.lock().await;
+                if state.closed {
+                    state.flush_scheduled = false;
+                    return;
+                }
+                // Пока ждали, чат могли занять правки других операций — ждем следующего слота
+                wait = reserve_edit_slot(&mut *chat_clock.lock().await, min_interval);
+                if wait.is_zero() {
+                    state.flush_scheduled = false;
+                    let item = state.pending.take();
+                    drop(state);
+                    if let Some(item) = item {
+                        send(item).await;
+                    }
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Закрыть троттлер перед финальной правкой: отложенные состояния больше не отправляются
+    pub async fn close(&self) {
+        let mut state = self.state.lock().await;
+        state.closed = true;
+        state.pending = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Sent = Arc<Mutex<Vec<u32>>>;
+
+    const INTERVAL: Duration = Duration::from_millis(50);
+
+    fn record(sent: &Sent) -> impl FnOnce(u32) -> futures::future::Ready<()> + Send + 'static {
+        let sent = sent.clone();
+        move |item| {
+            sent.lock().unwrap().push(item);
+            futures::future::ready(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn burst_is_coalesced_into_latest_state() {
+        let throttle = ChatEditClocks::default().throttle(ChatId(1), INTERVAL);
+        let sent: Sent = Arc::default();
+
+        throttle.submit(1, record(&sent)).await;
+        throttle.submit(2, record(&sent)).await;
+        throttle.submit(3, record(&sent)).await;
+        assert_eq!(*sent.lock().unwrap(), vec![1]);
+
+        tokio::time::sleep(INTERVAL).await;
+        tokio::task::yield_now().await;
+        assert_eq!(*sent.lock().unwrap(), vec![1, 3]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn close_drops_pending_state() {
+        let throttle = ChatEditClocks::default().throttle(ChatId(1), INTERVAL);
+        let sent: Sent = Arc::default();
+
+        throttle.submit(1, record(&sent)).await;
+        throttle.submit(2, record(&sent)).await;
+        throttle.close().await;
+
+        tokio::time::sleep(INTERVAL * 2).await;
+        assert_eq!(*sent.lock().unwrap(), vec![1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn messages_in_same_chat_share_interval() {
+        let clocks = ChatEditClocks::default();
+        let first = clocks.throttle(ChatId(1), INTERVAL);
+        let second = clocks.throttle(ChatId(1), INTERVAL);
+        let other_chat = clocks.throttle(ChatId(2), INTERVAL);
+        let sent: Sent = Arc::default();
+
+        first.submit(1, record(&sent)).await;
+        // Другое сообщение того же чата ждет интервал, другой чат — нет
+        second.submit(2, record(&sent)).await;
+        other_chat.submit(3, record(&sent)).await;
+        assert_eq!(*sent.lock().unwrap(), vec![1, 3]);
+
+        tokio::time::sleep(INTERVAL / 2).await;
+        tokio::task::yield_now().await;
+        assert_eq!(*sent.lock().unwrap(), vec![1, 3]);
+
+        tokio::time::sleep(INTERVAL / 2).await;
+        tokio::task::yield_now().await;
+        assert_eq!(*sent.lock().unwrap(), vec![1, 3, 2]);
+    }
+}
@@ -0,0 +1,94 @@
+// src/notifier/failure_cooldown.rs
+
+//! Пауза после неудачного хеджа: новые хеджи по тому же символу отклоняются на failure_cooldown_secs.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex as TokioMutex;
+
+/// Время последней неудачи по символу (ключ — символ в верхнем регистре)
+#[derive(Debug, Clone, Default)]
+pub struct FailureCooldowns {
+    last_failures: Arc<TokioMutex<HashMap<String, Instant>>>,
+}
+
+impl FailureCooldowns {
+    /// Запомнить неудачу по символу (отсчет паузы начинается заново)
+    pub async fn record_failure(&self, symbol: &str) {
+        self.record_failure_at(symbol, Instant::now()).await;
+    }
+
+    /// Сколько осталось до разрешения повторного хеджа (None — можно запускать)
+    pub async fn remaining(&self, symbol: &str, cooldown: Duration) -> Option<Duration> {
+        self.remaining_at(symbol, cooldown, Instant::now()).await
+    }
+
+    /// Снять паузу по символу; true, если она была
+    pub async fn clear(&self, symbol: &str) -> bool {
+        self.last_failures.lock().await.remove(&symbol.to_uppercase()).is_some()
+    }
+
+    /// Снять паузы по всем символам; возвращает число снятых записей
+    pub async fn clear_all(&self) -> usize {
+        let mut guard = self.last_failures.lock().await;
+        let count = guard.len();
+        guard.clear();
+        count
+    }
+
+    async fn record_failure_at(&self, symbol: &str, at: Instant) {
+        self.last_failures.lock().await.insert(symbol.to_uppercase(), at);
+    }
+
+    async fn remaining_at(&self, symbol: &str, cooldown: Duration, now: Instant) -> Option<Duration> {
+        if cooldown.is_zero() {
+            return None;
+        }
+        let key = symbol.to_uppercase();
+        let mut guard = self.last_failures.lock().await;
+        let elapsed = now.saturating_duration_since(*guard.get(&key)?);
+        if elapsed >= cooldown {
+            // Пауза истекла — запись больше не нужна
+            guard.remove(&key);
+            return None;
+        }
+        Some(cooldown - elapsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COOLDOWN: Duration = Duration::from_secs(60);
+
+    #[tokio::test]
+    async fn failure_blocks_symbol_until_window_expires() {
+        let cooldowns = FailureCooldowns::default();
+        let failed_at = Instant::now();
+        cooldowns.record_failure_at("btc", failed_at).await;
+
+        let remaining = cooldowns.remaining_at("BTC", COOLDOWN, failed_at + Duration::from_secs(20)).await;
+        assert_eq!(remaining, Some(Duration::from_secs(40)));
+        assert_eq!(cooldowns.remaining_at("ETH", COOLDOWN, failed_at).await, None);
+
+        assert_eq!(cooldowns.remaining_at("BTC", COOLDOWN, failed_at + COOLDOWN).await, None);
+        assert!(!cooldowns.clear("BTC").await, "expired entry should be dropped");
+    }
+
+    #[tokio::test]
+    async fn zero_cooldown_and_clear_allow_retry() {
+        let cooldowns = FailureCooldowns::default();
+        let failed_at = Instant::now();
+        cooldowns.record_failure_at("BTC", failed_at).await;
+        cooldowns.record_failure_at("ETH", failed_at).await;
+
+        assert_eq!(cooldowns.remaining_at("BTC", Duration::ZERO, failed_at).await, None);
+
+        assert!(cooldowns.clear("btc").await);
+        assert_eq!(cooldowns.remaining_at("BTC", COOLDOWN, failed_at).await, None);
+        assert_eq!(cooldowns.clear_all().await, 1);
+        assert_eq!(cooldowns.remaining_at("ETH", COOLDOWN, failed_at).await, None);
+    }
+}
@@ -0,0 +1,188 @@
+// src/notifier/flatten.rs
+
+//! Аварийная продажа всего свободного спота монеты (/flatten) — вне учета операций в БД.
+
+use crate::notifier::{callback_data, navigation};
+use crate::notifier::utils::format_qty;
+use crate::config::Config;
+use crate::exchange::Exchange;
+use crate::exchange::types::OrderSide;
+use rust_decimal::Decimal;
+use rust_decimal::prelude::FromPrimitive;
+use std::str::FromStr;
+use std::sync::Arc;
+use teloxide::prelude::*;
+use teloxide::types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, Message};
+use tracing::{error, warn};
+
+/// Свободный спот, который можно продать; None — баланс пуст или меньше минимального ордера
+async fn sellable_spot_qty<E: Exchange>(exchange: &E, symbol: &str) -> anyhow::Result<Option<f64>> {
+    let free = exchange.get_balance(symbol).await?.free;
+    let info = exchange.get_spot_instrument_info(symbol).await?;
+    let min_qty = Decimal::from_str(&info.lot_size_filter.min_order_qty).unwrap_or(Decimal::ZERO);
+    match Decimal::from_f64(free) {
+        Some(free_d) if free_d > Decimal::ZERO && free_d >= min_qty => Ok(Some(free)),
+        _ => Ok(None),
+    }
+}
+
+/// Подтверждение привязано к окружению биржи: после перезапуска с ключами другого окружения кнопка не сработает
+fn make_flatten_confirm_keyboard(environment: &str, symbol: &str) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback(
+            "🔥 Продать по рынку",
+            format!("{}{}:{}", callback_data::PREFIX_FLATTEN_CONFIRM, environment, symbol),
+        ),
+        InlineKeyboardButton::callback("❌ Отмена", callback_data::CANCEL_DIALOG),
+    ]])
+}
+
+/// Окружение и монета из данных кнопки подтверждения
+fn parse_flatten_callback(data: &str) -> Option<(&str, &str)> {
+    data.strip_prefix(callback_data::PREFIX_FLATTEN_CONFIRM)?.split_once(':')
+}
+
+/// Обработчик команды /flatten <SYMBOL> (админ): показывает объем и просит подтверждение
+pub async fn handle_flatten_command<E>(
+    bot: Bot,
+    msg: Message,
+    symbol: String,
+    exchange: Arc<E>,
+    cfg: Arc<Config>,
+) -> anyhow::Result<()>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let chat_id = msg.chat.id;
+
+    if !cfg.is_admin_chat(chat_id.0) {
+        warn!("Chat {} tried to use /flatten without admin rights", chat_id);
+        bot.send_message(chat_id, "⛔ Команда доступна только администраторам.").await?;
+        return Ok(());
+    }
+
+    let symbol = symbol.trim().to_uppercase();
+    if symbol.is_empty() {
+        bot.send_message(chat_id, "⚠️ Укажите монету: /flatten <SYMBOL>").await?;
+        return Ok(());
+    }
+
+    let text = match sellable_spot_qty(exchange.as_ref(), &symbol).await {
+        Ok(Some(qty)) => {
+            bot.send_message(
+                chat_id,
+                format!(
+                    "🚨 Аварийная продажа спота {} ({})\n\
+                     Будет продан по рынку весь свободный баланс: {} {}.\n\
+                     Операции в истории не изменятся, фьючерсные позиции не закрываются.\n\n\
+                     Подтвердите продажу.",
+                    symbol, cfg.environment(), format_qty(qty, cfg.display_max_decimals), symbol
+                ),
+            )
+            .reply_markup(make_flatten_confirm_keyboard(cfg.environment(), &symbol))
+            .await?;
+            return Ok(());
+        }
+        Ok(None) => format!("ℹ️ Свободного баланса {} для продажи нет (или он меньше минимального ордера).", symbol),
+        Err(e) => {
+            warn!("Failed to check {} balance for /flatten: {}", symbol, e);
+            format!("❌ Не удалось получить баланс {}: {}", symbol, e)
+        }
+    };
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+/// Обработчик подтверждения /flatten (префикс flatten_conf_): продает текущий свободный баланс по рынку
+pub async fn handle_flatten_confirm_callback<E>(
+    bot: Bot,
+    q: CallbackQuery,
+    exchange: Arc<E>,
+    cfg: Arc<Config>,
+) -> anyhow::Result<()>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let (Some(data), Some(msg)) = (q.data.as_deref(), q.message.as_ref()) else {
+        warn!("CallbackQuery missing data or message in handle_flatten_confirm_callback");
+        bot.answer_callback_query(q.id).await?;
+        return Ok(());
+    };
+    let chat_id = msg.chat().id;
+    let message_id = msg.id();
+
+    if !cfg.is_admin_chat(chat_id.0) {
+        warn!("Chat {} tried to confirm /flatten without admin rights", chat_id);
+        bot.answer_callback_query(q.id).text("⛔ Только для администраторов.").show_alert(true).await?;
+        return Ok(());
+    }
+    let Some((environment, symbol)) = parse_flatten_callback(data) else {
+        error!("Failed to parse symbol from flatten callback data: {}", data);
+        bot.answer_callback_query(q.id).text("Ошибка: неверные данные.").await?;
+        return Ok(());
+    };
+    if environment != cfg.environment() {
+        warn!("Chat {} confirmed /flatten for {} while connected to {}", chat_id, environment, cfg.environment());
+        let text = format!("⛔ Подтверждение выдано для окружения {}, а бот подключен к {}. Повторите /flatten.", environment, cfg.environment());
+        bot.answer_callback_query(q.id).text(text).show_alert(true).await?;
+        return Ok(());
+    }
+    let symbol = symbol.to_string();
+    bot.answer_callback_query(q.id).await?;
+
+    // Баланс перечитывается: между командой и подтверждением он мог измениться
+    let text = match sellable_spot_qty(exchange.as_ref(), &symbol).await {
+        Ok(Some(qty)) => {
+            warn!("EMERGENCY FLATTEN: chat {} market-selling {:.8} {} spot (out of band, no DB record)", chat_id, qty, symbol);
+            match exchange.place_spot_market_order(&symbol, OrderSide::Sell, qty).await {
+                Ok(order) => {
+                    warn!("EMERGENCY FLATTEN: {} spot sell order {} placed for {:.8}", symbol, order.id, qty);
+                    format!("✅ Продано по рынку {} {} (ордер {}).", format_qty(qty, cfg.display_max_decimals), symbol, order.id)
+                }
+                Err(e) => {
+                    error!("EMERGENCY FLATTEN: {} spot sell of {:.8} failed: {}", symbol, qty, e);
+                    format!("❌ Не удалось продать {}: {}", symbol, e)
+                }
+            }
+        }
+        Ok(None) => format!("ℹ️ Свободного баланса {} для продажи уже нет.", symbol),
+        Err(e) => {
+            warn!("Failed to re-check {} balance before flatten: {}", symbol, e);
+            format!("❌ Не удалось получить баланс {}: {}", symbol, e)
+        }
+    };
+    bot.edit_message_text(chat_id, message_id, text)
+        .reply_markup(navigation::make_main_menu_keyboard())
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::mock::MockExchange;
+    use crate::exchange::types::Balance;
+
+    #[tokio::test]
+    async fn only_free_balance_above_min_qty_is_sellable() {
+        let exchange = MockExchange {
+            balances: vec![
+                ("BTC".to_string(), Balance { free: 0.25, locked: 1.0 }),
+                ("ETH".to_string(), Balance { free: 0.00005, locked: 0.0 }),
+            ],
+            ..MockExchange::default()
+        };
+
+        assert_eq!(sellable_spot_qty(&exchange, "BTC").await.unwrap(), Some(0.25));
+        assert_eq!(sellable_spot_qty(&exchange, "ETH").await.unwrap(), None, "below min order qty");
+        assert_eq!(sellable_spot_qty(&exchange, "SOL").await.unwrap(), None);
+    }
+
+    #[test]
+    fn flatten_confirmation_carries_environment() {
+        let data = format!("{}testnet:BTC", callback_data::PREFIX_FLATTEN_CONFIRM);
+        assert_eq!(parse_flatten_callback(&data), Some(("testnet", "BTC")));
+        // Кнопка старого формата без окружения не подтверждает продажу
+        assert_eq!(parse_flatten_callback(&format!("{}BTC", callback_data::PREFIX_FLATTEN_CONFIRM)), None);
+    }
+}
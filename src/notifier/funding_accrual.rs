@@ -0,0 +1,154 @@
+// src/notifier/funding_accrual.rs
+
+//! Фоновый учет накопленного фандинга по открытым хеджам (завершены, но не расхеджированы):
+//! расход пересчитывается по истории ставок с момента открытия шорта и сохраняется в БД.
+
+use crate::config::Config;
+use crate::exchange::Exchange;
+use crate::exchange::bybit::{FUNDING_INTERVALS_PER_DAY, MAX_FUNDING_HISTORY_ENTRIES};
+use crate::exchange::types::ExchangeError;
+use crate::notifier::utils::operation_label;
+use crate::storage::{Db, HedgeOperation, claim_funding_alert, get_open_hedge_operations, update_accrued_funding};
+use std::time::Duration;
+use teloxide::prelude::*;
+use tracing::{debug, error, info, warn};
+
+/// Длительность одного интервала начисления фандинга, секунд
+const FUNDING_INTERVAL_SECS: i64 = 86_400 / FUNDING_INTERVALS_PER_DAY as i64;
+
+/// Глубина истории ставок, которую отдает биржа, дней
+const MAX_ACCRUAL_HISTORY_DAYS: u16 = (MAX_FUNDING_HISTORY_ENTRIES / FUNDING_INTERVALS_PER_DAY) as u16;
+
+/// Сколько начислений фандинга прошло с открытия позиции
+pub fn funding_intervals_since(opened_at: i64, now: i64) -> u32 {
+    u32::try_from((now - opened_at).max(0) / FUNDING_INTERVAL_SECS).unwrap_or(u32::MAX)
+}
+
+/// Расход шорта на фандинг: при положительной ставке шорт получает (расход отрицательный)
+pub fn short_funding_cost(avg_rate: f64, intervals: u32, notional: f64) -> f64 {
+    -avg_rate * f64::from(intervals) * notional
+}
+
+/// Оценка накопленного расхода на фандинг операции; None — данные по ставкам/цене недоступны
+async fn estimate_accrued_funding<E: Exchange>(exchange: &E, operation: &HedgeOperation, now: i64) -> anyhow::Result<Option<f64>> {
+    // Шорт открыт к моменту завершения операции
+    let opened_at = operation.end_timestamp.unwrap_or(operation.start_timestamp);
+    let intervals = funding_intervals_since(opened_at, now);
+    if intervals == 0 {
+        return Ok(Some(0.0));
+    }
+    let days = ((now - opened_at) as f64 / 86_400.0).ceil().clamp(1.0, f64::from(MAX_ACCRUAL_HISTORY_DAYS)) as u16;
+    let futures_symbol = operation.futures_contract();
+
+    let stats = exchange.get_funding_rate(&futures_symbol, days).await?;
+    if stats.intervals == 0 {
+        return Ok(None);
+    }
+    let ticker = exchange.get_futures_ticker(&futures_symbol).await?;
+    let price = (ticker.bid_price + ticker.ask_price) / 2.0;
+    if price <= 0.0 {
+        return Ok(None);
+    }
+    Ok(Some(short_funding_cost(stats.avg_rate, intervals, operation.futures_filled_qty * price)))
+}
+
+/// Текст уведомления о превышении порога расхода на фандинг
+pub fn format_funding_alert(operation: &HedgeOperation, accrued_cost: f64, threshold: f64) -> String {
+    format!(
+        "💸 Расход на фандинг по хеджу {} ({}) достиг {:.2} {} (порог {:.2}).\nУдержание позиции дорожает — проверьте, стоит ли расхеджировать.",
+        operation_label(operation.op_ref.as_deref(), operation.id),
+        operation.base_symbol,
+        accrued_cost,
+        operation.quote_currency,
+        threshold,
+    )
+}
+
+/// Один проход по открытым хеджам: пересчет, сохранение и уведомления
+async fn run_accrual_pass<E: Exchange>(bot: &Bot, exchange: &E, cfg: &Config, db: &Db) {
+    // Хеджи другого окружения биржи (testnet/mainnet) этим ключом не проверяются
+    let operations = match get_open_hedge_operations(db).await {
+        Ok(ops) => ops.into_iter().filter(|op| op.belongs_to_environment(cfg.environment())).collect::<Vec<_>>(),
+        Err(e) => {
+            error!("Failed to load open hedges for funding accrual: {}", e);
+            return;
+        }
+    };
+    let now = chrono::Utc::now().timestamp();
+    for operation in operations {
+        let accrued_cost = match estimate_accrued_funding(exchange, &operation, now).await {
+            Ok(Some(cost)) => cost,
+            Ok(None) => {
+                debug!("op_id:{}: Funding data unavailable for {}, skipping accrual.", operation.id, operation.futures_contract());
+                continue;
+            }
+            Err(e) if ExchangeError::is_maintenance(&e) => {
+                info!("Exchange under maintenance, funding accrual pass paused.");
+                return;
+            }
+            Err(e) => {
+                warn!("op_id:{}: Failed to estimate accrued funding: {}", operation.id, e);
+                continue;
+            }
+        };
+        if let Err(e) = update_accrued_funding(db, operation.id, accrued_cost).await {
+            error!("op_id:{}: Failed to store accrued funding: {}", operation.id, e);
+            continue;
+        }
+
+        let Some(threshold) = cfg.funding_cost_alert_threshold else { continue };
+        if accrued_cost < threshold {
+            continue;
+        }
+        match claim_funding_alert(db, operation.id).await {
+            Ok(true) => {
+                info!("op_id:{}: Accrued funding {:.4} crossed threshold {:.4}, notifying chat {}", operation.id, accrued_cost, threshold, operation.chat_id);
+                let text = format_funding_alert(&operation, accrued_cost, threshold);
+                if let Err(e) = bot.send_message(ChatId(operation.chat_id), text).await {
+                    warn!("op_id:{}: Failed to send funding alert: {}", operation.id, e);
+                }
+            }
+            Ok(false) => {}
+            Err(e) => error!("op_id:{}: Failed to mark funding alert as sent: {}", operation.id, e),
+        }
+    }
+}
+
+/// Запускает периодический пересчет фандинга (funding_accrual_interval_secs = 0 — не запускается)
+pub fn spawn_funding_accrual_task<E>(bot: Bot, exchange: E, cfg: Config, db: Db)
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    if cfg.funding_accrual_interval_secs == 0 {
+        info!("Funding accrual task disabled (funding_accrual_interval_secs = 0).");
+        return;
+    }
+    let period = Duration::from_secs(cfg.funding_accrual_interval_secs);
+    info!("Starting funding accrual task (every {:?}).", period);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            run_accrual_pass(&bot, &exchange, &cfg, &db).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_are_counted_every_eight_hours() {
+        assert_eq!(funding_intervals_since(1_000, 1_000 + FUNDING_INTERVAL_SECS - 1), 0);
+        assert_eq!(funding_intervals_since(1_000, 1_000 + 3 * FUNDING_INTERVAL_SECS), 3);
+        assert_eq!(funding_intervals_since(5_000, 1_000), 0, "clock skew is not negative");
+    }
+
+    #[test]
+    fn positive_rate_is_income_for_short() {
+        // 0.01% x 6 начислений x 10 000 позиция
+        assert!((short_funding_cost(0.0001, 6, 10_000.0) + 6.0).abs() < 1e-9);
+        assert!((short_funding_cost(-0.0001, 6, 10_000.0) - 6.0).abs() < 1e-9);
+    }
+}
@@ -10,8 +10,9 @@
 // --- ИМПОРТЫ ЗАВИСИМОСТЕЙ ---
 use crate::config::Config;
 use crate::exchange::Exchange;
+use crate::hedger::WatcherRegistry;
 use crate::storage::Db;
-use crate::notifier::{StateStorage, RunningOperations};
+use crate::notifier::{StateStorage, RunningOperations, FailureCooldowns, TradingHalt, ChatEditClocks};
 use std::sync::Arc;
 use teloxide::prelude::*;
 use teloxide::types::{Message, CallbackQuery};
@@ -33,6 +34,11 @@ where E: Exchange + Clone + Send + Sync + 'static {
     crate::notifier::hedge_flow_logic::handlers::handle_hedge_command(bot, msg, symbol_arg, exchange, state_storage, running_operations, cfg, db).await
 }
 
+/// Обработчик команды /default [SYMBOL|off]
+pub async fn handle_default_command(bot: Bot, msg: Message, symbol_arg: String, cfg: Arc<Config>, db: Arc<Db>) -> anyhow::Result<()> {
+    crate::notifier::hedge_flow_logic::handlers::handle_default_command(bot, msg, symbol_arg, cfg, db).await
+}
+
 /// Обработчик колбэка кнопки "Захеджировать" из главного меню
 pub async fn handle_start_hedge_callback<E>(
     bot: Bot, q: CallbackQuery, exchange: Arc<E>, state_storage: StateStorage, cfg: Arc<Config>, db: Arc<Db>
@@ -51,6 +57,13 @@ where E: Exchange + Clone + Send + Sync + 'static {
     crate::notifier::hedge_flow_logic::handlers::handle_hedge_asset_callback(bot, q, exchange, state_storage, cfg, db).await
 }
 
+/// Обработчик выбора спотового рынка (котируемой валюты)
+pub async fn handle_hedge_pair_callback(
+    bot: Bot, q: CallbackQuery, state_storage: StateStorage, cfg: Arc<Config>
+) -> anyhow::Result<()> {
+    crate::notifier::hedge_flow_logic::handlers::handle_hedge_pair_callback(bot, q, state_storage, cfg).await
+}
+
 /// Обработчик ручного ввода тикера
 pub async fn handle_asset_ticker_input<E>(
     bot: Bot, msg: Message, exchange: Arc<E>, state_storage: StateStorage, cfg: Arc<Config>, db: Arc<Db>
@@ -61,11 +74,27 @@ where E: Exchange + Clone + Send + Sync + 'static {
 }
 
 /// Обработчик ввода суммы
-pub async fn handle_sum_input(
-    bot: Bot, msg: Message, state_storage: StateStorage, cfg: Arc<Config>
-) -> anyhow::Result<()> {
+pub async fn handle_sum_input<E>(
+    bot: Bot, msg: Message, exchange: Arc<E>, state_storage: StateStorage, cfg: Arc<Config>
+) -> anyhow::Result<()>
+where E: Exchange + Clone + Send + Sync + 'static {
     // --- ИЗМЕНЕНО: Указываем полный путь к модулю ---
-    crate::notifier::hedge_flow_logic::handlers::handle_sum_input(bot, msg, state_storage, cfg).await
+    crate::notifier::hedge_flow_logic::handlers::handle_sum_input(bot, msg, exchange, state_storage, cfg).await
+}
+
+/// Обработчик кнопки переключения единиц ввода объема
+pub async fn handle_hedge_units_callback(
+    bot: Bot, q: CallbackQuery, state_storage: StateStorage, cfg: Arc<Config>
+) -> anyhow::Result<()> {
+    crate::notifier::hedge_flow_logic::handlers::handle_hedge_units_callback(bot, q, state_storage, cfg).await
+}
+
+/// Обработчик ввода количества базовой монеты
+pub async fn handle_base_qty_input<E>(
+    bot: Bot, msg: Message, exchange: Arc<E>, state_storage: StateStorage, cfg: Arc<Config>
+) -> anyhow::Result<()>
+where E: Exchange + Clone + Send + Sync + 'static {
+    crate::notifier::hedge_flow_logic::handlers::handle_base_qty_input(bot, msg, exchange, state_storage, cfg).await
 }
 
 /// Обработчик ввода волатильности
@@ -81,9 +110,10 @@ where E: Exchange + Clone + Send + Sync + 'static {
 /// Обработчик колбэка подтверждения хеджа
 pub async fn handle_hedge_confirm_callback<E>(
     bot: Bot, q: CallbackQuery, exchange: Arc<E>, state_storage: StateStorage,
-    running_operations: RunningOperations, cfg: Arc<Config>, db: Arc<Db>
+    running_operations: RunningOperations, failure_cooldowns: FailureCooldowns, trading_halt: TradingHalt, edit_clocks: ChatEditClocks,
+    watchers: WatcherRegistry, cfg: Arc<Config>, db: Arc<Db>
 ) -> anyhow::Result<()>
 where E: Exchange + Clone + Send + Sync + 'static {
     // --- ИЗМЕНЕНО: Указываем полный путь к модулю ---
-    crate::notifier::hedge_flow_logic::handlers::handle_hedge_confirm_callback(bot, q, exchange, state_storage, running_operations, cfg, db).await
+    crate::notifier::hedge_flow_logic::handlers::handle_hedge_confirm_callback(bot, q, exchange, state_storage, running_operations, failure_cooldowns, trading_halt, edit_clocks, watchers, cfg, db).await
 }
\ No newline at end of file
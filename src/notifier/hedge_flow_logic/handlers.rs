@@ -1,17 +1,22 @@
 // src/notifier/hedge_flow_logic/handlers.rs
 
-use crate::notifier::hedge_flow_logic::ui::{make_dialog_keyboard, make_hedge_confirmation_keyboard, prompt_asset_selection};
+use crate::notifier::hedge_flow_logic::ui::{make_dialog_keyboard, make_hedge_amount_prompt, make_hedge_confirmation_keyboard, make_hedge_market_keyboard, make_leverage_confirmation_keyboard, prompt_asset_selection};
 use crate::notifier::hedge_flow_spawners::{spawn_sequential_hedge_task, spawn_ws_hedge_task};
-use crate::notifier::{StateStorage, UserState, RunningOperations, callback_data, navigation};
-use crate::config::{Config, HedgeStrategy};
+use crate::notifier::{StateStorage, UserState, RunningOperations, FailureCooldowns, TradingHalt, ChatEditClocks, callback_data, navigation};
+use crate::notifier::market_info::format_duration_secs;
+use crate::notifier::utils::delete_user_message;
+use crate::config::{Config, HedgeStrategy, LeverageMode};
 use crate::exchange::Exchange;
-use crate::storage::Db;
-use crate::hedger::Hedger;
+use crate::exchange::types::SpotMarket;
+use crate::storage::{get_default_symbol, set_default_symbol, Db};
+use crate::hedger::{hedge_spreads, leverage_filter_for, leverage_increase, leverage_to_set, HedgeParams, Hedger, WatcherRegistry};
 use crate::models::HedgeRequest;
+use crate::utils::trading_symbol;
 use std::sync::Arc;
+use std::time::Duration;
 use teloxide::prelude::*;
 // --- ИСПРАВЛЕНО: Удалены ChatId и MaybeInaccessibleMessage ---
-use teloxide::types::{Message, CallbackQuery, MessageId, InlineKeyboardMarkup, InlineKeyboardButton};
+use teloxide::types::{Message, CallbackQuery, ChatId, MessageId, InlineKeyboardMarkup, InlineKeyboardButton};
 use tracing::{info, warn, error};
 use anyhow::{Result, anyhow};
 
@@ -32,7 +37,7 @@ where
     E: Exchange + Clone + Send + Sync + 'static,
 {
     let chat_id = msg.chat.id;
-    let symbol = symbol_arg.trim().to_uppercase();
+    let symbol = resolve_hedge_symbol(&db, chat_id, &symbol_arg).await;
 
     // Получаем ID предыдущего сообщения бота, если оно было, чтобы удалить его
     let mut previous_bot_message_id: Option<i32> = None;
@@ -43,8 +48,10 @@ where
              previous_bot_message_id = match old_state {
                  UserState::AwaitingHedgeAssetSelection { last_bot_message_id, .. } => *last_bot_message_id,
                  UserState::AwaitingHedgeSum { last_bot_message_id, .. } => *last_bot_message_id,
+                 UserState::AwaitingHedgeBaseQty { last_bot_message_id, .. } => *last_bot_message_id,
                  UserState::AwaitingHedgeVolatility { last_bot_message_id, .. } => *last_bot_message_id,
                  UserState::AwaitingHedgeConfirmation { last_bot_message_id, .. } => *last_bot_message_id,
+                 UserState::AwaitingHedgeLeverageConfirmation { last_bot_message_id, .. } => *last_bot_message_id,
                  _ => None,
              };
         }
@@ -62,9 +69,7 @@ where
         }
     }
     // Удаляем сообщение пользователя с командой
-    if let Err(e) = bot.delete_message(chat_id, msg.id).await {
-        warn!("Failed delete user command msg: {}", e);
-    }
+    delete_user_message(&bot, &cfg, chat_id, msg.id, "user command msg").await;
 
 
     if symbol.is_empty() {
@@ -74,8 +79,7 @@ where
     } else {
         info!("Processing /hedge command for chat_id: {}, symbol: {}", chat_id, symbol);
         // Сразу запрашиваем сумму
-        let text = format!("Введите сумму {} для хеджирования {}:", cfg.quote_currency, symbol);
-        let kb = make_dialog_keyboard();
+        let (text, kb) = make_hedge_amount_prompt(&symbol, &cfg, false);
         let bot_msg = bot.send_message(chat_id, text).reply_markup(kb).await?;
         {
             let mut state_guard = state_storage.write().await;
@@ -90,6 +94,62 @@ where
     Ok(())
 }
 
+/// Символ для /hedge: аргумент команды, а без него — символ по умолчанию из /default (пусто — выбор актива)
+pub(crate) async fn resolve_hedge_symbol(db: &Db, chat_id: ChatId, symbol_arg: &str) -> String {
+    let symbol = symbol_arg.trim().to_uppercase();
+    if !symbol.is_empty() {
+        return symbol;
+    }
+    match get_default_symbol(db, chat_id.0).await {
+        Ok(default_symbol) => default_symbol.unwrap_or_default(),
+        Err(e) => {
+            warn!("Failed to load default symbol for chat {}: {}", chat_id, e);
+            String::new()
+        }
+    }
+}
+
+/// Обработчик команды /default [SYMBOL|off]: символ, который /hedge без аргумента выбирает сразу
+pub async fn handle_default_command(bot: Bot, msg: Message, symbol_arg: String, cfg: Arc<Config>, db: Arc<Db>) -> Result<()> {
+    let chat_id = msg.chat.id;
+    delete_user_message(&bot, &cfg, chat_id, msg.id, "/default command message").await;
+    let arg = symbol_arg.trim();
+    let text = if arg.is_empty() {
+        match get_default_symbol(&db, chat_id.0).await {
+            Ok(Some(symbol)) => format!("⭐ Символ по умолчанию: {}. /hedge без аргумента сразу спросит сумму.\nСбросить: /default off", symbol),
+            Ok(None) => "ℹ️ Символ по умолчанию не задан.\nИспользование: /default <SYMBOL>, например /default BTC".to_string(),
+            Err(e) => {
+                error!("Failed to load default symbol for chat {}: {}", chat_id, e);
+                "❌ Не удалось загрузить настройки.".to_string()
+            }
+        }
+    } else if arg.eq_ignore_ascii_case("off") {
+        match set_default_symbol(&db, chat_id.0, None).await {
+            Ok(()) => "✅ Символ по умолчанию сброшен: /hedge без аргумента снова предложит выбор актива.".to_string(),
+            Err(e) => {
+                error!("Failed to clear default symbol for chat {}: {}", chat_id, e);
+                "❌ Не удалось сохранить настройки.".to_string()
+            }
+        }
+    } else {
+        match validate_ticker_input(arg) {
+            Err(reason) => format!("⚠️ Некорректный тикер: {}", reason),
+            Ok(symbol) => match set_default_symbol(&db, chat_id.0, Some(&symbol)).await {
+                Ok(()) => {
+                    info!("Default symbol for chat {} set to {}", chat_id, symbol);
+                    format!("✅ Символ по умолчанию: {}. Теперь /hedge без аргумента сразу спросит сумму.", symbol)
+                }
+                Err(e) => {
+                    error!("Failed to save default symbol for chat {}: {}", chat_id, e);
+                    "❌ Не удалось сохранить настройки.".to_string()
+                }
+            },
+        }
+    };
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
 /// Обработчик колбэка кнопки "Захеджировать" из главного меню
 pub async fn handle_start_hedge_callback<E>(
     bot: Bot,
@@ -139,8 +199,7 @@ where
 
              if is_correct_state {
                  // Запрашиваем сумму
-                 let text = format!("Введите сумму {} для хеджирования {}:", cfg.quote_currency, symbol);
-                 let kb = make_dialog_keyboard(); // Клавиатура с кнопкой "Отмена"
+                 let (text, kb) = make_hedge_amount_prompt(symbol, &cfg, false); // Клавиатура с переключением единиц и "Отмена"
                  bot.edit_message_text(chat_id, msg.id(), text).reply_markup(kb).await?;
                  // Обновляем состояние пользователя
                  {
@@ -186,7 +245,7 @@ where
 pub async fn handle_asset_ticker_input<E>(
     bot: Bot,
     msg: Message,
-    _exchange: Arc<E>, // Не используется напрямую
+    exchange: Arc<E>,
     state_storage: StateStorage,
     cfg: Arc<Config>,
     _db: Arc<Db>, // Не используется напрямую
@@ -196,11 +255,11 @@ where
 {
     let chat_id = msg.chat.id;
     let message_id = msg.id; // ID сообщения пользователя
-    let ticker_input = msg.text().unwrap_or("").trim().to_uppercase();
+    let raw_input = msg.text().unwrap_or("").trim();
 
     // Игнорируем пустые сообщения или команды
-    if ticker_input.is_empty() || ticker_input.starts_with('/') {
-        if let Err(e) = bot.delete_message(chat_id, message_id).await { warn!("Failed to delete ignored message: {}", e); }
+    if raw_input.is_empty() || raw_input.starts_with('/') {
+        delete_user_message(&bot, &cfg, chat_id, message_id, "ignored message").await;
         return Ok(());
     }
 
@@ -209,27 +268,61 @@ where
          let state_guard = state_storage.read().await;
          match state_guard.get(&chat_id) {
             // Убеждаемся, что пользователь в нужном состоянии
-            Some(UserState::AwaitingHedgeAssetSelection { last_bot_message_id }) => *last_bot_message_id,
+            Some(UserState::AwaitingHedgeAssetSelection { last_bot_message_id })
+            | Some(UserState::AwaitingHedgeMarketSelection { last_bot_message_id, .. }) => *last_bot_message_id,
             _ => {
                  // Пользователь не в том состоянии, удаляем его сообщение и выходим
-                if let Err(e) = bot.delete_message(chat_id, message_id).await { warn!("Failed to delete unexpected text message: {}", e); }
+                delete_user_message(&bot, &cfg, chat_id, message_id, "unexpected text message").await;
                 return Ok(());
             }
          }
     };
 
-    info!("User {} entered ticker '{}' for hedge", chat_id, ticker_input);
-
     // Удаляем сообщение пользователя с тикером
-    if let Err(e) = bot.delete_message(chat_id, message_id).await { warn!("Failed to delete user ticker message: {}", e); }
+    delete_user_message(&bot, &cfg, chat_id, message_id, "user ticker message").await;
 
-    // TODO: Добавить валидацию тикера через exchange.get_spot_instrument_info или подобное
-    let is_valid_ticker = true; // Заглушка валидации
+    // Проверка формата до любых запросов к бирже
+    let validated_ticker = validate_ticker_input(raw_input);
+    if let Ok(ticker_input) = validated_ticker.as_ref() {
+        info!("User {} entered ticker '{}' for hedge", chat_id, ticker_input);
+    } else {
+        info!("User {} entered invalid ticker ({} chars) for hedge", chat_id, raw_input.chars().count());
+    }
+
+    if let Ok(ticker_input) = validated_ticker {
+        // Если у монеты несколько спотовых рынков — сначала выбор котируемой валюты.
+        // Ошибка загрузки списка пар не блокирует хедж: используем quote_currency из конфига
+        let quotes = match exchange.get_all_spot_symbols().await {
+            Ok(markets) => quote_markets_for(&markets, &ticker_input, &cfg.quote_currency),
+            Err(e) => {
+                warn!("Failed to load spot markets for {}: {}. Using configured quote {}", ticker_input, e, cfg.quote_currency);
+                Vec::new()
+            }
+        };
+        if quotes.len() > 1 {
+            let text = format!(
+                "У {} несколько спотовых рынков. Выберите пару для хеджирования (✅ — по умолчанию, {}):",
+                ticker_input, cfg.quote_currency
+            );
+            let kb = make_hedge_market_keyboard(&ticker_input, &quotes, &cfg.quote_currency);
+            let edited_id = match previous_bot_message_id {
+                Some(id) => bot.edit_message_text(chat_id, MessageId(id), &text).reply_markup(kb.clone()).await.ok().map(|_| id),
+                None => None,
+            };
+            let bot_msg_id = match edited_id {
+                Some(id) => id,
+                None => bot.send_message(chat_id, text).reply_markup(kb).await?.id.0,
+            };
+            state_storage.write().await.insert(chat_id, UserState::AwaitingHedgeMarketSelection {
+                symbol: ticker_input.clone(),
+                last_bot_message_id: Some(bot_msg_id),
+            });
+            info!("User state for {} set to AwaitingHedgeMarketSelection for {} ({} markets)", chat_id, ticker_input, quotes.len());
+            return Ok(());
+        }
 
-    if is_valid_ticker {
         // Запрашиваем сумму
-        let prompt_text = format!("Введите сумму {} для хеджирования {}:", cfg.quote_currency, ticker_input);
-        let kb = make_dialog_keyboard();
+        let (prompt_text, kb) = make_hedge_amount_prompt(&ticker_input, &cfg, false);
 
         if let Some(bot_msg_id_int) = previous_bot_message_id {
             let bot_msg_id = MessageId(bot_msg_id_int);
@@ -238,7 +331,7 @@ where
                     // Устанавливаем новое состояние - ожидание суммы
                     {
                         let mut state_guard = state_storage.write().await;
-                         if let Some(current_state @ UserState::AwaitingHedgeAssetSelection { .. }) = state_guard.get_mut(&chat_id) {
+                         if let Some(current_state @ (UserState::AwaitingHedgeAssetSelection { .. } | UserState::AwaitingHedgeMarketSelection { .. })) = state_guard.get_mut(&chat_id) {
                              *current_state = UserState::AwaitingHedgeSum {
                                  symbol: ticker_input.clone(), // Сохраняем введенный тикер
                                  last_bot_message_id: Some(bot_msg_id.0),
@@ -270,9 +363,9 @@ where
                  info!("User state for {} set to AwaitingHedgeSum for {}", chat_id, ticker_input);
                }
         }
-    } else {
+    } else if let Err(reason) = validated_ticker {
         // Тикер невалидный
-        let error_text = format!("❌ Символ '{}' не найден или не подходит для хеджирования. Попробуйте другой.", ticker_input);
+        let error_text = format!("❌ Символ выглядит некорректно: {}\nВведите тикер монеты, например BTC.", reason);
         if let Some(bot_msg_id_int) = previous_bot_message_id {
              let _ = bot.edit_message_text(chat_id, MessageId(bot_msg_id_int), error_text).await;
         } else {
@@ -284,14 +377,107 @@ where
     Ok(())
 }
 
+/// Котируемые валюты торгуемых спотовых рынков монеты; quote_currency из конфига — первой
+pub(crate) fn quote_markets_for(markets: &[SpotMarket], base: &str, default_quote: &str) -> Vec<String> {
+    let mut quotes: Vec<String> = markets
+        .iter()
+        .filter(|m| m.base_coin.eq_ignore_ascii_case(base))
+        .filter(|m| m.status.as_deref().is_none_or(|status| status.eq_ignore_ascii_case("Trading")))
+        .map(|m| m.quote_coin.to_uppercase())
+        .collect();
+    quotes.sort_by(|a, b| (a != default_quote).cmp(&(b != default_quote)).then_with(|| a.cmp(b)));
+    quotes.dedup();
+    quotes
+}
+
+/// Обработчик выбора спотового рынка (кнопки "BTC/USDT", "BTC/USDC", ...).
+/// Хедж строится в quote_currency (спот, фьючерс и балансы), поэтому другие рынки только показываются
+pub async fn handle_hedge_pair_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    state_storage: StateStorage,
+    cfg: Arc<Config>,
+) -> Result<()> {
+    let (Some(data), Some(msg)) = (q.data.as_deref(), q.message.as_ref()) else {
+        warn!("CallbackQuery missing data or message in handle_hedge_pair_callback");
+        bot.answer_callback_query(q.id).await?;
+        return Ok(());
+    };
+    let chat_id = msg.chat().id;
+    let Some((symbol, quote)) = data.strip_prefix(callback_data::PREFIX_HEDGE_PAIR).and_then(|pair| pair.split_once('/')) else {
+        warn!("Invalid callback data format for hedge pair selection: {}", data);
+        bot.answer_callback_query(q.id).await?;
+        return Ok(());
+    };
+
+    let is_correct_state = {
+        let state_guard = state_storage.read().await;
+        matches!(state_guard.get(&chat_id), Some(UserState::AwaitingHedgeMarketSelection { symbol: s, .. }) if s == symbol)
+    };
+    if !is_correct_state {
+        warn!("User {} clicked hedge pair button but was in wrong state", chat_id);
+        { state_storage.write().await.insert(chat_id, UserState::None); }
+        let _ = navigation::show_main_menu(&bot, chat_id, Some(msg.id())).await;
+        bot.answer_callback_query(q.id).text("Состояние изменилось, начните заново.").show_alert(true).await?;
+        return Ok(());
+    }
+
+    if !quote.eq_ignore_ascii_case(&cfg.quote_currency) {
+        info!("User {} selected unsupported market {}/{} for hedge", chat_id, symbol, quote);
+        let text = format!(
+            "Хедж на паре {}/{} не поддерживается: бот торгует в {} (спот, фьючерс и балансы считаются в ней). Выберите {}/{}.",
+            symbol, quote, cfg.quote_currency, symbol, cfg.quote_currency
+        );
+        bot.answer_callback_query(q.id).text(text).show_alert(true).await?;
+        return Ok(());
+    }
+
+    info!("User {} selected market {}/{} for hedge", chat_id, symbol, quote);
+    let (text, kb) = make_hedge_amount_prompt(symbol, &cfg, false);
+    bot.edit_message_text(chat_id, msg.id(), text).reply_markup(kb).await?;
+    {
+        let mut state_guard = state_storage.write().await;
+        state_guard.insert(chat_id, UserState::AwaitingHedgeSum {
+            symbol: symbol.to_string(),
+            last_bot_message_id: Some(msg.id().0),
+        });
+        info!("User state for {} set to AwaitingHedgeSum for {}", chat_id, symbol);
+    }
+    bot.answer_callback_query(q.id).await?;
+    Ok(())
+}
+
+
+/// Максимальная длина тикера монеты (самые длинные на бирже — вида 1000000MOG)
+const MAX_TICKER_LEN: usize = 20;
+
+/// Проверка введенного тикера: только латиница и цифры, не длиннее MAX_TICKER_LEN.
+/// Ok — тикер в верхнем регистре, Err — причина для пользователя
+pub(crate) fn validate_ticker_input(text: &str) -> Result<String, String> {
+    let ticker = text.trim().to_uppercase();
+    if ticker.is_empty() {
+        return Err("пустой тикер.".to_string());
+    }
+    if ticker.chars().count() > MAX_TICKER_LEN {
+        return Err(format!("слишком длинный тикер (максимум {} символов).", MAX_TICKER_LEN));
+    }
+    if !ticker.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()) {
+        return Err("допустимы только латинские буквы и цифры (A-Z, 0-9).".to_string());
+    }
+    Ok(ticker)
+}
 
 /// Обработчик ввода суммы хеджирования
-pub async fn handle_sum_input(
+pub async fn handle_sum_input<E>(
     bot: Bot,
     msg: Message,
+    exchange: Arc<E>,
     state_storage: StateStorage,
     cfg: Arc<Config>,
-) -> Result<()> {
+) -> Result<()>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
      let chat_id = msg.chat.id;
     let message_id = msg.id; // ID сообщения пользователя
     let text = msg.text().unwrap_or("").trim();
@@ -303,61 +489,57 @@ pub async fn handle_sum_input(
             // Убеждаемся, что пользователь в состоянии ожидания суммы
             Some(UserState::AwaitingHedgeSum { symbol, last_bot_message_id }) => (symbol.clone(), *last_bot_message_id),
             _ => {
-                if let Err(e) = bot.delete_message(chat_id, message_id).await { warn!("Failed to delete unexpected sum message: {}", e); }
+                delete_user_message(&bot, &cfg, chat_id, message_id, "unexpected sum message").await;
                 return Ok(());
             }
         }
     };
 
      // Удаляем сообщение пользователя с суммой
-     if let Err(e) = bot.delete_message(chat_id, message_id).await { warn!("Failed to delete user sum message: {}", e); }
+     delete_user_message(&bot, &cfg, chat_id, message_id, "user sum message").await;
+
+    // Доля свободного баланса quote: "50%"
+    if let Some(pct_result) = parse_balance_percent(text) {
+        let reprompt = |error_text: String| {
+            let bot = bot.clone();
+            let prompt = format!("{}\nВведите сумму {} или долю баланса (например, 50%) для хеджирования {}:", error_text, cfg.quote_currency, symbol);
+            async move {
+                if let Some(bot_msg_id_int) = previous_bot_message_id {
+                    let _ = bot.edit_message_text(chat_id, MessageId(bot_msg_id_int), prompt).await;
+                }
+            }
+        };
+        let pct = match pct_result {
+            Ok(pct) => pct,
+            Err(error_text) => {
+                warn!("User {} entered invalid balance percent: {}", chat_id, text);
+                reprompt(error_text).await;
+                return Ok(());
+            }
+        };
+        let free_balance = match exchange.get_balance(&cfg.quote_currency).await {
+            Ok(balance) => balance.free,
+            Err(e) => {
+                error!("Failed to get {} balance for percent sum: {}", cfg.quote_currency, e);
+                reprompt(format!("❌ Не удалось получить баланс {}: {}", cfg.quote_currency, e)).await;
+                return Ok(());
+            }
+        };
+        let sum = sum_from_balance_percent(free_balance, pct);
+        if sum <= 0.0 {
+            reprompt(format!("⚠️ Свободный баланс {} пуст ({:.2}).", cfg.quote_currency, free_balance)).await;
+            return Ok(());
+        }
+        info!("User {} entered {}% of {} {} = {} for hedge {}", chat_id, pct, free_balance, cfg.quote_currency, sum, symbol);
+        let note = format!("{}% от свободного баланса {:.2} {} = {:.2} {}", pct, free_balance, cfg.quote_currency, sum, cfg.quote_currency);
+        return advance_to_volatility_prompt(&bot, chat_id, &state_storage, &cfg, &symbol, sum, Some(&note), previous_bot_message_id).await;
+    }
 
     // Пытаемся распарсить сумму
     match text.parse::<f64>() {
          Ok(sum) if sum > 0.0 => {
              info!("User {} entered sum {} for hedge {}", chat_id, sum, symbol);
-             // Запрашиваем волатильность
-             let prompt_text = format!("Введите ожидаемую волатильность для {} {} (%):", sum, cfg.quote_currency);
-             let kb = make_dialog_keyboard(); // Клавиатура с отменой
-
-             if let Some(bot_msg_id_int) = previous_bot_message_id {
-                 let bot_msg_id = MessageId(bot_msg_id_int);
-                 match bot.edit_message_text(chat_id, bot_msg_id, prompt_text).reply_markup(kb).await {
-                    Ok(_) => {
-                         // Устанавливаем новое состояние - ожидание волатильности
-                         {
-                             let mut state_guard = state_storage.write().await;
-                              if let Some(current_state @ UserState::AwaitingHedgeSum { .. }) = state_guard.get_mut(&chat_id) {
-                                  *current_state = UserState::AwaitingHedgeVolatility {
-                                      symbol: symbol.clone(), // Сохраняем символ
-                                      sum,                    // Сохраняем сумму
-                                      last_bot_message_id: Some(bot_msg_id.0), // Обновляем ID сообщения бота
-                                 };
-                                 info!("User state for {} set to AwaitingHedgeVolatility", chat_id);
-                             } else {
-                                 warn!("State changed for {} before setting AwaitingHedgeVolatility", chat_id);
-                             }
-                         }
-                    }
-                    Err(e) => {
-                         error!("Failed to edit message {} to prompt volatility: {}", bot_msg_id, e);
-                         let _ = navigation::show_main_menu(&bot, chat_id, None).await;
-                         { state_storage.write().await.insert(chat_id, UserState::None); }
-                    }
-                 }
-             } else {
-                 warn!("No previous bot message id found for chat_id {} to edit for volatility prompt", chat_id);
-                 let bot_msg = bot.send_message(chat_id, prompt_text).reply_markup(kb).await?;
-                  {
-                     let mut state_guard = state_storage.write().await;
-                     state_guard.insert(chat_id, UserState::AwaitingHedgeVolatility {
-                         symbol: symbol.clone(),
-                         sum,
-                         last_bot_message_id: Some(bot_msg.id.0),
-                    });
-                      info!("User state for {} set to AwaitingHedgeVolatility", chat_id);
-                  }
-             }
+             advance_to_volatility_prompt(&bot, chat_id, &state_storage, &cfg, &symbol, sum, None, previous_bot_message_id).await?;
          }
          Ok(_) => {
              // Сумма не положительная
@@ -379,6 +561,270 @@ pub async fn handle_sum_input(
      Ok(())
 }
 
+/// Разбор доли баланса "50%": None — ввод не в процентах; Err — процент вне диапазона (0, 100]
+fn parse_balance_percent(text: &str) -> Option<Result<f64, String>> {
+    let value = text.strip_suffix('%')?.trim();
+    Some(match value.replace(',', ".").parse::<f64>() {
+        Ok(pct) if pct > 0.0 && pct <= 100.0 => Ok(pct),
+        Ok(pct) => Err(format!("⚠️ Доля баланса должна быть больше 0% и не больше 100% (введено {}%).", pct)),
+        Err(_) => Err(format!("⚠️ Неверный формат процента: '{}'.", text)),
+    })
+}
+
+/// Сумма по доле свободного баланса, округленная вниз до центов (чтобы не превысить баланс)
+fn sum_from_balance_percent(free_balance: f64, pct: f64) -> f64 {
+    (free_balance.max(0.0) * pct / 100.0 * 100.0).floor() / 100.0
+}
+
+// Переход к запросу волатильности после ввода объема (суммой, долей баланса или количеством)
+async fn advance_to_volatility_prompt(
+    bot: &Bot,
+    chat_id: ChatId,
+    state_storage: &StateStorage,
+    cfg: &Config,
+    symbol: &str,
+    sum: f64,
+    sum_note: Option<&str>, // Пояснение к рассчитанной сумме (например, доля баланса)
+    previous_bot_message_id: Option<i32>,
+) -> Result<()> {
+    // Запрашиваем волатильность
+    let mut prompt_text = format!(
+        "Введите ожидаемую волатильность для {} {} (%).\nМожно добавить худшую допустимую цену покупки спота: например, «5 @64000»",
+        sum, cfg.quote_currency
+    );
+    if let Some(note) = sum_note {
+        prompt_text = format!("💰 {}\n\n{}", note, prompt_text);
+    }
+    let kb = make_dialog_keyboard(); // Клавиатура с отменой
+
+    if let Some(bot_msg_id_int) = previous_bot_message_id {
+        let bot_msg_id = MessageId(bot_msg_id_int);
+        match bot.edit_message_text(chat_id, bot_msg_id, prompt_text).reply_markup(kb).await {
+            Ok(_) => {
+                // Устанавливаем новое состояние - ожидание волатильности
+                let mut state_guard = state_storage.write().await;
+                if let Some(current_state @ (UserState::AwaitingHedgeSum { .. } | UserState::AwaitingHedgeBaseQty { .. })) = state_guard.get_mut(&chat_id) {
+                    *current_state = UserState::AwaitingHedgeVolatility {
+                        symbol: symbol.to_string(), // Сохраняем символ
+                        sum,                        // Сохраняем сумму
+                        last_bot_message_id: Some(bot_msg_id.0), // Обновляем ID сообщения бота
+                    };
+                    info!("User state for {} set to AwaitingHedgeVolatility", chat_id);
+                } else {
+                    warn!("State changed for {} before setting AwaitingHedgeVolatility", chat_id);
+                }
+            }
+            Err(e) => {
+                error!("Failed to edit message {} to prompt volatility: {}", bot_msg_id, e);
+                let _ = navigation::show_main_menu(bot, chat_id, None).await;
+                { state_storage.write().await.insert(chat_id, UserState::None); }
+            }
+        }
+    } else {
+        warn!("No previous bot message id found for chat_id {} to edit for volatility prompt", chat_id);
+        let bot_msg = bot.send_message(chat_id, prompt_text).reply_markup(kb).await?;
+        let mut state_guard = state_storage.write().await;
+        state_guard.insert(chat_id, UserState::AwaitingHedgeVolatility {
+            symbol: symbol.to_string(),
+            sum,
+            last_bot_message_id: Some(bot_msg.id.0),
+        });
+        info!("User state for {} set to AwaitingHedgeVolatility", chat_id);
+    }
+    Ok(())
+}
+
+/// Обработчик кнопки переключения единиц ввода (сумма в валюте котировки / количество базовой монеты)
+pub async fn handle_hedge_units_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    state_storage: StateStorage,
+    cfg: Arc<Config>,
+) -> Result<()> {
+    if let (Some(data), Some(msg)) = (q.data.as_deref(), q.message.as_ref()) {
+        let chat_id = msg.chat().id;
+        let in_base = data.strip_prefix(callback_data::PREFIX_HEDGE_UNITS) == Some("base");
+
+        let symbol = {
+            let mut state_guard = state_storage.write().await;
+            let current_symbol = match state_guard.get(&chat_id) {
+                Some(UserState::AwaitingHedgeSum { symbol, .. }) | Some(UserState::AwaitingHedgeBaseQty { symbol, .. }) => Some(symbol.clone()),
+                _ => None,
+            };
+            if let Some(symbol) = &current_symbol {
+                let new_state = if in_base {
+                    UserState::AwaitingHedgeBaseQty { symbol: symbol.clone(), last_bot_message_id: Some(msg.id().0) }
+                } else {
+                    UserState::AwaitingHedgeSum { symbol: symbol.clone(), last_bot_message_id: Some(msg.id().0) }
+                };
+                state_guard.insert(chat_id, new_state);
+            }
+            current_symbol
+        };
+
+        match symbol {
+            Some(symbol) => {
+                info!("User {} switched hedge input units for {} (base: {})", chat_id, symbol, in_base);
+                let (text, kb) = make_hedge_amount_prompt(&symbol, &cfg, in_base);
+                bot.edit_message_text(chat_id, msg.id(), text).reply_markup(kb).await?;
+            }
+            None => {
+                warn!("User {} clicked hedge units button but was in wrong state", chat_id);
+                bot.answer_callback_query(q.id).text("Состояние изменилось, начните заново.").show_alert(true).await?;
+                return Ok(());
+            }
+        }
+    } else {
+        warn!("CallbackQuery missing data or message in handle_hedge_units_callback");
+    }
+    bot.answer_callback_query(q.id).await?;
+    Ok(())
+}
+
+/// Обработчик ввода объема хеджа в базовой монете (пересчитывается в сумму по текущей цене)
+pub async fn handle_base_qty_input<E>(
+    bot: Bot,
+    msg: Message,
+    exchange: Arc<E>,
+    state_storage: StateStorage,
+    cfg: Arc<Config>,
+) -> Result<()>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let chat_id = msg.chat.id;
+    let message_id = msg.id;
+    let text = msg.text().unwrap_or("").trim();
+
+    let (symbol, previous_bot_message_id) = {
+        let state_guard = state_storage.read().await;
+        match state_guard.get(&chat_id) {
+            Some(UserState::AwaitingHedgeBaseQty { symbol, last_bot_message_id }) => (symbol.clone(), *last_bot_message_id),
+            _ => {
+                delete_user_message(&bot, &cfg, chat_id, message_id, "unexpected qty message").await;
+                return Ok(());
+            }
+        }
+    };
+
+    delete_user_message(&bot, &cfg, chat_id, message_id, "user qty message").await;
+
+    // Повторный запрос количества с пояснением ошибки
+    let reprompt = |error_text: String| {
+        let bot = bot.clone();
+        let (prompt_text, kb) = make_hedge_amount_prompt(&symbol, &cfg, true);
+        async move {
+            if let Some(bot_msg_id_int) = previous_bot_message_id {
+                let _ = bot
+                    .edit_message_text(chat_id, MessageId(bot_msg_id_int), format!("{}\n\n{}", error_text, prompt_text))
+                    .reply_markup(kb)
+                    .await;
+            }
+        }
+    };
+
+    let qty = match parse_base_qty(text) {
+        Ok(qty) => qty,
+        Err(error_text) => {
+            warn!("User {} entered invalid base qty: {}", chat_id, text);
+            reprompt(error_text.to_string()).await;
+            return Ok(());
+        }
+    };
+
+    // Проверяем количество против минимального размера спотового ордера
+    match exchange.get_spot_instrument_info(&symbol).await {
+        Ok(info) => {
+            if let Err(error_text) = check_base_qty_minimum(qty, &info.lot_size_filter.min_order_qty, &symbol) {
+                warn!("User {} entered base qty {} below min {} for {}", chat_id, qty, info.lot_size_filter.min_order_qty, symbol);
+                reprompt(error_text).await;
+                return Ok(());
+            }
+        }
+        Err(e) => {
+            error!("Failed to get spot instrument info for {}: {}", symbol, e);
+            reprompt(format!("❌ Не удалось получить параметры инструмента {}: {}", symbol, e)).await;
+            return Ok(());
+        }
+    }
+
+    // Пересчитываем количество в сумму: qty * price = sum
+    let price = match exchange.get_spot_price(&symbol).await {
+        Ok(price) if price > 0.0 => price,
+        Ok(price) => {
+            error!("Invalid spot price {} for {}", price, symbol);
+            reprompt(format!("❌ Некорректная цена {} для {}.", price, symbol)).await;
+            return Ok(());
+        }
+        Err(e) => {
+            error!("Failed to get spot price for {}: {}", symbol, e);
+            reprompt(format!("❌ Не удалось получить цену {}: {}", symbol, e)).await;
+            return Ok(());
+        }
+    };
+    let sum = qty * price;
+    info!("User {} entered base qty {} {} for hedge (~{:.2} {} at {})", chat_id, qty, symbol, sum, cfg.quote_currency, price);
+
+    advance_to_volatility_prompt(&bot, chat_id, &state_storage, &cfg, &symbol, sum, None, previous_bot_message_id).await
+}
+
+/// Количество базовой монеты из ввода пользователя (только положительное число)
+fn parse_base_qty(text: &str) -> Result<f64, &'static str> {
+    match text.trim().parse::<f64>() {
+        Ok(qty) if qty > 0.0 && qty.is_finite() => Ok(qty),
+        Ok(_) => Err("⚠️ Количество должно быть положительным."),
+        Err(_) => Err("⚠️ Неверный формат количества."),
+    }
+}
+
+/// Количество не меньше минимального спотового ордера (minOrderQty инструмента)
+fn check_base_qty_minimum(qty: f64, min_order_qty: &str, symbol: &str) -> Result<(), String> {
+    let min_qty = min_order_qty.parse::<f64>().unwrap_or(0.0);
+    if qty < min_qty {
+        return Err(format!("⚠️ Количество {} меньше минимального ({} {}).", qty, min_order_qty, symbol));
+    }
+    Ok(())
+}
+
+/// Плечо в подтверждении: требуемое, а в режиме computed — и значение на шаге плеча символа, которое уйдет на биржу
+async fn format_required_leverage<E: Exchange>(exchange: &E, cfg: &Config, params: &HedgeParams) -> String {
+    let required_leverage = (params.fut_order_qty * params.current_spot_price) / params.available_collateral.max(f64::EPSILON);
+    if cfg.leverage_mode != LeverageMode::Computed {
+        return format!("~{:.2}x", required_leverage);
+    }
+    let leverage_filter = leverage_filter_for(exchange, &params.futures_symbol, &cfg.quote_currency).await;
+    match leverage_to_set(required_leverage, leverage_filter.as_ref()) {
+        Ok(leverage) if (leverage - required_leverage).abs() >= 0.005 => {
+            format!("~{:.2}x (будет выставлено {}x по шагу плеча символа)", required_leverage, leverage)
+        }
+        Ok(_) => format!("~{:.2}x", required_leverage),
+        Err(e) => format!("~{:.2}x ⚠️ {}", required_leverage, e),
+    }
+}
+
+/// Строка превью о займе: если свободного quote не хватает на спот, оцениваем суточную стоимость займа
+async fn format_borrow_estimate<E: Exchange>(exchange: &E, params: &HedgeParams, quote_currency: &str) -> Option<String> {
+    if params.borrow_required <= 0.0 {
+        return None;
+    }
+    let text = match exchange.get_borrow_rate(quote_currency).await {
+        Ok(hourly_rate) => format!(
+            "💳 Не хватает свободного баланса: спот будет куплен в заём ~{:.2} {}\nСтавка: {:.4}%/ч, стоимость займа ≈{:.4} {} в сутки",
+            params.borrow_required, quote_currency,
+            hourly_rate * 100.0,
+            params.borrow_required * hourly_rate * 24.0, quote_currency,
+        ),
+        Err(e) => {
+            warn!("Failed to get borrow rate for {}: {}", quote_currency, e);
+            format!(
+                "⚠️ Не хватает свободного баланса ~{:.2} {}, а заём недоступен ({}). Ордер на спот может быть отклонен.",
+                params.borrow_required, quote_currency, e
+            )
+        }
+    };
+    Some(text)
+}
+
 /// Обработчик ввода волатильности хеджирования
 pub async fn handle_volatility_input<E>(
     bot: Bot,
@@ -404,23 +850,36 @@ where
         match state_guard.get(&chat_id) {
             Some(UserState::AwaitingHedgeVolatility { symbol, sum, last_bot_message_id }) => (symbol.clone(), *sum, *last_bot_message_id),
             _ => {
-                if let Err(e) = bot.delete_message(chat_id, message_id).await { warn!("Failed to delete unexpected volatility message: {}", e); }
+                delete_user_message(&bot, &cfg, chat_id, message_id, "unexpected volatility message").await;
                 return Ok(());
             }
         }
     };
 
      // Удаляем сообщение пользователя
-     if let Err(e) = bot.delete_message(chat_id, message_id).await { warn!("Failed to delete user volatility message: {}", e); }
+     delete_user_message(&bot, &cfg, chat_id, message_id, "user volatility message").await;
+
+    // Необязательный ограничитель цены спота после "@"
+    let (volatility_text, spot_price_guard) = match parse_spot_price_guard(text) {
+        Ok(parsed) => parsed,
+        Err(()) => {
+            warn!("User {} entered invalid spot price guard: {}", chat_id, text);
+            if let Some(bot_msg_id_int) = previous_bot_message_id {
+                let error_text = "⚠️ Неверная цена после «@» (нужно положительное число). Введите снова:";
+                let _ = bot.edit_message_text(chat_id, MessageId(bot_msg_id_int), error_text).await;
+            }
+            return Ok(());
+        }
+    };
 
     // Парсим волатильность
-    match text.trim_end_matches('%').trim().parse::<f64>() {
+    match volatility_text.trim_end_matches('%').trim().parse::<f64>() {
         Ok(volatility_percent) if volatility_percent >= 0.0 => {
-            info!("User {} entered volatility {}% for hedge {} {}", chat_id, volatility_percent, sum, symbol);
+            info!("User {} entered volatility {}% (price guard {:?}) for hedge {} {}", chat_id, volatility_percent, spot_price_guard, sum, symbol);
             let volatility_fraction = volatility_percent / 100.0;
 
             // Создаем запрос хеджирования
-            let hedge_request = HedgeRequest { sum, symbol: symbol.clone(), volatility: volatility_fraction };
+            let hedge_request = HedgeRequest { sum, symbol: symbol.clone(), volatility: volatility_fraction, spot_price_guard };
             // Создаем экземпляр старого Hedger для расчета параметров
             let hedger = Hedger::new((*exchange).clone(), (*cfg).clone());
 
@@ -441,24 +900,53 @@ where
             match hedger.calculate_hedge_params(&hedge_request).await {
                 Ok(params) => {
                     info!("Hedge parameters calculated for {}: {:?}", chat_id, params);
+                    let borrow_text = format_borrow_estimate(exchange.as_ref(), &params, &cfg.quote_currency).await;
+                    let leverage_text = format_required_leverage(exchange.as_ref(), &cfg, &params).await;
                     // Формируем текст подтверждения
-                    let confirmation_text = format!(
+                    let mut confirmation_text = format!(
                         "Подтвердите параметры хеджирования для {}:\n\n\
                          Сумма: {:.2} {}\n\
                          Волатильность: {:.1}%\n\
                          --- Расчет ---\n\
                          Спот (брутто): ~{:.8} {}\n\
                          Фьючерс (нетто): ~{:.8} {}\n\
-                         Требуемое плечо: ~{:.2}x (Макс: {:.1}x)\n\n\
+                         Требуемое плечо: {} (Макс: {:.1}x)\n\n\
                          Запустить хеджирование?",
-                        symbol, sum, cfg.quote_currency,
+                        symbol, params.hedge_sum, cfg.quote_currency,
                         volatility_percent,
                         params.spot_order_qty, symbol,
                         params.fut_order_qty, symbol,
-                        // Расчет плеча
-                        (params.fut_order_qty * params.current_spot_price) / params.available_collateral.max(f64::EPSILON),
+                        leverage_text,
                         cfg.max_allowed_leverage
                     );
+                    if (params.hedge_sum - sum).abs() >= 0.005 {
+                        confirmation_text.push_str(&format!(
+                            "\n\nℹ️ Сумма скорректирована с {:.2} {} под целый шаг количества (snap_sum_to_qty)",
+                            sum, cfg.quote_currency,
+                        ));
+                    }
+                    if let Some(liquidation_price) = params.estimated_liquidation_price {
+                        confirmation_text.push_str(&format!(
+                            "\n\n⚠️ Оценка цены ликвидации шорта: ~{:.4} {} (+{:.1}% от текущей; приблизительно, без учета остального баланса)",
+                            liquidation_price, cfg.quote_currency,
+                            (liquidation_price / params.current_spot_price - 1.0) * 100.0,
+                        ));
+                    }
+                    if let Some(borrow_text) = borrow_text {
+                        confirmation_text.push_str(&format!("\n\n{}", borrow_text));
+                    }
+                    if let Some(guard) = spot_price_guard {
+                        confirmation_text.push_str(&format!(
+                            "\n\n🛡 Ограничитель: спот покупается не дороже {} {}; при уходе цены выше хедж остановится с уже купленным объемом",
+                            guard, cfg.quote_currency,
+                        ));
+                    }
+                    if params.futures_only {
+                        confirmation_text.push_str(&format!(
+                            "\n\nℹ️ Режим только фьючерса: спот не покупается (хранится вне бота), открывается шорт ~{:.8} {}.\nЗалог — свободный баланс: {:.2} {}",
+                            params.fut_order_qty, symbol, params.available_collateral, cfg.quote_currency,
+                        ));
+                    }
                     // Создаем клавиатуру подтверждения
                     let kb = make_hedge_confirmation_keyboard();
                     bot.edit_message_text(chat_id, bot_msg_id, confirmation_text).reply_markup(kb).await?;
@@ -469,8 +957,9 @@ where
                         if let Some(current_state @ UserState::AwaitingHedgeVolatility { .. }) = state_guard.get_mut(&chat_id) {
                             *current_state = UserState::AwaitingHedgeConfirmation {
                                 symbol: symbol.clone(),
-                                sum,
+                                sum: params.hedge_sum, // Подтверждается показанная (возможно, скорректированная) сумма
                                 volatility: volatility_fraction,
+                                spot_price_guard,
                                 last_bot_message_id: Some(bot_msg_id.0),
                            };
                            info!("User state for {} set to AwaitingHedgeConfirmation", chat_id);
@@ -511,6 +1000,32 @@ where
 }
 
 
+/// Отделяет необязательный ограничитель цены спота: "5 @64000" -> ("5", Some(64000)).
+/// Err — после "@" не положительное число
+fn parse_spot_price_guard(text: &str) -> Result<(&str, Option<f64>), ()> {
+    let Some((volatility_text, guard_text)) = text.split_once('@') else {
+        return Ok((text.trim(), None));
+    };
+    match guard_text.trim().parse::<f64>() {
+        Ok(guard) if guard > 0.0 && guard.is_finite() => Ok((volatility_text.trim(), Some(guard))),
+        _ => Err(()),
+    }
+}
+
+/// Пересчитывает параметры и сравнивает требуемое плечо (на шаге плеча символа) с текущим плечом на бирже.
+/// Плечо, которое биржа не примет (выше maxLeverage), — ошибка до подтверждения
+async fn pending_leverage_increase<E>(exchange: &E, cfg: &Config, symbol: &str, sum: f64, volatility: f64) -> Result<Option<(f64, f64)>>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let hedger = Hedger::new(exchange.clone(), cfg.clone());
+    let params = hedger.calculate_confirmed_hedge_params(&HedgeRequest { sum, symbol: symbol.to_string(), volatility, spot_price_guard: None }).await?;
+    let required_leverage = (params.fut_order_qty * params.current_spot_price) / params.available_collateral.max(f64::EPSILON);
+    let current_leverage = exchange.get_current_leverage(&params.futures_symbol).await?;
+    let leverage_filter = leverage_filter_for(exchange, &params.futures_symbol, &cfg.quote_currency).await;
+    leverage_increase(cfg.leverage_mode, cfg.fixed_leverage, required_leverage, current_leverage, leverage_filter.as_ref())
+}
+
 /// Обработчик колбэка подтверждения хеджа
 pub async fn handle_hedge_confirm_callback<E>(
     bot: Bot,
@@ -518,6 +1033,10 @@ pub async fn handle_hedge_confirm_callback<E>(
     exchange: Arc<E>,
     state_storage: StateStorage,
     running_operations: RunningOperations,
+    failure_cooldowns: FailureCooldowns,
+    trading_halt: TradingHalt,
+    edit_clocks: ChatEditClocks,
+    watchers: WatcherRegistry,
     cfg: Arc<Config>,
     db: Arc<Db>,
 ) -> Result<()>
@@ -531,18 +1050,22 @@ where
         let message_id = msg_ref.id();
 
         if let Some(payload) = data.strip_prefix(callback_data::PREFIX_HEDGE_CONFIRM) {
-            if payload == "yes" {
+            // "leverage" — повторное подтверждение после предупреждения о повышении плеча
+            if payload == "yes" || payload == "leverage" {
                 // --- q.message перемещается сюда для передачи в спавнер ---
                 if let Some(msg_owned) = q.message {
                     // --- Логика выбора стратегии ---
-                    let chosen_strategy = cfg.hedge_strategy_default;
-                    info!("User {} confirmed hedge operation. Chosen strategy: {:?}", chat_id, chosen_strategy);
+                    // Хедж только фьючерсом реализован в последовательной стратегии
+                    let mut chosen_strategy = cfg.effective_hedge_strategy();
 
                     // --- Получаем данные из состояния ---
-                    let (symbol, sum, volatility_fraction) = {
+                    let (symbol, sum, volatility_fraction, spot_price_guard) = {
                         let state_guard = state_storage.read().await;
-                        match state_guard.get(&chat_id) {
-                            Some(UserState::AwaitingHedgeConfirmation { symbol, sum, volatility, .. }) => (symbol.clone(), *sum, *volatility),
+                        match (payload, state_guard.get(&chat_id)) {
+                            ("yes", Some(UserState::AwaitingHedgeConfirmation { symbol, sum, volatility, spot_price_guard, .. }))
+                            | ("leverage", Some(UserState::AwaitingHedgeLeverageConfirmation { symbol, sum, volatility, spot_price_guard, .. })) => {
+                                (symbol.clone(), *sum, *volatility, *spot_price_guard)
+                            }
                             _ => {
                                 warn!("User {} confirmed hedge but was in wrong state", chat_id);
                                 bot.answer_callback_query(query_id).text("Состояние изменилось, начните заново.").show_alert(true).await?;
@@ -554,6 +1077,106 @@ where
                     };
                     // --- Сбрасываем state ПОСЛЕ извлечения данных ---
                     { state_storage.write().await.insert(chat_id, UserState::None); }
+                    // Ограничитель цены соблюдает только последовательная стратегия (цикл перестановки лимиток)
+                    if spot_price_guard.is_some() {
+                        chosen_strategy = HedgeStrategy::Sequential;
+                    }
+                    info!("User {} confirmed hedge operation. Chosen strategy: {:?}", chat_id, chosen_strategy);
+
+                    // --- Аварийная остановка торговли (/halt или файл-сигнал) ---
+                    if let Err(halted_text) = trading_halt.ensure_trading_allowed() {
+                        info!("User {} hedge on {} rejected: trading halted", chat_id, symbol);
+                        bot.edit_message_text(chat_id, message_id, halted_text)
+                            .reply_markup(navigation::make_main_menu_keyboard()).await?;
+                        bot.answer_callback_query(query_id).await?;
+                        return Ok(());
+                    }
+
+                    // --- Пауза после недавней неудачи по этому символу ---
+                    let cooldown = Duration::from_secs(cfg.failure_cooldown_secs);
+                    if let Some(remaining) = failure_cooldowns.remaining(&symbol, cooldown).await {
+                        info!("User {} hedge on {} rejected: failure cooldown, {:?} left", chat_id, symbol, remaining);
+                        let text = format!(
+                            "⏸ Хеджирование {} временно недоступно после недавней ошибки.\nПовторите через {}.",
+                            symbol, format_duration_secs(remaining.as_secs_f64().ceil()),
+                        );
+                        bot.edit_message_text(chat_id, message_id, text)
+                            .reply_markup(navigation::make_main_menu_keyboard()).await?;
+                        bot.answer_callback_query(query_id).await?;
+                        return Ok(());
+                    }
+
+                    // --- Защита от широкого спреда (max_spread_pct) ---
+                    if let Some(max_spread_pct) = cfg.max_spread_pct {
+                        let futures_symbol = trading_symbol(&symbol, &cfg.quote_currency);
+                        match hedge_spreads(exchange.as_ref(), &symbol, &futures_symbol).await {
+                            Ok(spreads) if spreads.exceeds(max_spread_pct) => {
+                                info!(
+                                    "User {} hedge on {} rejected: spot spread {:.4}%, futures spread {:.4}%, max {:.4}%",
+                                    chat_id, symbol, spreads.spot_pct, spreads.futures_pct, max_spread_pct,
+                                );
+                                let text = format!(
+                                    "⚠️ Спред слишком широкий (допустимо до {:.3}%):
+спот {}: {:.3}%
+фьючерс {}: {:.3}%
+Хедж не запущен — исполнение было бы по невыгодной цене. Попробуйте позже.",
+                                    max_spread_pct, symbol, spreads.spot_pct, futures_symbol, spreads.futures_pct,
+                                );
+                                bot.edit_message_text(chat_id, message_id, text)
+                                    .reply_markup(navigation::make_main_menu_keyboard()).await?;
+                                bot.answer_callback_query(query_id).await?;
+                                return Ok(());
+                            }
+                            Ok(spreads) => info!(
+                                "Spread check for {} passed: spot {:.4}%, futures {:.4}% <= {:.4}%",
+                                symbol, spreads.spot_pct, spreads.futures_pct, max_spread_pct,
+                            ),
+                            Err(e) => {
+                                warn!("Failed to check spread for {}: {}", symbol, e);
+                                let text = format!("❌ Не удалось проверить спред {}: {}
+Попробуйте снова.", symbol, e);
+                                bot.edit_message_text(chat_id, message_id, text)
+                                    .reply_markup(navigation::make_main_menu_keyboard()).await?;
+                                bot.answer_callback_query(query_id).await?;
+                                return Ok(());
+                            }
+                        }
+                    }
+
+                    // --- Повышение плеча требует отдельного подтверждения (confirm_leverage_increase) ---
+                    if payload == "yes" && cfg.confirm_leverage_increase {
+                        match pending_leverage_increase(exchange.as_ref(), &cfg, &symbol, sum, volatility_fraction).await {
+                            Ok(Some((current_leverage, new_leverage))) => {
+                                info!("User {} hedge on {} raises leverage {:.2}x -> {:.2}x, asking for confirmation", chat_id, symbol, current_leverage, new_leverage);
+                                let text = format!(
+                                    "⚠️ Хедж повысит плечо {}: {:.2}x → {:.2}x.\n\
+                                     Плечо меняется для всего символа: цена ликвидации уже открытых позиций по нему станет ближе.\n\n\
+                                     Повысить плечо и запустить хеджирование?",
+                                    trading_symbol(&symbol, &cfg.quote_currency), current_leverage, new_leverage,
+                                );
+                                bot.edit_message_text(chat_id, message_id, text)
+                                    .reply_markup(make_leverage_confirmation_keyboard()).await?;
+                                state_storage.write().await.insert(chat_id, UserState::AwaitingHedgeLeverageConfirmation {
+                                    symbol: symbol.clone(),
+                                    sum,
+                                    volatility: volatility_fraction,
+                                    spot_price_guard,
+                                    last_bot_message_id: Some(message_id.0),
+                                });
+                                bot.answer_callback_query(query_id).await?;
+                                return Ok(());
+                            }
+                            Ok(None) => info!("Hedge on {} does not raise leverage, no extra confirmation needed", symbol),
+                            Err(e) => {
+                                warn!("Failed to check leverage change for {}: {}", symbol, e);
+                                let text = format!("❌ Плечо {} не подтверждено: {}\nИзмените сумму или попробуйте снова.", symbol, e);
+                                bot.edit_message_text(chat_id, message_id, text)
+                                    .reply_markup(navigation::make_main_menu_keyboard()).await?;
+                                bot.answer_callback_query(query_id).await?;
+                                return Ok(());
+                            }
+                        }
+                    }
 
                     // --- Запуск выбранной стратегии ---
                     match chosen_strategy {
@@ -562,15 +1185,17 @@ where
                              bot.edit_message_text(chat_id, message_id, waiting_text)
                                 .reply_markup(InlineKeyboardMarkup::new(Vec::<Vec<InlineKeyboardButton>>::new())).await?;
 
-                             let hedge_request = HedgeRequest { sum, symbol: symbol.clone(), volatility: volatility_fraction };
+                             let hedge_request = HedgeRequest { sum, symbol: symbol.clone(), volatility: volatility_fraction, spot_price_guard };
                              let hedger = Hedger::new((*exchange).clone(), (*cfg).clone());
 
-                             match hedger.calculate_hedge_params(&hedge_request).await {
+                             // Сумма уже подтверждена (и при snap_sum_to_qty подогнана) — повторно не подгоняется
+                             match hedger.calculate_confirmed_hedge_params(&hedge_request).await {
                                  Ok(params) => {
                                      info!("Sequential hedge params OK for {}: {:?}", chat_id, params);
+                                     let hedge_sum = params.hedge_sum;
                                      spawn_sequential_hedge_task(
                                          bot.clone(), exchange.clone(), cfg.clone(), db.clone(),
-                                         running_operations.clone(), chat_id, params, sum,
+                                         running_operations.clone(), failure_cooldowns.clone(), edit_clocks.clone(), watchers.clone(), chat_id, params, hedge_sum,
                                          volatility_fraction * 100.0, msg_owned,
                                      ).await;
                                      // Успешный спавн, отвечаем на колбэк
@@ -578,6 +1203,7 @@ where
                                  }
                                  Err(e) => {
                                      error!("Hedge parameter calculation failed just before execution for {}: {}", chat_id, e);
+                                     failure_cooldowns.record_failure(&symbol).await;
                                      let error_text = format!("❌ Ошибка расчета параметров перед запуском: {}\nПопробуйте снова.", e);
                                      let _ = bot.edit_message_text(chat_id, message_id, error_text)
                                               .reply_markup(navigation::make_main_menu_keyboard())
@@ -598,8 +1224,10 @@ where
                                 cfg.clone(),
                                 db.clone(),
                                 running_operations.clone(),
+                                failure_cooldowns.clone(),
+                                edit_clocks.clone(),
                                 chat_id,
-                                HedgeRequest { sum, symbol: symbol.clone(), volatility: volatility_fraction },
+                                HedgeRequest { sum, symbol: symbol.clone(), volatility: volatility_fraction, spot_price_guard: None },
                                 msg_owned,
                              ).await;
 
@@ -642,4 +1270,137 @@ where
     // --- ИСПРАВЛЕНО: Удален финальный answer_callback_query ---
     // let _ = bot.answer_callback_query(query_id).await;
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volatility_input_accepts_optional_price_guard() {
+        assert_eq!(parse_spot_price_guard("5"), Ok(("5", None)));
+        assert_eq!(parse_spot_price_guard("5% @ 64000"), Ok(("5%", Some(64000.0))));
+        assert_eq!(parse_spot_price_guard("5@0.25"), Ok(("5", Some(0.25))));
+        assert!(parse_spot_price_guard("5 @").is_err());
+        assert!(parse_spot_price_guard("5 @-1").is_err());
+    }
+
+    #[test]
+    fn base_qty_input_is_parsed_and_checked_against_minimum() {
+        assert_eq!(parse_base_qty(" 0.25 "), Ok(0.25));
+        assert!(parse_base_qty("0").is_err());
+        assert!(parse_base_qty("-1").is_err());
+        assert!(parse_base_qty("abc").is_err());
+        assert!(parse_base_qty("inf").is_err());
+
+        assert!(check_base_qty_minimum(0.001, "0.001", "BTC").is_ok());
+        let err = check_base_qty_minimum(0.0005, "0.001", "BTC").unwrap_err();
+        assert!(err.contains("0.001 BTC"), "{}", err);
+        // Нераспознанный минимум не блокирует ввод
+        assert!(check_base_qty_minimum(0.0005, "", "BTC").is_ok());
+    }
+
+    #[test]
+    fn ticker_input_is_validated_before_exchange_calls() {
+        assert_eq!(validate_ticker_input(" btc "), Ok("BTC".to_string()));
+        assert_eq!(validate_ticker_input("1000PEPE"), Ok("1000PEPE".to_string()));
+        assert!(validate_ticker_input(&"A".repeat(50)).is_err());
+        assert!(validate_ticker_input("BT C!").is_err());
+        assert!(validate_ticker_input("БТК").is_err());
+    }
+
+    #[tokio::test]
+    async fn confirmation_shows_leverage_on_symbol_step() {
+        use crate::exchange::mock::MockExchange;
+        use crate::exchange::types::LeverageFilter;
+        use rust_decimal::Decimal;
+
+        let integer_only = LeverageFilter { min_leverage: "1".to_string(), max_leverage: "25".to_string(), leverage_step: "1".to_string() };
+        let exchange = MockExchange { leverage_filter: Some(integer_only), ..MockExchange::default() };
+        let params = HedgeParams {
+            spot_order_qty: 1.0,
+            fut_order_qty: 2.35,
+            current_spot_price: 100.0,
+            initial_limit_price: 100.0,
+            symbol: "ETH".to_string(),
+            hedge_sum: 200.0,
+            spot_value: 100.0,
+            available_collateral: 100.0,
+            borrow_required: 0.0,
+            estimated_liquidation_price: None,
+            min_spot_qty_decimal: Decimal::ZERO,
+            min_fut_qty_decimal: Decimal::ZERO,
+            spot_decimals: 4,
+            fut_decimals: 2,
+            futures_symbol: "ETHUSDT".to_string(),
+            spot_price_guard: None,
+            futures_only: false,
+        };
+
+        // Превью показывает то же плечо, что уйдет в set-leverage и в подтверждение повышения
+        let text = format_required_leverage(&exchange, &crate::config::test_config(""), &params).await;
+        assert_eq!(text, "~2.35x (будет выставлено 3x по шагу плеча символа)");
+        let fixed = crate::config::test_config("leverage_mode = \"fixed\"");
+        assert_eq!(format_required_leverage(&exchange, &fixed, &params).await, "~2.35x");
+    }
+
+    #[tokio::test]
+    async fn bare_hedge_uses_stored_default_symbol() {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+        crate::storage::schema::apply_migrations(&db).await.expect("migrations");
+        let chat_id = ChatId(42);
+
+        // Без настройки — пустой символ, т.е. выбор актива
+        assert_eq!(resolve_hedge_symbol(&db, chat_id, "  ").await, "");
+
+        set_default_symbol(&db, chat_id.0, Some("ETH")).await.expect("set default");
+        assert_eq!(resolve_hedge_symbol(&db, chat_id, "").await, "ETH");
+        // Явный аргумент важнее настройки
+        assert_eq!(resolve_hedge_symbol(&db, chat_id, "btc").await, "BTC");
+        // Настройка одного чата не влияет на другой
+        assert_eq!(resolve_hedge_symbol(&db, ChatId(7), "").await, "");
+    }
+
+    fn market(base: &str, quote: &str, status: &str) -> SpotMarket {
+        SpotMarket {
+            symbol: format!("{}{}", base, quote),
+            base_coin: base.to_string(),
+            quote_coin: quote.to_string(),
+            status: Some(status.to_string()),
+        }
+    }
+
+    #[test]
+    fn spot_quote_markets_put_configured_quote_first() {
+        let markets = vec![
+            market("BTC", "USDC", "Trading"),
+            market("BTC", "EUR", "Trading"),
+            market("BTC", "USDT", "Trading"),
+            market("BTC", "DAI", "Closed"),
+            market("ETH", "USDT", "Trading"),
+        ];
+        assert_eq!(quote_markets_for(&markets, "BTC", "USDT"), vec!["USDT", "EUR", "USDC"]);
+        assert_eq!(quote_markets_for(&markets, "ETH", "USDT"), vec!["USDT"]);
+        assert!(quote_markets_for(&markets, "SOL", "USDT").is_empty());
+    }
+
+    #[test]
+    fn balance_percent_is_parsed_and_resolved() {
+        assert_eq!(parse_balance_percent("50%"), Some(Ok(50.0)));
+        assert_eq!(parse_balance_percent("100%"), Some(Ok(100.0)));
+        assert_eq!(sum_from_balance_percent(1234.567, 50.0), 617.28);
+        assert_eq!(sum_from_balance_percent(1234.567, 100.0), 1234.56);
+    }
+
+    #[test]
+    fn balance_percent_out_of_range_is_rejected() {
+        assert!(parse_balance_percent("150%").expect("percent input").is_err());
+        assert!(parse_balance_percent("0%").expect("percent input").is_err());
+        assert!(parse_balance_percent("abc%").expect("percent input").is_err());
+        assert_eq!(parse_balance_percent("500"), None);
+    }
+}
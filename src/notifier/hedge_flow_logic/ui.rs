@@ -24,6 +24,16 @@ pub(super) fn make_hedge_confirmation_keyboard() -> InlineKeyboardMarkup {
     ])
 }
 
+// Клавиатура подтверждения повышения плеча
+pub(super) fn make_leverage_confirmation_keyboard() -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![
+        vec![
+            InlineKeyboardButton::callback("✅ Повысить плечо и запустить", format!("{}{}", callback_data::PREFIX_HEDGE_CONFIRM, "leverage")),
+            InlineKeyboardButton::callback("❌ Нет, отмена", callback_data::CANCEL_DIALOG),
+        ],
+    ])
+}
+
 // Создает простую клавиатуру с отменой
 pub(super) fn make_dialog_keyboard() -> InlineKeyboardMarkup {
      InlineKeyboardMarkup::new(vec![vec![
@@ -31,6 +41,43 @@ pub(super) fn make_dialog_keyboard() -> InlineKeyboardMarkup {
     ]])
 }
 
+// Текст и клавиатура запроса объема хеджа: суммой в валюте котировки или количеством базовой монеты
+pub(super) fn make_hedge_amount_prompt(symbol: &str, cfg: &Config, in_base: bool) -> (String, InlineKeyboardMarkup) {
+    let (text, toggle_button) = if in_base {
+        (
+            format!("Введите количество {} для хеджирования (сумма в {} будет рассчитана по текущей цене):", symbol, cfg.quote_currency),
+            InlineKeyboardButton::callback(format!("💵 Ввести сумму в {}", cfg.quote_currency), format!("{}{}", callback_data::PREFIX_HEDGE_UNITS, "quote")),
+        )
+    } else {
+        (
+            format!("Введите сумму {} или долю свободного баланса (например, 50%) для хеджирования {}:", cfg.quote_currency, symbol),
+            InlineKeyboardButton::callback(format!("🪙 Ввести количество {}", symbol), format!("{}{}", callback_data::PREFIX_HEDGE_UNITS, "base")),
+        )
+    };
+    let kb = InlineKeyboardMarkup::new(vec![
+        vec![toggle_button],
+        vec![InlineKeyboardButton::callback("❌ Отмена", callback_data::CANCEL_DIALOG)],
+    ]);
+    (text, kb)
+}
+
+// Клавиатура выбора спотового рынка, когда у монеты несколько котируемых валют
+pub(super) fn make_hedge_market_keyboard(symbol: &str, quotes: &[String], default_quote: &str) -> InlineKeyboardMarkup {
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = quotes
+        .chunks(2)
+        .map(|row| {
+            row.iter()
+                .map(|quote| {
+                    let label = if quote == default_quote { format!("✅ {}/{}", symbol, quote) } else { format!("{}/{}", symbol, quote) };
+                    InlineKeyboardButton::callback(label, format!("{}{}/{}", callback_data::PREFIX_HEDGE_PAIR, symbol, quote))
+                })
+                .collect()
+        })
+        .collect();
+    buttons.push(vec![InlineKeyboardButton::callback("❌ Отмена", callback_data::CANCEL_DIALOG)]);
+    InlineKeyboardMarkup::new(buttons)
+}
+
 // Запрашивает у пользователя выбор актива для хеджирования
 pub(super) async fn prompt_asset_selection<E>(
     bot: &Bot, // Принимаем бот по ссылке
@@ -60,7 +107,7 @@ where
     }
 
     // Получаем балансы и формируем кнопки
-    match wallet_info::get_formatted_balances(exchange.as_ref(), &cfg.quote_currency, false).await {
+    match wallet_info::get_formatted_balances(exchange.as_ref(), &cfg.quote_currency, false, cfg.wallet_sort).await {
         Ok((_, asset_data)) => {
             let mut buttons: Vec<Vec<InlineKeyboardButton>> = Vec::new();
             let mut assets_found = false;
@@ -11,24 +11,46 @@ use futures::future::FutureExt;
 
 // --- ИСПРАВЛЕНО: Убран неиспользуемый импорт ---
 // use crate::webservice_hedge::hedge_logic::helpers::send_progress_update;
-use crate::webservice_hedge::hedge_task::HedgerWsHedgeTask;
+use crate::webservice_hedge::{run_websocket_operation, WsOperation};
 use crate::config::Config;
 use crate::exchange::Exchange;
-use crate::exchange::bybit_ws;
-use crate::exchange::types::SubscriptionType;
-use crate::hedger::{HedgeParams, HedgeProgressCallback, HedgeProgressUpdate, HedgeStage, Hedger, ORDER_FILL_TOLERANCE};
+use crate::hedger::{
+    compare_exposure, snapshot_exposure, ExposureSnapshot, FuturesOrderLeftActive, HedgeParams, HedgeProgressCallback, HedgeProgressUpdate, HedgeStage, Hedger,
+    PriceGuardHit, SpotOnlyOrphan, WatcherRegistry, HEDGE_DELTA_TOLERANCE_RATIO, ORDER_FILL_TOLERANCE,
+};
 use crate::models::HedgeRequest;
 use crate::storage::{Db, insert_hedge_operation};
-use crate::notifier::{RunningOperations, RunningOperationInfo, OperationType, navigation, callback_data};
+use crate::notifier::{RunningOperations, RunningOperationGuard, FailureCooldowns, RunningOperationInfo, OperationType, alerts, navigation, callback_data};
+use crate::notifier::utils::{describe_error, display_decimals, edit_message_text_safe, format_qty, operation_label};
+use crate::notifier::edit_throttle::{ChatEditClocks, EditThrottle};
+use crate::notifier::trailing_stop::make_completed_hedge_keyboard;
+use crate::notifier::active_ops::make_keep_spot_cancel_button;
+use crate::notifier::spot_orphan::{format_spot_orphan_warning, make_spot_orphan_keyboard};
 // Ensure the correct path to the module
 
 
+/// Повторное подтверждение (двойное нажатие): пользователю сообщается, какая операция уже идет.
+/// Отдельным сообщением — исходное продолжает обновлять задача первой операции
+async fn report_duplicate_hedge(bot: &Bot, chat_id: ChatId, operation_id: i64, op_ref: &str, symbol: &str) {
+    let text = format!(
+        "ℹ️ Хедж {} уже запущен: операция {}.\nПовторное подтверждение проигнорировано, прогресс — в сообщении выше.",
+        symbol,
+        operation_label(Some(op_ref), operation_id),
+    );
+    if let Err(e) = bot.send_message(chat_id, text).await {
+        warn!("op_id:{}: Failed to report duplicate hedge request: {}", operation_id, e);
+    }
+}
+
 pub(super) async fn spawn_sequential_hedge_task<E>(
     bot: Bot,
     exchange: Arc<E>,
     cfg: Arc<Config>,
     db: Arc<Db>,
     running_operations: RunningOperations,
+    failure_cooldowns: FailureCooldowns,
+    edit_clocks: ChatEditClocks,
+    watchers: WatcherRegistry,
     chat_id: ChatId,
     params: HedgeParams,
     initial_sum: f64,
@@ -55,18 +77,31 @@ E: Exchange + Clone + Send + Sync + 'static,
     let symbol_for_callback = params.symbol.clone();
     let symbol_for_task_body = params.symbol.clone();
     let symbol_for_info = params.symbol.clone();
+    let futures_symbol_for_task = params.futures_symbol.clone();
     let initial_spot_target_for_cb = params.spot_order_qty;
     let initial_fut_target_for_cb = params.fut_order_qty;
+    let futures_only = params.futures_only;
+    // Точность отображения количеств (по инструменту, но не больше display_max_decimals)
+    let spot_display_decimals = display_decimals(Some(params.spot_decimals), cfg.display_max_decimals);
+    let fut_display_decimals = display_decimals(Some(params.fut_decimals), cfg.display_max_decimals);
 
-    let hedger = Hedger::new((*exchange).clone(), (*cfg).clone());
+    let hedger = Hedger::new((*exchange).clone(), (*cfg).clone()).with_watchers(watchers);
+    let active_order_storage = hedger.active_order_storage();
+    let stage_storage = hedger.stage_storage();
 
     let operation_id_result = insert_hedge_operation(
-        db.as_ref(), chat_id.0, &params.symbol, &cfg.quote_currency, initial_sum,
-        volatility_percent / 100.0, params.spot_order_qty, params.fut_order_qty,
+        db.as_ref(), chat_id.0, &params.symbol, &cfg.quote_currency, cfg.environment(), initial_sum,
+        volatility_percent / 100.0, params.spot_order_qty, params.fut_order_qty, params.futures_only,
     ).await;
 
-    let operation_id = match operation_id_result {
-        Ok(id) => { info!("op_id:{}: Created DB record for hedge operation.", id); id }
+    let (operation_id, op_ref) = match operation_id_result {
+        Ok((id, op_ref, true)) => { info!("op_id:{}: Created DB record for hedge operation {}.", id, op_ref); (id, op_ref) }
+        Ok((id, op_ref, false)) => {
+            // Повторное подтверждение (двойное нажатие): операцию ведет первая задача
+            warn!("op_id:{}: Duplicate hedge request for {} ignored, operation already exists.", id, params.symbol);
+            report_duplicate_hedge(&bot, chat_id, id, &op_ref, &params.symbol).await;
+            return;
+        }
         Err(e) => {
             error!("Failed insert hedge op to DB: {}", e);
             let _ = bot.edit_message_text(chat_id, bot_message_id, format!("❌ DB Error: {}", e))
@@ -82,10 +117,19 @@ E: Exchange + Clone + Send + Sync + 'static,
     let total_filled_qty_storage_clone = total_filled_qty_storage.clone();
     let running_operations_clone = running_operations.clone();
 
+    let progress_throttle: EditThrottle<(String, InlineKeyboardMarkup)> = edit_clocks.throttle(chat_id, Duration::from_millis(cfg.min_edit_interval_ms));
+    let progress_throttle_for_callback = progress_throttle.clone();
+
     // --- ИСПРАВЛЕНО: bot_message_id захватывается по значению (Copy) ---
+    let op_label = operation_label(Some(&op_ref), operation_id);
+    let op_label_for_callback = op_label.clone();
     let progress_callback: HedgeProgressCallback = Box::new(move |update: HedgeProgressUpdate| {
          let bot_for_callback = bot_clone.clone();
+         let op_label_cb = op_label_for_callback.clone();
+         let throttle_cb = progress_throttle_for_callback.clone();
          let qc = cfg_clone.quote_currency.clone();
+         let edit_max_retries = cfg_clone.progress_edit_max_retries;
+         let edit_min_delay = Duration::from_millis(cfg_clone.min_edit_interval_ms);
          let symbol_cb = symbol_for_callback.clone();
          let msg_id_cb = bot_message_id; // Используем ID, захваченный ранее
          let chat_id_cb = chat_id;
@@ -108,9 +152,9 @@ E: Exchange + Clone + Send + Sync + 'static,
                      let progress_bar = format!("[{}{}]", "█".repeat(filled_blocks), "░".repeat(empty_blocks));
                      // Проверка завершения спотовой части
                      if (update.cumulative_filled_qty - spot_target_cb).abs() <= ORDER_FILL_TOLERANCE {
-                         format!( "✅ Спот куплен ID:{} ({})\nРын.цена: {:.2}\nОжидание продажи фьючерса...", operation_id_cb, symbol, update.current_spot_price)
+                         format!( "✅ Спот куплен {} ({})\nРын.цена: {:.2}\nОжидание продажи фьючерса...", op_label_cb, symbol, update.current_spot_price)
                      } else {
-                         format!( "⏳ Хедж (Спот) ID:{} {} {:.2} {} ({})\nРын.цена: {:.2}\nОрдер ПОКУПКА: {:.2} {}\nИсполнено (тек.ордер): {:.6}/{:.6} ({:.1}%)", operation_id_cb, progress_bar, initial_sum_cb, qc, symbol, update.current_spot_price, update.new_limit_price, status_text, update.filled_qty, update.target_qty, filled_percent)
+                         format!( "⏳ Хедж (Спот) {} {} {:.2} {} ({})\nРын.цена: {:.2}\nОрдер ПОКУПКА: {:.2} {}\nИсполнено (тек.ордер): {}/{} ({:.1}%)", op_label_cb, progress_bar, initial_sum_cb, qc, symbol, update.current_spot_price, update.new_limit_price, status_text, format_qty(update.filled_qty, spot_display_decimals), format_qty(update.target_qty, spot_display_decimals), filled_percent)
                      }
                  }
                  HedgeStage::Futures => {
@@ -119,16 +163,23 @@ E: Exchange + Clone + Send + Sync + 'static,
                      let filled_blocks = (filled_percent / (100.0 / progress_bar_len as f64)).round() as usize;
                      let empty_blocks = progress_bar_len - filled_blocks;
                      let progress_bar = format!("[{}{}]", "█".repeat(filled_blocks), "░".repeat(empty_blocks));
-                     format!( "⏳ Хедж (Фьюч) ID:{} {} {:.2} {} ({})\nСпот цена: {:.2}\nОрдер ПРОДАЖА: {:.2} {}\nИсполнено (фьюч): {:.6}/{:.6} ({:.1}%)", operation_id_cb, progress_bar, initial_sum_cb, qc, symbol, update.current_spot_price, update.new_limit_price, status_text, update.cumulative_filled_qty, fut_target_cb, filled_percent)
+                     format!( "⏳ Хедж (Фьюч) {} {} {:.2} {} ({})\nСпот цена: {:.2}\nОрдер ПРОДАЖА: {:.2} {}\nИсполнено (фьюч): {}/{} ({:.1}%)", op_label_cb, progress_bar, initial_sum_cb, qc, symbol, update.current_spot_price, update.new_limit_price, status_text, format_qty(update.cumulative_filled_qty, fut_display_decimals), format_qty(fut_target_cb, fut_display_decimals), filled_percent)
                  }
              };
              let cancel_callback_data = format!("{}{}", callback_data::PREFIX_CANCEL_ACTIVE_OP, operation_id_cb);
              let cancel_button = InlineKeyboardButton::callback("❌ Отменить эту операцию", cancel_callback_data);
-             let kb = InlineKeyboardMarkup::new(vec![vec![cancel_button]]);
-             // --- ИСПРАВЛЕНО: Используем msg_id_cb ---
-             if let Err(e) = bot_for_callback.edit_message_text(chat_id_cb, msg_id_cb, text).reply_markup(kb).await {
-                 if !e.to_string().contains("not modified") { warn!("op_id:{}: Progress callback failed: {}", operation_id_cb, e); }
+             let mut buttons = vec![vec![cancel_button]];
+             // На этапе фьючерса можно бросить только фьючерсную ногу, оставив купленный спот
+             if let Some(keep_spot_button) = make_keep_spot_cancel_button(operation_id_cb, update.stage, spot_target_cb) {
+                 buttons.push(vec![keep_spot_button]);
              }
+             let kb = InlineKeyboardMarkup::new(buttons);
+             // Правки схлопываются троттлером (не чаще min_edit_interval_ms)
+             throttle_cb.submit((text, kb), move |(text, kb)| async move {
+                 if let Err(e) = edit_message_text_safe(&bot_for_callback, chat_id_cb, msg_id_cb, text, kb, edit_max_retries, edit_min_delay).await {
+                     warn!("op_id:{}: Progress callback failed: {}", operation_id_cb, e);
+                 }
+             }).await;
              Ok(())
          }.boxed()
     });
@@ -136,36 +187,101 @@ E: Exchange + Clone + Send + Sync + 'static,
     let exchange_task = exchange.clone();
     let cfg_task = cfg.clone();
 
+    // Держим блокировку до вставки записи, чтобы быстро завершившаяся задача не оставила ее в карте
+    let mut ops_guard = running_operations.lock().await;
+    let cleanup_guard = RunningOperationGuard::new(running_operations_clone, chat_id, operation_id);
+
     // --- ИСПРАВЛЕНО: Не передаем waiting_message в spawn ---
     let task = tokio::spawn(async move {
+        let exposure_before = exposure_before_hedge(&*exchange_task, &cfg_task, futures_only, operation_id, &symbol_for_task_body, &futures_symbol_for_task).await;
+
         let result = hedger.run_hedge(
-            params, progress_callback, total_filled_qty_storage_clone, operation_id, db_clone.as_ref(),
+            params, progress_callback, total_filled_qty_storage_clone, operation_id, chat_id.0, db_clone.as_ref(),
         ).await;
+        progress_throttle.close().await; // Отложенная правка прогресса не должна затереть итоговое сообщение
 
         let is_cancelled_by_button = result.is_err() && result.as_ref().err().map_or(false, |e| e.to_string().contains("cancelled by user"));
-        if !is_cancelled_by_button {
-             running_operations_clone.lock().await.remove(&(chat_id, operation_id));
-             info!("op_id:{}: Removed running operation info for chat_id: {}", operation_id, chat_id);
-        } else { info!("op_id:{}: Operation was cancelled via button, info already removed.", operation_id); }
+        drop(cleanup_guard); // Запись удаляется и при отмене кнопкой, и при abort/панике
 
         match result {
-            Ok((spot_qty_gross, fut_qty_net, final_spot_value_gross)) => {
-                 info!( "op_id:{}: Hedge OK. Spot Gross: {}, Fut Net: {}, Value: {:.2}", operation_id, spot_qty_gross, fut_qty_net, final_spot_value_gross );
-                 tokio::time::sleep(Duration::from_millis(500)).await;
-                 let final_net_spot_balance = match exchange_task.get_balance(&symbol_for_task_body).await { Ok(b) => b.free, Err(_) => spot_qty_gross };
-                 let success_text = format!(
-                      "✅ Хеджирование ID:{} ~{:.2} {} ({}) при V={:.1}% завершено:\n\n🟢 Спот куплено (брутто): {:.8}\nspot_balance_check {:.8}\n🔴 Фьюч продано (нетто): {:.8}",
-                     operation_id, final_spot_value_gross, cfg_task.quote_currency, symbol_for_task_body,
-                     volatility_percent, spot_qty_gross, final_net_spot_balance, fut_qty_net,
+            Ok(outcome) => {
+                 info!(
+                     "op_id:{}: Hedge OK. Spot Gross: {}, Fut Net: {}, Value: {:.2}, Avg Spot Price: {:.8} (fallback: {})",
+                     operation_id, outcome.spot_filled, outcome.fut_filled, outcome.spot_value, outcome.avg_spot_price, outcome.used_fallback
                  );
+                 tokio::time::sleep(Duration::from_millis(500)).await;
+                 let final_net_spot_balance = match exchange_task.get_balance(&symbol_for_task_body).await { Ok(b) => b.free, Err(_) => outcome.spot_filled };
+                 let mut success_text = if futures_only {
+                     format!(
+                         "✅ Хеджирование {} (только фьючерс) ~{:.2} {} ({}) завершено:\n\n🔴 Фьюч продано (нетто): {}\nСпот не покупался — шорт закрывает спот, хранящийся вне бота.",
+                         op_label, outcome.spot_value, cfg_task.quote_currency, symbol_for_task_body,
+                         format_qty(outcome.fut_filled, fut_display_decimals),
+                     )
+                 } else { format!(
+                      "✅ Хеджирование {} ~{:.2} {} ({}) при V={:.1}% завершено:\n\n🟢 Спот куплено (брутто): {}\nspot_balance_check {}\n🔴 Фьюч продано (нетто): {}",
+                     op_label, outcome.spot_value, cfg_task.quote_currency, symbol_for_task_body,
+                     volatility_percent,
+                     format_qty(outcome.spot_filled, spot_display_decimals),
+                     format_qty(final_net_spot_balance, spot_display_decimals),
+                     format_qty(outcome.fut_filled, fut_display_decimals),
+                 ) };
+                 if outcome.spot_overfill > 0.0 {
+                     let spot_target = outcome.spot_filled - outcome.spot_overfill;
+                     let overfill_pct = if spot_target > 0.0 { outcome.spot_overfill / spot_target * 100.0 } else { 0.0 };
+                     let marker = if overfill_pct > cfg_task.spot_overfill_warn_pct { "⚠️" } else { "ℹ️" };
+                     success_text.push_str(&format!(
+                         "\n{} Спот куплен сверх цели на {} ({:.2}%), фьючерс рассчитан на весь купленный объем",
+                         marker, format_qty(outcome.spot_overfill, spot_display_decimals), overfill_pct,
+                     ));
+                 }
+                 success_text.push_str(&exposure_report_text(
+                     &*exchange_task, exposure_before, operation_id, &symbol_for_task_body, &futures_symbol_for_task,
+                     spot_display_decimals, fut_display_decimals,
+                 ).await);
                  // --- ИСПРАВЛЕНО: Используем bot_message_id ---
-                 let _ = bot.edit_message_text(chat_id, bot_message_id, success_text).reply_markup(navigation::make_main_menu_keyboard()).await;
+                 let _ = bot.edit_message_text(chat_id, bot_message_id, success_text).reply_markup(make_completed_hedge_keyboard(operation_id, &cfg_task)).await;
             }
             Err(e) => {
                  if is_cancelled_by_button { info!("op_id:{}: Hedge task finished after cancellation via button.", operation_id); }
+                 else if let Some(left_active) = e.downcast_ref::<FuturesOrderLeftActive>() {
+                      info!("op_id:{}: Hedge left futures order {} active.", operation_id, left_active.order_id);
+                      let pending_text = format!(
+                          "⏳ Хеджирование {} {}: фьючерсный ордер {} оставлен на бирже по таймауту.\nИсполнено: {} из {}. Бот продолжит следить за ордером (статус PendingFutures).",
+                          op_label, symbol_for_task_body, left_active.order_id,
+                          format_qty(left_active.base_filled_qty + left_active.order_filled_qty, fut_display_decimals),
+                          format_qty(left_active.target_qty, fut_display_decimals),
+                      );
+                      let _ = bot.edit_message_text(chat_id, bot_message_id, pending_text)
+                                 .reply_markup(navigation::make_main_menu_keyboard())
+                                 .await;
+                 }
+                 else if let Some(guard_hit) = e.downcast_ref::<PriceGuardHit>() {
+                      // Спот не куплен вовсе (при частичной покупке приходит SpotOnlyOrphan)
+                      info!("op_id:{}: Hedge halted by price guard: {}", operation_id, guard_hit);
+                      let halted_text = format!(
+                          "⛔ Хеджирование {} {} остановлено ограничителем цены {}: для исполнения нужна цена {}.\nКуплено {} из {}, фьючерс не открывался.",
+                          op_label, symbol_for_task_body, guard_hit.guard_price, guard_hit.required_price,
+                          format_qty(guard_hit.filled_qty, spot_display_decimals),
+                          format_qty(guard_hit.target_qty, spot_display_decimals),
+                      );
+                      let _ = bot.edit_message_text(chat_id, bot_message_id, halted_text)
+                                 .reply_markup(navigation::make_main_menu_keyboard())
+                                 .await;
+                 }
+                 else if let Some(orphan) = e.downcast_ref::<SpotOnlyOrphan>() {
+                      error!("op_id:{}: Hedge left spot unhedged: {}", operation_id, orphan);
+                      failure_cooldowns.record_failure(&symbol_for_task_body).await;
+                      let warning_text = format_spot_orphan_warning(&op_label, &symbol_for_task_body, orphan, spot_display_decimals);
+                      alerts::send_alert(&bot, &cfg_task, chat_id, &warning_text).await;
+                      let _ = bot.edit_message_text(chat_id, bot_message_id, warning_text)
+                                 .reply_markup(make_spot_orphan_keyboard(operation_id))
+                                 .await;
+                 }
                  else {
                       error!("op_id:{}: Hedge execution failed: {}", operation_id, e);
-                      let error_text = format!("❌ Ошибка хеджирования ID:{}: {}", operation_id, e);
+                      failure_cooldowns.record_failure(&symbol_for_task_body).await;
+                      let error_text = format!("❌ Ошибка хеджирования {}: {}", op_label, describe_error(&e));
+                      alerts::send_alert(&bot, &cfg_task, chat_id, &error_text).await;
                        // --- ИСПРАВЛЕНО: Используем bot_message_id ---
                        let _ = bot.edit_message_text(chat_id, bot_message_id, error_text)
                                   .reply_markup(navigation::make_main_menu_keyboard())
@@ -176,11 +292,14 @@ E: Exchange + Clone + Send + Sync + 'static,
     });
 
     let info = RunningOperationInfo {
-        handle: task.abort_handle(), operation_id, operation_type: OperationType::Hedge,
+        handle: task.abort_handle(), operation_id, op_ref: Some(op_ref), operation_type: OperationType::Hedge,
         symbol: symbol_for_info, bot_message_id: bot_message_id.0, // Используем ID из переменной
         total_filled_spot_qty: total_filled_qty_storage,
+        active_order: active_order_storage,
+        stage: stage_storage,
     };
-    running_operations.lock().await.insert((chat_id, operation_id), info);
+    ops_guard.insert((chat_id, operation_id), info);
+    drop(ops_guard);
     info!("op_id:{}: Stored running hedge info.", operation_id);
 }
 
@@ -192,6 +311,8 @@ pub(super) async fn spawn_ws_hedge_task<E>(
     cfg: Arc<Config>,
     db: Arc<Db>,
     running_operations: RunningOperations,
+    failure_cooldowns: FailureCooldowns,
+    edit_clocks: ChatEditClocks,
     chat_id: ChatId,
     request: HedgeRequest,
     waiting_message: MaybeInaccessibleMessage, // Keep taking ownership here
@@ -219,12 +340,18 @@ where
     info!("op_chat_id:{}: Preparing to spawn WS Hedge Task for {}...", chat_id, symbol);
 
     let operation_id_result = insert_hedge_operation(
-        db.as_ref(), chat_id.0, &symbol, &cfg.quote_currency, initial_sum,
-        request.volatility, 0.0, 0.0, // В WS стратегии начальные target_qty могут быть 0, они определятся позже
+        db.as_ref(), chat_id.0, &symbol, &cfg.quote_currency, cfg.environment(), initial_sum,
+        request.volatility, 0.0, 0.0, false, // В WS стратегии начальные target_qty могут быть 0, они определятся позже
     ).await;
 
-    let operation_id = match operation_id_result {
-        Ok(id) => { info!("op_id:{}: Created DB record for WS hedge operation.", id); id }
+    let (operation_id, op_ref) = match operation_id_result {
+        Ok((id, op_ref, true)) => { info!("op_id:{}: Created DB record for WS hedge operation {}.", id, op_ref); (id, op_ref) }
+        Ok((id, op_ref, false)) => {
+            // Повторное подтверждение (двойное нажатие): операцию ведет первая задача
+            warn!("op_id:{}: Duplicate WS hedge request for {} ignored, operation already exists.", id, symbol);
+            report_duplicate_hedge(&bot, chat_id, id, &op_ref, &symbol).await;
+            return Ok(());
+        }
         Err(e) => {
             error!("op_id:?: Failed insert WS hedge op to DB: {}", e);
             // --- ИСПРАВЛЕНО: Используем bot_message_id ---
@@ -234,45 +361,28 @@ where
         }
     };
 
-    // --- ИСПРАВЛЕНО: Используем bot_message_id ---
+    // Подключение и инициализацию WS-задачи выполняет диспетчер run_websocket_operation внутри фоновой задачи
     let _ = bot.edit_message_text(chat_id, bot_message_id, format!("⏳ Подключение WebSocket для {}...", symbol)).await;
-    let spot_symbol_ws = format!("{}{}", symbol, cfg.quote_currency);
     let futures_symbol_ws = format!("{}{}", symbol, cfg.quote_currency);
 
-    let subscriptions = vec![
-        SubscriptionType::Order,
-        SubscriptionType::Orderbook { symbol: spot_symbol_ws.clone(), depth: cfg.ws_order_book_depth },
-        SubscriptionType::Orderbook { symbol: futures_symbol_ws.clone(), depth: cfg.ws_order_book_depth },
-    ];
-
-    let ws_receiver_result = bybit_ws::connect_and_subscribe((*cfg).clone(), subscriptions).await;
-    let ws_receiver = match ws_receiver_result {
-        Ok(receiver) => {
-            info!("op_id:{}: WebSocket connected and subscribed successfully.", operation_id);
-            // --- ИСПРАВЛЕНО: Используем bot_message_id ---
-            let _ = bot.edit_message_text(chat_id, bot_message_id, format!("⏳ Инициализация стратегии WS для {}...", symbol)).await;
-            receiver
-        },
-        Err(e) => {
-            error!("op_id:{}: Failed to connect WebSocket: {}", operation_id, e);
-            let error_text = format!("❌ Ошибка подключения WebSocket: {}", e);
-             // --- ИСПРАВЛЕНО: Используем bot_message_id ---
-             let _ = bot.edit_message_text(chat_id, bot_message_id, error_text.clone())
-                      .reply_markup(navigation::make_main_menu_keyboard()).await;
-             // Обновляем статус в БД на Failed
-             let _ = crate::storage::update_hedge_final_status(db.as_ref(), operation_id, "Failed", None, 0.0, Some(&error_text)).await;
-             return Err(e);
-        }
-    };
-
     let bot_clone_for_callback = bot.clone();
     let cfg_clone_for_callback = cfg.clone();
     let symbol_for_callback = symbol.clone();
 
+    let progress_throttle: EditThrottle<(String, InlineKeyboardMarkup)> = edit_clocks.throttle(chat_id, Duration::from_millis(cfg.min_edit_interval_ms));
+    let progress_throttle_for_callback = progress_throttle.clone();
+
     // --- ИСПРАВЛЕНО: bot_message_id захватывается по значению (Copy) ---
+    let op_label = operation_label(Some(&op_ref), operation_id);
+    let op_label_for_callback = op_label.clone();
     let progress_callback: HedgeProgressCallback = Box::new(move |update: HedgeProgressUpdate| {
         let bot_cb = bot_clone_for_callback.clone();
+        let op_label_cb = op_label_for_callback.clone();
+        let throttle_cb = progress_throttle_for_callback.clone();
         let _qc = cfg_clone_for_callback.quote_currency.clone();
+        let edit_max_retries = cfg_clone_for_callback.progress_edit_max_retries;
+        let edit_min_delay = Duration::from_millis(cfg_clone_for_callback.min_edit_interval_ms);
+        let qty_decimals = cfg_clone_for_callback.display_max_decimals;
         let symbol_cb = symbol_for_callback.clone();
         let msg_id_cb = bot_message_id; // Используем ID, захваченный ранее
         let chat_id_cb = chat_id;
@@ -300,10 +410,10 @@ where
                     let empty_blocks = progress_bar_len - filled_blocks;
                     let progress_bar = format!("[{}{}]", "█".repeat(filled_blocks), "░".repeat(empty_blocks));
                     // Текст показывает детали текущего ордера и общий прогресс
-                    format!( "⏳ Хедж WS (Спот) ID:{} {} ({})\nРын.цена: {:.2}\nТек. ордер ПОКУПКА: {:.2} {}\nИсполнено (всего): {:.6}/{:.6} ({:.1}%)",
-                             operation_id_cb, progress_bar, symbol, current_spot_price_cb,
+                    format!( "⏳ Хедж WS (Спот) {} {} ({})\nРын.цена: {:.2}\nТек. ордер ПОКУПКА: {:.2} {}\nИсполнено (всего): {}/{} ({:.1}%)",
+                             op_label_cb, progress_bar, symbol, current_spot_price_cb,
                              new_limit_price_cb, status_text,
-                             cumulative_filled_qty_cb, overall_spot_target, filled_percent)
+                             format_qty(cumulative_filled_qty_cb, qty_decimals), format_qty(overall_spot_target, qty_decimals), filled_percent)
                     // --- КОНЕЦ ИСПРАВЛЕНИЯ ---
                 }
                 HedgeStage::Futures => {
@@ -313,10 +423,10 @@ where
                     let empty_blocks = progress_bar_len - filled_blocks;
                     let progress_bar = format!("[{}{}]", "█".repeat(filled_blocks), "░".repeat(empty_blocks));
                     // Текст показывает детали текущего ордера и общий прогресс
-                    format!( "⏳ Хедж WS (Фьюч) ID:{} {} ({})\nСпот цена: {:.2}\nТек. ордер ПРОДАЖА: {:.2} {}\nИсполнено (всего): {:.6}/{:.6} ({:.1}%)",
-                             operation_id_cb, progress_bar, symbol, current_spot_price_cb,
+                    format!( "⏳ Хедж WS (Фьюч) {} {} ({})\nСпот цена: {:.2}\nТек. ордер ПРОДАЖА: {:.2} {}\nИсполнено (всего): {}/{} ({:.1}%)",
+                             op_label_cb, progress_bar, symbol, current_spot_price_cb,
                              new_limit_price_cb, status_text,
-                             cumulative_filled_qty_cb, overall_fut_target, filled_percent)
+                             format_qty(cumulative_filled_qty_cb, qty_decimals), format_qty(overall_fut_target, qty_decimals), filled_percent)
                     // --- КОНЕЦ ИСПРАВЛЕНИЯ ---
                 }
             };
@@ -324,86 +434,77 @@ where
             let cancel_button = InlineKeyboardButton::callback("❌ Отменить эту операцию", cancel_callback_data);
             let kb = InlineKeyboardMarkup::new(vec![vec![cancel_button]]);
 
-            // --- ИСПРАВЛЕНО: Используем msg_id_cb ---
-            if let Err(e) = bot_cb.edit_message_text(chat_id_cb, msg_id_cb, text).reply_markup(kb).await {
-                if !e.to_string().contains("message is not modified") {
-                     warn!("op_id:{}: WS Progress callback failed: {}", operation_id_cb, e);
+            // Правки схлопываются троттлером (не чаще min_edit_interval_ms)
+            throttle_cb.submit((text, kb), move |(text, kb)| async move {
+                if let Err(e) = edit_message_text_safe(&bot_cb, chat_id_cb, msg_id_cb, text, kb, edit_max_retries, edit_min_delay).await {
+                    warn!("op_id:{}: WS Progress callback failed: {}", operation_id_cb, e);
                 }
-            }
+            }).await;
             Ok(())
         }.boxed()
      });
 
-    let hedge_task_result = HedgerWsHedgeTask::new(
-        operation_id,
-        request,
-        cfg.clone(),
-        db.clone(),
-        exchange_rest.clone(),
-        progress_callback,
-        ws_receiver,
-    ).await;
-
-    let mut hedge_task: HedgerWsHedgeTask = match hedge_task_result {
-        Ok(task) => {
-            info!("op_id:{}: HedgerWsHedgeTask initialized successfully.", operation_id);
-             // --- ИСПРАВЛЕНО: Используем bot_message_id ---
-             let _ = bot.edit_message_text(chat_id, bot_message_id, format!("⏳ Запуск WS стратегии для {} (ID: {})...", symbol, operation_id)).await;
-            task
-        },
-        Err(e) => {
-            error!("op_id:{}: Failed to initialize HedgerWsHedgeTask: {}", operation_id, e);
-            let error_text = format!("❌ Ошибка инициализации WS стратегии: {}", e);
-            // --- ИСПРАВЛЕНО: Используем bot_message_id ---
-            let _ = bot.edit_message_text(chat_id, bot_message_id, error_text.clone())
-                     .reply_markup(navigation::make_main_menu_keyboard()).await;
-             // Обновляем статус в БД на Failed
-             let _ = crate::storage::update_hedge_final_status(db.as_ref(), operation_id, "Failed", None, 0.0, Some(&error_text)).await;
-            return Err(e);
-        }
-    };
-
     let bot_clone_for_spawn = bot.clone();
     let running_operations_clone = running_operations.clone();
     let symbol_clone_for_spawn = symbol.clone();
+    let cfg_for_spawn = cfg.clone();
+    let db_for_spawn = db.clone();
+    let op_label_for_spawn = op_label.clone();
+
+    // Держим блокировку до вставки записи, чтобы быстро завершившаяся задача не оставила ее в карте
+    let mut ops_guard = running_operations.lock().await;
+    let cleanup_guard = RunningOperationGuard::new(running_operations_clone, chat_id, operation_id);
 
     // --- ИСПРАВЛЕНО: Не передаем waiting_message в spawn ---
     let task_handle = tokio::spawn(async move {
         info!("op_id:{}: Spawning WS hedge task execution...", operation_id);
-        let run_result = hedge_task.run().await;
+        let exposure_before = exposure_before_hedge(&*exchange_rest, &cfg_for_spawn, false, operation_id, &symbol_clone_for_spawn, &futures_symbol_ws).await;
+        let operation = WsOperation::Hedge { operation_id, request };
+        let run_result = run_websocket_operation(operation, cfg_for_spawn.clone(), db_for_spawn, exchange_rest.clone(), progress_callback).await;
+        progress_throttle.close().await; // Отложенная правка прогресса не должна затереть итоговое сообщение
 
         // Удаляем информацию об операции из running_operations ПОСЛЕ завершения задачи
-        // (кроме случая отмены через кнопку, где она удаляется раньше)
-        let mut ops_guard = running_operations_clone.lock().await;
-        // Проверяем, не была ли она уже удалена (например, при отмене через кнопку)
-        if ops_guard.contains_key(&(chat_id, operation_id)) {
-            ops_guard.remove(&(chat_id, operation_id));
-            info!("op_id:{}: Removed running WS operation info after task completion.", operation_id);
-        } else {
-            info!("op_id:{}: Running WS operation info already removed (likely due to cancellation).", operation_id);
-        }
-        drop(ops_guard);
+        // (при отмене через кнопку она уже удалена — guard это учитывает)
+        drop(cleanup_guard);
 
         match run_result {
             Ok(_) => {
                 info!("op_id:{}: WS Hedge task completed successfully.", operation_id);
                 // Финальный статус уже должен быть обновлен в БД внутри hedge_task.run()
-                let final_text = format!("✅ WS Хедж ID:{} для {} завершен.", operation_id, symbol_clone_for_spawn);
+                let mut final_text = format!("✅ WS Хедж {} для {} завершен.", op_label_for_spawn, symbol_clone_for_spawn);
+                let qty_decimals = display_decimals(None, cfg_for_spawn.display_max_decimals);
+                final_text.push_str(&exposure_report_text(
+                    &*exchange_rest, exposure_before, operation_id, &symbol_clone_for_spawn, &futures_symbol_ws,
+                    qty_decimals, qty_decimals,
+                ).await);
                 // --- ИСПРАВЛЕНО: Используем bot_message_id ---
                 if let Err(e) = bot_clone_for_spawn.edit_message_text(chat_id, bot_message_id, final_text)
-                         .reply_markup(navigation::make_main_menu_keyboard())
+                         .reply_markup(make_completed_hedge_keyboard(operation_id, &cfg_for_spawn))
                          .await {
                     warn!("op_id:{}: Failed to edit final success message: {}", operation_id, e);
                 }
             }
-            Err(e) => {
-                // Финальный статус (Failed или Cancelled) уже должен быть обновлен в БД внутри hedge_task.run()
-                if !e.to_string().contains("cancelled by user") {
-                    error!("op_id:{}: WS Hedge task failed: {}", operation_id, e);
-                } else {
-                    info!("op_id:{}: WS Hedge task cancelled by user.", operation_id);
+            Err(e) if e.downcast_ref::<SpotOnlyOrphan>().is_some() => {
+                // Статус SpotOnlyOrphan записан внутри hedge_task.run(): как и в последовательной стратегии — предупреждение и кнопка
+                let orphan = e.downcast_ref::<SpotOnlyOrphan>().expect("checked above");
+                error!("op_id:{}: WS hedge left spot unhedged: {}", operation_id, orphan);
+                failure_cooldowns.record_failure(&symbol_clone_for_spawn).await;
+                let qty_decimals = display_decimals(None, cfg_for_spawn.display_max_decimals);
+                let warning_text = format_spot_orphan_warning(&op_label_for_spawn, &symbol_clone_for_spawn, orphan, qty_decimals);
+                alerts::send_alert(&bot_clone_for_spawn, &cfg_for_spawn, chat_id, &warning_text).await;
+                if let Err(edit_err) = bot_clone_for_spawn.edit_message_text(chat_id, bot_message_id, warning_text)
+                         .reply_markup(make_spot_orphan_keyboard(operation_id))
+                         .await {
+                    warn!("op_id:{}: Failed to edit spot orphan warning: {}", operation_id, edit_err);
                 }
-                 let final_text = format!("❌ Ошибка WS Хедж ID:{}: {}", operation_id, e);
+            }
+            Err(e) => {
+                // Финальный статус Failed уже должен быть обновлен в БД внутри hedge_task.run().
+                // Отмена кнопкой прерывает задачу (abort) и сюда не доходит: любая ошибка здесь — сбой
+                error!("op_id:{}: WS Hedge task failed: {}", operation_id, e);
+                failure_cooldowns.record_failure(&symbol_clone_for_spawn).await;
+                 let final_text = format!("❌ Ошибка WS Хедж {}: {}", op_label_for_spawn, describe_error(&e));
+                 alerts::send_alert(&bot_clone_for_spawn, &cfg_for_spawn, chat_id, &final_text).await;
                  // --- ИСПРАВЛЕНО: Используем bot_message_id ---
                  if let Err(edit_err) = bot_clone_for_spawn.edit_message_text(chat_id, bot_message_id, final_text)
                           .reply_markup(navigation::make_main_menu_keyboard())
@@ -417,16 +518,55 @@ where
     let info = RunningOperationInfo {
         handle: task_handle.abort_handle(),
         operation_id,
+        op_ref: Some(op_ref),
         operation_type: OperationType::Hedge,
         symbol: symbol.clone(),
         bot_message_id: bot_message_id.0, // Используем ID из переменной
         // Для WS-задачи total_filled_spot_qty пока не отслеживается таким образом,
         // т.к. прогресс идет через колбэк с другими данными. Ставим заглушку.
         total_filled_spot_qty: Arc::new(TokioMutex::new(0.0)),
+        active_order: Arc::new(TokioMutex::new(None)), // WS-задача управляет ордерами сама
+        stage: Arc::new(TokioMutex::new(HedgeStage::Spot)), // Этап WS-задачи не отслеживается: только полная отмена
     };
-    running_operations.lock().await.insert((chat_id, operation_id), info);
+    ops_guard.insert((chat_id, operation_id), info);
+    drop(ops_guard);
     info!("op_id:{}: Stored running WS hedge info.", operation_id);
 
     Ok(())
 }
 
+/// Экспозиция до хеджа (при verify_hedge): сверяются именно изменения, а не ранее открытые позиции.
+/// В режиме только фьючерса спот не меняется — сверять ноги нечего
+async fn exposure_before_hedge<E: Exchange>(
+    exchange: &E, cfg: &Config, futures_only: bool, operation_id: i64, base_symbol: &str, futures_symbol: &str,
+) -> Option<ExposureSnapshot> {
+    if !cfg.verify_hedge || futures_only {
+        return None;
+    }
+    match snapshot_exposure(exchange, base_symbol, futures_symbol).await {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => { warn!("op_id:{}: Hedge verification skipped, pre-hedge snapshot failed: {}", operation_id, e); None }
+    }
+}
+
+/// Строки итогового сообщения о чистой дельте хеджа (пусто, если проверка выключена или не удалась)
+async fn exposure_report_text<E: Exchange>(
+    exchange: &E, before: Option<ExposureSnapshot>, operation_id: i64, base_symbol: &str, futures_symbol: &str,
+    spot_decimals: u32, fut_decimals: u32,
+) -> String {
+    let Some(before) = before else { return String::new() };
+    let after = match snapshot_exposure(exchange, base_symbol, futures_symbol).await {
+        Ok(after) => after,
+        Err(e) => { warn!("op_id:{}: Hedge verification failed: {}", operation_id, e); return String::new(); }
+    };
+    let report = compare_exposure(before, after, HEDGE_DELTA_TOLERANCE_RATIO);
+    let mut text = format!("\n⚖️ Чистая дельта: {} {}", format_qty(report.net_delta, spot_decimals), base_symbol);
+    if !report.balanced {
+        warn!("op_id:{}: Hedge is unbalanced: spot delta {:.8}, futures delta {:.8}, net {:.8}", operation_id, report.spot_delta, report.futures_delta, report.net_delta);
+        text.push_str(&format!(
+            "\n⚠️ Хедж несбалансирован: спот {}, фьючерс {}. Проверьте позиции",
+            format_qty(report.spot_delta, spot_decimals), format_qty(report.futures_delta, fut_decimals),
+        ));
+    }
+    text
+}
@@ -0,0 +1,85 @@
+// src/notifier/kill_switch.rs
+
+//! Аварийная остановка торговли: новые хеджи/расхеджи отклоняются, пока флаг включен (/halt)
+//! или существует файл-сигнал kill_switch_file.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Ответ пользователю при попытке запустить операцию во время остановки
+pub const TRADING_HALTED_TEXT: &str = "⛔ Торговля остановлена администратором (trading halted by admin). Новые операции не запускаются.";
+
+/// Флаг остановки торговли (общий для всех обработчиков) и необязательный файл-сигнал
+#[derive(Debug, Clone, Default)]
+pub struct TradingHalt {
+    halted: Arc<AtomicBool>,
+    sentinel_file: Option<PathBuf>,
+}
+
+impl TradingHalt {
+    pub fn new(sentinel_file: Option<&str>) -> Self {
+        Self {
+            halted: Arc::new(AtomicBool::new(false)),
+            sentinel_file: sentinel_file.map(PathBuf::from),
+        }
+    }
+
+    /// Включить остановку; true, если она была выключена
+    pub fn engage(&self) -> bool {
+        !self.halted.swap(true, Ordering::SeqCst)
+    }
+
+    /// Снять остановку командой; true, если она была включена (файл-сигнал нужно удалить вручную)
+    pub fn release(&self) -> bool {
+        self.halted.swap(false, Ordering::SeqCst)
+    }
+
+    /// Существует ли файл-сигнал
+    pub fn sentinel_present(&self) -> bool {
+        self.sentinel_file.as_ref().is_some_and(|path| path.exists())
+    }
+
+    /// Остановлена ли торговля (командой или файлом-сигналом)
+    pub fn is_halted(&self) -> bool {
+        self.halted.load(Ordering::SeqCst) || self.sentinel_present()
+    }
+
+    /// Проверка перед запуском новой операции: Err с текстом для пользователя, если торговля остановлена
+    pub fn ensure_trading_allowed(&self) -> Result<(), &'static str> {
+        if self.is_halted() { Err(TRADING_HALTED_TEXT) } else { Ok(()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn engaged_switch_blocks_new_operations() {
+        let halt = TradingHalt::new(None);
+        assert_eq!(halt.ensure_trading_allowed(), Ok(()));
+
+        assert!(halt.engage());
+        assert!(!halt.engage(), "second engage is a no-op");
+        assert_eq!(halt.clone().ensure_trading_allowed(), Err(TRADING_HALTED_TEXT));
+
+        assert!(halt.release());
+        assert_eq!(halt.ensure_trading_allowed(), Ok(()));
+    }
+
+    #[test]
+    fn sentinel_file_halts_trading_while_present() {
+        let path = std::env::temp_dir().join(format!("hedger-kill-switch-{}", std::process::id()));
+        let halt = TradingHalt::new(path.to_str());
+        assert!(!halt.is_halted());
+
+        std::fs::write(&path, b"").expect("create sentinel");
+        assert!(halt.is_halted());
+        assert!(!halt.release(), "command release does not remove the file");
+        assert!(halt.is_halted());
+
+        std::fs::remove_file(&path).expect("remove sentinel");
+        assert!(!halt.is_halted());
+    }
+}
@@ -3,7 +3,11 @@
 use crate::notifier::{StateStorage, UserState, callback_data}; // Command здесь нужен для BotCommands
 use crate::config::Config;
 use crate::exchange::Exchange;
-use crate::storage::Db;
+use crate::exchange::bybit::{FUNDING_INTERVALS_PER_DAY, MAX_FUNDING_HISTORY_ENTRIES};
+use crate::exchange::types::FundingRateStats;
+use crate::storage::{Db, get_hedge_operations_in_range, get_operation_stats, OperationStats};
+use crate::notifier::utils::{delete_user_message, describe_error, operation_label};
+use chrono::{NaiveDate, TimeZone, Utc};
 use std::sync::Arc;
 use teloxide::prelude::*;
 use teloxide::types::{
@@ -11,6 +15,47 @@ use teloxide::types::{
 use tracing::{info, warn, error};
 
 
+// --- Вспомогательные функции ---
+
+/// Формирует текст статуса: соединение с биржей + маржинальное состояние аккаунта
+async fn build_status_text<E>(exchange: &E, cfg: &Config) -> String
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let mut exchange_clone = exchange.clone();
+    let mut status_text = match exchange_clone.check_connection().await {
+         Ok(_) => "✅ Бот запущен и успешно подключен к бирже.".to_string(),
+         Err(e) => return format!("⚠️ Бот запущен, но есть проблема с подключением к бирже: {}", describe_error(&e)),
+    };
+
+    match exchange.get_account_margin().await {
+        Ok(margin) => {
+            status_text.push_str(&format!(
+                "\n\n🛡 Маржа аккаунта:\nMM rate: {:.2}%\nIM rate: {:.2}%\nEquity: {:.2}\nПоддерж. маржа: {:.2}",
+                margin.account_mm_rate * 100.0,
+                margin.account_im_rate * 100.0,
+                margin.total_equity,
+                margin.total_maintenance_margin,
+            ));
+            if margin.account_mm_rate >= cfg.margin_ratio_warning_threshold {
+                warn!(
+                    "Account MM rate {:.4} exceeds warning threshold {:.4}",
+                    margin.account_mm_rate, cfg.margin_ratio_warning_threshold
+                );
+                status_text.push_str(&format!(
+                    "\n\n🚨 ВНИМАНИЕ: риск ликвидации! MM rate превышает порог {:.0}% (ликвидация при 100%).",
+                    cfg.margin_ratio_warning_threshold * 100.0
+                ));
+            }
+        }
+        Err(e) => {
+            warn!("Failed to fetch account margin info: {}", e);
+            status_text.push_str(&format!("\n\n⚠️ Не удалось получить состояние маржи: {}", describe_error(&e)));
+        }
+    }
+    status_text
+}
+
 // --- Обработчики Команд ---
 
 /// Обработчик команды /status
@@ -19,7 +64,7 @@ pub async fn handle_status_command<E>(
     msg: Message,
     exchange: Arc<E>,
     _state_storage: StateStorage, // Тип StateStorage уже Arc<TokioRwLock<...>>
-    _cfg: Arc<Config>,
+    cfg: Arc<Config>,
     _db: Arc<Db>,
 ) -> anyhow::Result<()>
 where
@@ -29,21 +74,41 @@ where
     info!("Processing /status command for chat_id: {}", chat_id);
     let indicator_msg = bot.send_message(chat_id, "⏳ Проверка соединения с биржей...").await?;
 
-    let mut exchange_clone = (*exchange).clone();
-    let status_text = match exchange_clone.check_connection().await {
-         Ok(_) => "✅ Бот запущен и успешно подключен к бирже.".to_string(),
-         Err(e) => format!("⚠️ Бот запущен, но есть проблема с подключением к бирже: {}", e),
-    };
+    let status_text = build_status_text(exchange.as_ref(), &cfg).await;
 
     bot.edit_message_text(chat_id, indicator_msg.id, status_text).await?;
 
-    if let Err(e) = bot.delete_message(chat_id, msg.id).await {
-        warn!("Failed to delete /status command message: {}", e);
-    }
+    delete_user_message(&bot, &cfg, chat_id, msg.id, "/status command message").await;
 
     Ok(())
 }
 
+/// Максимальный период /funding: биржа отдает не более 200 начислений (3 в сутки)
+const MAX_FUNDING_DAYS: u16 = (MAX_FUNDING_HISTORY_ENTRIES / FUNDING_INTERVALS_PER_DAY) as u16;
+
+/// Разбор аргумента дней для /funding; без аргумента — default_days (в пределах 1..=MAX_FUNDING_DAYS)
+fn parse_funding_days(arg: Option<&str>, default_days: u16) -> Result<u16, String> {
+    let Some(arg) = arg else {
+        return Ok(default_days.clamp(1, MAX_FUNDING_DAYS));
+    };
+    match arg.parse::<u32>() {
+        Ok(days) if (1..=u32::from(MAX_FUNDING_DAYS)).contains(&days) => Ok(days as u16),
+        Ok(_) => Err(format!("⚠️ Количество дней должно быть от 1 до {}, получено: {}.", MAX_FUNDING_DAYS, arg)),
+        Err(_) => Err(format!("⚠️ Неверное количество дней: '{}'. Укажите целое число от 1 до {}.", arg, MAX_FUNDING_DAYS)),
+    }
+}
+
+/// Итог /funding: период и число усредненных начислений
+fn format_funding_rate(symbol: &str, days: u16, stats: &FundingRateStats) -> String {
+    if stats.intervals == 0 {
+        return format!("ℹ️ Нет истории финансирования {} за {} дн.", symbol, days);
+    }
+    format!(
+        "📈 Средняя ставка финансирования {} за {} дн.: {:.4}%\nУсреднено начислений: {}",
+        symbol, days, stats.avg_rate * 100.0, stats.intervals,
+    )
+}
+
 /// Обработчик команды /funding SYMBOL [days]
 pub async fn handle_funding_command<E>(
     bot: Bot,
@@ -51,7 +116,7 @@ pub async fn handle_funding_command<E>(
     args: String,
     exchange: Arc<E>,
     _state_storage: StateStorage, // Тип StateStorage уже Arc<TokioRwLock<...>>
-    _cfg: Arc<Config>,
+    cfg: Arc<Config>,
     _db: Arc<Db>,
 ) -> anyhow::Result<()>
 where
@@ -62,29 +127,26 @@ where
 
     if parts.is_empty() {
          bot.send_message(chat_id, "Использование: /funding <SYMBOL> [days]\nПример: /funding BTC или /funding BTC 7").await?;
-         if let Err(e) = bot.delete_message(chat_id, msg.id).await { warn!("Failed to delete invalid /funding command message: {}", e); }
+         delete_user_message(&bot, &cfg, chat_id, msg.id, "invalid /funding command message").await;
          return Ok(());
     }
 
     let symbol = parts[0].to_uppercase();
-    let days_u32 = parts.get(1).and_then(|s| s.parse::<u32>().ok()).unwrap_or(30);
-
-    if days_u32 == 0 {
-        bot.send_message(chat_id, "⚠️ Количество дней должно быть больше нуля.").await?;
-         if let Err(e) = bot.delete_message(chat_id, msg.id).await { warn!("Failed to delete invalid /funding command message: {}", e); }
-        return Ok(());
-    }
-    let days_u16 = days_u32.min(66) as u16;
-    if (days_u16 as u32) != days_u32 && parts.get(1).is_some() {
-         bot.send_message(chat_id, format!("ℹ️ Количество дней для фандинга ограничено до {}.", days_u16)).await?;
-    }
+    let days_u16 = match parse_funding_days(parts.get(1).copied(), cfg.default_funding_days) {
+        Ok(days) => days,
+        Err(err_text) => {
+            bot.send_message(chat_id, format!("{}\nИспользование: /funding <SYMBOL> [days]", err_text)).await?;
+            delete_user_message(&bot, &cfg, chat_id, msg.id, "invalid /funding command message").await;
+            return Ok(());
+        }
+    };
 
     info!("Processing /funding {} ({} days) command for chat_id: {}", symbol, days_u16, chat_id);
     let indicator_msg = bot.send_message(chat_id, format!("⏳ Загрузка ставки финансирования для {} ({} дн.)...", symbol, days_u16)).await?;
 
     match exchange.get_funding_rate(&symbol, days_u16).await {
-        Ok(rate) => {
-            let text = format!("📈 Средняя ставка финансирования {} за ~{} дн.: {:.4}%", symbol, days_u16, rate * 100.0);
+        Ok(stats) => {
+            let text = format_funding_rate(&symbol, days_u16, &stats);
             bot.edit_message_text(chat_id, indicator_msg.id, text).await?;
         }
         Err(e) => {
@@ -94,13 +156,160 @@ where
         }
     }
 
-     if let Err(e) = bot.delete_message(chat_id, msg.id).await {
-         warn!("Failed to delete /funding command message: {}", e);
-     }
+     delete_user_message(&bot, &cfg, chat_id, msg.id, "/funding command message").await;
+
+    Ok(())
+}
+
+
+/// Период истории по умолчанию (дней), если даты не указаны
+const HISTORY_DEFAULT_DAYS: i64 = 30;
+/// Максимум операций в одном сообщении /history
+const HISTORY_MAX_LINES: usize = 50;
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Разбор диапазона дат "/history [с ГГГГ-ММ-ДД] [по ГГГГ-ММ-ДД]" в [from_ts, to_ts).
+/// Дата "по" включается целиком; без дат — последние HISTORY_DEFAULT_DAYS дней.
+pub(crate) fn parse_history_range(args: &str, now_ts: i64) -> Result<(i64, i64), String> {
+    let parts: Vec<&str> = args.split_whitespace().collect();
+    if parts.len() > 2 {
+        return Err("Укажите не более двух дат: /history [ГГГГ-ММ-ДД] [ГГГГ-ММ-ДД]".to_string());
+    }
+
+    let parse_day_start = |value: &str| -> Result<i64, String> {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|dt| dt.and_utc().timestamp())
+            .ok_or_else(|| format!("Неверная дата '{}': ожидается формат ГГГГ-ММ-ДД", value))
+    };
+
+    let from_ts = match parts.first() {
+        Some(value) => parse_day_start(value)?,
+        None => now_ts - HISTORY_DEFAULT_DAYS * SECONDS_PER_DAY,
+    };
+    let to_ts = match parts.get(1) {
+        Some(value) => parse_day_start(value)? + SECONDS_PER_DAY,
+        None => now_ts + 1,
+    };
+
+    if from_ts >= to_ts {
+        return Err("Начальная дата должна быть не позже конечной.".to_string());
+    }
+    Ok((from_ts, to_ts))
+}
+
+fn format_history_date(ts: i64) -> String {
+    match Utc.timestamp_opt(ts, 0) {
+        chrono::LocalResult::Single(dt) => dt.format("%Y-%m-%d").to_string(),
+        _ => ts.to_string(),
+    }
+}
+
+/// Обработчик команды /history [с] [по]
+pub async fn handle_history_command(
+    bot: Bot,
+    msg: Message,
+    args: String,
+    db: Arc<Db>,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat.id;
+    let (from_ts, to_ts) = match parse_history_range(&args, Utc::now().timestamp()) {
+        Ok(range) => range,
+        Err(text) => {
+            bot.send_message(chat_id, format!("⚠️ {}", text)).await?;
+            return Ok(());
+        }
+    };
+
+    info!("Processing /history [{}, {}) for chat_id: {}", from_ts, to_ts, chat_id);
+    let operations = match get_hedge_operations_in_range(db.as_ref(), chat_id.0, Some(from_ts), Some(to_ts)).await {
+        Ok(ops) => ops,
+        Err(e) => {
+            error!("Failed to load history for chat_id: {}: {}", chat_id, e);
+            bot.send_message(chat_id, "❌ Не удалось загрузить историю операций.").await?;
+            return Ok(());
+        }
+    };
+
+    let period = format!("{} — {}", format_history_date(from_ts), format_history_date(to_ts - 1));
+    if operations.is_empty() {
+        bot.send_message(chat_id, format!("📜 Нет операций за период {}.", period)).await?;
+        return Ok(());
+    }
 
+    let mut text = format!("📜 История операций за {} ({} шт.):\n\n", period, operations.len());
+    for op in operations.iter().take(HISTORY_MAX_LINES) {
+        text.push_str(&format!(
+            "{} {} {:.2} {} — {} ({})",
+            operation_label(op.op_ref.as_deref(), op.id),
+            op.base_symbol,
+            op.initial_sum,
+            op.quote_currency,
+            op.status,
+            format_history_date(op.start_timestamp),
+        ));
+        if op.accrued_funding != 0.0 {
+            text.push_str(&format!(", фандинг {:+.2}", op.accrued_funding));
+        }
+        if let Some(environment) = &op.environment {
+            text.push_str(&format!(" [{}]", environment));
+        }
+        text.push('\n');
+    }
+    if operations.len() > HISTORY_MAX_LINES {
+        text.push_str(&format!("\n… показаны последние {} операций, сузьте период.", HISTORY_MAX_LINES));
+    }
+
+    bot.send_message(chat_id, text).await?;
     Ok(())
 }
 
+/// Длительность в виде "1ч 5м", "2м 30с" или "45с"
+pub(super) fn format_duration_secs(secs: f64) -> String {
+    let total = secs.max(0.0).round() as i64;
+    let (hours, minutes, seconds) = (total / 3600, total % 3600 / 60, total % 60);
+    if hours > 0 {
+        format!("{}ч {}м", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}м {}с", minutes, seconds)
+    } else {
+        format!("{}с", seconds)
+    }
+}
+
+/// Текст сводной статистики для /stats
+fn format_operation_stats(stats: &OperationStats, quote_currency: &str) -> String {
+    let avg_duration = stats.avg_duration_secs.map_or_else(|| "—".to_string(), format_duration_secs);
+    format!(
+        "📊 Статистика операций:\n\n✅ Завершено хеджей: {}\n💰 Объем хеджей: {:.2} {}\n❌ Ошибок: {}\n⏱ Среднее время исполнения: {}\n🛡 Открыто в хедже: {:.2} {}",
+        stats.completed_count,
+        stats.total_hedged_volume, quote_currency,
+        stats.failed_count,
+        avg_duration,
+        stats.open_notional, quote_currency,
+    )
+}
+
+/// Обработчик команды /stats
+pub async fn handle_stats_command(
+    bot: Bot,
+    msg: Message,
+    cfg: Arc<Config>,
+    db: Arc<Db>,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat.id;
+    info!("Processing /stats for chat_id: {}", chat_id);
+    let text = match get_operation_stats(db.as_ref(), chat_id.0).await {
+        Ok(stats) => format_operation_stats(&stats, &cfg.quote_currency),
+        Err(e) => {
+            error!("Failed to load operation stats for chat_id: {}: {}", chat_id, e);
+            "❌ Не удалось загрузить статистику операций.".to_string()
+        }
+    };
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
 
 // --- Обработчики Колбэков ---
 
@@ -150,7 +359,7 @@ pub async fn handle_show_status_callback<E>(
     bot: Bot,
     q: CallbackQuery,
     exchange: Arc<E>,
-    _cfg: Arc<Config>,
+    cfg: Arc<Config>,
     _db: Arc<Db>,
 ) -> anyhow::Result<()>
  where
@@ -165,11 +374,7 @@ pub async fn handle_show_status_callback<E>(
            .reply_markup(kb.clone())
            .await?;
 
-        let mut exchange_clone = (*exchange).clone();
-        let status_text = match exchange_clone.check_connection().await {
-            Ok(_) => "✅ Бот запущен и успешно подключен к бирже.".to_string(),
-            Err(e) => format!("⚠️ Бот запущен, но есть проблема с подключением к бирже: {}", e),
-        };
+        let status_text = build_status_text(exchange.as_ref(), &cfg).await;
 
         bot.edit_message_text(chat_id, msg.id(), status_text)
            .reply_markup(kb)
@@ -220,7 +425,7 @@ pub async fn handle_funding_symbol_input<E>(
     msg: Message,
     exchange: Arc<E>,
     state_storage: StateStorage, // Тип StateStorage уже Arc<TokioRwLock<...>>
-    _cfg: Arc<Config>,
+    cfg: Arc<Config>,
     _db: Arc<Db>,
 ) -> anyhow::Result<()>
 where
@@ -236,13 +441,13 @@ where
         match state_guard.get(&chat_id) {
             Some(UserState::AwaitingFundingSymbolInput { last_bot_message_id }) => *last_bot_message_id,
             _ => {
-                 if let Err(e) = bot.delete_message(chat_id, user_message_id).await { warn!("Failed delete unexpected funding symbol input: {}", e); }
+                 delete_user_message(&bot, &cfg, chat_id, user_message_id, "unexpected funding symbol input").await;
                  return Ok(());
             }
         }
     }; // Блокировка чтения освобождается здесь
 
-    if let Err(e) = bot.delete_message(chat_id, user_message_id).await { warn!("Failed delete user funding symbol message: {}", e); }
+    delete_user_message(&bot, &cfg, chat_id, user_message_id, "user funding symbol message").await;
 
     if symbol.is_empty() {
          if let Some(bot_msg_id) = previous_bot_message_id {
@@ -259,7 +464,7 @@ where
         info!("User state for {} reset to None", chat_id);
     } // Блокировка записи освобождается здесь
 
-    let days_u16 = 30;
+    let days_u16 = cfg.default_funding_days.clamp(1, MAX_FUNDING_DAYS);
     let loading_text = format!("⏳ Загрузка ставки финансирования для {} ({} дн.)...", symbol, days_u16);
     let bot_msg_id_opt = previous_bot_message_id.map(MessageId);
 
@@ -270,8 +475,8 @@ where
     }
 
     match exchange.get_funding_rate(&symbol, days_u16).await {
-        Ok(rate) => {
-            let text = format!("📈 Средняя ставка финансирования {} за ~{} дн.: {:.4}%", symbol, days_u16, rate * 100.0);
+        Ok(stats) => {
+            let text = format_funding_rate(&symbol, days_u16, &stats);
             if let Some(bot_msg_id) = bot_msg_id_opt {
                  let kb = make_info_menu_keyboard();
                  let _ = bot.edit_message_text(chat_id, bot_msg_id, text).reply_markup(kb).await;
@@ -292,4 +497,58 @@ where
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_706_745_600; // 2024-02-01 00:00:00 UTC
+
+    #[test]
+    fn history_range_defaults_to_last_30_days() {
+        assert_eq!(parse_history_range("", NOW), Ok((NOW - 30 * SECONDS_PER_DAY, NOW + 1)));
+    }
+
+    #[test]
+    fn history_range_includes_whole_end_day() {
+        let (from_ts, to_ts) = parse_history_range("2024-01-01 2024-01-31", NOW).expect("valid range");
+        assert_eq!(from_ts, 1_704_067_200);
+        assert_eq!(to_ts, NOW);
+    }
+
+    #[test]
+    fn duration_is_formatted_by_largest_units() {
+        assert_eq!(format_duration_secs(45.4), "45с");
+        assert_eq!(format_duration_secs(150.0), "2м 30с");
+        assert_eq!(format_duration_secs(3900.0), "1ч 5м");
+    }
+
+    #[test]
+    fn funding_days_default_when_omitted() {
+        assert_eq!(parse_funding_days(None, 30), Ok(30));
+        assert_eq!(parse_funding_days(None, 500), Ok(MAX_FUNDING_DAYS));
+    }
+
+    #[test]
+    fn funding_days_rejects_non_numeric_and_out_of_range() {
+        assert!(parse_funding_days(Some("abc"), 30).unwrap_err().contains("Неверное количество дней"));
+        assert!(parse_funding_days(Some("0"), 30).unwrap_err().contains("от 1 до 66"));
+        assert!(parse_funding_days(Some("500"), 30).unwrap_err().contains("от 1 до 66"));
+        assert_eq!(parse_funding_days(Some("66"), 30), Ok(66));
+    }
+
+    #[test]
+    fn funding_text_reports_period_and_intervals() {
+        let text = format_funding_rate("BTCUSDT", 7, &FundingRateStats { avg_rate: 0.0001, intervals: 21 });
+        assert!(text.contains("за 7 дн.: 0.0100%"), "{}", text);
+        assert!(text.contains("Усреднено начислений: 21"), "{}", text);
+    }
+
+    #[test]
+    fn history_range_rejects_bad_input() {
+        assert!(parse_history_range("2024-13-01", NOW).is_err());
+        assert!(parse_history_range("2024-02-01 2024-01-01", NOW).is_err());
+        assert!(parse_history_range("2024-01-01 2024-01-02 2024-01-03", NOW).is_err());
+    }
+}
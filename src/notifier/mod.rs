@@ -9,10 +9,24 @@ pub mod unhedge_flow;
 pub mod active_ops;
 pub mod hedge_flow_logic;
 pub mod hedge_flow_spawners;
+pub mod utils;
+pub mod trailing_stop;
+pub mod admin;
+pub mod edit_throttle;
+pub mod failure_cooldown;
+pub mod kill_switch;
+pub mod spot_orphan;
+pub mod flatten;
+pub mod roll;
+pub mod funding_accrual;
+pub mod alerts;
+pub mod analysis;
+pub mod watchers;
+pub mod recovery;
+pub mod daily_digest;
 
 // Заглушки
 //pub mod progress;      // TODO: Реализовать
-//pub mod utils;         // TODO: Реализовать
 
 // --- Импорт Зависимостей и Типов ---
 use std::sync::Arc;
@@ -27,7 +41,12 @@ use tokio::task::AbortHandle;
 use tokio::sync::Mutex as TokioMutex; // Tokio Mutex для RunningOperations - OK
 use crate::storage::{Db, HedgeOperation};
 use crate::config::Config;
+use utils::delete_user_message;
 use crate::exchange::Exchange;
+use crate::hedger::{ActiveOrderStorage, StageStorage, WatcherRegistry};
+pub use edit_throttle::ChatEditClocks;
+pub use failure_cooldown::FailureCooldowns;
+pub use kill_switch::TradingHalt;
 use teloxide::Bot;
 use teloxide::prelude::Requester;
 use teloxide::payloads::AnswerCallbackQuerySetters;
@@ -38,22 +57,35 @@ use tracing::{info, warn}; // Убран error
 #[derive(Debug, Clone)]
 pub enum UserState {
     AwaitingHedgeAssetSelection { last_bot_message_id: Option<i32> },
+    AwaitingHedgeMarketSelection { symbol: String, last_bot_message_id: Option<i32> }, // Выбор котируемой валюты, если рынков несколько
     AwaitingHedgeSum { symbol: String, last_bot_message_id: Option<i32> },
+    AwaitingHedgeBaseQty { symbol: String, last_bot_message_id: Option<i32> }, // Объем в базовой монете
     AwaitingHedgeVolatility { symbol: String, sum: f64, last_bot_message_id: Option<i32> },
     AwaitingHedgeConfirmation {
         symbol: String,
         sum: f64,
         volatility: f64,
+        spot_price_guard: Option<f64>, // Худшая допустимая цена покупки спота (ввод "волатильность @цена")
+        last_bot_message_id: Option<i32>,
+    },
+    // Хедж повысит плечо символа: ждем отдельного подтверждения (confirm_leverage_increase)
+    AwaitingHedgeLeverageConfirmation {
+        symbol: String,
+        sum: f64,
+        volatility: f64,
+        spot_price_guard: Option<f64>,
         last_bot_message_id: Option<i32>,
     },
     AwaitingUnhedgeAssetSelection { last_bot_message_id: Option<i32> },
     AwaitingUnhedgeOperationSelection {
         symbol: String,
         operations: Vec<HedgeOperation>,
+        spot_price_guard: Option<f64>, // Минимальная цена продажи спота (/unhedge SYMBOL @цена)
         last_bot_message_id: Option<i32>
     },
     AwaitingUnhedgeConfirmation {
         operation_id: i64,
+        spot_price_guard: Option<f64>,
         last_bot_message_id: Option<i32>,
     },
     ViewingAllPairs {
@@ -74,10 +106,13 @@ pub type StateStorage = Arc<TokioRwLock<HashMap<ChatId, UserState>>>;
 pub struct RunningOperationInfo {
     pub handle: AbortHandle,
     pub operation_id: i64,
+    pub op_ref: Option<String>, // Человекочитаемая ссылка на операцию (для подписей)
     pub operation_type: OperationType,
     pub symbol: String,
     pub bot_message_id: i32,
     pub total_filled_spot_qty: Arc<TokioMutex<f64>>,
+    pub active_order: ActiveOrderStorage, // Текущий ордер и его нога (спот/фьючерс)
+    pub stage: StageStorage,              // Этап хеджа: при Futures доступна отмена с сохранением спота
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -88,11 +123,52 @@ pub enum OperationType {
 
 pub type RunningOperations = Arc<TokioMutex<HashMap<(ChatId, i64), RunningOperationInfo>>>;
 
+/// Удаляет запись из RunningOperations при любом завершении задачи (Ok, Err, паника, abort)
+pub struct RunningOperationGuard {
+    running_operations: RunningOperations,
+    key: (ChatId, i64),
+}
+
+impl RunningOperationGuard {
+    pub fn new(running_operations: RunningOperations, chat_id: ChatId, operation_id: i64) -> Self {
+        Self { running_operations, key: (chat_id, operation_id) }
+    }
+}
+
+impl Drop for RunningOperationGuard {
+    fn drop(&mut self) {
+        let key = self.key;
+        // Drop синхронный: снимаем запись сразу, а если мьютекс занят — в фоновой задаче
+        match self.running_operations.try_lock() {
+            Ok(mut ops_guard) => {
+                if ops_guard.remove(&key).is_some() {
+                    info!("op_id:{}: Removed running operation info for chat_id: {}", key.1, key.0);
+                }
+            }
+            Err(_) => {
+                let running_operations = self.running_operations.clone();
+                match tokio::runtime::Handle::try_current() {
+                    Ok(handle) => {
+                        handle.spawn(async move {
+                            if running_operations.lock().await.remove(&key).is_some() {
+                                info!("op_id:{}: Removed running operation info for chat_id: {}", key.1, key.0);
+                            }
+                        });
+                    }
+                    Err(_) => warn!("op_id:{}: No runtime to remove running operation info.", key.1),
+                }
+            }
+        }
+    }
+}
+
 #[derive(BotCommands, Clone, Debug)]
 #[command(rename_rule = "lowercase", description = "Доступные команды:")]
 pub enum Command {
     #[command(description = "Начало работы и главное меню")]
     Start,
+    #[command(description = "Список команд и навигация по меню")]
+    Help,
     #[command(description = "Статус бота и API")]
     Status,
     #[command(description = "Баланс кошелька")]
@@ -101,12 +177,48 @@ pub enum Command {
     Balance(String),
     #[command(description = "Начать хеджирование: /hedge <SYMBOL> (опционально)")]
     Hedge(String),
-    #[command(description = "Начать расхеджирование: /unhedge <SYMBOL> (опционально)")]
+    #[command(description = "Символ по умолчанию для /hedge без аргумента: /default <SYMBOL> | off")]
+    Default(String),
+    #[command(description = "Начать расхеджирование: /unhedge <SYMBOL> [@мин. цена продажи спота] (опционально)")]
     Unhedge(String),
     #[command(description = "Средняя ставка финансирования: /funding <SYMBOL> [days]")]
     Funding(String),
+    #[command(description = "Прогноз доходности хеджа с учетом фандинга и комиссий: /analyze <SYMBOL> [days]")]
+    Analyze(String),
     #[command(description = "Показать активные операции")]
     Active,
+    #[command(description = "История операций: /history [ГГГГ-ММ-ДД] [ГГГГ-ММ-ДД]")]
+    History(String),
+    #[command(description = "Фоновые наблюдатели (ожидание фьючерсных ордеров) с кнопками остановки")]
+    Watchers,
+    #[command(description = "Статистика операций")]
+    Stats,
+    #[command(description = "Показать ваш chat_id (для allowed_chat_ids)")]
+    Whoami,
+    #[command(description = "Рассылка всем пользователям (админ): /broadcast <текст>")]
+    Broadcast(String),
+    #[command(description = "Снять паузу после ошибки (админ): /clearcooldown [SYMBOL]")]
+    ClearCooldown(String),
+    #[command(description = "Аварийно продать весь свободный спот монеты (админ): /flatten <SYMBOL>")]
+    Flatten(String),
+    #[command(description = "Перенести хедж на другой фьючерсный контракт: /roll <ID> <КОНТРАКТ>")]
+    Roll(String),
+    #[command(description = "Аварийно остановить запуск новых операций (админ)")]
+    Halt,
+    #[command(description = "Снять аварийную остановку (админ)")]
+    Resume,
+    #[command(description = "Отменить ордер на бирже (админ): /cancelorder <SYMBOL> <ORDER_ID> <spot|futures>")]
+    CancelOrder(String),
+    #[command(description = "Синхронизировать время с биржей (админ)")]
+    Resync,
+    #[command(description = "Выгрузить историю операций в JSON (админ): /exportops [ГГГГ-ММ-ДД] [ГГГГ-ММ-ДД]")]
+    ExportOps(String),
+    #[command(description = "Загрузить операции из JSON (админ): ответом на файл из /exportops")]
+    ImportOps,
+    #[command(description = "Закрыть зависшую операцию в БД (админ): /resolve <ID> <Completed|Cancelled|Failed>")]
+    Resolve(String),
+    #[command(description = "Показать действующий конфиг без ключей и токенов (админ)")]
+    Config,
 }
 
 // --- Главные Диспетчеры ---
@@ -118,20 +230,47 @@ pub async fn dispatch_command<E>(
     exchange: Arc<E>,
     state_storage: StateStorage, // Теперь это Arc<TokioRwLock<...>>
     running_operations: RunningOperations,
+    failure_cooldowns: FailureCooldowns,
+    trading_halt: TradingHalt,
+    watchers: WatcherRegistry, // Фоновые наблюдатели аккаунта (/watchers)
     cfg: Arc<Config>,
     db: Arc<Db>,
 ) -> anyhow::Result<()>
 where E: Exchange + Clone + Send + Sync + 'static,
 {
+    let username = msg.from.as_ref().and_then(|u| u.username.as_deref());
+    if let Err(e) = crate::storage::touch_user(db.as_ref(), msg.chat.id.0, username).await {
+        warn!("Failed to record user for chat {}: {}", msg.chat.id, e);
+    }
+
     match cmd {
         Command::Start => navigation::handle_start(bot, msg, exchange, state_storage, cfg, db).await?,
+        Command::Help => navigation::handle_help_command(bot, msg).await?,
         Command::Hedge(symbol) => hedge_flow::handle_hedge_command(bot, msg, symbol, exchange, state_storage, running_operations, cfg, db).await?,
+        Command::Default(symbol) => hedge_flow::handle_default_command(bot, msg, symbol, cfg, db).await?,
         Command::Unhedge(symbol) => unhedge_flow::handle_unhedge_command(bot, msg, symbol, exchange, state_storage, running_operations, cfg, db).await?,
         Command::Wallet => wallet_info::handle_wallet_command(bot, msg, exchange, state_storage, cfg, db).await?,
         Command::Balance(symbol) => wallet_info::handle_balance_command(bot, msg, symbol, exchange, state_storage, cfg, db).await?,
         Command::Status => market_info::handle_status_command(bot, msg, exchange, state_storage, cfg, db).await?,
         Command::Funding(params) => market_info::handle_funding_command(bot, msg, params, exchange, state_storage, cfg, db).await?,
+        Command::Analyze(args) => analysis::handle_analyze_command(bot, msg, args, exchange, cfg).await?,
         Command::Active => active_ops::handle_active_command(bot, msg, exchange, state_storage, running_operations, cfg, db).await?,
+        Command::History(args) => market_info::handle_history_command(bot, msg, args, db).await?,
+        Command::Watchers => watchers::handle_watchers_command(bot, msg, watchers).await?,
+        Command::Stats => market_info::handle_stats_command(bot, msg, cfg, db).await?,
+        Command::Whoami => admin::handle_whoami_command(bot, msg, cfg).await?,
+        Command::Broadcast(text) => admin::handle_broadcast_command(bot, msg, text, cfg, db).await?,
+        Command::ClearCooldown(symbol) => admin::handle_clear_cooldown_command(bot, msg, symbol, failure_cooldowns, cfg).await?,
+        Command::Flatten(symbol) => flatten::handle_flatten_command(bot, msg, symbol, exchange, cfg).await?,
+        Command::Roll(args) => roll::handle_roll_command(bot, msg, args, exchange, trading_halt, cfg, db).await?,
+        Command::Halt => admin::handle_halt_command(bot, msg, exchange, running_operations, trading_halt, cfg, db).await?,
+        Command::Resume => admin::handle_resume_command(bot, msg, trading_halt, cfg).await?,
+        Command::CancelOrder(args) => admin::handle_cancel_order_command(bot, msg, args, exchange, cfg).await?,
+        Command::Resync => admin::handle_resync_command(bot, msg, exchange, cfg).await?,
+        Command::ExportOps(args) => admin::handle_export_ops_command(bot, msg, args, cfg, db).await?,
+        Command::ImportOps => admin::handle_import_ops_command(bot, msg, cfg, db).await?,
+        Command::Resolve(args) => admin::handle_resolve_command(bot, msg, args, running_operations, watchers, cfg, db).await?,
+        Command::Config => admin::handle_config_command(bot, msg, cfg).await?,
     }
     Ok(())
 }
@@ -143,6 +282,10 @@ pub async fn dispatch_callback<E>(
     exchange: Arc<E>,
     state_storage: StateStorage, // Теперь это Arc<TokioRwLock<...>>
     running_operations: RunningOperations,
+    failure_cooldowns: FailureCooldowns,
+    trading_halt: TradingHalt,
+    edit_clocks: ChatEditClocks,
+    watchers: WatcherRegistry,
     cfg: Arc<Config>,
     db: Arc<Db>,
 ) -> anyhow::Result<()>
@@ -178,13 +321,28 @@ where
             active_ops::handle_menu_active_ops_callback(bot, q, running_operations, state_storage).await?;
         } else if data.starts_with(callback_data::PREFIX_CANCEL_ACTIVE_OP) {
               active_ops::handle_cancel_active_op_callback(bot, q, exchange, state_storage, running_operations, cfg, db).await?;
+        } else if data.starts_with(callback_data::PREFIX_CANCEL_FUTURES_KEEP_SPOT) {
+              active_ops::handle_cancel_futures_keep_spot_callback(bot, q, exchange, running_operations, cfg, db).await?;
+        } else if data.starts_with(callback_data::PREFIX_SHOW_OP_STATUS) || data.starts_with(callback_data::PREFIX_REFRESH_OP_STATUS) {
+              active_ops::handle_show_op_status_callback(bot, q, running_operations, cfg, db).await?;
+        } else if data.starts_with(callback_data::PREFIX_TRAILING_STOP) {
+              trailing_stop::handle_trailing_stop_callback(bot, q, exchange, state_storage, cfg, db).await?;
+        } else if data.starts_with(callback_data::PREFIX_RESUME_FUTURES_LEG) {
+              spot_orphan::handle_resume_futures_leg_callback(bot, q, exchange, running_operations, trading_halt, watchers, cfg, db).await?;
+        } else if data.starts_with(callback_data::PREFIX_CANCEL_WATCHER) {
+              watchers::handle_cancel_watcher_callback(bot, q, watchers, db).await?;
+        } else if data.starts_with(callback_data::PREFIX_FLATTEN_CONFIRM) {
+              flatten::handle_flatten_confirm_callback(bot, q, exchange, cfg).await?;
+        } else if data.starts_with(callback_data::PREFIX_RESOLVE_CONFIRM) {
+              admin::handle_resolve_confirm_callback(bot, q, running_operations, watchers, cfg, db).await?;
         } else if data.starts_with(callback_data::PREFIX_HEDGE_ASSET) {
               hedge_flow::handle_hedge_asset_callback(bot, q, exchange, state_storage, cfg, db).await?;
+        } else if data.starts_with(callback_data::PREFIX_HEDGE_UNITS) {
+              hedge_flow::handle_hedge_units_callback(bot, q, state_storage, cfg).await?;
         } else if data.starts_with(callback_data::PREFIX_HEDGE_PAIR) {
-              warn!("Handler for PREFIX_HEDGE_PAIR not implemented yet.");
-              bot.answer_callback_query(query_id).text("Функция пока не реализована.").show_alert(false).await?;
+              hedge_flow::handle_hedge_pair_callback(bot, q, state_storage, cfg).await?;
         } else if data.starts_with(callback_data::PREFIX_HEDGE_CONFIRM) {
-              hedge_flow::handle_hedge_confirm_callback(bot, q, exchange, state_storage, running_operations, cfg, db).await?;
+              hedge_flow::handle_hedge_confirm_callback(bot, q, exchange, state_storage, running_operations, failure_cooldowns, trading_halt, edit_clocks, watchers, cfg, db).await?;
         } else if data == callback_data::VIEW_ALL_PAIRS {
               warn!("Handler for VIEW_ALL_PAIRS not implemented yet.");
               bot.answer_callback_query(query_id).text("Функция пока не реализована.").show_alert(false).await?;
@@ -193,7 +351,7 @@ where
         } else if data.starts_with(callback_data::PREFIX_UNHEDGE_OP_SELECT) {
               unhedge_flow::handle_unhedge_select_op_callback(bot, q, exchange, state_storage, running_operations, cfg, db).await?;
         } else if data.starts_with(callback_data::PREFIX_UNHEDGE_CONFIRM) {
-              unhedge_flow::handle_unhedge_confirm_callback(bot, q, exchange, state_storage, running_operations, cfg, db).await?;
+              unhedge_flow::handle_unhedge_confirm_callback(bot, q, exchange, state_storage, running_operations, trading_halt, edit_clocks, cfg, db).await?;
         } else if data == callback_data::SHOW_STATUS {
               market_info::handle_show_status_callback(bot, q, exchange, cfg, db).await?;
         } else if data == callback_data::SHOW_FUNDING {
@@ -231,9 +389,10 @@ where
     info!("Dispatching message for chat {} in state: {:?}", msg.chat.id, state);
 
     match state {
-        UserState::AwaitingHedgeAssetSelection { .. } | UserState::ViewingAllPairs { .. } =>
+        UserState::AwaitingHedgeAssetSelection { .. } | UserState::AwaitingHedgeMarketSelection { .. } | UserState::ViewingAllPairs { .. } =>
             hedge_flow::handle_asset_ticker_input(bot, msg, exchange, state_storage, cfg, db).await?,
-        UserState::AwaitingHedgeSum { .. } => hedge_flow::handle_sum_input(bot, msg, state_storage, cfg).await?,
+        UserState::AwaitingHedgeSum { .. } => hedge_flow::handle_sum_input(bot, msg, exchange, state_storage, cfg).await?,
+        UserState::AwaitingHedgeBaseQty { .. } => hedge_flow::handle_base_qty_input(bot, msg, exchange, state_storage, cfg).await?,
         UserState::AwaitingHedgeVolatility { .. } => hedge_flow::handle_volatility_input(bot, msg, exchange, state_storage, running_operations, cfg, db).await?,
         UserState::AwaitingFundingSymbolInput { .. } =>
             market_info::handle_funding_symbol_input(bot, msg, exchange, state_storage, cfg, db).await?,
@@ -241,18 +400,14 @@ where
         UserState::AwaitingUnhedgeOperationSelection { .. } |
         UserState::AwaitingUnhedgeConfirmation { .. } => {
             warn!("Handler for state {:?} (text input) not implemented yet. Deleting message.", state);
-            if let Err(e) = bot.delete_message(msg.chat.id, msg.id).await {
-                 tracing::warn!("Failed to delete unhandled message: {}", e);
-            }
+            delete_user_message(&bot, &cfg, msg.chat.id, msg.id, "unhandled message").await;
         },
         _ => {
             if msg.text().map_or(false, |t| t.starts_with('/')) {
                 warn!("Received command message '{}' in dispatch_message. Should have been handled by dispatch_command.", msg.text().unwrap_or(""));
             } else if msg.text().is_some() {
                 warn!("Received unexpected text message in state {:?}. Deleting.", state);
-                if let Err(e) = bot.delete_message(msg.chat.id, msg.id).await {
-                    tracing::warn!("Failed to delete unexpected message: {}", e);
-                }
+                delete_user_message(&bot, &cfg, msg.chat.id, msg.id, "unexpected message").await;
             }
         }
     }
@@ -281,6 +436,7 @@ pub mod callback_data {
     // Префиксы выбора для Хеджирования
     pub const PREFIX_HEDGE_ASSET: &str = "h_asset_";
     pub const PREFIX_HEDGE_PAIR: &str = "h_pair_";
+    pub const PREFIX_HEDGE_UNITS: &str = "h_units_"; // base / quote
 
     // Префиксы выбора для Расхеджирования
     pub const PREFIX_UNHEDGE_ASSET: &str = "u_asset_";
@@ -292,6 +448,16 @@ pub mod callback_data {
 
     // Префиксы Отмены Активных Операций
     pub const PREFIX_CANCEL_ACTIVE_OP: &str = "cancel_op_";
+    pub const PREFIX_CANCEL_FUTURES_KEEP_SPOT: &str = "cancel_fut_";
+    pub const PREFIX_SHOW_OP_STATUS: &str = "op_status_";
+    pub const PREFIX_REFRESH_OP_STATUS: &str = "op_refresh_";
+    pub const PREFIX_CANCEL_WATCHER: &str = "cancel_watch_";
+
+    // Защита позиции после хеджирования
+    pub const PREFIX_TRAILING_STOP: &str = "tstop_";
+    pub const PREFIX_RESUME_FUTURES_LEG: &str = "resume_fut_";
+    pub const PREFIX_FLATTEN_CONFIRM: &str = "flatten_conf_";
+    pub const PREFIX_RESOLVE_CONFIRM: &str = "resolve_conf_";
 
     // Информация
     pub const SHOW_STATUS: &str = "show_status";
@@ -300,4 +466,63 @@ pub mod callback_data {
     // Пагинация (context_page_num)
     pub const PREFIX_PAGE_NEXT: &str = "page_next_";
     pub const PREFIX_PAGE_PREV: &str = "page_prev_";
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hedger::HedgeStage;
+
+    fn make_info(operation_id: i64) -> RunningOperationInfo {
+        RunningOperationInfo {
+            handle: tokio::spawn(async {}).abort_handle(),
+            operation_id,
+            op_ref: None,
+            operation_type: OperationType::Hedge,
+            symbol: "BTC".to_string(),
+            bot_message_id: 1,
+            total_filled_spot_qty: Arc::new(TokioMutex::new(0.0)),
+            active_order: Arc::new(TokioMutex::new(None)),
+            stage: Arc::new(TokioMutex::new(HedgeStage::Spot)),
+        }
+    }
+
+    #[tokio::test]
+    async fn guard_removes_operation_after_task_completion() {
+        let running_operations: RunningOperations = Arc::new(TokioMutex::new(HashMap::new()));
+        let chat_id = ChatId(42);
+
+        // Запись вставляется под блокировкой до того, как задача сможет завершиться
+        let task = {
+            let mut ops_guard = running_operations.lock().await;
+            let cleanup = RunningOperationGuard::new(running_operations.clone(), chat_id, 7);
+            let task = tokio::spawn(async move {
+                let _cleanup = cleanup;
+                Err::<(), _>(anyhow::anyhow!("simulated failure"))
+            });
+            ops_guard.insert((chat_id, 7), make_info(7));
+            task
+        };
+
+        assert!(task.await.unwrap().is_err());
+        tokio::task::yield_now().await;
+        assert!(running_operations.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn guard_removes_operation_after_abort() {
+        let running_operations: RunningOperations = Arc::new(TokioMutex::new(HashMap::new()));
+        let chat_id = ChatId(42);
+        running_operations.lock().await.insert((chat_id, 8), make_info(8));
+
+        let cleanup = RunningOperationGuard::new(running_operations.clone(), chat_id, 8);
+        let task = tokio::spawn(async move {
+            let _cleanup = cleanup;
+            std::future::pending::<()>().await;
+        });
+        task.abort();
+        let _ = task.await;
+
+        assert!(running_operations.lock().await.is_empty());
+    }
+}
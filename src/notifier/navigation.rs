@@ -1,8 +1,9 @@
 // src/notifier/navigation.rs
 
 // <<< ИСПРАВЛЕНО: Убран RunningOperations >>>
-use crate::notifier::{StateStorage, UserState, callback_data};
+use crate::notifier::{Command, StateStorage, UserState, callback_data};
 use crate::notifier::RunningOperations; // Убран из импорта super
+use crate::notifier::utils::delete_user_message;
 use crate::config::Config;
 use crate::exchange::Exchange;
 use crate::storage::Db;
@@ -12,10 +13,18 @@ use teloxide::types::{
     InlineKeyboardButton, InlineKeyboardMarkup, Message, MessageId, CallbackQuery, ChatId,
 };
 use teloxide::requests::Requester;
+use teloxide::utils::command::BotCommands;
 use tracing::{info, warn};
 
 // --- Константы ---
 const WELCOME_MESSAGE: &str = "Добро пожаловать в Hedgehog Bot! Выберите действие:";
+const MENU_GUIDE: &str = "Кнопки главного меню (/start):\n\
+💼 Кошелек — балансы монет и их стоимость\n\
+⚙️ Захеджировать — выбор монеты → сумма → волатильность → подтверждение; спот покупается, фьючерс шортится\n\
+🛠 Расхеджировать — выбор завершенной операции → подтверждение; спот продается, шорт откупается\n\
+📊 Информация — статус API и ставка финансирования (история и статистика — /history, /stats)\n\
+⚡ Активные операции — прогресс текущих операций и их отмена\n\
+Кнопка «Назад» возвращает в главное меню, «Отмена» прерывает ввод на любом шаге.";
 
 // --- Вспомогательные функции ---
 
@@ -67,7 +76,7 @@ pub async fn handle_start<E>(
     msg: Message,
     _exchange: Arc<E>,
     state_storage: StateStorage, // Тип StateStorage уже Arc<TokioRwLock<...>> из mod.rs
-    _cfg: Arc<Config>,
+    cfg: Arc<Config>,
     _db: Arc<Db>,
 ) -> anyhow::Result<()>
 where
@@ -83,15 +92,25 @@ where
         info!("User state for {} reset to None.", chat_id);
     } // Блокировка state_guard освобождается здесь
 
-    if let Err(e) = bot.delete_message(chat_id, msg.id).await {
-        warn!("Failed to delete /start command message: {}", e);
-    }
+    delete_user_message(&bot, &cfg, chat_id, msg.id, "/start command message").await;
 
     let _ = show_main_menu(&bot, chat_id, None).await;
 
     Ok(())
 }
 
+/// Текст /help: список команд из описаний Command и навигация по кнопкам
+pub fn help_text() -> String {
+    format!("{}\n\n{}", Command::descriptions(), MENU_GUIDE)
+}
+
+/// Обработчик команды /help
+pub async fn handle_help_command(bot: Bot, msg: Message) -> anyhow::Result<()> {
+    info!("Processing /help command for chat_id: {}", msg.chat.id);
+    bot.send_message(msg.chat.id, help_text()).await?;
+    Ok(())
+}
+
 /// Обработчик колбэка кнопки "Назад" (возврат в главное меню)
 pub async fn handle_back_to_main(
     bot: Bot,
@@ -166,4 +185,18 @@ pub async fn handle_menu_active_ops_callback(
     info!("Callback '{}' triggered. Calling active_ops handler...", callback_data::MENU_ACTIVE_OPS);
     bot.answer_callback_query(q.id).text("Раздел Активные операции (не реализовано)").await?;
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn help_lists_every_command_and_menu_guide() {
+        let text = help_text();
+        for command in Command::bot_commands() {
+            assert!(text.contains(&command.command), "missing {} in /help", command.command);
+        }
+        assert!(text.contains("/help"));
+        assert!(text.contains("Захеджировать"));
+    }
+}
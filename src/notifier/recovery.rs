@@ -0,0 +1,191 @@
+// src/notifier/recovery.rs
+
+//! Уведомления о восстановлении операций, прерванных перезапуском бота (см. hedger::recovery).
+
+use crate::config::Config;
+use crate::exchange::Exchange;
+use crate::hedger::{
+    recover_interrupted_operations, RecoveryAction, RecoveryReport, ResumeTracker, ResumedOperation, SpotOnlyOrphan, WatcherRegistry,
+};
+use crate::notifier::alerts::send_alert;
+use crate::notifier::navigation;
+use crate::notifier::spot_orphan::{format_spot_orphan_warning, make_spot_orphan_keyboard};
+use crate::notifier::utils::operation_label;
+use crate::notifier::{OperationType, RunningOperationGuard, RunningOperationInfo, RunningOperations};
+use crate::storage::Db;
+use futures::FutureExt;
+use std::sync::Arc;
+use teloxide::prelude::*;
+use teloxide::types::InlineKeyboardMarkup;
+use tokio::sync::Mutex as TokioMutex;
+use tracing::{info, warn};
+
+/// Текст уведомления по итогу восстановления; true — уровень алерта (нужны действия пользователя)
+pub fn format_recovery_report(report: &RecoveryReport, decimals: u32) -> (String, bool) {
+    let operation = &report.operation;
+    let op_label = operation_label(operation.op_ref.as_deref(), operation.id);
+    let symbol = &operation.base_symbol;
+    if let Err(e) = &report.result {
+        return match e.downcast_ref::<SpotOnlyOrphan>() {
+            Some(orphan) => (format_spot_orphan_warning(&op_label, symbol, orphan, decimals), true),
+            None => (
+                format!("❌ После перезапуска не удалось восстановить операцию {} ({}): {}\nПроверьте позиции на бирже.", op_label, symbol, e),
+                true,
+            ),
+        };
+    }
+    match &report.action {
+        RecoveryAction::ReattachFuturesOrder { order_id } => (
+            format!("🔄 Бот перезапущен во время операции {} ({}): слежение за фьючерсным ордером {} возобновлено.", op_label, symbol, order_id),
+            false,
+        ),
+        RecoveryAction::PlaceFuturesLeg => (
+            format!("🔄 Бот перезапущен во время операции {} ({}): фьючерсная нога довыставлена, позиция захеджирована.", op_label, symbol),
+            false,
+        ),
+        RecoveryAction::ManualReview { reason } => (
+            format!("⚠️ Операция {} ({}) прервана перезапуском бота и не возобновлена: {}.\nПроверьте позиции на бирже вручную.", op_label, symbol, reason),
+            true,
+        ),
+    }
+}
+
+/// Возобновленная при старте операция видна в /active, как обычный хедж: ее можно отменить,
+/// а /resolve не закроет ее, пока задача работает
+pub fn make_resume_tracker(running_operations: RunningOperations) -> ResumeTracker {
+    Box::new(move |resumed: ResumedOperation| {
+        let running_operations = running_operations.clone();
+        async move {
+            let operation = resumed.operation;
+            let chat_id = ChatId(operation.chat_id);
+            let info = RunningOperationInfo {
+                handle: resumed.handle,
+                operation_id: operation.id,
+                op_ref: operation.op_ref.clone(),
+                operation_type: OperationType::Hedge,
+                symbol: operation.base_symbol.clone(),
+                bot_message_id: 0, // Сообщения прогресса нет: итог уходит отдельным уведомлением
+                total_filled_spot_qty: Arc::new(TokioMutex::new(operation.spot_filled_qty)),
+                active_order: resumed.active_order,
+                stage: resumed.stage,
+            };
+            running_operations.lock().await.insert((chat_id, operation.id), info);
+            info!("op_id:{}: Stored running info for futures leg resumed after restart.", operation.id);
+            Box::new(RunningOperationGuard::new(running_operations, chat_id, operation.id)) as Box<dyn Send>
+        }
+        .boxed()
+    })
+}
+
+/// Разбор прерванных операций в фоне (не задерживает запуск диспетчера) с уведомлением их чатов
+pub fn spawn_startup_recovery<E>(
+    bot: Bot,
+    exchange: E,
+    cfg: Config,
+    db: Db,
+    watchers: WatcherRegistry,
+    running_operations: RunningOperations,
+) where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    tokio::spawn(async move {
+        let tracker = make_resume_tracker(running_operations);
+        let reports = recover_interrupted_operations(exchange, cfg.clone(), db, watchers, tracker).await;
+        if reports.is_empty() {
+            return;
+        }
+        info!("Processed {} operation(s) interrupted by restart", reports.len());
+        for report in &reports {
+            let chat_id = ChatId(report.operation.chat_id);
+            let (text, is_alert) = format_recovery_report(report, cfg.display_max_decimals);
+            let keyboard: InlineKeyboardMarkup = match report.result.as_ref().err().and_then(|e| e.downcast_ref::<SpotOnlyOrphan>()) {
+                Some(_) => make_spot_orphan_keyboard(report.operation.id),
+                None => navigation::make_main_menu_keyboard(),
+            };
+            if let Err(e) = bot.send_message(chat_id, &text).reply_markup(keyboard).await {
+                warn!("op_id:{}: Failed to send recovery notification: {}", report.operation.id, e);
+            }
+            if is_alert {
+                send_alert(&bot, &cfg, chat_id, &text).await;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::HedgeOperation;
+
+    fn report(action: RecoveryAction, result: anyhow::Result<()>) -> RecoveryReport {
+        let operation = HedgeOperation {
+            id: 7,
+            chat_id: 1,
+            base_symbol: "BTC".to_string(),
+            quote_currency: "USDT".to_string(),
+            initial_sum: 100.0,
+            volatility: 0.6,
+            target_spot_qty: 0.001,
+            target_futures_qty: 0.001,
+            start_timestamp: 0,
+            status: "Running".to_string(),
+            spot_order_id: None,
+            spot_filled_qty: 0.001,
+            futures_order_id: None,
+            futures_filled_qty: 0.0,
+            end_timestamp: None,
+            error_message: None,
+            unhedged_op_id: None,
+            op_ref: Some("BTC-0101-01".to_string()),
+            futures_symbol: None,
+            rolled_from_op_id: None,
+            accrued_funding: 0.0,
+            environment: None,
+            futures_only: false,
+        };
+        RecoveryReport { operation, action, result }
+    }
+
+    #[test]
+    fn only_unresumed_operations_raise_alerts() {
+        let (text, is_alert) = format_recovery_report(&report(RecoveryAction::PlaceFuturesLeg, Ok(())), 4);
+        assert!(!is_alert);
+        assert!(text.contains("BTC-0101-01 (ID:7)"));
+
+        let review = RecoveryAction::ManualReview { reason: "grace expired".to_string() };
+        let (text, is_alert) = format_recovery_report(&report(review, Ok(())), 4);
+        assert!(is_alert);
+        assert!(text.contains("grace expired"));
+
+        let orphan = SpotOnlyOrphan { spot_filled_qty: 0.001, futures_filled_qty: 0.0, reason: "price band".to_string() };
+        let (text, is_alert) = format_recovery_report(&report(RecoveryAction::PlaceFuturesLeg, Err(orphan.into())), 4);
+        assert!(is_alert);
+        assert!(text.contains("price band"));
+    }
+
+    #[tokio::test]
+    async fn resumed_operation_is_listed_until_tracking_ends() {
+        let running_operations = RunningOperations::default();
+        let tracker = make_resume_tracker(running_operations.clone());
+        let operation = report(RecoveryAction::PlaceFuturesLeg, Ok(())).operation;
+        let task = tokio::spawn(tokio::time::sleep(std::time::Duration::from_secs(60)));
+
+        let tracked = tracker(ResumedOperation {
+            operation,
+            handle: task.abort_handle(),
+            active_order: Arc::new(TokioMutex::new(None)),
+            stage: Arc::new(TokioMutex::new(crate::hedger::HedgeStage::Futures)),
+        })
+        .await;
+        {
+            let ops = running_operations.lock().await;
+            let info = ops.get(&(ChatId(1), 7)).expect("resumed operation is in /active");
+            assert_eq!(info.operation_type, OperationType::Hedge);
+            assert_eq!(*info.stage.lock().await, crate::hedger::HedgeStage::Futures);
+        }
+
+        drop(tracked);
+        assert!(running_operations.lock().await.is_empty());
+        task.abort();
+    }
+}
@@ -0,0 +1,142 @@
+// src/notifier/roll.rs
+
+//! Роллирование хеджа на другой фьючерсный контракт (/roll <ID> <КОНТРАКТ>).
+
+use crate::notifier::{navigation, TradingHalt};
+use crate::notifier::utils::{format_qty, operation_label};
+use crate::config::Config;
+use crate::exchange::Exchange;
+use crate::hedger::{HedgeProgressCallback, HedgeProgressUpdate, Hedger};
+use crate::storage::{Db, OperationStatus, get_hedge_operation_by_id, insert_rolled_hedge_operation};
+use futures::future::FutureExt;
+use std::sync::Arc;
+use teloxide::prelude::*;
+use teloxide::types::Message;
+use tracing::{error, info, warn};
+
+/// Разбор аргументов /roll: ID операции и символ нового контракта (BTCUSDT-26DEC25)
+fn parse_roll_args(args: &str) -> Option<(i64, String)> {
+    let mut parts = args.split_whitespace();
+    let operation_id = parts.next()?.parse::<i64>().ok()?;
+    let contract = parts.next()?.to_uppercase();
+    parts.next().is_none().then_some((operation_id, contract))
+}
+
+/// Обработчик команды /roll <ID> <КОНТРАКТ>: перенос фьючерсной ноги завершенного хеджа на другой контракт
+pub async fn handle_roll_command<E>(
+    bot: Bot,
+    msg: Message,
+    args: String,
+    exchange: Arc<E>,
+    trading_halt: TradingHalt,
+    cfg: Arc<Config>,
+    db: Arc<Db>,
+) -> anyhow::Result<()>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let chat_id = msg.chat.id;
+    let Some((operation_id, contract)) = parse_roll_args(&args) else {
+        bot.send_message(
+            chat_id,
+            "⚠️ Использование: /roll <ID операции> <КОНТРАКТ>\nНапример: /roll 42 BTCUSDT-26DEC25",
+        )
+        .await?;
+        return Ok(());
+    };
+    // --- Аварийная остановка торговли (/halt или файл-сигнал): роллирование выставляет новые ордера ---
+    if let Err(halted_text) = trading_halt.ensure_trading_allowed() {
+        info!("op_id:{}: Roll requested by {} rejected: trading halted", operation_id, chat_id);
+        bot.send_message(chat_id, halted_text).await?;
+        return Ok(());
+    }
+
+    let original = match get_hedge_operation_by_id(db.as_ref(), operation_id).await {
+        Ok(Some(op)) if op.chat_id == chat_id.0 => op,
+        Ok(_) => {
+            bot.send_message(chat_id, format!("❌ Операция {} не найдена.", operation_id)).await?;
+            return Ok(());
+        }
+        Err(e) => {
+            error!("op_id:{}: DB error loading operation for roll: {}", operation_id, e);
+            bot.send_message(chat_id, "❌ Ошибка БД.").await?;
+            return Ok(());
+        }
+    };
+    if !original.has_status(OperationStatus::Completed) || original.unhedged_op_id.is_some() {
+        bot.send_message(chat_id, "⚠️ Роллировать можно только завершенный и не расхеджированный хедж.").await?;
+        return Ok(());
+    }
+    if !original.belongs_to_environment(cfg.environment()) {
+        warn!("op_id:{}: Refusing to roll operation created on {:?} while connected to {}", operation_id, original.environment, cfg.environment());
+        bot.send_message(
+            chat_id,
+            format!(
+                "❌ Операция создана в окружении {}, а бот подключен к {}. Роллирование отменено.",
+                original.environment.as_deref().unwrap_or("?"), cfg.environment()
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+    if !contract.starts_with(&original.base_symbol.to_uppercase()) {
+        bot.send_message(chat_id, format!("⚠️ Контракт {} не относится к {}.", contract, original.base_symbol)).await?;
+        return Ok(());
+    }
+
+    let op_label = operation_label(original.op_ref.as_deref(), operation_id);
+    let old_contract = original.futures_contract();
+    let futures_qty = if original.futures_filled_qty > 0.0 { original.futures_filled_qty } else { original.target_futures_qty };
+    let (rolled_id, rolled_ref) = match insert_rolled_hedge_operation(db.as_ref(), &original, &contract, futures_qty).await {
+        Ok(inserted) => inserted,
+        Err(e) => {
+            error!("op_id:{}: Failed to create roll operation: {}", operation_id, e);
+            bot.send_message(chat_id, "❌ Не удалось создать операцию роллирования.").await?;
+            return Ok(());
+        }
+    };
+    let rolled_label = operation_label(Some(rolled_ref.as_str()), rolled_id);
+    info!("op_id:{}: User {} rolls {} -> {} as op_id:{}", operation_id, chat_id, old_contract, contract, rolled_id);
+
+    let status_msg = bot
+        .send_message(
+            chat_id,
+            format!("⏳ Роллирование {}: {} → {} (новая операция {})...", op_label, old_contract, contract, rolled_label),
+        )
+        .await?;
+    let message_id = status_msg.id;
+    let decimals = cfg.display_max_decimals;
+
+    // Прогресс не показываем: итог заменит сообщение целиком
+    let progress_callback: HedgeProgressCallback = Box::new(|_update: HedgeProgressUpdate| async { Ok::<(), anyhow::Error>(()) }.boxed());
+    let hedger = Hedger::new((*exchange).clone(), (*cfg).clone());
+    tokio::spawn(async move {
+        let text = match hedger.roll_futures_leg(original, &contract, rolled_id, progress_callback, db.as_ref()).await {
+            Ok(new_qty) => format!(
+                "✅ Хедж {} перенесен: {} → {}, зашорчено {}.\nНовая операция: {}",
+                op_label, old_contract, contract, format_qty(new_qty, decimals), rolled_label
+            ),
+            Err(e) => {
+                warn!("op_id:{}: Roll into op_id:{} failed: {}", operation_id, rolled_id, e);
+                format!("❌ Роллирование {} не выполнено: {}", op_label, e)
+            }
+        };
+        if let Err(e) = bot.edit_message_text(chat_id, message_id, text).reply_markup(navigation::make_main_menu_keyboard()).await {
+            warn!("op_id:{}: Failed to edit roll result message: {}", operation_id, e);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roll_args_require_id_and_contract() {
+        assert_eq!(parse_roll_args("42 btcusdt-26dec25"), Some((42, "BTCUSDT-26DEC25".to_string())));
+        assert_eq!(parse_roll_args("42"), None);
+        assert_eq!(parse_roll_args("abc BTCUSDT"), None);
+        assert_eq!(parse_roll_args("42 BTCUSDT extra"), None);
+    }
+}
@@ -0,0 +1,185 @@
+// src/notifier/spot_orphan.rs
+
+//! Спот куплен, а фьючерсная нога не выставлена (статус SpotOnlyOrphan): предупреждение и довыставление фьючерса.
+
+use crate::notifier::{callback_data, navigation, OperationType, RunningOperationGuard, RunningOperationInfo, RunningOperations, TradingHalt};
+use crate::notifier::utils::{format_qty, operation_label};
+use crate::config::Config;
+use crate::exchange::Exchange;
+use crate::hedger::{HedgeProgressCallback, HedgeProgressUpdate, HedgeStage, Hedger, SpotOnlyOrphan, WatcherRegistry};
+use crate::storage::{Db, OperationStatus, get_hedge_operation_by_id};
+use futures::future::FutureExt;
+use std::sync::Arc;
+use tokio::sync::Mutex as TokioMutex;
+use teloxide::prelude::*;
+use teloxide::types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup};
+use tracing::{error, info, warn};
+
+/// Клавиатура предупреждения: довыставить фьючерсную ногу + главное меню
+pub fn make_spot_orphan_keyboard(operation_id: i64) -> InlineKeyboardMarkup {
+    let mut rows = vec![vec![InlineKeyboardButton::callback(
+        "🩹 Выставить фьючерс сейчас",
+        format!("{}{}", callback_data::PREFIX_RESUME_FUTURES_LEG, operation_id),
+    )]];
+    rows.extend(navigation::make_main_menu_keyboard().inline_keyboard);
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// Текст предупреждения о незахеджированном споте
+pub fn format_spot_orphan_warning(op_label: &str, symbol: &str, orphan: &SpotOnlyOrphan, decimals: u32) -> String {
+    format!(
+        "🚨 ВНИМАНИЕ: спот куплен, но фьючерсная нога не выставлена!\n\
+         Операция {} ({}): куплено {} {}, зашорчено {}.\n\
+         Позиция НЕ захеджирована — падение цены приведет к убытку.\n\
+         Причина: {}\n\n\
+         Нажмите «Выставить фьючерс сейчас», чтобы повторить только фьючерсную часть, или продайте спот вручную.",
+        op_label, symbol,
+        format_qty(orphan.spot_filled_qty, decimals), symbol,
+        format_qty(orphan.futures_filled_qty, decimals),
+        orphan.reason,
+    )
+}
+
+/// Обработчик кнопки довыставления фьючерсной ноги (префикс resume_fut_)
+pub async fn handle_resume_futures_leg_callback<E>(
+    bot: Bot,
+    q: CallbackQuery,
+    exchange: Arc<E>,
+    running_operations: RunningOperations,
+    trading_halt: TradingHalt,
+    watchers: WatcherRegistry,
+    cfg: Arc<Config>,
+    db: Arc<Db>,
+) -> anyhow::Result<()>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let (Some(data), Some(msg)) = (q.data.as_deref(), q.message.as_ref()) else {
+        warn!("CallbackQuery missing data or message in handle_resume_futures_leg_callback");
+        bot.answer_callback_query(q.id).await?;
+        return Ok(());
+    };
+    let chat_id = msg.chat().id;
+    let message_id = msg.id();
+
+    let Some(operation_id) = data
+        .strip_prefix(callback_data::PREFIX_RESUME_FUTURES_LEG)
+        .and_then(|s| s.parse::<i64>().ok())
+    else {
+        error!("Failed to parse operation_id from resume futures callback data: {}", data);
+        bot.answer_callback_query(q.id).text("Ошибка: Неверный ID операции.").await?;
+        return Ok(());
+    };
+    info!("op_id:{}: User {} requested futures leg resume", operation_id, chat_id);
+
+    // --- Аварийная остановка торговли: фьючерсная нога не выставляется, кнопка остается для повтора после /resume ---
+    if let Err(halted_text) = trading_halt.ensure_trading_allowed() {
+        info!("op_id:{}: Futures leg resume rejected: trading halted", operation_id);
+        bot.answer_callback_query(q.id).text(halted_text).show_alert(true).await?;
+        return Ok(());
+    }
+
+    let operation = match get_hedge_operation_by_id(db.as_ref(), operation_id).await {
+        Ok(Some(op)) if op.chat_id == chat_id.0 => op,
+        Ok(_) => {
+            bot.answer_callback_query(q.id).text("Операция не найдена.").show_alert(true).await?;
+            return Ok(());
+        }
+        Err(e) => {
+            error!("op_id:{}: DB error loading operation for futures resume: {}", operation_id, e);
+            bot.answer_callback_query(q.id).text("Ошибка БД.").show_alert(true).await?;
+            return Ok(());
+        }
+    };
+    if !operation.has_status(OperationStatus::SpotOnlyOrphan) {
+        bot.answer_callback_query(q.id)
+            .text("Фьючерсная нога уже выставляется или операция не ждет ее.")
+            .show_alert(true)
+            .await?;
+        return Ok(());
+    }
+
+    let op_label = operation_label(operation.op_ref.as_deref(), operation_id);
+    let op_ref = operation.op_ref.clone();
+    let symbol = operation.base_symbol.clone();
+    let spot_filled_qty = operation.spot_filled_qty;
+    let decimals = cfg.display_max_decimals;
+    let _ = bot.edit_message_text(chat_id, message_id, format!("⏳ Выставление фьючерсной ноги {} ({})...", op_label, symbol))
+        .reply_markup(InlineKeyboardMarkup::new(Vec::<Vec<InlineKeyboardButton>>::new()))
+        .await;
+    bot.answer_callback_query(q.id).await?;
+
+    // Прогресс не показываем: итог сообщения заменит статус целиком
+    let progress_callback: HedgeProgressCallback = Box::new(|_update: HedgeProgressUpdate| async { Ok::<(), anyhow::Error>(()) }.boxed());
+    let hedger = Hedger::new((*exchange).clone(), (*cfg).clone()).with_watchers(watchers);
+    let active_order_storage = hedger.active_order_storage();
+    let stage_storage = hedger.stage_storage();
+    *stage_storage.lock().await = HedgeStage::Futures; // Спот уже куплен: отмена бросает только фьючерсную ногу
+
+    // Как у обычного хеджа: задача видна в /active и отменяется кнопкой; запись вставляется под блокировкой
+    let mut ops_guard = running_operations.lock().await;
+    let cleanup_guard = RunningOperationGuard::new(running_operations.clone(), chat_id, operation_id);
+    let symbol_for_task = symbol.clone();
+    let task = tokio::spawn(async move {
+        let symbol = symbol_for_task;
+        let result = hedger.resume_futures_leg(operation, progress_callback, db.as_ref()).await;
+        drop(cleanup_guard);
+        let (text, keyboard) = match result {
+            Ok(total_futures_qty) => {
+                info!("op_id:{}: Futures leg resumed, total futures {:.8}", operation_id, total_futures_qty);
+                (
+                    format!("✅ Фьючерсная нога {} ({}) выставлена: зашорчено {}. Позиция захеджирована.", op_label, symbol, format_qty(total_futures_qty, decimals)),
+                    navigation::make_main_menu_keyboard(),
+                )
+            }
+            Err(e) => match e.downcast_ref::<SpotOnlyOrphan>() {
+                Some(orphan) => {
+                    warn!("op_id:{}: Futures leg resume failed, spot still unhedged: {}", operation_id, orphan);
+                    (format_spot_orphan_warning(&op_label, &symbol, orphan, decimals), make_spot_orphan_keyboard(operation_id))
+                }
+                None => {
+                    warn!("op_id:{}: Futures leg resume failed: {}", operation_id, e);
+                    (
+                        format!("❌ Не удалось выставить фьючерсную ногу {} ({}): {}", op_label, symbol, e),
+                        make_spot_orphan_keyboard(operation_id),
+                    )
+                }
+            },
+        };
+        if let Err(e) = bot.edit_message_text(chat_id, message_id, text).reply_markup(keyboard).await {
+            warn!("op_id:{}: Failed to edit futures resume result message: {}", operation_id, e);
+        }
+    });
+    let info = RunningOperationInfo {
+        handle: task.abort_handle(),
+        operation_id,
+        op_ref,
+        operation_type: OperationType::Hedge,
+        symbol,
+        bot_message_id: message_id.0,
+        total_filled_spot_qty: Arc::new(TokioMutex::new(spot_filled_qty)),
+        active_order: active_order_storage,
+        stage: stage_storage,
+    };
+    ops_guard.insert((chat_id, operation_id), info);
+    drop(ops_guard);
+    info!("op_id:{}: Stored running futures resume info.", operation_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warning_shows_filled_legs_and_resume_button() {
+        let orphan = SpotOnlyOrphan { spot_filled_qty: 0.5, futures_filled_qty: 0.1, reason: "price band".to_string() };
+
+        let text = format_spot_orphan_warning("BTC-0101-01 (ID:7)", "BTC", &orphan, 4);
+        let keyboard = make_spot_orphan_keyboard(7);
+
+        assert!(text.contains("куплено 0.5 BTC, зашорчено 0.1"), "{}", text);
+        assert!(text.contains("price band"));
+        assert_eq!(keyboard.inline_keyboard.len(), 1 + navigation::make_main_menu_keyboard().inline_keyboard.len());
+    }
+}
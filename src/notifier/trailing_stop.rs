@@ -0,0 +1,113 @@
+// src/notifier/trailing_stop.rs
+
+//! Опциональная защита фьючерсной ноги трейлинг-стопом после завершения хеджирования.
+
+use crate::notifier::{StateStorage, callback_data, navigation};
+use crate::config::Config;
+use crate::exchange::Exchange;
+use crate::storage::{Db, OperationStatus, get_hedge_operation_by_id, set_trailing_stop_active};
+use std::sync::Arc;
+use teloxide::prelude::*;
+use teloxide::types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup};
+use tracing::{error, info, warn};
+
+/// Клавиатура итогового сообщения хеджа: предложение установить трейлинг-стоп + главное меню
+pub fn make_completed_hedge_keyboard(operation_id: i64, cfg: &Config) -> InlineKeyboardMarkup {
+    let mut rows = vec![vec![InlineKeyboardButton::callback(
+        format!("🛡 Трейлинг-стоп ({:.1}%)", cfg.trailing_stop_distance_ratio * 100.0),
+        format!("{}{}", callback_data::PREFIX_TRAILING_STOP, operation_id),
+    )]];
+    rows.extend(navigation::make_main_menu_keyboard().inline_keyboard);
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// Обработчик колбэка установки трейлинг-стопа (префикс tstop_)
+pub async fn handle_trailing_stop_callback<E>(
+    bot: Bot,
+    q: CallbackQuery,
+    exchange: Arc<E>,
+    _state_storage: StateStorage,
+    cfg: Arc<Config>,
+    db: Arc<Db>,
+) -> anyhow::Result<()>
+where
+    E: Exchange + Clone + Send + Sync + 'static,
+{
+    let (Some(data), Some(msg)) = (q.data.as_deref(), q.message.as_ref()) else {
+        warn!("CallbackQuery missing data or message in handle_trailing_stop_callback");
+        bot.answer_callback_query(q.id).await?;
+        return Ok(());
+    };
+    let chat_id = msg.chat().id;
+
+    let Some(operation_id) = data
+        .strip_prefix(callback_data::PREFIX_TRAILING_STOP)
+        .and_then(|s| s.parse::<i64>().ok())
+    else {
+        error!("Failed to parse operation_id from trailing stop callback data: {}", data);
+        bot.answer_callback_query(q.id).text("Ошибка: Неверный ID операции.").await?;
+        return Ok(());
+    };
+    info!("op_id:{}: User {} requested trailing stop", operation_id, chat_id);
+
+    let operation = match get_hedge_operation_by_id(db.as_ref(), operation_id).await {
+        Ok(Some(op)) if op.chat_id == chat_id.0 => op,
+        Ok(_) => {
+            bot.answer_callback_query(q.id).text("Операция не найдена.").show_alert(true).await?;
+            return Ok(());
+        }
+        Err(e) => {
+            error!("op_id:{}: DB error loading operation for trailing stop: {}", operation_id, e);
+            bot.answer_callback_query(q.id).text("Ошибка БД.").show_alert(true).await?;
+            return Ok(());
+        }
+    };
+    if !operation.has_status(OperationStatus::Completed) || operation.unhedged_op_id.is_some() {
+        bot.answer_callback_query(q.id)
+            .text("Трейлинг-стоп доступен только для завершенного и не расхеджированного хеджа.")
+            .show_alert(true)
+            .await?;
+        return Ok(());
+    }
+
+    let futures_symbol = operation.futures_contract();
+    let last_price = match exchange.get_futures_ticker(&futures_symbol).await {
+        Ok(ticker) if ticker.last_price > 0.0 => ticker.last_price,
+        Ok(ticker) => {
+            warn!("op_id:{}: Invalid futures last price {} for trailing stop", operation_id, ticker.last_price);
+            bot.answer_callback_query(q.id).text("Не удалось получить цену фьючерса.").show_alert(true).await?;
+            return Ok(());
+        }
+        Err(e) => {
+            warn!("op_id:{}: Failed to get futures ticker for trailing stop: {}", operation_id, e);
+            bot.answer_callback_query(q.id).text("Не удалось получить цену фьючерса.").show_alert(true).await?;
+            return Ok(());
+        }
+    };
+    let distance = last_price * cfg.trailing_stop_distance_ratio;
+
+    let result_line = match exchange.set_trailing_stop(&futures_symbol, distance).await {
+        Ok(()) => {
+            if let Err(e) = set_trailing_stop_active(db.as_ref(), cfg.environment(), &futures_symbol, true).await {
+                error!("op_id:{}: Failed to record trailing stop state in DB: {}", operation_id, e);
+            }
+            info!("op_id:{}: Trailing stop set for {} (distance {:.8})", operation_id, futures_symbol, distance);
+            format!(
+                "🛡 Трейлинг-стоп для {} установлен: {:.4} {} от цены.\nСтоп действует на всю позицию {} (все открытые хеджи контракта).",
+                futures_symbol, distance, cfg.quote_currency, futures_symbol
+            )
+        }
+        Err(e) => {
+            warn!("op_id:{}: Failed to set trailing stop: {}", operation_id, e);
+            format!("⚠️ Не удалось установить трейлинг-стоп: {}", e)
+        }
+    };
+
+    let base_text = msg.regular_message().and_then(|m| m.text()).unwrap_or_default();
+    let new_text = format!("{}\n\n{}", base_text, result_line);
+    let _ = bot.edit_message_text(chat_id, msg.id(), new_text)
+        .reply_markup(navigation::make_main_menu_keyboard())
+        .await;
+    bot.answer_callback_query(q.id).await?;
+    Ok(())
+}
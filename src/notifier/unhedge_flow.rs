@@ -1,18 +1,21 @@
 // src/notifier/unhedge_flow.rs
 use crate::notifier::{
-    StateStorage, UserState, RunningOperations, callback_data, navigation,
+    StateStorage, UserState, RunningOperations, TradingHalt, alerts, callback_data, navigation,
 };
 use crate::config::Config;
 use crate::exchange::Exchange;
 use crate::storage::{
-    Db, HedgeOperation, get_completed_unhedged_ops_for_symbol,
-    get_all_completed_unhedged_ops, get_hedge_operation_by_id,
+    Db, HedgeOperation, OperationStatus, get_completed_unhedged_ops_for_symbol,
+    get_all_completed_unhedged_ops, get_hedge_operation_by_id, get_hedge_status_counts,
 };
 // --- ДОБАВЛЕНЫ НУЖНЫЕ ИМПОРТЫ ---
 use crate::hedger::{
-    Hedger, HedgeProgressCallback, HedgeProgressUpdate, ORDER_FILL_TOLERANCE
+    Hedger, HedgeProgressCallback, HedgeProgressUpdate, PriceGuardHit, ORDER_FILL_TOLERANCE
 };
-use std::{collections::HashMap, sync::Arc};
+use crate::notifier::utils::{delete_user_message, describe_error, edit_message_text_safe, format_qty, operation_label};
+use crate::notifier::edit_throttle::{ChatEditClocks, EditThrottle};
+use crate::webservice_hedge::{run_websocket_operation, WsOperation};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use chrono::{Utc, TimeZone, LocalResult};
 use futures::future::FutureExt; // Для .boxed()
 // --- КОНЕЦ ДОБАВЛЕННЫХ ИМПОРТОВ ---
@@ -26,6 +29,29 @@ use tracing::{info, warn, error};
 
 // --- Вспомогательные функции --- (без изменений)
 
+/// Итог расхеджирования, остановленного ограничителем цены: реальная позиция после частичной продажи спота
+/// (проданный объем уже списан с операции, шорт не откуплен)
+fn format_unhedge_guard_halt(symbol: &str, op_label: &str, operation: &HedgeOperation, guard_hit: &PriceGuardHit, decimals: u32) -> String {
+    let mut text = format!(
+        "⛔ Расхеджирование {} (из операции {}) остановлено ограничителем цены {}: для продажи нужна цена {}.\n",
+        symbol, op_label, guard_hit.guard_price, guard_hit.required_price,
+    );
+    if guard_hit.filled_qty <= ORDER_FILL_TOLERANCE {
+        text.push_str("Спот не продан, фьючерс не откуплен — хедж остается открытым.");
+        return text;
+    }
+    let remaining_spot_qty = (operation.spot_filled_qty - guard_hit.filled_qty).max(0.0);
+    let short_qty = operation.target_futures_qty;
+    text.push_str(&format!(
+        "Спота продано {} из {}, фьючерс не откуплен: на счете {} {} против шорта {} — нетто-шорт {}.\n\
+         Повторное расхеджирование продаст оставшийся спот и откупит шорт.",
+        format_qty(guard_hit.filled_qty, decimals), format_qty(guard_hit.target_qty, decimals),
+        format_qty(remaining_spot_qty, decimals), symbol, format_qty(short_qty, decimals),
+        format_qty((short_qty - remaining_spot_qty).max(0.0), decimals),
+    ));
+    text
+}
+
 fn make_unhedge_asset_selection_keyboard(available_symbols: &[String]) -> InlineKeyboardMarkup {
     let mut sorted_symbols = available_symbols.to_vec();
     sorted_symbols.sort();
@@ -51,7 +77,7 @@ fn make_unhedge_selection_keyboard(operations: &[HedgeOperation]) -> InlineKeybo
             _ => Utc::now(),
         };
         let date_str = timestamp_dt.format("%y-%m-%d %H:%M").to_string();
-        let label = format!("ID:{} {:.4} {} ({})", op.id, op.target_futures_qty, op.base_symbol, date_str);
+        let label = format!("{} {:.4} {} ({})", operation_label(op.op_ref.as_deref(), op.id), op.target_futures_qty, op.base_symbol, date_str);
         let callback_data_op = format!("{}{}", callback_data::PREFIX_UNHEDGE_OP_SELECT, op.id);
         buttons.push(vec![InlineKeyboardButton::callback(label, callback_data_op)]);
     }
@@ -74,6 +100,7 @@ async fn prompt_operation_selection(
     chat_id: ChatId,
     symbol: &str,
     operations: Vec<HedgeOperation>,
+    spot_price_guard: Option<f64>,
     state_storage: StateStorage,
     message_id_to_edit: Option<MessageId>,
 ) -> anyhow::Result<()> {
@@ -93,6 +120,7 @@ async fn prompt_operation_selection(
           state_guard.insert(chat_id, UserState::AwaitingUnhedgeOperationSelection {
               symbol: symbol.to_string(),
               operations,
+              spot_price_guard,
               last_bot_message_id: Some(bot_msg_id.0),
           });
           info!("User state for {} set to AwaitingUnhedgeOperationSelection for symbol {}", chat_id, symbol);
@@ -100,6 +128,49 @@ async fn prompt_operation_selection(
       Ok(())
 }
 
+/// Формирует пояснение, почему нет операций для расхеджирования (с учетом операций в других статусах)
+async fn build_nothing_to_unhedge_text(db: &Db, chat_id: ChatId, symbol: Option<&str>) -> String {
+    let mut text = match symbol {
+        Some(s) => format!("ℹ️ Не найдено завершенных операций хеджирования для {}, которые можно было бы расхеджировать.", s),
+        None => "ℹ️ Не найдено завершенных операций хеджирования, которые можно было бы расхеджировать.".to_string(),
+    };
+
+    let counts = match get_hedge_status_counts(db, chat_id.0, symbol).await {
+        Ok(c) => c,
+        Err(e) => {
+            warn!("Failed to query hedge status counts for chat_id {}: {}", chat_id, e);
+            return text;
+        }
+    };
+
+    let mut explanations: Vec<String> = Vec::new();
+    for entry in &counts {
+        let Ok(status) = entry.status.parse::<OperationStatus>() else {
+            continue;
+        };
+        let line = match (status, entry.already_unhedged) {
+            (OperationStatus::Completed, true) => format!("• Уже расхеджировано: {} шт.", entry.count),
+            (OperationStatus::Running, _) => format!("• Выполняется: {} шт. — дождитесь завершения (см. /active).", entry.count),
+            (OperationStatus::PendingFutures, _) => format!("• Ждут исполнения фьючерсного ордера: {} шт. — ордер оставлен на бирже.", entry.count),
+            (OperationStatus::SpotOnlyOrphan, _) => format!("• 🚨 Спот без фьючерса: {} шт. — выставьте фьючерсную ногу или продайте спот вручную.", entry.count),
+            (OperationStatus::Failed, _) => format!("• Завершились ошибкой: {} шт. — такие операции нельзя расхеджировать.", entry.count),
+            (OperationStatus::Cancelled, _) => format!("• Отменены: {} шт. — такие операции нельзя расхеджировать.", entry.count),
+            (OperationStatus::Interrupted, _) => format!("• Прерваны: {} шт. — проверьте позиции на бирже вручную.", entry.count),
+            _ => continue,
+        };
+        explanations.push(line);
+    }
+
+    if explanations.is_empty() {
+        text.push_str("\n\nСначала откройте хедж командой /hedge.");
+    } else {
+        text.push_str("\n\nВаши операции в других статусах:\n");
+        text.push_str(&explanations.join("\n"));
+        text.push_str("\n\nРасхеджировать можно только успешно завершенные операции.");
+    }
+    text
+}
+
 /// Запускает фоновую задачу расхеджирования (без изменений)
 async fn spawn_unhedge_task<E>(
     bot: Bot,
@@ -107,15 +178,23 @@ async fn spawn_unhedge_task<E>(
     cfg: Arc<Config>,
     db: Arc<Db>,
     _running_operations: RunningOperations, // Пока не используется для отслеживания unhedge
+    edit_clocks: ChatEditClocks,
     chat_id: ChatId,
     op_to_unhedge: HedgeOperation, // Принимаем всю операцию
+    spot_price_guard: Option<f64>, // Минимальная цена продажи спота
     message_id_to_edit: MessageId,
 )
 where
     E: Exchange + Clone + Send + Sync + 'static,
 {
     let hedger = Hedger::new((*exchange).clone(), (*cfg).clone());
+    // Ограничитель соблюдает только цикл перестановки лимиток: с ним WS-путь не используется
+    let use_websocket = cfg.unhedge_via_websocket(op_to_unhedge.futures_only) && spot_price_guard.is_none();
+    let exchange_for_ws = exchange.clone();
+    let cfg_for_ws = cfg.clone();
+    let cfg_for_alerts = cfg.clone();
     let original_op_id = op_to_unhedge.id;
+    let op_label = operation_label(op_to_unhedge.op_ref.as_deref(), original_op_id);
     let symbol = op_to_unhedge.base_symbol.clone(); // Клон символа для задачи
 
     // --- Клоны для колбэка прогресса ---
@@ -123,24 +202,35 @@ where
     let symbol_for_callback = symbol.clone();
     let cfg_for_callback = cfg.clone();
     let original_op_for_callback = op_to_unhedge.clone();
+    let original_op_for_report = op_to_unhedge.clone(); // Объемы операции для итога остановки ограничителем
+    let op_label_for_callback = op_label.clone();
     // --- Конец клонов для колбэка ---
 
     // --- Клоны для основной задачи spawn ---
     let bot_for_spawn = bot.clone();
     let db_for_spawn = db.clone();
+    let display_max_decimals = cfg.display_max_decimals;
     // `op_to_unhedge` и `symbol` будут перемещены в spawn ниже
     // --- Конец клонов для основной задачи spawn ---
 
 
+    let progress_throttle: EditThrottle<(String, InlineKeyboardMarkup)> = edit_clocks.throttle(chat_id, Duration::from_millis(cfg.min_edit_interval_ms));
+    let progress_throttle_for_callback = progress_throttle.clone();
+
     // --- Создание колбэка прогресса для расхеджирования ---
     let progress_callback: HedgeProgressCallback = Box::new(move |update: HedgeProgressUpdate| {
         // Используем клоны, созданные специально для колбэка
         let bot_cb = bot_for_callback.clone(); // Клонируем еще раз внутри, т.к. async move
+        let throttle_cb = progress_throttle_for_callback.clone();
         let qc = cfg_for_callback.quote_currency.clone(); // Используем клон cfg
+        let edit_max_retries = cfg_for_callback.progress_edit_max_retries;
+        let edit_min_delay = Duration::from_millis(cfg_for_callback.min_edit_interval_ms);
+        let qty_decimals = cfg_for_callback.display_max_decimals;
         let symbol_cb = symbol_for_callback.clone(); // Используем клон symbol
         let msg_id_cb = message_id_to_edit; // Копируем ID сообщения
         let chat_id_cb = chat_id; // Копируем ID чата
         let operation_id_cb = original_op_id; // Копируем ID операции
+        let op_label_cb = op_label_for_callback.clone();
         // Используем целевое количество спота из оригинальной операции для расчета общего %
         let _overall_target_qty = original_op_for_callback.spot_filled_qty;
 
@@ -160,10 +250,10 @@ where
 
             // --- Адаптированный текст для Расхеджирования ---
             let text = format!(
-                 "⏳ Расхеджирование ID:{} {} ({}) в процессе...\nРын.цена: {:.2}\nОрдер на ПРОДАЖУ: {:.2} {}\nИсполнено (тек.ордер): {:.6}/{:.6} ({:.1}%)",
-                 operation_id_cb, progress_bar, symbol_cb, // Используем symbol_cb
+                 "⏳ Расхеджирование {} {} ({}) в процессе...\nРын.цена: {:.2}\nОрдер на ПРОДАЖУ: {:.2} {}\nИсполнено (тек.ордер): {}/{} ({:.1}%)",
+                 op_label_cb, progress_bar, symbol_cb, // Используем symbol_cb
                  update.current_spot_price, update.new_limit_price, status_text,
-                 update.filled_qty, update.target_qty, current_order_filled_percent
+                 format_qty(update.filled_qty, qty_decimals), format_qty(update.target_qty, qty_decimals), current_order_filled_percent
                  // Можно добавить общий прогресс, если передавать cumulative_filled_qty в update
                  // / {:.6} (Общий: {:.1}%)", ..., _overall_target_qty, overall_filled_percent
             );
@@ -175,14 +265,12 @@ where
             // let kb = InlineKeyboardMarkup::new(vec![vec![cancel_button]]);
             let kb = InlineKeyboardMarkup::new(Vec::<Vec<InlineKeyboardButton>>::new()); // Пока без кнопки отмены
 
-            if let Err(e) = bot_cb.edit_message_text(chat_id_cb, msg_id_cb, text) // Используем bot_cb
-                .reply_markup(kb)
-                .await {
-                // Игнорируем ошибку "message is not modified"
-                if !e.to_string().contains("message is not modified") {
+            // Правки схлопываются троттлером (не чаще min_edit_interval_ms)
+            throttle_cb.submit((text, kb), move |(text, kb)| async move {
+                if let Err(e) = edit_message_text_safe(&bot_cb, chat_id_cb, msg_id_cb, text, kb, edit_max_retries, edit_min_delay).await {
                     warn!("op_id:{}: Unhedge Progress callback failed: {}", operation_id_cb, e);
                 }
-            }
+            }).await;
             Ok(())
         }.boxed() // Используем .boxed() для преобразования в BoxFuture
     });
@@ -193,13 +281,39 @@ where
         // `op_to_unhedge` перемещается сюда
         // `db_for_spawn` перемещается сюда
         // `progress_callback` перемещается сюда
-        match hedger.run_unhedge(op_to_unhedge, db_for_spawn.as_ref(), progress_callback).await {
-            Ok((sold_spot_qty, bought_fut_qty)) => {
+        // WS-путь (use_websocket_hedge) возвращает только итог: объемы исполняет и сверяет сама задача
+        let unhedge_result = if use_websocket {
+            info!("op_id:{}: Routing unhedge to WebSocket task.", original_op_id);
+            // Как и run_unhedge, снимаем трейлинг-стоп до закрытия фьючерса
+            hedger.release_trailing_stop(db_for_spawn.as_ref(), original_op_id, &op_to_unhedge.futures_contract()).await;
+            let operation = WsOperation::Unhedge { original_operation: Box::new(op_to_unhedge) };
+            run_websocket_operation(operation, cfg_for_ws, db_for_spawn.clone(), exchange_for_ws, progress_callback)
+                .await
+                .map(|()| None)
+        } else {
+            hedger.run_unhedge(op_to_unhedge, db_for_spawn.as_ref(), progress_callback, spot_price_guard).await.map(Some)
+        };
+        progress_throttle.close().await; // Отложенная правка прогресса не должна затереть итоговое сообщение
+        match unhedge_result {
+            Ok(outcome) => {
                 info!("Unhedge OK for original op_id: {}", original_op_id);
-                let text = format!(
-                    "✅ Расхеджирование {} (из операции ID:{}) завершено:\n\n🟢 Спот продано: {:.8}\n🔴 Фьюч куплено: {:.8}",
-                    symbol, original_op_id, sold_spot_qty, bought_fut_qty // `symbol` перемещен сюда
-                );
+                let text = match outcome {
+                    Some(outcome) => {
+                        let mut text = format!(
+                            "✅ Расхеджирование {} (из операции {}) завершено:\n\n🟢 Спот продано: {}\n🔴 Фьюч куплено: {}",
+                            symbol, op_label, // `symbol` перемещен сюда
+                            format_qty(outcome.spot_sold, display_max_decimals), format_qty(outcome.fut_bought, display_max_decimals)
+                        );
+                        if outcome.spot_dust > 0.0 {
+                            text.push_str(&format!(
+                                "\n⚪ ~{} {} осталось непроданным (меньше минимального ордера)",
+                                format_qty(outcome.spot_dust, display_max_decimals), symbol
+                            ));
+                        }
+                        text
+                    }
+                    None => format!("✅ WS Расхеджирование {} (из операции {}) завершено.", symbol, op_label),
+                };
                 // Редактируем исходное сообщение с результатом
                 // `bot_for_spawn` перемещается сюда
                 let _ = bot_for_spawn.edit_message_text(chat_id, message_id_to_edit, text)
@@ -207,9 +321,17 @@ where
                              .await
                              .map_err(|e| warn!("op_id:{}: Failed edit success unhedge message: {}", original_op_id, e));
             }
-            Err(e) => {
+            Err(e) => if let Some(guard_hit) = e.downcast_ref::<PriceGuardHit>() {
+                info!("op_id:{}: Unhedge halted by price guard: {}", original_op_id, guard_hit);
+                let text = format_unhedge_guard_halt(&symbol, &op_label, &original_op_for_report, guard_hit, display_max_decimals);
+                let _ = bot_for_spawn.edit_message_text(chat_id, message_id_to_edit, text)
+                             .reply_markup(navigation::make_main_menu_keyboard())
+                             .await
+                             .map_err(|e| warn!("op_id:{}: Failed edit halted unhedge message: {}", original_op_id, e));
+            } else {
                 error!("Unhedge FAILED for original op_id: {}: {}", original_op_id, e);
-                let error_text = format!("❌ Ошибка расхеджирования операции ID:{}: {}", original_op_id, e);
+                let error_text = format!("❌ Ошибка расхеджирования операции {}: {}", op_label, describe_error(&e));
+                alerts::send_alert(&bot_for_spawn, &cfg_for_alerts, chat_id, &error_text).await;
                 // Редактируем исходное сообщение с ошибкой
                 // `bot_for_spawn` используется здесь (если не был использован в Ok)
                 let _ = bot_for_spawn.edit_message_text(chat_id, message_id_to_edit, error_text)
@@ -218,8 +340,8 @@ where
                              .map_err(|e| warn!("op_id:{}: Failed edit error unhedge message: {}", original_op_id, e));
             }
         }
-        // TODO: Удалить информацию об операции из running_operations, если она туда добавлялась для unhedge
-        // (Пока не добавлялась, т.к. нет отмены для unhedge)
+        // Расхеджирование не регистрируется в running_operations (нет отмены для unhedge),
+        // поэтому удалять нечего. При регистрации использовать RunningOperationGuard, как в hedge_flow_spawners.
     });
 } // Конец spawn_unhedge_task
 /// Определяет, нужно ли выбирать актив или можно сразу показать операции
@@ -228,6 +350,7 @@ async fn start_unhedge_asset_or_op_selection(
     chat_id: ChatId,
     state_storage: StateStorage, // Тип StateStorage уже Arc<TokioRwLock<...>>
     db: Arc<Db>,
+    environment: &str, // Только операции текущего окружения биржи (testnet / mainnet)
     message_id_to_edit: Option<MessageId>,
 ) -> anyhow::Result<()>
 {
@@ -244,11 +367,11 @@ async fn start_unhedge_asset_or_op_selection(
     }
     let bot_msg_id = current_message_id.ok_or_else(|| anyhow::anyhow!("Failed to obtain message ID for unhedge flow"))?;
 
-    match get_all_completed_unhedged_ops(db.as_ref(), chat_id.0).await {
+    match get_all_completed_unhedged_ops(db.as_ref(), chat_id.0, environment).await {
         Ok(all_operations) => {
             if all_operations.is_empty() {
                 info!("No completed hedge operations found for chat_id: {}", chat_id);
-                let text = "ℹ️ Не найдено завершенных операций хеджирования, которые можно было бы расхеджировать.";
+                let text = build_nothing_to_unhedge_text(db.as_ref(), chat_id, None).await;
                 let keyboard = InlineKeyboardMarkup::new(vec![vec![
                     InlineKeyboardButton::callback("⬅️ Назад", callback_data::BACK_TO_MAIN)
                 ]]);
@@ -273,9 +396,9 @@ async fn start_unhedge_asset_or_op_selection(
 
                 if symbol_operations.len() == 1 {
                     let op_to_confirm = symbol_operations.into_iter().next().unwrap();
-                    prompt_unhedge_confirmation(&bot, chat_id, op_to_confirm, state_storage, Some(bot_msg_id)).await?;
+                    prompt_unhedge_confirmation(&bot, chat_id, op_to_confirm, None, state_storage, Some(bot_msg_id)).await?;
                 } else {
-                    prompt_operation_selection(&bot, chat_id, &symbol, symbol_operations, state_storage, Some(bot_msg_id)).await?;
+                    prompt_operation_selection(&bot, chat_id, &symbol, symbol_operations, None, state_storage, Some(bot_msg_id)).await?;
                 }
 
             } else {
@@ -315,8 +438,10 @@ async fn find_and_process_symbol_operations(
     bot: Bot,
     chat_id: ChatId,
     symbol: String,
+    spot_price_guard: Option<f64>,
     state_storage: StateStorage, // Тип StateStorage уже Arc<TokioRwLock<...>>
     db: Arc<Db>,
+    environment: &str,
     message_id_to_edit: Option<MessageId>,
 ) -> anyhow::Result<()>
 {
@@ -333,10 +458,10 @@ async fn find_and_process_symbol_operations(
     }
     let bot_msg_id = bot_msg_id_opt.ok_or_else(|| anyhow::anyhow!("Failed to get bot message ID for unhedge status"))?;
 
-    match get_completed_unhedged_ops_for_symbol(db.as_ref(), chat_id.0, &symbol).await {
+    match get_completed_unhedged_ops_for_symbol(db.as_ref(), chat_id.0, &symbol, environment).await {
         Ok(operations) => {
             if operations.is_empty() {
-                let text = format!("ℹ️ Не найдено завершенных операций хеджирования для {}, которые можно было бы расхеджировать.", symbol);
+                let text = build_nothing_to_unhedge_text(db.as_ref(), chat_id, Some(&symbol)).await;
                  let keyboard = InlineKeyboardMarkup::new(vec![vec![
                      InlineKeyboardButton::callback("⬅️ Назад (в гл. меню)", callback_data::BACK_TO_MAIN)
                  ]]);
@@ -345,9 +470,9 @@ async fn find_and_process_symbol_operations(
                 { state_storage.write().await.insert(chat_id, UserState::None); }
             } else if operations.len() == 1 {
                  let op_to_unhedge = operations.into_iter().next().unwrap();
-                 prompt_unhedge_confirmation(&bot, chat_id, op_to_unhedge, state_storage, Some(bot_msg_id)).await?;
+                 prompt_unhedge_confirmation(&bot, chat_id, op_to_unhedge, spot_price_guard, state_storage, Some(bot_msg_id)).await?;
             } else {
-                 prompt_operation_selection(&bot, chat_id, &symbol, operations, state_storage, Some(bot_msg_id)).await?;
+                 prompt_operation_selection(&bot, chat_id, &symbol, operations, spot_price_guard, state_storage, Some(bot_msg_id)).await?;
             }
         }
         Err(e) => {
@@ -375,14 +500,24 @@ pub async fn handle_unhedge_command<E>(
     _exchange: Arc<E>,
     state_storage: StateStorage, // Тип StateStorage уже Arc<TokioRwLock<...>>
     _running_operations: RunningOperations,
-    _cfg: Arc<Config>,
+    cfg: Arc<Config>,
     db: Arc<Db>,
 ) -> anyhow::Result<()>
 where
     E: Exchange + Clone + Send + Sync + 'static,
 {
     let chat_id = msg.chat.id;
-    let symbol = symbol_arg.trim().to_uppercase();
+    // "/unhedge BTC @60000": минимальная цена продажи спота
+    let (symbol, spot_price_guard) = match symbol_arg.split_once('@') {
+        None => (symbol_arg.trim().to_uppercase(), None),
+        Some((symbol_part, guard_part)) => match guard_part.trim().parse::<f64>() {
+            Ok(guard) if guard > 0.0 && guard.is_finite() => (symbol_part.trim().to_uppercase(), Some(guard)),
+            _ => {
+                bot.send_message(chat_id, "⚠️ Неверная цена после «@». Пример: /unhedge BTC @60000").await?;
+                return Ok(());
+            }
+        },
+    };
 
     let mut previous_bot_message_id: Option<i32> = None;
       {
@@ -405,15 +540,18 @@ where
         if let Err(e) = bot.delete_message(chat_id, MessageId(bot_msg_id)).await { warn!("Failed delete prev bot msg: {}", e); }
     }
     let user_msg_id = msg.id;
-    if let Err(e) = bot.delete_message(chat_id, user_msg_id).await { warn!("Failed delete user command msg: {}", e); }
+    delete_user_message(&bot, &cfg, chat_id, user_msg_id, "user command msg").await;
 
 
     if symbol.is_empty() {
+        if spot_price_guard.is_some() {
+            info!("Ignoring price guard in /unhedge without symbol for chat_id: {}", chat_id);
+        }
         info!("Processing /unhedge command without symbol for chat_id: {}", chat_id);
-        start_unhedge_asset_or_op_selection(bot, chat_id, state_storage, db, None).await?;
+        start_unhedge_asset_or_op_selection(bot, chat_id, state_storage, db, cfg.environment(), None).await?;
     } else {
         info!("Processing /unhedge command for chat_id: {}, symbol: {}", chat_id, symbol);
-        find_and_process_symbol_operations(bot, chat_id, symbol, state_storage, db, None).await?;
+        find_and_process_symbol_operations(bot, chat_id, symbol, spot_price_guard, state_storage, db, cfg.environment(), None).await?;
     }
 
     Ok(())
@@ -425,7 +563,7 @@ pub async fn handle_start_unhedge_callback<E>(
     query: CallbackQuery,
     _exchange: Arc<E>,
     state_storage: StateStorage,
-    _cfg: Arc<Config>,
+    cfg: Arc<Config>,
     db: Arc<Db>,
 ) -> anyhow::Result<()>
 where
@@ -435,7 +573,7 @@ where
           let chat_id = msg.chat().id;
           info!("Processing '{}' callback for chat_id: {}", callback_data::START_UNHEDGE, chat_id);
           bot.answer_callback_query(query.id).await?;
-          start_unhedge_asset_or_op_selection(bot, chat_id, state_storage, db, Some(msg.id())).await?;
+          start_unhedge_asset_or_op_selection(bot, chat_id, state_storage, db, cfg.environment(), Some(msg.id())).await?;
       } else {
           warn!("CallbackQuery missing message in handle_start_unhedge_callback");
           bot.answer_callback_query(query.id).await?;
@@ -449,7 +587,7 @@ pub async fn handle_unhedge_asset_callback<E>(
     query: CallbackQuery,
     _exchange: Arc<E>,
     state_storage: StateStorage, // Тип StateStorage уже Arc<TokioRwLock<...>>
-    _cfg: Arc<Config>,
+    cfg: Arc<Config>,
     db: Arc<Db>,
 ) -> anyhow::Result<()>
 where
@@ -469,7 +607,7 @@ where
 
             if is_correct_state {
                  bot.answer_callback_query(query_id).await?;
-                 find_and_process_symbol_operations(bot, chat_id, symbol.to_string(), state_storage, db, Some(msg.id())).await?;
+                 find_and_process_symbol_operations(bot, chat_id, symbol.to_string(), None, state_storage, db, cfg.environment(), Some(msg.id())).await?;
                  return Ok(());
             } else {
                  warn!("User {} clicked unhedge asset button but was in wrong state", chat_id);
@@ -511,11 +649,11 @@ where
             if let Ok(operation_id) = op_id_str.parse::<i64>() {
                  info!("User {} selected operation ID {} to unhedge", chat_id, operation_id);
 
-                let op_to_confirm_opt: Option<HedgeOperation> = {
+                let (op_to_confirm_opt, spot_price_guard): (Option<HedgeOperation>, Option<f64>) = {
                      // <<< ИСПРАВЛЕНО: .await >>>
                     let state_guard = state_storage.read().await;
-                    if let Some(UserState::AwaitingUnhedgeOperationSelection { operations, .. }) = state_guard.get(&chat_id) {
-                        operations.iter().find(|op| op.id == operation_id).cloned()
+                    if let Some(UserState::AwaitingUnhedgeOperationSelection { operations, spot_price_guard, .. }) = state_guard.get(&chat_id) {
+                        (operations.iter().find(|op| op.id == operation_id).cloned(), *spot_price_guard)
                     } else {
                         warn!("User {} clicked unhedge operation selection but was in wrong state", chat_id);
                         drop(state_guard);
@@ -528,7 +666,7 @@ where
                 }; // Блокировка чтения освобождается здесь
 
                 if let Some(op) = op_to_confirm_opt {
-                    prompt_unhedge_confirmation(&bot, chat_id, op, state_storage, Some(msg.id())).await?;
+                    prompt_unhedge_confirmation(&bot, chat_id, op, spot_price_guard, state_storage, Some(msg.id())).await?;
                     bot.answer_callback_query(query_id).await?;
                     return Ok(());
                 } else {
@@ -567,21 +705,27 @@ async fn prompt_unhedge_confirmation(
     bot: &Bot,
     chat_id: ChatId,
     operation_to_unhedge: HedgeOperation,
+    spot_price_guard: Option<f64>,
     state_storage: StateStorage, // Тип StateStorage уже Arc<TokioRwLock<...>>
     message_id_to_edit: Option<MessageId>,
 ) -> anyhow::Result<()> {
     let operation_id = operation_to_unhedge.id;
+    let op_label = operation_label(operation_to_unhedge.op_ref.as_deref(), operation_id);
     let symbol = operation_to_unhedge.base_symbol.clone();
     let fut_qty = operation_to_unhedge.target_futures_qty;
     let spot_sell_qty_approx = operation_to_unhedge.spot_filled_qty;
 
+    let guard_text = match spot_price_guard {
+        Some(guard) => format!("🛡 Ограничитель: спот продается не дешевле {}; при падении цены ниже расхедж остановится.\n\n", guard),
+        None => String::new(),
+    };
     let text = format!(
-        "Подтвердите расхеджирование операции ID:{}\n\
+        "Подтвердите расхеджирование операции {}\n\
          Символ: {}\n\
          Будет продано ~{:.8} {} спота.\n\
          Будет куплено {:.8} {} фьючерса.\n\n\
-         Вы уверены?",
-        operation_id, symbol, spot_sell_qty_approx, symbol, fut_qty, symbol
+         {}Вы уверены?",
+        op_label, symbol, spot_sell_qty_approx, symbol, fut_qty, symbol, guard_text
     );
     let keyboard = make_unhedge_confirmation_keyboard(operation_id);
 
@@ -597,6 +741,7 @@ async fn prompt_unhedge_confirmation(
         let mut state_guard = state_storage.write().await;
         state_guard.insert(chat_id, UserState::AwaitingUnhedgeConfirmation {
             operation_id,
+            spot_price_guard,
             last_bot_message_id: Some(bot_msg_id.0),
         });
         info!("User state for {} set to AwaitingUnhedgeConfirmation for op_id {}", chat_id, operation_id);
@@ -612,6 +757,8 @@ pub async fn handle_unhedge_confirm_callback<E>(
     exchange: Arc<E>,
     state_storage: StateStorage, // Тип StateStorage уже Arc<TokioRwLock<...>>
     running_operations: RunningOperations,
+    trading_halt: TradingHalt,
+    edit_clocks: ChatEditClocks,
     cfg: Arc<Config>,
     db: Arc<Db>,
 ) -> anyhow::Result<()>
@@ -626,11 +773,11 @@ where
             if payload == "yes" {
                 info!("User {} confirmed unhedge operation", chat_id);
 
-                let operation_id_to_unhedge = {
+                let (operation_id_to_unhedge, spot_price_guard) = {
                      // <<< ИСПРАВЛЕНО: .await >>>
                      let state_guard = state_storage.read().await;
                       match state_guard.get(&chat_id) {
-                         Some(UserState::AwaitingUnhedgeConfirmation { operation_id, .. }) => *operation_id,
+                         Some(UserState::AwaitingUnhedgeConfirmation { operation_id, spot_price_guard, .. }) => (*operation_id, *spot_price_guard),
                          _ => {
                              warn!("User {} confirmed unhedge but was in wrong state", chat_id);
                              drop(state_guard);
@@ -647,20 +794,38 @@ where
 
                 match get_hedge_operation_by_id(db.as_ref(), operation_id_to_unhedge).await {
                      Ok(Some(original_op)) => {
-                         if original_op.status != "Completed" || original_op.unhedged_op_id.is_some() {
+                         if !original_op.has_status(OperationStatus::Completed) || original_op.unhedged_op_id.is_some() {
                              error!("Attempted to unhedge already unhedged or invalid op_id: {}", operation_id_to_unhedge);
                              let _ = bot.edit_message_text(chat_id, msg.id(), "❌ Операция уже расхеджирована или недействительна.")
                                       .reply_markup(navigation::make_main_menu_keyboard())
                                       .await;
                              bot.answer_callback_query(query_id).await?;
                              return Ok(());
+                         } else if !original_op.belongs_to_environment(cfg.environment()) {
+                             warn!("Refusing to unhedge op_id {} created on {:?} while connected to {}", operation_id_to_unhedge, original_op.environment, cfg.environment());
+                             let text = format!(
+                                 "❌ Операция создана в окружении {}, а бот подключен к {}. Расхеджирование отменено.",
+                                 original_op.environment.as_deref().unwrap_or("?"), cfg.environment()
+                             );
+                             let _ = bot.edit_message_text(chat_id, msg.id(), text)
+                                      .reply_markup(navigation::make_main_menu_keyboard())
+                                      .await;
+                             bot.answer_callback_query(query_id).await?;
+                             return Ok(());
+                         } else if let Err(halted_text) = trading_halt.ensure_trading_allowed() {
+                             info!("User {} unhedge of op_id {} rejected: trading halted", chat_id, operation_id_to_unhedge);
+                             let _ = bot.edit_message_text(chat_id, msg.id(), halted_text)
+                                      .reply_markup(navigation::make_main_menu_keyboard())
+                                      .await;
+                             bot.answer_callback_query(query_id).await?;
+                             return Ok(());
                          } else {
-                             let _ = bot.edit_message_text(chat_id, msg.id(), format!("⏳ Запуск расхеджирования операции ID:{}...", operation_id_to_unhedge))
+                             let _ = bot.edit_message_text(chat_id, msg.id(), format!("⏳ Запуск расхеджирования операции {}...", operation_label(original_op.op_ref.as_deref(), operation_id_to_unhedge)))
                                 .reply_markup(InlineKeyboardMarkup::new(Vec::<Vec<InlineKeyboardButton>>::new()))
                                 .await?;
                              spawn_unhedge_task(
                                  bot.clone(), exchange.clone(), cfg.clone(), db.clone(),
-                                 running_operations.clone(), chat_id, original_op, msg.id(),
+                                 running_operations.clone(), edit_clocks.clone(), chat_id, original_op, spot_price_guard, msg.id(),
                              ).await;
                          }
                      }
@@ -710,4 +875,47 @@ where
          return Ok(());
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guard_halt_reports_net_short_after_partial_sale() {
+        let operation = HedgeOperation {
+            id: 7,
+            chat_id: 1,
+            base_symbol: "BTC".to_string(),
+            quote_currency: "USDT".to_string(),
+            initial_sum: 100.0,
+            volatility: 0.6,
+            target_spot_qty: 1.0,
+            target_futures_qty: 1.0,
+            start_timestamp: 0,
+            status: "Completed".to_string(),
+            spot_order_id: None,
+            spot_filled_qty: 1.0,
+            futures_order_id: None,
+            futures_filled_qty: 1.0,
+            end_timestamp: None,
+            error_message: None,
+            unhedged_op_id: None,
+            op_ref: None,
+            futures_symbol: None,
+            rolled_from_op_id: None,
+            accrued_funding: 0.0,
+            environment: None,
+            futures_only: false,
+        };
+        let partial = PriceGuardHit { required_price: 95.0, guard_price: 96.0, filled_qty: 0.4, target_qty: 1.0 };
+
+        let text = format_unhedge_guard_halt("BTC", "ID:7", &operation, &partial, 4);
+        assert!(text.contains("Спота продано 0.4 из 1"), "{}", text);
+        assert!(text.contains("на счете 0.6 BTC против шорта 1 — нетто-шорт 0.4"), "{}", text);
+        assert!(!text.contains("хедж остается открытым"));
+
+        let untouched = PriceGuardHit { filled_qty: 0.0, ..partial };
+        assert!(format_unhedge_guard_halt("BTC", "ID:7", &operation, &untouched, 4).contains("хедж остается открытым"));
+    }
+}
@@ -0,0 +1,178 @@
+// src/notifier/utils.rs
+
+//! Вспомогательные функции форматирования и отправки сообщений Telegram.
+
+use crate::config::Config;
+use crate::exchange::types::ExchangeError;
+use crate::hedger::DbRecordDiverged;
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardMarkup, MessageId};
+use teloxide::{ApiError, RequestError};
+use tracing::{debug, warn};
+
+/// Сообщение пользователю, пока биржа на техническом обслуживании
+pub const EXCHANGE_MAINTENANCE_TEXT: &str = "🛠 Биржа на техническом обслуживании (exchange under maintenance), попробуйте позже.";
+
+/// Подсказка при отказе из-за режима позиций фьючерсного аккаунта
+pub const POSITION_MODE_MISMATCH_TEXT: &str = "Режим позиций фьючерса на Bybit не совпал с ордером (one-way / hedge-mode). \
+Бот определит режим заново при следующей попытке; если ошибка повторится, переключите символ в One-Way Mode в настройках деривативов Bybit.";
+
+/// Пояснение, когда сделка на бирже прошла, а запись в БД не удалась
+pub const DB_RECORD_DIVERGED_TEXT: &str = "⚠️ Сделка исполнена на бирже, но не записана в БД: состояние бота и биржи расходится. \
+Проверьте позиции на бирже и статус операции вручную.";
+
+/// Удаляет сообщение пользователя (команду или ввод), если включено delete_user_messages.
+/// Нет прав на удаление (группа без админки) или сообщение уже удалено — не ошибка, только debug
+pub async fn delete_user_message(bot: &Bot, cfg: &Config, chat_id: ChatId, message_id: MessageId, what: &str) {
+    if !cfg.delete_user_messages {
+        return;
+    }
+    match bot.delete_message(chat_id, message_id).await {
+        Ok(_) => {}
+        Err(RequestError::Api(ApiError::MessageCantBeDeleted | ApiError::MessageToDeleteNotFound)) => {
+            debug!("Cannot delete {} in chat {}: no permission or already deleted", what, chat_id);
+        }
+        Err(e) => warn!("Failed to delete {}: {}", what, e),
+    }
+}
+
+/// Пауза перед повтором правки после ошибки Telegram; None — ошибка не временная, повтор бесполезен.
+/// 429 — не раньше retry_after, сетевые ошибки — от min_delay с удвоением на каждой попытке
+pub fn edit_retry_delay(error: &RequestError, attempt: u32, min_delay: Duration) -> Option<Duration> {
+    match error {
+        RequestError::RetryAfter(seconds) => Some(seconds.duration().max(min_delay)),
+        RequestError::Network(_) | RequestError::Io(_) => Some(min_delay.saturating_mul(2u32.saturating_pow(attempt))),
+        RequestError::Api(_) | RequestError::MigrateToChatId(_) | RequestError::InvalidJson { .. } => None,
+    }
+}
+
+/// Правка сообщения прогресса с повтором при временных ошибках Telegram (не более max_retries раз).
+/// Пауза между попытками не короче min_delay (интервала троттлинга правок); "message is not modified" — не ошибка
+pub async fn edit_message_text_safe(
+    bot: &Bot,
+    chat_id: ChatId,
+    message_id: MessageId,
+    text: String,
+    keyboard: InlineKeyboardMarkup,
+    max_retries: u32,
+    min_delay: Duration,
+) -> Result<(), RequestError> {
+    let mut attempt = 0;
+    loop {
+        match bot.edit_message_text(chat_id, message_id, text.clone()).reply_markup(keyboard.clone()).await {
+            Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => return Ok(()),
+            Err(e) => match edit_retry_delay(&e, attempt, min_delay) {
+                Some(delay) if attempt < max_retries => {
+                    debug!("Edit of message {} in chat {} failed ({}), retrying in {:?}", message_id, chat_id, e, delay);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                _ => return Err(e),
+            },
+        }
+    }
+}
+
+/// Количество знаков для отображения: точность инструмента (если известна), но не больше max_decimals
+pub fn display_decimals(instrument_decimals: Option<u32>, max_decimals: u32) -> u32 {
+    instrument_decimals.map_or(max_decimals, |d| d.min(max_decimals))
+}
+
+/// Форматирует количество с заданной точностью и убирает хвостовые нули
+pub fn format_qty(value: f64, decimals: u32) -> String {
+    let formatted = format!("{:.*}", decimals as usize, value);
+    if !formatted.contains('.') {
+        return formatted;
+    }
+    let trimmed = formatted.trim_end_matches('0').trim_end_matches('.');
+    if trimmed == "-0" { "0".to_string() } else { trimmed.to_string() }
+}
+
+/// Подпись операции для сообщений: человекочитаемая ссылка и числовой ID (старые операции — только ID)
+pub fn operation_label(op_ref: Option<&str>, operation_id: i64) -> String {
+    match op_ref {
+        Some(op_ref) => format!("{} (ID:{})", op_ref, operation_id),
+        None => format!("ID:{}", operation_id),
+    }
+}
+
+/// Текст ошибки для пользователя: техобслуживание биржи — понятным сообщением, остальное как есть
+pub fn describe_error(error: &anyhow::Error) -> String {
+    if ExchangeError::is_maintenance(error) {
+        EXCHANGE_MAINTENANCE_TEXT.to_string()
+    } else if error.downcast_ref::<DbRecordDiverged>().is_some() {
+        // {:#}: расхождение могло прийти в контексте исходной ошибки этапа — показываем всю цепочку
+        format!("{:#}\n{}", error, DB_RECORD_DIVERGED_TEXT)
+    } else if ExchangeError::is_position_mode_mismatch(error) {
+        format!("{}\n{}", error, POSITION_MODE_MISMATCH_TEXT)
+    } else {
+        error.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maintenance_error_is_described_for_user() {
+        let maintenance: anyhow::Error = ExchangeError::Maintenance("Bybit API (10000): System maintenance in progress".into()).into();
+        let other = anyhow::anyhow!("insufficient balance");
+
+        assert_eq!(describe_error(&maintenance), EXCHANGE_MAINTENANCE_TEXT);
+        assert_eq!(describe_error(&other), "insufficient balance");
+    }
+
+    #[test]
+    fn position_mode_mismatch_gets_a_hint() {
+        let mismatch: anyhow::Error = ExchangeError::PositionModeMismatch("ETHUSDT: order sent for one-way, account is in hedge-mode".into()).into();
+        assert!(describe_error(&mismatch).ends_with(POSITION_MODE_MISMATCH_TEXT));
+        // Общая ошибка параметров с тем же текстом без подтвержденной смены режима подсказки не получает
+        let params_error: anyhow::Error = ExchangeError::Api {
+            code: 10001,
+            message: "position idx not match position mode".into(),
+            raw: String::new(),
+        }
+        .into();
+        assert!(!describe_error(&params_error).ends_with(POSITION_MODE_MISMATCH_TEXT));
+    }
+
+    #[test]
+    fn only_transient_telegram_errors_are_retried() {
+        let min_delay = Duration::from_millis(500);
+        let rate_limited = RequestError::RetryAfter(teloxide::types::Seconds::from_seconds(3));
+        let io_error = RequestError::Io(std::sync::Arc::new(std::io::Error::from(std::io::ErrorKind::TimedOut)));
+
+        assert_eq!(edit_retry_delay(&rate_limited, 0, min_delay), Some(Duration::from_secs(3)));
+        assert_eq!(edit_retry_delay(&io_error, 0, min_delay), Some(min_delay));
+        assert_eq!(edit_retry_delay(&io_error, 2, min_delay), Some(Duration::from_secs(2)));
+
+        assert_eq!(edit_retry_delay(&RequestError::Api(ApiError::MessageNotModified), 0, min_delay), None);
+        assert_eq!(edit_retry_delay(&RequestError::Api(ApiError::BotBlocked), 0, min_delay), None);
+        assert_eq!(edit_retry_delay(&RequestError::MigrateToChatId(ChatId(-100)), 0, min_delay), None);
+    }
+
+    #[test]
+    fn operation_label_falls_back_to_numeric_id() {
+        assert_eq!(operation_label(Some("BTC-0425-01"), 7), "BTC-0425-01 (ID:7)");
+        assert_eq!(operation_label(None, 7), "ID:7");
+    }
+
+    #[test]
+    fn trims_trailing_zeros_for_small_qty() {
+        assert_eq!(format_qty(0.00010000, 8), "0.0001");
+    }
+
+    #[test]
+    fn keeps_significant_digits_for_large_qty() {
+        assert_eq!(format_qty(12345.6789, 8), "12345.6789");
+    }
+
+    #[test]
+    fn respects_instrument_precision() {
+        assert_eq!(format_qty(12345.6789, display_decimals(Some(2), 8)), "12345.68");
+        assert_eq!(format_qty(1.0, display_decimals(None, 8)), "1");
+        assert_eq!(format_qty(0.0, 6), "0");
+    }
+}
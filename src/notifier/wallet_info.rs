@@ -3,8 +3,11 @@
 // <<< ИСПРАВЛЕНО: Убраны UserState, RunningOperations, Balance, MessageId, ChatId >>>
 // Command и callback_data используются (косвенно через Command::descriptions и в handle_menu_wallet_callback)
 use crate::notifier::{callback_data, StateStorage}; // Оставляем StateStorage, т.к. он в сигнатурах
-use crate::config::Config;
+use crate::notifier::utils::delete_user_message;
+use crate::config::{Config, WalletSort};
 use crate::exchange::Exchange; // Оставляем Exchange
+use crate::exchange::bybit::SPOT_CATEGORY;
+use crate::exchange::types::Balance;
 use crate::storage::Db;
 use crate::hedger::ORDER_FILL_TOLERANCE; // Используется в get_formatted_balances
 use std::sync::Arc;
@@ -22,6 +25,7 @@ pub async fn get_formatted_balances<E: Exchange>(
     exchange: &E,
     quote_currency: &str,
     include_approx_value: bool,
+    sort: WalletSort,
 ) -> Result<(String, Vec<(String, f64, f64)>), anyhow::Error> {
     info!("Fetching all balances from exchange...");
     let balances = exchange.get_all_balances().await?;
@@ -35,19 +39,49 @@ pub async fn get_formatted_balances<E: Exchange>(
     let mut asset_data = Vec::new();
 
     let mut prices: std::collections::HashMap<String, f64> = std::collections::HashMap::new();
-    if include_approx_value {
+    // Для сортировки по стоимости цены нужны даже без вывода оценки
+    if include_approx_value || sort == WalletSort::Value {
         info!("Fetching prices for value approximation...");
-        for (coin, _) in &sorted_balances {
-             if coin != quote_currency {
-                 match exchange.get_spot_price(coin).await {
-                     Ok(price) => { prices.insert(coin.clone(), price); },
-                     Err(e) => warn!("Could not fetch price for {}: {}", coin, e),
-                 }
-             }
+        // Все спотовые цены одним запросом; при ошибке — по одной монете
+        match exchange.get_all_tickers(SPOT_CATEGORY).await {
+            Ok(all_tickers) => {
+                for (coin, _) in &sorted_balances {
+                    if coin != quote_currency {
+                        match all_tickers.get(&format!("{}{}", coin, quote_currency)) {
+                            Some(price) => { prices.insert(coin.clone(), *price); },
+                            None => warn!("No {} ticker for {}", quote_currency, coin),
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Could not fetch all tickers: {}. Fetching prices one by one.", e);
+                for (coin, _) in &sorted_balances {
+                     if coin != quote_currency {
+                         match exchange.get_spot_price(coin).await {
+                             Ok(price) => { prices.insert(coin.clone(), price); },
+                             Err(e) => warn!("Could not fetch price for {}: {}", coin, e),
+                         }
+                     }
+                }
+            }
         }
         info!("Fetched {} prices.", prices.len());
     }
 
+    // Сортировка по убыванию (алфавитный порядок уже задан выше и сохраняется при равенстве)
+    match sort {
+        WalletSort::Alpha => {}
+        WalletSort::Free => sorted_balances.sort_by(|(_, a), (_, b)| b.free.total_cmp(&a.free)),
+        WalletSort::Value => {
+            let value_of = |coin: &String, balance: &Balance| {
+                let price = if coin == quote_currency { 1.0 } else { prices.get(coin).copied().unwrap_or(0.0) };
+                (balance.free + balance.locked) * price
+            };
+            sorted_balances.sort_by(|(coin_a, a), (coin_b, b)| value_of(coin_b, b).total_cmp(&value_of(coin_a, a)));
+        }
+    }
+
     for (coin, balance) in sorted_balances {
         if balance.free > ORDER_FILL_TOLERANCE || balance.locked > ORDER_FILL_TOLERANCE || coin == quote_currency {
             let mut line = format!(
@@ -96,7 +130,7 @@ where
     info!("Processing /wallet command for chat_id: {}", chat_id);
     let indicator_msg = bot.send_message(chat_id, "⏳ Загрузка баланса...").await?;
 
-    match get_formatted_balances(exchange.as_ref(), &cfg.quote_currency, true).await {
+    match get_formatted_balances(exchange.as_ref(), &cfg.quote_currency, true, cfg.wallet_sort).await {
         Ok((text, _)) => {
             bot.edit_message_text(chat_id, indicator_msg.id, text).await?;
         }
@@ -107,9 +141,7 @@ where
         }
     }
 
-     if let Err(e) = bot.delete_message(chat_id, msg.id).await {
-         warn!("Failed to delete /wallet command message: {}", e);
-     }
+     delete_user_message(&bot, &cfg, chat_id, msg.id, "/wallet command message").await;
 
     Ok(())
 }
@@ -121,7 +153,7 @@ pub async fn handle_balance_command<E>(
     symbol_arg: String,
     exchange: Arc<E>,
     _state_storage: StateStorage, // Аргумент добавлен, но пока не используется
-    _cfg: Arc<Config>,
+    cfg: Arc<Config>,
     _db: Arc<Db>,
 ) -> anyhow::Result<()>
 where
@@ -134,9 +166,7 @@ where
         // <<< ИСПРАВЛЕНО: Упрощенная строка использования >>>
         bot.send_message(chat_id, "Использование: /balance <SYMBOL>\nПример: /balance BTC").await?;
         // ---
-        if let Err(e) = bot.delete_message(chat_id, msg.id).await {
-             warn!("Failed to delete invalid /balance command message: {}", e);
-        }
+        delete_user_message(&bot, &cfg, chat_id, msg.id, "invalid /balance command message").await;
         return Ok(());
     }
 
@@ -155,9 +185,7 @@ where
         }
     }
 
-     if let Err(e) = bot.delete_message(chat_id, msg.id).await {
-         warn!("Failed to delete /balance command message: {}", e);
-     }
+     delete_user_message(&bot, &cfg, chat_id, msg.id, "/balance command message").await;
 
     Ok(())
 }
@@ -188,7 +216,7 @@ where
            .reply_markup(kb.clone())
            .await?;
 
-        match get_formatted_balances(exchange.as_ref(), &cfg.quote_currency, true).await {
+        match get_formatted_balances(exchange.as_ref(), &cfg.quote_currency, true, cfg.wallet_sort).await {
             Ok((text, _)) => {
                  // Используем msg.id() - вызов метода
                 bot.edit_message_text(chat_id, msg.id(), text)
@@ -211,4 +239,41 @@ where
     Ok(())
 }
 
-// Конец оригинального кода. Дубликат удален.
\ No newline at end of file
+// Конец оригинального кода. Дубликат удален.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::mock::MockExchange;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn value_sort_puts_largest_holdings_first() {
+        let exchange = MockExchange {
+            balances: vec![
+                ("ADA".to_string(), Balance { free: 1000.0, locked: 0.0 }),   // 500
+                ("BTC".to_string(), Balance { free: 0.01, locked: 0.0 }),     // 1000
+                ("ETH".to_string(), Balance { free: 0.5, locked: 0.5 }),      // 2000
+                ("USDT".to_string(), Balance { free: 750.0, locked: 0.0 }),   // 750
+            ],
+            spot_prices: HashMap::from([
+                ("ADA".to_string(), 0.5),
+                ("BTC".to_string(), 100000.0),
+                ("ETH".to_string(), 2000.0),
+            ]),
+            ..MockExchange::default()
+        };
+
+        let (_, asset_data) = get_formatted_balances(&exchange, "USDT", false, WalletSort::Value)
+            .await
+            .expect("balances");
+        let order: Vec<&str> = asset_data.iter().map(|(coin, _, _)| coin.as_str()).collect();
+        assert_eq!(order, vec!["ETH", "BTC", "USDT", "ADA"]);
+
+        let (_, asset_data) = get_formatted_balances(&exchange, "USDT", false, WalletSort::Alpha)
+            .await
+            .expect("balances");
+        let order: Vec<&str> = asset_data.iter().map(|(coin, _, _)| coin.as_str()).collect();
+        assert_eq!(order, vec!["ADA", "BTC", "ETH", "USDT"]);
+    }
+}
@@ -0,0 +1,129 @@
+// src/notifier/watchers.rs
+
+//! /watchers: список фоновых наблюдателей чата и их остановка.
+//! Остановленный наблюдатель снимается и в БД, чтобы не запуститься снова после перезапуска бота.
+
+use crate::hedger::watchers::{WatcherKind, WatcherRegistry, WatcherSummary};
+use crate::notifier::callback_data;
+use crate::storage::{finish_pending_futures_operation, get_hedge_operation_by_id, Db, OperationStatus};
+use std::sync::Arc;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+use tracing::{error, info, warn};
+
+/// Текст списка наблюдателей
+fn format_watchers(watchers: &[WatcherSummary]) -> String {
+    if watchers.is_empty() {
+        return "ℹ️ Нет активных фоновых наблюдателей.".to_string();
+    }
+    let mut text = "👀 Фоновые наблюдатели:\n".to_string();
+    for watcher in watchers {
+        text.push_str(&format!(
+            "\n#{} {} — {} (операция ID:{}), {}",
+            watcher.id, watcher.kind.label(), watcher.symbol, watcher.operation_id, watcher.details
+        ));
+    }
+    text
+}
+
+/// Кнопки остановки по одной на наблюдатель
+fn make_watchers_keyboard(watchers: &[WatcherSummary]) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(watchers.iter().map(|watcher| {
+        vec![InlineKeyboardButton::callback(
+            format!("❌ Остановить #{} ({})", watcher.id, watcher.symbol),
+            format!("{}{}", callback_data::PREFIX_CANCEL_WATCHER, watcher.id),
+        )]
+    }))
+}
+
+/// Обработчик команды /watchers
+pub async fn handle_watchers_command(bot: Bot, msg: Message, registry: WatcherRegistry) -> anyhow::Result<()> {
+    let chat_id = msg.chat.id;
+    info!("Processing /watchers for chat_id: {}", chat_id);
+    let watchers = registry.list_for_chat(chat_id.0);
+    bot.send_message(chat_id, format_watchers(&watchers))
+        .reply_markup(make_watchers_keyboard(&watchers))
+        .await?;
+    Ok(())
+}
+
+/// Снимает остановленный наблюдатель в БД, чтобы он не возобновился при старте
+async fn forget_watcher(db: &Db, watcher: &WatcherSummary) -> anyhow::Result<()> {
+    match watcher.kind {
+        WatcherKind::PendingFutures => {
+            let filled_qty = get_hedge_operation_by_id(db, watcher.operation_id)
+                .await?
+                .map_or(0.0, |operation| operation.futures_filled_qty);
+            finish_pending_futures_operation(
+                db,
+                watcher.operation_id,
+                OperationStatus::Interrupted,
+                filled_qty,
+                Some("Pending futures monitoring stopped by user; order left on exchange"),
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Обработчик кнопки остановки наблюдателя
+pub async fn handle_cancel_watcher_callback(bot: Bot, q: CallbackQuery, registry: WatcherRegistry, db: Arc<Db>) -> anyhow::Result<()> {
+    let (Some(data), Some(msg)) = (q.data.as_deref(), q.message.as_ref()) else {
+        warn!("CallbackQuery missing data or message in handle_cancel_watcher_callback");
+        bot.answer_callback_query(q.id).await?;
+        return Ok(());
+    };
+    let chat_id = msg.chat().id;
+    let message_id = msg.id();
+    let Some(watcher_id) = data.strip_prefix(callback_data::PREFIX_CANCEL_WATCHER).and_then(|id| id.parse::<u64>().ok()) else {
+        error!("Failed to parse watcher id from callback data: {}", data);
+        bot.answer_callback_query(q.id).text("Ошибка: неверные данные.").await?;
+        return Ok(());
+    };
+
+    let Some(watcher) = registry.cancel(chat_id.0, watcher_id) else {
+        bot.answer_callback_query(q.id).text("Наблюдатель уже завершен.").show_alert(false).await?;
+        return Ok(());
+    };
+    bot.answer_callback_query(q.id).await?;
+
+    let mut text = match forget_watcher(&db, &watcher).await {
+        Ok(()) => format!("✅ Наблюдатель #{} ({}) остановлен.", watcher.id, watcher.symbol),
+        Err(e) => {
+            error!("op_id:{}: Failed to remove watcher {} from DB: {}", watcher.operation_id, watcher.id, e);
+            format!("⚠️ Наблюдатель #{} остановлен, но не удален из БД — он возобновится после перезапуска бота.", watcher.id)
+        }
+    };
+    if watcher.kind == WatcherKind::PendingFutures {
+        text.push_str("\nФьючерсный ордер остался на бирже — проверьте его вручную.");
+    }
+    let remaining = registry.list_for_chat(chat_id.0);
+    text.push_str("\n\n");
+    text.push_str(&format_watchers(&remaining));
+    bot.edit_message_text(chat_id, message_id, text)
+        .reply_markup(make_watchers_keyboard(&remaining))
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchers_list_shows_kind_symbol_and_details() {
+        assert_eq!(format_watchers(&[]), "ℹ️ Нет активных фоновых наблюдателей.");
+        let watcher = WatcherSummary {
+            id: 3,
+            kind: WatcherKind::PendingFutures,
+            chat_id: 1,
+            operation_id: 42,
+            symbol: "BTCUSDT".to_string(),
+            details: "ордер abc, цель 0.01000000".to_string(),
+        };
+        let text = format_watchers(std::slice::from_ref(&watcher));
+        assert!(text.contains("#3 Ожидание фьючерсного ордера — BTCUSDT (операция ID:42), ордер abc"));
+        assert_eq!(make_watchers_keyboard(&[watcher]).inline_keyboard.len(), 1);
+    }
+}
@@ -2,19 +2,21 @@
 
 //! Функции для взаимодействия с базой данных SQLite.
 
-use super::schema::{apply_migrations, HedgeOperation}; // Импортируем структуру
-use anyhow::{Context, Result};
-use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use super::schema::{apply_migrations, verify_schema, DailyActivity, HedgeOperation, HedgeStatusCount, OperationStats, OperationStatus, HEDGE_OPERATIONS_COLUMNS}; // Импортируем структуру
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use sqlx::sqlite::{SqliteConnection, SqliteConnectOptions, SqlitePool, SqlitePoolOptions, SqliteRow};
 use sqlx::{Error as SqlxError, Row}; // Переименовываем Error и добавляем Row
 use std::str::FromStr;
-use tracing::info;
+use tracing::{info, warn};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 // Переопределяем Db как SqlitePool для простоты
 pub type Db = SqlitePool;
 
 /// Асинхронная функция для подключения к базе данных SQLite.
-pub async fn connect(db_path: &str) -> Result<Db> {
+pub async fn connect(db_path: &str, schema_self_check: bool) -> Result<Db> {
     info!("Connecting to database: {}", db_path);
     let options = SqliteConnectOptions::from_str(db_path)?
         .create_if_missing(true)
@@ -29,6 +31,11 @@ pub async fn connect(db_path: &str) -> Result<Db> {
     // Применяем миграции при подключении
     apply_migrations(&pool).await?;
 
+    // Самопроверка схемы (отключается через db_schema_self_check = false)
+    if schema_self_check {
+        verify_schema(&pool).await?;
+    }
+
     info!("Database connection pool established.");
     Ok(pool)
 }
@@ -43,42 +50,126 @@ fn current_timestamp() -> i64 {
 
 // --- Функции для работы с hedge_operations ---
 
-/// Вставить новую операцию хеджирования и вернуть ее ID.
+/// Окно дедупликации, сек: повторная операция с тем же чатом, символом и суммой считается дублем (двойное нажатие)
+const DUPLICATE_OPERATION_WINDOW_SECS: i64 = 10;
+
+/// Условие на кандидата в дубли: завершенная операция (Completed/Cancelled/Failed) повтор не блокирует —
+/// после быстрой неудачи пользователь может сразу запустить хедж заново
+const DUPLICATE_CANDIDATE_FILTER: &str =
+    "chat_id = ? AND base_symbol = ? AND ROUND(initial_sum, 2) = ROUND(?, 2) AND start_timestamp >= ? AND status NOT IN ('Completed', 'Cancelled', 'Failed')";
+
+/// Вставить новую операцию хеджирования и вернуть ее ID, человекочитаемую ссылку (op_ref) и флаг создания.
+/// Если за последние DUPLICATE_OPERATION_WINDOW_SECS уже создана незавершенная операция с тем же chat_id, символом
+/// и суммой (до центов), новая строка не вставляется: возвращается существующая операция с флагом false.
 pub async fn insert_hedge_operation(
     db: &Db,
     chat_id: i64,
     base_symbol: &str,
     quote_currency: &str,
+    environment: &str, // testnet / mainnet — по адресу API, на котором исполняется операция
     initial_sum: f64,
     volatility: f64,
     target_spot_qty: f64,
     target_futures_qty: f64,
-) -> Result<i64, SqlxError> {
+    futures_only: bool, // Хедж только фьючерсом: спот хранится вне бота
+) -> Result<(i64, String, bool), SqlxError> {
     let ts = current_timestamp();
-    let status = "Running"; // Начальный статус
+    let status = OperationStatus::Running.as_str(); // Начальный статус
 
-    // Используем query! так как он не возвращает сложную структуру
-    let result = sqlx::query!(
+    // Проверка дубля, вставка и присвоение op_ref — одна транзакция: строка не видна без op_ref,
+    // а номер за сутки считается под блокировкой записи, поэтому параллельные вставки не получат одну ссылку
+    let mut tx = db.begin().await?;
+    let insert_sql = format!(
         r#"
         INSERT INTO hedge_operations (
             chat_id, base_symbol, quote_currency, initial_sum, volatility,
-            target_spot_qty, target_futures_qty, start_timestamp, status
-        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            target_spot_qty, target_futures_qty, start_timestamp, status, environment, futures_only
+        )
+        SELECT ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?
+        WHERE NOT EXISTS (SELECT 1 FROM hedge_operations WHERE {})
         "#,
-        chat_id,
-        base_symbol,
-        quote_currency,
-        initial_sum,
-        volatility,
-        target_spot_qty,
-        target_futures_qty,
-        ts,
-        status
-    )
-    .execute(db)
+        DUPLICATE_CANDIDATE_FILTER
+    );
+    let result = sqlx::query(&insert_sql)
+    .bind(chat_id)
+    .bind(base_symbol)
+    .bind(quote_currency)
+    .bind(initial_sum)
+    .bind(volatility)
+    .bind(target_spot_qty)
+    .bind(target_futures_qty)
+    .bind(ts)
+    .bind(status)
+    .bind(environment)
+    .bind(futures_only)
+    .bind(chat_id)
+    .bind(base_symbol)
+    .bind(initial_sum)
+    .bind(ts - DUPLICATE_OPERATION_WINDOW_SECS)
+    .execute(&mut *tx)
     .await?;
 
-    Ok(result.last_insert_rowid())
+    if result.rows_affected() == 0 {
+        let select_sql = format!(
+            "SELECT id, op_ref, start_timestamp FROM hedge_operations WHERE {} ORDER BY id DESC LIMIT 1",
+            DUPLICATE_CANDIDATE_FILTER
+        );
+        let row = sqlx::query(&select_sql)
+            .bind(chat_id)
+            .bind(base_symbol)
+            .bind(initial_sum)
+            .bind(ts - DUPLICATE_OPERATION_WINDOW_SECS)
+            .fetch_one(&mut *tx)
+            .await?;
+        let operation_id: i64 = row.try_get("id")?;
+        // op_ref присваивается в транзакции вставки; у строк, созданных до введения ссылок, присваиваем сейчас
+        let op_ref = match row.try_get::<Option<String>, _>("op_ref")? {
+            Some(op_ref) if !op_ref.is_empty() => op_ref,
+            _ => assign_operation_ref(&mut tx, operation_id, base_symbol, row.try_get("start_timestamp")?).await?,
+        };
+        tx.commit().await?;
+        return Ok((operation_id, op_ref, false));
+    }
+
+    let operation_id = result.last_insert_rowid();
+    let op_ref = assign_operation_ref(&mut tx, operation_id, base_symbol, ts).await?;
+    tx.commit().await?;
+    Ok((operation_id, op_ref, true))
+}
+
+/// Человекочитаемая ссылка на операцию: СИМВОЛ-ММДД-NN (NN — порядковый номер операции по символу за сутки UTC)
+pub fn format_operation_ref(base_symbol: &str, start_timestamp: i64, day_seq: i64) -> String {
+    let day = chrono::DateTime::from_timestamp(start_timestamp, 0)
+        .map(|dt| dt.format("%m%d").to_string())
+        .unwrap_or_else(|| "0000".to_string());
+    format!("{}-{}-{:02}", base_symbol.to_uppercase(), day, day_seq)
+}
+
+/// Сгенерировать и сохранить op_ref для только что вставленной операции (в транзакции ее вставки)
+async fn assign_operation_ref(conn: &mut SqliteConnection, operation_id: i64, base_symbol: &str, start_timestamp: i64) -> Result<String, SqlxError> {
+    const SECONDS_PER_DAY: i64 = 86_400;
+    let day_start = start_timestamp - start_timestamp.rem_euclid(SECONDS_PER_DAY);
+    let day_seq: i64 = sqlx::query(
+        r#"
+        SELECT COUNT(*) AS seq FROM hedge_operations
+        WHERE base_symbol = ? AND start_timestamp >= ? AND start_timestamp < ? AND id <= ?
+        "#,
+    )
+    .bind(base_symbol)
+    .bind(day_start)
+    .bind(day_start + SECONDS_PER_DAY)
+    .bind(operation_id)
+    .fetch_one(&mut *conn)
+    .await?
+    .try_get("seq")?;
+
+    let op_ref = format_operation_ref(base_symbol, start_timestamp, day_seq);
+    sqlx::query("UPDATE hedge_operations SET op_ref = ? WHERE id = ?")
+        .bind(&op_ref)
+        .bind(operation_id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(op_ref)
 }
 
 /// Обновить ID спотового ордера и исполненное количество для операции.
@@ -108,11 +199,12 @@ pub async fn update_hedge_spot_order(
 pub async fn update_hedge_final_status(
     db: &Db,
     operation_id: i64,
-    status: &str, // "Completed", "Cancelled", "Failed"
+    status: OperationStatus, // Completed, Cancelled, Failed
     futures_order_id: Option<&str>,
     futures_filled_qty: f64,
     error_message: Option<&str>,
 ) -> Result<(), SqlxError> {
+    let status = status.as_str();
     let ts = current_timestamp();
     // Используем query!
     sqlx::query!(
@@ -137,92 +229,461 @@ pub async fn update_hedge_final_status(
     Ok(())
 }
 
+/// Итог ноги операции для finalize_operation: последний ордер и исполненный объем
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LegFill<'a> {
+    pub order_id: Option<&'a str>,
+    pub filled_qty: f64,
+}
+
+impl LegFill<'static> {
+    /// Нога не исполнялась: ордера нет, объем 0
+    pub const NONE: LegFill<'static> = LegFill { order_id: None, filled_qty: 0.0 };
+}
+
+/// Атомарно завершить операцию: спотовый ордер и объем (spot = None — не менять), фьючерсный ордер и объем,
+/// статус, время и ошибка пишутся одной транзакцией, только пока операция 'Running'.
+/// Сбой любой из записей откатывает все. false — операция уже не 'Running' (изменения откатываются).
+pub async fn finalize_operation(
+    db: &Db,
+    operation_id: i64,
+    status: OperationStatus, // Completed, Cancelled, Failed
+    spot: Option<LegFill<'_>>,
+    futures: LegFill<'_>,
+    error_message: Option<&str>,
+) -> Result<bool, SqlxError> {
+    let mut tx = db.begin().await?;
+    if let Some(spot) = spot {
+        write_spot_fill(&mut tx, operation_id, spot).await?;
+    }
+    let updated = write_final_status(&mut tx, operation_id, status, futures, error_message).await?;
+    if !updated {
+        tx.rollback().await?;
+        return Ok(false);
+    }
+    tx.commit().await?;
+    info!("Hedge operation {} finalized with status {}", operation_id, status);
+    Ok(true)
+}
+
+/// Итог спотовой ноги в рамках открытой транзакции (ID ордера не затирается, если не передан)
+async fn write_spot_fill(conn: &mut SqliteConnection, operation_id: i64, spot: LegFill<'_>) -> Result<(), SqlxError> {
+    sqlx::query(
+        r#"
+        UPDATE hedge_operations
+        SET spot_order_id = COALESCE(?, spot_order_id), spot_filled_qty = ?
+        WHERE id = ? AND status = 'Running'
+        "#,
+    )
+    .bind(spot.order_id)
+    .bind(spot.filled_qty)
+    .bind(operation_id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Финальная запись операции в рамках открытой транзакции; true — строка обновлена
+async fn write_final_status(
+    conn: &mut SqliteConnection,
+    operation_id: i64,
+    status: OperationStatus,
+    futures: LegFill<'_>,
+    error_message: Option<&str>,
+) -> Result<bool, SqlxError> {
+    let result = sqlx::query(
+        r#"
+        UPDATE hedge_operations
+        SET status = ?, futures_order_id = ?, futures_filled_qty = ?, end_timestamp = ?, error_message = ?
+        WHERE id = ? AND status = 'Running'
+        "#,
+    )
+    .bind(status.as_str())
+    .bind(futures.order_id)
+    .bind(futures.filled_qty)
+    .bind(current_timestamp())
+    .bind(error_message)
+    .bind(operation_id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Ручное закрытие зависшей операции (/resolve): итоговый статус, время окончания и отметка для аудита
+/// в error_message (дописывается к прежней ошибке). Уже завершенные операции не меняются; false — строка не обновлена
+pub async fn resolve_operation_manually(
+    db: &Db,
+    operation_id: i64,
+    status: OperationStatus,
+    note: &str,
+) -> Result<bool, SqlxError> {
+    if !status.is_terminal() {
+        return Ok(false);
+    }
+    let result = sqlx::query(
+        r#"
+        UPDATE hedge_operations
+        SET status = ?,
+            end_timestamp = COALESCE(end_timestamp, ?),
+            error_message = CASE WHEN error_message IS NULL OR error_message = '' THEN ? ELSE error_message || ' | ' || ? END
+        WHERE id = ? AND status NOT IN ('Completed', 'Cancelled', 'Failed')
+        "#,
+    )
+    .bind(status.as_str())
+    .bind(current_timestamp())
+    .bind(note)
+    .bind(note)
+    .bind(operation_id)
+    .execute(db)
+    .await?;
+    let updated = result.rows_affected() == 1;
+    if updated {
+        warn!("Hedge operation {} manually resolved as {}: {}", operation_id, status, note);
+    }
+    Ok(updated)
+}
+
+/// SELECT всех колонок hedge_operations (HEDGE_OPERATIONS_COLUMNS) с условием/сортировкой из filter
+fn hedge_operations_query(filter: &str) -> String {
+    let columns = HEDGE_OPERATIONS_COLUMNS.iter().map(|(column, _)| *column).collect::<Vec<_>>().join(", ");
+    format!("SELECT {} FROM hedge_operations {}", columns, filter)
+}
+
+/// Строка hedge_operations -> HedgeOperation (общий маппинг для всех выборок операций)
+fn hedge_operation_from_row(row: &SqliteRow) -> Result<HedgeOperation, SqlxError> {
+    Ok(HedgeOperation {
+        id: row.try_get("id")?,
+        chat_id: row.try_get("chat_id")?,
+        base_symbol: row.try_get("base_symbol")?,
+        quote_currency: row.try_get("quote_currency")?,
+        initial_sum: row.try_get("initial_sum")?,
+        volatility: row.try_get("volatility")?,
+        target_spot_qty: row.try_get("target_spot_qty")?,
+        target_futures_qty: row.try_get("target_futures_qty")?,
+        start_timestamp: row.try_get("start_timestamp")?,
+        status: row.try_get("status")?,
+        spot_order_id: row.try_get("spot_order_id")?,
+        spot_filled_qty: row.try_get("spot_filled_qty")?,
+        futures_order_id: row.try_get("futures_order_id")?,
+        futures_filled_qty: row.try_get("futures_filled_qty")?,
+        end_timestamp: row.try_get("end_timestamp")?,
+        error_message: row.try_get("error_message")?,
+        unhedged_op_id: row.try_get("unhedged_op_id")?,
+        op_ref: row.try_get("op_ref")?,
+        futures_symbol: row.try_get("futures_symbol")?,
+        rolled_from_op_id: row.try_get("rolled_from_op_id")?,
+        accrued_funding: row.try_get("accrued_funding")?,
+        environment: row.try_get("environment")?,
+        futures_only: row.try_get("futures_only")?,
+    })
+}
+
 /// Получить все операции хеджирования в статусе 'Running'.
 pub async fn get_running_hedge_operations(db: &Db) -> Result<Vec<HedgeOperation>, SqlxError> {
-    // ---> ИЗМЕНЕНО ЗДЕСЬ: Ручной маппинг <---
-    let rows = sqlx::query( // Используем sqlx::query()
+    let rows = sqlx::query(&hedge_operations_query(
         r#"
-        SELECT
-            id, chat_id, base_symbol, quote_currency, initial_sum, volatility,
-            target_spot_qty, target_futures_qty, start_timestamp, status,
-            spot_order_id, spot_filled_qty, futures_order_id, futures_filled_qty,
-            end_timestamp, error_message, unhedged_op_id
-        FROM hedge_operations
         WHERE status = 'Running'
         ORDER BY start_timestamp ASC
         "#,
-    )
+    ))
     .fetch_all(db)
     .await?;
 
-    let mut operations = Vec::with_capacity(rows.len());
-    for row in rows {
-        let operation = HedgeOperation {
-             id: row.try_get("id")?,
-             chat_id: row.try_get("chat_id")?,
-             base_symbol: row.try_get("base_symbol")?,
-             quote_currency: row.try_get("quote_currency")?,
-             initial_sum: row.try_get("initial_sum")?,
-             volatility: row.try_get("volatility")?,
-             target_spot_qty: row.try_get("target_spot_qty")?,
-             target_futures_qty: row.try_get("target_futures_qty")?,
-             start_timestamp: row.try_get("start_timestamp")?,
-             status: row.try_get("status")?,
-             spot_order_id: row.try_get("spot_order_id")?,
-             spot_filled_qty: row.try_get("spot_filled_qty")?,
-             futures_order_id: row.try_get("futures_order_id")?,
-             futures_filled_qty: row.try_get("futures_filled_qty")?,
-             end_timestamp: row.try_get("end_timestamp")?,
-             error_message: row.try_get("error_message")?,
-             unhedged_op_id: row.try_get("unhedged_op_id")?,
-        };
-        operations.push(operation);
+    rows.iter().map(hedge_operation_from_row).collect()
+}
+
+/// Получить операции, фьючерсный ордер которых оставлен на бирже ('PendingFutures').
+pub async fn get_pending_futures_operations(db: &Db) -> Result<Vec<HedgeOperation>, SqlxError> {
+    let rows = sqlx::query(&hedge_operations_query(
+        r#"
+        WHERE status = 'PendingFutures'
+        ORDER BY start_timestamp ASC
+        "#,
+    ))
+    .fetch_all(db)
+    .await?;
+
+    rows.iter().map(hedge_operation_from_row).collect()
+}
+
+/// Перевести операцию в 'PendingFutures': фьючерсный ордер оставлен на бирже после таймаута.
+pub async fn mark_hedge_pending_futures(
+    db: &Db,
+    operation_id: i64,
+    futures_order_id: &str,
+    futures_filled_qty: f64, // Исполнено до оставленного ордера
+    target_futures_qty: f64, // Итоговая цель фьючерсного этапа
+) -> Result<(), SqlxError> {
+    sqlx::query(
+        r#"
+        UPDATE hedge_operations
+        SET status = 'PendingFutures', futures_order_id = ?, futures_filled_qty = ?, target_futures_qty = ?
+        WHERE id = ? AND status = 'Running'
+        "#,
+    )
+    .bind(futures_order_id)
+    .bind(futures_filled_qty)
+    .bind(target_futures_qty)
+    .bind(operation_id)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Записать живой фьючерсный ордер операции 'Running': после перезапуска за ним можно продолжить следить.
+pub async fn update_running_futures_order(
+    db: &Db,
+    operation_id: i64,
+    futures_order_id: &str,
+    futures_filled_qty: f64, // Исполнено до этого ордера
+    target_futures_qty: f64, // Итоговая цель фьючерсного этапа
+) -> Result<(), SqlxError> {
+    sqlx::query(
+        r#"
+        UPDATE hedge_operations
+        SET futures_order_id = ?, futures_filled_qty = ?, target_futures_qty = ?
+        WHERE id = ? AND status = 'Running'
+        "#,
+    )
+    .bind(futures_order_id)
+    .bind(futures_filled_qty)
+    .bind(target_futures_qty)
+    .bind(operation_id)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Перевести операцию 'Running', прерванную перезапуском, в 'Interrupted' (нужна ручная проверка позиций).
+/// false — операция уже не 'Running'.
+pub async fn mark_hedge_interrupted(db: &Db, operation_id: i64, reason: &str) -> Result<bool, SqlxError> {
+    let result = sqlx::query(
+        r#"
+        UPDATE hedge_operations
+        SET status = 'Interrupted', end_timestamp = ?, error_message = ?
+        WHERE id = ? AND status = 'Running'
+        "#,
+    )
+    .bind(current_timestamp())
+    .bind(reason)
+    .bind(operation_id)
+    .execute(db)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Завершить операцию в статусе 'PendingFutures' (ордер исполнился или пропал с биржи).
+pub async fn finish_pending_futures_operation(
+    db: &Db,
+    operation_id: i64,
+    status: OperationStatus, // Completed или Failed
+    futures_filled_qty: f64,
+    error_message: Option<&str>,
+) -> Result<(), SqlxError> {
+    sqlx::query(
+        r#"
+        UPDATE hedge_operations
+        SET status = ?, futures_filled_qty = ?, end_timestamp = ?, error_message = ?
+        WHERE id = ? AND status = 'PendingFutures'
+        "#,
+    )
+    .bind(status.as_str())
+    .bind(futures_filled_qty)
+    .bind(current_timestamp())
+    .bind(error_message)
+    .bind(operation_id)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Перевести операцию в 'SpotOnlyOrphan': спот куплен, а фьючерсная нога не выставлена (позиция не захеджирована).
+pub async fn mark_hedge_spot_only_orphan(
+    db: &Db,
+    operation_id: i64,
+    futures_filled_qty: f64, // Сколько фьючерса успело исполниться до ошибки
+    error_message: &str,
+) -> Result<(), SqlxError> {
+    sqlx::query(
+        r#"
+        UPDATE hedge_operations
+        SET status = 'SpotOnlyOrphan', futures_filled_qty = ?, end_timestamp = ?, error_message = ?
+        WHERE id = ? AND status = 'Running'
+        "#,
+    )
+    .bind(futures_filled_qty)
+    .bind(current_timestamp())
+    .bind(error_message)
+    .bind(operation_id)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Вернуть операцию 'SpotOnlyOrphan' в 'Running' перед довыставлением фьючерса.
+/// false — операция уже не в этом статусе (например, повторное нажатие кнопки).
+pub async fn claim_spot_only_orphan(db: &Db, operation_id: i64) -> Result<bool, SqlxError> {
+    let result = sqlx::query(
+        r#"
+        UPDATE hedge_operations
+        SET status = 'Running', end_timestamp = NULL, error_message = NULL
+        WHERE id = ? AND status = 'SpotOnlyOrphan'
+        "#,
+    )
+    .bind(operation_id)
+    .execute(db)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Вставить операцию-преемника при роллировании хеджа на другой фьючерсный контракт.
+/// Спот переходит из исходной операции без сделок, фьючерсная нога открывается заново (статус 'Running').
+pub async fn insert_rolled_hedge_operation(
+    db: &Db,
+    original: &HedgeOperation,
+    futures_symbol: &str,
+    target_futures_qty: f64,
+) -> Result<(i64, String), SqlxError> {
+    let ts = current_timestamp();
+    let mut tx = db.begin().await?;
+    let result = sqlx::query(
+        r#"
+        INSERT INTO hedge_operations (
+            chat_id, base_symbol, quote_currency, initial_sum, volatility,
+            target_spot_qty, target_futures_qty, start_timestamp, status,
+            spot_filled_qty, futures_symbol, rolled_from_op_id, environment, futures_only
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, 'Running', ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(original.chat_id)
+    .bind(&original.base_symbol)
+    .bind(&original.quote_currency)
+    .bind(original.initial_sum)
+    .bind(original.volatility)
+    .bind(original.target_spot_qty)
+    .bind(target_futures_qty)
+    .bind(ts)
+    .bind(original.spot_filled_qty)
+    .bind(futures_symbol)
+    .bind(original.id)
+    .bind(&original.environment)
+    .bind(original.futures_only)
+    .execute(&mut *tx)
+    .await?;
+
+    let operation_id = result.last_insert_rowid();
+    let op_ref = assign_operation_ref(&mut tx, operation_id, &original.base_symbol, ts).await?;
+    tx.commit().await?;
+    Ok((operation_id, op_ref))
+}
+
+/// Завершить роллирование: операция-преемник 'Completed', исходная помечается расхеджированной (метка времени,
+/// как при обычном расхеджировании). Связь операций хранится только в rolled_from_op_id преемника.
+/// false — исходная операция уже была расхеджирована (изменения откатываются).
+pub async fn complete_hedge_roll(
+    db: &Db,
+    original_op_id: i64,
+    rolled_op_id: i64,
+    futures_order_id: Option<&str>,
+    futures_filled_qty: f64,
+) -> Result<bool, SqlxError> {
+    let ts = current_timestamp();
+    let mut tx = db.begin().await?;
+    let original = sqlx::query(
+        "UPDATE hedge_operations SET unhedged_op_id = ? WHERE id = ? AND status = 'Completed' AND unhedged_op_id IS NULL",
+    )
+    .bind(ts)
+    .bind(original_op_id)
+    .execute(&mut *tx)
+    .await?;
+    if original.rows_affected() != 1 {
+        tx.rollback().await?;
+        return Ok(false);
     }
-    Ok(operations)
+    sqlx::query(
+        r#"
+        UPDATE hedge_operations
+        SET status = 'Completed', futures_order_id = ?, futures_filled_qty = ?, end_timestamp = ?, error_message = NULL
+        WHERE id = ? AND status = 'Running'
+        "#,
+    )
+    .bind(futures_order_id)
+    .bind(futures_filled_qty)
+    .bind(ts)
+    .bind(rolled_op_id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    info!("Hedge operation {} rolled into operation {}", original_op_id, rolled_op_id);
+    Ok(true)
+}
+
+/// Все открытые хеджи (завершены, но не расхеджированы) по всем пользователям — для фоновых задач
+pub async fn get_open_hedge_operations(db: &Db) -> Result<Vec<HedgeOperation>, SqlxError> {
+    let rows = sqlx::query(&hedge_operations_query(
+        r#"
+        WHERE status = 'Completed' AND unhedged_op_id IS NULL
+        ORDER BY id ASC
+        "#,
+    ))
+    .fetch_all(db)
+    .await?;
+
+    rows.iter().map(hedge_operation_from_row).collect()
+}
+
+/// Сохранить пересчитанный накопленный фандинг операции
+pub async fn update_accrued_funding(db: &Db, operation_id: i64, accrued_funding: f64) -> Result<(), SqlxError> {
+    sqlx::query("UPDATE hedge_operations SET accrued_funding = ? WHERE id = ?")
+        .bind(accrued_funding)
+        .bind(operation_id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Сохранить остаток спота, не проданный при расхеджировании (меньше минимального ордера)
+pub async fn update_unhedge_dust_qty(db: &Db, operation_id: i64, dust_qty: f64) -> Result<(), SqlxError> {
+    sqlx::query("UPDATE hedge_operations SET unhedge_dust_qty = ? WHERE id = ?")
+        .bind(dust_qty)
+        .bind(operation_id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Расхеджирование остановлено после частичной продажи спота: в операции остается непроданный спот,
+/// чтобы повторное расхеджирование продавало только его. false — операция уже расхеджирована
+pub async fn record_partial_spot_sale(db: &Db, operation_id: i64, remaining_spot_qty: f64) -> Result<bool, SqlxError> {
+    let result = sqlx::query("UPDATE hedge_operations SET spot_filled_qty = ? WHERE id = ? AND unhedged_op_id IS NULL")
+        .bind(remaining_spot_qty)
+        .bind(operation_id)
+        .execute(db)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Отметить, что уведомление о пороге фандинга отправлено; false — уже было отправлено раньше
+pub async fn claim_funding_alert(db: &Db, operation_id: i64) -> Result<bool, SqlxError> {
+    let result = sqlx::query("UPDATE hedge_operations SET funding_alert_sent = 1 WHERE id = ? AND funding_alert_sent = 0")
+        .bind(operation_id)
+        .execute(db)
+        .await?;
+    Ok(result.rows_affected() == 1)
 }
 
 /// Получить операцию хеджирования по ID.
 pub async fn get_hedge_operation_by_id(db: &Db, operation_id: i64) -> Result<Option<HedgeOperation>, SqlxError> {
-    // ---> ИЗМЕНЕНО ЗДЕСЬ: Ручной маппинг <---
-    let row_opt = sqlx::query( // Используем sqlx::query()
+    let row_opt = sqlx::query(&hedge_operations_query(
         r#"
-        SELECT
-            id, chat_id, base_symbol, quote_currency, initial_sum, volatility,
-            target_spot_qty, target_futures_qty, start_timestamp, status,
-            spot_order_id, spot_filled_qty, futures_order_id, futures_filled_qty,
-            end_timestamp, error_message, unhedged_op_id
-        FROM hedge_operations
         WHERE id = ?
         "#,
-    )
+    ))
     .bind(operation_id)
     .fetch_optional(db) // Используем fetch_optional
     .await?;
 
-    if let Some(row) = row_opt {
-        let operation = HedgeOperation {
-            id: row.try_get("id")?,
-            chat_id: row.try_get("chat_id")?,
-            base_symbol: row.try_get("base_symbol")?,
-            quote_currency: row.try_get("quote_currency")?,
-            initial_sum: row.try_get("initial_sum")?,
-            volatility: row.try_get("volatility")?,
-            target_spot_qty: row.try_get("target_spot_qty")?,
-            target_futures_qty: row.try_get("target_futures_qty")?,
-            start_timestamp: row.try_get("start_timestamp")?,
-            status: row.try_get("status")?,
-            spot_order_id: row.try_get("spot_order_id")?,
-            spot_filled_qty: row.try_get("spot_filled_qty")?,
-            futures_order_id: row.try_get("futures_order_id")?,
-            futures_filled_qty: row.try_get("futures_filled_qty")?,
-            end_timestamp: row.try_get("end_timestamp")?,
-            error_message: row.try_get("error_message")?,
-            unhedged_op_id: row.try_get("unhedged_op_id")?,
-        };
-        Ok(Some(operation))
-    } else {
-        Ok(None)
-    }
+    row_opt.as_ref().map(hedge_operation_from_row).transpose()
 }
 
 /// Получить список завершенных (Completed) и еще не расхеджированных операций для пользователя и символа.
@@ -230,52 +691,25 @@ pub async fn get_completed_unhedged_ops_for_symbol(
     db: &Db,
     chat_id: i64,
     base_symbol: &str,
+    environment: &str,
 ) -> Result<Vec<HedgeOperation>, SqlxError> {
-    // ---> ИЗМЕНЕНО ЗДЕСЬ: Ручной маппинг <---
-    let rows = sqlx::query( // Используем sqlx::query()
+    let rows = sqlx::query(&hedge_operations_query(
         r#"
-        SELECT
-            id, chat_id, base_symbol, quote_currency, initial_sum, volatility,
-            target_spot_qty, target_futures_qty, start_timestamp, status,
-            spot_order_id, spot_filled_qty, futures_order_id, futures_filled_qty,
-            end_timestamp, error_message, unhedged_op_id
-        FROM hedge_operations
         WHERE chat_id = ?
           AND base_symbol = ?
           AND status = 'Completed'
           AND unhedged_op_id IS NULL
+          AND (environment IS NULL OR environment = ?)
         ORDER BY end_timestamp DESC
         "#,
-    )
+    ))
     .bind(chat_id)
     .bind(base_symbol)
+    .bind(environment)
     .fetch_all(db)
     .await?;
 
-    let mut operations = Vec::with_capacity(rows.len());
-    for row in rows {
-        let operation = HedgeOperation {
-            id: row.try_get("id")?,
-            chat_id: row.try_get("chat_id")?,
-            base_symbol: row.try_get("base_symbol")?,
-            quote_currency: row.try_get("quote_currency")?,
-            initial_sum: row.try_get("initial_sum")?,
-            volatility: row.try_get("volatility")?,
-            target_spot_qty: row.try_get("target_spot_qty")?,
-            target_futures_qty: row.try_get("target_futures_qty")?,
-            start_timestamp: row.try_get("start_timestamp")?,
-            status: row.try_get("status")?,
-            spot_order_id: row.try_get("spot_order_id")?,
-            spot_filled_qty: row.try_get("spot_filled_qty")?,
-            futures_order_id: row.try_get("futures_order_id")?,
-            futures_filled_qty: row.try_get("futures_filled_qty")?,
-            end_timestamp: row.try_get("end_timestamp")?,
-            error_message: row.try_get("error_message")?,
-            unhedged_op_id: row.try_get("unhedged_op_id")?,
-        };
-        operations.push(operation);
-    }
-    Ok(operations)
+    rows.iter().map(hedge_operation_from_row).collect()
 }
 
 /// Пометить операцию хеджирования как расхеджированную (установить unhedged_op_id).
@@ -301,52 +735,871 @@ pub async fn mark_hedge_as_unhedged(
 }
 
 // TODO: Добавить функции для работы с unhedge_operations, если нужно
+/// Операции другого окружения (testnet/mainnet) не возвращаются: их нельзя расхеджировать текущим ключом.
+/// Записи без окружения (созданные до колонки environment) показываются всегда
 pub async fn get_all_completed_unhedged_ops(
     db: &Db,
     chat_id: i64,
+    environment: &str,
 ) -> Result<Vec<HedgeOperation>, SqlxError> {
-    // Используем sqlx::query() и ручной маппинг, как в других функциях этого файла
-    let rows = sqlx::query(
+    let rows = sqlx::query(&hedge_operations_query(
         r#"
-        SELECT
-            id, chat_id, base_symbol, quote_currency, initial_sum, volatility,
-            target_spot_qty, target_futures_qty, start_timestamp, status,
-            spot_order_id, spot_filled_qty, futures_order_id, futures_filled_qty,
-            end_timestamp, error_message, unhedged_op_id
-        FROM hedge_operations
         WHERE chat_id = ?         -- Фильтр по пользователю
           AND status = 'Completed'  -- Только завершенные
           AND unhedged_op_id IS NULL -- Только те, что еще не расхеджированы
+          AND (environment IS NULL OR environment = ?) -- Только текущее окружение биржи
         ORDER BY end_timestamp DESC -- Сначала более новые
         "#,
+    ))
+    .bind(chat_id)
+    .bind(environment)
+    .fetch_all(db)
+    .await?;
+
+    rows.iter().map(hedge_operation_from_row).collect()
+}
+/// Получить операции хеджирования пользователя, начатые в диапазоне [from_ts, to_ts) (None — без ограничения).
+pub async fn get_hedge_operations_in_range(
+    db: &Db,
+    chat_id: i64,
+    from_ts: Option<i64>,
+    to_ts: Option<i64>,
+) -> Result<Vec<HedgeOperation>, SqlxError> {
+    let rows = sqlx::query(&hedge_operations_query(
+        r#"
+        WHERE chat_id = ?
+          AND (? IS NULL OR start_timestamp >= ?)
+          AND (? IS NULL OR start_timestamp < ?)
+        ORDER BY start_timestamp DESC, id DESC
+        "#,
+    ))
+    .bind(chat_id)
+    .bind(from_ts)
+    .bind(from_ts)
+    .bind(to_ts)
+    .bind(to_ts)
+    .fetch_all(db)
+    .await?;
+
+    rows.iter().map(hedge_operation_from_row).collect()
+}
+
+/// Получить количество операций пользователя по статусам (опционально для одного символа).
+/// Используется, чтобы объяснить, почему список операций для расхеджирования пуст.
+pub async fn get_hedge_status_counts(
+    db: &Db,
+    chat_id: i64,
+    base_symbol: Option<&str>,
+) -> Result<Vec<HedgeStatusCount>, SqlxError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            status,
+            (unhedged_op_id IS NOT NULL) AS already_unhedged,
+            COUNT(*) AS cnt
+        FROM hedge_operations
+        WHERE chat_id = ?
+          AND (? IS NULL OR base_symbol = ?)
+        GROUP BY status, already_unhedged
+        "#,
     )
     .bind(chat_id)
+    .bind(base_symbol)
+    .bind(base_symbol)
     .fetch_all(db)
     .await?;
 
-    // Ручной маппинг строк в Vec<HedgeOperation>
-    let mut operations = Vec::with_capacity(rows.len());
+    let mut counts = Vec::with_capacity(rows.len());
     for row in rows {
-        let operation = HedgeOperation {
-            id: row.try_get("id")?,
-            chat_id: row.try_get("chat_id")?,
-            base_symbol: row.try_get("base_symbol")?,
-            quote_currency: row.try_get("quote_currency")?,
-            initial_sum: row.try_get("initial_sum")?,
-            volatility: row.try_get("volatility")?,
-            target_spot_qty: row.try_get("target_spot_qty")?,
-            target_futures_qty: row.try_get("target_futures_qty")?,
-            start_timestamp: row.try_get("start_timestamp")?,
+        let already_unhedged: i64 = row.try_get("already_unhedged")?;
+        counts.push(HedgeStatusCount {
             status: row.try_get("status")?,
-            spot_order_id: row.try_get("spot_order_id")?,
-            spot_filled_qty: row.try_get("spot_filled_qty")?,
-            futures_order_id: row.try_get("futures_order_id")?,
-            futures_filled_qty: row.try_get("futures_filled_qty")?,
-            end_timestamp: row.try_get("end_timestamp")?,
-            error_message: row.try_get("error_message")?,
-            unhedged_op_id: row.try_get("unhedged_op_id")?,
-        };
-        operations.push(operation);
+            already_unhedged: already_unhedged != 0,
+            count: row.try_get("cnt")?,
+        });
     }
-    Ok(operations)
-}
\ No newline at end of file
+    Ok(counts)
+}
+
+/// Отметить, установлен ли трейлинг-стоп на позицию фьючерсного контракта.
+/// Стоп на Bybit — свойство позиции, общей для всех хеджей этого контракта в окружении.
+pub async fn set_trailing_stop_active(
+    db: &Db,
+    environment: &str,
+    futures_symbol: &str,
+    active: bool,
+) -> Result<(), SqlxError> {
+    if active {
+        sqlx::query("INSERT OR REPLACE INTO trailing_stops (environment, futures_symbol, set_at) VALUES (?, ?, ?)")
+            .bind(environment)
+            .bind(futures_symbol)
+            .bind(current_timestamp())
+            .execute(db)
+            .await?;
+    } else {
+        sqlx::query("DELETE FROM trailing_stops WHERE environment = ? AND futures_symbol = ?")
+            .bind(environment)
+            .bind(futures_symbol)
+            .execute(db)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Проверить, установлен ли трейлинг-стоп на позицию контракта.
+pub async fn is_trailing_stop_active(db: &Db, environment: &str, futures_symbol: &str) -> Result<bool, SqlxError> {
+    let row_opt = sqlx::query("SELECT 1 FROM trailing_stops WHERE environment = ? AND futures_symbol = ?")
+        .bind(environment)
+        .bind(futures_symbol)
+        .fetch_optional(db)
+        .await?;
+    Ok(row_opt.is_some())
+}
+
+/// Запомнить пользователя (чат), обратившегося к боту: вставка или обновление last_seen.
+pub async fn touch_user(db: &Db, chat_id: i64, username: Option<&str>) -> Result<(), SqlxError> {
+    let ts = current_timestamp();
+    sqlx::query(
+        r#"
+        INSERT INTO users (chat_id, username, first_seen, last_seen)
+        VALUES (?, ?, ?, ?)
+        ON CONFLICT(chat_id) DO UPDATE SET username = excluded.username, last_seen = excluded.last_seen
+        "#,
+    )
+    .bind(chat_id)
+    .bind(username)
+    .bind(ts)
+    .bind(ts)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Сводная статистика операций пользователя (агрегация на стороне SQLite).
+pub async fn get_operation_stats(db: &Db, chat_id: i64) -> Result<OperationStats, SqlxError> {
+    let row = sqlx::query(
+        r#"
+        SELECT
+            COALESCE(SUM(status = 'Completed'), 0) AS completed_count,
+            COALESCE(SUM(status IN ('Failed', 'SpotOnlyOrphan')), 0) AS failed_count,
+            COALESCE(SUM(CASE WHEN status = 'Completed' THEN initial_sum END), 0.0) AS total_hedged_volume,
+            AVG(CASE WHEN status = 'Completed' AND end_timestamp IS NOT NULL
+                     THEN CAST(end_timestamp - start_timestamp AS REAL) END) AS avg_duration_secs,
+            COALESCE(SUM(CASE WHEN status = 'Completed' AND unhedged_op_id IS NULL THEN initial_sum END), 0.0) AS open_notional
+        FROM hedge_operations
+        WHERE chat_id = ?
+        "#,
+    )
+    .bind(chat_id)
+    .fetch_one(db)
+    .await?;
+
+    Ok(OperationStats {
+        completed_count: row.try_get("completed_count")?,
+        failed_count: row.try_get("failed_count")?,
+        total_hedged_volume: row.try_get("total_hedged_volume")?,
+        avg_duration_secs: row.try_get("avg_duration_secs")?,
+        open_notional: row.try_get("open_notional")?,
+    })
+}
+
+/// Активность по чатам за [from_ts, to_ts): только чаты, где за период завершилась или упала хотя бы одна операция.
+pub async fn get_daily_activity(db: &Db, from_ts: i64, to_ts: i64, environment: &str) -> Result<Vec<DailyActivity>, SqlxError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            chat_id,
+            COALESCE(SUM(status = 'Completed' AND end_timestamp >= ?1 AND end_timestamp < ?2), 0) AS completed_count,
+            COALESCE(SUM(status IN ('Failed', 'SpotOnlyOrphan') AND end_timestamp >= ?1 AND end_timestamp < ?2), 0) AS failed_count,
+            COALESCE(SUM(CASE WHEN status = 'Completed' AND end_timestamp >= ?1 AND end_timestamp < ?2 THEN initial_sum END), 0.0) AS hedged_volume,
+            COALESCE(SUM(CASE WHEN status = 'Completed' AND unhedged_op_id IS NULL THEN accrued_funding END), 0.0) AS accrued_funding
+        FROM hedge_operations
+        WHERE environment IS NULL OR environment = ?3 -- Только текущее окружение биржи
+        GROUP BY chat_id
+        HAVING completed_count + failed_count > 0
+        ORDER BY chat_id
+        "#,
+    )
+    .bind(from_ts)
+    .bind(to_ts)
+    .bind(environment)
+    .fetch_all(db)
+    .await?;
+
+    let mut activity = Vec::with_capacity(rows.len());
+    for row in rows {
+        activity.push(DailyActivity {
+            chat_id: row.try_get("chat_id")?,
+            completed_count: row.try_get("completed_count")?,
+            failed_count: row.try_get("failed_count")?,
+            hedged_volume: row.try_get("hedged_volume")?,
+            accrued_funding: row.try_get("accrued_funding")?,
+        });
+    }
+    Ok(activity)
+}
+
+/// Получить chat_id всех пользователей, когда-либо обращавшихся к боту.
+pub async fn get_all_user_chat_ids(db: &Db) -> Result<Vec<i64>, SqlxError> {
+    let rows = sqlx::query("SELECT chat_id FROM users ORDER BY first_seen ASC")
+        .fetch_all(db)
+        .await?;
+    let mut chat_ids = Vec::with_capacity(rows.len());
+    for row in rows {
+        chat_ids.push(row.try_get("chat_id")?);
+    }
+    Ok(chat_ids)
+}
+
+/// Символ по умолчанию для /hedge без аргумента (None — сбросить)
+pub async fn set_default_symbol(db: &Db, chat_id: i64, symbol: Option<&str>) -> Result<(), SqlxError> {
+    sqlx::query(
+        r#"
+        INSERT INTO user_prefs (chat_id, default_symbol)
+        VALUES (?, ?)
+        ON CONFLICT(chat_id) DO UPDATE SET default_symbol = excluded.default_symbol
+        "#,
+    )
+    .bind(chat_id)
+    .bind(symbol)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Символ по умолчанию пользователя, если задан
+pub async fn get_default_symbol(db: &Db, chat_id: i64) -> Result<Option<String>, SqlxError> {
+    let row = sqlx::query("SELECT default_symbol FROM user_prefs WHERE chat_id = ?")
+        .bind(chat_id)
+        .fetch_optional(db)
+        .await?;
+    match row {
+        Some(row) => row.try_get("default_symbol"),
+        None => Ok(None),
+    }
+}
+
+/// Операции hedge_operations, начатые в диапазоне [from_ts, to_ts) (None — без ограничения), в JSON
+/// (массив объектов со всеми колонками) — для переноса между БД
+pub async fn export_operations_json(db: &Db, from_ts: Option<i64>, to_ts: Option<i64>) -> Result<String> {
+    let fields = HEDGE_OPERATIONS_COLUMNS
+        .iter()
+        .map(|(column, _)| format!("'{0}', {0}", column))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let sql = format!(
+        "SELECT json_object({}) AS operation FROM hedge_operations \
+         WHERE (? IS NULL OR start_timestamp >= ?) AND (? IS NULL OR start_timestamp < ?) ORDER BY id",
+        fields
+    );
+    let rows = sqlx::query(&sql)
+        .bind(from_ts)
+        .bind(from_ts)
+        .bind(to_ts)
+        .bind(to_ts)
+        .fetch_all(db)
+        .await?;
+    let operations = rows
+        .iter()
+        .map(|row| Ok(serde_json::from_str::<Value>(&row.try_get::<String, _>("operation")?)?))
+        .collect::<Result<Vec<Value>>>()?;
+    info!("Exported {} hedge operations", operations.len());
+    Ok(serde_json::to_string_pretty(&operations)?)
+}
+
+/// Колонки-ссылки на другие операции: после импорта переназначаются на новые ID
+const LINK_COLUMNS: [&str; 1] = ["rolled_from_op_id"];
+
+/// Статус операции, за которой следят задачи бота (восстановление при старте, мониторы ордеров)
+fn is_live_status(value: &Value) -> bool {
+    matches!(
+        value.as_str().and_then(|status| status.parse::<OperationStatus>().ok()),
+        Some(OperationStatus::Running | OperationStatus::PendingFutures)
+    )
+}
+
+/// Импорт операций из export_operations_json. Операции получают новые ID (без конфликтов с имеющимися),
+/// ссылка rolled_from_op_id переназначается на новые ID; unhedged_op_id (метка времени расхеджирования)
+/// сохраняется как есть. Незавершенные операции (Running, PendingFutures) импортируются как Interrupted:
+/// их позиции и ордера принадлежат другой БД, и восстановление при старте не должно за ними следить.
+/// Все или ничего (транзакция). Возвращает пары (старый ID, новый ID)
+pub async fn import_operations_json(db: &Db, json: &str) -> Result<Vec<(i64, i64)>> {
+    let operations: Vec<Map<String, Value>> = serde_json::from_str(json).context("Invalid operations JSON")?;
+    let mut tx = db.begin().await?;
+    let mut id_map = HashMap::new();
+    let mut imported = Vec::with_capacity(operations.len());
+    let interrupted_status = Value::from(OperationStatus::Interrupted.as_str());
+
+    for operation in &operations {
+        let old_id = operation.get("id").and_then(Value::as_i64).ok_or_else(|| anyhow!("Operation without integer id"))?;
+        let mut columns = Vec::new();
+        let mut values = Vec::new();
+        for (column, value) in operation {
+            if !HEDGE_OPERATIONS_COLUMNS.iter().any(|(known, _)| known == column) {
+                bail!("Operation {}: unknown column '{}'", old_id, column);
+            }
+            if column != "id" && !LINK_COLUMNS.contains(&column.as_str()) {
+                columns.push(column.as_str());
+                values.push(if column == "status" && is_live_status(value) {
+                    warn!("Import: operation {} has live status {}, imported as Interrupted", old_id, value);
+                    &interrupted_status
+                } else {
+                    value
+                });
+            }
+        }
+        let sql = format!(
+            "INSERT INTO hedge_operations ({}) VALUES ({})",
+            columns.join(", "),
+            vec!["?"; columns.len()].join(", ")
+        );
+        let mut query = sqlx::query(&sql);
+        for (column, value) in columns.iter().zip(values) {
+            query = match value {
+                Value::Null => query.bind(None::<i64>),
+                Value::String(text) => query.bind(text.clone()),
+                Value::Number(number) if number.is_i64() => query.bind(number.as_i64()),
+                Value::Number(number) => query.bind(number.as_f64()),
+                other => bail!("Operation {}: unsupported value for '{}': {}", old_id, column, other),
+            };
+        }
+        let new_id = query
+            .execute(&mut *tx)
+            .await
+            .with_context(|| format!("Failed to import operation {}", old_id))?
+            .last_insert_rowid();
+        if id_map.insert(old_id, new_id).is_some() {
+            bail!("Duplicate operation id {} in import", old_id);
+        }
+        imported.push((old_id, new_id));
+    }
+
+    for operation in &operations {
+        let old_id = operation["id"].as_i64().unwrap_or_default();
+        let link = |column: &str| operation.get(column).and_then(Value::as_i64);
+        let rolled_from = link("rolled_from_op_id").and_then(|source| {
+            let mapped = id_map.get(&source).copied();
+            if mapped.is_none() {
+                warn!("Import: operation {} rolled from {} which is not in the import, link dropped", old_id, source);
+            }
+            mapped
+        });
+        sqlx::query("UPDATE hedge_operations SET rolled_from_op_id = ? WHERE id = ?")
+            .bind(rolled_from)
+            .bind(id_map[&old_id])
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    info!("Imported {} hedge operations", imported.len());
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    async fn memory_db() -> Db {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+        apply_migrations(&pool).await.expect("migrations");
+        pool
+    }
+
+    async fn insert_op_at(db: &Db, chat_id: i64, start_timestamp: i64) -> i64 {
+        sqlx::query(
+            "INSERT INTO hedge_operations (chat_id, base_symbol, quote_currency, initial_sum, volatility, target_spot_qty, target_futures_qty, start_timestamp, status) VALUES (?, 'BTC', 'USDT', 100.0, 0.6, 0.001, 0.001, ?, 'Completed')",
+        )
+        .bind(chat_id)
+        .bind(start_timestamp)
+        .execute(db)
+        .await
+        .expect("insert op")
+        .last_insert_rowid()
+    }
+
+    async fn insert_stats_op(db: &Db, chat_id: i64, status: OperationStatus, initial_sum: f64, start: i64, end: Option<i64>, unhedged: bool) {
+        sqlx::query(
+            "INSERT INTO hedge_operations (chat_id, base_symbol, quote_currency, initial_sum, volatility, target_spot_qty, target_futures_qty, start_timestamp, status, end_timestamp, unhedged_op_id) VALUES (?, 'BTC', 'USDT', ?, 0.6, 0.001, 0.001, ?, ?, ?, ?)",
+        )
+        .bind(chat_id)
+        .bind(initial_sum)
+        .bind(start)
+        .bind(status.as_str())
+        .bind(end)
+        .bind(unhedged.then_some(999_i64))
+        .execute(db)
+        .await
+        .expect("insert op");
+    }
+
+    #[tokio::test]
+    async fn trailing_stop_is_tracked_per_symbol_and_environment() {
+        let db = memory_db().await;
+        set_trailing_stop_active(&db, "mainnet", "BTCUSDT", true).await.expect("set");
+        // Повторная установка (второй хедж того же контракта) не дублирует запись
+        set_trailing_stop_active(&db, "mainnet", "BTCUSDT", true).await.expect("set again");
+
+        assert!(is_trailing_stop_active(&db, "mainnet", "BTCUSDT").await.expect("get"));
+        assert!(!is_trailing_stop_active(&db, "testnet", "BTCUSDT").await.expect("get"));
+        assert!(!is_trailing_stop_active(&db, "mainnet", "ETHUSDT").await.expect("get"));
+
+        set_trailing_stop_active(&db, "mainnet", "BTCUSDT", false).await.expect("clear");
+        assert!(!is_trailing_stop_active(&db, "mainnet", "BTCUSDT").await.expect("get"));
+    }
+
+    #[tokio::test]
+    async fn default_symbol_round_trips_per_chat() {
+        let db = memory_db().await;
+        assert_eq!(get_default_symbol(&db, 1).await.expect("get"), None);
+
+        set_default_symbol(&db, 1, Some("BTC")).await.expect("set");
+        set_default_symbol(&db, 2, Some("ETH")).await.expect("set");
+        set_default_symbol(&db, 1, Some("SOL")).await.expect("overwrite");
+        assert_eq!(get_default_symbol(&db, 1).await.expect("get").as_deref(), Some("SOL"));
+        assert_eq!(get_default_symbol(&db, 2).await.expect("get").as_deref(), Some("ETH"));
+
+        set_default_symbol(&db, 1, None).await.expect("clear");
+        assert_eq!(get_default_symbol(&db, 1).await.expect("get"), None);
+    }
+
+    #[tokio::test]
+    async fn operation_stats_aggregate_seeded_dataset() {
+        let db = memory_db().await;
+        insert_stats_op(&db, 1, OperationStatus::Completed, 100.0, 1000, Some(1060), false).await;
+        insert_stats_op(&db, 1, OperationStatus::Completed, 250.0, 2000, Some(2120), true).await;
+        insert_stats_op(&db, 1, OperationStatus::Completed, 50.0, 3000, None, false).await;
+        insert_stats_op(&db, 1, OperationStatus::Failed, 500.0, 4000, Some(4010), false).await;
+        insert_stats_op(&db, 1, OperationStatus::Cancelled, 70.0, 5000, Some(5005), false).await;
+        insert_stats_op(&db, 2, OperationStatus::Completed, 1000.0, 1000, Some(2000), false).await;
+
+        let stats = get_operation_stats(&db, 1).await.expect("stats");
+
+        assert_eq!(stats.completed_count, 3);
+        assert_eq!(stats.failed_count, 1);
+        assert_eq!(stats.total_hedged_volume, 400.0);
+        assert_eq!(stats.avg_duration_secs, Some(90.0));
+        assert_eq!(stats.open_notional, 150.0);
+    }
+
+    #[tokio::test]
+    async fn daily_activity_counts_only_the_window() {
+        let db = memory_db().await;
+        // Сутки [86_400, 172_800): два завершения, одна ошибка; накануне — еще одно завершение
+        insert_stats_op(&db, 1, OperationStatus::Completed, 100.0, 86_000, Some(90_000), false).await;
+        insert_stats_op(&db, 1, OperationStatus::Completed, 300.0, 100_000, Some(100_060), true).await;
+        insert_stats_op(&db, 1, OperationStatus::Failed, 500.0, 120_000, Some(120_010), false).await;
+        insert_stats_op(&db, 1, OperationStatus::Completed, 700.0, 1_000, Some(2_000), false).await;
+        insert_stats_op(&db, 2, OperationStatus::Completed, 50.0, 1_000, Some(2_000), false).await; // Без активности за сутки
+        insert_stats_op(&db, 3, OperationStatus::Completed, 900.0, 100_000, Some(100_060), false).await;
+        sqlx::query("UPDATE hedge_operations SET environment = 'testnet' WHERE chat_id = 3")
+            .execute(&db)
+            .await
+            .expect("seed environment"); // Операция другого окружения в сводку не попадает
+        sqlx::query("UPDATE hedge_operations SET accrued_funding = 1.5 WHERE chat_id = 1 AND unhedged_op_id IS NULL AND status = 'Completed'")
+            .execute(&db)
+            .await
+            .expect("seed funding");
+
+        let activity = get_daily_activity(&db, 86_400, 172_800, "mainnet").await.expect("activity");
+
+        assert_eq!(
+            activity,
+            vec![DailyActivity { chat_id: 1, completed_count: 2, failed_count: 1, hedged_volume: 400.0, accrued_funding: 3.0 }]
+        );
+    }
+
+    #[tokio::test]
+    async fn operation_stats_are_empty_without_operations() {
+        let db = memory_db().await;
+
+        let stats = get_operation_stats(&db, 1).await.expect("stats");
+
+        assert_eq!(stats, OperationStats { completed_count: 0, failed_count: 0, total_hedged_volume: 0.0, avg_duration_secs: None, open_notional: 0.0 });
+    }
+
+    #[tokio::test]
+    async fn unhedge_candidates_are_filtered_by_environment() {
+        let db = memory_db().await;
+        let (testnet_id, _, _) = insert_hedge_operation(&db, 1, "BTC", "USDT", "testnet", 100.0, 0.6, 0.001, 0.001, false).await.expect("insert");
+        let (mainnet_id, _, _) = insert_hedge_operation(&db, 1, "BTC", "USDT", "mainnet", 200.0, 0.6, 0.002, 0.002, false).await.expect("insert");
+        let legacy_id = insert_op_at(&db, 1, 1000).await; // Без окружения: запись до появления колонки
+        for id in [testnet_id, mainnet_id] {
+            let futures = LegFill { order_id: None, filled_qty: 0.001 };
+            assert!(finalize_operation(&db, id, OperationStatus::Completed, None, futures, None).await.expect("finalize"));
+        }
+
+        let ids = |ops: Vec<HedgeOperation>| ops.into_iter().map(|op| op.id).collect::<HashSet<_>>();
+        let mainnet = ids(get_all_completed_unhedged_ops(&db, 1, "mainnet").await.expect("query"));
+        assert_eq!(mainnet, HashSet::from([mainnet_id, legacy_id]));
+        let testnet = ids(get_completed_unhedged_ops_for_symbol(&db, 1, "BTC", "testnet").await.expect("query"));
+        assert_eq!(testnet, HashSet::from([testnet_id, legacy_id]));
+
+        let op = get_hedge_operation_by_id(&db, testnet_id).await.expect("query").expect("op");
+        assert_eq!(op.environment.as_deref(), Some("testnet"));
+    }
+
+    #[tokio::test]
+    async fn stuck_operation_is_resolved_once_with_audit_note() {
+        let db = memory_db().await;
+        let (op_id, _, _) = insert_hedge_operation(&db, 1, "BTC", "USDT", "mainnet", 100.0, 0.6, 0.001, 0.001, false).await.expect("insert");
+        sqlx::query("UPDATE hedge_operations SET error_message = 'spot order lost' WHERE id = ?")
+            .bind(op_id)
+            .execute(&db)
+            .await
+            .expect("error note");
+
+        // Нетерминальный статус не принимается
+        assert!(!resolve_operation_manually(&db, op_id, OperationStatus::PendingFutures, "note").await.expect("resolve"));
+        assert!(resolve_operation_manually(&db, op_id, OperationStatus::Failed, "resolved manually by chat 1").await.expect("resolve"));
+
+        let op = get_hedge_operation_by_id(&db, op_id).await.expect("query").expect("op");
+        assert!(op.has_status(OperationStatus::Failed));
+        assert!(op.end_timestamp.is_some());
+        assert_eq!(op.error_message.as_deref(), Some("spot order lost | resolved manually by chat 1"));
+
+        // Завершенная операция повторно не переписывается
+        assert!(!resolve_operation_manually(&db, op_id, OperationStatus::Completed, "again").await.expect("resolve"));
+        let op = get_hedge_operation_by_id(&db, op_id).await.expect("query").expect("op");
+        assert!(op.has_status(OperationStatus::Failed));
+    }
+
+    #[tokio::test]
+    async fn live_futures_order_is_persisted_until_operation_is_interrupted() {
+        let db = memory_db().await;
+        let (op_id, _, _) = insert_hedge_operation(&db, 1, "BTC", "USDT", "mainnet", 100.0, 0.6, 0.001, 0.001, false).await.expect("insert");
+
+        update_running_futures_order(&db, op_id, "fut-2", 0.0004, 0.0011).await.expect("persist order");
+        let op = get_running_hedge_operations(&db).await.expect("running").pop().expect("op");
+        assert_eq!(op.futures_order_id.as_deref(), Some("fut-2"));
+        assert_eq!((op.futures_filled_qty, op.target_futures_qty), (0.0004, 0.0011));
+
+        assert!(mark_hedge_interrupted(&db, op_id, "restart").await.expect("interrupt"));
+        assert!(!mark_hedge_interrupted(&db, op_id, "again").await.expect("interrupt"));
+        // Операция уже не Running — живой ордер больше не перезаписывается
+        update_running_futures_order(&db, op_id, "fut-3", 0.0, 0.0011).await.expect("persist order");
+        let op = get_hedge_operation_by_id(&db, op_id).await.expect("query").expect("op");
+        assert!(op.has_status(OperationStatus::Interrupted));
+        assert_eq!(op.futures_order_id.as_deref(), Some("fut-2"));
+        assert!(get_running_hedge_operations(&db).await.expect("running").is_empty());
+    }
+
+    #[tokio::test]
+    async fn finalize_writes_each_leg_to_its_own_columns() {
+        let db = memory_db().await;
+        let (op_id, _, _) = insert_hedge_operation(&db, 1, "BTC", "USDT", "mainnet", 100.0, 0.6, 0.0012, 0.001, false).await.expect("insert");
+        // Разные объемы ног: спот с перекупом, фьючерс по шагу контракта
+        let spot = LegFill { order_id: Some("spot-1"), filled_qty: 0.0012 };
+        let futures = LegFill { order_id: Some("fut-1"), filled_qty: 0.001 };
+
+        assert!(finalize_operation(&db, op_id, OperationStatus::Completed, Some(spot), futures, None).await.expect("finalize"));
+
+        let op = get_hedge_operation_by_id(&db, op_id).await.expect("query").expect("op");
+        assert_eq!((op.spot_order_id.as_deref(), op.spot_filled_qty), (Some("spot-1"), 0.0012));
+        assert_eq!((op.futures_order_id.as_deref(), op.futures_filled_qty), (Some("fut-1"), 0.001));
+    }
+
+    #[tokio::test]
+    async fn failed_status_write_rolls_back_spot_fill() {
+        let db = memory_db().await;
+        let (op_id, _, _) = insert_hedge_operation(&db, 1, "BTC", "USDT", "mainnet", 100.0, 0.6, 0.001, 0.001, false).await.expect("insert");
+        update_hedge_spot_order(&db, op_id, Some("spot-1"), 0.0005).await.expect("spot progress");
+        let spot = LegFill { order_id: Some("spot-2"), filled_qty: 0.001 };
+        let futures = LegFill { order_id: Some("fut-1"), filled_qty: 0.001 };
+
+        // Сбой между записью спота и статуса: запись статуса отклоняется триггером
+        sqlx::query(
+            "CREATE TRIGGER fail_finalize BEFORE UPDATE OF status ON hedge_operations BEGIN SELECT RAISE(ABORT, 'injected failure'); END",
+        )
+        .execute(&db)
+        .await
+        .expect("trigger");
+        assert!(finalize_operation(&db, op_id, OperationStatus::Completed, Some(spot), futures, None).await.is_err());
+
+        let op = get_hedge_operation_by_id(&db, op_id).await.expect("query").expect("op");
+        assert!(op.has_status(OperationStatus::Running));
+        assert_eq!((op.spot_order_id.as_deref(), op.spot_filled_qty), (Some("spot-1"), 0.0005));
+        assert_eq!((op.futures_order_id, op.futures_filled_qty), (None, 0.0));
+        assert_eq!(op.end_timestamp, None);
+
+        sqlx::query("DROP TRIGGER fail_finalize").execute(&db).await.expect("drop trigger");
+        assert!(finalize_operation(&db, op_id, OperationStatus::Completed, Some(spot), futures, None).await.expect("finalize"));
+        let op = get_hedge_operation_by_id(&db, op_id).await.expect("query").expect("op");
+        assert!(op.has_status(OperationStatus::Completed));
+        assert_eq!((op.spot_order_id.as_deref(), op.spot_filled_qty), (Some("spot-2"), 0.001));
+        assert_eq!((op.futures_order_id.as_deref(), op.futures_filled_qty), (Some("fut-1"), 0.001));
+        assert!(op.end_timestamp.is_some());
+
+        // Повторное завершение уже закрытой операции ничего не меняет
+        let late_spot = LegFill { order_id: None, filled_qty: 0.0 };
+        let late = finalize_operation(&db, op_id, OperationStatus::Failed, Some(late_spot), LegFill { order_id: None, filled_qty: 0.0 }, Some("late"));
+        assert!(!late.await.expect("finalize"));
+        let op = get_hedge_operation_by_id(&db, op_id).await.expect("query").expect("op");
+        assert!(op.has_status(OperationStatus::Completed));
+        assert_eq!(op.spot_filled_qty, 0.001);
+        assert_eq!(op.error_message, None);
+    }
+
+    #[test]
+    fn operation_ref_uses_symbol_utc_day_and_sequence() {
+        // 2024-04-25 23:59:59 UTC
+        assert_eq!(format_operation_ref("btc", 1_714_089_599, 1), "BTC-0425-01");
+        assert_eq!(format_operation_ref("ETH", 1_714_089_600, 12), "ETH-0426-12");
+    }
+
+    #[tokio::test]
+    async fn operation_refs_are_numbered_per_symbol() {
+        let db = memory_db().await;
+        let (_, first, _) = insert_hedge_operation(&db, 1, "BTC", "USDT", "mainnet", 100.0, 0.6, 0.001, 0.001, false).await.expect("insert");
+        let (_, other, _) = insert_hedge_operation(&db, 2, "ETH", "USDT", "mainnet", 100.0, 0.6, 0.01, 0.01, false).await.expect("insert");
+        let (second_id, second, _) = insert_hedge_operation(&db, 1, "BTC", "USDT", "mainnet", 200.0, 0.6, 0.002, 0.002, false).await.expect("insert");
+
+        assert!(first.starts_with("BTC-") && first.ends_with("-01"), "{}", first);
+        assert!(other.starts_with("ETH-") && other.ends_with("-01"), "{}", other);
+        assert!(second.ends_with("-02"), "{}", second);
+
+        let stored = get_hedge_operation_by_id(&db, second_id).await.expect("query").expect("op");
+        assert_eq!(stored.op_ref.as_deref(), Some(second.as_str()));
+    }
+
+    #[tokio::test]
+    async fn concurrent_inserts_get_distinct_refs() {
+        let file = std::env::temp_dir().join(format!("hedgehog-op-ref-{}.db", std::process::id()));
+        let db = connect(&format!("sqlite://{}", file.display()), false).await.expect("file db");
+
+        // Разные суммы — не дубли; пул на несколько соединений, вставки идут параллельно
+        let inserts = (0..8).map(|i| {
+            let db = db.clone();
+            tokio::spawn(async move { insert_hedge_operation(&db, 1, "BTC", "USDT", "mainnet", 100.0 + f64::from(i), 0.6, 0.001, 0.001, false).await })
+        });
+        let mut refs: Vec<String> = Vec::new();
+        for insert in inserts {
+            let (_, op_ref, created) = insert.await.expect("join").expect("insert");
+            assert!(created);
+            refs.push(op_ref);
+        }
+        db.close().await;
+        let _ = std::fs::remove_file(&file);
+
+        refs.sort();
+        refs.dedup();
+        assert_eq!(refs.len(), 8, "{:?}", refs);
+    }
+
+    #[tokio::test]
+    async fn repeated_operation_returns_existing_row() {
+        let db = memory_db().await;
+        let (first_id, first_ref, first_created) = insert_hedge_operation(&db, 1, "BTC", "USDT", "mainnet", 100.0, 0.6, 0.001, 0.001, false).await.expect("insert");
+        let (second_id, second_ref, second_created) = insert_hedge_operation(&db, 1, "BTC", "USDT", "mainnet", 100.001, 0.6, 0.001, 0.001, false).await.expect("insert");
+
+        assert!(first_created);
+        assert!(!second_created);
+        assert_eq!((second_id, second_ref), (first_id, first_ref));
+        let count: i64 = sqlx::query("SELECT COUNT(*) AS n FROM hedge_operations")
+            .fetch_one(&db).await.expect("count").try_get("n").expect("n");
+        assert_eq!(count, 1);
+    }
+
+    #[tokio::test]
+    async fn failed_operation_does_not_block_retry() {
+        let db = memory_db().await;
+        let (first_id, _, _) = insert_hedge_operation(&db, 1, "BTC", "USDT", "mainnet", 100.0, 0.6, 0.001, 0.001, false).await.expect("insert");
+        update_hedge_final_status(&db, first_id, OperationStatus::Failed, None, 0.0, Some("spread")).await.expect("fail");
+
+        let (retry_id, retry_ref, retry_created) = insert_hedge_operation(&db, 1, "BTC", "USDT", "mainnet", 100.0, 0.6, 0.001, 0.001, false).await.expect("insert");
+
+        assert!(retry_created, "terminal operation is not a duplicate");
+        assert_ne!(retry_id, first_id);
+        assert!(!retry_ref.is_empty());
+    }
+
+    #[tokio::test]
+    async fn duplicate_of_legacy_row_gets_op_ref() {
+        let db = memory_db().await;
+        let (first_id, _, _) = insert_hedge_operation(&db, 1, "BTC", "USDT", "mainnet", 100.0, 0.6, 0.001, 0.001, false).await.expect("insert");
+        // Строка, созданная до введения op_ref
+        sqlx::query("UPDATE hedge_operations SET op_ref = NULL WHERE id = ?").bind(first_id).execute(&db).await.expect("clear ref");
+
+        let (duplicate_id, duplicate_ref, created) = insert_hedge_operation(&db, 1, "BTC", "USDT", "mainnet", 100.0, 0.6, 0.001, 0.001, false).await.expect("insert");
+
+        assert!(!created);
+        assert_eq!(duplicate_id, first_id);
+        assert!(duplicate_ref.starts_with("BTC-"), "{}", duplicate_ref);
+        let stored = get_hedge_operation_by_id(&db, first_id).await.expect("query").expect("op");
+        assert_eq!(stored.op_ref.as_deref(), Some(duplicate_ref.as_str()));
+    }
+
+    #[tokio::test]
+    async fn open_hedges_store_accrued_funding_and_alert_once() {
+        let db = memory_db().await;
+        let first = insert_op_at(&db, 1, 1000).await;
+        let second = insert_op_at(&db, 2, 2000).await;
+        insert_stats_op(&db, 1, OperationStatus::Completed, 100.0, 3000, Some(3100), true).await;
+
+        update_accrued_funding(&db, second, 1.25).await.expect("update");
+        let open = get_open_hedge_operations(&db).await.expect("query");
+
+        assert_eq!(open.iter().map(|op| op.id).collect::<Vec<_>>(), vec![first, second]);
+        assert_eq!(open[0].accrued_funding, 0.0);
+        assert_eq!(open[1].accrued_funding, 1.25);
+        assert!(claim_funding_alert(&db, second).await.expect("claim"));
+        assert!(!claim_funding_alert(&db, second).await.expect("second claim"));
+    }
+
+    #[tokio::test]
+    async fn unhedge_dust_qty_is_recorded() {
+        let db = memory_db().await;
+        let id = insert_op_at(&db, 1, 1000).await;
+
+        update_unhedge_dust_qty(&db, id, 0.00003).await.expect("update");
+        let dust: f64 = sqlx::query_scalar("SELECT unhedge_dust_qty FROM hedge_operations WHERE id = ?")
+            .bind(id)
+            .fetch_one(&db)
+            .await
+            .expect("query");
+
+        assert_eq!(dust, 0.00003);
+    }
+
+    #[tokio::test]
+    async fn roll_links_original_and_successor_once() {
+        let db = memory_db().await;
+        let original_id = insert_op_at(&db, 1, 1000).await;
+        let original = get_hedge_operation_by_id(&db, original_id).await.expect("query").expect("op");
+        assert_eq!(original.futures_contract(), "BTCUSDT");
+
+        let (rolled_id, _) = insert_rolled_hedge_operation(&db, &original, "BTCUSDT-26DEC25", 0.001).await.expect("insert");
+        assert!(complete_hedge_roll(&db, original_id, rolled_id, Some("fut-2"), 0.001).await.expect("roll"));
+        assert!(!complete_hedge_roll(&db, original_id, rolled_id, Some("fut-2"), 0.001).await.expect("roll"), "original already rolled");
+
+        let original = get_hedge_operation_by_id(&db, original_id).await.expect("query").expect("op");
+        let rolled = get_hedge_operation_by_id(&db, rolled_id).await.expect("query").expect("op");
+        assert!(original.unhedged_op_id.is_some_and(|marker| marker != rolled_id), "original is closed by a timestamp marker, not a link");
+        assert!(rolled.has_status(OperationStatus::Completed));
+        assert_eq!(rolled.rolled_from_op_id, Some(original_id));
+        assert_eq!(rolled.futures_contract(), "BTCUSDT-26DEC25");
+        assert_eq!(rolled.spot_filled_qty, original.spot_filled_qty);
+    }
+
+    #[tokio::test]
+    async fn operations_export_import_round_trip_remaps_ids_and_links() {
+        let source = memory_db().await;
+        let original_id = insert_op_at(&source, 1, 1000).await;
+        let original = get_hedge_operation_by_id(&source, original_id).await.expect("query").expect("op");
+        let (rolled_id, _) = insert_rolled_hedge_operation(&source, &original, "BTCUSDT-26DEC25", 0.001).await.expect("insert");
+        assert!(complete_hedge_roll(&source, original_id, rolled_id, Some("fut-2"), 0.001).await.expect("roll"));
+        let rolled_marker = get_hedge_operation_by_id(&source, original_id).await.expect("query").expect("op").unhedged_op_id;
+        let unhedged_id = insert_op_at(&source, 2, 2000).await;
+        mark_hedge_as_unhedged(&source, unhedged_id).await.expect("unhedge");
+        update_accrued_funding(&source, rolled_id, 0.75).await.expect("funding");
+        let marker = get_hedge_operation_by_id(&source, unhedged_id).await.expect("query").expect("op").unhedged_op_id;
+
+        // В целевой БД уже есть операции с теми же ID
+        let target = memory_db().await;
+        insert_op_at(&target, 9, 500).await;
+        insert_op_at(&target, 9, 600).await;
+        insert_op_at(&target, 9, 700).await;
+
+        let json = export_operations_json(&source, None, None).await.expect("export");
+        let mapping: HashMap<i64, i64> = import_operations_json(&target, &json).await.expect("import").into_iter().collect();
+        assert_eq!(mapping.len(), 3);
+        assert!(mapping.values().all(|new_id| *new_id > 3), "imported rows must not overwrite existing ones");
+
+        let new_original = get_hedge_operation_by_id(&target, mapping[&original_id]).await.expect("query").expect("op");
+        let new_rolled = get_hedge_operation_by_id(&target, mapping[&rolled_id]).await.expect("query").expect("op");
+        let new_unhedged = get_hedge_operation_by_id(&target, mapping[&unhedged_id]).await.expect("query").expect("op");
+        assert_eq!(new_original.unhedged_op_id, rolled_marker);
+        assert_eq!(new_rolled.rolled_from_op_id, Some(mapping[&original_id]));
+        assert_eq!(new_rolled.futures_contract(), "BTCUSDT-26DEC25");
+        assert_eq!(new_rolled.accrued_funding, 0.75);
+        assert_eq!(new_unhedged.unhedged_op_id, marker, "unhedge timestamp marker is kept");
+        assert_eq!(new_unhedged.chat_id, 2);
+        assert_eq!(get_hedge_operation_by_id(&target, 1).await.expect("query").expect("op").chat_id, 9);
+
+        // Ошибка в любой строке откатывает весь импорт
+        let broken = format!("{}, {{\"id\": 99, \"bogus\": 1}}]", json.trim_end().trim_end_matches(']'));
+        assert!(import_operations_json(&target, &broken).await.is_err());
+        let count: i64 = sqlx::query("SELECT COUNT(*) AS n FROM hedge_operations").fetch_one(&target).await.expect("count").try_get("n").expect("n");
+        assert_eq!(count, 6);
+    }
+
+    #[tokio::test]
+    async fn live_operations_are_imported_as_interrupted() {
+        let source = memory_db().await;
+        let completed_id = insert_op_at(&source, 1, 1000).await;
+        let (running_id, _, _) = insert_hedge_operation(&source, 2, "BTC", "USDT", "testnet", 100.0, 0.6, 0.001, 0.001, false).await.expect("insert");
+        let (pending_id, _, _) = insert_hedge_operation(&source, 3, "ETH", "USDT", "testnet", 200.0, 0.6, 0.1, 0.1, false).await.expect("insert");
+        mark_hedge_pending_futures(&source, pending_id, "fut-1", 0.0, 0.001).await.expect("pending");
+        let json = export_operations_json(&source, None, None).await.expect("export");
+
+        let target = memory_db().await;
+        let mapping: HashMap<i64, i64> = import_operations_json(&target, &json).await.expect("import").into_iter().collect();
+
+        for (old_id, expected) in [
+            (completed_id, OperationStatus::Completed),
+            (running_id, OperationStatus::Interrupted),
+            (pending_id, OperationStatus::Interrupted),
+        ] {
+            let operation = get_hedge_operation_by_id(&target, mapping[&old_id]).await.expect("query").expect("op");
+            assert!(operation.has_status(expected), "op {}: {}", old_id, operation.status);
+        }
+        // Восстановление и мониторы чужих операций не подхватывают
+        assert!(get_running_hedge_operations(&target).await.expect("running").is_empty());
+        assert!(get_pending_futures_operations(&target).await.expect("pending").is_empty());
+    }
+
+    #[tokio::test]
+    async fn operations_export_respects_date_range() {
+        let db = memory_db().await;
+        let _before = insert_op_at(&db, 1, 999).await;
+        let at_start = insert_op_at(&db, 1, 1000).await;
+        let _at_end = insert_op_at(&db, 2, 2000).await;
+
+        let json = export_operations_json(&db, Some(1000), Some(2000)).await.expect("export");
+        let exported: Vec<Value> = serde_json::from_str(&json).expect("json");
+        let ids: Vec<i64> = exported.iter().filter_map(|op| op["id"].as_i64()).collect();
+        assert_eq!(ids, vec![at_start]);
+
+        let all: Vec<Value> = serde_json::from_str(&export_operations_json(&db, None, None).await.expect("export")).expect("json");
+        assert_eq!(all.len(), 3);
+    }
+    #[tokio::test]
+    async fn range_filter_includes_start_and_excludes_end() {
+        let db = memory_db().await;
+        let _before = insert_op_at(&db, 1, 999).await;
+        let at_start = insert_op_at(&db, 1, 1000).await;
+        let inside = insert_op_at(&db, 1, 1500).await;
+        let _at_end = insert_op_at(&db, 1, 2000).await;
+        let _other_chat = insert_op_at(&db, 2, 1500).await;
+
+        let ops = get_hedge_operations_in_range(&db, 1, Some(1000), Some(2000)).await.expect("query");
+        let ids: Vec<i64> = ops.iter().map(|op| op.id).collect();
+        assert_eq!(ids, vec![inside, at_start]);
+
+        let all = get_hedge_operations_in_range(&db, 1, None, None).await.expect("query");
+        assert_eq!(all.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn spot_only_orphan_can_be_claimed_once() {
+        let db = memory_db().await;
+        let (id, _, _) = insert_hedge_operation(&db, 1, "BTC", "USDT", "mainnet", 100.0, 0.6, 0.001, 0.001, false).await.expect("insert");
+
+        mark_hedge_spot_only_orphan(&db, id, 0.0004, "Futures stage failed").await.expect("mark");
+        let op = get_hedge_operation_by_id(&db, id).await.expect("query").expect("op");
+        assert!(op.has_status(OperationStatus::SpotOnlyOrphan));
+        assert_eq!(op.futures_filled_qty, 0.0004);
+        assert_eq!(op.error_message.as_deref(), Some("Futures stage failed"));
+
+        assert!(claim_spot_only_orphan(&db, id).await.expect("claim"));
+        assert!(!claim_spot_only_orphan(&db, id).await.expect("second claim"));
+        let op = get_hedge_operation_by_id(&db, id).await.expect("query").expect("op");
+        assert!(op.has_status(OperationStatus::Running));
+        assert_eq!(op.error_message, None);
+    }
+}
@@ -10,14 +10,41 @@ pub use db::{
     insert_hedge_operation,
     update_hedge_spot_order,
     update_hedge_final_status,
+    finalize_operation, LegFill,
+    resolve_operation_manually,
     get_completed_unhedged_ops_for_symbol,
     mark_hedge_as_unhedged,
     // <<<--- ДОБАВЛЕНЫ НЕДОСТАЮЩИЕ ЭКСПОРТЫ ---
     get_all_completed_unhedged_ops,
     get_hedge_operation_by_id,
     // --->>>
-    // Можно также экспортировать get_running_hedge_operations, если она нужна где-то еще
-    // get_running_hedge_operations,
+    get_hedge_status_counts,
+    get_hedge_operations_in_range,
+    get_operation_stats,
+    get_daily_activity,
+    set_trailing_stop_active,
+    is_trailing_stop_active,
+    get_pending_futures_operations,
+    mark_hedge_pending_futures,
+    finish_pending_futures_operation,
+    mark_hedge_spot_only_orphan,
+    claim_spot_only_orphan,
+    insert_rolled_hedge_operation,
+    complete_hedge_roll,
+    get_open_hedge_operations,
+    update_accrued_funding,
+    claim_funding_alert,
+    update_unhedge_dust_qty,
+    record_partial_spot_sale,
+    touch_user,
+    get_all_user_chat_ids,
+    set_default_symbol,
+    get_default_symbol,
+    export_operations_json,
+    import_operations_json,
+    get_running_hedge_operations,
+    update_running_futures_order,
+    mark_hedge_interrupted,
 };
 // Экспортируем структуру операции
-pub use schema::HedgeOperation;
\ No newline at end of file
+pub use schema::{DailyActivity, HedgeOperation, HedgeStatusCount, OperationStats, OperationStatus};
\ No newline at end of file
@@ -4,7 +4,100 @@
 
 use sqlx::sqlite::SqlitePool;
 use sqlx::{Error, FromRow, Row};
-use tracing::info;
+use std::fmt;
+use std::str::FromStr;
+use tracing::{info, warn};
+
+/// Текущая версия схемы (хранится в PRAGMA user_version)
+pub const SCHEMA_VERSION: i64 = 11;
+
+/// Статус операции; в БД хранится строковая форма (CHECK на колонке status)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OperationStatus {
+    Running,
+    Completed,
+    Cancelled,
+    Failed,
+    Interrupted,    // Бот перезапущен во время операции
+    PendingFutures, // Спот куплен, фьючерсный ордер оставлен на бирже
+    SpotOnlyOrphan, // Спот куплен, фьючерсная нога не выставлена
+}
+
+impl OperationStatus {
+    /// Все статусы (для CHECK на колонке status)
+    pub const ALL: [OperationStatus; 7] = [
+        OperationStatus::Running,
+        OperationStatus::Completed,
+        OperationStatus::Cancelled,
+        OperationStatus::Failed,
+        OperationStatus::Interrupted,
+        OperationStatus::PendingFutures,
+        OperationStatus::SpotOnlyOrphan,
+    ];
+
+    /// Итоговый статус: операция завершена и больше не меняется
+    pub fn is_terminal(self) -> bool {
+        matches!(self, OperationStatus::Completed | OperationStatus::Cancelled | OperationStatus::Failed)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OperationStatus::Running => "Running",
+            OperationStatus::Completed => "Completed",
+            OperationStatus::Cancelled => "Cancelled",
+            OperationStatus::Failed => "Failed",
+            OperationStatus::Interrupted => "Interrupted",
+            OperationStatus::PendingFutures => "PendingFutures",
+            OperationStatus::SpotOnlyOrphan => "SpotOnlyOrphan",
+        }
+    }
+}
+
+impl fmt::Display for OperationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for OperationStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OperationStatus::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| format!("Unknown operation status: {}", s))
+    }
+}
+
+/// Ожидаемые колонки hedge_operations и их определения для ALTER TABLE (при обновлении старых БД)
+pub(super) const HEDGE_OPERATIONS_COLUMNS: &[(&str, &str)] = &[
+    ("id", "INTEGER"),
+    ("chat_id", "BIGINT NOT NULL DEFAULT 0"),
+    ("base_symbol", "TEXT NOT NULL DEFAULT ''"),
+    ("quote_currency", "TEXT NOT NULL DEFAULT ''"),
+    ("initial_sum", "REAL NOT NULL DEFAULT 0.0"),
+    ("volatility", "REAL NOT NULL DEFAULT 0.0"),
+    ("target_spot_qty", "REAL NOT NULL DEFAULT 0.0"),
+    ("target_futures_qty", "REAL NOT NULL DEFAULT 0.0"),
+    ("start_timestamp", "INTEGER NOT NULL DEFAULT 0"),
+    ("status", "TEXT NOT NULL DEFAULT 'Interrupted'"),
+    ("spot_order_id", "TEXT"),
+    ("spot_filled_qty", "REAL NOT NULL DEFAULT 0.0"),
+    ("futures_order_id", "TEXT"),
+    ("futures_filled_qty", "REAL NOT NULL DEFAULT 0.0"),
+    ("end_timestamp", "INTEGER"),
+    ("error_message", "TEXT"),
+    ("unhedged_op_id", "INTEGER"),
+    ("op_ref", "TEXT"),
+    ("futures_symbol", "TEXT"),
+    ("rolled_from_op_id", "INTEGER"),
+    ("accrued_funding", "REAL NOT NULL DEFAULT 0.0"),
+    ("funding_alert_sent", "INTEGER NOT NULL DEFAULT 0"),
+    ("unhedge_dust_qty", "REAL NOT NULL DEFAULT 0.0"),
+    ("environment", "TEXT"), // testnet / mainnet; NULL — операция создана до появления колонки
+    ("futures_only", "INTEGER NOT NULL DEFAULT 0"), // Хедж только фьючерсом: спот хранится вне бота
+];
 
 /// Асинхронная функция для применения миграций и создания таблиц.
 pub async fn apply_migrations(pool: &SqlitePool) -> Result<(), Error> {
@@ -23,7 +116,7 @@ pub async fn apply_migrations(pool: &SqlitePool) -> Result<(), Error> {
             target_spot_qty REAL NOT NULL,
             target_futures_qty REAL NOT NULL,
             start_timestamp INTEGER NOT NULL,
-            status TEXT NOT NULL CHECK(status IN ('Running', 'Completed', 'Cancelled', 'Failed', 'Interrupted')),
+            status TEXT NOT NULL CHECK(status IN ('Running', 'Completed', 'Cancelled', 'Failed', 'Interrupted', 'PendingFutures', 'SpotOnlyOrphan')),
             spot_order_id TEXT,
             spot_filled_qty REAL NOT NULL DEFAULT 0.0,
             futures_order_id TEXT,
@@ -37,6 +130,55 @@ pub async fn apply_migrations(pool: &SqlitePool) -> Result<(), Error> {
     .execute(pool)
     .await?;
 
+    // Колонки, добавленные после первой версии схемы (для существующих БД)
+    for (column, definition) in HEDGE_OPERATIONS_COLUMNS.iter().skip(1) { // id добавить нельзя
+        add_column_if_missing(pool, "hedge_operations", column, definition).await?;
+    }
+
+    // Пользователи, когда-либо писавшие боту (для рассылок)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS users (
+            chat_id BIGINT PRIMARY KEY,
+            username TEXT,
+            first_seen INTEGER NOT NULL,
+            last_seen INTEGER NOT NULL
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Настройки пользователя (символ по умолчанию для /hedge)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS user_prefs (
+            chat_id BIGINT PRIMARY KEY,
+            default_symbol TEXT
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Трейлинг-стоп на Bybit — свойство позиции контракта, а не операции: храним по символу
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS trailing_stops (
+            environment TEXT NOT NULL,
+            futures_symbol TEXT NOT NULL,
+            set_at INTEGER NOT NULL,
+            PRIMARY KEY (environment, futures_symbol)
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    migrate_trailing_stop_flags(pool).await?;
+
+    // Старые БД создавались с CHECK без новых статусов (например, PendingFutures)
+    rebuild_if_status_check_outdated(pool).await?;
+
     // Можно добавить индексы для ускорения запросов
     sqlx::query(
         r#"
@@ -47,6 +189,14 @@ pub async fn apply_migrations(pool: &SqlitePool) -> Result<(), Error> {
     .execute(pool)
     .await?;
 
+    let previous_version = get_schema_version(pool).await?;
+    if previous_version != SCHEMA_VERSION {
+        info!("Upgrading schema version {} -> {}", previous_version, SCHEMA_VERSION);
+        sqlx::query(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))
+            .execute(pool)
+            .await?;
+    }
+
     // TODO: Добавить таблицу unhedge_operations, если решим разделять
     // sqlx::query(
     //     r#"
@@ -77,6 +227,135 @@ pub async fn apply_migrations(pool: &SqlitePool) -> Result<(), Error> {
     Ok(())
 }
 
+/// Версия схемы из PRAGMA user_version
+async fn get_schema_version(pool: &SqlitePool) -> Result<i64, Error> {
+    let row = sqlx::query("PRAGMA user_version").fetch_one(pool).await?;
+    row.try_get::<i64, _>(0)
+}
+
+/// Список колонок таблицы (PRAGMA table_info)
+async fn get_table_columns(pool: &SqlitePool, table: &str) -> Result<Vec<String>, Error> {
+    let rows = sqlx::query(&format!("PRAGMA table_info({})", table))
+        .fetch_all(pool)
+        .await?;
+    let mut columns = Vec::with_capacity(rows.len());
+    for row in &rows {
+        columns.push(row.try_get::<String, _>("name")?);
+    }
+    Ok(columns)
+}
+
+/// Самопроверка схемы после миграций: все ожидаемые колонки hedge_operations на месте.
+/// Возвращает обнаруженную версию схемы.
+pub async fn verify_schema(pool: &SqlitePool) -> anyhow::Result<i64> {
+    let version = get_schema_version(pool).await?;
+    let columns = get_table_columns(pool, "hedge_operations").await?;
+    let missing: Vec<&str> = HEDGE_OPERATIONS_COLUMNS
+        .iter()
+        .map(|(name, _)| *name)
+        .filter(|name| !columns.iter().any(|c| c == name))
+        .collect();
+
+    if !missing.is_empty() {
+        warn!("Schema self-check failed: hedge_operations is missing columns {:?}", missing);
+        anyhow::bail!("Database schema is incomplete: hedge_operations is missing columns {:?}", missing);
+    }
+    if version != SCHEMA_VERSION {
+        warn!("Schema version {} differs from expected {}", version, SCHEMA_VERSION);
+    }
+    info!("Schema self-check passed (version {}, {} columns in hedge_operations).", version, columns.len());
+    Ok(version)
+}
+
+/// Пересоздает hedge_operations, если CHECK на status не знает всех статусов.
+/// SQLite не умеет менять CHECK через ALTER, поэтому данные копируются в новую таблицу.
+async fn rebuild_if_status_check_outdated(pool: &SqlitePool) -> Result<(), Error> {
+    let table_sql: String = sqlx::query("SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'hedge_operations'")
+        .fetch_one(pool)
+        .await?
+        .try_get("sql")?;
+    let has_status_check = table_sql.contains("CHECK");
+    let outdated = has_status_check
+        && OperationStatus::ALL
+            .iter()
+            .any(|status| !table_sql.contains(&format!("'{}'", status)));
+    if !outdated {
+        return Ok(());
+    }
+
+    info!("Rebuilding hedge_operations to update the status CHECK constraint");
+    let column_definitions: Vec<String> = HEDGE_OPERATIONS_COLUMNS
+        .iter()
+        .map(|(name, definition)| match *name {
+            "id" => "id INTEGER PRIMARY KEY AUTOINCREMENT".to_string(),
+            _ => format!("{} {}", name, definition),
+        })
+        .collect();
+    let allowed_statuses: Vec<String> = OperationStatus::ALL.iter().map(|s| format!("'{}'", s)).collect();
+    let column_names: Vec<&str> = HEDGE_OPERATIONS_COLUMNS.iter().map(|(name, _)| *name).collect();
+
+    let mut tx = pool.begin().await?;
+    sqlx::query(&format!(
+        "CREATE TABLE hedge_operations_new ({}, CHECK(status IN ({})))",
+        column_definitions.join(", "),
+        allowed_statuses.join(", ")
+    ))
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(&format!(
+        "INSERT INTO hedge_operations_new ({cols}) SELECT {cols} FROM hedge_operations",
+        cols = column_names.join(", ")
+    ))
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DROP TABLE hedge_operations").execute(&mut *tx).await?;
+    sqlx::query("ALTER TABLE hedge_operations_new RENAME TO hedge_operations")
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Переносит флаги trailing_stop_active открытых операций (прежнее хранение по операции)
+/// в trailing_stops; сама колонка остается в старых БД неиспользуемой.
+/// Операции без окружения (созданные до колонки environment) считаются mainnet
+async fn migrate_trailing_stop_flags(pool: &SqlitePool) -> Result<(), Error> {
+    if !get_table_columns(pool, "hedge_operations").await?.iter().any(|c| c == "trailing_stop_active") {
+        return Ok(());
+    }
+    sqlx::query(
+        r#"
+        INSERT OR IGNORE INTO trailing_stops (environment, futures_symbol, set_at)
+        SELECT COALESCE(environment, 'mainnet'), COALESCE(futures_symbol, base_symbol || quote_currency), COALESCE(end_timestamp, start_timestamp)
+        FROM hedge_operations
+        WHERE trailing_stop_active != 0 AND status = 'Completed' AND unhedged_op_id IS NULL
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    sqlx::query("UPDATE hedge_operations SET trailing_stop_active = 0 WHERE trailing_stop_active != 0")
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Добавляет колонку в таблицу, если ее еще нет (CREATE TABLE IF NOT EXISTS не меняет старые БД)
+async fn add_column_if_missing(
+    pool: &SqlitePool,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), Error> {
+    let exists = get_table_columns(pool, table).await?.iter().any(|c| c == column);
+    if !exists {
+        info!("Adding column {}.{} ({})", table, column, definition);
+        sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+            .execute(pool)
+            .await?;
+    }
+    Ok(())
+}
+
 // Структура, соответствующая строке в таблице hedge_operations
 // Добавляем поля, которые нам нужны для чтения
 #[derive(Debug, FromRow, Clone)] // Добавляем Clone
@@ -90,7 +369,7 @@ pub struct HedgeOperation {
     pub target_spot_qty: f64,
     pub target_futures_qty: f64,
     pub start_timestamp: i64,
-    pub status: String, // "Running", "Completed", "Cancelled", "Failed", "Interrupted"
+    pub status: String, // Строковая форма OperationStatus (см. has_status)
     pub spot_order_id: Option<String>,
     pub spot_filled_qty: f64,
     pub futures_order_id: Option<String>,
@@ -98,4 +377,188 @@ pub struct HedgeOperation {
     pub end_timestamp: Option<i64>,
     pub error_message: Option<String>,
     pub unhedged_op_id: Option<i64>,
+    pub op_ref: Option<String>, // Человекочитаемая ссылка (например, BTC-0425-01)
+    pub futures_symbol: Option<String>, // Фьючерсный контракт после роллирования; None — бессрочный BASE+QUOTE
+    pub rolled_from_op_id: Option<i64>, // Операция, из которой роллирован хедж
+    pub accrued_funding: f64, // Накопленный расход на фандинг шорта в quote (отрицательный — доход)
+    pub environment: Option<String>, // Окружение биржи (testnet / mainnet); None — неизвестно (старые записи)
+    pub futures_only: bool, // Хедж только фьючерсом (futures_only_hedge): спот не покупался и при расхедже не продается
+}
+
+impl HedgeOperation {
+    /// Операция создана в этом окружении биржи (или окружение неизвестно — старая запись)
+    pub fn belongs_to_environment(&self, environment: &str) -> bool {
+        self.environment.as_deref().is_none_or(|env| env == environment)
+    }
+
+    /// Операция в указанном статусе (неизвестная строка в БД не совпадает ни с одним)
+    pub fn has_status(&self, status: OperationStatus) -> bool {
+        self.status == status.as_str()
+    }
+
+    /// Символ фьючерса операции: сохраненный контракт или бессрочный BASE+QUOTE
+    pub fn futures_contract(&self) -> String {
+        self.futures_symbol
+            .clone()
+            .unwrap_or_else(|| format!("{}{}", self.base_symbol, self.quote_currency))
+    }
+}
+
+// Агрегат по статусам операций (для подсказок пользователю)
+#[derive(Debug, Clone)]
+pub struct HedgeStatusCount {
+    pub status: String,
+    pub already_unhedged: bool, // true, если операция уже была расхеджирована
+    pub count: i64,
+}
+
+// Сводная статистика операций пользователя (/stats)
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperationStats {
+    pub completed_count: i64,
+    pub failed_count: i64,
+    pub total_hedged_volume: f64,     // Сумма initial_sum завершенных хеджей (в quote)
+    pub avg_duration_secs: Option<f64>, // Среднее время от старта до завершения (None — нет данных)
+    pub open_notional: f64,           // Завершенные, но еще не расхеджированные хеджи (в quote)
+}
+
+// Активность чата за период (ежедневная сводка)
+#[derive(Debug, Clone, PartialEq)]
+pub struct DailyActivity {
+    pub chat_id: i64,
+    pub completed_count: i64,
+    pub failed_count: i64,
+    pub hedged_volume: f64,   // Сумма initial_sum хеджей, завершенных за период (в quote)
+    pub accrued_funding: f64, // Накопленный фандинг по открытым хеджам на момент сводки (расход > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    #[test]
+    fn operation_status_round_trips_through_string() {
+        for status in OperationStatus::ALL {
+            assert_eq!(status.to_string().parse::<OperationStatus>(), Ok(status));
+        }
+        assert!("Done".parse::<OperationStatus>().is_err());
+        assert!("completed".parse::<OperationStatus>().is_err());
+    }
+
+    #[tokio::test]
+    async fn old_schema_is_upgraded_in_place() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+
+        // Первая версия таблицы: без unhedged_op_id и op_ref
+        sqlx::query(
+            r#"
+            CREATE TABLE hedge_operations (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                chat_id BIGINT NOT NULL,
+                base_symbol TEXT NOT NULL,
+                quote_currency TEXT NOT NULL,
+                initial_sum REAL NOT NULL,
+                volatility REAL NOT NULL,
+                target_spot_qty REAL NOT NULL,
+                target_futures_qty REAL NOT NULL,
+                start_timestamp INTEGER NOT NULL,
+                status TEXT NOT NULL,
+                spot_order_id TEXT,
+                spot_filled_qty REAL NOT NULL DEFAULT 0.0,
+                futures_order_id TEXT,
+                futures_filled_qty REAL NOT NULL DEFAULT 0.0,
+                end_timestamp INTEGER,
+                error_message TEXT
+            );
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .expect("create old table");
+        sqlx::query(
+            "INSERT INTO hedge_operations (chat_id, base_symbol, quote_currency, initial_sum, volatility, target_spot_qty, target_futures_qty, start_timestamp, status) VALUES (1, 'BTC', 'USDT', 100.0, 0.6, 0.001, 0.001, 0, 'Completed')",
+        )
+        .execute(&pool)
+        .await
+        .expect("insert old row");
+
+        assert!(verify_schema(&pool).await.is_err());
+
+        apply_migrations(&pool).await.expect("migrations");
+
+        let version = verify_schema(&pool).await.expect("schema self-check");
+        assert_eq!(version, SCHEMA_VERSION);
+        let row = sqlx::query("SELECT COUNT(*) AS cnt, COUNT(op_ref) AS refs FROM hedge_operations WHERE unhedged_op_id IS NULL")
+            .fetch_one(&pool)
+            .await
+            .expect("query upgraded table");
+        assert_eq!(row.try_get::<i64, _>("cnt").unwrap(), 1);
+        assert_eq!(row.try_get::<i64, _>("refs").unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn outdated_status_check_is_rebuilt() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+
+        // Таблица со старым CHECK (без PendingFutures)
+        sqlx::query(
+            r#"
+            CREATE TABLE hedge_operations (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                chat_id BIGINT NOT NULL,
+                base_symbol TEXT NOT NULL,
+                quote_currency TEXT NOT NULL,
+                initial_sum REAL NOT NULL,
+                volatility REAL NOT NULL,
+                target_spot_qty REAL NOT NULL,
+                target_futures_qty REAL NOT NULL,
+                start_timestamp INTEGER NOT NULL,
+                status TEXT NOT NULL CHECK(status IN ('Running', 'Completed', 'Cancelled', 'Failed', 'Interrupted')),
+                spot_order_id TEXT,
+                spot_filled_qty REAL NOT NULL DEFAULT 0.0,
+                futures_order_id TEXT,
+                futures_filled_qty REAL NOT NULL DEFAULT 0.0,
+                end_timestamp INTEGER,
+                error_message TEXT,
+                unhedged_op_id INTEGER
+            );
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .expect("create old table");
+        sqlx::query(
+            "INSERT INTO hedge_operations (chat_id, base_symbol, quote_currency, initial_sum, volatility, target_spot_qty, target_futures_qty, start_timestamp, status) VALUES (1, 'BTC', 'USDT', 100.0, 0.6, 0.001, 0.001, 0, 'Running')",
+        )
+        .execute(&pool)
+        .await
+        .expect("insert old row");
+
+        apply_migrations(&pool).await.expect("migrations");
+        verify_schema(&pool).await.expect("schema self-check");
+
+        sqlx::query("UPDATE hedge_operations SET status = 'PendingFutures' WHERE id = 1")
+            .execute(&pool)
+            .await
+            .expect("new status accepted");
+        assert!(sqlx::query("UPDATE hedge_operations SET status = 'Bogus' WHERE id = 1")
+            .execute(&pool)
+            .await
+            .is_err());
+        let row = sqlx::query("SELECT base_symbol, status FROM hedge_operations WHERE id = 1")
+            .fetch_one(&pool)
+            .await
+            .expect("row preserved");
+        assert_eq!(row.try_get::<String, _>("base_symbol").unwrap(), "BTC");
+        assert_eq!(row.try_get::<String, _>("status").unwrap(), OperationStatus::PendingFutures.as_str());
+    }
 }
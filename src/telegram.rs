@@ -2,10 +2,9 @@
 
 use crate::config::Config;
 use crate::notifier::{
-    Command, StateStorage, RunningOperations, // Используем обновленный StateStorage
+    Command, StateStorage, RunningOperations, FailureCooldowns, TradingHalt, ChatEditClocks, // Используем обновленный StateStorage
     dispatch_command, dispatch_callback, dispatch_message
 };
-use tokio::sync::Mutex as TokioMutex;
 // <<< ДОБАВЛЕНО: Импортируем RwLock из tokio >>>
 use tokio::sync::RwLock as TokioRwLock;
 use teloxide::{
@@ -14,13 +13,14 @@ use teloxide::{
     types::{CallbackQuery, Message},
 };
 use crate::exchange::Exchange;
+use crate::hedger::WatcherRegistry;
 use crate::storage::Db;
 use std::sync::Arc;
 // <<< ИЗМЕНЕНО: Убираем RwLock из std::sync >>>
 // use std::sync::RwLock;
 use std::collections::HashMap;
 
-pub async fn run<E>(bot: Bot, exchange: E, cfg: Config, db: Db)
+pub async fn run<E>(bot: Bot, exchange: E, cfg: Config, db: Db, watchers: WatcherRegistry, running_operations: RunningOperations)
 where
     E: Exchange + Clone + Send + Sync + 'static,
 {
@@ -28,7 +28,9 @@ where
     // <<< ИЗМЕНЕНО: Инициализация с TokioRwLock >>>
     let state_storage: StateStorage = Arc::new(TokioRwLock::new(HashMap::new()));
     // ---
-    let running_operations: RunningOperations = Arc::new(TokioMutex::new(HashMap::new()));
+    let failure_cooldowns = FailureCooldowns::default();
+    let trading_halt = TradingHalt::new(cfg.kill_switch_file.as_deref());
+    let edit_clocks = ChatEditClocks::default(); // Интервал правок прогресса — общий на чат
 
     let cfg_arc = Arc::new(cfg);
     let db_arc = Arc::new(db);
@@ -41,6 +43,9 @@ where
             let exchange = exchange.clone();
             let state_storage = state_storage.clone(); // Клонируем Arc<TokioRwLock<...>>
             let running_operations = running_operations.clone();
+            let failure_cooldowns = failure_cooldowns.clone();
+            let trading_halt = trading_halt.clone();
+            let watchers = watchers.clone();
             let cfg = cfg_arc.clone();
             let db = db_arc.clone();
 
@@ -49,10 +54,13 @@ where
                 let exchange = exchange.clone();
                 let state_storage = state_storage.clone();
                 let running_operations = running_operations.clone();
+                let failure_cooldowns = failure_cooldowns.clone();
+                let trading_halt = trading_halt.clone();
+                let watchers = watchers.clone();
                 let cfg = cfg.clone();
                 let db = db.clone();
                 async move {
-                    if let Err(err) = dispatch_command(bot, msg, cmd, exchange, state_storage, running_operations, cfg, db).await {
+                    if let Err(err) = dispatch_command(bot, msg, cmd, exchange, state_storage, running_operations, failure_cooldowns, trading_halt, watchers, cfg, db).await {
                         tracing::error!("command handler error: {:?}", err);
                     }
                     respond(())
@@ -67,6 +75,10 @@ where
             let exchange = exchange.clone();
             let state_storage = state_storage.clone(); // Клонируем Arc<TokioRwLock<...>>
             let running_operations = running_operations.clone();
+            let failure_cooldowns = failure_cooldowns.clone();
+            let trading_halt = trading_halt.clone();
+            let edit_clocks = edit_clocks.clone();
+            let watchers = watchers.clone();
             let cfg = cfg_arc.clone();
             let db = db_arc.clone();
 
@@ -75,10 +87,14 @@ where
                 let exchange = exchange.clone();
                 let state_storage = state_storage.clone();
                 let running_operations = running_operations.clone();
+                let failure_cooldowns = failure_cooldowns.clone();
+                let trading_halt = trading_halt.clone();
+                let edit_clocks = edit_clocks.clone();
+                let watchers = watchers.clone();
                 let cfg = cfg.clone();
                 let db = db.clone();
                 async move {
-                    if let Err(err) = dispatch_callback(bot, q, exchange, state_storage, running_operations, cfg, db).await {
+                    if let Err(err) = dispatch_callback(bot, q, exchange, state_storage, running_operations, failure_cooldowns, trading_halt, edit_clocks, watchers, cfg, db).await {
                         tracing::error!("callback handler error: {:?}", err);
                     }
                     respond(())
@@ -1,10 +1,190 @@
 // src/utils.rs
 
+use anyhow::Result;
+use rust_decimal::Decimal;
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::exchange::types::OrderSide;
+
 /// Округление вниз с шагом `step`
 pub fn round_step(value: f64, step: f64) -> f64 {
     (value / step).floor() * step
 }
 
+/// Привязка цены к кратному `tick_size` (шаг может быть не степенью десяти, например 0.5).
+/// Buy округляется вниз, Sell — вверх (лимитка не становится агрессивнее); без стороны — к ближайшему
+pub fn round_to_tick(price: Decimal, tick_size: Decimal, side: Option<OrderSide>) -> Decimal {
+    if tick_size <= Decimal::ZERO {
+        return price;
+    }
+    let ticks = price / tick_size;
+    let ticks = match side {
+        Some(OrderSide::Buy) => ticks.floor(),
+        Some(OrderSide::Sell) => ticks.ceil(),
+        None => ticks.round(),
+    };
+    (ticks * tick_size).normalize()
+}
+
 pub fn trading_symbol(base: &str, quote: &str) -> String {
     format!("{}{}", base.to_uppercase(), quote.to_uppercase())
 }
+
+/// Цена устарела: возраст больше `max_staleness_ms` или цена еще ни разу не запрашивалась. 0 — без проверки.
+/// Общий порог для WS-режима и последовательного цикла ордеров
+pub fn is_price_stale(age: Option<Duration>, max_staleness_ms: u64) -> bool {
+    max_staleness_ms != 0 && age.is_none_or(|age| age > Duration::from_millis(max_staleness_ms))
+}
+
+/// Политика повторов для with_retry
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub label: &'static str,                    // Название операции для лога
+    pub max_attempts: u32,                      // Всего попыток, включая первую (0 — одна попытка)
+    pub base_delay: Duration,                   // Пауза перед первым повтором, дальше удваивается
+    pub jitter: Duration,                       // Случайная добавка к паузе от 0 до jitter
+    pub retryable: fn(&anyhow::Error) -> bool,  // Какие ошибки имеет смысл повторять
+}
+
+impl RetryPolicy {
+    /// Повторяет любую ошибку, без разброса паузы
+    pub fn new(label: &'static str, max_attempts: u32, base_delay: Duration) -> Self {
+        Self { label, max_attempts, base_delay, jitter: Duration::ZERO, retryable: |_| true }
+    }
+
+    pub fn with_jitter(self, jitter: Duration) -> Self {
+        Self { jitter, ..self }
+    }
+
+    /// Повторять только ошибки, для которых predicate возвращает true (остальные — сразу наружу)
+    pub fn retry_if(self, retryable: fn(&anyhow::Error) -> bool) -> Self {
+        Self { retryable, ..self }
+    }
+
+    /// Пауза перед повтором номер `retry` (с нуля): base_delay * 2^retry + jitter
+    fn delay_for(&self, retry: u32) -> Duration {
+        let backoff = self.base_delay.saturating_mul(2u32.saturating_pow(retry));
+        if self.jitter.is_zero() {
+            return backoff;
+        }
+        let random = RandomState::new().build_hasher().finish();
+        let jitter_ns = random % (self.jitter.as_nanos() as u64).saturating_add(1);
+        backoff.saturating_add(Duration::from_nanos(jitter_ns))
+    }
+}
+
+/// Выполняет операцию с повторами по политике; возвращает первый успех или последнюю ошибку.
+/// Ошибки, не прошедшие policy.retryable, возвращаются сразу
+pub async fn with_retry<F, Fut, T>(policy: RetryPolicy, mut op: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let max_attempts = policy.max_attempts.max(1);
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < max_attempts && (policy.retryable)(&e) => {
+                let delay = policy.delay_for(attempt - 1);
+                warn!("{} failed (attempt {}/{}): {}. Retrying in {:?}...", policy.label, attempt, max_attempts, e, delay);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn d(s: &str) -> Decimal {
+        Decimal::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn half_tick_snaps_by_side() {
+        assert_eq!(round_to_tick(d("100.3"), d("0.5"), Some(OrderSide::Buy)), d("100"));
+        assert_eq!(round_to_tick(d("100.3"), d("0.5"), Some(OrderSide::Sell)), d("100.5"));
+        assert_eq!(round_to_tick(d("100.3"), d("0.5"), None), d("100.5"));
+    }
+
+    #[test]
+    fn five_hundredths_tick() {
+        assert_eq!(round_to_tick(d("1.2345"), d("0.05"), Some(OrderSide::Buy)), d("1.2"));
+        assert_eq!(round_to_tick(d("1.2345"), d("0.05"), Some(OrderSide::Sell)), d("1.25"));
+        assert_eq!(round_to_tick(d("1.25"), d("0.05"), Some(OrderSide::Buy)), d("1.25"), "aligned price is kept");
+    }
+
+    #[test]
+    fn tick_larger_than_one() {
+        assert_eq!(round_to_tick(d("1003.7"), d("2.5"), Some(OrderSide::Buy)), d("1002.5"));
+        assert_eq!(round_to_tick(d("1003.7"), d("2.5"), Some(OrderSide::Sell)), d("1005"));
+        assert_eq!(round_to_tick(d("1003.7"), d("2.5"), None), d("1002.5"));
+    }
+
+    const FAST: RetryPolicy = RetryPolicy {
+        label: "Test op",
+        max_attempts: 3,
+        base_delay: Duration::from_millis(1),
+        jitter: Duration::ZERO,
+        retryable: |_| true,
+    };
+
+    #[tokio::test]
+    async fn succeeds_after_failures() {
+        let attempts = AtomicU32::new(0);
+        let result = with_retry(FAST.with_jitter(Duration::from_millis(1)), || async {
+            if attempts.fetch_add(1, Ordering::SeqCst) < 2 { Err(anyhow!("connection reset")) } else { Ok(7) }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), 7);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn exhausted_attempts_return_last_error() {
+        let attempts = AtomicU32::new(0);
+        let result: Result<()> = with_retry(FAST, || async {
+            let n = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            Err(anyhow!("still down {}", n))
+        })
+        .await;
+
+        assert_eq!(result.unwrap_err().to_string(), "still down 3");
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn non_retryable_error_is_returned_immediately() {
+        let attempts = AtomicU32::new(0);
+        let policy = FAST.retry_if(|e| e.to_string().contains("timeout"));
+        let result: Result<()> = with_retry(policy, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(anyhow!("insufficient balance"))
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn delay_doubles_and_jitter_is_bounded() {
+        let policy = RetryPolicy::new("Test op", 3, Duration::from_millis(10));
+        assert_eq!(policy.delay_for(0), Duration::from_millis(10));
+        assert_eq!(policy.delay_for(2), Duration::from_millis(40));
+        let jittered = policy.with_jitter(Duration::from_millis(5)).delay_for(1);
+        assert!(jittered >= Duration::from_millis(20) && jittered <= Duration::from_millis(25), "{:?}", jittered);
+    }
+}
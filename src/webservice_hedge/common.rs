@@ -1,10 +1,109 @@
 // src/hedger_ws/common.rs
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use rust_decimal::prelude::*;
 use rust_decimal_macros::dec;
 use tracing::{debug, warn}; // Добавили warn
 
+use crate::exchange::bybit::{LINEAR_CATEGORY, SPOT_CATEGORY};
+use crate::exchange::types::{OrderSide, PriceSource};
+use crate::exchange::Exchange;
+use crate::webservice_hedge::state::{HedgerWsState, Leg, MarketUpdate};
+
+/// Текущее время в мс (метки свежести рыночных данных)
+pub fn now_ms() -> i64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_millis() as i64
+}
+
+/// Перед расчетом лимитной цены: если цена из потока старше `max_staleness_ms`, запрашивает ее через REST
+/// и обновляет `market_data`. Ошибка — цену обновить не удалось, ордер выставлять нельзя
+pub async fn refresh_market_data_if_stale(
+    exchange: &dyn Exchange,
+    market_data: &mut MarketUpdate,
+    symbol: &str,
+    is_spot: bool,
+    max_staleness_ms: u64,
+) -> Result<()> {
+    let now = now_ms();
+    if !market_data.is_stale(now, max_staleness_ms) {
+        return Ok(());
+    }
+    let age = market_data.last_update_time_ms.map(|updated_ms| now - updated_ms);
+    warn!(symbol, ?age, max_staleness_ms, "Market data is stale, fetching fresh price via REST");
+    let (bid, ask) = if is_spot {
+        let price = exchange.get_spot_price(symbol).await;
+        (price.as_ref().ok().copied(), price.ok())
+    } else {
+        match exchange.get_futures_ticker(symbol).await {
+            Ok(ticker) => (Some(ticker.bid_price), Some(ticker.ask_price)),
+            Err(e) => {
+                warn!(symbol, "Failed to refresh futures ticker: {}", e);
+                (None, None)
+            }
+        }
+    };
+    let to_price = |value: Option<f64>| value.filter(|v| *v > 0.0).and_then(Decimal::from_f64);
+    match (to_price(bid), to_price(ask)) {
+        (Some(bid), Some(ask)) => {
+            market_data.best_bid_price = Some(bid);
+            market_data.best_ask_price = Some(ask);
+            market_data.last_update_time_ms = Some(now_ms());
+            Ok(())
+        }
+        _ => Err(anyhow!(
+            "Price for {} is older than {} ms and could not be refreshed; refusing to place orders",
+            symbol, max_staleness_ms
+        )),
+    }
+}
+
+/// Обновление устаревшей цены ноги через REST перед расчетом лимитной цены (Hedge и Unhedge).
+/// Ошибка — свежую цену получить не удалось, ордер не выставляем
+pub async fn refresh_leg_price_if_stale(
+    exchange: &dyn Exchange,
+    state: &mut HedgerWsState,
+    leg: Leg,
+    max_staleness_ms: u64,
+) -> Result<()> {
+    let operation_id = state.operation_id;
+    let (market_data, symbol, is_spot) = match leg {
+        Leg::Spot => (&mut state.spot_market_data, state.symbol_spot.as_str(), true),
+        Leg::Futures => (&mut state.futures_market_data, state.symbol_futures.as_str(), false),
+    };
+    refresh_market_data_if_stale(exchange, market_data, symbol, is_spot, max_staleness_ms)
+        .await
+        .with_context(|| format!("op_id:{}: stale {:?} price", operation_id, leg))
+}
+
+/// Опорная цена лимитки ноги по настройке price_source: None — лучшая цена стакана со стороны
+/// исполнения (ask для покупки, bid для продажи), Mid — середина стакана из потока, Last/Index — тикер через REST
+pub async fn leg_reference_price(
+    exchange: &dyn Exchange,
+    market_data: &MarketUpdate,
+    symbol: &str,
+    is_spot: bool,
+    side: OrderSide,
+    price_source: Option<PriceSource>,
+) -> Result<Decimal> {
+    match price_source {
+        None => match side {
+            OrderSide::Buy => market_data.best_ask_price.ok_or_else(|| anyhow!("No best ask price available for {}", symbol)),
+            OrderSide::Sell => market_data.best_bid_price.ok_or_else(|| anyhow!("No best bid price available for {}", symbol)),
+        },
+        Some(PriceSource::Mid) => match (market_data.best_bid_price, market_data.best_ask_price) {
+            (Some(bid), Some(ask)) => Ok((bid + ask) / dec!(2)),
+            _ => Err(anyhow!("No bid/ask available for mid price of {}", symbol)),
+        },
+        Some(source) => {
+            let category = if is_spot { SPOT_CATEGORY } else { LINEAR_CATEGORY };
+            let price = exchange.get_reference_price(symbol, category, source).await?;
+            Decimal::from_f64(price)
+                .filter(|price| *price > Decimal::ZERO)
+                .ok_or_else(|| anyhow!("Invalid {:?} reference price for {}: {}", source, symbol, price))
+        }
+    }
+}
+
 // Функция для автоматического расчета параметров чанков
 // Возвращает: Ok((итоговое_количество_чанков, объем_спота_на_чанк, объем_фьюча_на_чанк))
 // или Err, если не удалось подобрать размер даже для 1 чанка.
@@ -274,4 +373,51 @@ mod tests {
            assert!(result.is_err());
            assert!(result.unwrap_err().to_string().contains("Failed to find suitable chunk size"));
        }
-}
\ No newline at end of file
+    #[tokio::test]
+    async fn stale_price_is_refreshed_before_pricing() {
+        let exchange = crate::exchange::mock::MockExchange { futures_bid_ask: Some((99.5, 100.5)), ..Default::default() };
+        let mut market_data = MarketUpdate {
+            best_bid_price: Some(dec!(80)),
+            best_ask_price: Some(dec!(81)),
+            last_update_time_ms: Some(now_ms() - 60_000),
+            ..Default::default()
+        };
+        refresh_market_data_if_stale(&exchange, &mut market_data, "BTCUSDT", false, 5000).await.unwrap();
+        assert_eq!(market_data.best_bid_price, Some(dec!(99.5)));
+        assert_eq!(market_data.best_ask_price, Some(dec!(100.5)));
+        assert!(!market_data.is_stale(now_ms(), 5000));
+    }
+
+    #[tokio::test]
+    async fn leg_reference_price_follows_price_source() {
+        let exchange = crate::exchange::mock::MockExchange { index_price: Some(95.0), ..Default::default() };
+        let market_data = MarketUpdate { best_bid_price: Some(dec!(99)), best_ask_price: Some(dec!(101)), ..Default::default() };
+        let reference = |side, source| leg_reference_price(&exchange, &market_data, "BTCUSDT", false, side, source);
+
+        // Без настройки — лучшая цена стакана со стороны исполнения
+        assert_eq!(reference(OrderSide::Buy, None).await.unwrap(), dec!(101));
+        assert_eq!(reference(OrderSide::Sell, None).await.unwrap(), dec!(99));
+        assert_eq!(reference(OrderSide::Buy, Some(PriceSource::Mid)).await.unwrap(), dec!(100));
+        // Индексная цена берется с биржи, а не из стакана потока
+        assert_eq!(reference(OrderSide::Sell, Some(PriceSource::Index)).await.unwrap(), dec!(95));
+    }
+
+    #[tokio::test]
+    async fn stale_price_refuses_orders_when_refresh_fails() {
+        let exchange = crate::exchange::mock::MockExchange::default();
+        exchange.return_zero_spot_prices(1);
+        let mut market_data = MarketUpdate {
+            best_bid_price: Some(dec!(80)),
+            best_ask_price: Some(dec!(81)),
+            last_update_time_ms: Some(now_ms() - 60_000),
+            ..Default::default()
+        };
+        let result = refresh_market_data_if_stale(&exchange, &mut market_data, "BTCUSDT", true, 5000).await;
+        assert!(result.is_err());
+        assert_eq!(market_data.best_bid_price, Some(dec!(80)));
+        // Свежие данные не трогаем, 0 отключает проверку
+        let fresh = MarketUpdate { last_update_time_ms: Some(now_ms()), ..Default::default() };
+        assert!(!fresh.is_stale(now_ms(), 5000));
+        assert!(!MarketUpdate::default().is_stale(now_ms(), 0));
+    }
+}
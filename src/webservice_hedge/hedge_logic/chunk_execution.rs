@@ -7,6 +7,7 @@ use tracing::{debug, error, info, warn};
 
 use crate::exchange::types::OrderSide;
 use crate::webservice_hedge::hedge_task::HedgerWsHedgeTask;
+use crate::webservice_hedge::common::refresh_leg_price_if_stale;
 use crate::webservice_hedge::state::{ChunkOrderState, HedgerWsStatus, Leg};
 use crate::webservice_hedge::hedge_logic::helpers::{calculate_limit_price_for_leg, round_down_step, get_current_price, send_progress_update}; // Используем хелперы
 
@@ -85,8 +86,10 @@ pub async fn start_next_chunk(task: &mut HedgerWsHedgeTask) -> Result<bool> {
 
 
     // --- Определение лимитных цен и выставление ордеров ---
-    let spot_limit_price = if place_spot && spot_notional_ok { Some(calculate_limit_price_for_leg(task, Leg::Spot)?) } else { None };
-    let futures_limit_price = if place_futures && futures_notional_ok { Some(calculate_limit_price_for_leg(task, Leg::Futures)?) } else { None };
+    if place_spot && spot_notional_ok { refresh_leg_price_if_stale(task.exchange_rest.as_ref(), &mut task.state, Leg::Spot, task.config.max_price_staleness_ms).await?; }
+    if place_futures && futures_notional_ok { refresh_leg_price_if_stale(task.exchange_rest.as_ref(), &mut task.state, Leg::Futures, task.config.max_price_staleness_ms).await?; }
+    let spot_limit_price = if place_spot && spot_notional_ok { Some(calculate_limit_price_for_leg(task, Leg::Spot).await?) } else { None };
+    let futures_limit_price = if place_futures && futures_notional_ok { Some(calculate_limit_price_for_leg(task, Leg::Futures).await?) } else { None };
 
     let mut placed_spot_order: Option<ChunkOrderState> = None;
     let mut placed_futures_order: Option<ChunkOrderState> = None;
@@ -197,8 +200,7 @@ pub async fn start_next_chunk(task: &mut HedgerWsHedgeTask) -> Result<bool> {
     // --- Обработка ошибок размещения ---
     if let Some(error) = spot_place_error.or(futures_place_error) {
         task.state.status = HedgerWsStatus::Failed(format!("Chunk {} placement error: {}", chunk_index, error));
-        crate::webservice_hedge::hedge_logic::helpers::update_final_db_status(task).await;
-        return Err(error);
+        return Err(crate::webservice_hedge::hedge_logic::helpers::finalize_with_error(task, error).await);
     }
 
     // --- Успешное размещение ---
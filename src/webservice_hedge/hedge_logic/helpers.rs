@@ -4,26 +4,30 @@ use anyhow::{anyhow, Context, Result};
 use rust_decimal::prelude::*;
 use rust_decimal_macros::dec;
 // Убираем debug и warn, если они больше не используются
-use tracing::{error, info, trace};
+use tracing::{error, info, trace, warn};
 use std::str::FromStr;
 
 use crate::config::WsLimitOrderPlacementStrategy;
 // Убираем лишние скобки
 use crate::exchange::types::OrderSide;
+use crate::hedger::{finalize_with_retry, write_with_retry, DbWriteRetry, SpotOnlyOrphan};
 use crate::storage;
 use crate::webservice_hedge::hedge_task::HedgerWsHedgeTask;
-use crate::webservice_hedge::state::{HedgerWsStatus, Leg};
+use crate::webservice_hedge::common::leg_reference_price;
+use crate::webservice_hedge::state::{HedgerWsState, HedgerWsStatus, Leg};
 
 // Расчет лимитной цены для ноги
-pub fn calculate_limit_price_for_leg(task: &HedgerWsHedgeTask, leg: Leg) -> Result<Decimal> {
+pub async fn calculate_limit_price_for_leg(task: &HedgerWsHedgeTask, leg: Leg) -> Result<Decimal> {
      let (market_data, side, tick_size) = match leg {
          Leg::Spot => (&task.state.spot_market_data, OrderSide::Buy, task.state.spot_tick_size),
          Leg::Futures => (&task.state.futures_market_data, OrderSide::Sell, task.state.futures_tick_size),
      };
-     let reference_price = match side {
-         OrderSide::Buy => market_data.best_ask_price.ok_or_else(|| anyhow!("No best ask price available for {:?}", leg)),
-         OrderSide::Sell => market_data.best_bid_price.ok_or_else(|| anyhow!("No best bid price available for {:?}", leg)),
-     }?;
+     let (symbol, is_spot) = match leg {
+         Leg::Spot => (task.state.symbol_spot.as_str(), true),
+         Leg::Futures => (task.state.symbol_futures.as_str(), false),
+     };
+     // Опорная цена — по price_source (как в последовательном режиме), без настройки — лучшая цена стакана
+     let reference_price = leg_reference_price(task.exchange_rest.as_ref(), market_data, symbol, is_spot, side, task.config.price_source).await?;
      match task.config.ws_limit_order_placement_strategy {
          WsLimitOrderPlacementStrategy::BestAskBid => Ok(reference_price),
          WsLimitOrderPlacementStrategy::OneTickInside => {
@@ -77,15 +81,41 @@ pub async fn send_progress_update(task: &mut HedgerWsHedgeTask) -> Result<()> {
      Ok(())
 }
 
-// Обновление финального статуса операции в БД
-pub async fn update_final_db_status(task: &HedgerWsHedgeTask) {
-     let status_str = match &task.state.status {
-         HedgerWsStatus::Completed => "Completed",
-         HedgerWsStatus::Cancelled => "Cancelled",
-         HedgerWsStatus::Failed(_) => "Failed",
+// Сбой после покупки спота, который фьючерс не догнал хотя бы на минимальный ордер: позиция не захеджирована
+pub fn unhedged_spot_orphan(state: &HedgerWsState) -> Option<SpotOnlyOrphan> {
+     let HedgerWsStatus::Failed(reason) = &state.status else { return None };
+     let spot_filled = state.cumulative_spot_filled_quantity;
+     let futures_filled = state.cumulative_futures_filled_quantity;
+     let min_unhedged = state.min_futures_quantity.max(state.futures_quantity_step);
+     if spot_filled <= Decimal::ZERO || spot_filled - futures_filled < min_unhedged {
+         return None;
+     }
+     Some(SpotOnlyOrphan {
+         spot_filled_qty: spot_filled.to_f64().unwrap_or(0.0),
+         futures_filled_qty: futures_filled.to_f64().unwrap_or(0.0),
+         reason: reason.clone(),
+     })
+}
+
+// Обновление финального статуса операции в БД (с повторами записи)
+// Ошибка — DbRecordDiverged: запись не удалась после повторов, состояние биржи и БД расходится
+pub async fn update_final_db_status(task: &HedgerWsHedgeTask) -> Result<()> {
+     let db_retry = DbWriteRetry::from_config(&task.config);
+     // Незахеджированный спот — статус SpotOnlyOrphan (кнопка довыставления фьючерса), а не Failed
+     if let Some(orphan) = unhedged_spot_orphan(&task.state) {
+         error!(operation_id = task.operation_id, %orphan, "WS hedge left spot unhedged.");
+         return write_with_retry(db_retry, task.operation_id, "mark SpotOnlyOrphan", || {
+             storage::mark_hedge_spot_only_orphan(&task.database, task.operation_id, orphan.futures_filled_qty, &orphan.reason)
+         })
+         .await;
+     }
+     let status = match &task.state.status {
+         HedgerWsStatus::Completed => storage::OperationStatus::Completed,
+         HedgerWsStatus::Cancelled => storage::OperationStatus::Cancelled,
+         HedgerWsStatus::Failed(_) => storage::OperationStatus::Failed,
          _ => {
              tracing::warn!(operation_id = task.operation_id, status = ?task.state.status, "update_final_db_status called with non-final status.");
-             return;
+             return Ok(());
          }
      };
      let error_message = match &task.state.status {
@@ -99,19 +129,31 @@ pub async fn update_final_db_status(task: &HedgerWsHedgeTask) {
      let last_futures_order_id: Option<&str> = None;
 
      // --- ИСПРАВЛЕННЫЙ ВЫЗОВ ---
-     if let Err(e) = storage::update_hedge_final_status(
+     let finalized = finalize_with_retry(
          &task.database,          // 1. db
-         task.operation_id,       // 2. operation_id
-         status_str,              // 3. status
-         last_futures_order_id,   // 4. futures_order_id (Option<&str>)
-         fut_qty_f64,             // 5. futures_filled_qty (f64)
-         error_message,           // 6. error_message (Option<&str>)
-     ).await {
-         error!(operation_id = task.operation_id, %e, "Failed to update final status in DB!");
+         db_retry,                // 2. повторы записи
+         task.operation_id,       // 3. operation_id
+         status,                  // 4. status
+         None,                    // 5. spot: объем спота пишется по ходу исполнения чанков
+         storage::LegFill { order_id: last_futures_order_id, filled_qty: fut_qty_f64 }, // 6. futures
+         error_message,           // 7. error_message (Option<&str>)
+     ).await?;
+     if finalized {
+         info!(operation_id = task.operation_id, %status, "Final status updated in DB.");
      } else {
-         info!(operation_id = task.operation_id, %status_str, "Final status updated in DB.");
+         warn!(operation_id = task.operation_id, %status, "Operation was no longer Running, final status not written.");
      }
      // --- КОНЕЦ ИСПРАВЛЕННОГО ВЫЗОВА ---
+     Ok(())
+}
+
+// Финальный статус перед выходом задачи с ошибкой `error`.
+// Если запись не удалась — DbRecordDiverged с исходной ошибкой в контексте
+pub async fn finalize_with_error(task: &HedgerWsHedgeTask, error: anyhow::Error) -> anyhow::Error {
+     match update_final_db_status(task).await {
+         Ok(()) => error,
+         Err(diverged) => diverged.context(error.to_string()),
+     }
 }
 
 // Получение количества знаков после запятой из строки шага
@@ -145,4 +187,34 @@ pub fn check_value_imbalance(task: &HedgerWsHedgeTask) -> bool {
      } else {
          false
      }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+     use super::*;
+
+     fn failed_state(spot_filled: Decimal, futures_filled: Decimal) -> HedgerWsState {
+          let mut state = HedgerWsState::new_hedge(1, "BTCUSDT".into(), "BTCUSDT".into(), dec!(100), dec!(1));
+          state.min_futures_quantity = dec!(0.01);
+          state.futures_quantity_step = dec!(0.01);
+          state.cumulative_spot_filled_quantity = spot_filled;
+          state.cumulative_futures_filled_quantity = futures_filled;
+          state.status = HedgerWsStatus::Failed("futures order rejected".into());
+          state
+     }
+
+     #[test]
+     fn failed_ws_hedge_with_unhedged_spot_is_orphan() {
+          let orphan = unhedged_spot_orphan(&failed_state(dec!(0.5), dec!(0.2))).expect("spot left unhedged");
+          assert_eq!((orphan.spot_filled_qty, orphan.futures_filled_qty), (0.5, 0.2));
+          assert_eq!(orphan.reason, "futures order rejected");
+
+          // Остаток меньше минимального фьючерсного ордера и сбой до покупки спота — обычный Failed
+          assert!(unhedged_spot_orphan(&failed_state(dec!(0.5), dec!(0.495))).is_none());
+          assert!(unhedged_spot_orphan(&failed_state(Decimal::ZERO, Decimal::ZERO)).is_none());
+
+          let mut completed = failed_state(dec!(0.5), dec!(0.2));
+          completed.status = HedgerWsStatus::Completed;
+          assert!(unhedged_spot_orphan(&completed).is_none());
+     }
+}
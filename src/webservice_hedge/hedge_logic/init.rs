@@ -14,8 +14,8 @@ use std::str::FromStr;
 use crate::config::Config;
 // Убираем неиспользуемый LINEAR_CATEGORY из прямого импорта
 use crate::exchange::{Exchange, bybit::SPOT_CATEGORY};
-use crate::exchange::types::WebSocketMessage;
-use crate::hedger::HedgeProgressCallback;
+use crate::exchange::types::{ensure_instrument_trading, WebSocketMessage};
+use crate::hedger::{decide_leverage, HedgeProgressCallback, LeverageAction};
 use crate::models::HedgeRequest;
 use crate::storage;
 use crate::webservice_hedge::hedge_task::HedgerWsHedgeTask; // Доступ к структуре
@@ -47,22 +47,27 @@ pub(crate) async fn initialize_task(
         linear_info_res,
         fee_rate_res,
         mmr_res,
-        spot_price_res
+        spot_price_res,
+        leverage_res
     ) = tokio::join!(
         exchange_rest.get_spot_instrument_info(&base_symbol),
         exchange_rest.get_linear_instrument_info(&base_symbol),
         exchange_rest.get_fee_rate(&spot_symbol_name, SPOT_CATEGORY),
         exchange_rest.get_mmr(&futures_symbol_name),
-        exchange_rest.get_spot_price(&base_symbol)
+        exchange_rest.get_spot_price(&base_symbol),
+        exchange_rest.get_current_leverage(&futures_symbol_name) // Текущее плечо нужно решению по плечу ниже
     );
     // --- КОНЕЦ ИСПРАВЛЕНИЯ ---
 
     // Обработка результатов
     let spot_info = spot_info_res.context("Failed to get SPOT instrument info")?;
     let linear_info = linear_info_res.context("Failed to get LINEAR instrument info")?;
+    ensure_instrument_trading(&spot_info.symbol, spot_info.status.as_deref())?;
+    ensure_instrument_trading(&linear_info.symbol, linear_info.status.as_deref())?;
     let _fee_rate = fee_rate_res.context("Failed to get SPOT fee rate")?;
     let maintenance_margin_rate = mmr_res.context("Failed to get Futures MMR")?;
     let current_spot_price_f64 = spot_price_res.context("Failed to get current SPOT price")?;
+    let current_leverage = leverage_res.context("Failed to get current leverage via REST API")?;
 
     if current_spot_price_f64 <= 0.0 { return Err(anyhow!("Initial spot price is non-positive")); }
     let current_spot_price = Decimal::try_from(current_spot_price_f64)?;
@@ -113,11 +118,16 @@ pub(crate) async fn initialize_task(
     if required_leverage < 1.0 { return Err(anyhow!("Calculated required leverage ({:.2}) is less than 1.0", required_leverage)); }
     if required_leverage > config.max_allowed_leverage { return Err(anyhow!("Required leverage {:.2}x exceeds max allowed {:.2}x", required_leverage, config.max_allowed_leverage)); }
 
-    let leverage_to_set = (required_leverage * 100.0).round() / 100.0;
-    info!(operation_id, leverage_to_set, %futures_symbol_name, "Setting leverage via REST...");
-    exchange_rest.set_leverage(&futures_symbol_name, leverage_to_set).await.context("Failed to set leverage via REST API")?;
-    info!(operation_id, "Leverage set successfully.");
-    sleep(Duration::from_millis(500)).await;
+    match decide_leverage(config.leverage_mode, config.fixed_leverage, required_leverage, current_leverage, linear_info.leverage_filter.as_ref()) {
+        LeverageAction::Set(leverage_to_set) => {
+            info!(operation_id, leverage_to_set, %futures_symbol_name, "Setting leverage via REST...");
+            exchange_rest.set_leverage(&futures_symbol_name, leverage_to_set).await.context("Failed to set leverage via REST API")?;
+            info!(operation_id, "Leverage set successfully.");
+            sleep(Duration::from_millis(500)).await;
+        }
+        LeverageAction::Keep => info!(operation_id, current_leverage, required_leverage, "Leverage kept"),
+        LeverageAction::Reject(message) => return Err(anyhow!(message)),
+    }
 
     // --- 4. Создание начального состояния ---
     let spot_tick_size = Decimal::from_str(&spot_info.price_filter.tick_size)?;
@@ -9,6 +9,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::exchange::types::{OrderSide, OrderStatusText};
 use crate::webservice_hedge::hedge_task::HedgerWsHedgeTask;
+use crate::webservice_hedge::common::refresh_leg_price_if_stale;
 use crate::webservice_hedge::state::{ChunkOrderState, HedgerWsStatus, Leg};
 use crate::webservice_hedge::hedge_logic::helpers::{calculate_limit_price_for_leg, round_down_step};
 
@@ -123,7 +124,8 @@ pub async fn handle_cancel_confirmation(task: &mut HedgerWsHedgeTask, cancelled_
       }
       else if remaining_quantity_rounded > tolerance {
           info!(operation_id = task.operation_id, %remaining_quantity_rounded, ?leg, "Placing replacement order...");
-          let new_limit_price = calculate_limit_price_for_leg(task, leg)?;
+          refresh_leg_price_if_stale(task.exchange_rest.as_ref(), &mut task.state, leg, task.config.max_price_staleness_ms).await?;
+          let new_limit_price = calculate_limit_price_for_leg(task, leg).await?;
 
           let (symbol, side, qty_precision, price_precision) = match leg {
               Leg::Spot => (&task.state.symbol_spot, OrderSide::Buy, step.scale(), task.state.spot_tick_size.scale()),
@@ -159,8 +161,8 @@ pub async fn handle_cancel_confirmation(task: &mut HedgerWsHedgeTask, cancelled_
                     let error_msg = format!("Failed to place replacement {:?} order: {}", leg, e);
                     error!(operation_id = task.operation_id, error=%error_msg, ?leg);
                     task.state.status = HedgerWsStatus::Failed(error_msg); // <-- Добавляем строку ошибки
-                    crate::webservice_hedge::hedge_logic::helpers::update_final_db_status(task).await;
-                    return Err(e).context("Failed to place replacement order");
+                    let error = e.context("Failed to place replacement order");
+                    return Err(crate::webservice_hedge::hedge_logic::helpers::finalize_with_error(task, error).await);
                }
            }
       } else {
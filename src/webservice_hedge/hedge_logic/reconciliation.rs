@@ -61,7 +61,7 @@ pub async fn reconcile(task: &mut HedgerWsHedgeTask) -> Result<()> {
     }
 
     task.state.status = HedgerWsStatus::Completed;
-    update_final_db_status(task).await;
+    update_final_db_status(task).await?;
     info!(operation_id = task.operation_id, "Hedge reconciliation complete. Final Status: Completed.");
     Ok(())
 }
\ No newline at end of file
@@ -34,6 +34,12 @@ pub async fn handle_websocket_message(task: &mut HedgerWsHedgeTask, message: Web
                      return Ok(());
                  }
             }
+            // Ордер закрыт на бирже не ботом (отменен вручную или отклонен)
+            if let Some(leg) = active_order_leg(task, &details.order_id)
+                && (details.status_text.is_cancelled() || details.status_text == OrderStatusText::Rejected)
+            {
+                return handle_external_close(task, details, leg).await;
+            }
             handle_order_update(task, details).await?; // <-- Вызываем ИСПРАВЛЕННУЮ версию ниже
         }
         WebSocketMessage::OrderBookL2 { symbol, bids, asks, is_snapshot } => {
@@ -59,6 +65,36 @@ pub async fn handle_websocket_message(task: &mut HedgerWsHedgeTask, message: Web
 }
 
 
+// Нога, к которой относится активный ордер с order_id
+fn active_order_leg(task: &HedgerWsHedgeTask, order_id: &str) -> Option<Leg> {
+    if task.state.active_spot_order.as_ref().is_some_and(|o| o.order_id == order_id) {
+        Some(Leg::Spot)
+    } else if task.state.active_futures_order.as_ref().is_some_and(|o| o.order_id == order_id) {
+        Some(Leg::Futures)
+    } else {
+        None
+    }
+}
+
+// Активный ордер закрыт на бирже в обход бота: исполненная часть учитывается, после ручной отмены
+// остаток выставляется заново (как при своей перестановке), отклонение завершает операцию ошибкой
+async fn handle_external_close(task: &mut HedgerWsHedgeTask, details: DetailedOrderStatus, leg: Leg) -> Result<()> {
+    let order_id = details.order_id.clone();
+    let status_text = details.status_text.clone();
+    let reject_reason = details.reject_reason.clone();
+    handle_order_update(task, details).await?;
+    if status_text == OrderStatusText::Rejected {
+        return Err(anyhow!("{:?} order {} rejected by exchange: {}", leg, order_id, reject_reason.unwrap_or_default()));
+    }
+    let HedgerWsStatus::RunningChunk(chunk_index) = task.state.status else {
+        warn!(operation_id = task.operation_id, %order_id, ?leg, status = ?task.state.status, "Order cancelled externally while not running a chunk. Remainder is not re-placed.");
+        return Ok(());
+    };
+    warn!(operation_id = task.operation_id, %order_id, ?leg, ?status_text, "Order cancelled externally. Placing the remainder.");
+    task.state.status = HedgerWsStatus::WaitingCancelConfirmation { chunk_index, cancelled_leg: leg, cancelled_order_id: order_id.clone() };
+    handle_cancel_confirmation(task, &order_id, leg).await
+}
+
 // --- ПЕРЕРАБОТАННАЯ ФУНКЦИЯ handle_order_update ---
 async fn handle_order_update(task: &mut HedgerWsHedgeTask, details: DetailedOrderStatus) -> Result<()> {
     info!(operation_id = task.operation_id, order_id = %details.order_id, status = ?details.status_text, filled_qty = details.filled_qty, "Handling order update");
@@ -174,4 +210,76 @@ async fn handle_order_book_update(task: &mut HedgerWsHedgeTask, symbol: String,
 async fn handle_public_trade_update(task: &mut HedgerWsHedgeTask, symbol: String, price: f64, qty: f64, side: OrderSide, timestamp: i64) -> Result<()> {
     trace!(operation_id = task.operation_id, %symbol, %price, %qty, ?side, %timestamp, "Received public trade (currently ignored)");
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::mock::MockExchange;
+    use crate::webservice_hedge::state::{ChunkOrderState, HedgerWsState};
+    use futures::FutureExt;
+    use std::sync::Arc;
+
+    fn task_with_spot_order(exchange: MockExchange) -> HedgerWsHedgeTask {
+        let mut state = HedgerWsState::new_hedge(1, "ETHUSDT".to_string(), "ETHUSDT".to_string(), dec!(100), dec!(1));
+        state.spot_tick_size = dec!(0.01);
+        state.spot_quantity_step = dec!(0.0001);
+        state.min_spot_quantity = dec!(0.0001);
+        state.spot_market_data.best_bid_price = Some(dec!(100));
+        state.spot_market_data.best_ask_price = Some(dec!(100));
+        state.status = HedgerWsStatus::RunningChunk(1);
+        state.active_spot_order = Some(ChunkOrderState::new("spot-1".to_string(), "ETHUSDT".to_string(), OrderSide::Buy, dec!(100), dec!(1)));
+        let (_ws_sender, ws_receiver) = tokio::sync::mpsc::channel(1);
+        HedgerWsHedgeTask {
+            operation_id: 1,
+            config: Arc::new(crate::config::test_config("")),
+            database: Arc::new(sqlx::sqlite::SqlitePoolOptions::new().connect_lazy("sqlite::memory:").expect("lazy db")),
+            state,
+            ws_receiver,
+            exchange_rest: Arc::new(exchange),
+            progress_callback: Box::new(|_update| async { Ok(()) }.boxed()),
+        }
+    }
+
+    fn closed_update(status_text: OrderStatusText, filled_qty: f64) -> WebSocketMessage {
+        WebSocketMessage::OrderUpdate(DetailedOrderStatus {
+            order_id: "spot-1".to_string(),
+            symbol: "ETHUSDT".to_string(),
+            side: OrderSide::Buy,
+            filled_qty,
+            remaining_qty: 0.0,
+            cumulative_executed_value: filled_qty * 100.0,
+            average_price: 100.0,
+            status_text,
+            last_filled_price: None,
+            last_filled_qty: None,
+            reject_reason: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn externally_cancelled_ws_order_is_replaced_for_remainder() {
+        let exchange = MockExchange::default();
+        let mut task = task_with_spot_order(exchange.clone());
+
+        handle_websocket_message(&mut task, closed_update(OrderStatusText::PartiallyFilledCanceled, 0.4)).await.expect("handled");
+
+        assert!((task.state.cumulative_spot_filled_quantity - dec!(0.4)).abs() < dec!(0.000001));
+        let replacement = task.state.active_spot_order.as_ref().expect("remainder re-placed");
+        assert_eq!(replacement.order_id, "mock-spot-order");
+        assert_eq!(replacement.target_quantity, dec!(0.6));
+        assert_eq!(task.state.status, HedgerWsStatus::RunningChunk(1));
+        assert_eq!(exchange.created_limit_orders(), 1);
+    }
+
+    #[tokio::test]
+    async fn rejected_ws_order_fails_the_operation() {
+        let exchange = MockExchange::default();
+        let mut task = task_with_spot_order(exchange.clone());
+
+        let err = handle_websocket_message(&mut task, closed_update(OrderStatusText::Rejected, 0.0)).await.unwrap_err();
+
+        assert!(err.to_string().contains("rejected"), "{}", err);
+        assert!(task.state.active_spot_order.is_none());
+        assert_eq!(exchange.created_limit_orders(), 0);
+    }
+}
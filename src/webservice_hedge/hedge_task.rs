@@ -10,14 +10,14 @@ use tokio::sync::mpsc::Receiver;
 use crate::config::Config;
 use crate::exchange::Exchange;
 use crate::exchange::types::WebSocketMessage;
-use crate::hedger::HedgeProgressCallback;
+use crate::hedger::{DbRecordDiverged, HedgeProgressCallback};
 use crate::models::HedgeRequest;
 use crate::storage;
 
 // --- ДОБАВЛЕНО: Импортируем функции напрямую ---
 use crate::webservice_hedge::hedge_logic::{
     chunk_execution::start_next_chunk,
-    helpers::{check_chunk_completion, check_value_imbalance, update_final_db_status},
+    helpers::{check_chunk_completion, check_value_imbalance, finalize_with_error, unhedged_spot_orphan},
     init::initialize_task,
     reconciliation::reconcile,
     ws_handlers::handle_websocket_message,
@@ -56,8 +56,17 @@ impl HedgerWsHedgeTask {
         ).await
     }
 
-    // Основной цикл остается здесь, но вызывает функции из подмодулей
+    // Запуск стратегии: незахеджированный спот после сбоя уходит наверх как SpotOnlyOrphan (статус в БД уже записан)
     pub async fn run(&mut self) -> Result<()> {
+        let result = self.run_loop().await;
+        match (result, unhedged_spot_orphan(&self.state)) {
+            (Err(_), Some(orphan)) => Err(orphan.into()),
+            (result, _) => result,
+        }
+    }
+
+    // Основной цикл остается здесь, но вызывает функции из подмодулей
+    async fn run_loop(&mut self) -> Result<()> {
         info!(operation_id = self.operation_id, "Starting HedgerWsHedgeTask run loop...");
 
         // --- Запуск первого чанка ---
@@ -74,9 +83,7 @@ impl HedgerWsHedgeTask {
                 Err(error) => {
                     error!(operation_id = self.operation_id, %error, "Failed to start initial chunk");
                     self.state.status = HedgerWsStatus::Failed(format!("Failed start chunk 1: {}", error));
-                    // --- ИЗМЕНЕНО: Прямой вызов ---
-                    update_final_db_status(self).await;
-                    return Err(error);
+                    return Err(finalize_with_error(self, error).await);
                 }
             }
         } else if !matches!(self.state.status, HedgerWsStatus::RunningChunk(_)) {
@@ -84,8 +91,7 @@ impl HedgerWsHedgeTask {
             let error_message = format!("Task started in invalid state: {:?}", self.state.status);
             self.state.status = HedgerWsStatus::Failed(error_message.clone());
             // --- ИЗМЕНЕНО: Прямой вызов ---
-            update_final_db_status(self).await;
-            return Err(anyhow!(error_message));
+            return Err(finalize_with_error(self, anyhow!(error_message)).await);
         }
 
         // --- Цикл обработки сообщений WS ---
@@ -99,25 +105,20 @@ impl HedgerWsHedgeTask {
                             if let Err(error) = handle_websocket_message(self, message).await {
                                 error!(operation_id = self.operation_id, %error, "Error handling WebSocket message");
                                 self.state.status = HedgerWsStatus::Failed(format!("WS Handling Error: {}", error));
-                                // --- ИЗМЕНЕНО: Прямой вызов ---
-                                update_final_db_status(self).await;
-                                return Err(error);
+                                return Err(finalize_with_error(self, error).await);
                             }
                         }
                         Some(Err(error)) => {
                             error!(operation_id = self.operation_id, %error, "Error received from WebSocket channel. Stopping task.");
                             self.state.status = HedgerWsStatus::Failed(format!("WS channel error: {}", error));
-                            // --- ИЗМЕНЕНО: Прямой вызов ---
-                            update_final_db_status(self).await;
-                            return Err(error);
+                            return Err(finalize_with_error(self, error).await);
                         }
                         None => {
                             info!(operation_id = self.operation_id, "WebSocket channel closed. Stopping task.");
                             if !matches!(self.state.status, HedgerWsStatus::Completed | HedgerWsStatus::Cancelled | HedgerWsStatus::Failed(_)) {
                                 self.state.status = HedgerWsStatus::Failed("WebSocket channel closed unexpectedly".to_string());
                                 // --- ИЗМЕНЕНО: Прямой вызов ---
-                                update_final_db_status(self).await;
-                                return Err(anyhow!("WebSocket channel closed unexpectedly"));
+                                return Err(finalize_with_error(self, anyhow!("WebSocket channel closed unexpectedly")).await);
                             }
                             break;
                         }
@@ -177,10 +178,12 @@ impl HedgerWsHedgeTask {
                 // --- ИЗМЕНЕНО: Прямой вызов ---
                 if let Err(error) = reconcile(self).await {
                     error!(operation_id = self.operation_id, %error, "Reconciliation failed");
+                    // Не записан итог Completed: позиции на бирже собраны, Failed поверх писать нельзя
+                    if error.downcast_ref::<DbRecordDiverged>().is_some() {
+                        return Err(error);
+                    }
                     self.state.status = HedgerWsStatus::Failed(format!("Reconciliation failed: {}", error));
-                    // --- ИЗМЕНЕНО: Прямой вызов ---
-                    update_final_db_status(self).await;
-                    return Err(error);
+                    return Err(finalize_with_error(self, error).await);
                 }
                 info!(operation_id = self.operation_id, status = ?self.state.status, "Exiting run loop after reconciliation attempt.");
                 break;
@@ -195,9 +198,7 @@ impl HedgerWsHedgeTask {
                         Err(error) => {
                             error!(operation_id = self.operation_id, chunk = chunk_idx, %error, "Failed to start next chunk");
                             self.state.status = HedgerWsStatus::Failed(format!("Failed start chunk {}: {}", chunk_idx, error));
-                            // --- ИЗМЕНЕНО: Прямой вызов ---
-                            update_final_db_status(self).await;
-                            return Err(error);
+                            return Err(finalize_with_error(self, error).await);
                         }
                     }
                 } else {
@@ -8,7 +8,144 @@ pub mod hedge_logic;
 pub mod unhedge_logic;
 
 pub use state::{HedgerWsState, HedgerWsStatus, OperationType, MarketUpdate, Leg, ChunkOrderState};
-// Возможно, в будущем здесь будет какой-то общий тип или функция-диспетчер,
-// которая будет запускать либо hedge_task, либо unhedge_task.
-// Например:
-// pub async fn run_websocket_operation(...) -> Result<()> { ... }
\ No newline at end of file
+
+use anyhow::Result;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+use crate::config::Config;
+use crate::exchange::Exchange;
+use crate::exchange::bybit_ws;
+use crate::exchange::types::{SubscriptionType, WebSocketMessage};
+use crate::hedger::{fail_operation, DbWriteRetry, HedgeProgressCallback};
+use crate::models::HedgeRequest;
+use crate::storage::{Db, HedgeOperation, LegFill};
+use hedge_task::HedgerWsHedgeTask;
+use unhedge_task::HedgerWsUnhedgeTask;
+
+/// Операция, исполняемая через WebSocket
+pub enum WsOperation {
+    /// Хедж по запросу; запись в БД уже создана (operation_id)
+    Hedge { operation_id: i64, request: HedgeRequest },
+    /// Расхедж завершенной операции хеджа
+    Unhedge { original_operation: Box<HedgeOperation> },
+}
+
+/// Подписки WS для пары: приватные ордера и стаканы спота и фьючерса
+pub fn operation_subscriptions(spot_symbol: &str, futures_symbol: &str, depth: u32) -> Vec<SubscriptionType> {
+    vec![
+        SubscriptionType::Order,
+        SubscriptionType::Orderbook { symbol: spot_symbol.to_string(), depth },
+        SubscriptionType::Orderbook { symbol: futures_symbol.to_string(), depth },
+    ]
+}
+
+/// Диспетчер WS-операций: подключение, инициализация задачи хеджа или расхеджа и цикл до финального статуса.
+/// Финальный статус в БД записывают сами задачи — так же, как в последовательном пути;
+/// если хедж не дошел до задачи (нет подключения или инициализации), его запись помечается Failed здесь
+pub async fn run_websocket_operation(
+    operation: WsOperation,
+    cfg: Arc<Config>,
+    db: Arc<Db>,
+    exchange_rest: Arc<dyn Exchange>,
+    progress_callback: HedgeProgressCallback,
+) -> Result<()> {
+    let (spot_symbol, futures_symbol) = match &operation {
+        WsOperation::Hedge { request, .. } => {
+            let pair = format!("{}{}", request.symbol, cfg.quote_currency);
+            (pair.clone(), pair)
+        }
+        WsOperation::Unhedge { original_operation } => (
+            format!("{}{}", original_operation.base_symbol, cfg.quote_currency),
+            original_operation.futures_contract(),
+        ),
+    };
+    let subscriptions = operation_subscriptions(&spot_symbol, &futures_symbol, cfg.ws_order_book_depth);
+    let ws_receiver = match bybit_ws::connect_and_subscribe((*cfg).clone(), subscriptions).await {
+        Ok(receiver) => receiver,
+        Err(e) => {
+            if let WsOperation::Hedge { operation_id, .. } = &operation {
+                let error_text = format!("WebSocket connection failed: {}", e);
+                return Err(mark_hedge_failed(&db, DbWriteRetry::from_config(&cfg), *operation_id, &error_text, e).await);
+            }
+            return Err(e);
+        }
+    };
+    info!(%spot_symbol, %futures_symbol, "WebSocket connected for WS operation.");
+    run_operation_with_receiver(operation, cfg, db, exchange_rest, progress_callback, ws_receiver).await
+}
+
+/// Инициализация и запуск задачи на уже подключенном канале WS-сообщений
+async fn run_operation_with_receiver(
+    operation: WsOperation,
+    cfg: Arc<Config>,
+    db: Arc<Db>,
+    exchange_rest: Arc<dyn Exchange>,
+    progress_callback: HedgeProgressCallback,
+    ws_receiver: mpsc::Receiver<Result<WebSocketMessage>>,
+) -> Result<()> {
+    match operation {
+        WsOperation::Hedge { operation_id, request } => {
+            let db_retry = DbWriteRetry::from_config(&cfg);
+            let mut task = match HedgerWsHedgeTask::new(operation_id, request, cfg, db.clone(), exchange_rest, progress_callback, ws_receiver).await {
+                Ok(task) => task,
+                Err(e) => {
+                    let error_text = format!("WS hedge initialization failed: {}", e);
+                    return Err(mark_hedge_failed(&db, db_retry, operation_id, &error_text, e).await);
+                }
+            };
+            task.run().await
+        }
+        WsOperation::Unhedge { original_operation } => {
+            let mut task = HedgerWsUnhedgeTask::new(*original_operation, cfg, db, exchange_rest, progress_callback, ws_receiver).await?;
+            task.run().await
+        }
+    }
+}
+
+/// Хедж завершился до запуска задачи: запись операции (Running) закрывается как Failed.
+/// Возвращает ошибку для вызывающего: `error` или DbRecordDiverged, если запись не удалась
+async fn mark_hedge_failed(db: &Db, db_retry: DbWriteRetry, operation_id: i64, error_text: &str, error: anyhow::Error) -> anyhow::Error {
+    error!("op_id:{}: {}", operation_id, error_text);
+    fail_operation(db, db_retry, operation_id, None, LegFill::NONE, error_text, error).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{get_hedge_operation_by_id, insert_hedge_operation, OperationStatus};
+    use crate::storage::{get_hedge_operation_by_id, insert_hedge_operation};
+    use futures::FutureExt;
+
+    #[tokio::test]
+    async fn rejected_ws_hedge_is_marked_failed_by_dispatcher() {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("in-memory db");
+        crate::storage::schema::apply_migrations(&db).await.expect("migrations");
+        let cfg = Arc::new(crate::config::test_config("use_websocket_hedge = true"));
+        assert!(cfg.unhedge_via_websocket(false));
+        // Волатильность 1%: под фьючерс остается ~1% суммы, нужное плечо выше max_allowed_leverage
+        let request = HedgeRequest { sum: 1000.0, symbol: "ETH".to_string(), volatility: 0.01, spot_price_guard: None };
+        let (operation_id, _, _) = insert_hedge_operation(&db, 1, "ETH", "USDT", "testnet", request.sum, request.volatility, 0.0, 0.0, false)
+            .await
+            .expect("insert");
+        let db = Arc::new(db);
+        let exchange: Arc<dyn Exchange> = Arc::new(MockExchange::default());
+        let progress_callback: HedgeProgressCallback = Box::new(|_update| async { Ok(()) }.boxed());
+        let (_ws_sender, ws_receiver) = mpsc::channel(1);
+
+        let operation = WsOperation::Hedge { operation_id, request };
+        let err = run_operation_with_receiver(operation, cfg, db.clone(), exchange, progress_callback, ws_receiver)
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("exceeds max allowed"), "{}", err);
+        let op = get_hedge_operation_by_id(&db, operation_id).await.expect("query").expect("operation");
+        assert!(op.has_status(OperationStatus::Failed), "{}", op.status);
+        assert!(op.error_message.unwrap_or_default().contains("exceeds max allowed"));
+    }
+}
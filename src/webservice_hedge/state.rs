@@ -1,7 +1,9 @@
 // src/hedger_ws/state.rs
 
 use rust_decimal::Decimal;
+use std::time::Duration;
 use crate::exchange::types::{OrderSide, OrderStatusText, DetailedOrderStatus};
+use crate::utils::is_price_stale;
 /// Статус конкретного ордера внутри чанка
 #[derive(Debug, Clone)]
 pub struct ChunkOrderState {
@@ -103,6 +105,15 @@ pub struct MarketUpdate {
     pub last_update_time_ms: Option<i64>, // Добавим время обновления
 }
 
+impl MarketUpdate {
+    /// Данные устарели: старше `max_staleness_ms` или еще ни разу не обновлялись. 0 — без проверки
+    pub fn is_stale(&self, now_ms: i64, max_staleness_ms: u64) -> bool {
+        let age = self.last_update_time_ms
+            .map(|updated_ms| Duration::from_millis(u64::try_from(now_ms.saturating_sub(updated_ms)).unwrap_or(0)));
+        is_price_stale(age, max_staleness_ms)
+    }
+}
+
 /// Основная структура состояния для задачи HedgerWsTask
 #[derive(Debug, Clone)]
 pub struct HedgerWsState {
@@ -7,6 +7,7 @@ use tracing::{debug, error, info, warn};
 
 use crate::exchange::types::OrderSide;
 use crate::webservice_hedge::unhedge_task::HedgerWsUnhedgeTask; // Ссылка на структуру unhedge
+use crate::webservice_hedge::common::refresh_leg_price_if_stale;
 use crate::webservice_hedge::state::{ChunkOrderState, HedgerWsStatus, Leg};
 // --- ИЗМЕНЕНО: Используем хелперы из ЭТОГО ЖЕ модуля ---
 use crate::webservice_hedge::unhedge_logic::helpers::{calculate_limit_price_for_leg, round_down_step, get_current_price, send_progress_update, update_final_db_status}; // Добавили update_final_db_status
@@ -76,8 +77,10 @@ pub(crate) async fn start_next_chunk(task: &mut HedgerWsUnhedgeTask) -> Result<(
 
 
     // --- Определение лимитных цен (Unhedge) ---
-    let spot_limit_price = if place_spot && spot_notional_ok { Some(calculate_limit_price_for_leg(task, Leg::Spot)?) } else { None };       // Sell Spot -> цена на основе Bid
-    let futures_limit_price = if place_futures && futures_notional_ok { Some(calculate_limit_price_for_leg(task, Leg::Futures)?) } else { None }; // Buy Futures -> цена на основе Ask
+    if place_spot && spot_notional_ok { refresh_leg_price_if_stale(task.exchange_rest.as_ref(), &mut task.state, Leg::Spot, task.config.max_price_staleness_ms).await?; }
+    if place_futures && futures_notional_ok { refresh_leg_price_if_stale(task.exchange_rest.as_ref(), &mut task.state, Leg::Futures, task.config.max_price_staleness_ms).await?; }
+    let spot_limit_price = if place_spot && spot_notional_ok { Some(calculate_limit_price_for_leg(task, Leg::Spot).await?) } else { None };       // Sell Spot -> цена на основе Bid
+    let futures_limit_price = if place_futures && futures_notional_ok { Some(calculate_limit_price_for_leg(task, Leg::Futures).await?) } else { None }; // Buy Futures -> цена на основе Ask
 
     // --- Выставление ордеров через REST API (Unhedge) ---
     let mut placed_spot_order: Option<ChunkOrderState> = None;
@@ -8,22 +8,26 @@ use std::str::FromStr;
 
 use crate::config::WsLimitOrderPlacementStrategy;
 use crate::exchange::types::OrderSide;
+use crate::storage::OperationStatus;
 // --- ИЗМЕНЕНО: Ссылка на HedgerWsUnhedgeTask ---
 use crate::webservice_hedge::unhedge_task::HedgerWsUnhedgeTask;
+use crate::webservice_hedge::common::leg_reference_price;
 use crate::webservice_hedge::state::{HedgerWsStatus, Leg};
 
 // Расчет лимитной цены для ноги (Unhedge)
 // Логика та же, но тип task другой
-pub fn calculate_limit_price_for_leg(task: &HedgerWsUnhedgeTask, leg: Leg) -> Result<Decimal> {
+pub async fn calculate_limit_price_for_leg(task: &HedgerWsUnhedgeTask, leg: Leg) -> Result<Decimal> {
      let (market_data, side, tick_size) = match leg {
          // При расхедже: ПРОДАЕМ спот, ПОКУПАЕМ фьюч
          Leg::Spot => (&task.state.spot_market_data, OrderSide::Sell, task.state.spot_tick_size),
          Leg::Futures => (&task.state.futures_market_data, OrderSide::Buy, task.state.futures_tick_size),
      };
-     let reference_price = match side {
-         OrderSide::Buy => market_data.best_ask_price.ok_or_else(|| anyhow!("No best ask price available for {:?}", leg)),
-         OrderSide::Sell => market_data.best_bid_price.ok_or_else(|| anyhow!("No best bid price available for {:?}", leg)),
-     }?;
+     let (symbol, is_spot) = match leg {
+         Leg::Spot => (task.state.symbol_spot.as_str(), true),
+         Leg::Futures => (task.state.symbol_futures.as_str(), false),
+     };
+     // Опорная цена — по price_source (как в последовательном режиме), без настройки — лучшая цена стакана
+     let reference_price = leg_reference_price(task.exchange_rest.as_ref(), market_data, symbol, is_spot, side, task.config.price_source).await?;
      match task.config.ws_limit_order_placement_strategy {
          WsLimitOrderPlacementStrategy::BestAskBid => Ok(reference_price),
          WsLimitOrderPlacementStrategy::OneTickInside => {
@@ -83,10 +87,10 @@ pub async fn send_progress_update(task: &mut HedgerWsUnhedgeTask) -> Result<()>
 // Обновление финального статуса операции в БД (Unhedge)
 // Тип task изменен
 pub async fn update_final_db_status(task: &HedgerWsUnhedgeTask) {
-     let status_str = match &task.state.status {
-         HedgerWsStatus::Completed => "Completed", // Статус самой задачи
-         HedgerWsStatus::Cancelled => "Cancelled",
-         HedgerWsStatus::Failed(_) => "Failed",
+     let status = match &task.state.status {
+         HedgerWsStatus::Completed => OperationStatus::Completed, // Статус самой задачи
+         HedgerWsStatus::Cancelled => OperationStatus::Cancelled,
+         HedgerWsStatus::Failed(_) => OperationStatus::Failed,
          _ => {
              warn!(operation_id = task.operation_id, status = ?task.state.status, "update_final_db_status (unhedge) called with non-final status.");
              return;
@@ -100,7 +104,7 @@ pub async fn update_final_db_status(task: &HedgerWsUnhedgeTask) {
      // самой ОРИГИНАЛЬНОЙ операции хеджирования (через mark_hedge_as_unhedged или при ошибке).
      // Поэтому здесь просто логируем финальный статус задачи.
      // Если бы была отдельная таблица Unhedge Operations, здесь был бы вызов к ней.
-     info!(operation_id = task.operation_id, final_status = %status_str, error=?error_message, "Unhedge task reached final state.");
+     info!(operation_id = task.operation_id, final_status = %status, error=?error_message, "Unhedge task reached final state.");
 
      // Вызов mark_hedge_as_unhedged происходит в reconciliation.rs
 }
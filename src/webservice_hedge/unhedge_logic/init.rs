@@ -33,7 +33,7 @@ pub(crate) async fn initialize_task(
     // --- 1. Получение целей и информации об инструментах ---
     let base_symbol = original_operation.base_symbol.to_uppercase();
     let spot_symbol_name = format!("{}{}", base_symbol, config.quote_currency);
-    let futures_symbol_name = format!("{}{}", base_symbol, config.quote_currency);
+    let futures_symbol_name = original_operation.futures_contract();
 
     let target_spot_sell_quantity = Decimal::try_from(original_operation.spot_filled_qty)
         .context("Failed to convert original spot_filled_qty to Decimal")?;
@@ -55,7 +55,7 @@ pub(crate) async fn initialize_task(
         futures_price_res
     ) = tokio::join!(
         exchange_rest.get_spot_instrument_info(&base_symbol),
-        exchange_rest.get_linear_instrument_info(&base_symbol),
+        exchange_rest.get_linear_instrument_info(original_operation.futures_symbol.as_deref().unwrap_or(&base_symbol)),
         exchange_rest.get_balance(&base_symbol),
         exchange_rest.get_spot_price(&base_symbol),
         exchange_rest.get_market_price(&futures_symbol_name, false) // false для фьючерса
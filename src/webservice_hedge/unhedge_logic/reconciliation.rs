@@ -9,6 +9,7 @@ use tokio::time::sleep;
 use std::time::Duration;
 
 use crate::exchange::types::OrderSide;
+use crate::hedger::{write_with_retry, DbWriteRetry};
 use crate::storage; // Для mark_hedge_as_unhedged
 use crate::webservice_hedge::unhedge_task::HedgerWsUnhedgeTask;
 use crate::webservice_hedge::state::HedgerWsStatus; // Leg больше не нужен здесь
@@ -69,11 +70,12 @@ pub async fn reconcile(task: &mut HedgerWsUnhedgeTask) -> Result<()> {
     // Пауза перед финальным статусом
     sleep(Duration::from_secs(2)).await;
 
-    // Помечаем исходную операцию как расхеджированную
-    match storage::mark_hedge_as_unhedged(&task.database, task.operation_id).await {
-         Ok(_) => info!(operation_id=task.operation_id, "Marked original hedge operation as unhedged."),
-         Err(e) => error!(operation_id=task.operation_id, %e, "Failed to mark original hedge operation as unhedged in DB!"),
-    }
+    // Помечаем исходную операцию как расхеджированную (с повторами; сбой — DbRecordDiverged)
+    write_with_retry(DbWriteRetry::from_config(&task.config), task.operation_id, "mark unhedged", || {
+         storage::mark_hedge_as_unhedged(&task.database, task.operation_id)
+    })
+    .await?;
+    info!(operation_id=task.operation_id, "Marked original hedge operation as unhedged.");
 
     task.state.status = HedgerWsStatus::Completed;
     // --- ИСПРАВЛЕНО: Вызов локального хелпера ---